@@ -8,6 +8,7 @@ extern crate bluez;
 use anyhow::{bail, Context};
 use bluez::management::interface::*;
 use bluez::management::*;
+use bluez::AddressType;
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn main() -> std::result::Result<(), anyhow::Error> {
@@ -43,12 +44,12 @@ pub async fn main() -> std::result::Result<(), anyhow::Error> {
     }
 
     // stop discovery if it is active
-    let _ = stop_discovery(&mut mgmt, controller, AddressTypeFlag::BREDR.into(), None).await;
+    let _ = stop_discovery(&mut mgmt, controller, AddressType::BREDR.into(), None).await;
 
     // scan for some devices
     // to do this we'll need to listen for the Device Found event
 
-    start_discovery(&mut mgmt, controller, AddressTypeFlag::BREDR.into(), None)
+    start_discovery(&mut mgmt, controller, AddressType::BREDR.into(), None)
         .await
         .context("starting discovery failed")?;
 
@@ -6,7 +6,7 @@ extern crate bluez;
 
 use anyhow::Context;
 use bluez::communication::discovery::{
-    ServiceAttributeId, ServiceAttributeRange, ServiceDiscoveryClient, SDP_BROWSE_ROOT,
+    AttributeIdList, ServiceAttributeId, ServiceDiscoveryClient, SDP_BROWSE_ROOT,
 };
 use bluez::Address;
 use clap::Parser;
@@ -44,7 +44,7 @@ pub async fn main() -> Result<(), anyhow::Error> {
         // get all of the attributes for each service that was revealed
 
         let mut response = client
-            .service_attribute(service_handle, u16::MAX, vec![ServiceAttributeRange::ALL])
+            .service_attribute(service_handle, u16::MAX, AttributeIdList::all())
             .await
             .context("service attribute request failed")?;
 
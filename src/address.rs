@@ -1,11 +1,11 @@
 use std::{
-    fmt::{Display, Formatter},
+    fmt::{self, Display, Formatter},
     str::FromStr,
 };
 
 use bytes::Buf;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
 pub struct Address {
     bytes: [u8; 6],
 }
@@ -38,6 +38,97 @@ impl Address {
     pub const fn zero() -> Address {
         Address { bytes: [0u8; 6] }
     }
+
+    /// Generates a new static random address from the random number
+    /// generator of the kernel.
+    pub fn random_static() -> std::io::Result<Address> {
+        Self::random_static_with(|buf| {
+            let mut filled = 0;
+
+            while filled < buf.len() {
+                let len = unsafe {
+                    libc::getrandom(
+                        buf[filled..].as_mut_ptr() as *mut libc::c_void,
+                        buf.len() - filled,
+                        0,
+                    )
+                };
+
+                if len < 0 {
+                    let err = std::io::Error::last_os_error();
+
+                    // a signal arrived before the entropy pool was ready
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                } else {
+                    filled += len as usize;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Generates a new static random address, with random bytes from `rng`,
+    /// which fills the buffer that it is passed or fails. This can be used
+    /// with any random number generator, such as `|buf| rng.try_fill_bytes(buf)`
+    /// with the `rand` crate. The first error of `rng` is returned.
+    pub fn random_static_with<E>(
+        mut rng: impl FnMut(&mut [u8]) -> Result<(), E>,
+    ) -> Result<Address, E> {
+        loop {
+            let mut bytes = [0u8; 6];
+            rng(&mut bytes)?;
+
+            // the two most significant bits of a static address are set
+            bytes[5] |= 0xC0;
+
+            let address = Address::new(bytes);
+            if address.is_static_random() {
+                return Ok(address);
+            }
+        }
+    }
+
+    /// Whether the 46 bits of a random address that follow the two most
+    /// significant bits, which decide its kind, are neither all zero nor all
+    /// one.
+    fn random_part_valid(self) -> bool {
+        let mut random = self.bytes;
+        random[5] &= 0x3F;
+
+        random != [0x00; 6] && random != [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x3F]
+    }
+
+    /// Whether this is a valid static random address: the two most
+    /// significant bits are set, and the other 46 bits are neither all zero
+    /// nor all one.
+    ///
+    /// Like the other checks for random addresses, this only means something
+    /// for addresses with the type [`AddressType::LERandom`]; the bits of
+    /// public addresses have no such meaning.
+    pub fn is_static_random(self) -> bool {
+        self.bytes[5] & 0xC0 == 0xC0 && self.random_part_valid()
+    }
+
+    /// Whether this is a resolvable private address: the two most
+    /// significant bits are `01`, and the other 22 bits of the random part
+    /// in the upper half are neither all zero nor all one. The lower half is
+    /// a hash which can be checked against the identity resolving keys of
+    /// known devices.
+    pub fn is_resolvable_private(self) -> bool {
+        let prand = [self.bytes[3], self.bytes[4], self.bytes[5] & 0x3F];
+
+        self.bytes[5] & 0xC0 == 0x40 && prand != [0x00; 3] && prand != [0xFF, 0xFF, 0x3F]
+    }
+
+    /// Whether this is a non-resolvable private address: the two most
+    /// significant bits are clear, and the other 46 bits are neither all zero
+    /// nor all one.
+    pub fn is_non_resolvable_private(self) -> bool {
+        self.bytes[5] & 0xC0 == 0x00 && self.random_part_valid()
+    }
 }
 
 impl From<[u8; 6]> for Address {
@@ -129,18 +220,140 @@ pub enum AddressParseError {
     TooManyOctets,
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
+/// The type of a Bluetooth address.
+///
+/// The management API, the `bdaddr_type` field of socket addresses and HCI
+/// all encode address types as a byte, but not always with the same values:
+/// HCI has no BR/EDR address type and numbers the LE address types from
+/// zero. Use the conversion for the layer that the value is sent to instead
+/// of casting, so that values from different layers cannot be mixed up.
+///
+/// Values which this library does not know about are kept in
+/// [`AddressType::Other`], so that events from newer kernels can still be
+/// parsed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
 pub enum AddressType {
-    BREDR = 0,
-    LEPublic = 1,
-    LERandom = 2,
+    BREDR,
+    LEPublic,
+    LERandom,
+    Other(u8),
+}
+
+impl AddressType {
+    /// The value that is used for this address type by the management API.
+    pub fn to_mgmt_u8(self) -> u8 {
+        match self {
+            AddressType::BREDR => 0x00,
+            AddressType::LEPublic => 0x01,
+            AddressType::LERandom => 0x02,
+            AddressType::Other(value) => value,
+        }
+    }
+
+    pub fn from_mgmt_u8(value: u8) -> Self {
+        match value {
+            0x00 => AddressType::BREDR,
+            0x01 => AddressType::LEPublic,
+            0x02 => AddressType::LERandom,
+            value => AddressType::Other(value),
+        }
+    }
+
+    /// The value that is used for this address type in the `bdaddr_type`
+    /// field of L2CAP and ISO socket addresses (`BDADDR_BREDR`,
+    /// `BDADDR_LE_PUBLIC` and `BDADDR_LE_RANDOM`).
+    pub fn to_socket_u8(self) -> u8 {
+        match self {
+            AddressType::BREDR => 0x00,
+            AddressType::LEPublic => 0x01,
+            AddressType::LERandom => 0x02,
+            AddressType::Other(value) => value,
+        }
+    }
+
+    pub fn from_socket_u8(value: u8) -> Self {
+        match value {
+            0x00 => AddressType::BREDR,
+            0x01 => AddressType::LEPublic,
+            0x02 => AddressType::LERandom,
+            value => AddressType::Other(value),
+        }
+    }
+
+    /// The value that is used for this address type by LE HCI commands and
+    /// events, or `None` for [`AddressType::BREDR`] and unknown address
+    /// types, which HCI does not have values for.
+    pub fn to_hci_le_u8(self) -> Option<u8> {
+        match self {
+            AddressType::LEPublic => Some(0x00),
+            AddressType::LERandom => Some(0x01),
+            AddressType::BREDR | AddressType::Other(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AddressType::BREDR => "BR/EDR",
+            AddressType::LEPublic => "LE public",
+            AddressType::LERandom => "LE random",
+            AddressType::Other(value) => return write!(f, "unknown address type 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
 }
 
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, FromPrimitive, ToPrimitive)]
 pub enum Protocol {
     L2CAP = bluez_sys::BTPROTO_L2CAP,
     HCI = bluez_sys::BTPROTO_HCI,
     RFCOMM = bluez_sys::BTPROTO_RFCOMM,
+    /// Isochronous channels, see [`crate::communication::iso`].
+    ISO = 8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_address_kinds() {
+        let mut counter = 0u8;
+        let address = Address::random_static_with(|buf| {
+            // all zero at first, which is not valid
+            buf.fill(counter);
+            counter += 1;
+            Ok::<_, std::io::Error>(())
+        });
+        assert_eq!(
+            address.unwrap(),
+            Address::new([0x01, 0x01, 0x01, 0x01, 0x01, 0xC1])
+        );
+
+        // a generator that fails is not asked again
+        let mut calls = 0;
+        let address = Address::random_static_with(|_| {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::Interrupted))
+        });
+        assert_eq!(address.unwrap_err().kind(), std::io::ErrorKind::Interrupted);
+        assert_eq!(calls, 1);
+
+        let rpa: Address = "4d:12:34:56:78:9a".parse().unwrap();
+        assert!(rpa.is_resolvable_private());
+        assert!(!rpa.is_static_random());
+        assert!(!rpa.is_non_resolvable_private());
+        assert!(!"40:00:00:56:78:9a"
+            .parse::<Address>()
+            .unwrap()
+            .is_resolvable_private());
+
+        let nrpa: Address = "12:34:56:78:9a:bc".parse().unwrap();
+        assert!(nrpa.is_non_resolvable_private());
+        assert!(!Address::zero().is_non_resolvable_private());
+    }
 }
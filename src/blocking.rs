@@ -0,0 +1,163 @@
+//! A blocking API for applications that do not use an async runtime, such as
+//! command line utilities. This module is only available with the `blocking`
+//! feature, and [`BluetoothStream`] only with the `communication` feature as
+//! well.
+//!
+//! Every type in this module owns a current-thread tokio runtime and blocks
+//! on the async version of each operation. These types must not be used from
+//! inside of an async runtime, because blocking on a runtime from inside of
+//! another one panics.
+
+#[cfg(feature = "communication")]
+use std::io::{Read, Write};
+#[cfg(feature = "communication")]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use futures::future::BoxFuture;
+#[cfg(feature = "communication")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+
+use crate::management::{self, Controller, ControllerInfo, ManagementStream, Response};
+#[cfg(feature = "communication")]
+use crate::{Address, AddressType, Protocol};
+
+fn runtime() -> Result<Runtime, std::io::Error> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+}
+
+/// A blocking wrapper around a [`ManagementStream`].
+///
+/// A few common commands have methods of their own. Any other command can be
+/// run with [`ManagementClient::run`], for example
+/// `client.run(|socket| set_local_name(socket, controller, "name", None, None).boxed())`.
+#[derive(Debug)]
+pub struct ManagementClient {
+    runtime: Runtime,
+    socket: ManagementStream,
+}
+
+impl ManagementClient {
+    pub fn open() -> Result<Self, std::io::Error> {
+        let runtime = runtime()?;
+
+        // the socket is registered with the runtime that is entered when it
+        // is created
+        let socket = {
+            let _guard = runtime.enter();
+            ManagementStream::open()?
+        };
+
+        Ok(Self { runtime, socket })
+    }
+
+    /// Runs a command and blocks until it completes. `command` is called with
+    /// the socket of this client, and usually boxes the future of a command
+    /// function.
+    pub fn run<T, F>(&mut self, command: F) -> Result<T, management::Error>
+    where
+        F: for<'a> FnOnce(&'a mut ManagementStream) -> BoxFuture<'a, Result<T, management::Error>>,
+    {
+        let socket = &mut self.socket;
+        self.runtime.block_on(command(socket))
+    }
+
+    /// Blocks until the next event is received.
+    pub fn receive(&mut self) -> Result<Response, management::Error> {
+        let socket = &mut self.socket;
+        self.runtime.block_on(socket.receive())
+    }
+
+    /// See [`management::get_controller_list`].
+    pub fn get_controller_list(&mut self) -> Result<Vec<Controller>, management::Error> {
+        self.run(|socket| Box::pin(management::get_controller_list(socket, None)))
+    }
+
+    /// See [`management::get_controller_info`].
+    pub fn get_controller_info(
+        &mut self,
+        controller: Controller,
+    ) -> Result<ControllerInfo, management::Error> {
+        self.run(move |socket| Box::pin(management::get_controller_info(socket, controller, None)))
+    }
+
+    /// See [`management::set_powered`].
+    pub fn set_powered(
+        &mut self,
+        controller: Controller,
+        powered: bool,
+    ) -> Result<management::ControllerSettings, management::Error> {
+        self.run(move |socket| Box::pin(management::set_powered(socket, controller, powered, None)))
+    }
+
+    /// Returns the async stream that this client wraps.
+    pub fn get_mut(&mut self) -> &mut ManagementStream {
+        &mut self.socket
+    }
+}
+
+#[cfg(feature = "communication")]
+/// A blocking wrapper around a
+/// [`BluetoothStream`](crate::communication::BluetoothStream), which
+/// implements [`Read`] and [`Write`].
+#[derive(Debug)]
+pub struct BluetoothStream {
+    runtime: Runtime,
+    inner: crate::communication::BluetoothStream,
+}
+
+#[cfg(feature = "communication")]
+impl BluetoothStream {
+    /// See [`crate::communication::BluetoothStream::connect`].
+    pub fn connect(
+        proto: Protocol,
+        addr: Address,
+        addr_type: AddressType,
+        port: u16,
+    ) -> Result<Self, std::io::Error> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::communication::BluetoothStream::connect(
+            proto, addr, addr_type, port,
+        ))?;
+
+        Ok(Self { runtime, inner })
+    }
+
+    /// See [`crate::communication::BluetoothStream::local_sockaddr`].
+    pub fn local_sockaddr(&self) -> Result<crate::communication::BtSockAddr, std::io::Error> {
+        self.inner.local_sockaddr()
+    }
+
+    /// See [`crate::communication::BluetoothStream::peer_sockaddr`].
+    pub fn peer_sockaddr(&self) -> Result<crate::communication::BtSockAddr, std::io::Error> {
+        self.inner.peer_sockaddr()
+    }
+}
+
+#[cfg(feature = "communication")]
+impl Read for BluetoothStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.runtime.block_on(self.inner.read(buf))
+    }
+}
+
+#[cfg(feature = "communication")]
+impl Write for BluetoothStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.runtime.block_on(self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.runtime.block_on(self.inner.flush())
+    }
+}
+
+#[cfg(feature = "communication")]
+impl AsRawFd for BluetoothStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
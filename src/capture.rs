@@ -0,0 +1,362 @@
+//! Hooks for capturing the raw packets that this library sends and receives.
+//!
+//! A [`PacketLogger`] can be attached to a
+//! [`ManagementStream`](crate::management::ManagementStream) or a
+//! [`BluetoothStream`](crate::communication::BluetoothStream) with their
+//! `set_packet_logger` methods. [`BtsnoopWriter`] is a logger which writes a
+//! btsnoop file that can be opened with Wireshark or `btmon -r`.
+//!
+//! [`Recorder`] writes a trace of the messages of a management socket which
+//! can be fed back to the code that produced it with
+//! `bluez::testing::replay::Replayer`, so that bug reports can include a trace
+//! that maintainers replay in a test.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, BytesMut};
+
+use crate::management::ManagementStream;
+use crate::Protocol;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A packet that was sent or received.
+#[derive(Debug, Clone, Copy)]
+pub struct Packet<'a> {
+    /// The protocol of the socket. Packets of the management API use
+    /// [`Protocol::HCI`], because they are sent on the control channel of an
+    /// HCI socket.
+    pub protocol: Protocol,
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    /// For the management API, this is a whole message including its header.
+    /// For other sockets, this is the payload of one read or write.
+    pub data: &'a [u8],
+}
+
+/// Receives every packet that is sent or received on the sockets that it is
+/// attached to. It is called from inside of the socket operations, so it
+/// should not block for long.
+pub trait PacketLogger: Send + Sync {
+    fn log(&self, packet: &Packet<'_>);
+}
+
+/// A logger which is attached to a socket.
+#[derive(Clone)]
+pub(crate) struct AttachedLogger(Arc<dyn PacketLogger>);
+
+impl AttachedLogger {
+    pub(crate) fn new(logger: Arc<dyn PacketLogger>) -> Self {
+        Self(logger)
+    }
+
+    pub(crate) fn log(&self, protocol: Protocol, direction: Direction, data: &[u8]) {
+        self.0.log(&Packet {
+            protocol,
+            direction,
+            timestamp: SystemTime::now(),
+            data,
+        })
+    }
+}
+
+impl fmt::Debug for AttachedLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AttachedLogger")
+    }
+}
+
+/// The datalink type of btsnoop files in the format of the Linux Bluetooth
+/// monitor, which can hold management messages.
+const BTSNOOP_LINUX_MONITOR: u32 = 2001;
+
+/// The number of microseconds between the btsnoop epoch (midnight, January
+/// 1st, year 0) and the Unix epoch.
+const BTSNOOP_EPOCH_OFFSET: i64 = 0x00E0_3AB4_4A67_6000;
+
+const MONITOR_USER_LOGGING: u16 = 13;
+const MONITOR_CTRL_COMMAND: u16 = 16;
+const MONITOR_CTRL_EVENT: u16 = 17;
+
+const MONITOR_INDEX_NONE: u16 = 0xFFFF;
+
+/// The syslog priority of the user logging records that hold the payloads
+/// of L2CAP, RFCOMM and ISO sockets.
+const LOG_INFO: u8 = 6;
+
+/// A [`PacketLogger`] which writes a btsnoop file in the format of the Linux
+/// Bluetooth monitor.
+///
+/// Management messages are written as control commands and events, so
+/// Wireshark decodes them. The payloads of L2CAP, RFCOMM and ISO sockets have
+/// no link layer headers, so they are written as user logging records whose
+/// identifier is the protocol and direction, such as `l2cap tx`.
+///
+/// Errors while writing are ignored, because they cannot be reported to the
+/// socket operation that logs the packet. Call [`BtsnoopWriter::flush`] to
+/// check that the capture was written.
+pub struct BtsnoopWriter<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl BtsnoopWriter<BufWriter<File>> {
+    /// Creates a btsnoop file at `path`, replacing it if it already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> BtsnoopWriter<W> {
+    /// Writes the btsnoop file header to `writer`.
+    pub fn new(mut writer: W) -> Result<Self, std::io::Error> {
+        let mut header = BytesMut::with_capacity(16);
+        header.put_slice(b"btsnoop\0");
+        header.put_u32(1);
+        header.put_u32(BTSNOOP_LINUX_MONITOR);
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.lock().flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, W> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the monitor opcode, controller index and data of the record
+    /// for `packet`, or `None` if it cannot be written.
+    fn record(packet: &Packet<'_>) -> Option<(u16, u16, BytesMut)> {
+        let data = packet.data;
+
+        if packet.protocol == Protocol::HCI {
+            // opcode or event code, controller index, parameter length
+            if data.len() < 6 {
+                return None;
+            }
+
+            let opcode = match packet.direction {
+                Direction::Sent => MONITOR_CTRL_COMMAND,
+                Direction::Received => MONITOR_CTRL_EVENT,
+            };
+
+            let mut record = BytesMut::with_capacity(data.len());
+            // the cookie of the socket, which is only known to the kernel
+            record.put_u32_le(0);
+            record.put_slice(&data[0..2]);
+            record.put_slice(&data[6..]);
+
+            return Some((opcode, u16::from_le_bytes([data[2], data[3]]), record));
+        }
+
+        let ident = format!(
+            "{} {}",
+            format!("{:?}", packet.protocol).to_lowercase(),
+            match packet.direction {
+                Direction::Sent => "tx",
+                Direction::Received => "rx",
+            }
+        );
+
+        let mut record = BytesMut::with_capacity(3 + ident.len() + data.len());
+        record.put_u8(LOG_INFO);
+        record.put_u8(ident.len() as u8 + 1);
+        record.put_slice(ident.as_bytes());
+        record.put_u8(0);
+        record.put_slice(data);
+
+        Some((MONITOR_USER_LOGGING, MONITOR_INDEX_NONE, record))
+    }
+}
+
+impl<W: Write + Send> PacketLogger for BtsnoopWriter<W> {
+    fn log(&self, packet: &Packet<'_>) {
+        let (opcode, index, record) = match Self::record(packet) {
+            Some(record) => record,
+            None => return,
+        };
+
+        let timestamp = packet
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_micros() as i64)
+            .unwrap_or(0)
+            + BTSNOOP_EPOCH_OFFSET;
+
+        let mut header = BytesMut::with_capacity(24);
+        header.put_u32(record.len() as u32);
+        header.put_u32(record.len() as u32);
+        header.put_u32(((index as u32) << 16) | opcode as u32);
+        header.put_u32(0);
+        header.put_i64(timestamp);
+
+        let mut writer = self.lock();
+        let _ = writer
+            .write_all(&header)
+            .and_then(|_| writer.write_all(&record));
+    }
+}
+
+impl<W: Write + Send> fmt::Debug for BtsnoopWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BtsnoopWriter").finish_non_exhaustive()
+    }
+}
+
+/// The start of a trace that is written by a [`Recorder`], which includes
+/// the version of the format.
+pub(crate) const TRACE_MAGIC: &[u8; 8] = b"mgmttrc1";
+
+/// A [`PacketLogger`] which writes every message of the management sockets
+/// that it is attached to, in the order in which they were sent and
+/// received.
+///
+/// The trace starts with [`TRACE_MAGIC`], followed by a record for every
+/// message: the direction as a byte (0 for sent and 1 for received), the
+/// time since the recorder was created in microseconds as a 64-bit integer,
+/// the length of the message as a 16-bit integer, and the message including
+/// its header. Integers are little-endian. Packets of other sockets are
+/// ignored.
+///
+/// As with [`BtsnoopWriter`], errors while writing are ignored; call
+/// [`Recorder::flush`] to check that the trace was written.
+pub struct Recorder<W: Write + Send> {
+    writer: Mutex<W>,
+    start: Instant,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a trace file at `path`, replacing it if it already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send + 'static> Recorder<W> {
+    /// Starts recording the messages of `stream`, replacing its current
+    /// packet logger.
+    pub fn attach(self: &Arc<Self>, stream: &mut ManagementStream) {
+        stream.set_packet_logger(Some(self.clone()));
+    }
+}
+
+impl<W: Write + Send> Recorder<W> {
+    /// Writes the start of the trace to `writer`.
+    pub fn new(mut writer: W) -> Result<Self, std::io::Error> {
+        writer.write_all(TRACE_MAGIC)?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.lock().flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, W> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<W: Write + Send> PacketLogger for Recorder<W> {
+    fn log(&self, packet: &Packet<'_>) {
+        if packet.protocol != Protocol::HCI || packet.data.len() > u16::MAX as usize {
+            return;
+        }
+
+        let mut record = BytesMut::with_capacity(11 + packet.data.len());
+        record.put_u8(match packet.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        });
+        record.put_u64_le(self.start.elapsed().as_micros() as u64);
+        record.put_u16_le(packet.data.len() as u16);
+        record.put_slice(packet.data);
+
+        let _ = self.lock().write_all(&record);
+    }
+}
+
+impl<W: Write + Send> fmt::Debug for Recorder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn btsnoop_management_command() {
+        let writer = BtsnoopWriter::new(Vec::new()).unwrap();
+
+        // Set Powered on hci0
+        writer.log(&Packet {
+            protocol: Protocol::HCI,
+            direction: Direction::Sent,
+            timestamp: UNIX_EPOCH + Duration::from_micros(1),
+            data: &[0x05, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01],
+        });
+
+        let file = writer.into_inner();
+        assert_eq!(&file[..8], b"btsnoop\0");
+        assert_eq!(&file[8..16], &[0, 0, 0, 1, 0, 0, 0x07, 0xD1]);
+
+        let record = &file[16..];
+        // lengths, then index 0 and opcode 16
+        assert_eq!(&record[..12], &[0, 0, 0, 7, 0, 0, 0, 7, 0, 0, 0, 16]);
+        assert_eq!(&record[16..24], &0x00E0_3AB4_4A67_6001i64.to_be_bytes());
+        assert_eq!(&record[24..], &[0, 0, 0, 0, 0x05, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn btsnoop_stream_payload() {
+        let writer = BtsnoopWriter::new(Vec::new()).unwrap();
+
+        writer.log(&Packet {
+            protocol: Protocol::L2CAP,
+            direction: Direction::Received,
+            timestamp: UNIX_EPOCH,
+            data: &[0xAA],
+        });
+
+        let file = writer.into_inner();
+        let record = &file[16..];
+        assert_eq!(&record[8..12], &[0xFF, 0xFF, 0, 13]);
+        assert_eq!(&record[24..], b"\x06\x09l2cap rx\0\xAA");
+    }
+}
@@ -0,0 +1,633 @@
+//! The LE central role in one object.
+//!
+//! A [`Central`] scans for LE devices, filters and deduplicates what it
+//! finds, connects to devices and opens a GATT client to them, and
+//! reconnects them when they are disconnected. [`Central::next_event`]
+//! reports all of this as one stream of [`CentralEvent`]s, while it restarts
+//! discovery and makes reconnection attempts in the background.
+
+use std::collections::{HashSet, VecDeque};
+
+use bytes::Bytes;
+use enumflags2::BitFlags;
+use futures::stream::{self, Stream};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::communication::att::{self, AttBearer, EattClient, DEFAULT_LE_MTU};
+use crate::communication::BluetoothStream;
+use crate::management::{
+    connect_device, AddressTypeFlag, ConnectOptions, ConnectedDevice, ContinuousDiscovery,
+    Controller, DeviceFlag, DisconnectionReason, DiscoveryFilter, DiscoverySession, EirData, Event,
+    ManagementStream, PairingAgent, ReconnectConfig, ReconnectEvent, ReconnectPolicy, Response,
+    Result,
+};
+use crate::{Address, AddressType, Error};
+
+/// Which devices [`Central::scan`] reports.
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilter {
+    /// Only report devices that advertise one of these service UUIDs. If this
+    /// is not empty, the kernel filters the devices using
+    /// [`start_service_discovery`](crate::management::start_service_discovery).
+    pub uuids: Vec<[u8; 16]>,
+
+    /// Only report devices with at least this RSSI.
+    pub rssi_threshold: Option<i8>,
+
+    /// Only report devices whose advertised name contains this string.
+    pub name: Option<String>,
+
+    /// Report every device only once until the next call to
+    /// [`Central::scan`], instead of for every advertisement.
+    pub deduplicate: bool,
+}
+
+impl ScanFilter {
+    fn matches(&self, device: &ScannedDevice) -> bool {
+        if let Some(rssi_threshold) = self.rssi_threshold {
+            if device.rssi < rssi_threshold {
+                return false;
+            }
+        }
+
+        match &self.name {
+            Some(name) => device
+                .eir()
+                .name()
+                .is_some_and(|device_name| device_name.contains(name.as_str())),
+            None => true,
+        }
+    }
+}
+
+/// A device that was found by [`Central::scan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedDevice {
+    pub address: Address,
+    pub address_type: AddressType,
+    pub rssi: i8,
+    pub flags: BitFlags<DeviceFlag>,
+    pub eir_data: Bytes,
+}
+
+impl ScannedDevice {
+    /// Parses the advertising data of the device.
+    pub fn eir(&self) -> EirData {
+        EirData::parse(&self.eir_data)
+    }
+}
+
+/// A device that was connected with [`Central::connect`], with a GATT client
+/// on the fixed ATT channel of the link.
+#[derive(Debug)]
+pub struct ConnectedPeripheral {
+    pub device: ConnectedDevice,
+
+    /// The GATT client, which starts out with one bearer with an MTU of
+    /// [`DEFAULT_LE_MTU`]. It stops working when the device disconnects,
+    /// even if the central reconnects it; use
+    /// [`ConnectedPeripheral::reopen_gatt`] then.
+    pub gatt: EattClient,
+}
+
+impl ConnectedPeripheral {
+    /// Opens a new GATT client to the device, for example after the central
+    /// reconnected it.
+    pub async fn reopen_gatt(&mut self) -> std::result::Result<(), att::Error> {
+        self.gatt = open_gatt(self.device.address, self.device.address_type).await?;
+        Ok(())
+    }
+
+    /// Enables notifications, or indications if `indicate` is set, of the
+    /// characteristic whose value has the handle `value_handle`, by writing
+    /// its Client Characteristic Configuration descriptor at `cccd_handle`.
+    /// Returns the values that the device sends for the characteristic.
+    ///
+    /// Notifications of other characteristics that arrive while the stream
+    /// is polled are dropped, so use [`EattClient::recv_notification`] on
+    /// [`ConnectedPeripheral::gatt`] to receive several characteristics at
+    /// once.
+    pub async fn subscribe(
+        &mut self,
+        value_handle: u16,
+        cccd_handle: u16,
+        indicate: bool,
+    ) -> std::result::Result<
+        impl Stream<Item = std::result::Result<Bytes, att::Error>> + '_,
+        att::Error,
+    > {
+        let value: &[u8] = if indicate {
+            &[0x02, 0x00]
+        } else {
+            &[0x01, 0x00]
+        };
+        self.gatt
+            .write(cccd_handle, Bytes::from_static(value))
+            .await?;
+
+        Ok(stream::unfold(&mut self.gatt, move |gatt| async move {
+            loop {
+                match gatt.recv_notification().await {
+                    Ok(notification) if notification.handle == value_handle => {
+                        return Some((Ok(notification.value), gatt))
+                    }
+                    Ok(_) => continue,
+                    Err(att::Error::BearerClosed) => return None,
+                    Err(err) => return Some((Err(err), gatt)),
+                }
+            }
+        }))
+    }
+}
+
+/// Opens a GATT client on the fixed ATT channel to a device.
+async fn open_gatt(
+    address: Address,
+    address_type: AddressType,
+) -> std::result::Result<EattClient, att::Error> {
+    let stream = BluetoothStream::connect_att(address, address_type).await?;
+    Ok(EattClient::new(vec![AttBearer::with_mtu(
+        stream,
+        DEFAULT_LE_MTU,
+    )]))
+}
+
+/// Something that happened while a [`Central`] was running.
+#[derive(Debug)]
+pub enum CentralEvent {
+    /// A device that matches the filter of the scan was found.
+    DeviceFound(ScannedDevice),
+
+    /// A device disconnected from the controller.
+    Disconnected {
+        address: Address,
+        address_type: AddressType,
+        reason: DisconnectionReason,
+    },
+
+    /// A device that was connected with [`Central::connect`] was
+    /// reconnected, or an attempt to reconnect it failed.
+    Reconnect(ReconnectEvent),
+}
+
+/// Scans for LE devices, connects to them and reconnects them when they are
+/// disconnected, using one management socket.
+///
+/// This combines a [`DiscoverySession`] that restarts discovery whenever
+/// the kernel ends it, [`connect_device`] and a [`ReconnectPolicy`].
+/// Connected devices come with a GATT client on the fixed ATT channel; open
+/// Enhanced ATT bearers or other channels to them with [`EattClient`] and
+/// [`BluetoothStream`].
+///
+/// Like [`DiscoverySession`], the central only restarts discovery and
+/// reconnects devices while [`Central::next_event`] is being awaited.
+#[derive(Debug)]
+pub struct Central {
+    socket: ManagementStream,
+    controller: Controller,
+    discovery: DiscoverySession,
+    filter: ScanFilter,
+    seen: HashSet<Address>,
+    reconnect: ReconnectPolicy,
+    reconnect_config: Option<ReconnectConfig>,
+    /// Events that were received while discovery was restarted or a device
+    /// was reconnected, which have not been processed yet.
+    pending: VecDeque<Response>,
+}
+
+impl Central {
+    pub fn new(socket: ManagementStream, controller: Controller) -> Self {
+        Self {
+            socket,
+            controller,
+            discovery: DiscoverySession::new(
+                controller,
+                AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom,
+            ),
+            filter: ScanFilter::default(),
+            seen: HashSet::new(),
+            reconnect: ReconnectPolicy::new(),
+            reconnect_config: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Reconnects the devices that are connected with [`Central::connect`]
+    /// when they are disconnected.
+    pub fn with_reconnect(mut self, config: ReconnectConfig) -> Self {
+        self.reconnect_config = Some(config);
+        self
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    /// The management socket of the central, for commands that it does not
+    /// wrap.
+    pub fn socket(&mut self) -> &mut ManagementStream {
+        &mut self.socket
+    }
+
+    /// Whether the central is scanning.
+    pub fn is_scanning(&self) -> bool {
+        self.discovery.is_active()
+    }
+
+    /// Starts scanning for LE devices that match `filter`. The devices are
+    /// reported by [`Central::next_event`]. If the central is already
+    /// scanning, the scan is restarted with the new filter.
+    pub async fn scan(
+        &mut self,
+        filter: ScanFilter,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<()> {
+        if self.discovery.is_active() {
+            self.discovery
+                .stop(&mut self.socket, event_tx.clone())
+                .await?;
+        }
+
+        let discovery_filter = if filter.uuids.is_empty() {
+            DiscoveryFilter::None
+        } else {
+            DiscoveryFilter::Service {
+                rssi_threshold: filter.rssi_threshold.unwrap_or(127),
+                uuids: filter.uuids.clone(),
+            }
+        };
+
+        self.discovery = DiscoverySession::new(
+            self.controller,
+            AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom,
+        )
+        .with_filter(discovery_filter)
+        .continuous(ContinuousDiscovery::default());
+        self.filter = filter;
+        self.seen.clear();
+
+        self.discovery.start(&mut self.socket, event_tx).await?;
+        Ok(())
+    }
+
+    /// Stops scanning.
+    pub async fn stop_scan(&mut self, event_tx: Option<mpsc::Sender<Response>>) -> Result<()> {
+        if self.discovery.is_active() {
+            self.discovery.stop(&mut self.socket, event_tx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Connects to a device that was found by a scan, using
+    /// [`connect_device`], opens a GATT client to it, and starts watching it
+    /// if the central reconnects devices.
+    pub async fn connect(
+        &mut self,
+        device: &ScannedDevice,
+        options: ConnectOptions,
+        agent: Option<&mut dyn PairingAgent>,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> std::result::Result<ConnectedPeripheral, Error> {
+        let connected = connect_device(
+            &mut self.socket,
+            self.controller,
+            device.address,
+            device.address_type,
+            options,
+            agent,
+            event_tx,
+        )
+        .await?;
+
+        let gatt = open_gatt(device.address, device.address_type).await?;
+
+        if let Some(config) = self.reconnect_config {
+            self.reconnect
+                .watch(self.controller, device.address, device.address_type, config);
+        }
+
+        Ok(ConnectedPeripheral {
+            device: connected,
+            gatt,
+        })
+    }
+
+    /// Disconnects a device and stops reconnecting it.
+    pub async fn disconnect(
+        &mut self,
+        device: &ConnectedDevice,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<()> {
+        self.reconnect.unwatch(device.controller, device.address);
+        device.disconnect(&mut self.socket, event_tx).await
+    }
+
+    /// Processes events, restarts discovery and reconnects devices when it is
+    /// due, until something happens that should be reported to the caller.
+    ///
+    /// All other events received while this function is running are
+    /// forwarded to `event_tx`. Events that are received while discovery is
+    /// restarted or a device is reconnected are processed once that has
+    /// finished, so they are reported by this function like any other event,
+    /// after the outcome of the reconnection attempt.
+    pub async fn next_event(
+        &mut self,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<CentralEvent> {
+        loop {
+            let restart = self.discovery.next_restart();
+            let attempt = self.reconnect.next_attempt();
+
+            let deadline = match (restart, attempt) {
+                (Some(restart), Some((attempt, _))) => Some(restart.min(attempt)),
+                (restart, attempt) => restart.or(attempt.map(|(at, _)| at)),
+            };
+
+            let response = match (self.pending.pop_front(), deadline) {
+                (Some(response), _) => response,
+                // receive is cancel safe, see ManagementStream::receive
+                (None, Some(at)) => {
+                    match tokio::time::timeout_at(at, self.socket.receive()).await {
+                        Ok(response) => response?,
+                        Err(_) => {
+                            if let Some(event) = self.run_due().await? {
+                                return Ok(event);
+                            }
+
+                            continue;
+                        }
+                    }
+                }
+                (None, None) => self.socket.receive().await?,
+            };
+
+            self.discovery.handle_event(&response);
+            self.reconnect.handle_event(&response);
+
+            if let Some(event) = self.handle_event(&response) {
+                return Ok(event);
+            }
+
+            if let Some(event_tx) = &event_tx {
+                let _ = event_tx.send(response).await;
+            }
+        }
+    }
+
+    /// Restarts discovery or makes a reconnection attempt, whichever is due.
+    async fn run_due(&mut self) -> Result<Option<CentralEvent>> {
+        let now = Instant::now();
+
+        if self.discovery.next_restart().is_some_and(|at| at <= now) {
+            let restarted = self.discovery.restart(&mut self.socket).await;
+            self.pending.extend(self.discovery.take_pending());
+            restarted?;
+        }
+
+        match self.reconnect.next_attempt() {
+            Some((at, key)) if at <= now => {
+                let event = self.reconnect.attempt(&mut self.socket, key).await;
+                self.pending.extend(self.reconnect.take_pending());
+                Ok(event.map(CentralEvent::Reconnect))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn handle_event(&mut self, response: &Response) -> Option<CentralEvent> {
+        if response.controller != self.controller {
+            return None;
+        }
+
+        match &response.event {
+            Event::DeviceFound {
+                address,
+                address_type,
+                rssi,
+                flags,
+                eir_data,
+            } if self.discovery.is_active() => {
+                let device = ScannedDevice {
+                    address: *address,
+                    address_type: *address_type,
+                    rssi: *rssi,
+                    flags: *flags,
+                    eir_data: eir_data.clone(),
+                };
+
+                if !self.filter.matches(&device) {
+                    return None;
+                }
+
+                if self.filter.deduplicate && !self.seen.insert(device.address) {
+                    return None;
+                }
+
+                Some(CentralEvent::DeviceFound(device))
+            }
+            Event::DeviceDisconnected {
+                address,
+                address_type,
+                reason,
+            } => Some(CentralEvent::Disconnected {
+                address: *address,
+                address_type: *address_type,
+                reason: *reason,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn into_inner(self) -> ManagementStream {
+        self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::communication::att::AttPdu;
+    use crate::management::Command;
+    use crate::testing::mock::{
+        address_param, converse, device_connected, device_found, MockKernel, MOCK_ADDRESS,
+        MOCK_CONTROLLER,
+    };
+
+    #[tokio::test]
+    async fn scan_filters_devices() {
+        let (socket, mut kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let lamp_address = Address::from([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]);
+        let mut central = Central::new(socket, controller);
+
+        let filter = ScanFilter {
+            name: Some("lamp".into()),
+            deduplicate: true,
+            ..ScanFilter::default()
+        };
+        let (started, request) = futures::join!(central.scan(filter, None), kernel.answer(&[0x06]));
+        started.unwrap();
+        assert_eq!(request.unwrap().opcode, Command::StartDiscovery);
+
+        let kernel = async {
+            // a device without a name, then the same lamp twice
+            let unnamed = device_found(MOCK_ADDRESS, AddressType::LEPublic, -60, &[]);
+            kernel
+                .send_event(controller, 0x0012, &unnamed)
+                .await
+                .unwrap();
+
+            // the complete local name
+            let lamp = device_found(lamp_address, AddressType::LERandom, -60, b"\x06\x09lamp1");
+            for _ in 0..2 {
+                kernel.send_event(controller, 0x0012, &lamp).await.unwrap();
+            }
+
+            // disconnected by the remote device
+            let mut disconnected = address_param(lamp_address, AddressType::LERandom);
+            disconnected.push(0x02);
+            kernel
+                .send_event(controller, 0x000C, &disconnected)
+                .await
+                .unwrap();
+        };
+
+        let events = async {
+            let found = central.next_event(None).await.unwrap();
+            let disconnected = central.next_event(None).await.unwrap();
+            (found, disconnected)
+        };
+
+        let (found, disconnected) = converse(events, kernel).await;
+
+        match found {
+            CentralEvent::DeviceFound(device) => {
+                assert_eq!(device.address_type, AddressType::LERandom);
+                assert_eq!(device.eir().name(), Some("lamp1"));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // the second advertisement of the lamp is not reported again
+        assert!(matches!(disconnected, CentralEvent::Disconnected { .. }));
+    }
+
+    #[tokio::test]
+    async fn device_found_during_reconnect() {
+        let (socket, mut kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let address = MOCK_ADDRESS;
+        let other = Address::from([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]);
+        let mut central = Central::new(socket, controller);
+
+        let (started, _) = futures::join!(
+            central.scan(ScanFilter::default(), None),
+            kernel.answer(&[0x06])
+        );
+        started.unwrap();
+
+        let config = ReconnectConfig {
+            initial_delay: Duration::from_millis(1),
+            ..ReconnectConfig::default()
+        };
+        central
+            .reconnect
+            .watch(controller, address, AddressType::LEPublic, config);
+        central.reconnect.schedule(controller, address);
+
+        // a device is found while the other one is being reconnected, so the
+        // event is received by connect_device
+        let kernel = async {
+            let request = kernel.answer(&[0x00, 0x00]).await.unwrap();
+            assert_eq!(request.opcode, Command::GetConnections);
+            let request = kernel
+                .answer(&address_param(address, AddressType::LEPublic))
+                .await
+                .unwrap();
+            assert_eq!(request.opcode, Command::AddDevice);
+
+            let found = device_found(other, AddressType::LERandom, -60, &[]);
+            kernel.send_event(controller, 0x0012, &found).await.unwrap();
+            let connected = device_connected(address, AddressType::LEPublic);
+            kernel
+                .send_event(controller, 0x000B, &connected)
+                .await
+                .unwrap();
+        };
+
+        let events = async {
+            let reconnected = central.next_event(None).await.unwrap();
+            let found = central.next_event(None).await.unwrap();
+            (reconnected, found)
+        };
+
+        let (reconnected, found) = converse(events, kernel).await;
+        assert!(matches!(
+            reconnected,
+            CentralEvent::Reconnect(ReconnectEvent::Reconnected(_))
+        ));
+        assert!(matches!(found, CentralEvent::DeviceFound(_)));
+    }
+
+    #[tokio::test]
+    async fn subscribe_notifications() {
+        let (stream, mut server) = BluetoothStream::pair(crate::Protocol::L2CAP).unwrap();
+        let mut peripheral = ConnectedPeripheral {
+            device: ConnectedDevice {
+                controller: MOCK_CONTROLLER,
+                address: MOCK_ADDRESS,
+                address_type: AddressType::LEPublic,
+                flags: BitFlags::empty(),
+                eir_data: Bytes::new(),
+                paired: false,
+            },
+            gatt: EattClient::new(vec![AttBearer::with_mtu(stream, DEFAULT_LE_MTU)]),
+        };
+
+        // the server closes its socket once it has sent the notifications
+        let server = async move {
+            let mut buf = [0; 64];
+            let len = server.read(&mut buf).await.unwrap();
+            assert_eq!(
+                AttPdu::parse(Bytes::copy_from_slice(&buf[..len])).unwrap(),
+                AttPdu::WriteRequest {
+                    handle: 0x0011,
+                    value: Bytes::from_static(&[0x01, 0x00]),
+                }
+            );
+            server
+                .write_all(&AttPdu::WriteResponse.encode())
+                .await
+                .unwrap();
+
+            // a notification of another characteristic, which is skipped
+            for (handle, value) in [(0x0020, 0x01), (0x0010, 0x02)] {
+                let notification = AttPdu::HandleValueNotification {
+                    handle,
+                    value: Bytes::copy_from_slice(&[value]),
+                };
+                server.write_all(&notification.encode()).await.unwrap();
+            }
+        };
+
+        let client = async {
+            let values = peripheral.subscribe(0x0010, 0x0011, false).await.unwrap();
+            futures::pin_mut!(values);
+            let value = values.next().await.unwrap().unwrap();
+
+            // the stream ends when the device disconnects
+            assert!(values.next().await.is_none());
+            value
+        };
+
+        let (value, _) = futures::join!(client, server);
+        assert_eq!(&value[..], &[0x02]);
+    }
+}
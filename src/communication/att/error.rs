@@ -0,0 +1,26 @@
+use super::pdu::{ErrorCode, Opcode};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid pdu")]
+    InvalidPdu,
+
+    #[error("the remote device rejected {request:?} on handle {handle:#06x} with {code:?}")]
+    Rejected {
+        request: Opcode,
+        handle: u16,
+        code: ErrorCode,
+    },
+
+    #[error("the remote device answered {request:?} with {response:?}")]
+    UnexpectedResponse { request: Opcode, response: Opcode },
+
+    #[error("the remote device did not answer within 30 seconds")]
+    TimedOut,
+
+    #[error("the att bearer has been closed")]
+    BearerClosed,
+}
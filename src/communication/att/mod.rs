@@ -0,0 +1,557 @@
+//! The Attribute Protocol (ATT), which GATT runs over, with Enhanced ATT
+//! (EATT) bearers.
+//!
+//! An [`AttBearer`] carries one ATT transaction at a time: a request has to
+//! be answered before the next one can be sent on the same bearer. EATT
+//! lifts this limit by opening several bearers to the same device, which
+//! are L2CAP channels on [`EATT_PSM`] in
+//! [`L2capMode::ExtendedFlowControl`]. An [`EattClient`] spreads requests
+//! over its bearers, so that independent transactions, such as reads of
+//! several characteristics, run in parallel.
+//!
+//! Notifications and indications that arrive while a bearer waits for a
+//! response are queued, and indications are confirmed right away. As with
+//! the other protocols in this module, no tasks are spawned, so call
+//! [`EattClient::recv_notification`] in a loop to receive them.
+//!
+//! The other side of GATT is a [`GattServer`], which holds the services of
+//! the local device. Each client that connects is served by a
+//! [`GattConnection`], which answers its requests and tells which values it
+//! wrote and which characteristics it subscribed to.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::{join_all, select_all};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+pub use pdu::*;
+pub use server::*;
+
+use super::stream::{BluetoothStream, L2capMode};
+use super::{Psm, EATT_PSM};
+use crate::{Address, AddressType};
+
+mod error;
+mod pdu;
+mod server;
+
+/// The MTU of ATT on LE before it is exchanged, and the smallest MTU that
+/// bearers may use.
+pub const DEFAULT_LE_MTU: u16 = 23;
+
+/// The smallest MTU of Enhanced ATT bearers.
+pub const MIN_EATT_MTU: u16 = 64;
+
+/// How long the remote device has to answer a request. A bearer on which a
+/// request timed out may not be used anymore.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest PDU that is read from a bearer.
+const MAX_PDU_LEN: usize = u16::MAX as usize;
+
+/// A notification or indication of the value of an attribute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub handle: u16,
+    pub value: Bytes,
+    /// Whether the server asked for a confirmation. The bearer confirms
+    /// indications as soon as it receives them.
+    pub indication: bool,
+}
+
+/// One ATT bearer, on which this side acts as the client.
+///
+/// Requests from the server on the same bearer are answered with
+/// [`ErrorCode::RequestNotSupported`].
+#[derive(Debug)]
+pub struct AttBearer {
+    stream: BluetoothStream,
+    mtu: u16,
+    notifications: VecDeque<Notification>,
+    /// Whether a request has been sent whose response has not been read,
+    /// because the future that sent it was dropped.
+    outstanding: bool,
+    timed_out: bool,
+    buf: Vec<u8>,
+}
+
+impl AttBearer {
+    /// Creates a bearer on an L2CAP channel, using the smaller of the MTUs
+    /// that the channel was configured with.
+    pub fn new(stream: BluetoothStream) -> Result<Self, Error> {
+        let mtu = stream.send_mtu()?.min(stream.recv_mtu()?);
+        Ok(Self::with_mtu(stream, mtu))
+    }
+
+    /// Creates a bearer with a known MTU, for example on the unenhanced
+    /// bearer before the MTU has been exchanged, which uses
+    /// [`DEFAULT_LE_MTU`].
+    pub fn with_mtu(stream: BluetoothStream, mtu: u16) -> Self {
+        Self {
+            stream,
+            mtu,
+            notifications: VecDeque::new(),
+            outstanding: false,
+            timed_out: false,
+            buf: vec![0; MAX_PDU_LEN],
+        }
+    }
+
+    /// The largest PDU that may be sent on this bearer.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Agrees on a larger MTU with the server, and returns it. This may only
+    /// be used on the unenhanced bearer; the MTU of Enhanced ATT bearers is
+    /// the MTU of their L2CAP channel.
+    pub async fn exchange_mtu(&mut self, mtu: u16) -> Result<u16, Error> {
+        match self.request(AttPdu::ExchangeMtuRequest { mtu }).await? {
+            AttPdu::ExchangeMtuResponse { mtu: server_mtu } => {
+                self.mtu = mtu.min(server_mtu).max(DEFAULT_LE_MTU);
+                Ok(self.mtu)
+            }
+            response => Err(unexpected(Opcode::ExchangeMtuRequest, &response)),
+        }
+    }
+
+    /// Sends a request and waits for its response. An Error Response is
+    /// returned as [`Error::Rejected`].
+    ///
+    /// If the server does not answer within 30 seconds, this fails with
+    /// [`Error::TimedOut`], and the bearer cannot be used anymore.
+    pub async fn request(&mut self, request: AttPdu) -> Result<AttPdu, Error> {
+        let opcode = request.opcode();
+
+        if self.timed_out {
+            return Err(Error::BearerClosed);
+        }
+
+        // the response to a request whose future was dropped is still on its
+        // way, and would be taken for the response to this one
+        if self.outstanding {
+            self.wait_for_response().await?;
+        }
+
+        self.stream.write_all(&request.encode()[..]).await?;
+        self.outstanding = true;
+
+        match self.wait_for_response().await? {
+            AttPdu::ErrorResponse { handle, code, .. } => Err(Error::Rejected {
+                request: opcode,
+                handle,
+                code,
+            }),
+            response => Ok(response),
+        }
+    }
+
+    /// Sends a PDU which the server does not answer, such as a Write Command.
+    pub async fn command(&mut self, command: AttPdu) -> Result<(), Error> {
+        self.stream.write_all(&command.encode()[..]).await?;
+        Ok(())
+    }
+
+    /// Waits for the next notification or indication from the server.
+    pub async fn recv_notification(&mut self) -> Result<Notification, Error> {
+        loop {
+            if let Some(notification) = self.notifications.pop_front() {
+                return Ok(notification);
+            }
+
+            let pdu = self.read_pdu().await?;
+
+            // a response may still arrive for a request whose future was
+            // dropped, and is not waited for anymore
+            if pdu.opcode().is_response() {
+                self.outstanding = false;
+                continue;
+            }
+
+            self.handle_pdu(pdu).await?;
+        }
+    }
+
+    /// Returns the stream that this bearer runs on.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+
+    async fn wait_for_response(&mut self) -> Result<AttPdu, Error> {
+        let response = tokio::time::timeout(TRANSACTION_TIMEOUT, async {
+            loop {
+                let pdu = self.read_pdu().await?;
+
+                if pdu.opcode().is_response() {
+                    return Ok(pdu);
+                }
+
+                self.handle_pdu(pdu).await?;
+            }
+        })
+        .await;
+
+        match response {
+            Ok(response) => {
+                self.outstanding = false;
+                response
+            }
+            Err(_) => {
+                self.timed_out = true;
+                Err(Error::TimedOut)
+            }
+        }
+    }
+
+    /// Queues notifications, confirms indications and rejects requests.
+    async fn handle_pdu(&mut self, pdu: AttPdu) -> Result<(), Error> {
+        match pdu {
+            AttPdu::HandleValueNotification { handle, value } => {
+                self.notifications.push_back(Notification {
+                    handle,
+                    value,
+                    indication: false,
+                });
+            }
+            AttPdu::MultipleHandleValueNotification { values } => {
+                self.notifications
+                    .extend(values.into_iter().map(|(handle, value)| Notification {
+                        handle,
+                        value,
+                        indication: false,
+                    }));
+            }
+            AttPdu::HandleValueIndication { handle, value } => {
+                self.notifications.push_back(Notification {
+                    handle,
+                    value,
+                    indication: true,
+                });
+                self.command(AttPdu::HandleValueConfirmation).await?;
+            }
+            pdu if pdu.opcode().is_request() => {
+                let response = AttPdu::ErrorResponse {
+                    request: pdu.opcode(),
+                    handle: 0x0000,
+                    code: ErrorCode::RequestNotSupported,
+                };
+                self.command(response).await?;
+            }
+            // commands which a client does not handle are ignored
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn read_pdu(&mut self) -> Result<AttPdu, Error> {
+        let len = self.stream.read(&mut self.buf[..]).await?;
+
+        if len == 0 {
+            return Err(Error::BearerClosed);
+        }
+
+        AttPdu::parse(Bytes::copy_from_slice(&self.buf[..len]))
+    }
+}
+
+fn unexpected(request: Opcode, response: &AttPdu) -> Error {
+    Error::UnexpectedResponse {
+        request,
+        response: response.opcode(),
+    }
+}
+
+/// A GATT client which runs its transactions over several Enhanced ATT
+/// bearers to the same device.
+#[derive(Debug)]
+pub struct EattClient {
+    bearers: Vec<AttBearer>,
+    next: usize,
+}
+
+impl EattClient {
+    /// Opens `count` Enhanced ATT bearers to a device. The device has to be
+    /// connected already, and the link usually has to be encrypted, since
+    /// servers only accept EATT bearers on encrypted links.
+    ///
+    /// The kernel only supports Enhanced Credit Based Flow Control mode if
+    /// the `enable_ecred` parameter of the `bluetooth` module is set.
+    pub async fn connect(
+        address: Address,
+        address_type: AddressType,
+        count: usize,
+    ) -> Result<Self, Error> {
+        let psm = Psm::new(EATT_PSM, address_type).map_err(std::io::Error::from)?;
+
+        let streams = futures::future::try_join_all((0..count.max(1)).map(|_| {
+            BluetoothStream::connect_l2cap_mode(
+                address,
+                address_type,
+                psm,
+                L2capMode::ExtendedFlowControl,
+            )
+        }))
+        .await?;
+
+        let bearers = streams
+            .into_iter()
+            .map(AttBearer::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::new(bearers))
+    }
+
+    /// Creates a client from bearers that were opened elsewhere. This can
+    /// include the unenhanced bearer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bearers` is empty.
+    pub fn new(bearers: Vec<AttBearer>) -> Self {
+        assert!(!bearers.is_empty(), "an eatt client needs a bearer");
+        Self { bearers, next: 0 }
+    }
+
+    pub fn bearers(&self) -> &[AttBearer] {
+        &self.bearers
+    }
+
+    /// Sends a request on the next bearer, and waits for its response.
+    pub async fn request(&mut self, request: AttPdu) -> Result<AttPdu, Error> {
+        let index = self.next;
+        self.next = (self.next + 1) % self.bearers.len();
+        self.bearers[index].request(request).await
+    }
+
+    /// Runs several requests in parallel, and returns their results in the
+    /// same order. Every bearer takes the next request as soon as it has
+    /// received the response to its last one, so one slow transaction does
+    /// not hold up the others.
+    pub async fn requests(&mut self, requests: Vec<AttPdu>) -> Vec<Result<AttPdu, Error>> {
+        let count = requests.len();
+        let queue = Mutex::new(requests.into_iter().enumerate());
+        let results = Mutex::new((0..count).map(|_| None).collect::<Vec<_>>());
+
+        join_all(self.bearers.iter_mut().map(|bearer| {
+            let (queue, results) = (&queue, &results);
+
+            async move {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let (index, request) = match next {
+                        Some(next) => next,
+                        None => return,
+                    };
+
+                    let result = bearer.request(request).await;
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            }
+        }))
+        .await;
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.unwrap_or(Err(Error::BearerClosed)))
+            .collect()
+    }
+
+    /// Reads the value of an attribute.
+    pub async fn read(&mut self, handle: u16) -> Result<Bytes, Error> {
+        read_value(self.request(AttPdu::ReadRequest { handle }).await?)
+    }
+
+    /// Reads the values of several attributes in parallel.
+    pub async fn read_many(&mut self, handles: &[u16]) -> Vec<Result<Bytes, Error>> {
+        let requests = handles
+            .iter()
+            .map(|&handle| AttPdu::ReadRequest { handle })
+            .collect();
+
+        self.requests(requests)
+            .await
+            .into_iter()
+            .map(|response| read_value(response?))
+            .collect()
+    }
+
+    /// Writes the value of an attribute, and waits for the server to accept
+    /// it.
+    pub async fn write(&mut self, handle: u16, value: Bytes) -> Result<(), Error> {
+        match self.request(AttPdu::WriteRequest { handle, value }).await? {
+            AttPdu::WriteResponse => Ok(()),
+            response => Err(unexpected(Opcode::WriteRequest, &response)),
+        }
+    }
+
+    /// Writes the value of an attribute without waiting for the server.
+    pub async fn write_command(&mut self, handle: u16, value: Bytes) -> Result<(), Error> {
+        let index = self.next;
+        self.next = (self.next + 1) % self.bearers.len();
+        self.bearers[index]
+            .command(AttPdu::WriteCommand { handle, value })
+            .await
+    }
+
+    /// Waits for the next notification or indication on any bearer.
+    pub async fn recv_notification(&mut self) -> Result<Notification, Error> {
+        if let Some(notification) = self
+            .bearers
+            .iter_mut()
+            .find_map(|bearer| bearer.notifications.pop_front())
+        {
+            return Ok(notification);
+        }
+
+        // receiving is cancel safe, so the bearers that lose the race have
+        // not consumed anything
+        let (notification, _, _) = select_all(
+            self.bearers
+                .iter_mut()
+                .map(|bearer| Box::pin(bearer.recv_notification())),
+        )
+        .await;
+
+        notification
+    }
+}
+
+fn read_value(response: AttPdu) -> Result<Bytes, Error> {
+    match response {
+        AttPdu::ReadResponse { value } => Ok(value),
+        response => Err(unexpected(Opcode::ReadRequest, &response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    /// A bearer connected to a socket which plays the part of the server.
+    fn bearer() -> (AttBearer, UnixStream) {
+        let (stream, server) = BluetoothStream::pair(crate::Protocol::L2CAP).unwrap();
+        (AttBearer::with_mtu(stream, MIN_EATT_MTU), server)
+    }
+
+    async fn receive(server: &mut UnixStream) -> AttPdu {
+        let mut buf = vec![0; MAX_PDU_LEN];
+        let len = server.read(&mut buf).await.unwrap();
+        AttPdu::parse(Bytes::copy_from_slice(&buf[..len])).unwrap()
+    }
+
+    async fn send(server: &mut UnixStream, pdu: AttPdu) {
+        server.write_all(&pdu.encode()[..]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn parallel_reads() {
+        let (first, mut first_server) = bearer();
+        let (second, mut second_server) = bearer();
+        let mut client = EattClient::new(vec![first, second]);
+
+        // the first bearer answers only after the second one has answered
+        // both of its reads, which it can only do if they are not queued
+        // behind the first read
+        let servers = async {
+            let request = receive(&mut first_server).await;
+            assert_eq!(request, AttPdu::ReadRequest { handle: 1 });
+
+            for _ in 0..2 {
+                let handle = match receive(&mut second_server).await {
+                    AttPdu::ReadRequest { handle } => handle,
+                    request => panic!("unexpected request {:?}", request),
+                };
+                let value = Bytes::copy_from_slice(&[handle as u8]);
+                send(&mut second_server, AttPdu::ReadResponse { value }).await;
+            }
+
+            send(
+                &mut first_server,
+                AttPdu::ReadResponse {
+                    value: Bytes::from_static(&[0x01]),
+                },
+            )
+            .await;
+        };
+
+        let (values, ()) = futures::join!(client.read_many(&[1, 2, 3]), servers);
+        let values: Vec<_> = values.into_iter().map(Result::unwrap).collect();
+        assert_eq!(values, vec![&[1][..], &[2][..], &[3][..]]);
+    }
+
+    #[tokio::test]
+    async fn notifications_during_request() {
+        let (bearer, mut server) = bearer();
+        let mut client = EattClient::new(vec![bearer]);
+
+        let server = async {
+            receive(&mut server).await;
+
+            send(
+                &mut server,
+                AttPdu::HandleValueIndication {
+                    handle: 0x0010,
+                    value: Bytes::from_static(&[0x2A]),
+                },
+            )
+            .await;
+            assert_eq!(receive(&mut server).await, AttPdu::HandleValueConfirmation);
+
+            // the server acts as a client as well, which is not supported
+            send(&mut server, AttPdu::ReadRequest { handle: 0x0001 }).await;
+            assert!(matches!(
+                receive(&mut server).await,
+                AttPdu::ErrorResponse {
+                    request: Opcode::ReadRequest,
+                    code: ErrorCode::RequestNotSupported,
+                    ..
+                }
+            ));
+
+            send(
+                &mut server,
+                AttPdu::ErrorResponse {
+                    request: Opcode::WriteRequest,
+                    handle: 0x0003,
+                    code: ErrorCode::WriteNotPermitted,
+                },
+            )
+            .await;
+
+            send(
+                &mut server,
+                AttPdu::MultipleHandleValueNotification {
+                    values: vec![(0x0003, Bytes::from_static(&[0x01]))],
+                },
+            )
+            .await;
+        };
+
+        let (written, ()) =
+            futures::join!(client.write(0x0003, Bytes::from_static(&[0x01])), server);
+        assert!(matches!(
+            written,
+            Err(Error::Rejected {
+                code: ErrorCode::WriteNotPermitted,
+                ..
+            })
+        ));
+
+        let indication = client.recv_notification().await.unwrap();
+        assert_eq!(indication.handle, 0x0010);
+        assert!(indication.indication);
+
+        let notification = client.recv_notification().await.unwrap();
+        assert_eq!(notification.handle, 0x0003);
+        assert!(!notification.indication);
+    }
+}
@@ -0,0 +1,749 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+use crate::{Uuid, Uuid128, Uuid16};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    ErrorResponse,
+    ExchangeMtuRequest,
+    ExchangeMtuResponse,
+    FindInformationRequest,
+    FindInformationResponse,
+    FindByTypeValueRequest,
+    FindByTypeValueResponse,
+    ReadByTypeRequest,
+    ReadByTypeResponse,
+    ReadRequest,
+    ReadResponse,
+    ReadBlobRequest,
+    ReadBlobResponse,
+    ReadMultipleRequest,
+    ReadMultipleResponse,
+    ReadByGroupTypeRequest,
+    ReadByGroupTypeResponse,
+    WriteRequest,
+    WriteResponse,
+    WriteCommand,
+    PrepareWriteRequest,
+    PrepareWriteResponse,
+    ExecuteWriteRequest,
+    ExecuteWriteResponse,
+    HandleValueNotification,
+    HandleValueIndication,
+    HandleValueConfirmation,
+    ReadMultipleVariableRequest,
+    ReadMultipleVariableResponse,
+    MultipleHandleValueNotification,
+    SignedWriteCommand,
+    Other(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(opcode: u8) -> Self {
+        match opcode {
+            0x01 => Opcode::ErrorResponse,
+            0x02 => Opcode::ExchangeMtuRequest,
+            0x03 => Opcode::ExchangeMtuResponse,
+            0x04 => Opcode::FindInformationRequest,
+            0x05 => Opcode::FindInformationResponse,
+            0x06 => Opcode::FindByTypeValueRequest,
+            0x07 => Opcode::FindByTypeValueResponse,
+            0x08 => Opcode::ReadByTypeRequest,
+            0x09 => Opcode::ReadByTypeResponse,
+            0x0A => Opcode::ReadRequest,
+            0x0B => Opcode::ReadResponse,
+            0x0C => Opcode::ReadBlobRequest,
+            0x0D => Opcode::ReadBlobResponse,
+            0x0E => Opcode::ReadMultipleRequest,
+            0x0F => Opcode::ReadMultipleResponse,
+            0x10 => Opcode::ReadByGroupTypeRequest,
+            0x11 => Opcode::ReadByGroupTypeResponse,
+            0x12 => Opcode::WriteRequest,
+            0x13 => Opcode::WriteResponse,
+            0x52 => Opcode::WriteCommand,
+            0x16 => Opcode::PrepareWriteRequest,
+            0x17 => Opcode::PrepareWriteResponse,
+            0x18 => Opcode::ExecuteWriteRequest,
+            0x19 => Opcode::ExecuteWriteResponse,
+            0x1B => Opcode::HandleValueNotification,
+            0x1D => Opcode::HandleValueIndication,
+            0x1E => Opcode::HandleValueConfirmation,
+            0x20 => Opcode::ReadMultipleVariableRequest,
+            0x21 => Opcode::ReadMultipleVariableResponse,
+            0x23 => Opcode::MultipleHandleValueNotification,
+            0xD2 => Opcode::SignedWriteCommand,
+            opcode => Opcode::Other(opcode),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::ErrorResponse => 0x01,
+            Opcode::ExchangeMtuRequest => 0x02,
+            Opcode::ExchangeMtuResponse => 0x03,
+            Opcode::FindInformationRequest => 0x04,
+            Opcode::FindInformationResponse => 0x05,
+            Opcode::FindByTypeValueRequest => 0x06,
+            Opcode::FindByTypeValueResponse => 0x07,
+            Opcode::ReadByTypeRequest => 0x08,
+            Opcode::ReadByTypeResponse => 0x09,
+            Opcode::ReadRequest => 0x0A,
+            Opcode::ReadResponse => 0x0B,
+            Opcode::ReadBlobRequest => 0x0C,
+            Opcode::ReadBlobResponse => 0x0D,
+            Opcode::ReadMultipleRequest => 0x0E,
+            Opcode::ReadMultipleResponse => 0x0F,
+            Opcode::ReadByGroupTypeRequest => 0x10,
+            Opcode::ReadByGroupTypeResponse => 0x11,
+            Opcode::WriteRequest => 0x12,
+            Opcode::WriteResponse => 0x13,
+            Opcode::WriteCommand => 0x52,
+            Opcode::PrepareWriteRequest => 0x16,
+            Opcode::PrepareWriteResponse => 0x17,
+            Opcode::ExecuteWriteRequest => 0x18,
+            Opcode::ExecuteWriteResponse => 0x19,
+            Opcode::HandleValueNotification => 0x1B,
+            Opcode::HandleValueIndication => 0x1D,
+            Opcode::HandleValueConfirmation => 0x1E,
+            Opcode::ReadMultipleVariableRequest => 0x20,
+            Opcode::ReadMultipleVariableResponse => 0x21,
+            Opcode::MultipleHandleValueNotification => 0x23,
+            Opcode::SignedWriteCommand => 0xD2,
+            Opcode::Other(opcode) => opcode,
+        }
+    }
+}
+
+impl Opcode {
+    /// Whether the remote device has to answer this PDU with a response or
+    /// an Error Response. Commands, notifications and indications are not
+    /// requests.
+    pub fn is_request(self) -> bool {
+        let opcode = u8::from(self);
+
+        // requests have even opcodes without the command flag, which also
+        // holds for requests that this library does not know about; the only
+        // exception is the confirmation of an indication
+        opcode & 0x40 == 0 && opcode % 2 == 0 && self != Opcode::HandleValueConfirmation
+    }
+
+    /// Whether this PDU answers a request.
+    pub fn is_response(self) -> bool {
+        matches!(
+            self,
+            Opcode::ErrorResponse
+                | Opcode::ExchangeMtuResponse
+                | Opcode::FindInformationResponse
+                | Opcode::FindByTypeValueResponse
+                | Opcode::ReadByTypeResponse
+                | Opcode::ReadResponse
+                | Opcode::ReadBlobResponse
+                | Opcode::ReadMultipleResponse
+                | Opcode::ReadByGroupTypeResponse
+                | Opcode::WriteResponse
+                | Opcode::PrepareWriteResponse
+                | Opcode::ExecuteWriteResponse
+                | Opcode::ReadMultipleVariableResponse
+        )
+    }
+}
+
+/// The reason in an Error Response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    AttributeNotFound,
+    AttributeNotLong,
+    EncryptionKeySizeTooShort,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+    DatabaseOutOfSync,
+    ValueNotAllowed,
+    /// A code that is defined by the application or by a profile.
+    Other(u8),
+}
+
+impl From<u8> for ErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => ErrorCode::InvalidHandle,
+            0x02 => ErrorCode::ReadNotPermitted,
+            0x03 => ErrorCode::WriteNotPermitted,
+            0x04 => ErrorCode::InvalidPdu,
+            0x05 => ErrorCode::InsufficientAuthentication,
+            0x06 => ErrorCode::RequestNotSupported,
+            0x07 => ErrorCode::InvalidOffset,
+            0x08 => ErrorCode::InsufficientAuthorization,
+            0x09 => ErrorCode::PrepareQueueFull,
+            0x0A => ErrorCode::AttributeNotFound,
+            0x0B => ErrorCode::AttributeNotLong,
+            0x0C => ErrorCode::EncryptionKeySizeTooShort,
+            0x0D => ErrorCode::InvalidAttributeValueLength,
+            0x0E => ErrorCode::UnlikelyError,
+            0x0F => ErrorCode::InsufficientEncryption,
+            0x10 => ErrorCode::UnsupportedGroupType,
+            0x11 => ErrorCode::InsufficientResources,
+            0x12 => ErrorCode::DatabaseOutOfSync,
+            0x13 => ErrorCode::ValueNotAllowed,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::InvalidHandle => 0x01,
+            ErrorCode::ReadNotPermitted => 0x02,
+            ErrorCode::WriteNotPermitted => 0x03,
+            ErrorCode::InvalidPdu => 0x04,
+            ErrorCode::InsufficientAuthentication => 0x05,
+            ErrorCode::RequestNotSupported => 0x06,
+            ErrorCode::InvalidOffset => 0x07,
+            ErrorCode::InsufficientAuthorization => 0x08,
+            ErrorCode::PrepareQueueFull => 0x09,
+            ErrorCode::AttributeNotFound => 0x0A,
+            ErrorCode::AttributeNotLong => 0x0B,
+            ErrorCode::EncryptionKeySizeTooShort => 0x0C,
+            ErrorCode::InvalidAttributeValueLength => 0x0D,
+            ErrorCode::UnlikelyError => 0x0E,
+            ErrorCode::InsufficientEncryption => 0x0F,
+            ErrorCode::UnsupportedGroupType => 0x10,
+            ErrorCode::InsufficientResources => 0x11,
+            ErrorCode::DatabaseOutOfSync => 0x12,
+            ErrorCode::ValueNotAllowed => 0x13,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+/// A PDU of the Attribute Protocol.
+///
+/// The PDUs that this library uses are parsed into their fields; the others
+/// are kept as [`AttPdu::Other`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttPdu {
+    ErrorResponse {
+        request: Opcode,
+        handle: u16,
+        code: ErrorCode,
+    },
+    ExchangeMtuRequest {
+        mtu: u16,
+    },
+    ExchangeMtuResponse {
+        mtu: u16,
+    },
+    FindInformationRequest {
+        start: u16,
+        end: u16,
+    },
+    /// The handles and types of the attributes in the requested range. The
+    /// types have to be either all 16-bit or all 128-bit UUIDs.
+    FindInformationResponse {
+        information: Vec<(u16, Uuid)>,
+    },
+    ReadByTypeRequest {
+        start: u16,
+        end: u16,
+        attribute_type: Uuid,
+    },
+    /// The handles and values of the attributes with the requested type. The
+    /// values all have the same length.
+    ReadByTypeResponse {
+        values: Vec<(u16, Bytes)>,
+    },
+    ReadRequest {
+        handle: u16,
+    },
+    ReadResponse {
+        value: Bytes,
+    },
+    ReadBlobRequest {
+        handle: u16,
+        offset: u16,
+    },
+    ReadBlobResponse {
+        value: Bytes,
+    },
+    ReadByGroupTypeRequest {
+        start: u16,
+        end: u16,
+        group_type: Uuid,
+    },
+    /// The first handle, last handle and value of each group. The values
+    /// all have the same length.
+    ReadByGroupTypeResponse {
+        groups: Vec<(u16, u16, Bytes)>,
+    },
+    WriteRequest {
+        handle: u16,
+        value: Bytes,
+    },
+    WriteResponse,
+    WriteCommand {
+        handle: u16,
+        value: Bytes,
+    },
+    HandleValueNotification {
+        handle: u16,
+        value: Bytes,
+    },
+    HandleValueIndication {
+        handle: u16,
+        value: Bytes,
+    },
+    HandleValueConfirmation,
+    /// Notifications for several attributes at once, which may only be sent
+    /// on Enhanced ATT bearers.
+    MultipleHandleValueNotification {
+        values: Vec<(u16, Bytes)>,
+    },
+    Other {
+        opcode: Opcode,
+        param: Bytes,
+    },
+}
+
+fn put_uuid(buf: &mut BytesMut, uuid: Uuid) {
+    match uuid {
+        Uuid::Uuid16(uuid) => buf.put_u16_le(uuid.0),
+        Uuid::Uuid32(uuid) => buf.put_u128_le(Uuid128::from(uuid).0),
+        Uuid::Uuid128(uuid) => buf.put_u128_le(uuid.0),
+    }
+}
+
+fn get_uuid(buf: &mut Bytes) -> Result<Uuid, Error> {
+    match buf.len() {
+        2 => Ok(Uuid16(buf.get_u16_le()).into()),
+        16 => Ok(Uuid128(buf.get_u128_le()).into()),
+        _ => Err(Error::InvalidPdu),
+    }
+}
+
+/// The length of a UUID in a Find Information Response or a request, which
+/// is 2 for 16-bit UUIDs and 16 for all others.
+fn uuid_len(uuid: &Uuid) -> usize {
+    match uuid {
+        Uuid::Uuid16(_) => 2,
+        _ => 16,
+    }
+}
+
+impl AttPdu {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            AttPdu::ErrorResponse { .. } => Opcode::ErrorResponse,
+            AttPdu::ExchangeMtuRequest { .. } => Opcode::ExchangeMtuRequest,
+            AttPdu::ExchangeMtuResponse { .. } => Opcode::ExchangeMtuResponse,
+            AttPdu::FindInformationRequest { .. } => Opcode::FindInformationRequest,
+            AttPdu::FindInformationResponse { .. } => Opcode::FindInformationResponse,
+            AttPdu::ReadByTypeRequest { .. } => Opcode::ReadByTypeRequest,
+            AttPdu::ReadByTypeResponse { .. } => Opcode::ReadByTypeResponse,
+            AttPdu::ReadRequest { .. } => Opcode::ReadRequest,
+            AttPdu::ReadResponse { .. } => Opcode::ReadResponse,
+            AttPdu::ReadBlobRequest { .. } => Opcode::ReadBlobRequest,
+            AttPdu::ReadBlobResponse { .. } => Opcode::ReadBlobResponse,
+            AttPdu::ReadByGroupTypeRequest { .. } => Opcode::ReadByGroupTypeRequest,
+            AttPdu::ReadByGroupTypeResponse { .. } => Opcode::ReadByGroupTypeResponse,
+            AttPdu::WriteRequest { .. } => Opcode::WriteRequest,
+            AttPdu::WriteResponse => Opcode::WriteResponse,
+            AttPdu::WriteCommand { .. } => Opcode::WriteCommand,
+            AttPdu::HandleValueNotification { .. } => Opcode::HandleValueNotification,
+            AttPdu::HandleValueIndication { .. } => Opcode::HandleValueIndication,
+            AttPdu::HandleValueConfirmation => Opcode::HandleValueConfirmation,
+            AttPdu::MultipleHandleValueNotification { .. } => {
+                Opcode::MultipleHandleValueNotification
+            }
+            AttPdu::Other { opcode, .. } => *opcode,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_u8(self.opcode().into());
+
+        match self {
+            AttPdu::ErrorResponse {
+                request,
+                handle,
+                code,
+            } => {
+                buf.put_u8((*request).into());
+                buf.put_u16_le(*handle);
+                buf.put_u8((*code).into());
+            }
+            AttPdu::ExchangeMtuRequest { mtu } | AttPdu::ExchangeMtuResponse { mtu } => {
+                buf.put_u16_le(*mtu)
+            }
+            AttPdu::FindInformationRequest { start, end } => {
+                buf.put_u16_le(*start);
+                buf.put_u16_le(*end);
+            }
+            AttPdu::FindInformationResponse { information } => {
+                let format = match information.first() {
+                    Some((_, uuid)) if uuid_len(uuid) == 2 => 0x01,
+                    _ => 0x02,
+                };
+                buf.put_u8(format);
+
+                for (handle, uuid) in information {
+                    buf.put_u16_le(*handle);
+                    put_uuid(&mut buf, *uuid);
+                }
+            }
+            AttPdu::ReadByTypeRequest {
+                start,
+                end,
+                attribute_type: uuid,
+            }
+            | AttPdu::ReadByGroupTypeRequest {
+                start,
+                end,
+                group_type: uuid,
+            } => {
+                buf.put_u16_le(*start);
+                buf.put_u16_le(*end);
+                put_uuid(&mut buf, *uuid);
+            }
+            AttPdu::ReadByTypeResponse { values } => {
+                let len = values.first().map_or(0, |(_, value)| value.len());
+                buf.put_u8((2 + len) as u8);
+
+                for (handle, value) in values {
+                    buf.put_u16_le(*handle);
+                    buf.put_slice(&value[..]);
+                }
+            }
+            AttPdu::ReadRequest { handle } => buf.put_u16_le(*handle),
+            AttPdu::ReadResponse { value } | AttPdu::ReadBlobResponse { value } => {
+                buf.put_slice(&value[..])
+            }
+            AttPdu::ReadBlobRequest { handle, offset } => {
+                buf.put_u16_le(*handle);
+                buf.put_u16_le(*offset);
+            }
+            AttPdu::ReadByGroupTypeResponse { groups } => {
+                let len = groups.first().map_or(0, |(_, _, value)| value.len());
+                buf.put_u8((4 + len) as u8);
+
+                for (start, end, value) in groups {
+                    buf.put_u16_le(*start);
+                    buf.put_u16_le(*end);
+                    buf.put_slice(&value[..]);
+                }
+            }
+            AttPdu::WriteRequest { handle, value }
+            | AttPdu::WriteCommand { handle, value }
+            | AttPdu::HandleValueNotification { handle, value }
+            | AttPdu::HandleValueIndication { handle, value } => {
+                buf.put_u16_le(*handle);
+                buf.put_slice(&value[..]);
+            }
+            AttPdu::WriteResponse | AttPdu::HandleValueConfirmation => {}
+            AttPdu::MultipleHandleValueNotification { values } => {
+                for (handle, value) in values {
+                    buf.put_u16_le(*handle);
+                    buf.put_u16_le(value.len() as u16);
+                    buf.put_slice(&value[..]);
+                }
+            }
+            AttPdu::Other { param, .. } => buf.put_slice(&param[..]),
+        }
+
+        buf.freeze()
+    }
+
+    /// Parses a PDU. Returns [`Error::InvalidPdu`] if it is truncated or its
+    /// parameters are inconsistent, instead of panicking, so this can be used
+    /// on untrusted input.
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.is_empty() {
+            return Err(Error::InvalidPdu);
+        }
+
+        let opcode = Opcode::from(buf.get_u8());
+
+        // the length of the fixed parameters of each PDU
+        let fixed = match opcode {
+            Opcode::ErrorResponse => 4,
+            Opcode::ExchangeMtuRequest | Opcode::ExchangeMtuResponse => 2,
+            Opcode::FindInformationRequest => 4,
+            Opcode::FindInformationResponse => 1,
+            Opcode::ReadByTypeRequest | Opcode::ReadByGroupTypeRequest => 6,
+            Opcode::ReadByTypeResponse | Opcode::ReadByGroupTypeResponse => 1,
+            Opcode::ReadRequest => 2,
+            Opcode::ReadBlobRequest => 4,
+            Opcode::WriteRequest
+            | Opcode::WriteCommand
+            | Opcode::HandleValueNotification
+            | Opcode::HandleValueIndication => 2,
+            _ => 0,
+        };
+
+        if buf.len() < fixed {
+            return Err(Error::InvalidPdu);
+        }
+
+        Ok(match opcode {
+            Opcode::ErrorResponse => AttPdu::ErrorResponse {
+                request: buf.get_u8().into(),
+                handle: buf.get_u16_le(),
+                code: buf.get_u8().into(),
+            },
+            Opcode::ExchangeMtuRequest => AttPdu::ExchangeMtuRequest {
+                mtu: buf.get_u16_le(),
+            },
+            Opcode::ExchangeMtuResponse => AttPdu::ExchangeMtuResponse {
+                mtu: buf.get_u16_le(),
+            },
+            Opcode::FindInformationRequest => AttPdu::FindInformationRequest {
+                start: buf.get_u16_le(),
+                end: buf.get_u16_le(),
+            },
+            Opcode::FindInformationResponse => {
+                let uuid_len = match buf.get_u8() {
+                    0x01 => 2,
+                    0x02 => 16,
+                    _ => return Err(Error::InvalidPdu),
+                };
+
+                let mut information = vec![];
+                for mut entry in split_entries(buf, 2 + uuid_len)? {
+                    let handle = entry.get_u16_le();
+                    information.push((handle, get_uuid(&mut entry)?));
+                }
+
+                AttPdu::FindInformationResponse { information }
+            }
+            Opcode::ReadByTypeRequest | Opcode::ReadByGroupTypeRequest => {
+                let start = buf.get_u16_le();
+                let end = buf.get_u16_le();
+                let uuid = get_uuid(&mut buf)?;
+
+                match opcode {
+                    Opcode::ReadByTypeRequest => AttPdu::ReadByTypeRequest {
+                        start,
+                        end,
+                        attribute_type: uuid,
+                    },
+                    _ => AttPdu::ReadByGroupTypeRequest {
+                        start,
+                        end,
+                        group_type: uuid,
+                    },
+                }
+            }
+            Opcode::ReadByTypeResponse => {
+                let len = buf.get_u8() as usize;
+                if len < 2 {
+                    return Err(Error::InvalidPdu);
+                }
+
+                let values = split_entries(buf, len)?
+                    .map(|mut entry| (entry.get_u16_le(), entry))
+                    .collect();
+
+                AttPdu::ReadByTypeResponse { values }
+            }
+            Opcode::ReadRequest => AttPdu::ReadRequest {
+                handle: buf.get_u16_le(),
+            },
+            Opcode::ReadResponse => AttPdu::ReadResponse { value: buf },
+            Opcode::ReadBlobRequest => AttPdu::ReadBlobRequest {
+                handle: buf.get_u16_le(),
+                offset: buf.get_u16_le(),
+            },
+            Opcode::ReadBlobResponse => AttPdu::ReadBlobResponse { value: buf },
+            Opcode::ReadByGroupTypeResponse => {
+                let len = buf.get_u8() as usize;
+                if len < 4 {
+                    return Err(Error::InvalidPdu);
+                }
+
+                let groups = split_entries(buf, len)?
+                    .map(|mut entry| (entry.get_u16_le(), entry.get_u16_le(), entry))
+                    .collect();
+
+                AttPdu::ReadByGroupTypeResponse { groups }
+            }
+            Opcode::WriteRequest => AttPdu::WriteRequest {
+                handle: buf.get_u16_le(),
+                value: buf,
+            },
+            Opcode::WriteResponse => AttPdu::WriteResponse,
+            Opcode::WriteCommand => AttPdu::WriteCommand {
+                handle: buf.get_u16_le(),
+                value: buf,
+            },
+            Opcode::HandleValueNotification => AttPdu::HandleValueNotification {
+                handle: buf.get_u16_le(),
+                value: buf,
+            },
+            Opcode::HandleValueIndication => AttPdu::HandleValueIndication {
+                handle: buf.get_u16_le(),
+                value: buf,
+            },
+            Opcode::HandleValueConfirmation => AttPdu::HandleValueConfirmation,
+            Opcode::MultipleHandleValueNotification => {
+                let mut values = vec![];
+
+                while !buf.is_empty() {
+                    if buf.len() < 4 {
+                        return Err(Error::InvalidPdu);
+                    }
+
+                    let handle = buf.get_u16_le();
+                    let len = buf.get_u16_le() as usize;
+                    if buf.len() < len {
+                        return Err(Error::InvalidPdu);
+                    }
+
+                    values.push((handle, buf.split_to(len)));
+                }
+
+                AttPdu::MultipleHandleValueNotification { values }
+            }
+            opcode => AttPdu::Other { opcode, param: buf },
+        })
+    }
+}
+
+/// Splits the list of a response into entries of `len` bytes. The list must
+/// not be empty, and its length must be a multiple of `len`.
+fn split_entries(mut buf: Bytes, len: usize) -> Result<impl Iterator<Item = Bytes>, Error> {
+    if buf.is_empty() || !buf.chunks_exact(len).remainder().is_empty() {
+        return Err(Error::InvalidPdu);
+    }
+
+    Ok(std::iter::from_fn(move || {
+        if buf.is_empty() {
+            None
+        } else {
+            Some(buf.split_to(len))
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid32_encoding() {
+        // ATT has no 32-bit UUIDs, so they are sent as their 128-bit
+        // expansion
+        let pdu = AttPdu::ReadByTypeRequest {
+            start: 0x0001,
+            end: 0xFFFF,
+            attribute_type: 0x12345678u32.into(),
+        };
+
+        let mut expected = vec![0x08, 0x01, 0x00, 0xFF, 0xFF];
+        expected.extend_from_slice(&0x12345678_0000_1000_8000_00805F9B34FBu128.to_le_bytes());
+        assert_eq!(&pdu.encode()[..], &expected[..]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let pdus = vec![
+            AttPdu::ErrorResponse {
+                request: Opcode::ReadRequest,
+                handle: 0x0003,
+                code: ErrorCode::ReadNotPermitted,
+            },
+            AttPdu::ExchangeMtuRequest { mtu: 517 },
+            AttPdu::FindInformationResponse {
+                information: vec![(0x0004, Uuid16(0x2902).into())],
+            },
+            AttPdu::ReadByTypeRequest {
+                start: 0x0001,
+                end: 0xFFFF,
+                attribute_type: Uuid16(0x2803).into(),
+            },
+            AttPdu::ReadByTypeResponse {
+                values: vec![
+                    (0x0002, Bytes::from_static(&[0x02, 0x03, 0x00, 0x00, 0x2A])),
+                    (0x0004, Bytes::from_static(&[0x12, 0x05, 0x00, 0x01, 0x2A])),
+                ],
+            },
+            AttPdu::ReadByGroupTypeResponse {
+                groups: vec![(0x0001, 0x0005, Bytes::from_static(&[0x00, 0x18]))],
+            },
+            AttPdu::WriteRequest {
+                handle: 0x0005,
+                value: Bytes::from_static(&[0x01, 0x00]),
+            },
+            AttPdu::MultipleHandleValueNotification {
+                values: vec![
+                    (0x0003, Bytes::from_static(&[0x64])),
+                    (0x0007, Bytes::from_static(&[0x01, 0x02])),
+                ],
+            },
+            AttPdu::Other {
+                opcode: Opcode::PrepareWriteRequest,
+                param: Bytes::from_static(&[0x05, 0x00, 0x00, 0x00, 0xAB]),
+            },
+        ];
+
+        for pdu in pdus {
+            assert_eq!(AttPdu::parse(pdu.encode()).unwrap(), pdu);
+        }
+    }
+
+    #[test]
+    fn wire_format() {
+        // a read by group type request for primary services, as sent by
+        // bluetoothd when it discovers the services of a device
+        let pdu = AttPdu::ReadByGroupTypeRequest {
+            start: 0x0001,
+            end: 0xFFFF,
+            group_type: Uuid16(0x2800).into(),
+        };
+        assert_eq!(
+            &pdu.encode()[..],
+            &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]
+        );
+
+        let pdu = AttPdu::parse(Bytes::from_static(&[0x1B, 0x03, 0x00, 0x64])).unwrap();
+        assert_eq!(
+            pdu,
+            AttPdu::HandleValueNotification {
+                handle: 0x0003,
+                value: Bytes::from_static(&[0x64]),
+            }
+        );
+
+        assert!(Opcode::ReadRequest.is_request());
+        assert!(Opcode::ExchangeMtuRequest.is_request());
+        assert!(!Opcode::WriteCommand.is_request());
+        assert!(!Opcode::HandleValueIndication.is_request());
+    }
+
+    #[test]
+    fn invalid_pdus() {
+        for pdu in [
+            &[][..],
+            &[0x01, 0x0A, 0x03, 0x00],
+            &[0x05, 0x03, 0x01, 0x00],
+            &[0x09, 0x04, 0x01, 0x00, 0x02],
+            &[0x23, 0x03, 0x00, 0x02, 0x00, 0x01],
+        ] {
+            assert!(AttPdu::parse(Bytes::copy_from_slice(pdu)).is_err());
+        }
+    }
+}
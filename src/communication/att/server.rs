@@ -0,0 +1,1085 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use enumflags2::{bitflags, BitFlags};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{AttPdu, Error, ErrorCode, Opcode, DEFAULT_LE_MTU, MAX_PDU_LEN};
+use crate::communication::BluetoothStream;
+use crate::{Uuid, Uuid128};
+
+/// The types of the attributes that make up the services of a server.
+const PRIMARY_SERVICE: u16 = 0x2800;
+const CHARACTERISTIC: u16 = 0x2803;
+const CLIENT_CONFIGURATION: u16 = 0x2902;
+
+/// The largest MTU that the server agrees on, which is the largest that ATT
+/// allows on LE.
+const SERVER_MTU: u16 = 517;
+
+/// How a client may use the value of a characteristic.
+#[bitflags]
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CharacteristicProperty {
+    Broadcast = 1 << 0,
+    Read = 1 << 1,
+    WriteWithoutResponse = 1 << 2,
+    Write = 1 << 3,
+    Notify = 1 << 4,
+    Indicate = 1 << 5,
+}
+
+/// A characteristic of a service in a [`GattServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Characteristic {
+    pub uuid: Uuid,
+    pub properties: BitFlags<CharacteristicProperty>,
+    /// The value that clients read until it is changed.
+    pub value: Bytes,
+}
+
+impl Characteristic {
+    pub fn new(
+        uuid: impl Into<Uuid>,
+        properties: impl Into<BitFlags<CharacteristicProperty>>,
+        value: Bytes,
+    ) -> Self {
+        Self {
+            uuid: uuid.into(),
+            properties: properties.into(),
+            value,
+        }
+    }
+}
+
+/// Something that a client did on a [`GattServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GattEvent {
+    /// The client enabled or disabled notifications or indications of the
+    /// characteristic whose value has the handle `handle`.
+    Subscribed {
+        handle: u16,
+        notify: bool,
+        indicate: bool,
+    },
+
+    /// The client wrote the value of the characteristic whose value has the
+    /// handle `handle`. The server has already stored the value.
+    Written { handle: u16, value: Bytes },
+}
+
+#[derive(Debug, Clone)]
+enum Attribute {
+    Service {
+        uuid: Uuid,
+        end: u16,
+    },
+    Declaration {
+        properties: BitFlags<CharacteristicProperty>,
+        value_handle: u16,
+        uuid: Uuid,
+    },
+    Value {
+        uuid: Uuid,
+        properties: BitFlags<CharacteristicProperty>,
+        value: Bytes,
+    },
+    ClientConfiguration {
+        value_handle: u16,
+    },
+}
+
+impl Attribute {
+    fn attribute_type(&self) -> Uuid {
+        match self {
+            Attribute::Service { .. } => PRIMARY_SERVICE.into(),
+            Attribute::Declaration { .. } => CHARACTERISTIC.into(),
+            Attribute::Value { uuid, .. } => *uuid,
+            Attribute::ClientConfiguration { .. } => CLIENT_CONFIGURATION.into(),
+        }
+    }
+
+    /// The value of the attribute as `client` reads it, or `None` if it may
+    /// not be read.
+    fn read(&self, client: &ClientState) -> Option<Bytes> {
+        let mut buf = BytesMut::new();
+
+        match self {
+            Attribute::Service { uuid, .. } => put_uuid(&mut buf, *uuid),
+            Attribute::Declaration {
+                properties,
+                value_handle,
+                uuid,
+            } => {
+                buf.put_u8(properties.bits());
+                buf.put_u16_le(*value_handle);
+                put_uuid(&mut buf, *uuid);
+            }
+            Attribute::Value {
+                properties, value, ..
+            } if properties.contains(CharacteristicProperty::Read) => return Some(value.clone()),
+            Attribute::Value { .. } => return None,
+            Attribute::ClientConfiguration { value_handle } => {
+                let subscription = client
+                    .subscriptions
+                    .get(value_handle)
+                    .copied()
+                    .unwrap_or_default();
+                buf.put_u16_le(subscription.notify as u16 | (subscription.indicate as u16) << 1);
+            }
+        }
+
+        Some(buf.freeze())
+    }
+}
+
+/// The value of `uuid` as a 128-bit UUID.
+fn uuid_value(uuid: Uuid) -> u128 {
+    match uuid {
+        Uuid::Uuid16(uuid) => Uuid128::from(uuid).0,
+        Uuid::Uuid32(uuid) => Uuid128::from(uuid).0,
+        Uuid::Uuid128(uuid) => uuid.0,
+    }
+}
+
+/// Writes `uuid` the way ATT does, which only has 16-bit and 128-bit UUIDs.
+fn put_uuid(buf: &mut BytesMut, uuid: Uuid) {
+    match uuid {
+        Uuid::Uuid16(uuid) => buf.put_u16_le(uuid.0),
+        uuid => buf.put_u128_le(uuid_value(uuid)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Subscription {
+    notify: bool,
+    indicate: bool,
+}
+
+/// What the server knows about one of its clients.
+#[derive(Debug, Clone)]
+struct ClientState {
+    mtu: u16,
+    /// The subscriptions of the client, by value handle.
+    subscriptions: HashMap<u16, Subscription>,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self {
+            mtu: DEFAULT_LE_MTU,
+            subscriptions: HashMap::new(),
+        }
+    }
+}
+
+/// The reason for an Error Response, and the handle that caused it.
+type Rejection = (u16, ErrorCode);
+
+/// The services that the local device offers to GATT clients.
+///
+/// Every characteristic gets a declaration, a value and, if it can be
+/// notified or indicated, a client characteristic configuration
+/// descriptor, in this order. Clients are served by a [`GattConnection`]
+/// each, which share the values of the server but have their own
+/// subscriptions.
+#[derive(Debug, Clone, Default)]
+pub struct GattServer {
+    /// The attributes, where the handle of each is its index plus one.
+    attributes: Vec<Attribute>,
+}
+
+impl GattServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a primary service with `characteristics`, and returns the
+    /// handles of their values in the same order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server runs out of handles.
+    pub fn add_service(
+        &mut self,
+        uuid: impl Into<Uuid>,
+        characteristics: Vec<Characteristic>,
+    ) -> Vec<u16> {
+        let service = self.attributes.len();
+        self.attributes.push(Attribute::Service {
+            uuid: uuid.into(),
+            end: 0,
+        });
+
+        let mut value_handles = vec![];
+
+        for characteristic in characteristics {
+            let value_handle = self.attributes.len() as u16 + 2;
+            self.attributes.push(Attribute::Declaration {
+                properties: characteristic.properties,
+                value_handle,
+                uuid: characteristic.uuid,
+            });
+            self.attributes.push(Attribute::Value {
+                uuid: characteristic.uuid,
+                properties: characteristic.properties,
+                value: characteristic.value,
+            });
+
+            if characteristic
+                .properties
+                .intersects(CharacteristicProperty::Notify | CharacteristicProperty::Indicate)
+            {
+                self.attributes
+                    .push(Attribute::ClientConfiguration { value_handle });
+            }
+
+            value_handles.push(value_handle);
+        }
+
+        assert!(
+            self.attributes.len() <= u16::MAX as usize,
+            "a gatt server has at most 65535 attributes"
+        );
+
+        let last = self.attributes.len() as u16;
+        if let Attribute::Service { end, .. } = &mut self.attributes[service] {
+            *end = last;
+        }
+
+        value_handles
+    }
+
+    /// The value of the characteristic whose value has the handle `handle`.
+    pub fn value(&self, handle: u16) -> Option<&Bytes> {
+        match self.attribute(handle) {
+            Some(Attribute::Value { value, .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Changes the value of the characteristic whose value has the handle
+    /// `handle`, and returns whether there is such a characteristic. Clients
+    /// are not notified; use [`GattConnection::notify`] for that.
+    pub fn set_value(&mut self, handle: u16, value: Bytes) -> bool {
+        match self.attribute_mut(handle) {
+            Some(Attribute::Value { value: old, .. }) => {
+                *old = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn attribute(&self, handle: u16) -> Option<&Attribute> {
+        self.attributes.get((handle as usize).checked_sub(1)?)
+    }
+
+    fn attribute_mut(&mut self, handle: u16) -> Option<&mut Attribute> {
+        self.attributes.get_mut((handle as usize).checked_sub(1)?)
+    }
+
+    /// The handles and attributes from `start` to `end`.
+    fn range(
+        &self,
+        start: u16,
+        end: u16,
+    ) -> Result<impl Iterator<Item = (u16, &Attribute)>, Rejection> {
+        if start == 0 || start > end {
+            return Err((start, ErrorCode::InvalidHandle));
+        }
+
+        Ok(self
+            .attributes
+            .iter()
+            .enumerate()
+            .map(|(index, attribute)| (index as u16 + 1, attribute))
+            .skip(start as usize - 1)
+            .take_while(move |(handle, _)| *handle <= end))
+    }
+
+    /// Answers a PDU from `client`. Returns the response, if the PDU needs
+    /// one, and what the client did.
+    fn answer(
+        &mut self,
+        client: &mut ClientState,
+        pdu: Bytes,
+    ) -> (Option<AttPdu>, Option<GattEvent>) {
+        let opcode = match pdu.first() {
+            Some(&opcode) => Opcode::from(opcode),
+            None => return (None, None),
+        };
+
+        let result = match AttPdu::parse(pdu) {
+            Ok(AttPdu::WriteCommand { handle, value }) => {
+                // commands are not answered, even if they fail
+                return (None, self.write(client, handle, value, true).ok());
+            }
+            Ok(request) if opcode.is_request() => self.request(client, request),
+            Ok(_) => return (None, None),
+            Err(_) if opcode.is_request() => Err((0x0000, ErrorCode::InvalidPdu)),
+            Err(_) => return (None, None),
+        };
+
+        match result {
+            Ok((response, event)) => (Some(response), event),
+            Err((handle, code)) => (
+                Some(AttPdu::ErrorResponse {
+                    request: opcode,
+                    handle,
+                    code,
+                }),
+                None,
+            ),
+        }
+    }
+
+    fn request(
+        &mut self,
+        client: &mut ClientState,
+        request: AttPdu,
+    ) -> Result<(AttPdu, Option<GattEvent>), Rejection> {
+        let mtu = client.mtu as usize;
+
+        let response = match request {
+            AttPdu::ExchangeMtuRequest { mtu } => {
+                client.mtu = mtu.clamp(DEFAULT_LE_MTU, SERVER_MTU);
+                AttPdu::ExchangeMtuResponse { mtu: SERVER_MTU }
+            }
+            AttPdu::FindInformationRequest { start, end } => {
+                let mut information: Vec<(u16, Uuid)> = vec![];
+
+                for (handle, attribute) in self.range(start, end)? {
+                    let uuid = attribute.attribute_type();
+                    let len = uuid_len(uuid);
+
+                    if information
+                        .first()
+                        .is_some_and(|(_, first)| uuid_len(*first) != len)
+                        || 2 + (information.len() + 1) * (2 + len) > mtu
+                    {
+                        break;
+                    }
+
+                    // 32-bit UUIDs are sent as 128-bit UUIDs
+                    let uuid = match uuid {
+                        Uuid::Uuid32(_) => Uuid::from(uuid_value(uuid)),
+                        uuid => uuid,
+                    };
+                    information.push((handle, uuid));
+                }
+
+                if information.is_empty() {
+                    return Err((start, ErrorCode::AttributeNotFound));
+                }
+
+                AttPdu::FindInformationResponse { information }
+            }
+            AttPdu::ReadByGroupTypeRequest {
+                start,
+                end,
+                group_type,
+            } => {
+                if uuid_value(group_type) != uuid_value(PRIMARY_SERVICE.into()) {
+                    return Err((start, ErrorCode::UnsupportedGroupType));
+                }
+
+                let mut groups: Vec<(u16, u16, Bytes)> = vec![];
+
+                for (handle, attribute) in self.range(start, end)? {
+                    if let Attribute::Service { end, .. } = attribute {
+                        let value = attribute.read(client).unwrap_or_default();
+
+                        if groups
+                            .first()
+                            .is_some_and(|(_, _, first)| first.len() != value.len())
+                            || 2 + (groups.len() + 1) * (4 + value.len()) > mtu
+                        {
+                            break;
+                        }
+
+                        groups.push((handle, *end, value));
+                    }
+                }
+
+                if groups.is_empty() {
+                    return Err((start, ErrorCode::AttributeNotFound));
+                }
+
+                AttPdu::ReadByGroupTypeResponse { groups }
+            }
+            AttPdu::ReadByTypeRequest {
+                start,
+                end,
+                attribute_type,
+            } => {
+                let mut values: Vec<(u16, Bytes)> = vec![];
+
+                for (handle, attribute) in self.range(start, end)? {
+                    if uuid_value(attribute.attribute_type()) != uuid_value(attribute_type) {
+                        continue;
+                    }
+
+                    let mut value = match attribute.read(client) {
+                        Some(value) => value,
+                        None if values.is_empty() => {
+                            return Err((handle, ErrorCode::ReadNotPermitted))
+                        }
+                        None => break,
+                    };
+                    value.truncate(253.min(mtu - 4));
+
+                    if values
+                        .first()
+                        .is_some_and(|(_, first)| first.len() != value.len())
+                        || 2 + (values.len() + 1) * (2 + value.len()) > mtu
+                    {
+                        break;
+                    }
+
+                    values.push((handle, value));
+                }
+
+                if values.is_empty() {
+                    return Err((start, ErrorCode::AttributeNotFound));
+                }
+
+                AttPdu::ReadByTypeResponse { values }
+            }
+            AttPdu::ReadRequest { handle } => {
+                let mut value = self.read(client, handle)?;
+                value.truncate(mtu - 1);
+                AttPdu::ReadResponse { value }
+            }
+            AttPdu::ReadBlobRequest { handle, offset } => {
+                let mut value = self.read(client, handle)?;
+
+                if offset as usize > value.len() {
+                    return Err((handle, ErrorCode::InvalidOffset));
+                }
+
+                value.advance(offset as usize);
+                value.truncate(mtu - 1);
+                AttPdu::ReadBlobResponse { value }
+            }
+            AttPdu::WriteRequest { handle, value } => {
+                let event = self.write(client, handle, value, false)?;
+                return Ok((AttPdu::WriteResponse, Some(event)));
+            }
+            AttPdu::Other {
+                opcode: Opcode::FindByTypeValueRequest,
+                param,
+            } => return Ok((self.find_by_type_value(param)?, None)),
+            _ => return Err((0x0000, ErrorCode::RequestNotSupported)),
+        };
+
+        Ok((response, None))
+    }
+
+    fn read(&self, client: &ClientState, handle: u16) -> Result<Bytes, Rejection> {
+        self.attribute(handle)
+            .ok_or((handle, ErrorCode::InvalidHandle))?
+            .read(client)
+            .ok_or((handle, ErrorCode::ReadNotPermitted))
+    }
+
+    fn write(
+        &mut self,
+        client: &mut ClientState,
+        handle: u16,
+        value: Bytes,
+        command: bool,
+    ) -> Result<GattEvent, Rejection> {
+        let property = if command {
+            CharacteristicProperty::WriteWithoutResponse
+        } else {
+            CharacteristicProperty::Write
+        };
+
+        match self.attribute_mut(handle) {
+            Some(Attribute::Value {
+                properties,
+                value: old,
+                ..
+            }) if properties.contains(property) => {
+                *old = value.clone();
+                Ok(GattEvent::Written { handle, value })
+            }
+            Some(Attribute::ClientConfiguration { value_handle }) => {
+                if value.len() != 2 {
+                    return Err((handle, ErrorCode::InvalidAttributeValueLength));
+                }
+
+                let bits = u16::from_le_bytes([value[0], value[1]]);
+                let subscription = Subscription {
+                    notify: bits & 0x0001 != 0,
+                    indicate: bits & 0x0002 != 0,
+                };
+                client.subscriptions.insert(*value_handle, subscription);
+
+                Ok(GattEvent::Subscribed {
+                    handle: *value_handle,
+                    notify: subscription.notify,
+                    indicate: subscription.indicate,
+                })
+            }
+            Some(_) => Err((handle, ErrorCode::WriteNotPermitted)),
+            None => Err((handle, ErrorCode::InvalidHandle)),
+        }
+    }
+
+    /// Finds the services with a UUID, which is how clients discover a
+    /// service that they know.
+    fn find_by_type_value(&self, mut param: Bytes) -> Result<AttPdu, Rejection> {
+        if param.len() < 6 {
+            return Err((0x0000, ErrorCode::InvalidPdu));
+        }
+
+        let start = param.get_u16_le();
+        let end = param.get_u16_le();
+        let attribute_type = param.get_u16_le();
+        let range = self.range(start, end)?;
+
+        // only services are grouped, and their values are UUIDs
+        let service = match uuid_from_bytes(&param) {
+            Some(uuid) if attribute_type == PRIMARY_SERVICE => uuid_value(uuid),
+            _ => return Err((start, ErrorCode::AttributeNotFound)),
+        };
+
+        let mut found = BytesMut::new();
+
+        for (handle, attribute) in range {
+            match attribute {
+                Attribute::Service { uuid, end } if uuid_value(*uuid) == service => {
+                    found.put_u16_le(handle);
+                    found.put_u16_le(*end);
+                }
+                _ => {}
+            }
+        }
+
+        if found.is_empty() {
+            return Err((start, ErrorCode::AttributeNotFound));
+        }
+
+        Ok(AttPdu::Other {
+            opcode: Opcode::FindByTypeValueResponse,
+            param: found.freeze(),
+        })
+    }
+}
+
+fn uuid_len(uuid: Uuid) -> usize {
+    match uuid {
+        Uuid::Uuid16(_) => 2,
+        _ => 16,
+    }
+}
+
+fn uuid_from_bytes(buf: &[u8]) -> Option<Uuid> {
+    match buf.len() {
+        2 => Some(u16::from_le_bytes([buf[0], buf[1]]).into()),
+        16 => {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(buf);
+            Some(u128::from_le_bytes(bytes).into())
+        }
+        _ => None,
+    }
+}
+
+/// One client of a [`GattServer`], which is connected over an ATT bearer.
+///
+/// The connection only answers the client while [`GattConnection::answer`]
+/// or [`GattConnection::next_event`] is running, so call one of them in a
+/// loop.
+#[derive(Debug)]
+pub struct GattConnection {
+    stream: BluetoothStream,
+    client: ClientState,
+    buf: Vec<u8>,
+}
+
+impl GattConnection {
+    /// Serves a client on an ATT bearer that it opened, such as a stream
+    /// that was accepted from
+    /// [`BluetoothListener::bind_att`](crate::communication::BluetoothListener::bind_att).
+    pub fn new(stream: BluetoothStream) -> Self {
+        Self {
+            stream,
+            client: ClientState::default(),
+            buf: vec![0; MAX_PDU_LEN],
+        }
+    }
+
+    /// The largest PDU that may be sent to the client.
+    pub fn mtu(&self) -> u16 {
+        self.client.mtu
+    }
+
+    /// Whether the client subscribed to notifications or indications of the
+    /// characteristic whose value has the handle `handle`.
+    pub fn is_subscribed(&self, handle: u16) -> bool {
+        self.client
+            .subscriptions
+            .get(&handle)
+            .is_some_and(|subscription| subscription.notify || subscription.indicate)
+    }
+
+    /// Waits for the next PDU from the client. This is cancel safe, so it can
+    /// race against other sources of events; pass the PDU to
+    /// [`GattConnection::answer`].
+    pub async fn receive(&mut self) -> Result<Bytes, Error> {
+        let len = self.stream.read(&mut self.buf[..]).await?;
+
+        if len == 0 {
+            return Err(Error::BearerClosed);
+        }
+
+        Ok(Bytes::copy_from_slice(&self.buf[..len]))
+    }
+
+    /// Answers a PDU that was received from the client, and returns what the
+    /// client did, if anything.
+    pub async fn answer(
+        &mut self,
+        server: &mut GattServer,
+        pdu: Bytes,
+    ) -> Result<Option<GattEvent>, Error> {
+        let (response, event) = server.answer(&mut self.client, pdu);
+
+        if let Some(response) = response {
+            self.stream.write_all(&response.encode()[..]).await?;
+        }
+
+        Ok(event)
+    }
+
+    /// Answers the client until it writes a value or subscribes.
+    pub async fn next_event(&mut self, server: &mut GattServer) -> Result<GattEvent, Error> {
+        loop {
+            let pdu = self.receive().await?;
+
+            if let Some(event) = self.answer(server, pdu).await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Sends `value` as the value of the characteristic whose value has the
+    /// handle `handle`, if the client subscribed to it, and returns whether
+    /// it was sent. Indications are not retried, and their confirmation is
+    /// not waited for. The value is cut to the MTU of the client.
+    pub async fn notify(&mut self, handle: u16, value: &Bytes) -> Result<bool, Error> {
+        let subscription = match self.client.subscriptions.get(&handle) {
+            Some(subscription) => *subscription,
+            None => return Ok(false),
+        };
+
+        let mut value = value.clone();
+        value.truncate(self.client.mtu as usize - 3);
+
+        let pdu = if subscription.notify {
+            AttPdu::HandleValueNotification { handle, value }
+        } else if subscription.indicate {
+            AttPdu::HandleValueIndication { handle, value }
+        } else {
+            return Ok(false);
+        };
+
+        self.stream.write_all(&pdu.encode()[..]).await?;
+        Ok(true)
+    }
+
+    /// Returns the stream that this connection runs on.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    const BATTERY_SERVICE: u16 = 0x180F;
+    const BATTERY_LEVEL: u16 = 0x2A19;
+    const CUSTOM_SERVICE: u128 = 0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E;
+    const CUSTOM_CHARACTERISTIC: u128 = 0x6E400002_B5A3_F393_E0A9_E50E24DCCA9E;
+
+    /// A battery service at handles 1 to 4, and a service with a writable
+    /// characteristic at handles 5 to 7.
+    fn server() -> GattServer {
+        let mut server = GattServer::new();
+
+        let handles = server.add_service(
+            BATTERY_SERVICE,
+            vec![Characteristic::new(
+                BATTERY_LEVEL,
+                CharacteristicProperty::Read | CharacteristicProperty::Notify,
+                Bytes::from_static(&[0x64]),
+            )],
+        );
+        assert_eq!(handles, vec![3]);
+
+        let handles = server.add_service(
+            CUSTOM_SERVICE,
+            vec![Characteristic::new(
+                CUSTOM_CHARACTERISTIC,
+                CharacteristicProperty::Write | CharacteristicProperty::WriteWithoutResponse,
+                Bytes::new(),
+            )],
+        );
+        assert_eq!(handles, vec![7]);
+
+        server
+    }
+
+    fn answer(server: &mut GattServer, client: &mut ClientState, request: AttPdu) -> AttPdu {
+        let (response, event) = server.answer(client, request.encode());
+        assert_eq!(event, None);
+        response.unwrap()
+    }
+
+    fn rejection(request: Opcode, handle: u16, code: ErrorCode) -> AttPdu {
+        AttPdu::ErrorResponse {
+            request,
+            handle,
+            code,
+        }
+    }
+
+    #[test]
+    fn service_discovery() {
+        let mut server = server();
+        let mut client = ClientState::default();
+
+        // the services have UUIDs of different lengths, so they are found
+        // one at a time
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadByGroupTypeRequest {
+                    start: 0x0001,
+                    end: 0xFFFF,
+                    group_type: PRIMARY_SERVICE.into(),
+                }
+            ),
+            AttPdu::ReadByGroupTypeResponse {
+                groups: vec![(1, 4, Bytes::from_static(&[0x0F, 0x18]))],
+            }
+        );
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadByGroupTypeRequest {
+                    start: 0x0005,
+                    end: 0xFFFF,
+                    group_type: PRIMARY_SERVICE.into(),
+                }
+            ),
+            AttPdu::ReadByGroupTypeResponse {
+                groups: vec![(5, 7, Bytes::copy_from_slice(&CUSTOM_SERVICE.to_le_bytes()))],
+            }
+        );
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadByGroupTypeRequest {
+                    start: 0x0008,
+                    end: 0xFFFF,
+                    group_type: PRIMARY_SERVICE.into(),
+                }
+            ),
+            rejection(
+                Opcode::ReadByGroupTypeRequest,
+                8,
+                ErrorCode::AttributeNotFound
+            )
+        );
+
+        // a service is found by its UUID as well
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::Other {
+                    opcode: Opcode::FindByTypeValueRequest,
+                    param: Bytes::from_static(&[0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28, 0x0F, 0x18]),
+                }
+            ),
+            AttPdu::Other {
+                opcode: Opcode::FindByTypeValueResponse,
+                param: Bytes::from_static(&[0x01, 0x00, 0x04, 0x00]),
+            }
+        );
+
+        // properties, value handle and UUID of the battery level
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadByTypeRequest {
+                    start: 0x0001,
+                    end: 0x0004,
+                    attribute_type: CHARACTERISTIC.into(),
+                }
+            ),
+            AttPdu::ReadByTypeResponse {
+                values: vec![(2, Bytes::from_static(&[0x12, 0x03, 0x00, 0x19, 0x2A]))],
+            }
+        );
+
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::FindInformationRequest {
+                    start: 0x0004,
+                    end: 0x0004,
+                }
+            ),
+            AttPdu::FindInformationResponse {
+                information: vec![(4, CLIENT_CONFIGURATION.into())],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_and_writes() {
+        let mut server = server();
+        let mut client = ClientState::default();
+
+        assert_eq!(
+            answer(&mut server, &mut client, AttPdu::ReadRequest { handle: 3 }),
+            AttPdu::ReadResponse {
+                value: Bytes::from_static(&[0x64]),
+            }
+        );
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadBlobRequest {
+                    handle: 3,
+                    offset: 2,
+                }
+            ),
+            rejection(Opcode::ReadBlobRequest, 3, ErrorCode::InvalidOffset)
+        );
+        assert_eq!(
+            answer(&mut server, &mut client, AttPdu::ReadRequest { handle: 7 }),
+            rejection(Opcode::ReadRequest, 7, ErrorCode::ReadNotPermitted)
+        );
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::WriteRequest {
+                    handle: 3,
+                    value: Bytes::from_static(&[0x00]),
+                }
+            ),
+            rejection(Opcode::WriteRequest, 3, ErrorCode::WriteNotPermitted)
+        );
+
+        let value = Bytes::from_static(b"hello");
+        assert_eq!(
+            server.answer(
+                &mut client,
+                AttPdu::WriteRequest {
+                    handle: 7,
+                    value: value.clone(),
+                }
+                .encode()
+            ),
+            (
+                Some(AttPdu::WriteResponse),
+                Some(GattEvent::Written {
+                    handle: 7,
+                    value: value.clone(),
+                })
+            )
+        );
+        assert_eq!(server.value(7), Some(&value));
+
+        // commands are not answered, even if they are not permitted
+        let command = AttPdu::WriteCommand {
+            handle: 3,
+            value: Bytes::from_static(&[0x00]),
+        };
+        assert_eq!(server.answer(&mut client, command.encode()), (None, None));
+        assert_eq!(server.value(3), Some(&Bytes::from_static(&[0x64])));
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut server = server();
+        let mut client = ClientState::default();
+
+        let subscribe = AttPdu::WriteRequest {
+            handle: 4,
+            value: Bytes::from_static(&[0x01, 0x00]),
+        };
+        assert_eq!(
+            server.answer(&mut client, subscribe.encode()),
+            (
+                Some(AttPdu::WriteResponse),
+                Some(GattEvent::Subscribed {
+                    handle: 3,
+                    notify: true,
+                    indicate: false,
+                })
+            )
+        );
+        assert_eq!(
+            answer(&mut server, &mut client, AttPdu::ReadRequest { handle: 4 }),
+            AttPdu::ReadResponse {
+                value: Bytes::from_static(&[0x01, 0x00]),
+            }
+        );
+
+        // other clients have their own subscriptions
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut ClientState::default(),
+                AttPdu::ReadRequest { handle: 4 }
+            ),
+            AttPdu::ReadResponse {
+                value: Bytes::from_static(&[0x00, 0x00]),
+            }
+        );
+
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::WriteRequest {
+                    handle: 4,
+                    value: Bytes::from_static(&[0x01]),
+                }
+            ),
+            rejection(
+                Opcode::WriteRequest,
+                4,
+                ErrorCode::InvalidAttributeValueLength
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut server = server();
+        let mut client = ClientState::default();
+
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ExchangeMtuRequest { mtu: 100 }
+            ),
+            AttPdu::ExchangeMtuResponse { mtu: SERVER_MTU }
+        );
+        assert_eq!(client.mtu, 100);
+
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::FindInformationRequest {
+                    start: 0x0000,
+                    end: 0xFFFF,
+                }
+            ),
+            rejection(Opcode::FindInformationRequest, 0, ErrorCode::InvalidHandle)
+        );
+        assert_eq!(
+            answer(
+                &mut server,
+                &mut client,
+                AttPdu::ReadByGroupTypeRequest {
+                    start: 0x0001,
+                    end: 0xFFFF,
+                    group_type: CHARACTERISTIC.into(),
+                }
+            ),
+            rejection(
+                Opcode::ReadByGroupTypeRequest,
+                1,
+                ErrorCode::UnsupportedGroupType
+            )
+        );
+
+        // a Read Multiple Request, which the server does not support
+        let (response, _) = server.answer(
+            &mut client,
+            Bytes::from_static(&[0x0E, 0x01, 0x00, 0x03, 0x00]),
+        );
+        assert_eq!(
+            response,
+            Some(rejection(
+                Opcode::ReadMultipleRequest,
+                0,
+                ErrorCode::RequestNotSupported
+            ))
+        );
+
+        // a truncated Read Request
+        let (response, _) = server.answer(&mut client, Bytes::from_static(&[0x0A, 0x03]));
+        assert_eq!(
+            response,
+            Some(rejection(Opcode::ReadRequest, 0, ErrorCode::InvalidPdu))
+        );
+    }
+
+    #[tokio::test]
+    async fn connection() {
+        let (stream, mut client) = BluetoothStream::pair(crate::Protocol::L2CAP).unwrap();
+        let mut connection = GattConnection::new(stream);
+        let mut server = server();
+
+        async fn receive(client: &mut UnixStream) -> AttPdu {
+            let mut buf = vec![0; MAX_PDU_LEN];
+            let len = client.read(&mut buf).await.unwrap();
+            AttPdu::parse(Bytes::copy_from_slice(&buf[..len])).unwrap()
+        }
+
+        let value = Bytes::from_static(&[0x32]);
+        assert!(!connection.notify(3, &value).await.unwrap());
+
+        let subscribe = AttPdu::WriteRequest {
+            handle: 4,
+            value: Bytes::from_static(&[0x02, 0x00]),
+        };
+        client.write_all(&subscribe.encode()[..]).await.unwrap();
+
+        assert_eq!(
+            connection.next_event(&mut server).await.unwrap(),
+            GattEvent::Subscribed {
+                handle: 3,
+                notify: false,
+                indicate: true,
+            }
+        );
+        assert_eq!(receive(&mut client).await, AttPdu::WriteResponse);
+        assert!(connection.is_subscribed(3));
+
+        assert!(connection.notify(3, &value).await.unwrap());
+        assert_eq!(
+            receive(&mut client).await,
+            AttPdu::HandleValueIndication { handle: 3, value }
+        );
+
+        drop(client);
+        assert!(matches!(
+            connection.receive().await,
+            Err(Error::BearerClosed)
+        ));
+    }
+}
@@ -0,0 +1,22 @@
+use super::signal::{ErrorCode, SignalId};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid packet")]
+    InvalidPacket,
+
+    #[error("the remote device rejected {signal:?} with error {code:?}")]
+    Rejected { signal: SignalId, code: ErrorCode },
+
+    #[error("the remote device does not support {0:?}")]
+    NotSupported(SignalId),
+
+    #[error("the message does not fit in the 255 packets that a signal can have")]
+    MessageTooLong,
+
+    #[error("the avdtp session has been closed")]
+    SessionClosed,
+}
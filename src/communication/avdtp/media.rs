@@ -0,0 +1,176 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::error::Error;
+use crate::communication::stream::BluetoothStream;
+
+/// The size of an RTP header without CSRCs or extensions.
+const RTP_HEADER_LEN: usize = 12;
+
+/// The first dynamic RTP payload type, which A2DP uses for all codecs.
+pub const DEFAULT_PAYLOAD_TYPE: u8 = 96;
+
+/// A media packet, with its RTP header removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaPacket {
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub payload: Bytes,
+}
+
+impl MediaPacket {
+    /// Parses an RTP packet. CSRCs, header extensions and padding are
+    /// skipped.
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < RTP_HEADER_LEN {
+            return Err(Error::InvalidPacket);
+        }
+
+        let first = buf.get_u8();
+        if first >> 6 != 2 {
+            return Err(Error::InvalidPacket);
+        }
+
+        let padding = first & 0x20 != 0;
+        let extension = first & 0x10 != 0;
+        let csrc_count = (first & 0x0F) as usize;
+
+        buf.advance(1);
+        let sequence = buf.get_u16();
+        let timestamp = buf.get_u32();
+        buf.advance(4);
+
+        if buf.remaining() < csrc_count * 4 {
+            return Err(Error::InvalidPacket);
+        }
+        buf.advance(csrc_count * 4);
+
+        if extension {
+            if buf.remaining() < 4 {
+                return Err(Error::InvalidPacket);
+            }
+
+            buf.advance(2);
+            let len = buf.get_u16() as usize * 4;
+
+            if buf.remaining() < len {
+                return Err(Error::InvalidPacket);
+            }
+            buf.advance(len);
+        }
+
+        if padding {
+            let len = match buf.last() {
+                Some(&len) if (len as usize) <= buf.len() => len as usize,
+                _ => return Err(Error::InvalidPacket),
+            };
+            buf.truncate(buf.len() - len);
+        }
+
+        Ok(Self {
+            sequence,
+            timestamp,
+            payload: buf,
+        })
+    }
+}
+
+/// The transport channel of a stream, which carries media packets once the
+/// stream has been started.
+///
+/// Packets are framed with RTP headers. The payload of each packet is
+/// specific to the codec that the stream was configured with; for SBC it
+/// starts with a byte that holds the number of frames in the packet.
+#[derive(Debug)]
+pub struct MediaTransport {
+    stream: BluetoothStream,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    mtu: usize,
+}
+
+impl MediaTransport {
+    /// Wraps an L2CAP connection that was opened for a stream, either by
+    /// [`AvdtpSession::open`](super::AvdtpSession::open) or by the remote
+    /// device after it opened the stream.
+    pub fn new(stream: BluetoothStream) -> Self {
+        Self {
+            stream,
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            ssrc: 1,
+            sequence: 0,
+            mtu: super::DEFAULT_MTU as usize,
+        }
+    }
+
+    /// Sets the RTP payload type of outgoing packets.
+    pub fn set_payload_type(&mut self, payload_type: u8) {
+        self.payload_type = payload_type & 0x7F;
+    }
+
+    /// Sets the synchronization source of outgoing packets.
+    pub fn set_ssrc(&mut self, ssrc: u32) {
+        self.ssrc = ssrc;
+    }
+
+    /// Sets the largest packet that will be read, including the RTP header.
+    /// This should match the MTU of the L2CAP channel.
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu as usize;
+    }
+
+    /// Sends one media packet. `timestamp` is in units of the sampling
+    /// clock of the codec.
+    pub async fn send(&mut self, timestamp: u32, payload: &[u8]) -> Result<(), Error> {
+        let mut buf = BytesMut::with_capacity(RTP_HEADER_LEN + payload.len());
+        buf.put_u8(0x80);
+        buf.put_u8(self.payload_type);
+        buf.put_u16(self.sequence);
+        buf.put_u32(timestamp);
+        buf.put_u32(self.ssrc);
+        buf.put_slice(payload);
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.stream.write_all(&buf[..]).await?;
+        Ok(())
+    }
+
+    /// Receives one media packet.
+    pub async fn recv(&mut self) -> Result<MediaPacket, Error> {
+        let mut buf = BytesMut::with_capacity(self.mtu);
+
+        if self.stream.read_buf(&mut buf).await? == 0 {
+            return Err(Error::SessionClosed);
+        }
+
+        MediaPacket::parse(buf.freeze())
+    }
+
+    /// Returns the underlying L2CAP connection.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rtp() {
+        #[rustfmt::skip]
+        let packet = Bytes::from_static(&[
+            0xA1, 0x60, 0x00, 0x2A, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01,
+            // one csrc
+            0x00, 0x00, 0x00, 0x02,
+            // payload and two bytes of padding
+            0x01, 0x9C, 0x00, 0x02,
+        ]);
+
+        let packet = MediaPacket::parse(packet).unwrap();
+        assert_eq!(packet.sequence, 42);
+        assert_eq!(packet.timestamp, 256);
+        assert_eq!(&packet.payload[..], &[0x01, 0x9C]);
+    }
+}
@@ -0,0 +1,754 @@
+//! An implementation of AVDTP, the Audio/Video Distribution Transport
+//! Protocol, which is used by A2DP to set up and carry audio streams.
+//!
+//! A session consists of a signalling channel, which is an L2CAP connection
+//! to [`AVDTP_PSM`], and one transport channel for each open stream, which is
+//! another L2CAP connection to the same PSM. Streams connect a stream end
+//! point (SEP) on each device: the side that sets up the stream (the
+//! initiator) uses [`AvdtpSession::discover`] and
+//! [`AvdtpSession::get_capabilities`] to find a suitable end point on the
+//! remote device, and then configures, opens and starts it.
+//!
+//! End points registered with [`AvdtpSession::add_endpoint`] are offered to
+//! the remote device, and commands for them are answered automatically while
+//! the session is processing packets. Changes to their state are reported
+//! through [`AvdtpSession::recv`]. As with [`RfcommSession`], no tasks are
+//! spawned, so call [`AvdtpSession::recv`] in a loop to keep the session
+//! going.
+//!
+//! Media packets on the transport channel are sent and received with
+//! [`MediaTransport`], and the [`sbc`] module splits SBC packets into frames.
+//!
+//! [`RfcommSession`]: super::rfcomm::RfcommSession
+
+use std::collections::VecDeque;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+pub use media::*;
+pub use signal::*;
+
+use super::stream::{BluetoothListener, BluetoothStream};
+use crate::{Address, AddressType, Protocol};
+
+mod error;
+mod media;
+pub mod sbc;
+pub mod signal;
+
+pub const AVDTP_PSM: u16 = 0x0019;
+
+/// The default L2CAP MTU, which is used for the signalling channel if its
+/// MTU cannot be read, as on sockets which are not L2CAP connections.
+pub const DEFAULT_MTU: u16 = 672;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AvdtpEvent {
+    /// The remote device configured a stream on a local end point.
+    Configured {
+        seid: u8,
+        remote_seid: u8,
+        configuration: Vec<Capability>,
+    },
+    /// The remote device opened a stream. It will connect the transport
+    /// channel next, which has to be accepted on a listener for
+    /// [`AVDTP_PSM`] and wrapped in a [`MediaTransport`].
+    Opened { seid: u8 },
+    /// The remote device started a stream.
+    Started { seid: u8 },
+    /// The remote device suspended a stream.
+    Suspended { seid: u8 },
+    /// The remote device closed a stream.
+    Closed { seid: u8 },
+    /// The remote device aborted a stream.
+    Aborted { seid: u8 },
+    /// The remote device reported its playback delay, in units of 0.1 ms.
+    DelayReport { seid: u8, delay: u16 },
+    /// The remote device closed the signalling channel. No further events
+    /// will be received.
+    SessionClosed,
+}
+
+/// The state of a stream on a local end point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    Idle,
+    Configured,
+    Open,
+    Streaming,
+}
+
+#[derive(Debug)]
+struct LocalEndpoint {
+    info: EndpointInfo,
+    capabilities: Vec<Capability>,
+    state: StreamState,
+    configuration: Vec<Capability>,
+}
+
+/// An AVDTP session with a remote device.
+#[derive(Debug)]
+pub struct AvdtpSession {
+    stream: BluetoothStream,
+    address: Address,
+    send_mtu: usize,
+    recv_mtu: usize,
+    transaction: u8,
+    closed: bool,
+    assembler: SignalAssembler,
+    response: Option<Signal>,
+    endpoints: Vec<LocalEndpoint>,
+    events: VecDeque<AvdtpEvent>,
+}
+
+impl AvdtpSession {
+    /// Opens the signalling channel of an AVDTP session with a remote device.
+    pub async fn connect(address: Address) -> Result<Self, Error> {
+        let stream =
+            BluetoothStream::connect(Protocol::L2CAP, address, AddressType::BREDR, AVDTP_PSM)
+                .await?;
+
+        Ok(Self::new(stream, address))
+    }
+
+    /// Creates a session from an L2CAP connection that a remote device opened
+    /// on [`AVDTP_PSM`].
+    pub fn accept(stream: BluetoothStream) -> Result<Self, Error> {
+        let (address, _) = stream.peer_addr()?;
+        Ok(Self::new(stream, address))
+    }
+
+    /// Creates a listener for incoming AVDTP connections on the local adapter
+    /// with the given address. The first connection from a device is its
+    /// signalling channel, which should be passed to [`AvdtpSession::accept`];
+    /// later ones are transport channels.
+    pub fn bind(address: Address) -> Result<BluetoothListener, Error> {
+        Ok(BluetoothListener::bind(
+            Protocol::L2CAP,
+            address,
+            AddressType::BREDR,
+            AVDTP_PSM,
+        )?)
+    }
+
+    fn new(stream: BluetoothStream, address: Address) -> Self {
+        Self {
+            send_mtu: stream.send_mtu().unwrap_or(DEFAULT_MTU) as usize,
+            recv_mtu: stream.recv_mtu().unwrap_or(DEFAULT_MTU) as usize,
+            stream,
+            address,
+            transaction: 0,
+            closed: false,
+            assembler: SignalAssembler::new(),
+            response: None,
+            endpoints: vec![],
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Offers a local end point to the remote device. `info.in_use` is
+    /// ignored, since it is derived from the state of the end point.
+    pub fn add_endpoint(&mut self, info: EndpointInfo, capabilities: Vec<Capability>) {
+        self.endpoints.retain(|e| e.info.seid != info.seid);
+        self.endpoints.push(LocalEndpoint {
+            info,
+            capabilities,
+            state: StreamState::Idle,
+            configuration: vec![],
+        });
+    }
+
+    /// Returns the state of the stream on a local end point.
+    pub fn endpoint_state(&self, seid: u8) -> Option<StreamState> {
+        self.endpoint(seid).map(|e| e.state)
+    }
+
+    /// Returns the configuration of the stream on a local end point, as set
+    /// by the remote device.
+    pub fn endpoint_configuration(&self, seid: u8) -> Option<&[Capability]> {
+        self.endpoint(seid).map(|e| &e.configuration[..])
+    }
+
+    /// Lists the stream end points of the remote device.
+    pub async fn discover(&mut self) -> Result<Vec<EndpointInfo>, Error> {
+        let payload = self.command(SignalId::Discover, Bytes::new()).await?;
+        EndpointInfo::parse_list(payload)
+    }
+
+    /// Gets the capabilities of a remote stream end point.
+    pub async fn get_capabilities(&mut self, seid: u8) -> Result<Vec<Capability>, Error> {
+        let payload = self
+            .command(SignalId::GetCapabilities, seid_payload(seid))
+            .await?;
+        Capability::parse_list(payload)
+    }
+
+    /// Configures a stream between the remote end point `seid` and the local
+    /// end point `local_seid`. The configuration should contain
+    /// [`Capability::media_transport`] and a media codec capability.
+    pub async fn set_configuration(
+        &mut self,
+        seid: u8,
+        local_seid: u8,
+        configuration: &[Capability],
+    ) -> Result<(), Error> {
+        let mut payload = BytesMut::new();
+        payload.put_u8(seid << 2);
+        payload.put_u8(local_seid << 2);
+        Capability::encode_list(configuration, &mut payload);
+
+        self.command(SignalId::SetConfiguration, payload.freeze())
+            .await?;
+        Ok(())
+    }
+
+    /// Opens a configured stream, and connects its transport channel.
+    pub async fn open(&mut self, seid: u8) -> Result<MediaTransport, Error> {
+        self.command(SignalId::Open, seid_payload(seid)).await?;
+
+        let stream =
+            BluetoothStream::connect(Protocol::L2CAP, self.address, AddressType::BREDR, AVDTP_PSM)
+                .await?;
+
+        Ok(MediaTransport::new(stream))
+    }
+
+    /// Starts an open stream. Media packets may be sent once this returns.
+    pub async fn start(&mut self, seid: u8) -> Result<(), Error> {
+        self.command(SignalId::Start, seid_payload(seid)).await?;
+        Ok(())
+    }
+
+    /// Suspends a started stream. The transport channel stays open.
+    pub async fn suspend(&mut self, seid: u8) -> Result<(), Error> {
+        self.command(SignalId::Suspend, seid_payload(seid)).await?;
+        Ok(())
+    }
+
+    /// Closes a stream. The transport channel should be dropped afterwards.
+    pub async fn close(&mut self, seid: u8) -> Result<(), Error> {
+        self.command(SignalId::Close, seid_payload(seid)).await?;
+        Ok(())
+    }
+
+    /// Aborts a stream in any state.
+    pub async fn abort(&mut self, seid: u8) -> Result<(), Error> {
+        self.command(SignalId::Abort, seid_payload(seid)).await?;
+        Ok(())
+    }
+
+    /// Waits for the next event, answering commands from the remote device
+    /// in the meantime.
+    pub async fn recv(&mut self) -> Result<AvdtpEvent, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+
+            self.process().await?;
+        }
+    }
+
+    /// Returns the signalling channel.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+
+    fn endpoint(&self, seid: u8) -> Option<&LocalEndpoint> {
+        self.endpoints.iter().find(|e| e.info.seid == seid)
+    }
+
+    fn endpoint_mut(&mut self, seid: u8) -> Option<&mut LocalEndpoint> {
+        self.endpoints.iter_mut().find(|e| e.info.seid == seid)
+    }
+
+    /// Sends a command and waits for its response. Commands from the remote
+    /// device which arrive in the meantime are answered.
+    async fn command(&mut self, signal_id: SignalId, payload: Bytes) -> Result<Bytes, Error> {
+        self.transaction = (self.transaction + 1) & 0x0F;
+        let transaction = self.transaction;
+
+        self.send(Signal::command(transaction, signal_id, payload))
+            .await?;
+
+        loop {
+            self.process().await?;
+
+            if self.closed {
+                return Err(Error::SessionClosed);
+            }
+
+            let response = match self.response.take() {
+                Some(response)
+                    if response.transaction == transaction && response.signal_id == signal_id =>
+                {
+                    response
+                }
+                // a late response to a command that was abandoned
+                _ => continue,
+            };
+
+            return match response.message_type {
+                MessageType::ResponseAccept => Ok(response.payload),
+                MessageType::ResponseReject => {
+                    // commands which can fail for one of several categories
+                    // or end points put those before the error code
+                    let index = match signal_id {
+                        SignalId::SetConfiguration
+                        | SignalId::Reconfigure
+                        | SignalId::Start
+                        | SignalId::Suspend => 1,
+                        _ => 0,
+                    };
+
+                    match response.payload.get(index) {
+                        Some(&code) => Err(Error::Rejected {
+                            signal: signal_id,
+                            code: code.into(),
+                        }),
+                        None => Err(Error::InvalidPacket),
+                    }
+                }
+                _ => Err(Error::NotSupported(signal_id)),
+            };
+        }
+    }
+
+    async fn send(&mut self, signal: Signal) -> Result<(), Error> {
+        for packet in signal.encode(self.send_mtu)? {
+            self.stream.write_all(&packet[..]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn process(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::SessionClosed);
+        }
+
+        let mut buf = BytesMut::with_capacity(self.recv_mtu);
+
+        if self.stream.read_buf(&mut buf).await? == 0 {
+            self.closed = true;
+            self.events.push_back(AvdtpEvent::SessionClosed);
+            return Ok(());
+        }
+
+        let signal = match self.assembler.push(buf.freeze())? {
+            Some(signal) => signal,
+            None => return Ok(()),
+        };
+
+        if signal.message_type != MessageType::Command {
+            self.response = Some(signal);
+            return Ok(());
+        }
+
+        let (message_type, payload) = match self.handle_command(&signal) {
+            Some(Ok(payload)) => (MessageType::ResponseAccept, payload),
+            Some(Err(payload)) => (MessageType::ResponseReject, payload),
+            None => (MessageType::GeneralReject, Bytes::new()),
+        };
+
+        self.send(Signal {
+            transaction: signal.transaction,
+            message_type,
+            signal_id: signal.signal_id,
+            payload,
+        })
+        .await
+    }
+
+    /// Handles a command from the remote device, and returns the parameters
+    /// of an accept or reject response, or `None` if the command is not
+    /// supported.
+    fn handle_command(&mut self, signal: &Signal) -> Option<Result<Bytes, Bytes>> {
+        let payload = &signal.payload;
+        let seid = payload.first().map(|b| b >> 2);
+
+        let reject = |code: ErrorCode| Err(Bytes::copy_from_slice(&[code.into()]));
+
+        let result = match signal.signal_id {
+            SignalId::Discover => {
+                let mut buf = BytesMut::new();
+                for endpoint in &self.endpoints {
+                    EndpointInfo {
+                        in_use: endpoint.state != StreamState::Idle,
+                        ..endpoint.info
+                    }
+                    .encode(&mut buf);
+                }
+                Ok(buf.freeze())
+            }
+            SignalId::GetCapabilities | SignalId::GetAllCapabilities => {
+                match seid.and_then(|seid| self.endpoint(seid)) {
+                    Some(endpoint) => {
+                        // delay reporting is only reported to devices which
+                        // use the newer command
+                        let capabilities: Vec<_> = endpoint
+                            .capabilities
+                            .iter()
+                            .filter(|c| {
+                                signal.signal_id == SignalId::GetAllCapabilities
+                                    || c.category != ServiceCategory::DelayReporting
+                            })
+                            .cloned()
+                            .collect();
+
+                        let mut buf = BytesMut::new();
+                        Capability::encode_list(&capabilities, &mut buf);
+                        Ok(buf.freeze())
+                    }
+                    None => reject(ErrorCode::BadAcpSeid),
+                }
+            }
+            SignalId::SetConfiguration => self.set_configuration_command(payload.clone()),
+            SignalId::GetConfiguration => match seid.and_then(|seid| self.endpoint(seid)) {
+                Some(endpoint) if endpoint.state != StreamState::Idle => {
+                    let mut buf = BytesMut::new();
+                    Capability::encode_list(&endpoint.configuration, &mut buf);
+                    Ok(buf.freeze())
+                }
+                Some(_) => reject(ErrorCode::BadState),
+                None => reject(ErrorCode::BadAcpSeid),
+            },
+            SignalId::Open => self.transition(
+                seid,
+                &[StreamState::Configured],
+                StreamState::Open,
+                |seid| AvdtpEvent::Opened { seid },
+            ),
+            SignalId::Close => self.transition(
+                seid,
+                &[StreamState::Open, StreamState::Streaming],
+                StreamState::Idle,
+                |seid| AvdtpEvent::Closed { seid },
+            ),
+            SignalId::Abort => self.transition(
+                seid,
+                &[
+                    StreamState::Idle,
+                    StreamState::Configured,
+                    StreamState::Open,
+                    StreamState::Streaming,
+                ],
+                StreamState::Idle,
+                |seid| AvdtpEvent::Aborted { seid },
+            ),
+            SignalId::Start => {
+                self.transition_all(payload, StreamState::Open, StreamState::Streaming, |seid| {
+                    AvdtpEvent::Started { seid }
+                })
+            }
+            SignalId::Suspend => {
+                self.transition_all(payload, StreamState::Streaming, StreamState::Open, |seid| {
+                    AvdtpEvent::Suspended { seid }
+                })
+            }
+            SignalId::DelayReport => match (seid, payload.get(1..3)) {
+                (Some(seid), Some(delay)) if self.endpoint(seid).is_some() => {
+                    self.events.push_back(AvdtpEvent::DelayReport {
+                        seid,
+                        delay: u16::from_be_bytes([delay[0], delay[1]]),
+                    });
+                    Ok(Bytes::new())
+                }
+                (Some(_), Some(_)) => reject(ErrorCode::BadAcpSeid),
+                _ => reject(ErrorCode::BadLength),
+            },
+            _ => return None,
+        };
+
+        Some(result)
+    }
+
+    fn set_configuration_command(&mut self, payload: Bytes) -> Result<Bytes, Bytes> {
+        let reject =
+            |category: u8, code: ErrorCode| Err(Bytes::copy_from_slice(&[category, code.into()]));
+
+        if payload.len() < 2 {
+            return reject(0, ErrorCode::BadLength);
+        }
+
+        let seid = payload[0] >> 2;
+        let remote_seid = payload[1] >> 2;
+
+        let configuration = match Capability::parse_list(payload.slice(2..)) {
+            Ok(configuration) => configuration,
+            Err(_) => return reject(0, ErrorCode::BadPayloadFormat),
+        };
+
+        let endpoint = match self.endpoint_mut(seid) {
+            Some(endpoint) => endpoint,
+            None => return reject(0, ErrorCode::BadAcpSeid),
+        };
+
+        if endpoint.state != StreamState::Idle {
+            return reject(0, ErrorCode::SepInUse);
+        }
+
+        if let Some(unsupported) = configuration.iter().find(|c| {
+            !endpoint
+                .capabilities
+                .iter()
+                .any(|e| e.category == c.category)
+        }) {
+            return reject(unsupported.category.into(), ErrorCode::BadServiceCategory);
+        }
+
+        endpoint.state = StreamState::Configured;
+        endpoint.configuration = configuration.clone();
+
+        self.events.push_back(AvdtpEvent::Configured {
+            seid,
+            remote_seid,
+            configuration,
+        });
+
+        Ok(Bytes::new())
+    }
+
+    /// Moves a local end point from one of the `from` states to `to`.
+    fn transition(
+        &mut self,
+        seid: Option<u8>,
+        from: &[StreamState],
+        to: StreamState,
+        event: fn(u8) -> AvdtpEvent,
+    ) -> Result<Bytes, Bytes> {
+        let seid = match seid {
+            Some(seid) => seid,
+            None => return Err(Bytes::copy_from_slice(&[ErrorCode::BadLength.into()])),
+        };
+
+        let code = match self.endpoint_mut(seid) {
+            Some(endpoint) if from.contains(&endpoint.state) => {
+                endpoint.state = to;
+                if to == StreamState::Idle {
+                    endpoint.configuration.clear();
+                }
+
+                self.events.push_back(event(seid));
+                return Ok(Bytes::new());
+            }
+            Some(_) => ErrorCode::BadState,
+            None => ErrorCode::BadAcpSeid,
+        };
+
+        Err(Bytes::copy_from_slice(&[code.into()]))
+    }
+
+    /// Moves every local end point listed in a Start or Suspend command from
+    /// `from` to `to`. Nothing changes if any of them is in the wrong state.
+    fn transition_all(
+        &mut self,
+        payload: &Bytes,
+        from: StreamState,
+        to: StreamState,
+        event: fn(u8) -> AvdtpEvent,
+    ) -> Result<Bytes, Bytes> {
+        if payload.is_empty() {
+            return Err(Bytes::copy_from_slice(&[0, ErrorCode::BadLength.into()]));
+        }
+
+        let seids: Vec<u8> = payload.iter().map(|b| b >> 2).collect();
+
+        for &seid in &seids {
+            let code = match self.endpoint(seid) {
+                Some(endpoint) if endpoint.state == from => continue,
+                Some(_) => ErrorCode::BadState,
+                None => ErrorCode::BadAcpSeid,
+            };
+
+            return Err(Bytes::copy_from_slice(&[seid << 2, code.into()]));
+        }
+
+        for seid in seids {
+            if let Some(endpoint) = self.endpoint_mut(seid) {
+                endpoint.state = to;
+            }
+
+            self.events.push_back(event(seid));
+        }
+
+        Ok(Bytes::new())
+    }
+}
+
+fn seid_payload(seid: u8) -> Bytes {
+    Bytes::copy_from_slice(&[seid << 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    fn session() -> (AvdtpSession, UnixStream) {
+        let (stream, remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+        let mut session = AvdtpSession::new(stream, Address::from([1, 2, 3, 4, 5, 6]));
+
+        session.add_endpoint(
+            EndpointInfo {
+                seid: 1,
+                in_use: false,
+                media_type: MediaType::Audio,
+                endpoint_type: EndpointType::Sink,
+            },
+            vec![
+                Capability::media_transport(),
+                Capability::media_codec(MediaType::Audio, 0x00, &[0xFF, 0xFF, 0x02, 0x35]),
+                Capability {
+                    category: ServiceCategory::DelayReporting,
+                    info: Bytes::new(),
+                },
+            ],
+        );
+
+        (session, remote)
+    }
+
+    /// Sends a command from the remote device, and returns the response of
+    /// the session.
+    async fn exchange(
+        session: &mut AvdtpSession,
+        remote: &mut UnixStream,
+        packet: &[u8],
+    ) -> Vec<u8> {
+        remote.write_all(packet).await.unwrap();
+        session.process().await.unwrap();
+
+        let mut buf = vec![0u8; 64];
+        let len = remote.read(&mut buf).await.unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[tokio::test]
+    async fn acceptor_stream_lifecycle() {
+        let (mut session, mut remote) = session();
+
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x10, 0x01]).await,
+            [0x12, 0x01, 0x04, 0x08]
+        );
+        // delay reporting is left out of Get Capabilities
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x20, 0x02, 0x04]).await,
+            [0x22, 0x02, 0x01, 0x00, 0x07, 0x06, 0x00, 0x00, 0xFF, 0xFF, 0x02, 0x35]
+        );
+
+        let configuration = [0x01, 0x00, 0x07, 0x06, 0x00, 0x00, 0x21, 0x15, 0x02, 0x35];
+        let mut command = vec![0x30, 0x03, 0x04, 0x08];
+        command.extend_from_slice(&configuration);
+        assert_eq!(
+            exchange(&mut session, &mut remote, &command).await,
+            [0x32, 0x03]
+        );
+        assert!(matches!(
+            session.events.pop_front(),
+            Some(AvdtpEvent::Configured {
+                seid: 1,
+                remote_seid: 2,
+                ..
+            })
+        ));
+        assert_eq!(session.endpoint_state(1), Some(StreamState::Configured));
+        assert_eq!(session.endpoint_configuration(1).unwrap().len(), 2);
+        // the end point is now in use
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x40, 0x01]).await,
+            [0x42, 0x01, 0x06, 0x08]
+        );
+
+        let steps: [(u8, AvdtpEvent, StreamState); 4] = [
+            (0x06, AvdtpEvent::Opened { seid: 1 }, StreamState::Open),
+            (
+                0x07,
+                AvdtpEvent::Started { seid: 1 },
+                StreamState::Streaming,
+            ),
+            (0x09, AvdtpEvent::Suspended { seid: 1 }, StreamState::Open),
+            (0x08, AvdtpEvent::Closed { seid: 1 }, StreamState::Idle),
+        ];
+        for (signal_id, event, state) in steps.iter().cloned() {
+            assert_eq!(
+                exchange(&mut session, &mut remote, &[0x50, signal_id, 0x04]).await,
+                [0x52, signal_id]
+            );
+            assert_eq!(session.events.pop_front(), Some(event));
+            assert_eq!(session.endpoint_state(1), Some(state));
+        }
+        assert_eq!(session.endpoint_configuration(1), Some(&[][..]));
+
+        drop(remote);
+        assert_eq!(session.recv().await.unwrap(), AvdtpEvent::SessionClosed);
+        assert!(matches!(session.recv().await, Err(Error::SessionClosed)));
+    }
+
+    #[tokio::test]
+    async fn acceptor_rejects() {
+        let (mut session, mut remote) = session();
+
+        // Start before the stream is configured
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x10, 0x07, 0x04]).await,
+            [0x13, 0x07, 0x04, 0x31]
+        );
+        // Open on an end point that does not exist
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x20, 0x06, 0x08]).await,
+            [0x23, 0x06, 0x12]
+        );
+        // a configuration with a category that the end point does not have
+        assert_eq!(
+            exchange(
+                &mut session,
+                &mut remote,
+                &[0x30, 0x03, 0x04, 0x08, 0x04, 0x00]
+            )
+            .await,
+            [0x33, 0x03, 0x04, 0x17]
+        );
+        assert_eq!(session.endpoint_state(1), Some(StreamState::Idle));
+
+        assert_eq!(
+            exchange(
+                &mut session,
+                &mut remote,
+                &[0x40, 0x03, 0x04, 0x08, 0x01, 0x00]
+            )
+            .await,
+            [0x42, 0x03]
+        );
+        // a second configuration of the same end point
+        assert_eq!(
+            exchange(
+                &mut session,
+                &mut remote,
+                &[0x50, 0x03, 0x04, 0x0C, 0x01, 0x00]
+            )
+            .await,
+            [0x53, 0x03, 0x00, 0x13]
+        );
+        // Abort works in any state
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x60, 0x0A, 0x04]).await,
+            [0x62, 0x0A]
+        );
+        assert_eq!(session.endpoint_state(1), Some(StreamState::Idle));
+        // Security Control is not supported
+        assert_eq!(
+            exchange(&mut session, &mut remote, &[0x70, 0x0B, 0x04]).await,
+            [0x71, 0x0B]
+        );
+
+        let events: Vec<_> = session.events.drain(..).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1], AvdtpEvent::Aborted { seid: 1 });
+    }
+}
@@ -0,0 +1,198 @@
+//! Framing of SBC media packets, as defined by A2DP. This splits packets into
+//! frames and reads the frame headers; decoding and encoding the audio is
+//! left to a codec library.
+
+use bytes::{Buf, Bytes};
+
+use super::error::Error;
+use super::media::MediaPacket;
+
+/// The first byte of every SBC frame.
+pub const SBC_SYNCWORD: u8 = 0x9C;
+
+/// The length of an SBC frame header, up to and including the CRC.
+const FRAME_HEADER_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbcChannelMode {
+    Mono,
+    DualChannel,
+    Stereo,
+    JointStereo,
+}
+
+impl SbcChannelMode {
+    pub fn channels(&self) -> usize {
+        match self {
+            SbcChannelMode::Mono => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbcAllocationMethod {
+    Loudness,
+    Snr,
+}
+
+/// The header of an SBC frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SbcFrameHeader {
+    /// The sampling frequency in Hz.
+    pub sampling_frequency: u32,
+    pub blocks: usize,
+    pub channel_mode: SbcChannelMode,
+    pub allocation_method: SbcAllocationMethod,
+    pub subbands: usize,
+    pub bitpool: u8,
+}
+
+impl SbcFrameHeader {
+    /// Parses the header at the start of an SBC frame.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < FRAME_HEADER_LEN || data[0] != SBC_SYNCWORD {
+            return Err(Error::InvalidPacket);
+        }
+
+        let flags = data[1];
+
+        Ok(Self {
+            sampling_frequency: match flags >> 6 {
+                0 => 16000,
+                1 => 32000,
+                2 => 44100,
+                _ => 48000,
+            },
+            blocks: 4 * (((flags >> 4) & 0x03) as usize + 1),
+            channel_mode: match (flags >> 2) & 0x03 {
+                0 => SbcChannelMode::Mono,
+                1 => SbcChannelMode::DualChannel,
+                2 => SbcChannelMode::Stereo,
+                _ => SbcChannelMode::JointStereo,
+            },
+            allocation_method: if flags & 0x02 != 0 {
+                SbcAllocationMethod::Snr
+            } else {
+                SbcAllocationMethod::Loudness
+            },
+            subbands: if flags & 0x01 != 0 { 8 } else { 4 },
+            bitpool: data[2],
+        })
+    }
+
+    /// The length of the whole frame in bytes, including this header.
+    pub fn frame_len(&self) -> usize {
+        let channels = self.channel_mode.channels();
+        let bitpool = self.bitpool as usize;
+
+        let audio_bits = match self.channel_mode {
+            SbcChannelMode::Mono | SbcChannelMode::DualChannel => self.blocks * channels * bitpool,
+            SbcChannelMode::Stereo => self.blocks * bitpool,
+            SbcChannelMode::JointStereo => self.subbands + self.blocks * bitpool,
+        };
+
+        FRAME_HEADER_LEN + (4 * self.subbands * channels) / 8 + audio_bits.div_ceil(8)
+    }
+
+    /// The number of samples per channel in the frame.
+    pub fn samples(&self) -> usize {
+        self.blocks * self.subbands
+    }
+}
+
+/// A media packet which carries SBC frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SbcPacket {
+    pub sequence: u16,
+    pub timestamp: u32,
+    /// Set if a frame was split across several packets, in which case
+    /// `frames` holds one piece of it.
+    pub fragmented: bool,
+    /// Set on the first packet of a fragmented frame.
+    pub starting: bool,
+    /// Set on the last packet of a fragmented frame.
+    pub last: bool,
+    /// The number of frames in the packet, or the number of packets which are
+    /// still to come (including this one) for a fragmented frame.
+    pub frame_count: u8,
+    /// The frames in the packet, each including its header.
+    pub frames: Vec<Bytes>,
+}
+
+impl SbcPacket {
+    /// Reads the SBC payload header of a media packet, and splits the rest of
+    /// the payload into frames.
+    pub fn parse(packet: MediaPacket) -> Result<Self, Error> {
+        let mut payload = packet.payload;
+
+        let header = match payload.first() {
+            Some(&header) => header,
+            None => return Err(Error::InvalidPacket),
+        };
+        payload.advance(1);
+
+        let fragmented = header & 0x80 != 0;
+        let frame_count = header & 0x0F;
+
+        let frames = if fragmented {
+            vec![payload]
+        } else {
+            let mut frames = Vec::with_capacity(frame_count as usize);
+
+            while !payload.is_empty() {
+                let len = SbcFrameHeader::parse(&payload)?.frame_len();
+
+                if payload.len() < len {
+                    return Err(Error::InvalidPacket);
+                }
+
+                frames.push(payload.split_to(len));
+            }
+
+            frames
+        };
+
+        Ok(Self {
+            sequence: packet.sequence,
+            timestamp: packet.timestamp,
+            fragmented,
+            starting: header & 0x40 != 0,
+            last: header & 0x20 != 0,
+            frame_count,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_frames() {
+        // 44.1 kHz, 16 blocks, joint stereo, loudness, 8 subbands, bitpool 53
+        let header = SbcFrameHeader::parse(&[0x9C, 0xBD, 0x35, 0x00]).unwrap();
+        assert_eq!(header.sampling_frequency, 44100);
+        assert_eq!(header.channel_mode, SbcChannelMode::JointStereo);
+        assert_eq!(header.frame_len(), 119);
+        assert_eq!(header.samples(), 128);
+
+        let mut payload = vec![0x02];
+        for _ in 0..2 {
+            let mut frame = vec![0u8; 119];
+            frame[..4].copy_from_slice(&[0x9C, 0xBD, 0x35, 0x00]);
+            payload.extend_from_slice(&frame);
+        }
+
+        let packet = SbcPacket::parse(MediaPacket {
+            sequence: 7,
+            timestamp: 128,
+            payload: payload.into(),
+        })
+        .unwrap();
+        assert_eq!(packet.frame_count, 2);
+        assert_eq!(packet.frames.len(), 2);
+        assert!(!packet.fragmented);
+    }
+}
@@ -0,0 +1,608 @@
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+
+/// The kind of an AVDTP signalling message.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Command = 0x00,
+    GeneralReject = 0x01,
+    ResponseAccept = 0x02,
+    ResponseReject = 0x03,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0x00 => MessageType::Command,
+            0x01 => MessageType::GeneralReject,
+            0x02 => MessageType::ResponseAccept,
+            _ => MessageType::ResponseReject,
+        }
+    }
+}
+
+/// How a signalling message is split across L2CAP packets.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketType {
+    Single = 0x00,
+    Start = 0x01,
+    Continue = 0x02,
+    End = 0x03,
+}
+
+impl PacketType {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0x00 => PacketType::Single,
+            0x01 => PacketType::Start,
+            0x02 => PacketType::Continue,
+            _ => PacketType::End,
+        }
+    }
+}
+
+/// The procedure that a signalling message belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignalId {
+    Discover,
+    GetCapabilities,
+    SetConfiguration,
+    GetConfiguration,
+    Reconfigure,
+    Open,
+    Start,
+    Close,
+    Suspend,
+    Abort,
+    SecurityControl,
+    GetAllCapabilities,
+    DelayReport,
+    Other(u8),
+}
+
+impl From<u8> for SignalId {
+    fn from(id: u8) -> Self {
+        match id & 0x3F {
+            0x01 => SignalId::Discover,
+            0x02 => SignalId::GetCapabilities,
+            0x03 => SignalId::SetConfiguration,
+            0x04 => SignalId::GetConfiguration,
+            0x05 => SignalId::Reconfigure,
+            0x06 => SignalId::Open,
+            0x07 => SignalId::Start,
+            0x08 => SignalId::Close,
+            0x09 => SignalId::Suspend,
+            0x0A => SignalId::Abort,
+            0x0B => SignalId::SecurityControl,
+            0x0C => SignalId::GetAllCapabilities,
+            0x0D => SignalId::DelayReport,
+            id => SignalId::Other(id),
+        }
+    }
+}
+
+impl From<SignalId> for u8 {
+    fn from(id: SignalId) -> Self {
+        match id {
+            SignalId::Discover => 0x01,
+            SignalId::GetCapabilities => 0x02,
+            SignalId::SetConfiguration => 0x03,
+            SignalId::GetConfiguration => 0x04,
+            SignalId::Reconfigure => 0x05,
+            SignalId::Open => 0x06,
+            SignalId::Start => 0x07,
+            SignalId::Close => 0x08,
+            SignalId::Suspend => 0x09,
+            SignalId::Abort => 0x0A,
+            SignalId::SecurityControl => 0x0B,
+            SignalId::GetAllCapabilities => 0x0C,
+            SignalId::DelayReport => 0x0D,
+            SignalId::Other(id) => id & 0x3F,
+        }
+    }
+}
+
+/// A complete signalling message, after the packets that it was split into
+/// have been put back together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    /// Identifies a command and its response. Only the lower 4 bits are
+    /// used.
+    pub transaction: u8,
+    pub message_type: MessageType,
+    pub signal_id: SignalId,
+    pub payload: Bytes,
+}
+
+impl Signal {
+    pub fn command(transaction: u8, signal_id: SignalId, payload: Bytes) -> Self {
+        Self {
+            transaction,
+            message_type: MessageType::Command,
+            signal_id,
+            payload,
+        }
+    }
+
+    /// Encodes the message into packets of at most `mtu` bytes. Fails with
+    /// [`Error::MessageTooLong`] if the message needs more than 255 packets.
+    pub fn encode(&self, mtu: usize) -> Result<Vec<Bytes>, Error> {
+        let header = |packet_type: PacketType| {
+            ((self.transaction & 0x0F) << 4) | ((packet_type as u8) << 2) | self.message_type as u8
+        };
+
+        if self.payload.len() + 2 <= mtu {
+            let mut buf = BytesMut::with_capacity(self.payload.len() + 2);
+            buf.put_u8(header(PacketType::Single));
+            buf.put_u8(self.signal_id.into());
+            buf.put_slice(&self.payload[..]);
+            return Ok(vec![buf.freeze()]);
+        }
+
+        // the start packet has a 3 byte header and every other packet a 1
+        // byte header
+        let mtu = mtu.max(4);
+        let first = mtu - 3;
+        let rest = &self.payload[first..];
+        let count = 1 + (rest.len() + mtu - 2) / (mtu - 1);
+        // the start packet holds the number of packets in one byte
+        let count_byte = u8::try_from(count).map_err(|_| Error::MessageTooLong)?;
+
+        let mut packets = Vec::with_capacity(count);
+
+        let mut buf = BytesMut::with_capacity(mtu);
+        buf.put_u8(header(PacketType::Start));
+        buf.put_u8(count_byte);
+        buf.put_u8(self.signal_id.into());
+        buf.put_slice(&self.payload[..first]);
+        packets.push(buf.freeze());
+
+        let mut chunks = rest.chunks(mtu - 1).peekable();
+        while let Some(chunk) = chunks.next() {
+            let packet_type = match chunks.peek() {
+                Some(_) => PacketType::Continue,
+                None => PacketType::End,
+            };
+
+            let mut buf = BytesMut::with_capacity(chunk.len() + 1);
+            buf.put_u8(header(packet_type));
+            buf.put_slice(chunk);
+            packets.push(buf.freeze());
+        }
+
+        Ok(packets)
+    }
+}
+
+/// Puts signalling messages back together from the packets that they were
+/// split into.
+#[derive(Debug, Default)]
+pub struct SignalAssembler {
+    partial: Option<(Signal, usize)>,
+}
+
+impl SignalAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a packet, and returns the message once all of its packets have
+    /// been added.
+    pub fn push(&mut self, mut packet: Bytes) -> Result<Option<Signal>, Error> {
+        if packet.is_empty() {
+            return Err(Error::InvalidPacket);
+        }
+
+        let header = packet.get_u8();
+        let transaction = header >> 4;
+        let message_type = MessageType::from_bits(header);
+
+        match PacketType::from_bits(header >> 2) {
+            PacketType::Single => {
+                if !packet.has_remaining() {
+                    return Err(Error::InvalidPacket);
+                }
+
+                self.partial = None;
+                Ok(Some(Signal {
+                    transaction,
+                    message_type,
+                    signal_id: packet.get_u8().into(),
+                    payload: packet,
+                }))
+            }
+            PacketType::Start => {
+                if packet.remaining() < 2 {
+                    return Err(Error::InvalidPacket);
+                }
+
+                let count = packet.get_u8() as usize;
+                let signal_id = packet.get_u8().into();
+                self.partial = Some((
+                    Signal {
+                        transaction,
+                        message_type,
+                        signal_id,
+                        payload: packet,
+                    },
+                    count.saturating_sub(1),
+                ));
+                Ok(None)
+            }
+            packet_type => {
+                let (mut signal, remaining) = match self.partial.take() {
+                    Some((signal, remaining)) if signal.transaction == transaction => {
+                        (signal, remaining)
+                    }
+                    _ => return Err(Error::InvalidPacket),
+                };
+
+                let mut payload = BytesMut::from(&signal.payload[..]);
+                payload.put_slice(&packet[..]);
+                signal.payload = payload.freeze();
+
+                if packet_type == PacketType::End || remaining <= 1 {
+                    Ok(Some(signal))
+                } else {
+                    self.partial = Some((signal, remaining - 1));
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// Whether a stream end point sends or receives media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointType {
+    Source,
+    Sink,
+}
+
+/// The kind of media that a stream end point carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Audio,
+    Video,
+    Multimedia,
+    Other(u8),
+}
+
+impl From<u8> for MediaType {
+    fn from(media_type: u8) -> Self {
+        match media_type {
+            0x00 => MediaType::Audio,
+            0x01 => MediaType::Video,
+            0x02 => MediaType::Multimedia,
+            media_type => MediaType::Other(media_type),
+        }
+    }
+}
+
+impl From<MediaType> for u8 {
+    fn from(media_type: MediaType) -> Self {
+        match media_type {
+            MediaType::Audio => 0x00,
+            MediaType::Video => 0x01,
+            MediaType::Multimedia => 0x02,
+            MediaType::Other(media_type) => media_type,
+        }
+    }
+}
+
+/// A stream end point, as listed in a Discover response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointInfo {
+    /// The stream end point identifier, between 1 and 62.
+    pub seid: u8,
+    /// Whether the end point is already used by a stream.
+    pub in_use: bool,
+    pub media_type: MediaType,
+    pub endpoint_type: EndpointType,
+}
+
+impl EndpointInfo {
+    pub fn encode(&self, buf: &mut impl BufMut) {
+        buf.put_u8((self.seid << 2) | ((self.in_use as u8) << 1));
+        buf.put_u8(
+            (u8::from(self.media_type) << 4)
+                | ((matches!(self.endpoint_type, EndpointType::Sink) as u8) << 3),
+        );
+    }
+
+    /// Parses the list of end points in a Discover response.
+    pub fn parse_list(mut buf: Bytes) -> Result<Vec<Self>, Error> {
+        if !buf.chunks_exact(2).remainder().is_empty() {
+            return Err(Error::InvalidPacket);
+        }
+
+        let mut endpoints = Vec::with_capacity(buf.len() / 2);
+        while buf.has_remaining() {
+            let first = buf.get_u8();
+            let second = buf.get_u8();
+
+            endpoints.push(Self {
+                seid: first >> 2,
+                in_use: first & 0x02 != 0,
+                media_type: (second >> 4).into(),
+                endpoint_type: if second & 0x08 != 0 {
+                    EndpointType::Sink
+                } else {
+                    EndpointType::Source
+                },
+            });
+        }
+
+        Ok(endpoints)
+    }
+}
+
+/// The category of a service capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ServiceCategory {
+    MediaTransport,
+    Reporting,
+    Recovery,
+    ContentProtection,
+    HeaderCompression,
+    Multiplexing,
+    MediaCodec,
+    DelayReporting,
+    Other(u8),
+}
+
+impl From<u8> for ServiceCategory {
+    fn from(category: u8) -> Self {
+        match category {
+            0x01 => ServiceCategory::MediaTransport,
+            0x02 => ServiceCategory::Reporting,
+            0x03 => ServiceCategory::Recovery,
+            0x04 => ServiceCategory::ContentProtection,
+            0x05 => ServiceCategory::HeaderCompression,
+            0x06 => ServiceCategory::Multiplexing,
+            0x07 => ServiceCategory::MediaCodec,
+            0x08 => ServiceCategory::DelayReporting,
+            category => ServiceCategory::Other(category),
+        }
+    }
+}
+
+impl From<ServiceCategory> for u8 {
+    fn from(category: ServiceCategory) -> Self {
+        match category {
+            ServiceCategory::MediaTransport => 0x01,
+            ServiceCategory::Reporting => 0x02,
+            ServiceCategory::Recovery => 0x03,
+            ServiceCategory::ContentProtection => 0x04,
+            ServiceCategory::HeaderCompression => 0x05,
+            ServiceCategory::Multiplexing => 0x06,
+            ServiceCategory::MediaCodec => 0x07,
+            ServiceCategory::DelayReporting => 0x08,
+            ServiceCategory::Other(category) => category,
+        }
+    }
+}
+
+/// A service capability of a stream end point, or a part of the
+/// configuration of a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub category: ServiceCategory,
+    /// The service capabilities information elements, whose layout depends
+    /// on the category. See [`Capability::media_codec`] for the most common
+    /// one.
+    pub info: Bytes,
+}
+
+impl Capability {
+    pub fn new(category: ServiceCategory, info: impl Into<Bytes>) -> Self {
+        Self {
+            category,
+            info: info.into(),
+        }
+    }
+
+    /// The Media Transport capability, which every stream needs.
+    pub fn media_transport() -> Self {
+        Self::new(ServiceCategory::MediaTransport, Bytes::new())
+    }
+
+    /// A Media Codec capability. `codec_type` is defined by the assigned
+    /// numbers, such as 0x00 for SBC, and `codec_info` by the specification
+    /// of the codec.
+    pub fn media_codec(media_type: MediaType, codec_type: u8, codec_info: &[u8]) -> Self {
+        let mut info = BytesMut::with_capacity(codec_info.len() + 2);
+        info.put_u8(u8::from(media_type) << 4);
+        info.put_u8(codec_type);
+        info.put_slice(codec_info);
+        Self::new(ServiceCategory::MediaCodec, info.freeze())
+    }
+
+    /// Encodes a list of capabilities.
+    pub fn encode_list(capabilities: &[Capability], buf: &mut impl BufMut) {
+        for capability in capabilities {
+            buf.put_u8(capability.category.into());
+            buf.put_u8(capability.info.len() as u8);
+            buf.put_slice(&capability.info[..]);
+        }
+    }
+
+    /// Parses a list of capabilities, as found in Get Capabilities responses
+    /// and Set Configuration commands.
+    pub fn parse_list(mut buf: Bytes) -> Result<Vec<Self>, Error> {
+        let mut capabilities = vec![];
+
+        while buf.has_remaining() {
+            if buf.remaining() < 2 {
+                return Err(Error::InvalidPacket);
+            }
+
+            let category = buf.get_u8().into();
+            let len = buf.get_u8() as usize;
+
+            if buf.remaining() < len {
+                return Err(Error::InvalidPacket);
+            }
+
+            capabilities.push(Self {
+                category,
+                info: buf.split_to(len),
+            });
+        }
+
+        Ok(capabilities)
+    }
+}
+
+/// The reason that a command was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadHeaderFormat,
+    BadLength,
+    BadAcpSeid,
+    SepInUse,
+    SepNotInUse,
+    BadServiceCategory,
+    BadPayloadFormat,
+    NotSupportedCommand,
+    InvalidCapabilities,
+    BadRecoveryType,
+    BadMediaTransportFormat,
+    BadRecoveryFormat,
+    BadRohcFormat,
+    BadCpFormat,
+    BadMultiplexingFormat,
+    UnsupportedConfiguration,
+    BadState,
+    Other(u8),
+}
+
+impl From<u8> for ErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => ErrorCode::BadHeaderFormat,
+            0x11 => ErrorCode::BadLength,
+            0x12 => ErrorCode::BadAcpSeid,
+            0x13 => ErrorCode::SepInUse,
+            0x14 => ErrorCode::SepNotInUse,
+            0x17 => ErrorCode::BadServiceCategory,
+            0x18 => ErrorCode::BadPayloadFormat,
+            0x19 => ErrorCode::NotSupportedCommand,
+            0x1A => ErrorCode::InvalidCapabilities,
+            0x22 => ErrorCode::BadRecoveryType,
+            0x23 => ErrorCode::BadMediaTransportFormat,
+            0x25 => ErrorCode::BadRecoveryFormat,
+            0x26 => ErrorCode::BadRohcFormat,
+            0x27 => ErrorCode::BadCpFormat,
+            0x28 => ErrorCode::BadMultiplexingFormat,
+            0x29 => ErrorCode::UnsupportedConfiguration,
+            0x31 => ErrorCode::BadState,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::BadHeaderFormat => 0x01,
+            ErrorCode::BadLength => 0x11,
+            ErrorCode::BadAcpSeid => 0x12,
+            ErrorCode::SepInUse => 0x13,
+            ErrorCode::SepNotInUse => 0x14,
+            ErrorCode::BadServiceCategory => 0x17,
+            ErrorCode::BadPayloadFormat => 0x18,
+            ErrorCode::NotSupportedCommand => 0x19,
+            ErrorCode::InvalidCapabilities => 0x1A,
+            ErrorCode::BadRecoveryType => 0x22,
+            ErrorCode::BadMediaTransportFormat => 0x23,
+            ErrorCode::BadRecoveryFormat => 0x25,
+            ErrorCode::BadRohcFormat => 0x26,
+            ErrorCode::BadCpFormat => 0x27,
+            ErrorCode::BadMultiplexingFormat => 0x28,
+            ErrorCode::UnsupportedConfiguration => 0x29,
+            ErrorCode::BadState => 0x31,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_round_trip() {
+        let command = Signal::command(3, SignalId::Discover, Bytes::new());
+        assert_eq!(
+            command.encode(672).unwrap(),
+            vec![Bytes::from_static(&[0x30, 0x01])]
+        );
+
+        let mut payload = BytesMut::new();
+        EndpointInfo {
+            seid: 1,
+            in_use: false,
+            media_type: MediaType::Audio,
+            endpoint_type: EndpointType::Sink,
+        }
+        .encode(&mut payload);
+        assert_eq!(&payload[..], &[0x04, 0x08]);
+
+        let mut assembler = SignalAssembler::new();
+        let response = assembler
+            .push(Bytes::from_static(&[0x32, 0x01, 0x04, 0x08]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.message_type, MessageType::ResponseAccept);
+        assert_eq!(response.signal_id, SignalId::Discover);
+
+        let endpoints = EndpointInfo::parse_list(response.payload).unwrap();
+        assert_eq!(endpoints[0].seid, 1);
+        assert_eq!(endpoints[0].endpoint_type, EndpointType::Sink);
+    }
+
+    #[test]
+    fn fragmented_signal() {
+        let mut payload = BytesMut::new();
+        Capability::encode_list(
+            &[
+                Capability::media_transport(),
+                Capability::media_codec(MediaType::Audio, 0x00, &[0xFF; 40]),
+            ],
+            &mut payload,
+        );
+        let signal = Signal::command(5, SignalId::SetConfiguration, payload.freeze());
+
+        let packets = signal.encode(16).unwrap();
+        assert!(packets.len() > 2);
+        assert_eq!(packets[0][0] & 0x0C, 0x04);
+        assert_eq!(packets[0][1] as usize, packets.len());
+
+        let mut assembler = SignalAssembler::new();
+        let mut assembled = None;
+        for packet in packets {
+            assembled = assembler.push(packet).unwrap();
+        }
+        assert_eq!(assembled.unwrap(), signal);
+
+        let capabilities = Capability::parse_list(signal.payload).unwrap();
+        assert_eq!(capabilities[1].category, ServiceCategory::MediaCodec);
+        assert_eq!(capabilities[1].info.len(), 42);
+    }
+
+    #[test]
+    fn too_many_packets() {
+        // a start packet with 1 byte of payload, and 255 packets with 3
+        let signal = Signal::command(1, SignalId::SetConfiguration, vec![0; 1 + 255 * 3].into());
+        assert!(matches!(signal.encode(4), Err(Error::MessageTooLong)));
+
+        let signal = Signal::command(1, SignalId::SetConfiguration, vec![0; 1 + 254 * 3].into());
+        assert_eq!(signal.encode(4).unwrap().len(), 255);
+    }
+}
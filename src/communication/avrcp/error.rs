@@ -0,0 +1,19 @@
+use super::frame::AvcCode;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid packet")]
+    InvalidPacket,
+
+    #[error("the remote device does not implement this command")]
+    NotImplemented,
+
+    #[error("the remote device answered with {0:?}")]
+    Rejected(AvcCode),
+
+    #[error("the avrcp session has been closed")]
+    SessionClosed,
+}
@@ -0,0 +1,348 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+
+/// The profile identifier of AVRCP, which is carried in every AVCTP packet.
+pub const AV_REMOTE_CONTROL_PID: u16 = 0x110E;
+
+/// The company ID of the Bluetooth SIG, which prefixes every AVRCP specific
+/// command in a Vendor Dependent frame.
+pub const BLUETOOTH_SIG_COMPANY_ID: u32 = 0x001958;
+
+/// The subunit type (panel) and ID (0) that AVRCP commands are addressed to.
+const PANEL_SUBUNIT: u8 = 0x09 << 3;
+
+pub const PDU_REGISTER_NOTIFICATION: u8 = 0x31;
+pub const PDU_SET_ABSOLUTE_VOLUME: u8 = 0x50;
+
+pub const EVENT_PLAYBACK_STATUS_CHANGED: u8 = 0x01;
+pub const EVENT_TRACK_CHANGED: u8 = 0x02;
+pub const EVENT_VOLUME_CHANGED: u8 = 0x0D;
+
+/// An AVCTP packet. Packets that are split into several fragments are not
+/// supported, since AV/C frames fit in the minimum MTU of the control
+/// channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvctpPacket {
+    /// Identifies a command and its response. Only the lower 4 bits are
+    /// used.
+    pub transaction: u8,
+    pub response: bool,
+    /// Set in responses to packets with a profile identifier that the
+    /// receiver does not support.
+    pub invalid_pid: bool,
+    pub pid: u16,
+    pub payload: Bytes,
+}
+
+impl AvctpPacket {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(3 + self.payload.len());
+        buf.put_u8(
+            ((self.transaction & 0x0F) << 4)
+                | ((self.response as u8) << 1)
+                | self.invalid_pid as u8,
+        );
+        buf.put_u16(self.pid);
+        buf.put_slice(&self.payload[..]);
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < 3 {
+            return Err(Error::InvalidPacket);
+        }
+
+        let header = buf.get_u8();
+
+        // only single packets are supported
+        if header & 0x0C != 0 {
+            return Err(Error::InvalidPacket);
+        }
+
+        Ok(Self {
+            transaction: header >> 4,
+            response: header & 0x02 != 0,
+            invalid_pid: header & 0x01 != 0,
+            pid: buf.get_u16(),
+            payload: buf,
+        })
+    }
+}
+
+/// The command type of an AV/C command, or the response code of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvcCode {
+    Control,
+    Status,
+    SpecificInquiry,
+    Notify,
+    GeneralInquiry,
+    NotImplemented,
+    Accepted,
+    Rejected,
+    InTransition,
+    /// Also known as Implemented, in responses to inquiries.
+    Stable,
+    Changed,
+    Interim,
+    Other(u8),
+}
+
+impl From<u8> for AvcCode {
+    fn from(code: u8) -> Self {
+        match code & 0x0F {
+            0x00 => AvcCode::Control,
+            0x01 => AvcCode::Status,
+            0x02 => AvcCode::SpecificInquiry,
+            0x03 => AvcCode::Notify,
+            0x04 => AvcCode::GeneralInquiry,
+            0x08 => AvcCode::NotImplemented,
+            0x09 => AvcCode::Accepted,
+            0x0A => AvcCode::Rejected,
+            0x0B => AvcCode::InTransition,
+            0x0C => AvcCode::Stable,
+            0x0D => AvcCode::Changed,
+            0x0F => AvcCode::Interim,
+            code => AvcCode::Other(code),
+        }
+    }
+}
+
+impl From<AvcCode> for u8 {
+    fn from(code: AvcCode) -> Self {
+        match code {
+            AvcCode::Control => 0x00,
+            AvcCode::Status => 0x01,
+            AvcCode::SpecificInquiry => 0x02,
+            AvcCode::Notify => 0x03,
+            AvcCode::GeneralInquiry => 0x04,
+            AvcCode::NotImplemented => 0x08,
+            AvcCode::Accepted => 0x09,
+            AvcCode::Rejected => 0x0A,
+            AvcCode::InTransition => 0x0B,
+            AvcCode::Stable => 0x0C,
+            AvcCode::Changed => 0x0D,
+            AvcCode::Interim => 0x0F,
+            AvcCode::Other(code) => code & 0x0F,
+        }
+    }
+}
+
+/// The AV/C opcodes that AVRCP uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvcOpcode {
+    VendorDependent,
+    UnitInfo,
+    SubunitInfo,
+    PassThrough,
+    Other(u8),
+}
+
+impl From<u8> for AvcOpcode {
+    fn from(opcode: u8) -> Self {
+        match opcode {
+            0x00 => AvcOpcode::VendorDependent,
+            0x30 => AvcOpcode::UnitInfo,
+            0x31 => AvcOpcode::SubunitInfo,
+            0x7C => AvcOpcode::PassThrough,
+            opcode => AvcOpcode::Other(opcode),
+        }
+    }
+}
+
+impl From<AvcOpcode> for u8 {
+    fn from(opcode: AvcOpcode) -> Self {
+        match opcode {
+            AvcOpcode::VendorDependent => 0x00,
+            AvcOpcode::UnitInfo => 0x30,
+            AvcOpcode::SubunitInfo => 0x31,
+            AvcOpcode::PassThrough => 0x7C,
+            AvcOpcode::Other(opcode) => opcode,
+        }
+    }
+}
+
+/// An AV/C frame, which is the payload of AVCTP packets for AVRCP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvcFrame {
+    pub code: AvcCode,
+    /// The subunit type and ID. AVRCP commands use the panel subunit.
+    pub subunit: u8,
+    pub opcode: AvcOpcode,
+    pub operands: Bytes,
+}
+
+impl AvcFrame {
+    /// Creates a frame which is addressed to the panel subunit.
+    pub fn new(code: AvcCode, opcode: AvcOpcode, operands: Bytes) -> Self {
+        Self {
+            code,
+            subunit: PANEL_SUBUNIT,
+            opcode,
+            operands,
+        }
+    }
+
+    /// Creates a Pass Through command which presses or releases a button.
+    pub fn pass_through(operation: PassThroughOp, pressed: bool) -> Self {
+        let state = if pressed { 0x00 } else { 0x80 };
+        Self::new(
+            AvcCode::Control,
+            AvcOpcode::PassThrough,
+            Bytes::copy_from_slice(&[state | u8::from(operation), 0x00]),
+        )
+    }
+
+    /// Creates a Vendor Dependent frame which carries an AVRCP specific
+    /// command.
+    pub fn vendor_dependent(code: AvcCode, pdu_id: u8, params: &[u8]) -> Self {
+        let mut operands = BytesMut::with_capacity(7 + params.len());
+        operands.put_uint(BLUETOOTH_SIG_COMPANY_ID as u64, 3);
+        operands.put_u8(pdu_id);
+        // single packet
+        operands.put_u8(0x00);
+        operands.put_u16(params.len() as u16);
+        operands.put_slice(params);
+
+        Self::new(code, AvcOpcode::VendorDependent, operands.freeze())
+    }
+
+    /// Reads the PDU ID and parameters of an AVRCP specific command or
+    /// response.
+    pub fn vendor_pdu(&self) -> Result<(u8, Bytes), Error> {
+        let mut operands = self.operands.clone();
+
+        if self.opcode != AvcOpcode::VendorDependent || operands.len() < 7 {
+            return Err(Error::InvalidPacket);
+        }
+
+        if operands.get_uint(3) as u32 != BLUETOOTH_SIG_COMPANY_ID {
+            return Err(Error::InvalidPacket);
+        }
+
+        let pdu_id = operands.get_u8();
+        operands.advance(1);
+        let len = operands.get_u16() as usize;
+
+        if operands.len() < len {
+            return Err(Error::InvalidPacket);
+        }
+
+        Ok((pdu_id, operands.split_to(len)))
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(3 + self.operands.len());
+        buf.put_u8(self.code.into());
+        buf.put_u8(self.subunit);
+        buf.put_u8(self.opcode.into());
+        buf.put_slice(&self.operands[..]);
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < 3 {
+            return Err(Error::InvalidPacket);
+        }
+
+        Ok(Self {
+            code: buf.get_u8().into(),
+            subunit: buf.get_u8(),
+            opcode: buf.get_u8().into(),
+            operands: buf,
+        })
+    }
+}
+
+/// The buttons that can be pressed with Pass Through commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassThroughOp {
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    Play,
+    Stop,
+    Pause,
+    Rewind,
+    FastForward,
+    Forward,
+    Backward,
+    Other(u8),
+}
+
+impl From<u8> for PassThroughOp {
+    fn from(op: u8) -> Self {
+        match op & 0x7F {
+            0x41 => PassThroughOp::VolumeUp,
+            0x42 => PassThroughOp::VolumeDown,
+            0x43 => PassThroughOp::Mute,
+            0x44 => PassThroughOp::Play,
+            0x45 => PassThroughOp::Stop,
+            0x46 => PassThroughOp::Pause,
+            0x48 => PassThroughOp::Rewind,
+            0x49 => PassThroughOp::FastForward,
+            0x4B => PassThroughOp::Forward,
+            0x4C => PassThroughOp::Backward,
+            op => PassThroughOp::Other(op),
+        }
+    }
+}
+
+impl From<PassThroughOp> for u8 {
+    fn from(op: PassThroughOp) -> Self {
+        match op {
+            PassThroughOp::VolumeUp => 0x41,
+            PassThroughOp::VolumeDown => 0x42,
+            PassThroughOp::Mute => 0x43,
+            PassThroughOp::Play => 0x44,
+            PassThroughOp::Stop => 0x45,
+            PassThroughOp::Pause => 0x46,
+            PassThroughOp::Rewind => 0x48,
+            PassThroughOp::FastForward => 0x49,
+            PassThroughOp::Forward => 0x4B,
+            PassThroughOp::Backward => 0x4C,
+            PassThroughOp::Other(op) => op & 0x7F,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_through() {
+        let packet = AvctpPacket {
+            transaction: 2,
+            response: false,
+            invalid_pid: false,
+            pid: AV_REMOTE_CONTROL_PID,
+            payload: AvcFrame::pass_through(PassThroughOp::Play, true).encode(),
+        };
+
+        assert_eq!(
+            &packet.encode()[..],
+            &[0x20, 0x11, 0x0E, 0x00, 0x48, 0x7C, 0x44, 0x00]
+        );
+    }
+
+    #[test]
+    fn volume_notification() {
+        #[rustfmt::skip]
+        let response = Bytes::from_static(&[
+            0x22, 0x11, 0x0E,
+            0x0F, 0x48, 0x00, 0x00, 0x19, 0x58, 0x31, 0x00, 0x00, 0x02, 0x0D, 0x40,
+        ]);
+
+        let packet = AvctpPacket::parse(response).unwrap();
+        assert!(packet.response);
+
+        let frame = AvcFrame::parse(packet.payload).unwrap();
+        assert_eq!(frame.code, AvcCode::Interim);
+
+        let (pdu_id, params) = frame.vendor_pdu().unwrap();
+        assert_eq!(pdu_id, PDU_REGISTER_NOTIFICATION);
+        assert_eq!(&params[..], &[EVENT_VOLUME_CHANGED, 0x40]);
+    }
+}
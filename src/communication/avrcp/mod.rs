@@ -0,0 +1,468 @@
+//! The controller role of AVRCP, the Audio/Video Remote Control Profile, and
+//! the AVCTP transport that it runs on.
+//!
+//! An [`AvrcpController`] sends Pass Through commands, which press the
+//! buttons of a media player on the remote device, and sets and follows the
+//! absolute volume of a remote audio sink. Commands from the remote device are
+//! answered with Not Implemented, since the target role is not implemented.
+//!
+//! As with [`AvdtpSession`], no tasks are spawned, so call
+//! [`AvrcpController::recv`] in a loop to receive notifications.
+//!
+//! [`AvdtpSession`]: super::avdtp::AvdtpSession
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+use frame::*;
+
+use super::stream::{BluetoothListener, BluetoothStream};
+use crate::{Address, AddressType, Protocol};
+
+mod error;
+pub mod frame;
+
+/// The PSM of the AVCTP control channel.
+pub const AVCTP_PSM: u16 = 0x0017;
+
+/// The smallest MTU that the control channel may have.
+const CONTROL_MTU: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AvrcpEvent {
+    /// The volume of the remote device changed. This is only received after
+    /// [`AvrcpController::register_volume_notification`]. The volume is
+    /// between 0 and 0x7F.
+    VolumeChanged { volume: u8 },
+    /// The remote device closed the control channel. No further events will
+    /// be received.
+    SessionClosed,
+}
+
+/// The controller role of AVRCP, connected to a target on a remote device.
+#[derive(Debug)]
+pub struct AvrcpController {
+    stream: BluetoothStream,
+    transaction: u8,
+    closed: bool,
+    response: Option<(u8, AvcFrame)>,
+    /// The transaction labels of notifications that are registered, and the
+    /// events they are for.
+    notifications: HashMap<u8, u8>,
+    events: VecDeque<AvrcpEvent>,
+}
+
+impl AvrcpController {
+    /// Opens the control channel to a remote device.
+    pub async fn connect(address: Address) -> Result<Self, Error> {
+        let stream =
+            BluetoothStream::connect(Protocol::L2CAP, address, AddressType::BREDR, AVCTP_PSM)
+                .await?;
+
+        Ok(Self::accept(stream))
+    }
+
+    /// Creates a controller from an L2CAP connection that a remote device
+    /// opened on [`AVCTP_PSM`].
+    pub fn accept(stream: BluetoothStream) -> Self {
+        Self {
+            stream,
+            transaction: 0,
+            closed: false,
+            response: None,
+            notifications: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Creates a listener for incoming AVCTP connections on the local adapter
+    /// with the given address. Use [`AvrcpController::accept`] on the streams
+    /// it returns.
+    pub fn bind(address: Address) -> Result<BluetoothListener, Error> {
+        Ok(BluetoothListener::bind(
+            Protocol::L2CAP,
+            address,
+            AddressType::BREDR,
+            AVCTP_PSM,
+        )?)
+    }
+
+    /// Presses and releases a button.
+    pub async fn pass_through(&mut self, operation: PassThroughOp) -> Result<(), Error> {
+        for pressed in [true, false] {
+            let response = self
+                .command(AvcFrame::pass_through(operation, pressed))
+                .await?;
+            expect(&response, AvcCode::Accepted)?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn play(&mut self) -> Result<(), Error> {
+        self.pass_through(PassThroughOp::Play).await
+    }
+
+    pub async fn pause(&mut self) -> Result<(), Error> {
+        self.pass_through(PassThroughOp::Pause).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), Error> {
+        self.pass_through(PassThroughOp::Stop).await
+    }
+
+    /// Skips to the next track.
+    pub async fn next(&mut self) -> Result<(), Error> {
+        self.pass_through(PassThroughOp::Forward).await
+    }
+
+    /// Skips to the previous track.
+    pub async fn previous(&mut self) -> Result<(), Error> {
+        self.pass_through(PassThroughOp::Backward).await
+    }
+
+    /// Sets the absolute volume of the remote device, between 0 and 0x7F,
+    /// and returns the volume that it actually set.
+    pub async fn set_absolute_volume(&mut self, volume: u8) -> Result<u8, Error> {
+        let response = self
+            .command(AvcFrame::vendor_dependent(
+                AvcCode::Control,
+                PDU_SET_ABSOLUTE_VOLUME,
+                &[volume & 0x7F],
+            ))
+            .await?;
+        expect(&response, AvcCode::Accepted)?;
+
+        let (_, params) = response.vendor_pdu()?;
+        params
+            .first()
+            .map(|volume| volume & 0x7F)
+            .ok_or(Error::InvalidPacket)
+    }
+
+    /// Asks the remote device to report changes to its volume, and returns
+    /// the current volume. Changes are reported through
+    /// [`AvrcpEvent::VolumeChanged`]; the notification is registered again
+    /// after each change, so they keep coming until the session is closed.
+    pub async fn register_volume_notification(&mut self) -> Result<u8, Error> {
+        let transaction = self.send_command(volume_registration()).await?;
+        let response = self.wait_for_response(transaction).await?;
+        expect(&response, AvcCode::Interim)?;
+
+        let (_, params) = response.vendor_pdu()?;
+        let volume = params.get(1).ok_or(Error::InvalidPacket)? & 0x7F;
+
+        self.notifications.insert(transaction, EVENT_VOLUME_CHANGED);
+
+        Ok(volume)
+    }
+
+    /// Waits for the next event, answering commands from the remote device
+    /// in the meantime.
+    pub async fn recv(&mut self) -> Result<AvrcpEvent, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+
+            self.process().await?;
+        }
+    }
+
+    /// Returns the control channel.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+
+    /// Picks the next transaction label which is not used by a registered
+    /// notification.
+    fn next_transaction(&mut self) -> u8 {
+        loop {
+            self.transaction = (self.transaction + 1) & 0x0F;
+
+            if !self.notifications.contains_key(&self.transaction) {
+                return self.transaction;
+            }
+        }
+    }
+
+    async fn send(&mut self, packet: AvctpPacket) -> Result<(), Error> {
+        self.stream.write_all(&packet.encode()[..]).await?;
+        Ok(())
+    }
+
+    async fn send_command(&mut self, frame: AvcFrame) -> Result<u8, Error> {
+        let transaction = self.next_transaction();
+
+        self.send(AvctpPacket {
+            transaction,
+            response: false,
+            invalid_pid: false,
+            pid: AV_REMOTE_CONTROL_PID,
+            payload: frame.encode(),
+        })
+        .await?;
+
+        Ok(transaction)
+    }
+
+    /// Sends a command and waits for its response.
+    async fn command(&mut self, frame: AvcFrame) -> Result<AvcFrame, Error> {
+        let transaction = self.send_command(frame).await?;
+        self.wait_for_response(transaction).await
+    }
+
+    async fn wait_for_response(&mut self, transaction: u8) -> Result<AvcFrame, Error> {
+        loop {
+            self.process().await?;
+
+            if self.closed {
+                return Err(Error::SessionClosed);
+            }
+
+            match self.response.take() {
+                Some((label, response)) if label == transaction => return Ok(response),
+                _ => continue,
+            }
+        }
+    }
+
+    async fn process(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::SessionClosed);
+        }
+
+        let mut buf = BytesMut::with_capacity(CONTROL_MTU);
+
+        if self.stream.read_buf(&mut buf).await? == 0 {
+            self.closed = true;
+            self.events.push_back(AvrcpEvent::SessionClosed);
+            return Ok(());
+        }
+
+        let packet = AvctpPacket::parse(buf.freeze())?;
+
+        if !packet.response {
+            return self.answer(packet).await;
+        }
+
+        let frame = AvcFrame::parse(packet.payload)?;
+
+        let event_id = match self.notifications.get(&packet.transaction) {
+            Some(&event_id) => event_id,
+            None => {
+                self.response = Some((packet.transaction, frame));
+                return Ok(());
+            }
+        };
+
+        match frame.code {
+            // the interim response to a registration that was renewed
+            AvcCode::Interim => {}
+            AvcCode::Changed => {
+                self.notifications.remove(&packet.transaction);
+
+                let (_, params) = frame.vendor_pdu()?;
+                if event_id == EVENT_VOLUME_CHANGED {
+                    let volume = params.get(1).ok_or(Error::InvalidPacket)? & 0x7F;
+                    self.events.push_back(AvrcpEvent::VolumeChanged { volume });
+
+                    let transaction = self.send_command(volume_registration()).await?;
+                    self.notifications.insert(transaction, event_id);
+                }
+            }
+            _ => {
+                self.notifications.remove(&packet.transaction);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers a command from the remote device.
+    async fn answer(&mut self, packet: AvctpPacket) -> Result<(), Error> {
+        let mut response = AvctpPacket {
+            transaction: packet.transaction,
+            response: true,
+            invalid_pid: false,
+            pid: packet.pid,
+            payload: Bytes::new(),
+        };
+
+        if packet.pid != AV_REMOTE_CONTROL_PID {
+            response.invalid_pid = true;
+        } else {
+            // a command which is too short to be an AV/C frame cannot be
+            // answered, and must not fail a command that is waiting for its
+            // response, so it is dropped
+            let mut frame = match AvcFrame::parse(packet.payload) {
+                Ok(frame) => frame,
+                Err(_) => return Ok(()),
+            };
+            frame.code = AvcCode::NotImplemented;
+            response.payload = frame.encode();
+        }
+
+        self.send(response).await
+    }
+}
+
+fn volume_registration() -> AvcFrame {
+    AvcFrame::vendor_dependent(
+        AvcCode::Notify,
+        PDU_REGISTER_NOTIFICATION,
+        &[EVENT_VOLUME_CHANGED, 0x00, 0x00, 0x00, 0x00],
+    )
+}
+
+fn expect(response: &AvcFrame, code: AvcCode) -> Result<(), Error> {
+    match response.code {
+        c if c == code => Ok(()),
+        AvcCode::NotImplemented => Err(Error::NotImplemented),
+        other => Err(Error::Rejected(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    /// Reads the next packet that the controller sent.
+    async fn receive(remote: &mut UnixStream) -> AvctpPacket {
+        let mut buf = vec![0u8; CONTROL_MTU];
+        let len = remote.read(&mut buf).await.unwrap();
+        buf.truncate(len);
+        AvctpPacket::parse(buf.into()).unwrap()
+    }
+
+    async fn send(remote: &mut UnixStream, transaction: u8, response: bool, frame: AvcFrame) {
+        let packet = AvctpPacket {
+            transaction,
+            response,
+            invalid_pid: false,
+            pid: AV_REMOTE_CONTROL_PID,
+            payload: frame.encode(),
+        };
+        remote.write_all(&packet.encode()[..]).await.unwrap();
+    }
+
+    /// Answers the next command with `code`, and returns the command.
+    async fn answer(remote: &mut UnixStream, code: AvcCode) -> AvcFrame {
+        let packet = receive(remote).await;
+        let mut frame = AvcFrame::parse(packet.payload).unwrap();
+        let command = frame.clone();
+
+        frame.code = code;
+        send(remote, packet.transaction, true, frame).await;
+        command
+    }
+
+    #[tokio::test]
+    async fn pass_through() {
+        let (stream, mut remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+        let mut controller = AvrcpController::accept(stream);
+
+        let target = async {
+            let press = answer(&mut remote, AvcCode::Accepted).await;
+            assert_eq!(press, AvcFrame::pass_through(PassThroughOp::Play, true));
+            let release = answer(&mut remote, AvcCode::Accepted).await;
+            assert_eq!(release, AvcFrame::pass_through(PassThroughOp::Play, false));
+
+            answer(&mut remote, AvcCode::NotImplemented).await;
+            answer(&mut remote, AvcCode::Rejected).await;
+        };
+        let commands = async {
+            controller.play().await.unwrap();
+            assert!(matches!(
+                controller.stop().await,
+                Err(Error::NotImplemented)
+            ));
+            assert!(matches!(
+                controller.pause().await,
+                Err(Error::Rejected(AvcCode::Rejected))
+            ));
+        };
+
+        futures::join!(target, commands);
+    }
+
+    #[tokio::test]
+    async fn commands_from_the_target() {
+        let (stream, mut remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+        let mut controller = AvrcpController::accept(stream);
+
+        let target = async {
+            let press = receive(&mut remote).await;
+
+            // a command which is too short to be an AV/C frame
+            remote.write_all(&[0x30, 0x11, 0x0E, 0x00]).await.unwrap();
+            let command = AvcFrame::pass_through(PassThroughOp::VolumeUp, true);
+            send(&mut remote, 4, false, command.clone()).await;
+
+            // only the valid command is answered
+            let response = receive(&mut remote).await;
+            assert!(response.response);
+            assert_eq!(response.transaction, 4);
+            let response = AvcFrame::parse(response.payload).unwrap();
+            assert_eq!(response.code, AvcCode::NotImplemented);
+            assert_eq!(response.operands, command.operands);
+
+            let mut frame = AvcFrame::parse(press.payload).unwrap();
+            frame.code = AvcCode::Accepted;
+            send(&mut remote, press.transaction, true, frame).await;
+            answer(&mut remote, AvcCode::Accepted).await;
+        };
+
+        let (_, result) = futures::join!(target, controller.next());
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn volume_notifications() {
+        let (stream, mut remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+        let mut controller = AvrcpController::accept(stream);
+
+        let target = async {
+            let registration = receive(&mut remote).await;
+            let interim = |volume| {
+                AvcFrame::vendor_dependent(
+                    AvcCode::Interim,
+                    PDU_REGISTER_NOTIFICATION,
+                    &[EVENT_VOLUME_CHANGED, volume],
+                )
+            };
+            send(&mut remote, registration.transaction, true, interim(0x20)).await;
+            registration.transaction
+        };
+        let (transaction, volume) =
+            futures::join!(target, controller.register_volume_notification());
+        assert_eq!(volume.unwrap(), 0x20);
+
+        let changed = AvcFrame::vendor_dependent(
+            AvcCode::Changed,
+            PDU_REGISTER_NOTIFICATION,
+            &[EVENT_VOLUME_CHANGED, 0x7F],
+        );
+        send(&mut remote, transaction, true, changed).await;
+        assert_eq!(
+            controller.recv().await.unwrap(),
+            AvrcpEvent::VolumeChanged { volume: 0x7F }
+        );
+
+        // the notification is registered again with a new transaction label
+        let registration = receive(&mut remote).await;
+        assert_ne!(registration.transaction, transaction);
+        assert_eq!(
+            AvcFrame::parse(registration.payload).unwrap(),
+            volume_registration()
+        );
+
+        drop(remote);
+        assert_eq!(controller.recv().await.unwrap(), AvrcpEvent::SessionClosed);
+    }
+}
@@ -0,0 +1,186 @@
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+
+use super::*;
+
+/// The attribute IDs that are requested from a server. The ranges are kept
+/// sorted, and overlapping or adjacent ranges are merged, so the list that is
+/// sent is as short as possible and is never rejected by the server for
+/// being out of order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AttributeIdList {
+    /// Inclusive ranges, sorted by their start and not overlapping.
+    ranges: Vec<(u16, u16)>,
+}
+
+impl AttributeIdList {
+    /// Creates an empty list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a list containing every attribute ID.
+    pub fn all() -> Self {
+        Self {
+            ranges: vec![(0, u16::MAX)],
+        }
+    }
+
+    /// Adds a single attribute ID to the list.
+    pub fn with(self, id: ServiceAttributeId) -> Self {
+        self.with_range(id, id)
+    }
+
+    /// Adds the attribute IDs from `start` to `end`, inclusive.
+    ///
+    /// # Panics
+    /// Panics if `start` is greater than `end`. Use [`AttributeIdList::try_from`]
+    /// to validate ranges which are not known in advance.
+    pub fn with_range(mut self, start: ServiceAttributeId, end: ServiceAttributeId) -> Self {
+        assert!(
+            start.0 <= end.0,
+            "attribute range starts after it ends: {:?}..={:?}",
+            start,
+            end
+        );
+
+        self.insert(start.0, end.0);
+        self
+    }
+
+    pub fn contains(&self, id: ServiceAttributeId) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= id.0 && id.0 <= end)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The ranges in this list, in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = ServiceAttributeRange> + '_ {
+        self.ranges.iter().map(|&(start, end)| {
+            if start == end {
+                ServiceAttributeRange::Single(ServiceAttributeId(start))
+            } else {
+                ServiceAttributeRange::Range(ServiceAttributeId(start), ServiceAttributeId(end))
+            }
+        })
+    }
+
+    fn insert(&mut self, mut start: u16, mut end: u16) {
+        // merge every range which overlaps or touches the new one
+        self.ranges.retain(|&(s, e)| {
+            let disjoint = (e as u32) + 1 < start as u32 || (end as u32) + 1 < s as u32;
+
+            if !disjoint {
+                start = start.min(s);
+                end = end.max(e);
+            }
+
+            disjoint
+        });
+
+        let index = self.ranges.partition_point(|&(s, _)| s < start);
+        self.ranges.insert(index, (start, end));
+    }
+}
+
+impl TryFrom<Vec<ServiceAttributeRange>> for AttributeIdList {
+    type Error = Error;
+
+    fn try_from(ranges: Vec<ServiceAttributeRange>) -> Result<Self, Self::Error> {
+        let mut list = Self::new();
+
+        for range in ranges {
+            let (start, end) = match range {
+                ServiceAttributeRange::Single(id) => (id, id),
+                ServiceAttributeRange::Range(start, end) => (start, end),
+            };
+
+            if start.0 > end.0 {
+                return Err(Error::InvalidAttributeRange { start, end });
+            }
+
+            list.insert(start.0, end.0);
+        }
+
+        Ok(list)
+    }
+}
+
+impl From<ServiceAttributeId> for AttributeIdList {
+    fn from(id: ServiceAttributeId) -> Self {
+        Self::new().with(id)
+    }
+}
+
+impl FromIterator<ServiceAttributeId> for AttributeIdList {
+    fn from_iter<T: IntoIterator<Item = ServiceAttributeId>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::new(), Self::with)
+    }
+}
+
+impl ToBuf for AttributeIdList {
+    fn to_buf<B: BufMut>(&self, buf: &mut B) {
+        DataElement::Sequence(
+            self.ranges
+                .iter()
+                .map(|&(start, end)| {
+                    if start == end {
+                        DataElement::Uint16(start)
+                    } else {
+                        DataElement::Uint32(((start as u32) << 16) | end as u32)
+                    }
+                })
+                .collect(),
+        )
+        .to_buf(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_ranges() {
+        let list = AttributeIdList::new()
+            .with(ServiceAttributeId::PROTOCOL_DESCRIPTOR_LIST)
+            .with_range(ServiceAttributeId(0x0100), ServiceAttributeId(0x0102))
+            .with(ServiceAttributeId::SERVICE_CLASS_ID_LIST)
+            .with(ServiceAttributeId::SERVICE_RECORD_STATE)
+            .with_range(ServiceAttributeId(0x0101), ServiceAttributeId(0x0110));
+
+        assert_eq!(
+            list.ranges,
+            vec![(0x0001, 0x0002), (0x0004, 0x0004), (0x0100, 0x0110)]
+        );
+        assert!(list.contains(ServiceAttributeId(0x0105)));
+        assert!(!list.contains(ServiceAttributeId::SERVICE_ID));
+
+        let mut buf = BytesMut::new();
+        list.to_buf(&mut buf);
+        assert_eq!(
+            &buf[..],
+            &[
+                0x35, 0x0D, 0x0A, 0x00, 0x01, 0x00, 0x02, 0x09, 0x00, 0x04, 0x0A, 0x01, 0x00, 0x01,
+                0x10
+            ]
+        );
+
+        assert_eq!(
+            AttributeIdList::new()
+                .with_range(ServiceAttributeId(0), ServiceAttributeId(0x8000))
+                .with_range(ServiceAttributeId(0x8001), ServiceAttributeId(u16::MAX)),
+            AttributeIdList::all()
+        );
+
+        assert!(AttributeIdList::try_from(vec![ServiceAttributeRange::Range(
+            ServiceAttributeId(2),
+            ServiceAttributeId(1)
+        )])
+        .is_err());
+    }
+}
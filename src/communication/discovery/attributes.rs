@@ -0,0 +1,188 @@
+use bytes::{Buf, Bytes, BytesMut};
+use futures::stream::{self, Stream};
+
+use super::*;
+
+/// The state of a Service Attribute transaction whose response is decoded
+/// while it is still being received.
+struct AttributeStream<'a> {
+    client: &'a mut ServiceDiscoveryClient,
+    service_handle: u32,
+    maximum_attribute_byte_count: u16,
+    attribute_id_list: AttributeIdList,
+    txn: u16,
+    deadline: Option<Instant>,
+    /// The attribute list bytes which have been received but not decoded.
+    buf: BytesMut,
+    /// The number of bytes of the attribute list that have not been decoded,
+    /// or `None` if the header of the attribute list has not been received.
+    remaining: Option<usize>,
+    continuation_state: Vec<u8>,
+    /// Set once the server has sent its last response.
+    received_all: bool,
+}
+
+impl AttributeStream<'_> {
+    async fn next_attribute(&mut self) -> Result<Option<(ServiceAttributeId, DataElement)>, Error> {
+        loop {
+            match self.remaining {
+                Some(0) => return Ok(None),
+                Some(remaining) => {
+                    if let Some(attribute) = self.decode_attribute(remaining)? {
+                        return Ok(Some(attribute));
+                    }
+                }
+                None => {
+                    if let Some(len) = DataElement::peek_len(&self.buf[..]) {
+                        // the attribute list is a data element sequence
+                        let header_len = match (self.buf[0] >> 3, self.buf[0] & 0b111) {
+                            (6, 5) => 2,
+                            (6, 6) => 3,
+                            (6, 7) => 5,
+                            _ => return Err(Error::InvalidResponse),
+                        };
+
+                        self.buf.advance(header_len);
+                        self.remaining = Some(len - header_len);
+                        continue;
+                    }
+                }
+            }
+
+            if self.received_all {
+                return Err(Error::InvalidResponse);
+            }
+
+            self.receive_more().await?;
+        }
+    }
+
+    /// Decodes the next attribute ID and value, if they have been received
+    /// completely.
+    fn decode_attribute(
+        &mut self,
+        remaining: usize,
+    ) -> Result<Option<(ServiceAttributeId, DataElement)>, Error> {
+        let id_len = match DataElement::peek_len(&self.buf[..]) {
+            Some(len) if len <= self.buf.len() => len,
+            _ => return Ok(None),
+        };
+
+        let value_len = match DataElement::peek_len(&self.buf[id_len..]) {
+            Some(len) if id_len + len <= self.buf.len() => len,
+            _ => return Ok(None),
+        };
+
+        if id_len + value_len > remaining {
+            return Err(Error::InvalidResponse);
+        }
+
+        let mut id = self.buf.split_to(id_len).freeze();
+        let mut value = self.buf.split_to(value_len).freeze();
+        self.remaining = Some(remaining - id_len - value_len);
+
+        let id = match DataElement::parse(&mut id)? {
+            DataElement::Uint16(id) => ServiceAttributeId(id),
+            _ => return Err(Error::InvalidResponse),
+        };
+
+        Ok(Some((id, DataElement::parse(&mut value)?)))
+    }
+
+    async fn receive_more(&mut self) -> Result<(), Error> {
+        if self.txn as usize > self.client.config.max_continuation_rounds {
+            return Err(Error::TooManyContinuations);
+        }
+
+        let req = ServiceAttributeRequest {
+            service_handle: self.service_handle,
+            maximum_attribute_byte_count: self.maximum_attribute_byte_count,
+            attribute_id_list: self.attribute_id_list.clone(),
+            continuation_state: std::mem::take(&mut self.continuation_state),
+        };
+
+        let req_pdu = Pdu::with_parameter(PduId::ServiceAttributeRequest, self.txn, req);
+        let mut res_pdu = self.client.transact(req_pdu, self.deadline).await?;
+        self.txn = self.txn.wrapping_add(1);
+
+        let param: &mut Bytes = &mut res_pdu.parameter;
+
+        match res_pdu.id {
+            PduId::ErrorResponse => Err(Error::Remote(ErrorCode::from(param))),
+            PduId::ServiceAttributeResponse => {
+                if param.remaining() < 2 {
+                    return Err(Error::InvalidResponse);
+                }
+
+                let byte_count = param.get_u16() as usize;
+
+                if param.remaining() < byte_count + 1 {
+                    return Err(Error::InvalidResponse);
+                }
+
+                self.buf.extend_from_slice(&param[..byte_count]);
+                param.advance(byte_count);
+
+                let continuation_state_size = param.get_u8() as usize;
+
+                if param.remaining() < continuation_state_size {
+                    return Err(Error::InvalidResponse);
+                }
+
+                self.continuation_state = param.get_vec_u8(continuation_state_size);
+                self.received_all = self.continuation_state.is_empty();
+
+                Ok(())
+            }
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+}
+
+impl ServiceDiscoveryClient {
+    /// Requests the attributes of a service, and returns them one by one as
+    /// they are received and decoded, instead of collecting all of them first.
+    /// If the server splits its response using continuation states, the next
+    /// part is only requested once the attributes that have been received so
+    /// far have been consumed.
+    ///
+    /// `maximum_attribute_byte_count` is capped by
+    /// [`ServiceDiscoveryConfig::max_attribute_byte_count`], and the limits on
+    /// the duration and number of rounds in the client's configuration apply
+    /// to the whole stream.
+    ///
+    /// The stream ends after the first error.
+    pub fn service_attribute_iter(
+        &mut self,
+        service_handle: u32,
+        maximum_attribute_byte_count: u16,
+        attribute_id_list: AttributeIdList,
+    ) -> impl Stream<Item = Result<(ServiceAttributeId, DataElement), Error>> + '_ {
+        let maximum_attribute_byte_count =
+            maximum_attribute_byte_count.min(self.config.max_attribute_byte_count);
+        let deadline = self.deadline();
+
+        let state = AttributeStream {
+            client: self,
+            service_handle,
+            maximum_attribute_byte_count,
+            attribute_id_list,
+            txn: 0,
+            deadline,
+            buf: BytesMut::new(),
+            remaining: None,
+            continuation_state: vec![],
+            received_all: false,
+        };
+
+        stream::unfold(Some(state), |state| async move {
+            let mut state = state?;
+
+            match state.next_attribute().await {
+                Ok(Some(attribute)) => Some((Ok(attribute), Some(state))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+}
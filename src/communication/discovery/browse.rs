@@ -0,0 +1,219 @@
+use std::collections::{HashSet, VecDeque};
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use super::*;
+
+/// The service class of the records which describe browse groups.
+pub const SDP_BROWSE_GROUP_DESCRIPTOR: Uuid16 = Uuid16(0x1001);
+
+struct ServiceSearchAttributeRequest {
+    service_search_pattern: Vec<Uuid>,
+    maximum_attribute_byte_count: u16,
+    attribute_id_list: AttributeIdList,
+    continuation_state: Vec<u8>,
+}
+
+impl ToBuf for ServiceSearchAttributeRequest {
+    fn to_buf<B: BufMut>(&self, buf: &mut B) {
+        uuid_list(&self.service_search_pattern).to_buf(buf);
+        buf.put_u16(self.maximum_attribute_byte_count);
+        self.attribute_id_list.to_buf(buf);
+        buf.put_u8(self.continuation_state.len() as u8);
+        buf.put(self.continuation_state.as_ref());
+    }
+}
+
+/// Parses the attribute lists of a Service Search Attribute response, once
+/// all of its parts have been received, into one record per list. Every list
+/// must include the service record handle. Returns
+/// [`Error::InvalidResponse`] if the lists are malformed, instead of
+/// panicking, so this can be used on untrusted input.
+pub fn parse_attribute_lists(data: &[u8]) -> Result<Vec<ServiceRecord>, Error> {
+    let mut buf = data;
+
+    if DataElement::peek_len(buf) != Some(buf.len()) {
+        return Err(Error::InvalidResponse);
+    }
+
+    let lists = match DataElement::parse(&mut buf)? {
+        DataElement::Sequence(lists) => lists,
+        _ => return Err(Error::InvalidResponse),
+    };
+
+    lists
+        .into_iter()
+        .map(|list| {
+            let list = match list {
+                DataElement::Sequence(list) => list,
+                _ => return Err(Error::InvalidResponse),
+            };
+
+            let mut attributes = HashMap::new();
+
+            for pair in list.chunks_exact(2) {
+                match &pair[0] {
+                    DataElement::Uint16(id) => {
+                        attributes.insert(ServiceAttributeId(*id), pair[1].clone());
+                    }
+                    _ => return Err(Error::InvalidResponse),
+                }
+            }
+
+            // the record handle is only known if it was requested
+            let handle = match attributes.get(&ServiceAttributeId::SERVICE_RECORD_HANDLE) {
+                Some(DataElement::Uint32(handle)) => *handle,
+                _ => return Err(Error::InvalidResponse),
+            };
+
+            Ok(ServiceRecord { handle, attributes })
+        })
+        .collect()
+}
+
+impl ServiceDiscoveryClient {
+    /// Finds the services which match `service_search_pattern` and requests
+    /// their attributes in the same transaction. The attribute list must
+    /// include [`ServiceAttributeId::SERVICE_RECORD_HANDLE`].
+    pub async fn service_search_attribute(
+        &mut self,
+        service_search_pattern: Vec<Uuid>,
+        maximum_attribute_byte_count: u16,
+        attribute_id_list: AttributeIdList,
+    ) -> Result<Vec<ServiceRecord>, Error> {
+        let maximum_attribute_byte_count =
+            maximum_attribute_byte_count.min(self.config.max_attribute_byte_count);
+        let deadline = self.deadline();
+
+        let mut buf = BytesMut::new();
+        let mut continuation_state = vec![];
+        let mut txn: u16 = 0;
+
+        loop {
+            if txn as usize > self.config.max_continuation_rounds {
+                return Err(Error::TooManyContinuations);
+            }
+
+            let req = ServiceSearchAttributeRequest {
+                service_search_pattern: service_search_pattern.clone(),
+                maximum_attribute_byte_count,
+                attribute_id_list: attribute_id_list.clone(),
+                continuation_state,
+            };
+
+            let req_pdu = Pdu::with_parameter(PduId::ServiceSearchAttributeRequest, txn, req);
+            let mut res_pdu = self.transact(req_pdu, deadline).await?;
+            txn = txn.wrapping_add(1);
+
+            let param: &mut Bytes = &mut res_pdu.parameter;
+
+            match res_pdu.id {
+                PduId::ErrorResponse => return Err(Error::Remote(ErrorCode::from(param))),
+                PduId::ServiceSearchAttributeResponse => {
+                    if param.remaining() < 2 {
+                        return Err(Error::InvalidResponse);
+                    }
+
+                    let byte_count = param.get_u16() as usize;
+
+                    if param.remaining() < byte_count + 1 {
+                        return Err(Error::InvalidResponse);
+                    }
+
+                    buf.extend_from_slice(&param[..byte_count]);
+                    param.advance(byte_count);
+
+                    let continuation_state_size = param.get_u8() as usize;
+
+                    if param.remaining() < continuation_state_size {
+                        return Err(Error::InvalidResponse);
+                    }
+
+                    continuation_state = param.get_vec_u8(continuation_state_size);
+
+                    if continuation_state.is_empty() {
+                        break;
+                    }
+                }
+                _ => return Err(Error::InvalidResponse),
+            }
+        }
+
+        parse_attribute_lists(&buf)
+    }
+
+    /// Returns every service record that can be reached from the browse group
+    /// `root`, which is usually [`SDP_BROWSE_ROOT`], with all of its
+    /// attributes. Browse group descriptors that are found are followed into
+    /// the groups they describe, so this is equivalent to `sdptool browse`.
+    pub async fn browse(&mut self, root: Uuid) -> Result<Vec<ServiceRecord>, Error> {
+        let mut records = vec![];
+        let mut handles = HashSet::new();
+        let mut visited = vec![];
+        let mut groups = VecDeque::new();
+        groups.push_back(root);
+
+        while let Some(group) = groups.pop_front() {
+            if visited.contains(&group) {
+                continue;
+            }
+
+            visited.push(group);
+
+            let found = self
+                .service_search_attribute(vec![group], u16::MAX, AttributeIdList::all())
+                .await?;
+
+            for record in found {
+                // records can be members of several groups
+                if !handles.insert(record.handle) {
+                    continue;
+                }
+
+                if let Some(group) = record.browse_group_id() {
+                    groups.push_back(group);
+                }
+
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browse_group_descriptor() {
+        let lists = DataElement::Sequence(vec![DataElement::Sequence(vec![
+            DataElement::Uint16(0x0000),
+            DataElement::Uint32(0x0001_0002),
+            DataElement::Uint16(0x0001),
+            DataElement::Sequence(vec![DataElement::Uuid16(SDP_BROWSE_GROUP_DESCRIPTOR)]),
+            DataElement::Uint16(0x0200),
+            DataElement::Uuid16(Uuid16(0x1234)),
+        ])]);
+
+        let mut buf = BytesMut::new();
+        lists.to_buf(&mut buf);
+
+        let records = parse_attribute_lists(&buf).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].handle, 0x0001_0002);
+        assert_eq!(
+            records[0].browse_group_id(),
+            Some(Uuid::Uuid16(Uuid16(0x1234)))
+        );
+
+        buf.truncate(buf.len() - 1);
+        assert!(parse_attribute_lists(&buf).is_err());
+
+        // the lengths of the sequences are consistent, but the nested
+        // element has an invalid size descriptor
+        let malformed = [0x35, 0x04, 0x35, 0x02, 0x18, 0x00];
+        assert!(parse_attribute_lists(&malformed).is_err());
+    }
+}
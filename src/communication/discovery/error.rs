@@ -1,5 +1,6 @@
 use bytes::Buf;
-use num_traits::FromPrimitive;
+
+use super::ServiceAttributeId;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -11,22 +12,68 @@ pub enum Error {
 
     #[error("the remote device returned invalid data")]
     InvalidResponse,
+
+    #[error("the remote device did not respond in time")]
+    TimedOut,
+
+    #[error("the remote device returned too many continuation states")]
+    TooManyContinuations,
+
+    #[error("the attribute range {start:?}..={end:?} starts after it ends")]
+    InvalidAttributeRange {
+        start: ServiceAttributeId,
+        end: ServiceAttributeId,
+    },
 }
 
-#[repr(u16)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+/// An error code that an SDP server returned. Codes which this library does
+/// not know about are kept in [`ErrorCode::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorCode {
-    UnsupportedSdpVersion = 0x0001,
+    UnsupportedSdpVersion,
     InvalidServiceRecordHandle,
     InvalidRequestSyntax,
     InvalidPduSize,
     InvalidContinuationState,
     InsufficientResources,
+    Other(u16),
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            0x0001 => ErrorCode::UnsupportedSdpVersion,
+            0x0002 => ErrorCode::InvalidServiceRecordHandle,
+            0x0003 => ErrorCode::InvalidRequestSyntax,
+            0x0004 => ErrorCode::InvalidPduSize,
+            0x0005 => ErrorCode::InvalidContinuationState,
+            0x0006 => ErrorCode::InsufficientResources,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::UnsupportedSdpVersion => 0x0001,
+            ErrorCode::InvalidServiceRecordHandle => 0x0002,
+            ErrorCode::InvalidRequestSyntax => 0x0003,
+            ErrorCode::InvalidPduSize => 0x0004,
+            ErrorCode::InvalidContinuationState => 0x0005,
+            ErrorCode::InsufficientResources => 0x0006,
+            ErrorCode::Other(code) => code,
+        }
+    }
 }
 
 impl<B: Buf> From<&mut B> for ErrorCode {
     fn from(buf: &mut B) -> Self {
-        let code = buf.get_u16();
-        FromPrimitive::from_u16(code).unwrap()
+        // an error response without a code is treated like an unknown code
+        if buf.remaining() < 2 {
+            return ErrorCode::Other(0);
+        }
+
+        buf.get_u16().into()
     }
 }
@@ -1,21 +1,34 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, time::Duration};
 
 use super::{stream::BluetoothStream, Uuid};
 use crate::address::Protocol;
 use crate::util::BufExt;
 use crate::{communication::Uuid16, Address, AddressType};
-use error::{Error, ErrorCode};
-use serialization::{DataElement, Pdu, PduId, ToBuf};
+use serialization::{DataElement, ToBuf};
 
 use bytes::{Buf, BufMut, BytesMut};
+use futures::TryStreamExt;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
 
+mod attribute_id;
+mod attributes;
+mod browse;
 mod error;
+mod record;
 mod serialization;
 
+pub use attribute_id::AttributeIdList;
+pub use browse::{parse_attribute_lists, SDP_BROWSE_GROUP_DESCRIPTOR};
+pub use error::{Error, ErrorCode};
+pub use record::{LanguageBase, ServiceRecord, SDP_ENCODING_UTF8};
+pub use serialization::{parse_sdp_pdu, Pdu, PduId};
+
 pub const SDP_PSM: u16 = 0x0001;
 pub const SDP_BROWSE_ROOT: Uuid16 = Uuid16(0x1002);
 
+/// A single attribute ID or an inclusive range of them. Requests take an
+/// [`AttributeIdList`], which can be built from these.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ServiceAttributeRange {
     Single(ServiceAttributeId),
@@ -32,19 +45,22 @@ struct ServiceSearchRequest {
     continuation_state: Vec<u8>,
 }
 
+fn uuid_list(uuids: &[Uuid]) -> DataElement {
+    DataElement::Sequence(
+        uuids
+            .iter()
+            .map(|u| match *u {
+                Uuid::Uuid16(u) => DataElement::Uuid16(u),
+                Uuid::Uuid32(u) => DataElement::Uuid32(u),
+                Uuid::Uuid128(u) => DataElement::Uuid128(u),
+            })
+            .collect(),
+    )
+}
+
 impl ToBuf for ServiceSearchRequest {
     fn to_buf<B: BufMut>(&self, buf: &mut B) {
-        let service_search_pat = DataElement::Sequence(
-            self.service_search_pattern
-                .iter()
-                .map(|u| match *u {
-                    Uuid::Uuid16(u) => DataElement::Uuid16(u),
-                    Uuid::Uuid32(u) => DataElement::Uuid32(u),
-                    Uuid::Uuid128(u) => DataElement::Uuid128(u),
-                })
-                .collect(),
-        );
-        service_search_pat.to_buf(buf);
+        uuid_list(&self.service_search_pattern).to_buf(buf);
         buf.put_u16(self.maximum_service_record_count);
         buf.put_u8(self.continuation_state.len() as u8);
         buf.put(self.continuation_state.as_ref());
@@ -79,7 +95,7 @@ impl<B: Buf> From<&mut B> for ServiceSearchResponse {
 struct ServiceAttributeRequest {
     service_handle: u32,
     maximum_attribute_byte_count: u16,
-    attribute_id_list: Vec<ServiceAttributeRange>,
+    attribute_id_list: AttributeIdList,
     continuation_state: Vec<u8>,
 }
 
@@ -87,20 +103,7 @@ impl ToBuf for ServiceAttributeRequest {
     fn to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_u32(self.service_handle);
         buf.put_u16(self.maximum_attribute_byte_count);
-
-        let attribute_id_list = DataElement::Sequence(
-            self.attribute_id_list
-                .iter()
-                .map(|range| match range {
-                    &ServiceAttributeRange::Single(item) => DataElement::Uint16(item.0),
-                    &ServiceAttributeRange::Range(start, end) => {
-                        DataElement::Uint32(((start.0 as u32) << 16) | end.0 as u32)
-                    }
-                })
-                .collect(),
-        );
-
-        attribute_id_list.to_buf(buf);
+        self.attribute_id_list.to_buf(buf);
 
         buf.put_u8(self.continuation_state.len() as u8);
         buf.put(self.continuation_state.as_ref());
@@ -131,6 +134,9 @@ impl ServiceAttributeId {
     pub const CLIENT_EXECUTABLE_URL: Self = Self(0x000B);
     pub const ICON_URL: Self = Self(0x000C);
     pub const ADDITIONAL_PROTOCOL_DESCRIPTOR_LISTS: Self = Self(0x000D);
+
+    /// The ID of the group described by a browse group descriptor.
+    pub const GROUP_ID: Self = Self(0x0200);
 }
 
 #[derive(Debug, Clone)]
@@ -145,8 +151,6 @@ impl<B: Buf> From<&mut B> for ServiceAttributeResponse {
         let attribute_list = DataElement::from(&mut *buf);
 
         if let DataElement::Sequence(attribute_list) = attribute_list {
-            // println!("recv attr list: {:#?}", attribute_list);
-
             let mut attributes = HashMap::new();
 
             for pair in attribute_list.chunks_exact(2) {
@@ -172,29 +176,110 @@ impl<B: Buf> From<&mut B> for ServiceAttributeResponse {
     }
 }
 
+/// Limits on how a [`ServiceDiscoveryClient`] paces its requests, and on how
+/// much work a remote device can make it do.
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceDiscoveryConfig {
+    /// The largest response PDU that is accepted, including its header.
+    /// Responses which do not fit are rejected as invalid.
+    pub max_pdu_size: usize,
+
+    /// The largest number of attribute bytes that the server may return in a
+    /// single Service Attribute response. Smaller values make the server split
+    /// its response into more rounds.
+    pub max_attribute_byte_count: u16,
+
+    /// How long a whole transaction, including all of its continuation
+    /// rounds, may take. `None` waits forever.
+    pub timeout: Option<Duration>,
+
+    /// The number of continuation states that are followed before a
+    /// transaction is abandoned. This protects against servers which keep
+    /// returning continuation states.
+    pub max_continuation_rounds: usize,
+}
+
+impl Default for ServiceDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            max_pdu_size: u16::MAX as usize + 5,
+            max_attribute_byte_count: u16::MAX,
+            timeout: Some(Duration::from_secs(30)),
+            max_continuation_rounds: 256,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct ServiceDiscoveryClient(BluetoothStream);
+pub struct ServiceDiscoveryClient {
+    stream: BluetoothStream,
+    config: ServiceDiscoveryConfig,
+}
 
 impl ServiceDiscoveryClient {
     async fn send(&mut self, req: Pdu) -> Result<(), Error> {
         let mut buf = BytesMut::new();
         req.to_buf(&mut buf);
-        // println!("send buf: {:02x?}", &buf[..]);
-        self.0.write_all(buf.as_ref()).await?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(pdu = %crate::util::Hex(&buf), "sdp send");
+        self.stream.write_all(buf.as_ref()).await?;
         Ok(())
     }
 
     async fn recv(&mut self) -> Result<Pdu, Error> {
-        let mut buf = BytesMut::with_capacity(65536);
-        self.0.read_buf(&mut buf).await?;
-        // println!("recv buf: {:02x?}", &buf[..]);
-        Ok(Pdu::from(&mut buf))
+        let mut buf = BytesMut::with_capacity(self.config.max_pdu_size);
+        self.stream.read_buf(&mut buf).await?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(pdu = %crate::util::Hex(&buf), "sdp recv");
+
+        parse_sdp_pdu(&buf)
+    }
+
+    /// Sends a request and waits for its response, giving up at `deadline`.
+    async fn transact(&mut self, req: Pdu, deadline: Option<Instant>) -> Result<Pdu, Error> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("sdp_transaction", id = ?req.id, txn = req.txn);
+
+        let exchange = async {
+            self.send(req).await?;
+            self.recv().await
+        };
+
+        #[cfg(feature = "tracing")]
+        let exchange = tracing::Instrument::instrument(exchange, span);
+
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, exchange)
+                .await
+                .unwrap_or(Err(Error::TimedOut)),
+            None => exchange.await,
+        }
+    }
+
+    /// The time at which a transaction that starts now must be finished.
+    fn deadline(&self) -> Option<Instant> {
+        self.config.timeout.map(|timeout| Instant::now() + timeout)
     }
 
     pub async fn connect(address: Address) -> Result<Self, Error> {
+        Self::connect_with_config(address, ServiceDiscoveryConfig::default()).await
+    }
+
+    pub async fn connect_with_config(
+        address: Address,
+        config: ServiceDiscoveryConfig,
+    ) -> Result<Self, Error> {
         let stream =
             BluetoothStream::connect(Protocol::L2CAP, address, AddressType::BREDR, SDP_PSM).await?;
-        Ok(Self(stream))
+        Ok(Self { stream, config })
+    }
+
+    pub fn config(&self) -> &ServiceDiscoveryConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: ServiceDiscoveryConfig) {
+        self.config = config;
     }
 
     pub async fn service_search(
@@ -204,8 +289,13 @@ impl ServiceDiscoveryClient {
     ) -> Result<ServiceSearchResponse, Error> {
         let mut res: Option<ServiceSearchResponse> = None;
         let mut txn = 0;
+        let deadline = self.deadline();
 
         Ok(loop {
+            if txn as usize > self.config.max_continuation_rounds {
+                return Err(Error::TooManyContinuations);
+            }
+
             let req = ServiceSearchRequest {
                 service_search_pattern: service_search_pattern.clone(),
                 maximum_service_record_count,
@@ -215,10 +305,9 @@ impl ServiceDiscoveryClient {
                     .unwrap_or(vec![]),
             };
             let req_pdu = Pdu::with_parameter(PduId::ServiceSearchRequest, txn, req);
-            self.send(req_pdu).await?;
-            txn += 1;
+            let mut res_pdu = self.transact(req_pdu, deadline).await?;
+            txn = txn.wrapping_add(1);
 
-            let mut res_pdu = self.recv().await?;
             match res_pdu.id {
                 PduId::ErrorResponse => {
                     return Err(Error::Remote(ErrorCode::from(&mut res_pdu.parameter)))
@@ -243,51 +332,26 @@ impl ServiceDiscoveryClient {
         })
     }
 
+    /// Requests the attributes of a service. This collects the stream
+    /// returned by [`ServiceDiscoveryClient::service_attribute_iter`].
     pub async fn service_attribute(
         &mut self,
         service_handle: u32,
         maximum_attribute_byte_count: u16,
-        attribute_id_list: Vec<ServiceAttributeRange>,
+        attribute_id_list: AttributeIdList,
     ) -> Result<ServiceAttributeResponse, Error> {
-        let mut res: Option<ServiceAttributeResponse> = None;
-        let mut txn = 0;
-
-        Ok(loop {
-            let req = ServiceAttributeRequest {
-                attribute_id_list: attribute_id_list.clone(),
-                maximum_attribute_byte_count,
+        let attributes = self
+            .service_attribute_iter(
                 service_handle,
-                continuation_state: res
-                    .as_ref()
-                    .map(|r| r.continuation_state.clone())
-                    .unwrap_or(vec![]),
-            };
-
-            let req_pdu = Pdu::with_parameter(PduId::ServiceAttributeRequest, txn, req);
-            self.send(req_pdu).await?;
-            txn += 1;
-
-            let mut res_pdu = self.recv().await?;
-            match res_pdu.id {
-                PduId::ErrorResponse => {
-                    return Err(Error::Remote(ErrorCode::from(&mut res_pdu.parameter)))
-                }
-                PduId::ServiceAttributeResponse => {
-                    let new_res = ServiceAttributeResponse::from(&mut res_pdu.parameter);
-
-                    if let Some(res) = &mut res {
-                        res.attributes.extend(new_res.attributes);
-                        res.continuation_state = new_res.continuation_state;
-                    } else {
-                        res = Some(new_res)
-                    }
-
-                    if res.as_ref().unwrap().continuation_state.len() == 0 {
-                        break res.unwrap();
-                    }
-                }
-                _ => return Err(Error::InvalidResponse),
-            }
+                maximum_attribute_byte_count,
+                attribute_id_list,
+            )
+            .try_collect()
+            .await?;
+
+        Ok(ServiceAttributeResponse {
+            attributes,
+            continuation_state: vec![],
         })
     }
 }
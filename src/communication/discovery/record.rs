@@ -0,0 +1,202 @@
+use super::*;
+
+/// The attribute ID offsets, relative to a language base, of the attributes
+/// which hold human-readable strings.
+const SERVICE_NAME_OFFSET: u16 = 0x0000;
+const SERVICE_DESCRIPTION_OFFSET: u16 = 0x0001;
+const PROVIDER_NAME_OFFSET: u16 = 0x0002;
+
+/// The IANA MIBenum of UTF-8, which is the encoding used by almost all
+/// devices.
+pub const SDP_ENCODING_UTF8: u16 = 106;
+
+/// An entry of the Language Base Attribute ID List of a service record. The
+/// human-readable strings of the record in this language are stored at fixed
+/// offsets from `base`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageBase {
+    /// The ISO 639:1988 language code, as two ASCII characters.
+    pub language: u16,
+    /// The IANA MIBenum of the character encoding of the strings.
+    pub encoding: u16,
+    pub base: ServiceAttributeId,
+}
+
+impl LanguageBase {
+    /// The language base that is used if a record does not have a Language
+    /// Base Attribute ID List.
+    pub const PRIMARY: Self = Self {
+        language: u16::from_be_bytes(*b"en"),
+        encoding: SDP_ENCODING_UTF8,
+        base: ServiceAttributeId(0x0100),
+    };
+
+    /// The language code as a string, such as `"en"`.
+    pub fn language_tag(&self) -> String {
+        String::from_utf8_lossy(&self.language.to_be_bytes()).into_owned()
+    }
+}
+
+/// A service record and the attributes which were requested for it.
+#[derive(Debug, Clone)]
+pub struct ServiceRecord {
+    pub handle: u32,
+    pub attributes: HashMap<ServiceAttributeId, DataElement>,
+}
+
+impl ServiceRecord {
+    /// If this record is a browse group descriptor, returns the ID of the
+    /// group that it describes.
+    pub(super) fn browse_group_id(&self) -> Option<Uuid> {
+        let is_descriptor = match self
+            .attributes
+            .get(&ServiceAttributeId::SERVICE_CLASS_ID_LIST)
+        {
+            Some(DataElement::Sequence(classes)) => classes.iter().any(|class| {
+                matches!(class, DataElement::Uuid16(uuid) if *uuid == SDP_BROWSE_GROUP_DESCRIPTOR)
+            }),
+            _ => false,
+        };
+
+        if !is_descriptor {
+            return None;
+        }
+
+        match self.attributes.get(&ServiceAttributeId::GROUP_ID)? {
+            DataElement::Uuid16(uuid) => Some((*uuid).into()),
+            DataElement::Uuid32(uuid) => Some((*uuid).into()),
+            DataElement::Uuid128(uuid) => Some((*uuid).into()),
+            _ => None,
+        }
+    }
+
+    /// Parses the Language Base Attribute ID List of this record. The first
+    /// entry is the primary language of the record. If the record does not
+    /// have this attribute, [`LanguageBase::PRIMARY`] is returned.
+    pub fn language_bases(&self) -> Vec<LanguageBase> {
+        let list = match self
+            .attributes
+            .get(&ServiceAttributeId::LANGUAGE_BASE_ATTRIBUTE_ID_LIST)
+        {
+            Some(DataElement::Sequence(list)) => list,
+            _ => return vec![LanguageBase::PRIMARY],
+        };
+
+        list.chunks_exact(3)
+            .filter_map(|entry| match entry {
+                [DataElement::Uint16(language), DataElement::Uint16(encoding), DataElement::Uint16(base)] => {
+                    Some(LanguageBase {
+                        language: *language,
+                        encoding: *encoding,
+                        base: ServiceAttributeId(*base),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn language_base(&self, language_tag: &str) -> Option<LanguageBase> {
+        self.language_bases()
+            .into_iter()
+            .find(|base| base.language_tag().eq_ignore_ascii_case(language_tag))
+    }
+
+    fn string_at(&self, base: LanguageBase, offset: u16) -> Option<String> {
+        let id = ServiceAttributeId(base.base.0.checked_add(offset)?);
+
+        match self.attributes.get(&id)? {
+            DataElement::String(value) => Some(value.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn primary_string(&self, offset: u16) -> Option<String> {
+        let base = *self.language_bases().first()?;
+        self.string_at(base, offset)
+    }
+
+    /// The name of the service in its primary language.
+    pub fn service_name(&self) -> Option<String> {
+        self.primary_string(SERVICE_NAME_OFFSET)
+    }
+
+    /// The description of the service in its primary language.
+    pub fn service_description(&self) -> Option<String> {
+        self.primary_string(SERVICE_DESCRIPTION_OFFSET)
+    }
+
+    /// The name of the provider of the service in its primary language.
+    pub fn provider_name(&self) -> Option<String> {
+        self.primary_string(PROVIDER_NAME_OFFSET)
+    }
+
+    /// The name of the service in the language with the given ISO 639 code,
+    /// such as `"en"`.
+    pub fn service_name_for(&self, language_tag: &str) -> Option<String> {
+        self.string_at(self.language_base(language_tag)?, SERVICE_NAME_OFFSET)
+    }
+
+    /// The description of the service in the language with the given ISO 639
+    /// code.
+    pub fn service_description_for(&self, language_tag: &str) -> Option<String> {
+        self.string_at(
+            self.language_base(language_tag)?,
+            SERVICE_DESCRIPTION_OFFSET,
+        )
+    }
+
+    /// The name of the provider of the service in the language with the given
+    /// ISO 639 code.
+    pub fn provider_name_for(&self, language_tag: &str) -> Option<String> {
+        self.string_at(self.language_base(language_tag)?, PROVIDER_NAME_OFFSET)
+    }
+
+    /// The descriptions of the service in every language that the record
+    /// provides one for.
+    pub fn descriptions(&self) -> Vec<(LanguageBase, String)> {
+        self.language_bases()
+            .into_iter()
+            .filter_map(|base| Some((base, self.string_at(base, SERVICE_DESCRIPTION_OFFSET)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localized_strings() {
+        let string = |s: &str| DataElement::String(s.into());
+        let mut attributes = HashMap::new();
+
+        attributes.insert(
+            ServiceAttributeId::LANGUAGE_BASE_ATTRIBUTE_ID_LIST,
+            DataElement::Sequence(vec![
+                DataElement::Uint16(u16::from_be_bytes(*b"en")),
+                DataElement::Uint16(SDP_ENCODING_UTF8),
+                DataElement::Uint16(0x0100),
+                DataElement::Uint16(u16::from_be_bytes(*b"fr")),
+                DataElement::Uint16(SDP_ENCODING_UTF8),
+                DataElement::Uint16(0x0110),
+            ]),
+        );
+        attributes.insert(ServiceAttributeId(0x0100), string("Serial Port"));
+        attributes.insert(ServiceAttributeId(0x0101), string("A serial port"));
+        attributes.insert(ServiceAttributeId(0x0110), string("Port série"));
+
+        let record = ServiceRecord {
+            handle: 0x0001_0000,
+            attributes,
+        };
+
+        assert_eq!(record.language_bases().len(), 2);
+        assert_eq!(record.language_bases()[1].language_tag(), "fr");
+        assert_eq!(record.service_name().as_deref(), Some("Serial Port"));
+        assert_eq!(record.service_name_for("FR").as_deref(), Some("Port série"));
+        assert_eq!(record.service_name_for("de"), None);
+        assert_eq!(record.service_description_for("fr"), None);
+        assert_eq!(record.descriptions().len(), 1);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::communication::{Uuid128, Uuid16, Uuid32};
 use crate::util::BufExt;
 
+use super::Error;
+
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use num_traits::FromPrimitive;
 use std::ffi::OsString;
@@ -10,11 +12,13 @@ pub trait ToBuf {
     fn to_buf<B: BufMut>(&self, buf: &mut B);
 }
 
-#[derive(Debug)]
-pub(super) struct Pdu {
-    pub(super) id: PduId,
-    pub(super) txn: u16,
-    pub(super) parameter: Bytes,
+/// A protocol data unit of SDP, with its parameters still encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub id: PduId,
+    /// The transaction ID, which is copied from a request to its response.
+    pub txn: u16,
+    pub parameter: Bytes,
 }
 
 #[repr(u8)]
@@ -41,17 +45,30 @@ impl Pdu {
     }
 }
 
-impl<B: Buf> From<&mut B> for Pdu {
-    fn from(buf: &mut B) -> Self {
-        Pdu {
-            id: FromPrimitive::from_u8(buf.get_u8()).unwrap(),
-            txn: buf.get_u16(),
-            parameter: {
-                let param_size = buf.get_u16() as usize;
-                buf.copy_to_bytes(param_size)
-            },
-        }
+/// Parses a single SDP PDU, including its header. Returns
+/// [`Error::InvalidResponse`] if the PDU is truncated or has an unknown ID,
+/// instead of panicking, so this can be used on untrusted input. Bytes after
+/// the end of the PDU are ignored.
+pub fn parse_sdp_pdu(data: &[u8]) -> Result<Pdu, Error> {
+    // the header is 5 bytes long and ends with the parameter length
+    if data.len() < 5 {
+        return Err(Error::InvalidResponse);
+    }
+
+    let mut buf = data;
+    let id = PduId::from_u8(buf.get_u8()).ok_or(Error::InvalidResponse)?;
+    let txn = buf.get_u16();
+    let len = buf.get_u16() as usize;
+
+    if buf.len() < len {
+        return Err(Error::InvalidResponse);
     }
+
+    Ok(Pdu {
+        id,
+        txn,
+        parameter: Bytes::copy_from_slice(&buf[..len]),
+    })
 }
 
 impl ToBuf for Pdu {
@@ -91,101 +108,136 @@ pub enum DataElement {
     Alternative(Vec<DataElement>),
 }
 
+/// How deeply sequences and alternatives may be nested in a parsed data
+/// element. Records in practice nest a few levels at most, so this only stops
+/// malicious input from exhausting the stack.
+const MAX_NESTING_DEPTH: usize = 32;
+
 impl<B: Buf> From<&mut B> for DataElement {
+    /// Parses a data element from trusted input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data element is malformed or truncated. Use
+    /// [`DataElement::parse`] for data that was received from a remote device.
     fn from(buf: &mut B) -> Self {
+        Self::parse(buf).expect("invalid data element")
+    }
+}
+
+impl DataElement {
+    /// Parses a single data element from the start of `buf`. Returns
+    /// [`Error::InvalidResponse`] if the element is truncated, has an invalid
+    /// type or size descriptor, or is nested too deeply, instead of panicking,
+    /// so this can be used on untrusted input.
+    pub fn parse<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        Self::parse_nested(buf, 0)
+    }
+
+    fn parse_nested<B: Buf>(buf: &mut B, depth: usize) -> Result<Self, Error> {
+        if !buf.has_remaining() {
+            return Err(Error::InvalidResponse);
+        }
+
         let desc = buf.get_u8();
         let type_desc = (desc & 0b11111000) >> 3;
         let size_desc = desc & 0b00000111;
 
-        match type_desc {
-            0 => Self::Nil,
-            1 => match size_desc {
-                0 => Self::Uint8(buf.get_u8()),
-                1 => Self::Uint16(buf.get_u16()),
-                2 => Self::Uint32(buf.get_u32()),
-                3 => Self::Uint64(buf.get_u64()),
-                4 => Self::Uint128(buf.get_u128()),
-                _ => panic!("invalid size descriptor"),
-            },
-            2 => match size_desc {
-                0 => Self::Int8(buf.get_i8()),
-                1 => Self::Int16(buf.get_i16()),
-                2 => Self::Int32(buf.get_i32()),
-                3 => Self::Int64(buf.get_i64()),
-                4 => Self::Int128(buf.get_i128()),
-                _ => panic!("invalid size descriptor"),
-            },
-            3 => match size_desc {
-                1 => Self::Uuid16(Uuid16(buf.get_u16())),
-                2 => Self::Uuid32(Uuid32(buf.get_u32())),
-                4 => Self::Uuid128(Uuid128(buf.get_u128())),
-                _ => panic!("invalid size descriptor"),
-            },
-            4 => {
-                let size = match size_desc {
-                    5 => buf.get_u8() as usize,
-                    6 => buf.get_u16() as usize,
-                    7 => buf.get_u32() as usize,
-                    _ => panic!("invalid size descriptor"),
-                };
-                let bytes = buf.get_vec_u8(size);
-                Self::String(OsString::from_vec(bytes))
-            }
-            5 => match size_desc {
-                0 => Self::Bool(buf.get_bool()),
-                _ => panic!("invalid size descriptor"),
-            },
-            6 => {
-                let size = match size_desc {
-                    5 => buf.get_u8() as usize,
-                    6 => buf.get_u16() as usize,
-                    7 => buf.get_u32() as usize,
-                    _ => panic!("invalid size descriptor"),
-                };
-
-                let mut seq_buf = buf.copy_to_bytes(size);
-                let mut seq = vec![];
-
-                while seq_buf.len() > 0 {
-                    seq.push(DataElement::from(&mut seq_buf))
-                }
+        // the size of the data, which follows the descriptor for variable
+        // sized types
+        let size = match (type_desc, size_desc) {
+            (0, 0) => 0,
+            (0, _) => return Err(Error::InvalidResponse),
+            (_, 0..=4) => 1 << size_desc,
+            (_, 5) if buf.remaining() >= 1 => buf.get_u8() as usize,
+            (_, 6) if buf.remaining() >= 2 => buf.get_u16() as usize,
+            (_, 7) if buf.remaining() >= 4 => buf.get_u32() as usize,
+            _ => return Err(Error::InvalidResponse),
+        };
 
-                Self::Sequence(seq)
-            }
-            7 => {
-                let size = match size_desc {
-                    5 => buf.get_u8() as usize,
-                    6 => buf.get_u16() as usize,
-                    7 => buf.get_u32() as usize,
-                    _ => panic!("invalid size descriptor"),
-                };
-
-                let mut seq_buf = buf.copy_to_bytes(size);
-                let mut seq = vec![];
-
-                while seq_buf.len() > 0 {
-                    seq.push(DataElement::from(&mut seq_buf))
-                }
+        if buf.remaining() < size {
+            return Err(Error::InvalidResponse);
+        }
 
-                Self::Alternative(seq)
-            }
-            8 => {
-                let size = match size_desc {
-                    5 => buf.get_u8() as usize,
-                    6 => buf.get_u16() as usize,
-                    7 => buf.get_u32() as usize,
-                    _ => panic!("invalid size descriptor"),
-                };
-                let bytes = buf.get_vec_u8(size);
-                Self::Url(OsString::from_vec(bytes))
-            }
-            _ => panic!("invalid size descriptor"),
+        Ok(match (type_desc, size_desc) {
+            (0, _) => Self::Nil,
+            (1, 0) => Self::Uint8(buf.get_u8()),
+            (1, 1) => Self::Uint16(buf.get_u16()),
+            (1, 2) => Self::Uint32(buf.get_u32()),
+            (1, 3) => Self::Uint64(buf.get_u64()),
+            (1, 4) => Self::Uint128(buf.get_u128()),
+            (2, 0) => Self::Int8(buf.get_i8()),
+            (2, 1) => Self::Int16(buf.get_i16()),
+            (2, 2) => Self::Int32(buf.get_i32()),
+            (2, 3) => Self::Int64(buf.get_i64()),
+            (2, 4) => Self::Int128(buf.get_i128()),
+            (3, 1) => Self::Uuid16(Uuid16(buf.get_u16())),
+            (3, 2) => Self::Uuid32(Uuid32(buf.get_u32())),
+            (3, 4) => Self::Uuid128(Uuid128(buf.get_u128())),
+            (4, 5..=7) => Self::String(OsString::from_vec(buf.get_vec_u8(size))),
+            (5, 0) => Self::Bool(buf.get_bool()),
+            (6, 5..=7) => Self::Sequence(Self::parse_sequence(buf, size, depth)?),
+            (7, 5..=7) => Self::Alternative(Self::parse_sequence(buf, size, depth)?),
+            (8, 5..=7) => Self::Url(OsString::from_vec(buf.get_vec_u8(size))),
+            _ => return Err(Error::InvalidResponse),
+        })
+    }
+
+    /// Parses the elements of a sequence or alternative whose data is `size`
+    /// bytes long. The elements must fill the data exactly.
+    fn parse_sequence<B: Buf>(buf: &mut B, size: usize, depth: usize) -> Result<Vec<Self>, Error> {
+        if depth >= MAX_NESTING_DEPTH {
+            return Err(Error::InvalidResponse);
+        }
+
+        let mut seq_buf = buf.copy_to_bytes(size);
+        let mut seq = vec![];
+
+        while seq_buf.has_remaining() {
+            seq.push(Self::parse_nested(&mut seq_buf, depth + 1)?);
         }
+
+        Ok(seq)
+    }
+}
+
+fn variable_size_desc(size: usize) -> u8 {
+    if size < u8::MAX as usize {
+        5
+    } else if size < u16::MAX as usize {
+        6
+    } else if size < u32::MAX as usize {
+        7
+    } else {
+        panic!("size of data too large");
     }
 }
 
 impl DataElement {
-    pub(super) fn to_buf<B: BufMut>(&self, buf: &mut B) {
+    /// Returns the total length of the data element at the start of `buf`,
+    /// including its header, or `None` if `buf` does not contain enough bytes
+    /// to tell.
+    pub(super) fn peek_len(buf: &[u8]) -> Option<usize> {
+        let desc = *buf.first()?;
+        let type_desc = desc >> 3;
+        let size_desc = desc & 0b00000111;
+
+        Some(match (type_desc, size_desc) {
+            (0, _) => 1,
+            (_, 0..=4) => 1 + (1 << size_desc),
+            (_, 5) => 2 + *buf.get(1)? as usize,
+            (_, 6) => 3 + u16::from_be_bytes([*buf.get(1)?, *buf.get(2)?]) as usize,
+            _ => {
+                let len = buf.get(1..5)?;
+                5 + u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize
+            }
+        })
+    }
+
+    /// Returns the type descriptor, the size descriptor and the size of the
+    /// data of this element. The size of sequences is computed from the sizes
+    /// of their elements, without serializing them.
+    fn descriptor(&self) -> (u8, u8, usize) {
         let (type_desc, size_desc, size): (u8, Option<u8>, usize) = match self {
             DataElement::Nil => (0, Some(0), 0),
             DataElement::Uint8(_) => (1, Some(0), 0),
@@ -202,47 +254,43 @@ impl DataElement {
             DataElement::Uuid32(_) => (3, Some(2), 0),
             DataElement::Uuid128(_) => (3, Some(4), 0),
             DataElement::String(s) => (4, None, s.len()),
-            DataElement::Bool(_) => (5, None, 0),
-            DataElement::Sequence(s) => (
-                6,
-                None,
-                s.iter()
-                    .map(|i| {
-                        let mut b = BytesMut::new();
-                        i.to_buf(&mut b);
-                        b.len()
-                    })
-                    .sum(),
-            ),
-            DataElement::Alternative(s) => (
-                7,
-                None,
-                s.iter()
-                    .map(|i| {
-                        let mut b = BytesMut::new();
-                        i.to_buf(&mut b);
-                        b.len()
-                    })
-                    .sum(),
-            ),
+            DataElement::Bool(_) => (5, Some(0), 0),
+            DataElement::Sequence(s) => (6, None, s.iter().map(Self::serialized_size).sum()),
+            DataElement::Alternative(s) => (7, None, s.iter().map(Self::serialized_size).sum()),
             DataElement::Url(s) => (8, None, s.len()),
         };
 
-        let size_desc = match size_desc {
-            Some(size_desc) => size_desc,
-            None => {
-                if size < u8::MAX as usize {
-                    5
-                } else if size < u16::MAX as usize {
-                    6
-                } else if size < u32::MAX as usize {
-                    7
-                } else {
-                    panic!("size of data too large");
-                }
-            }
+        (
+            type_desc,
+            size_desc.unwrap_or_else(|| variable_size_desc(size)),
+            size,
+        )
+    }
+
+    /// The number of bytes that [`DataElement::to_buf`] writes for this
+    /// element, including its header.
+    pub(super) fn serialized_size(&self) -> usize {
+        let (type_desc, size_desc, size) = self.descriptor();
+
+        let data_size = match (type_desc, size_desc) {
+            (0, _) => 0,
+            (_, 0..=4) => 1 << size_desc,
+            _ => size,
+        };
+
+        let length_size = match size_desc {
+            5 => 1,
+            6 => 2,
+            7 => 4,
+            _ => 0,
         };
 
+        1 + length_size + data_size
+    }
+
+    pub(super) fn to_buf<B: BufMut>(&self, buf: &mut B) {
+        let (type_desc, size_desc, size) = self.descriptor();
+
         let header = (type_desc << 3) | size_desc;
 
         buf.put_u8(header);
@@ -279,3 +327,168 @@ impl DataElement {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An attribute list with `count` attributes, each of which holds a
+    /// nested sequence.
+    fn attribute_list(count: u16) -> DataElement {
+        let record: Vec<DataElement> = (0..count)
+            .map(|i| {
+                DataElement::Sequence(vec![
+                    DataElement::Uint16(i),
+                    DataElement::Sequence(vec![
+                        DataElement::Uuid16(Uuid16(0x1101)),
+                        DataElement::Uuid128(Uuid128(i as u128)),
+                        DataElement::String(OsString::from("serial port")),
+                        DataElement::Bool(true),
+                    ]),
+                ])
+            })
+            .collect();
+
+        DataElement::Sequence(record)
+    }
+
+    /// Serializes `element` like `to_buf` did before the sizes of sequences
+    /// were computed up front, by serializing every sequence into a buffer of
+    /// its own to measure it.
+    fn to_buf_measured(element: &DataElement, buf: &mut BytesMut) {
+        let (type_desc, items) = match element {
+            DataElement::Sequence(items) => (6, items),
+            DataElement::Alternative(items) => (7, items),
+            element => return element.to_buf(buf),
+        };
+
+        let mut data = BytesMut::new();
+        for item in items {
+            to_buf_measured(item, &mut data);
+        }
+
+        let size_desc = variable_size_desc(data.len());
+        buf.put_u8((type_desc << 3) | size_desc);
+
+        match size_desc {
+            5 => buf.put_u8(data.len() as u8),
+            6 => buf.put_u16(data.len() as u16),
+            _ => buf.put_u32(data.len() as u32),
+        }
+
+        buf.put(data);
+    }
+
+    #[test]
+    fn serialized_size() {
+        let element = attribute_list(2000);
+
+        let mut buf = BytesMut::new();
+        element.to_buf(&mut buf);
+        assert_eq!(element.serialized_size(), buf.len());
+
+        let mut buf = buf.freeze();
+        let parsed = DataElement::parse(&mut buf).unwrap();
+        assert!(matches!(parsed, DataElement::Sequence(s) if s.len() == 2000));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let parse = |mut data: &[u8]| DataElement::parse(&mut data);
+
+        assert!(matches!(
+            parse(&[0x19, 0x11, 0x01]),
+            Ok(DataElement::Uuid16(Uuid16(0x1101)))
+        ));
+
+        // empty input, truncated integer and truncated string length
+        assert!(parse(&[]).is_err());
+        assert!(parse(&[0x09, 0x00]).is_err());
+        assert!(parse(&[0x26, 0x00]).is_err());
+
+        // invalid size descriptors for a UUID, a boolean and nil
+        assert!(parse(&[0x18, 0x00]).is_err());
+        assert!(parse(&[0x29, 0x00, 0x01]).is_err());
+        assert!(parse(&[0x01]).is_err());
+
+        // unknown type descriptor
+        assert!(parse(&[0x48, 0x00]).is_err());
+
+        // a sequence whose length is larger than its data, a nested sequence
+        // which is cut off by the outer length and a nested sequence whose
+        // element is cut off by its own length
+        assert!(parse(&[0x35, 0x04, 0x08, 0x00]).is_err());
+        assert!(parse(&[0x35, 0x04, 0x35, 0x03, 0x09, 0x00, 0x01]).is_err());
+        assert!(parse(&[0x35, 0x04, 0x35, 0x02, 0x09, 0x00, 0x01]).is_err());
+    }
+
+    #[test]
+    fn parse_nesting_limit() {
+        let nested = |depth: usize| {
+            let mut element = DataElement::Nil;
+            for _ in 0..depth {
+                element = DataElement::Sequence(vec![element]);
+            }
+
+            let mut buf = BytesMut::new();
+            element.to_buf(&mut buf);
+            buf.freeze()
+        };
+
+        assert!(DataElement::parse(&mut nested(MAX_NESTING_DEPTH)).is_ok());
+        assert!(DataElement::parse(&mut nested(MAX_NESTING_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn parse_pdu() {
+        let pdu = parse_sdp_pdu(&[0x01, 0x00, 0x07, 0x00, 0x02, 0x00, 0x03]).unwrap();
+        assert_eq!(pdu.id, PduId::ErrorResponse);
+        assert_eq!(pdu.txn, 7);
+        assert_eq!(&pdu.parameter[..], &[0x00, 0x03]);
+
+        // truncated parameters, truncated header and unknown ID
+        assert!(parse_sdp_pdu(&[0x01, 0x00, 0x07, 0x00, 0x02, 0x00]).is_err());
+        assert!(parse_sdp_pdu(&[0x01, 0x00]).is_err());
+        assert!(parse_sdp_pdu(&[0xFF, 0x00, 0x07, 0x00, 0x00]).is_err());
+    }
+
+    /// Compares serializing large attribute lists with and without computing
+    /// the sizes of sequences up front. Run it with
+    /// `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_attribute_list_to_buf() {
+        const ITERATIONS: u32 = 100;
+
+        for count in [100, 1000, 10000] {
+            let element = attribute_list(count);
+
+            let mut expected = BytesMut::new();
+            to_buf_measured(&element, &mut expected);
+
+            let start = std::time::Instant::now();
+            for _ in 0..ITERATIONS {
+                let mut buf = BytesMut::new();
+                to_buf_measured(&element, &mut buf);
+                assert_eq!(buf.len(), expected.len());
+            }
+            let measured = start.elapsed() / ITERATIONS;
+
+            let start = std::time::Instant::now();
+            for _ in 0..ITERATIONS {
+                let mut buf = BytesMut::with_capacity(element.serialized_size());
+                element.to_buf(&mut buf);
+                assert_eq!(buf, expected);
+            }
+            let precomputed = start.elapsed() / ITERATIONS;
+
+            println!(
+                "{} attributes: {:?} measured, {:?} precomputed ({:.1}x)",
+                count,
+                measured,
+                precomputed,
+                measured.as_secs_f64() / precomputed.as_secs_f64()
+            );
+        }
+    }
+}
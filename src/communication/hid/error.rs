@@ -0,0 +1,16 @@
+use super::message::HandshakeResult;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid packet")]
+    InvalidPacket,
+
+    #[error("the remote device answered with {0:?}")]
+    Handshake(HandshakeResult),
+
+    #[error("the hid connection has been closed")]
+    SessionClosed,
+}
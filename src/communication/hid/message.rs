@@ -0,0 +1,377 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+
+const TYPE_HANDSHAKE: u8 = 0x0;
+const TYPE_HID_CONTROL: u8 = 0x1;
+const TYPE_GET_REPORT: u8 = 0x4;
+const TYPE_SET_REPORT: u8 = 0x5;
+const TYPE_GET_PROTOCOL: u8 = 0x6;
+const TYPE_SET_PROTOCOL: u8 = 0x7;
+const TYPE_DATA: u8 = 0xA;
+
+/// Set in the parameter of Get Report when the request limits the size of
+/// the report.
+const GET_REPORT_SIZE: u8 = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeResult {
+    Successful,
+    NotReady,
+    InvalidReportId,
+    UnsupportedRequest,
+    InvalidParameter,
+    Unknown,
+    Fatal,
+    Other(u8),
+}
+
+impl From<u8> for HandshakeResult {
+    fn from(result: u8) -> Self {
+        match result {
+            0x0 => HandshakeResult::Successful,
+            0x1 => HandshakeResult::NotReady,
+            0x2 => HandshakeResult::InvalidReportId,
+            0x3 => HandshakeResult::UnsupportedRequest,
+            0x4 => HandshakeResult::InvalidParameter,
+            0xE => HandshakeResult::Unknown,
+            0xF => HandshakeResult::Fatal,
+            result => HandshakeResult::Other(result),
+        }
+    }
+}
+
+impl From<HandshakeResult> for u8 {
+    fn from(result: HandshakeResult) -> Self {
+        match result {
+            HandshakeResult::Successful => 0x0,
+            HandshakeResult::NotReady => 0x1,
+            HandshakeResult::InvalidReportId => 0x2,
+            HandshakeResult::UnsupportedRequest => 0x3,
+            HandshakeResult::InvalidParameter => 0x4,
+            HandshakeResult::Unknown => 0xE,
+            HandshakeResult::Fatal => 0xF,
+            HandshakeResult::Other(result) => result & 0x0F,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlOperation {
+    Suspend,
+    ExitSuspend,
+    /// Removes the bond between the host and the device. Both sides close
+    /// the channels afterwards.
+    VirtualCableUnplug,
+    Other(u8),
+}
+
+impl From<u8> for ControlOperation {
+    fn from(operation: u8) -> Self {
+        match operation {
+            0x3 => ControlOperation::Suspend,
+            0x4 => ControlOperation::ExitSuspend,
+            0x5 => ControlOperation::VirtualCableUnplug,
+            operation => ControlOperation::Other(operation),
+        }
+    }
+}
+
+impl From<ControlOperation> for u8 {
+    fn from(operation: ControlOperation) -> Self {
+        match operation {
+            ControlOperation::Suspend => 0x3,
+            ControlOperation::ExitSuspend => 0x4,
+            ControlOperation::VirtualCableUnplug => 0x5,
+            ControlOperation::Other(operation) => operation & 0x0F,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportType {
+    Other,
+    Input,
+    Output,
+    Feature,
+}
+
+impl From<u8> for ReportType {
+    fn from(report_type: u8) -> Self {
+        match report_type & 0x03 {
+            0x1 => ReportType::Input,
+            0x2 => ReportType::Output,
+            0x3 => ReportType::Feature,
+            _ => ReportType::Other,
+        }
+    }
+}
+
+impl From<ReportType> for u8 {
+    fn from(report_type: ReportType) -> Self {
+        match report_type {
+            ReportType::Other => 0x0,
+            ReportType::Input => 0x1,
+            ReportType::Output => 0x2,
+            ReportType::Feature => 0x3,
+        }
+    }
+}
+
+/// The format of reports. Every device starts in report mode, where reports
+/// follow its report descriptor. Keyboards and mice also support boot mode,
+/// where they send the fixed reports of [`BootKeyboardReport`] and
+/// [`BootMouseReport`], so that hosts without a descriptor parser, such as
+/// a BIOS, can use them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolMode {
+    Boot,
+    Report,
+}
+
+impl From<u8> for ProtocolMode {
+    fn from(mode: u8) -> Self {
+        match mode & 0x01 {
+            0x0 => ProtocolMode::Boot,
+            _ => ProtocolMode::Report,
+        }
+    }
+}
+
+impl From<ProtocolMode> for u8 {
+    fn from(mode: ProtocolMode) -> Self {
+        match mode {
+            ProtocolMode::Boot => 0x0,
+            ProtocolMode::Report => 0x1,
+        }
+    }
+}
+
+/// A message on the control or interrupt channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HidpMessage {
+    Handshake(HandshakeResult),
+    Control(ControlOperation),
+    GetReport {
+        report_type: ReportType,
+        /// The ID of the report, if the device uses report IDs.
+        report_id: Option<u8>,
+        /// The largest report that the host accepts.
+        buffer_size: Option<u16>,
+    },
+    SetReport {
+        report_type: ReportType,
+        /// The report, starting with its ID if the device uses report IDs.
+        data: Bytes,
+    },
+    GetProtocol,
+    SetProtocol(ProtocolMode),
+    Data {
+        report_type: ReportType,
+        data: Bytes,
+    },
+}
+
+impl HidpMessage {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+
+        match self {
+            HidpMessage::Handshake(result) => {
+                buf.put_u8(TYPE_HANDSHAKE << 4 | u8::from(*result));
+            }
+            HidpMessage::Control(operation) => {
+                buf.put_u8(TYPE_HID_CONTROL << 4 | u8::from(*operation));
+            }
+            HidpMessage::GetReport {
+                report_type,
+                report_id,
+                buffer_size,
+            } => {
+                let mut param = u8::from(*report_type);
+                if buffer_size.is_some() {
+                    param |= GET_REPORT_SIZE;
+                }
+
+                buf.put_u8(TYPE_GET_REPORT << 4 | param);
+                if let Some(report_id) = report_id {
+                    buf.put_u8(*report_id);
+                }
+                if let Some(buffer_size) = buffer_size {
+                    buf.put_u16_le(*buffer_size);
+                }
+            }
+            HidpMessage::SetReport { report_type, data } => {
+                buf.put_u8(TYPE_SET_REPORT << 4 | u8::from(*report_type));
+                buf.put_slice(&data[..]);
+            }
+            HidpMessage::GetProtocol => buf.put_u8(TYPE_GET_PROTOCOL << 4),
+            HidpMessage::SetProtocol(mode) => {
+                buf.put_u8(TYPE_SET_PROTOCOL << 4 | u8::from(*mode));
+            }
+            HidpMessage::Data { report_type, data } => {
+                buf.put_u8(TYPE_DATA << 4 | u8::from(*report_type));
+                buf.put_slice(&data[..]);
+            }
+        }
+
+        buf.freeze()
+    }
+
+    /// Parses a message. The message does not say whether a Get Report
+    /// request contains a report ID, so `report_ids` has to be set if the
+    /// reports of the device start with an ID.
+    pub fn parse(mut buf: Bytes, report_ids: bool) -> Result<Self, Error> {
+        if !buf.has_remaining() {
+            return Err(Error::InvalidPacket);
+        }
+
+        let header = buf.get_u8();
+        let param = header & 0x0F;
+
+        Ok(match header >> 4 {
+            TYPE_HANDSHAKE => HidpMessage::Handshake(param.into()),
+            TYPE_HID_CONTROL => HidpMessage::Control(param.into()),
+            TYPE_GET_REPORT => {
+                let report_id = if report_ids {
+                    if !buf.has_remaining() {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    Some(buf.get_u8())
+                } else {
+                    None
+                };
+
+                let buffer_size = if param & GET_REPORT_SIZE != 0 {
+                    if buf.remaining() < 2 {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    Some(buf.get_u16_le())
+                } else {
+                    None
+                };
+
+                HidpMessage::GetReport {
+                    report_type: param.into(),
+                    report_id,
+                    buffer_size,
+                }
+            }
+            TYPE_SET_REPORT => HidpMessage::SetReport {
+                report_type: param.into(),
+                data: buf,
+            },
+            TYPE_GET_PROTOCOL => HidpMessage::GetProtocol,
+            TYPE_SET_PROTOCOL => HidpMessage::SetProtocol(param.into()),
+            TYPE_DATA => HidpMessage::Data {
+                report_type: param.into(),
+                data: buf,
+            },
+            _ => return Err(Error::InvalidPacket),
+        })
+    }
+}
+
+/// The report of a keyboard in boot mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootKeyboardReport {
+    /// The modifier keys that are held, with left control in bit 0 and right
+    /// GUI in bit 7.
+    pub modifiers: u8,
+    /// The usage IDs of up to six keys that are held, padded with zeros.
+    pub keys: [u8; 6],
+}
+
+impl BootKeyboardReport {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(8);
+        buf.put_u8(self.modifiers);
+        buf.put_u8(0);
+        buf.put_slice(&self.keys);
+        buf.freeze()
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < 8 {
+            return Err(Error::InvalidPacket);
+        }
+
+        let mut keys = [0; 6];
+        keys.copy_from_slice(&buf[2..8]);
+
+        Ok(Self {
+            modifiers: buf[0],
+            keys,
+        })
+    }
+}
+
+/// The report of a mouse in boot mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootMouseReport {
+    /// The buttons that are held, with the left button in bit 0.
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+}
+
+impl BootMouseReport {
+    pub fn encode(&self) -> Bytes {
+        Bytes::copy_from_slice(&[self.buttons, self.x as u8, self.y as u8])
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < 3 {
+            return Err(Error::InvalidPacket);
+        }
+
+        Ok(Self {
+            buttons: buf[0],
+            x: buf[1] as i8,
+            y: buf[2] as i8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_report() {
+        let message = HidpMessage::GetReport {
+            report_type: ReportType::Feature,
+            report_id: Some(0x02),
+            buffer_size: Some(0x0040),
+        };
+
+        let buf = message.encode();
+        assert_eq!(&buf[..], &[0x4B, 0x02, 0x40, 0x00]);
+        assert_eq!(HidpMessage::parse(buf, true).unwrap(), message);
+    }
+
+    #[test]
+    fn boot_keyboard_input() {
+        let report = BootKeyboardReport {
+            modifiers: 0x02,
+            keys: [0x04, 0, 0, 0, 0, 0],
+        };
+
+        let buf = HidpMessage::Data {
+            report_type: ReportType::Input,
+            data: report.encode(),
+        }
+        .encode();
+        assert_eq!(&buf[..], &[0xA1, 0x02, 0x00, 0x04, 0, 0, 0, 0, 0]);
+
+        match HidpMessage::parse(buf, false).unwrap() {
+            HidpMessage::Data {
+                report_type: ReportType::Input,
+                data,
+            } => assert_eq!(BootKeyboardReport::parse(&data).unwrap(), report),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,687 @@
+//! The channels of HIDP, the Bluetooth HID Protocol, which keyboards, mice
+//! and game controllers use on BR/EDR.
+//!
+//! A HID connection is made of two L2CAP channels: requests such as Get
+//! Report and Set Protocol go over the control channel, and reports are sent
+//! over the interrupt channel. A [`HidHost`] drives a remote device, and a
+//! [`HidDevice`] lets the local adapter act as a keyboard or mouse for a
+//! remote host. The device role also needs an SDP record describing the
+//! device, which has to be registered through BlueZ, and the kernel's own
+//! HIDP support must not be listening on the same PSMs.
+//!
+//! As with the other protocols in this module, no tasks are spawned, so
+//! call [`HidHost::recv_input_report`] or [`HidDevice::recv_request`] in a
+//! loop.
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{select, Either};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+pub use message::*;
+
+use super::stream::{BluetoothListener, BluetoothStream};
+use crate::{Address, AddressType, Protocol};
+
+mod error;
+mod message;
+
+/// The PSM of the HID control channel.
+pub const HID_CONTROL_PSM: u16 = 0x0011;
+
+/// The PSM of the HID interrupt channel.
+pub const HID_INTERRUPT_PSM: u16 = 0x0013;
+
+/// The default MTU of L2CAP channels, which HIDP does not change.
+const MTU: usize = 672;
+
+/// The host role of HIDP, connected to a remote keyboard, mouse or other
+/// HID device.
+#[derive(Debug)]
+pub struct HidHost {
+    control: BluetoothStream,
+    interrupt: BluetoothStream,
+}
+
+impl HidHost {
+    /// Opens the control channel and then the interrupt channel to a remote
+    /// device.
+    pub async fn connect(address: Address) -> Result<Self, Error> {
+        let (control, interrupt) = connect_channels(address).await?;
+        Ok(Self::accept(control, interrupt))
+    }
+
+    /// Creates a host from the control and interrupt channels that a remote
+    /// device opened, for example when it reconnects to the host.
+    pub fn accept(control: BluetoothStream, interrupt: BluetoothStream) -> Self {
+        Self { control, interrupt }
+    }
+
+    /// Creates listeners for the control and interrupt channels on the local
+    /// adapter with the given address. Accept a stream from each, and pass
+    /// them to [`HidHost::accept`].
+    pub fn bind(address: Address) -> Result<(BluetoothListener, BluetoothListener), Error> {
+        bind_channels(address)
+    }
+
+    /// Reads the protocol mode of the device.
+    pub async fn get_protocol(&mut self) -> Result<ProtocolMode, Error> {
+        let data = self.request_data(HidpMessage::GetProtocol).await?;
+        data.first()
+            .map(|&mode| mode.into())
+            .ok_or(Error::InvalidPacket)
+    }
+
+    /// Switches the device between boot mode and report mode. Devices which
+    /// do not support boot mode answer with
+    /// [`HandshakeResult::UnsupportedRequest`].
+    pub async fn set_protocol(&mut self, mode: ProtocolMode) -> Result<(), Error> {
+        self.request(HidpMessage::SetProtocol(mode)).await
+    }
+
+    /// Reads a report from the device over the control channel. `report_id`
+    /// has to be given if the device uses report IDs.
+    pub async fn get_report(
+        &mut self,
+        report_type: ReportType,
+        report_id: Option<u8>,
+        buffer_size: Option<u16>,
+    ) -> Result<Bytes, Error> {
+        self.request_data(HidpMessage::GetReport {
+            report_type,
+            report_id,
+            buffer_size,
+        })
+        .await
+    }
+
+    /// Sends a report to the device over the control channel, and waits for
+    /// the device to accept it.
+    pub async fn set_report(&mut self, report_type: ReportType, data: Bytes) -> Result<(), Error> {
+        self.request(HidpMessage::SetReport { report_type, data })
+            .await
+    }
+
+    /// Sends an output report, such as the state of the LEDs of a keyboard,
+    /// over the interrupt channel. The device does not answer these.
+    pub async fn send_output_report(&mut self, data: Bytes) -> Result<(), Error> {
+        let message = HidpMessage::Data {
+            report_type: ReportType::Output,
+            data,
+        };
+
+        self.interrupt.write_all(&message.encode()[..]).await?;
+        Ok(())
+    }
+
+    /// Waits for the next input report from the device.
+    pub async fn recv_input_report(&mut self) -> Result<Bytes, Error> {
+        loop {
+            if let HidpMessage::Data {
+                report_type: ReportType::Input,
+                data,
+            } = HidpMessage::parse(read_message(&mut self.interrupt).await?, false)?
+            {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// Tells the device to remove its bond with the host. The channels should
+    /// be closed afterwards.
+    pub async fn virtual_cable_unplug(&mut self) -> Result<(), Error> {
+        let message = HidpMessage::Control(ControlOperation::VirtualCableUnplug);
+        self.control.write_all(&message.encode()[..]).await?;
+        Ok(())
+    }
+
+    /// Returns the control and interrupt channels.
+    pub fn into_inner(self) -> (BluetoothStream, BluetoothStream) {
+        (self.control, self.interrupt)
+    }
+
+    /// Sends a request which is answered with a handshake.
+    async fn request(&mut self, request: HidpMessage) -> Result<(), Error> {
+        match self.send_request(request).await? {
+            HidpMessage::Handshake(HandshakeResult::Successful) => Ok(()),
+            HidpMessage::Handshake(result) => Err(Error::Handshake(result)),
+            _ => Err(Error::InvalidPacket),
+        }
+    }
+
+    /// Sends a request which is answered with data, or with a handshake if it
+    /// fails.
+    async fn request_data(&mut self, request: HidpMessage) -> Result<Bytes, Error> {
+        match self.send_request(request).await? {
+            HidpMessage::Data { data, .. } => Ok(data),
+            HidpMessage::Handshake(result) => Err(Error::Handshake(result)),
+            _ => Err(Error::InvalidPacket),
+        }
+    }
+
+    async fn send_request(&mut self, request: HidpMessage) -> Result<HidpMessage, Error> {
+        self.control.write_all(&request.encode()[..]).await?;
+
+        loop {
+            match HidpMessage::parse(read_message(&mut self.control).await?, false)? {
+                HidpMessage::Control(ControlOperation::VirtualCableUnplug) => {
+                    return Err(Error::SessionClosed)
+                }
+                // suspend notifications may arrive at any time
+                HidpMessage::Control(_) => continue,
+                response => return Ok(response),
+            }
+        }
+    }
+}
+
+/// A request from the host to a [`HidDevice`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HidRequest {
+    /// The host wants a report. Answer with [`HidDevice::reply_report`], or
+    /// with [`HidDevice::reply_handshake`] if the report does not exist.
+    GetReport {
+        report_type: ReportType,
+        report_id: Option<u8>,
+        buffer_size: Option<u16>,
+    },
+    /// The host sent a report over the control channel. Answer with
+    /// [`HidDevice::reply_handshake`].
+    SetReport {
+        report_type: ReportType,
+        data: Bytes,
+    },
+    /// The host sent an output report over the interrupt channel. These are
+    /// not answered.
+    OutputReport {
+        data: Bytes,
+    },
+    /// The host switched the protocol mode, which has already been
+    /// acknowledged. Input reports have to use the new format from now on.
+    ProtocolChanged(ProtocolMode),
+    Suspend,
+    ExitSuspend,
+    /// The host removed its bond with the device, and the channels are about
+    /// to be closed.
+    VirtualCableUnplug,
+}
+
+/// The device role of HIDP, connected to a remote host.
+#[derive(Debug)]
+pub struct HidDevice {
+    control: BluetoothStream,
+    interrupt: BluetoothStream,
+    protocol: ProtocolMode,
+    boot_protocol: bool,
+    report_ids: bool,
+}
+
+impl HidDevice {
+    /// Opens the control channel and then the interrupt channel to a host
+    /// that the device is bonded with.
+    pub async fn connect(address: Address) -> Result<Self, Error> {
+        let (control, interrupt) = connect_channels(address).await?;
+        Ok(Self::accept(control, interrupt))
+    }
+
+    /// Creates a device from the control and interrupt channels that a remote
+    /// host opened.
+    pub fn accept(control: BluetoothStream, interrupt: BluetoothStream) -> Self {
+        Self {
+            control,
+            interrupt,
+            protocol: ProtocolMode::Report,
+            boot_protocol: false,
+            report_ids: false,
+        }
+    }
+
+    /// Creates listeners for the control and interrupt channels on the local
+    /// adapter with the given address. Accept a stream from each, and pass
+    /// them to [`HidDevice::accept`].
+    pub fn bind(address: Address) -> Result<(BluetoothListener, BluetoothListener), Error> {
+        bind_channels(address)
+    }
+
+    /// Sets whether the host may switch the device to boot mode. This should
+    /// match the subclass in the SDP record of the device. Requests for boot
+    /// mode are refused until this is set.
+    pub fn set_boot_protocol_supported(&mut self, supported: bool) {
+        self.boot_protocol = supported;
+    }
+
+    /// Sets whether the reports of the device start with a report ID, which
+    /// is needed to parse Get Report requests.
+    pub fn set_report_ids(&mut self, report_ids: bool) {
+        self.report_ids = report_ids;
+    }
+
+    /// The current protocol mode.
+    pub fn protocol(&self) -> ProtocolMode {
+        self.protocol
+    }
+
+    /// Sends an input report, such as the keys that are held, over the
+    /// interrupt channel. In boot mode, this has to be a
+    /// [`BootKeyboardReport`] or a [`BootMouseReport`].
+    pub async fn send_input_report(&mut self, data: Bytes) -> Result<(), Error> {
+        let message = HidpMessage::Data {
+            report_type: ReportType::Input,
+            data,
+        };
+
+        self.interrupt.write_all(&message.encode()[..]).await?;
+        Ok(())
+    }
+
+    /// Answers a [`HidRequest::GetReport`].
+    pub async fn reply_report(
+        &mut self,
+        report_type: ReportType,
+        data: Bytes,
+    ) -> Result<(), Error> {
+        self.send_control(HidpMessage::Data { report_type, data })
+            .await
+    }
+
+    /// Answers a request with a handshake.
+    pub async fn reply_handshake(&mut self, result: HandshakeResult) -> Result<(), Error> {
+        self.send_control(HidpMessage::Handshake(result)).await
+    }
+
+    /// Waits for the next request from the host. Get Protocol and Set
+    /// Protocol requests are answered without being returned.
+    pub async fn recv_request(&mut self) -> Result<HidRequest, Error> {
+        loop {
+            // read_message is cancel safe
+            let (buf, control) = match select(
+                Box::pin(read_message(&mut self.control)),
+                Box::pin(read_message(&mut self.interrupt)),
+            )
+            .await
+            {
+                Either::Left((buf, _)) => (buf, true),
+                Either::Right((buf, _)) => (buf, false),
+            };
+            let buf = buf?;
+
+            let message = match HidpMessage::parse(buf, self.report_ids) {
+                Ok(message) => message,
+                Err(_) if control => {
+                    self.reply_handshake(HandshakeResult::InvalidParameter)
+                        .await?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let request = match message {
+                HidpMessage::Data {
+                    report_type: ReportType::Output,
+                    data,
+                } if !control => HidRequest::OutputReport { data },
+                _ if !control => continue,
+                HidpMessage::GetReport {
+                    report_type,
+                    report_id,
+                    buffer_size,
+                } => HidRequest::GetReport {
+                    report_type,
+                    report_id,
+                    buffer_size,
+                },
+                HidpMessage::SetReport { report_type, data } => {
+                    HidRequest::SetReport { report_type, data }
+                }
+                HidpMessage::GetProtocol => {
+                    let mode = u8::from(self.protocol);
+                    self.reply_report(ReportType::Other, Bytes::copy_from_slice(&[mode]))
+                        .await?;
+                    continue;
+                }
+                HidpMessage::SetProtocol(ProtocolMode::Boot) if !self.boot_protocol => {
+                    self.reply_handshake(HandshakeResult::UnsupportedRequest)
+                        .await?;
+                    continue;
+                }
+                HidpMessage::SetProtocol(mode) => {
+                    self.reply_handshake(HandshakeResult::Successful).await?;
+
+                    if mode == self.protocol {
+                        continue;
+                    }
+
+                    self.protocol = mode;
+                    HidRequest::ProtocolChanged(mode)
+                }
+                HidpMessage::Control(ControlOperation::Suspend) => HidRequest::Suspend,
+                HidpMessage::Control(ControlOperation::ExitSuspend) => HidRequest::ExitSuspend,
+                HidpMessage::Control(ControlOperation::VirtualCableUnplug) => {
+                    HidRequest::VirtualCableUnplug
+                }
+                _ => {
+                    self.reply_handshake(HandshakeResult::UnsupportedRequest)
+                        .await?;
+                    continue;
+                }
+            };
+
+            return Ok(request);
+        }
+    }
+
+    /// Returns the control and interrupt channels.
+    pub fn into_inner(self) -> (BluetoothStream, BluetoothStream) {
+        (self.control, self.interrupt)
+    }
+
+    async fn send_control(&mut self, message: HidpMessage) -> Result<(), Error> {
+        self.control.write_all(&message.encode()[..]).await?;
+        Ok(())
+    }
+}
+
+/// Opens the control channel and then the interrupt channel, which is the
+/// order that the specification requires.
+async fn connect_channels(address: Address) -> Result<(BluetoothStream, BluetoothStream), Error> {
+    let control = BluetoothStream::connect(
+        Protocol::L2CAP,
+        address,
+        AddressType::BREDR,
+        HID_CONTROL_PSM,
+    )
+    .await?;
+    let interrupt = BluetoothStream::connect(
+        Protocol::L2CAP,
+        address,
+        AddressType::BREDR,
+        HID_INTERRUPT_PSM,
+    )
+    .await?;
+
+    Ok((control, interrupt))
+}
+
+fn bind_channels(address: Address) -> Result<(BluetoothListener, BluetoothListener), Error> {
+    let control = BluetoothListener::bind(
+        Protocol::L2CAP,
+        address,
+        AddressType::BREDR,
+        HID_CONTROL_PSM,
+    )?;
+    let interrupt = BluetoothListener::bind(
+        Protocol::L2CAP,
+        address,
+        AddressType::BREDR,
+        HID_INTERRUPT_PSM,
+    )?;
+
+    Ok((control, interrupt))
+}
+
+/// Reads one message. This is cancel safe, since a packet is only taken
+/// from the socket by the read that returns it.
+async fn read_message(stream: &mut BluetoothStream) -> Result<Bytes, Error> {
+    let mut buf = BytesMut::with_capacity(MTU);
+
+    if stream.read_buf(&mut buf).await? == 0 {
+        return Err(Error::SessionClosed);
+    }
+
+    Ok(buf.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    /// The remote ends of the control and interrupt channels.
+    struct Remote {
+        control: UnixStream,
+        interrupt: UnixStream,
+    }
+
+    fn channels() -> ((BluetoothStream, BluetoothStream), Remote) {
+        let (control, remote_control) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+        let (interrupt, remote_interrupt) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+
+        (
+            (control, interrupt),
+            Remote {
+                control: remote_control,
+                interrupt: remote_interrupt,
+            },
+        )
+    }
+
+    async fn send(channel: &mut UnixStream, message: HidpMessage) {
+        channel.write_all(&message.encode()[..]).await.unwrap();
+    }
+
+    async fn receive(channel: &mut UnixStream) -> HidpMessage {
+        let mut buf = vec![0u8; MTU];
+        let len = channel.read(&mut buf).await.unwrap();
+        buf.truncate(len);
+        HidpMessage::parse(buf.into(), true).unwrap()
+    }
+
+    #[tokio::test]
+    async fn host_requests() {
+        let ((control, interrupt), mut remote) = channels();
+        let mut host = HidHost::accept(control, interrupt);
+
+        let device = async {
+            assert_eq!(
+                receive(&mut remote.control).await,
+                HidpMessage::GetReport {
+                    report_type: ReportType::Feature,
+                    report_id: Some(0x02),
+                    buffer_size: None,
+                }
+            );
+
+            // a notification before the answer is skipped
+            send(
+                &mut remote.control,
+                HidpMessage::Control(ControlOperation::Suspend),
+            )
+            .await;
+            send(
+                &mut remote.control,
+                HidpMessage::Data {
+                    report_type: ReportType::Feature,
+                    data: Bytes::from_static(&[0x02, 0x10]),
+                },
+            )
+            .await;
+
+            assert_eq!(
+                receive(&mut remote.control).await,
+                HidpMessage::SetProtocol(ProtocolMode::Boot)
+            );
+            send(
+                &mut remote.control,
+                HidpMessage::Handshake(HandshakeResult::UnsupportedRequest),
+            )
+            .await;
+        };
+
+        let requests = async {
+            let report = host
+                .get_report(ReportType::Feature, Some(0x02), None)
+                .await
+                .unwrap();
+            let protocol = host.set_protocol(ProtocolMode::Boot).await;
+            (report, protocol)
+        };
+
+        let ((report, protocol), ()) = futures::join!(requests, device);
+        assert_eq!(&report[..], &[0x02, 0x10]);
+        assert!(matches!(
+            protocol,
+            Err(Error::Handshake(HandshakeResult::UnsupportedRequest))
+        ));
+    }
+
+    #[tokio::test]
+    async fn host_input_reports() {
+        let ((control, interrupt), mut remote) = channels();
+        let mut host = HidHost::accept(control, interrupt);
+
+        // only input reports are returned
+        send(
+            &mut remote.interrupt,
+            HidpMessage::Data {
+                report_type: ReportType::Feature,
+                data: Bytes::from_static(&[0x01]),
+            },
+        )
+        .await;
+        send(
+            &mut remote.interrupt,
+            HidpMessage::Data {
+                report_type: ReportType::Input,
+                data: Bytes::from_static(&[0x01, 0x02, 0x03]),
+            },
+        )
+        .await;
+
+        let report = host.recv_input_report().await.unwrap();
+        assert_eq!(&report[..], &[0x01, 0x02, 0x03]);
+
+        host.send_output_report(Bytes::from_static(&[0x04]))
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&mut remote.interrupt).await,
+            HidpMessage::Data {
+                report_type: ReportType::Output,
+                data: Bytes::from_static(&[0x04]),
+            }
+        );
+
+        drop(remote);
+        assert!(matches!(
+            host.recv_input_report().await,
+            Err(Error::SessionClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn device_protocol_requests() {
+        let ((control, interrupt), mut remote) = channels();
+        let mut device = HidDevice::accept(control, interrupt);
+
+        let host = async {
+            send(&mut remote.control, HidpMessage::GetProtocol).await;
+            assert_eq!(
+                receive(&mut remote.control).await,
+                HidpMessage::Data {
+                    report_type: ReportType::Other,
+                    data: Bytes::from_static(&[0x01]),
+                }
+            );
+
+            // boot mode has not been enabled
+            send(
+                &mut remote.control,
+                HidpMessage::SetProtocol(ProtocolMode::Boot),
+            )
+            .await;
+            assert_eq!(
+                receive(&mut remote.control).await,
+                HidpMessage::Handshake(HandshakeResult::UnsupportedRequest)
+            );
+
+            // a reserved message type
+            remote.control.write_all(&[0x20]).await.unwrap();
+            assert_eq!(
+                receive(&mut remote.control).await,
+                HidpMessage::Handshake(HandshakeResult::InvalidParameter)
+            );
+
+            send(
+                &mut remote.interrupt,
+                HidpMessage::Data {
+                    report_type: ReportType::Output,
+                    data: Bytes::from_static(&[0x01]),
+                },
+            )
+            .await;
+        };
+
+        let (request, ()) = futures::join!(device.recv_request(), host);
+        assert_eq!(
+            request.unwrap(),
+            HidRequest::OutputReport {
+                data: Bytes::from_static(&[0x01]),
+            }
+        );
+        assert_eq!(device.protocol(), ProtocolMode::Report);
+    }
+
+    #[tokio::test]
+    async fn device_boot_mode() {
+        let ((control, interrupt), mut remote) = channels();
+        let mut device = HidDevice::accept(control, interrupt);
+        device.set_boot_protocol_supported(true);
+        device.set_report_ids(true);
+
+        send(
+            &mut remote.control,
+            HidpMessage::SetProtocol(ProtocolMode::Boot),
+        )
+        .await;
+        assert_eq!(
+            device.recv_request().await.unwrap(),
+            HidRequest::ProtocolChanged(ProtocolMode::Boot)
+        );
+        assert_eq!(
+            receive(&mut remote.control).await,
+            HidpMessage::Handshake(HandshakeResult::Successful)
+        );
+
+        send(
+            &mut remote.control,
+            HidpMessage::GetReport {
+                report_type: ReportType::Input,
+                report_id: Some(0x01),
+                buffer_size: Some(8),
+            },
+        )
+        .await;
+        assert_eq!(
+            device.recv_request().await.unwrap(),
+            HidRequest::GetReport {
+                report_type: ReportType::Input,
+                report_id: Some(0x01),
+                buffer_size: Some(8),
+            }
+        );
+
+        let report = BootKeyboardReport::default().encode();
+        device
+            .reply_report(ReportType::Input, report.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&mut remote.control).await,
+            HidpMessage::Data {
+                report_type: ReportType::Input,
+                data: report,
+            }
+        );
+
+        send(
+            &mut remote.control,
+            HidpMessage::Control(ControlOperation::VirtualCableUnplug),
+        )
+        .await;
+        assert_eq!(
+            device.recv_request().await.unwrap(),
+            HidRequest::VirtualCableUnplug
+        );
+    }
+}
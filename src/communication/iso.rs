@@ -0,0 +1,402 @@
+//! Isochronous channels, which carry LE Audio.
+//!
+//! Unicast audio uses Connected Isochronous Streams (CIS), which are grouped
+//! into Connected Isochronous Groups (CIG). Broadcast audio uses Broadcast
+//! Isochronous Streams (BIS), which are grouped into Broadcast Isochronous
+//! Groups (BIG). The kernel exposes both through ISO sockets, which need the
+//! [`EXP_FEATURE_ISO_SOCKET`](crate::management::EXP_FEATURE_ISO_SOCKET)
+//! experimental feature to be enabled.
+//!
+//! Receiving a broadcast starts with synchronizing to the periodic
+//! advertising train of the broadcaster, which carries the description of
+//! its BIG. [`crate::hci::PeriodicSync`] does only that, and reads the
+//! periodic advertisements.
+
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::os::unix::prelude::FromRawFd;
+
+use tokio::io::unix::AsyncFd;
+use tokio::net::UnixStream;
+
+use super::stream::finish_connect;
+use super::{BluetoothListener, BluetoothStream, DEFAULT_BACKLOG};
+use crate::util::check_error;
+use crate::{Address, AddressType, Protocol};
+
+const SOL_BLUETOOTH: libc::c_int = 274;
+const BT_DEFER_SETUP: libc::c_int = 7;
+const BT_ISO_QOS: libc::c_int = 17;
+
+/// The maximum number of BISes that can be synchronized to with one socket.
+pub const ISO_MAX_NUM_BIS: usize = 0x1F;
+
+/// Lets the controller choose the CIG, CIS, BIG or BIS.
+pub const ISO_UNSET: u8 = 0xFF;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct sockaddr_iso_bc {
+    bc_bdaddr: [u8; 6],
+    bc_bdaddr_type: u8,
+    bc_sid: u8,
+    bc_num_bis: u8,
+    bc_bis: [u8; ISO_MAX_NUM_BIS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct sockaddr_iso {
+    pub(super) iso_family: libc::sa_family_t,
+    pub(super) iso_bdaddr: [u8; 6],
+    pub(super) iso_bdaddr_type: u8,
+    iso_bc: sockaddr_iso_bc,
+}
+
+/// The length of a `sockaddr_iso` without the broadcast part, including the
+/// padding that the kernel expects.
+pub(super) const SOCKADDR_ISO_LEN: usize = 10;
+
+/// The QoS of one direction of an isochronous stream.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IsoIoQos {
+    /// The SDU interval in microseconds.
+    pub interval: u32,
+    /// The maximum transport latency in milliseconds.
+    pub latency: u16,
+    /// The maximum SDU size. A size of 0 disables this direction.
+    pub sdu: u16,
+    /// A bit field of the PHYs that may be used: 0x01 for LE 1M, 0x02 for
+    /// LE 2M and 0x04 for LE Coded.
+    pub phy: u8,
+    /// The number of retransmissions.
+    pub rtn: u8,
+}
+
+/// The QoS of a Connected Isochronous Stream.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoUnicastQos {
+    /// The CIG that the CIS belongs to, or [`ISO_UNSET`].
+    pub cig: u8,
+    /// The ID of the CIS in its CIG, or [`ISO_UNSET`].
+    pub cis: u8,
+    /// The worst case sleep clock accuracy.
+    pub sca: u8,
+    pub packing: u8,
+    pub framing: u8,
+    /// From the remote device to this host.
+    pub input: IsoIoQos,
+    /// From this host to the remote device.
+    pub output: IsoIoQos,
+}
+
+impl Default for IsoUnicastQos {
+    fn default() -> Self {
+        Self {
+            cig: ISO_UNSET,
+            cis: ISO_UNSET,
+            sca: 0,
+            packing: 0,
+            framing: 0,
+            input: IsoIoQos::default(),
+            output: IsoIoQos::default(),
+        }
+    }
+}
+
+/// The QoS of a Broadcast Isochronous Stream, for both broadcasting and
+/// receiving broadcasts.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsoBroadcastQos {
+    /// The BIG that the BIS belongs to, or [`ISO_UNSET`].
+    pub big: u8,
+    /// The index of the BIS in its BIG, or [`ISO_UNSET`].
+    pub bis: u8,
+    pub sync_factor: u8,
+    pub packing: u8,
+    pub framing: u8,
+    pub input: IsoIoQos,
+    pub output: IsoIoQos,
+    /// Whether the BIG is encrypted with `bcode`.
+    pub encryption: u8,
+    /// The broadcast code.
+    pub bcode: [u8; 16],
+    pub options: u8,
+    pub skip: u16,
+    /// The timeout for synchronizing to the periodic advertising train, in
+    /// units of 10 ms.
+    pub sync_timeout: u16,
+    pub sync_cte_type: u8,
+    /// The maximum number of subevents that are used to receive PDUs.
+    pub mse: u8,
+    /// The timeout for synchronizing to the BIG, in units of 10 ms.
+    pub timeout: u16,
+}
+
+impl Default for IsoBroadcastQos {
+    fn default() -> Self {
+        Self {
+            big: ISO_UNSET,
+            bis: ISO_UNSET,
+            sync_factor: 0x07,
+            packing: 0,
+            framing: 0,
+            input: IsoIoQos::default(),
+            output: IsoIoQos::default(),
+            encryption: 0,
+            bcode: [0; 16],
+            options: 0,
+            skip: 0,
+            sync_timeout: 0x4000,
+            sync_cte_type: 0,
+            mse: 0,
+            timeout: 0x4000,
+        }
+    }
+}
+
+/// The QoS of an isochronous stream, which is set before the stream is
+/// connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoQos {
+    Unicast(IsoUnicastQos),
+    Broadcast(IsoBroadcastQos),
+}
+
+#[repr(C)]
+union bt_iso_qos {
+    ucast: IsoUnicastQos,
+    bcast: IsoBroadcastQos,
+}
+
+fn iso_socket() -> Result<RawFd, std::io::Error> {
+    check_error(unsafe {
+        libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK | libc::SOCK_SEQPACKET,
+            Protocol::ISO as libc::c_int,
+        )
+    })
+}
+
+fn set_qos(fd: RawFd, qos: &IsoQos) -> Result<(), std::io::Error> {
+    let qos = match *qos {
+        IsoQos::Unicast(ucast) => bt_iso_qos { ucast },
+        IsoQos::Broadcast(bcast) => bt_iso_qos { bcast },
+    };
+
+    check_error(unsafe {
+        libc::setsockopt(
+            fd,
+            SOL_BLUETOOTH,
+            BT_ISO_QOS,
+            &qos as *const bt_iso_qos as *const libc::c_void,
+            std::mem::size_of::<bt_iso_qos>() as libc::socklen_t,
+        )
+    })?;
+
+    Ok(())
+}
+
+fn iso_addr(addr: Address, addr_type: AddressType) -> sockaddr_iso {
+    sockaddr_iso {
+        iso_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        iso_bdaddr: addr.into(),
+        iso_bdaddr_type: addr_type.to_socket_u8(),
+        iso_bc: sockaddr_iso_bc {
+            bc_bdaddr: [0; 6],
+            bc_bdaddr_type: 0,
+            bc_sid: 0,
+            bc_num_bis: 0,
+            bc_bis: [0; ISO_MAX_NUM_BIS],
+        },
+    }
+}
+
+/// Runs `f` with a new ISO socket, which is closed if `f` fails.
+fn with_iso_socket<T>(
+    f: impl FnOnce(RawFd) -> Result<T, std::io::Error>,
+) -> Result<(OwnedFd, T), std::io::Error> {
+    let fd = unsafe { OwnedFd::from_raw_fd(iso_socket()?) };
+    let value = f(fd.as_raw_fd())?;
+    Ok((fd, value))
+}
+
+impl BluetoothStream {
+    /// Opens an isochronous stream. With [`IsoQos::Unicast`], this connects a
+    /// CIS to the remote device. With [`IsoQos::Broadcast`], this starts
+    /// broadcasting a BIS, and `addr` should be [`Address::zero`].
+    pub async fn connect_iso(
+        addr: Address,
+        addr_type: AddressType,
+        qos: IsoQos,
+    ) -> Result<Self, std::io::Error> {
+        let (fd, res) = with_iso_socket(|fd| {
+            set_qos(fd, &qos)?;
+
+            let addr = iso_addr(addr, addr_type);
+
+            Ok(check_error(unsafe {
+                libc::connect(
+                    fd,
+                    &addr as *const sockaddr_iso as *const libc::sockaddr,
+                    SOCKADDR_ISO_LEN as u32,
+                )
+            }))
+        })?;
+
+        let fd = finish_connect(fd, res, addr).await?;
+
+        Ok(BluetoothStream {
+            inner: UnixStream::from_std(StdUnixStream::from(fd))?,
+            proto: Protocol::ISO,
+            logger: None,
+        })
+    }
+}
+
+impl BluetoothListener {
+    /// Listens for incoming CISes from remote devices.
+    pub fn bind_iso(
+        addr: Address,
+        addr_type: AddressType,
+        qos: IsoUnicastQos,
+    ) -> Result<Self, std::io::Error> {
+        let (fd, _) = with_iso_socket(|fd| {
+            let addr = iso_addr(addr, addr_type);
+
+            check_error(unsafe {
+                libc::bind(
+                    fd,
+                    &addr as *const sockaddr_iso as *const libc::sockaddr,
+                    SOCKADDR_ISO_LEN as u32,
+                )
+            })?;
+
+            set_qos(fd, &IsoQos::Unicast(qos))?;
+            check_error(unsafe { libc::listen(fd, DEFAULT_BACKLOG) })
+        })?;
+
+        Ok(BluetoothListener {
+            inner: AsyncFd::new(fd)?,
+            proto: Protocol::ISO,
+            policy: None,
+        })
+    }
+
+    /// Synchronizes to the periodic advertising train of the broadcaster
+    /// `broadcaster`, which advertises with the advertising set `sid`, and
+    /// then to the BISes with the indexes in `bis` (up to
+    /// [`ISO_MAX_NUM_BIS`]). The BISes are returned by
+    /// [`accept`](BluetoothListener::accept) once the controller has
+    /// synchronized to them.
+    pub fn bind_iso_broadcast(
+        broadcaster: Address,
+        broadcaster_type: AddressType,
+        sid: u8,
+        bis: &[u8],
+        qos: IsoBroadcastQos,
+    ) -> Result<Self, std::io::Error> {
+        if bis.is_empty() || bis.len() > ISO_MAX_NUM_BIS {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+
+        Self::bind_broadcast(
+            Address::zero(),
+            broadcaster,
+            broadcaster_type,
+            sid,
+            bis,
+            qos,
+        )
+    }
+
+    /// Synchronizes to the periodic advertising train of the broadcaster
+    /// `broadcaster`, which advertises with the advertising set `sid`, but
+    /// not to any of its BISes. The controller with the address `local` is
+    /// used, or any controller if it is [`Address::zero`].
+    ///
+    /// The sync lasts until the listener is dropped. The kernel does not pass
+    /// the periodic advertising reports to ISO sockets, so they are read
+    /// with [`PeriodicSync`](crate::hci::PeriodicSync), which also binds
+    /// this listener.
+    pub fn bind_periodic_sync(
+        local: Address,
+        broadcaster: Address,
+        broadcaster_type: AddressType,
+        sid: u8,
+        qos: IsoBroadcastQos,
+    ) -> Result<Self, std::io::Error> {
+        Self::bind_broadcast(local, broadcaster, broadcaster_type, sid, &[], qos)
+    }
+
+    fn bind_broadcast(
+        local: Address,
+        broadcaster: Address,
+        broadcaster_type: AddressType,
+        sid: u8,
+        bis: &[u8],
+        qos: IsoBroadcastQos,
+    ) -> Result<Self, std::io::Error> {
+        let (fd, _) = with_iso_socket(|fd| {
+            let mut addr = iso_addr(local, AddressType::LEPublic);
+            addr.iso_bc.bc_bdaddr = broadcaster.into();
+            addr.iso_bc.bc_bdaddr_type = broadcaster_type.to_socket_u8();
+            addr.iso_bc.bc_sid = sid;
+            addr.iso_bc.bc_num_bis = bis.len() as u8;
+            addr.iso_bc.bc_bis[..bis.len()].copy_from_slice(bis);
+
+            check_error(unsafe {
+                libc::bind(
+                    fd,
+                    &addr as *const sockaddr_iso as *const libc::sockaddr,
+                    std::mem::size_of::<sockaddr_iso>() as u32,
+                )
+            })?;
+
+            // without BISes to synchronize to, the kernel must not try to
+            // synchronize to the BIG as soon as it learns about it
+            if bis.is_empty() {
+                let defer: u32 = 1;
+
+                check_error(unsafe {
+                    libc::setsockopt(
+                        fd,
+                        SOL_BLUETOOTH,
+                        BT_DEFER_SETUP,
+                        &defer as *const u32 as *const libc::c_void,
+                        std::mem::size_of::<u32>() as libc::socklen_t,
+                    )
+                })?;
+            }
+
+            set_qos(fd, &IsoQos::Broadcast(qos))?;
+            check_error(unsafe { libc::listen(fd, DEFAULT_BACKLOG) })
+        })?;
+
+        Ok(BluetoothListener {
+            inner: AsyncFd::new(fd)?,
+            proto: Protocol::ISO,
+            policy: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_layout() {
+        assert_eq!(std::mem::size_of::<IsoIoQos>(), 12);
+        assert_eq!(std::mem::size_of::<IsoUnicastQos>(), 32);
+        assert_eq!(std::mem::size_of::<IsoBroadcastQos>(), 60);
+        assert_eq!(
+            std::mem::size_of::<sockaddr_iso>(),
+            SOCKADDR_ISO_LEN + std::mem::size_of::<sockaddr_iso_bc>()
+        );
+    }
+}
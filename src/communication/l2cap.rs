@@ -0,0 +1,239 @@
+//! L2CAP signaling commands that are not tied to a channel.
+
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use tokio::io::unix::AsyncFd;
+
+use super::stream::finish_connect;
+use crate::util::check_error;
+use crate::{Address, AddressType, Protocol};
+
+const L2CAP_COMMAND_REJ: u8 = 0x01;
+const L2CAP_ECHO_REQ: u8 = 0x08;
+const L2CAP_ECHO_RSP: u8 = 0x09;
+
+/// The length of the code, identifier and length of a signaling command.
+const L2CAP_CMD_HDR_SIZE: usize = 4;
+
+/// How long [`BluetoothStream::ping`](super::BluetoothStream::ping) waits for
+/// an answer.
+pub(super) const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends an L2CAP Echo Request with `payload` to the BR/EDR device `address`,
+/// like `l2ping` does, and returns the time until the device answered.
+///
+/// The request goes over the signaling channel of the link, so the device
+/// only has to be connectable; a link is created if there is none yet. This
+/// needs the `CAP_NET_RAW` capability. It fails with
+/// [`TimedOut`](std::io::ErrorKind::TimedOut) if the link cannot be created
+/// or no answer arrives within `timeout`, with
+/// [`Unsupported`](std::io::ErrorKind::Unsupported) if the device rejects
+/// the request, and with [`InvalidData`](std::io::ErrorKind::InvalidData) if
+/// it echoes different data.
+pub async fn ping(
+    address: Address,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Duration, std::io::Error> {
+    echo(None, address, payload, timeout).await
+}
+
+/// Sends an echo request from the adapter with the address `local`, or from
+/// any adapter if it is `None`.
+pub(super) async fn echo(
+    local: Option<Address>,
+    remote: Address,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Duration, std::io::Error> {
+    if payload.len() > u16::MAX as usize {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the payload of an echo request is limited to 65535 bytes",
+        ));
+    }
+
+    match tokio::time::timeout(timeout, exchange(local, remote, payload)).await {
+        Ok(res) => res,
+        Err(_) => Err(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+    }
+}
+
+async fn exchange(
+    local: Option<Address>,
+    remote: Address,
+    payload: &[u8],
+) -> Result<Duration, std::io::Error> {
+    let fd = unsafe {
+        OwnedFd::from_raw_fd(check_error(libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            Protocol::L2CAP as libc::c_int,
+        ))?)
+    };
+
+    let sockaddr = |address: Address| bluez_sys::sockaddr_l2 {
+        l2_family: libc::AF_BLUETOOTH as u16,
+        l2_bdaddr: address.into(),
+        l2_bdaddr_type: AddressType::BREDR.to_socket_u8(),
+        l2_psm: 0,
+        l2_cid: 0,
+    };
+    let addr_len = std::mem::size_of::<bluez_sys::sockaddr_l2>() as u32;
+
+    // bind to the adapter of the caller, in case there are several
+    if let Some(local) = local {
+        let local_addr = sockaddr(local);
+        check_error(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &local_addr as *const bluez_sys::sockaddr_l2 as *const libc::sockaddr,
+                addr_len,
+            )
+        })?;
+    }
+
+    let remote_addr = sockaddr(remote);
+    let res = check_error(unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            &remote_addr as *const bluez_sys::sockaddr_l2 as *const libc::sockaddr,
+            addr_len,
+        )
+    });
+
+    let socket = AsyncFd::new(finish_connect(fd, res, remote).await?)?;
+
+    // the same identifier that l2ping starts with
+    let ident = 200;
+    let request = echo_request(ident, payload);
+
+    let start = Instant::now();
+
+    loop {
+        let mut guard = socket.writable().await?;
+
+        match guard.try_io(|fd| {
+            check_error(unsafe {
+                libc::send(
+                    fd.as_raw_fd(),
+                    request.as_ptr() as *const libc::c_void,
+                    request.len(),
+                    libc::MSG_NOSIGNAL,
+                ) as libc::c_int
+            })
+        }) {
+            Ok(res) => {
+                res?;
+                break;
+            }
+            Err(_would_block) => continue,
+        }
+    }
+
+    // room for the echoed payload and for the other commands on the link
+    let mut buf = vec![0u8; request.len().max(64)];
+
+    loop {
+        let mut guard = socket.readable().await?;
+
+        let len = match guard.try_io(|fd| {
+            check_error(unsafe {
+                libc::recv(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                ) as libc::c_int
+            })
+        }) {
+            Ok(len) => len? as usize,
+            Err(_would_block) => continue,
+        };
+
+        if let Some(res) = parse_echo_response(&buf[..len], ident, payload) {
+            return res.map(|()| start.elapsed());
+        }
+    }
+}
+
+/// Encodes an Echo Request signaling command.
+fn echo_request(ident: u8, payload: &[u8]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(L2CAP_CMD_HDR_SIZE + payload.len());
+    request.push(L2CAP_ECHO_REQ);
+    request.push(ident);
+    request.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    request.extend_from_slice(payload);
+    request
+}
+
+/// Checks whether the signaling command `command` answers the echo request
+/// with `ident` and `payload`. Returns `None` for other commands, since the
+/// socket receives every signaling command on the link.
+fn parse_echo_response(
+    command: &[u8],
+    ident: u8,
+    payload: &[u8],
+) -> Option<Result<(), std::io::Error>> {
+    if command.len() < L2CAP_CMD_HDR_SIZE || command[1] != ident {
+        return None;
+    }
+
+    match command[0] {
+        L2CAP_ECHO_RSP if command[L2CAP_CMD_HDR_SIZE..] == *payload => Some(Ok(())),
+        L2CAP_ECHO_RSP => Some(Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the remote device echoed different data",
+        ))),
+        L2CAP_COMMAND_REJ => Some(Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the remote device rejected the echo request",
+        ))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_request_encoding() {
+        assert_eq!(
+            echo_request(200, b"ping"),
+            [0x08, 200, 0x04, 0x00, b'p', b'i', b'n', b'g']
+        );
+        assert_eq!(echo_request(1, b""), [0x08, 1, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn echo_response_matching() {
+        let answer = |command: &[u8]| {
+            parse_echo_response(command, 200, b"ping").map(|res| res.map_err(|err| err.kind()))
+        };
+
+        assert_eq!(
+            answer(&[0x09, 200, 0x04, 0x00, b'p', b'i', b'n', b'g']),
+            Some(Ok(()))
+        );
+        assert_eq!(
+            answer(&[0x09, 200, 0x04, 0x00, b'p', b'o', b'n', b'g']),
+            Some(Err(std::io::ErrorKind::InvalidData))
+        );
+        // a Command Reject with the reason Command Not Understood
+        assert_eq!(
+            answer(&[0x01, 200, 0x02, 0x00, 0x00, 0x00]),
+            Some(Err(std::io::ErrorKind::Unsupported))
+        );
+
+        // the answer to another request, another command, and a truncated
+        // command
+        assert_eq!(
+            answer(&[0x09, 201, 0x04, 0x00, b'p', b'i', b'n', b'g']),
+            None
+        );
+        assert_eq!(answer(&[0x0A, 200, 0x02, 0x00, 0x02, 0x00]), None);
+        assert_eq!(answer(&[0x09, 200]), None);
+    }
+}
@@ -0,0 +1,16 @@
+use super::packet::ResponseCode;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid packet")]
+    InvalidPacket,
+
+    #[error("the remote device answered with {0:?}")]
+    Rejected(ResponseCode),
+
+    #[error("the obex session has been closed")]
+    SessionClosed,
+}
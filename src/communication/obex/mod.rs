@@ -0,0 +1,336 @@
+//! A client for OBEX, the Object Exchange protocol, which the Object Push,
+//! File Transfer and Phone Book Access profiles are built on.
+//!
+//! An [`ObexClient`] runs over an RFCOMM channel or, as described by GOEP
+//! 2.0, over an L2CAP channel. Over L2CAP, the client asks the server for
+//! Single Response Mode, so that objects are sent without waiting for a
+//! response to every packet. GOEP 2.0 servers expect the L2CAP channel to use
+//! Enhanced Retransmission Mode, which [`BluetoothStream`] does not configure,
+//! so [`ObexClient::connect_l2cap`] only works with servers that also accept
+//! Basic Mode. Use RFCOMM otherwise.
+//!
+//! The channel or PSM of a service can be found with
+//! [`ServiceDiscoveryClient`](super::discovery::ServiceDiscoveryClient).
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+use packet::*;
+
+use super::stream::BluetoothStream;
+use crate::{Address, AddressType, Protocol};
+
+mod error;
+pub mod packet;
+
+/// The Target header which selects the folder browsing service of the File
+/// Transfer profile. Object Push does not use a target.
+pub const FILE_TRANSFER_TARGET: [u8; 16] = [
+    0xF9, 0xEC, 0x7B, 0xC4, 0x95, 0x3C, 0x11, 0xD2, 0x98, 0x4E, 0x52, 0x54, 0x00, 0xDC, 0x9E, 0x09,
+];
+
+/// The largest packet that the client accepts.
+const MAX_PACKET_LEN: usize = 0xFFFF;
+
+/// The smallest packet that every OBEX server accepts, which is used until
+/// the server reports its own limit.
+const MIN_PACKET_LEN: usize = 255;
+
+/// An OBEX client, connected to a server on a remote device.
+#[derive(Debug)]
+pub struct ObexClient {
+    stream: BluetoothStream,
+    buf: BytesMut,
+    max_packet_len: usize,
+    connection_id: Option<u32>,
+}
+
+impl ObexClient {
+    /// Creates a client which runs over `stream`, which must be an RFCOMM or
+    /// L2CAP connection to an OBEX server. Call [`ObexClient::connect`]
+    /// before sending other requests.
+    pub fn new(stream: BluetoothStream) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::new(),
+            max_packet_len: MIN_PACKET_LEN,
+            connection_id: None,
+        }
+    }
+
+    /// Opens an RFCOMM channel to a remote device and connects to the OBEX
+    /// service on it.
+    pub async fn connect_rfcomm(
+        address: Address,
+        channel: u8,
+        target: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let stream = BluetoothStream::connect(
+            Protocol::RFCOMM,
+            address,
+            AddressType::BREDR,
+            channel as u16,
+        )
+        .await?;
+
+        let mut client = Self::new(stream);
+        client.connect(target).await?;
+        Ok(client)
+    }
+
+    /// Opens an L2CAP channel to a remote device and connects to the OBEX
+    /// service on it.
+    pub async fn connect_l2cap(
+        address: Address,
+        psm: u16,
+        target: Option<&[u8]>,
+    ) -> Result<Self, Error> {
+        let stream =
+            BluetoothStream::connect(Protocol::L2CAP, address, AddressType::BREDR, psm).await?;
+
+        let mut client = Self::new(stream);
+        client.connect(target).await?;
+        Ok(client)
+    }
+
+    /// Connects to the OBEX service. `target` selects a service on servers
+    /// that have several, such as [`FILE_TRANSFER_TARGET`].
+    pub async fn connect(&mut self, target: Option<&[u8]>) -> Result<(), Error> {
+        let max_packet_len = (MAX_PACKET_LEN as u16).to_be_bytes();
+        let fields = [OBEX_VERSION, 0x00, max_packet_len[0], max_packet_len[1]];
+
+        let mut headers = vec![];
+        if let Some(target) = target {
+            headers.push(Header::target(target));
+        }
+
+        self.send(Opcode::Connect, true, &fields, &headers).await?;
+
+        let response = self.recv(fields.len()).await?;
+        expect(&response, ResponseCode::Success)?;
+
+        let server_max = u16::from_be_bytes([response.fields[2], response.fields[3]]) as usize;
+        self.max_packet_len = server_max.clamp(MIN_PACKET_LEN, MAX_PACKET_LEN);
+        self.connection_id = find_u32(&response.headers, header_id::CONNECTION_ID);
+
+        Ok(())
+    }
+
+    /// Sends an object to the server, such as a vCard to an Object Push
+    /// server or a file to the current folder of a File Transfer server.
+    pub async fn put(
+        &mut self,
+        name: &str,
+        mime_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let mut headers = self.request_headers();
+        headers.push(Header::name(name));
+        if let Some(mime_type) = mime_type {
+            headers.push(Header::mime_type(mime_type));
+        }
+        headers.push(Header::length(body.len() as u32));
+        if self.is_l2cap() {
+            headers.push(Header::single_response_mode(true));
+        }
+
+        let mut body = Bytes::copy_from_slice(body);
+        let mut srm = false;
+
+        loop {
+            // the body header itself takes three bytes
+            let used: usize = headers.iter().map(Header::encoded_len).sum();
+            let room = self
+                .max_packet_len
+                .saturating_sub(PACKET_HEADER_LEN + used + 3);
+
+            let chunk = body.split_to(room.min(body.len()));
+            let last = body.is_empty();
+            headers.push(Header::body(chunk, last));
+
+            self.send(Opcode::Put, last, &[], &headers).await?;
+            headers.clear();
+
+            if last {
+                let response = self.recv(0).await?;
+                return expect(&response, ResponseCode::Success);
+            }
+
+            // in single response mode, the server only answers the first
+            // packet and the last one
+            if !srm {
+                let response = self.recv(0).await?;
+                expect(&response, ResponseCode::Continue)?;
+                srm = srm_enabled(&response);
+            }
+        }
+    }
+
+    /// Retrieves an object from the server. Either `name` or `mime_type`
+    /// has to be given; for example, the folder listing of a File Transfer
+    /// server has the type `x-obex/folder-listing` and no name.
+    pub async fn get(
+        &mut self,
+        name: Option<&str>,
+        mime_type: Option<&str>,
+    ) -> Result<Bytes, Error> {
+        let mut headers = self.request_headers();
+        if let Some(name) = name {
+            headers.push(Header::name(name));
+        }
+        if let Some(mime_type) = mime_type {
+            headers.push(Header::mime_type(mime_type));
+        }
+        if self.is_l2cap() {
+            headers.push(Header::single_response_mode(true));
+        }
+
+        self.send(Opcode::Get, true, &[], &headers).await?;
+
+        let mut body = BytesMut::new();
+        let mut srm = false;
+
+        loop {
+            let response = self.recv(0).await?;
+
+            for header in &response.headers {
+                if let (header_id::BODY | header_id::END_OF_BODY, HeaderValue::Bytes(part)) =
+                    (header.id, &header.value)
+                {
+                    body.extend_from_slice(part);
+                }
+            }
+
+            match response.code {
+                ResponseCode::Success => return Ok(body.freeze()),
+                ResponseCode::Continue => {
+                    srm = srm || srm_enabled(&response);
+
+                    // in single response mode, the server sends the rest of
+                    // the object without being asked
+                    if !srm {
+                        let headers = self.request_headers();
+                        self.send(Opcode::Get, true, &[], &headers).await?;
+                    }
+                }
+                other => return Err(Error::Rejected(other)),
+            }
+        }
+    }
+
+    /// Changes the current folder of the server. `name` enters a subfolder,
+    /// and an empty name returns to the root folder. `backup` first goes up
+    /// to the parent folder. A folder which does not exist is created if
+    /// `create` is set.
+    pub async fn setpath(
+        &mut self,
+        name: Option<&str>,
+        backup: bool,
+        create: bool,
+    ) -> Result<(), Error> {
+        let mut flags = 0;
+        if backup {
+            flags |= 0x01;
+        }
+        if !create {
+            flags |= 0x02;
+        }
+
+        let mut headers = self.request_headers();
+        if let Some(name) = name {
+            headers.push(Header::name(name));
+        }
+
+        self.send(Opcode::SetPath, true, &[flags, 0x00], &headers)
+            .await?;
+
+        let response = self.recv(0).await?;
+        expect(&response, ResponseCode::Success)
+    }
+
+    /// Ends the OBEX session. The transport stays open, and can be taken
+    /// with [`ObexClient::into_inner`].
+    pub async fn disconnect(&mut self) -> Result<(), Error> {
+        let headers = self.request_headers();
+        self.send(Opcode::Disconnect, true, &[], &headers).await?;
+
+        let response = self.recv(0).await?;
+        self.connection_id = None;
+        expect(&response, ResponseCode::Success)
+    }
+
+    /// Returns the transport.
+    pub fn into_inner(self) -> BluetoothStream {
+        self.stream
+    }
+
+    fn is_l2cap(&self) -> bool {
+        self.stream.proto == Protocol::L2CAP
+    }
+
+    /// The headers that every request of the session starts with.
+    fn request_headers(&self) -> Vec<Header> {
+        self.connection_id
+            .map(Header::connection_id)
+            .into_iter()
+            .collect()
+    }
+
+    async fn send(
+        &mut self,
+        opcode: Opcode,
+        last: bool,
+        fields: &[u8],
+        headers: &[Header],
+    ) -> Result<(), Error> {
+        let mut code = u8::from(opcode);
+        if last {
+            code |= FINAL_BIT;
+        }
+
+        let packet = encode_packet(code, fields, headers);
+        self.stream.write_all(&packet[..]).await?;
+        Ok(())
+    }
+
+    /// Receives the next response. RFCOMM may split a packet over several
+    /// reads, so they are collected until the packet is complete.
+    async fn recv(&mut self, fields_len: usize) -> Result<Response, Error> {
+        loop {
+            if self.buf.len() >= PACKET_HEADER_LEN {
+                let len = u16::from_be_bytes([self.buf[1], self.buf[2]]) as usize;
+
+                if len < PACKET_HEADER_LEN {
+                    return Err(Error::InvalidPacket);
+                }
+
+                if self.buf.len() >= len {
+                    let packet = self.buf.split_to(len).freeze();
+                    return Response::parse(packet, fields_len);
+                }
+            }
+
+            // a packet on an L2CAP channel has to be read in one go
+            self.buf.reserve(MAX_PACKET_LEN);
+
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                return Err(Error::SessionClosed);
+            }
+        }
+    }
+}
+
+fn srm_enabled(response: &Response) -> bool {
+    find_u8(&response.headers, header_id::SINGLE_RESPONSE_MODE) == Some(0x01)
+}
+
+/// Checks the code of a response. Any successful code is accepted in place of
+/// [`ResponseCode::Success`].
+fn expect(response: &Response, code: ResponseCode) -> Result<(), Error> {
+    match response.code {
+        c if c == code => Ok(()),
+        c if code == ResponseCode::Success && c.is_success() => Ok(()),
+        other => Err(Error::Rejected(other)),
+    }
+}
@@ -0,0 +1,419 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+
+/// The length of the opcode or response code and the packet length which
+/// start every packet.
+pub const PACKET_HEADER_LEN: usize = 3;
+
+/// The OBEX version that is sent in Connect requests, 1.0.
+pub const OBEX_VERSION: u8 = 0x10;
+
+/// Set on the opcode of the last packet of a request, and on every response
+/// code.
+pub const FINAL_BIT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Connect,
+    Disconnect,
+    Put,
+    Get,
+    SetPath,
+    Abort,
+    Other(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(opcode: u8) -> Self {
+        match opcode & !FINAL_BIT {
+            0x00 => Opcode::Connect,
+            0x01 => Opcode::Disconnect,
+            0x02 => Opcode::Put,
+            0x03 => Opcode::Get,
+            0x05 => Opcode::SetPath,
+            0x7F => Opcode::Abort,
+            opcode => Opcode::Other(opcode),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::Connect => 0x00,
+            Opcode::Disconnect => 0x01,
+            Opcode::Put => 0x02,
+            Opcode::Get => 0x03,
+            Opcode::SetPath => 0x05,
+            Opcode::Abort => 0x7F,
+            Opcode::Other(opcode) => opcode & !FINAL_BIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+    Continue,
+    Success,
+    Created,
+    Accepted,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    NotAcceptable,
+    PreconditionFailed,
+    UnsupportedMediaType,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    DatabaseFull,
+    Other(u8),
+}
+
+impl ResponseCode {
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self,
+            ResponseCode::Success | ResponseCode::Created | ResponseCode::Accepted
+        )
+    }
+}
+
+impl From<u8> for ResponseCode {
+    fn from(code: u8) -> Self {
+        match code & !FINAL_BIT {
+            0x10 => ResponseCode::Continue,
+            0x20 => ResponseCode::Success,
+            0x21 => ResponseCode::Created,
+            0x22 => ResponseCode::Accepted,
+            0x40 => ResponseCode::BadRequest,
+            0x41 => ResponseCode::Unauthorized,
+            0x43 => ResponseCode::Forbidden,
+            0x44 => ResponseCode::NotFound,
+            0x46 => ResponseCode::NotAcceptable,
+            0x4C => ResponseCode::PreconditionFailed,
+            0x4F => ResponseCode::UnsupportedMediaType,
+            0x50 => ResponseCode::InternalServerError,
+            0x51 => ResponseCode::NotImplemented,
+            0x53 => ResponseCode::ServiceUnavailable,
+            0x60 => ResponseCode::DatabaseFull,
+            code => ResponseCode::Other(code),
+        }
+    }
+}
+
+/// The identifiers of the headers that this module uses. The top two bits
+/// of an identifier give the encoding of its value.
+pub mod header_id {
+    pub const NAME: u8 = 0x01;
+    pub const DESCRIPTION: u8 = 0x05;
+    pub const TYPE: u8 = 0x42;
+    pub const TARGET: u8 = 0x46;
+    pub const BODY: u8 = 0x48;
+    pub const END_OF_BODY: u8 = 0x49;
+    pub const WHO: u8 = 0x4A;
+    pub const APP_PARAMETERS: u8 = 0x4C;
+    pub const SINGLE_RESPONSE_MODE: u8 = 0x97;
+    pub const LENGTH: u8 = 0xC3;
+    pub const CONNECTION_ID: u8 = 0xCB;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderValue {
+    /// Text, which is sent as null-terminated UTF-16.
+    Unicode(String),
+    Bytes(Bytes),
+    U8(u8),
+    U32(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub id: u8,
+    pub value: HeaderValue,
+}
+
+impl Header {
+    pub fn name(name: &str) -> Self {
+        Self {
+            id: header_id::NAME,
+            value: HeaderValue::Unicode(name.to_owned()),
+        }
+    }
+
+    /// A Type header, which holds a MIME type such as `text/x-vcard`.
+    pub fn mime_type(mime_type: &str) -> Self {
+        let mut value = BytesMut::with_capacity(mime_type.len() + 1);
+        value.put_slice(mime_type.as_bytes());
+        value.put_u8(0);
+
+        Self {
+            id: header_id::TYPE,
+            value: HeaderValue::Bytes(value.freeze()),
+        }
+    }
+
+    pub fn target(target: &[u8]) -> Self {
+        Self {
+            id: header_id::TARGET,
+            value: HeaderValue::Bytes(Bytes::copy_from_slice(target)),
+        }
+    }
+
+    pub fn length(length: u32) -> Self {
+        Self {
+            id: header_id::LENGTH,
+            value: HeaderValue::U32(length),
+        }
+    }
+
+    pub fn connection_id(connection_id: u32) -> Self {
+        Self {
+            id: header_id::CONNECTION_ID,
+            value: HeaderValue::U32(connection_id),
+        }
+    }
+
+    /// Asks the server to enable or disable Single Response Mode, in which it
+    /// only answers the last packet of a Put and sends every packet of a Get
+    /// without waiting for requests. This is only used over L2CAP.
+    pub fn single_response_mode(enable: bool) -> Self {
+        Self {
+            id: header_id::SINGLE_RESPONSE_MODE,
+            value: HeaderValue::U8(enable as u8),
+        }
+    }
+
+    /// A part of the body of an object. `last` selects the End of Body
+    /// header, which marks the last part.
+    pub fn body(body: Bytes, last: bool) -> Self {
+        Self {
+            id: if last {
+                header_id::END_OF_BODY
+            } else {
+                header_id::BODY
+            },
+            value: HeaderValue::Bytes(body),
+        }
+    }
+
+    /// The number of bytes that this header takes up in a packet.
+    pub fn encoded_len(&self) -> usize {
+        match &self.value {
+            HeaderValue::Unicode(text) if text.is_empty() => 3,
+            HeaderValue::Unicode(text) => 3 + 2 * (text.encode_utf16().count() + 1),
+            HeaderValue::Bytes(bytes) => 3 + bytes.len(),
+            HeaderValue::U8(_) => 2,
+            HeaderValue::U32(_) => 5,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut impl BufMut) {
+        buf.put_u8(self.id);
+
+        match &self.value {
+            HeaderValue::Unicode(text) => {
+                buf.put_u16(self.encoded_len() as u16);
+
+                // an empty name is sent without a terminator
+                if !text.is_empty() {
+                    for unit in text.encode_utf16() {
+                        buf.put_u16(unit);
+                    }
+                    buf.put_u16(0);
+                }
+            }
+            HeaderValue::Bytes(bytes) => {
+                buf.put_u16(self.encoded_len() as u16);
+                buf.put_slice(&bytes[..]);
+            }
+            HeaderValue::U8(value) => buf.put_u8(*value),
+            HeaderValue::U32(value) => buf.put_u32(*value),
+        }
+    }
+
+    /// Parses the headers that make up the rest of a packet.
+    pub fn parse_list(mut buf: Bytes) -> Result<Vec<Self>, Error> {
+        let mut headers = vec![];
+
+        while buf.has_remaining() {
+            let id = buf.get_u8();
+
+            let value = match id >> 6 {
+                0b00 | 0b01 => {
+                    if buf.remaining() < 2 {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    let len = buf.get_u16() as usize;
+                    if len < 3 || buf.remaining() < len - 3 {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    let value = buf.split_to(len - 3);
+
+                    if id >> 6 == 0b01 {
+                        HeaderValue::Bytes(value)
+                    } else {
+                        let units: Vec<u16> = value
+                            .chunks_exact(2)
+                            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                            .take_while(|&unit| unit != 0)
+                            .collect();
+                        HeaderValue::Unicode(String::from_utf16_lossy(&units))
+                    }
+                }
+                0b10 => {
+                    if !buf.has_remaining() {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    HeaderValue::U8(buf.get_u8())
+                }
+                _ => {
+                    if buf.remaining() < 4 {
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    HeaderValue::U32(buf.get_u32())
+                }
+            };
+
+            headers.push(Self { id, value });
+        }
+
+        Ok(headers)
+    }
+}
+
+/// A response from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub code: ResponseCode,
+    /// The fixed fields which the response to Connect has before its headers.
+    pub fields: Bytes,
+    pub headers: Vec<Header>,
+}
+
+impl Response {
+    /// Parses a complete response packet. `fields_len` is the length of the
+    /// fixed fields, which depends on the request that is answered.
+    pub fn parse(mut packet: Bytes, fields_len: usize) -> Result<Self, Error> {
+        if packet.len() < PACKET_HEADER_LEN + fields_len {
+            return Err(Error::InvalidPacket);
+        }
+
+        let code = ResponseCode::from(packet.get_u8());
+        let len = packet.get_u16() as usize;
+
+        if len != packet.len() + PACKET_HEADER_LEN {
+            return Err(Error::InvalidPacket);
+        }
+
+        let fields = packet.split_to(fields_len);
+
+        Ok(Self {
+            code,
+            fields,
+            headers: Header::parse_list(packet)?,
+        })
+    }
+}
+
+/// Finds the value of a byte sequence header.
+pub fn find_bytes(headers: &[Header], id: u8) -> Option<&Bytes> {
+    headers.iter().find_map(|header| match &header.value {
+        HeaderValue::Bytes(bytes) if header.id == id => Some(bytes),
+        _ => None,
+    })
+}
+
+/// Finds the value of a one byte header.
+pub fn find_u8(headers: &[Header], id: u8) -> Option<u8> {
+    headers.iter().find_map(|header| match header.value {
+        HeaderValue::U8(value) if header.id == id => Some(value),
+        _ => None,
+    })
+}
+
+/// Finds the value of a four byte header.
+pub fn find_u32(headers: &[Header], id: u8) -> Option<u32> {
+    headers.iter().find_map(|header| match header.value {
+        HeaderValue::U32(value) if header.id == id => Some(value),
+        _ => None,
+    })
+}
+
+/// Encodes a packet. `fields` are the fixed fields which some opcodes have
+/// before their headers.
+pub fn encode_packet(code: u8, fields: &[u8], headers: &[Header]) -> Bytes {
+    let len =
+        PACKET_HEADER_LEN + fields.len() + headers.iter().map(Header::encoded_len).sum::<usize>();
+
+    let mut buf = BytesMut::with_capacity(len);
+    buf.put_u8(code);
+    buf.put_u16(len as u16);
+    buf.put_slice(fields);
+
+    for header in headers {
+        header.encode(&mut buf);
+    }
+
+    buf.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_request() {
+        let packet = encode_packet(
+            u8::from(Opcode::Put) | FINAL_BIT,
+            &[],
+            &[
+                Header::connection_id(1),
+                Header::name("a.vcf"),
+                Header::length(2),
+                Header::body(Bytes::from_static(b"hi"), true),
+            ],
+        );
+
+        #[rustfmt::skip]
+        assert_eq!(&packet[..], &[
+            0x82, 0x00, 0x21,
+            0xCB, 0x00, 0x00, 0x00, 0x01,
+            0x01, 0x00, 0x0F, 0x00, b'a', 0x00, b'.', 0x00, b'v', 0x00, b'c', 0x00, b'f', 0x00, 0x00,
+            0xC3, 0x00, 0x00, 0x00, 0x02,
+            0x49, 0x00, 0x05, b'h', b'i',
+        ][..]);
+
+        let headers = Header::parse_list(packet.slice(PACKET_HEADER_LEN..)).unwrap();
+        assert_eq!(headers[1], Header::name("a.vcf"));
+        assert_eq!(find_u32(&headers, header_id::LENGTH), Some(2));
+        assert_eq!(
+            find_bytes(&headers, header_id::END_OF_BODY).map(|b| &b[..]),
+            Some(&b"hi"[..])
+        );
+    }
+
+    #[test]
+    fn connect_response() {
+        #[rustfmt::skip]
+        let packet = Bytes::from_static(&[
+            0xA0, 0x00, 0x0C,
+            0x10, 0x00, 0x20, 0x00,
+            0xCB, 0x12, 0x34, 0x56, 0x78,
+        ]);
+
+        let response = Response::parse(packet, 4).unwrap();
+        assert_eq!(response.code, ResponseCode::Success);
+        assert_eq!(&response.fields[..], &[0x10, 0x00, 0x20, 0x00]);
+        assert_eq!(
+            find_u32(&response.headers, header_id::CONNECTION_ID),
+            Some(0x12345678)
+        );
+    }
+}
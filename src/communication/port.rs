@@ -0,0 +1,165 @@
+//! Checked ports for L2CAP and RFCOMM sockets.
+
+use crate::{AddressType, Protocol};
+
+/// The error that is returned when a socket is connected or bound to a port
+/// that is not valid for its protocol, such as an even PSM on BR/EDR or
+/// RFCOMM channel 31. Like [`UnsupportedProtocol`](super::UnsupportedProtocol),
+/// it is returned inside of an [`std::io::Error`] of kind
+/// [`InvalidInput`](std::io::ErrorKind::InvalidInput) by the socket methods
+/// that take a bare port.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("{port:#06x} is not a valid port for {protocol:?}")]
+pub struct InvalidPort {
+    pub protocol: Protocol,
+    pub port: u16,
+}
+
+impl From<InvalidPort> for std::io::Error {
+    fn from(err: InvalidPort) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// A Protocol/Service Multiplexer, which is the port of an L2CAP channel.
+///
+/// On BR/EDR, a PSM has to be odd and the lowest bit of its upper byte has
+/// to be clear. Values up to 0x0FFF are assigned by the Bluetooth SIG, and
+/// 0x1001 to 0xFFFF are dynamic. On LE, PSMs are between 0x0001 and 0x00FF,
+/// and 0x0080 to 0x00FF are dynamic.
+///
+/// [`Psm::dynamic`] asks the kernel to pick a free dynamic PSM when a
+/// listener is bound; the PSM that it picked can be read with
+/// [`BluetoothListener::local_addr`](super::BluetoothListener::local_addr).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Psm(u16);
+
+impl Psm {
+    /// Checks that `psm` is valid on the transport of `address_type`.
+    pub fn new(psm: u16, address_type: AddressType) -> Result<Self, InvalidPort> {
+        let valid = match address_type {
+            AddressType::BREDR => psm & 0x0101 == 0x0001,
+            _ => (0x0001..=0x00FF).contains(&psm),
+        };
+
+        if valid {
+            Ok(Self(psm))
+        } else {
+            Err(InvalidPort {
+                protocol: Protocol::L2CAP,
+                port: psm,
+            })
+        }
+    }
+
+    /// A placeholder which makes the kernel pick a free dynamic PSM when a
+    /// listener is bound. It cannot be connected to.
+    pub const fn dynamic() -> Self {
+        Self(0)
+    }
+
+    /// Whether this is a PSM from the dynamic range of the transport of
+    /// `address_type`, rather than one assigned by the Bluetooth SIG.
+    pub fn is_dynamic(self, address_type: AddressType) -> bool {
+        match address_type {
+            AddressType::BREDR => self.0 >= 0x1001,
+            _ => self.0 >= 0x0080,
+        }
+    }
+
+    pub fn value(self) -> u16 {
+        self.0
+    }
+}
+
+impl From<Psm> for u16 {
+    fn from(psm: Psm) -> Self {
+        psm.0
+    }
+}
+
+/// An RFCOMM server channel, between 1 and 30.
+///
+/// [`RfcommChannel::any`] asks the kernel to pick a free channel when a
+/// listener is bound, as
+/// [`BluetoothListener::bind_rfcomm_auto`](super::BluetoothListener::bind_rfcomm_auto)
+/// does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RfcommChannel(u8);
+
+impl RfcommChannel {
+    pub fn new(channel: u8) -> Result<Self, InvalidPort> {
+        if (1..=30).contains(&channel) {
+            Ok(Self(channel))
+        } else {
+            Err(InvalidPort {
+                protocol: Protocol::RFCOMM,
+                port: channel as u16,
+            })
+        }
+    }
+
+    /// A placeholder which makes the kernel pick a free channel when a
+    /// listener is bound. It cannot be connected to.
+    pub const fn any() -> Self {
+        Self(0)
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<RfcommChannel> for u16 {
+    fn from(channel: RfcommChannel) -> Self {
+        channel.0 as u16
+    }
+}
+
+/// Checks the bare `port` that a socket of `proto` is connected or bound to.
+/// Port 0 is only allowed when binding, where it makes the kernel pick one.
+pub(super) fn check_port(
+    proto: Protocol,
+    address_type: AddressType,
+    port: u16,
+    bind: bool,
+) -> Result<(), InvalidPort> {
+    if port == 0 && bind {
+        return Ok(());
+    }
+
+    match proto {
+        Protocol::L2CAP => Psm::new(port, address_type).map(|_| ()),
+        Protocol::RFCOMM if port <= u8::MAX as u16 => RfcommChannel::new(port as u8).map(|_| ()),
+        Protocol::RFCOMM => Err(InvalidPort {
+            protocol: proto,
+            port,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn psm_ranges() {
+        assert!(Psm::new(0x0019, AddressType::BREDR).is_ok());
+        assert!(Psm::new(0x1001, AddressType::BREDR)
+            .unwrap()
+            .is_dynamic(AddressType::BREDR));
+        // even, and the upper byte is odd
+        assert!(Psm::new(0x1002, AddressType::BREDR).is_err());
+        assert!(Psm::new(0x0101, AddressType::BREDR).is_err());
+
+        assert!(Psm::new(0x0080, AddressType::LEPublic)
+            .unwrap()
+            .is_dynamic(AddressType::LEPublic));
+        assert!(Psm::new(0x1001, AddressType::LERandom).is_err());
+
+        assert!(check_port(Protocol::L2CAP, AddressType::BREDR, 0, true).is_ok());
+        assert!(check_port(Protocol::L2CAP, AddressType::BREDR, 0, false).is_err());
+        assert!(check_port(Protocol::RFCOMM, AddressType::BREDR, 31, false).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the remote device sent an invalid frame")]
+    InvalidFrame,
+
+    #[error("the remote device sent a frame with an invalid checksum")]
+    InvalidChecksum,
+
+    #[error("the remote device refused to open dlci {0}")]
+    Refused(u8),
+
+    #[error("dlci {0} is not open")]
+    NotOpen(u8),
+
+    #[error("the rfcomm session has been closed")]
+    SessionClosed,
+}
@@ -0,0 +1,384 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::Error;
+
+/// The type of an RFCOMM frame, as encoded in the control field (with the
+/// P/F bit cleared).
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    /// Set Asynchronous Balanced Mode, used to open a DLC.
+    Sabm = 0x2F,
+    /// Unnumbered Acknowledgement.
+    Ua = 0x63,
+    /// Disconnected Mode, used to refuse a DLC.
+    Dm = 0x0F,
+    /// Disconnect, used to close a DLC.
+    Disc = 0x43,
+    /// Unnumbered Information with Header check, which carries data.
+    Uih = 0xEF,
+}
+
+const PF_BIT: u8 = 0x10;
+
+impl FrameType {
+    fn from_control(control: u8) -> Option<Self> {
+        match control & !PF_BIT {
+            0x2F => Some(FrameType::Sabm),
+            0x63 => Some(FrameType::Ua),
+            0x0F => Some(FrameType::Dm),
+            0x43 => Some(FrameType::Disc),
+            0xEF => Some(FrameType::Uih),
+            _ => None,
+        }
+    }
+}
+
+/// A single RFCOMM frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub dlci: u8,
+    /// The command/response bit of the address field.
+    pub cr: bool,
+    pub frame_type: FrameType,
+    /// The poll/final bit of the control field.
+    pub poll_final: bool,
+    /// Credits granted to the receiver of this frame. Only valid for UIH
+    /// frames on DLCs which use credit based flow control.
+    pub credits: Option<u8>,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    pub fn new(dlci: u8, cr: bool, frame_type: FrameType, poll_final: bool) -> Self {
+        Self {
+            dlci,
+            cr,
+            frame_type,
+            poll_final,
+            credits: None,
+            payload: Bytes::new(),
+        }
+    }
+
+    pub fn uih(dlci: u8, cr: bool, credits: Option<u8>, payload: Bytes) -> Self {
+        Self {
+            dlci,
+            cr,
+            frame_type: FrameType::Uih,
+            poll_final: credits.is_some(),
+            credits,
+            payload,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.payload.len() + 6);
+
+        buf.put_u8((self.dlci << 2) | ((self.cr as u8) << 1) | 0x01);
+        buf.put_u8(self.frame_type as u8 | if self.poll_final { PF_BIT } else { 0 });
+
+        let len = self.payload.len();
+        if len <= 0x7F {
+            buf.put_u8(((len as u8) << 1) | 0x01);
+        } else {
+            buf.put_u8(((len & 0x7F) as u8) << 1);
+            buf.put_u8((len >> 7) as u8);
+        }
+
+        // the checksum of UIH frames only covers the address and control
+        // fields; all other frames also include the length
+        let fcs = match self.frame_type {
+            FrameType::Uih => fcs(&buf[..2]),
+            _ => fcs(&buf[..]),
+        };
+
+        if let Some(credits) = self.credits {
+            buf.put_u8(credits);
+        }
+
+        buf.put_slice(&self.payload[..]);
+        buf.put_u8(fcs);
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < 4 {
+            return Err(Error::InvalidFrame);
+        }
+
+        let fcs_byte = buf[buf.len() - 1];
+        buf.truncate(buf.len() - 1);
+
+        let address = buf[0];
+        let control = buf[1];
+        let frame_type = FrameType::from_control(control).ok_or(Error::InvalidFrame)?;
+        let header_len = if buf[2] & 0x01 == 0x01 { 3 } else { 4 };
+
+        if buf.len() < header_len {
+            return Err(Error::InvalidFrame);
+        }
+
+        let expected_fcs = match frame_type {
+            FrameType::Uih => fcs(&buf[..2]),
+            _ => fcs(&buf[..header_len]),
+        };
+
+        if expected_fcs != fcs_byte {
+            return Err(Error::InvalidChecksum);
+        }
+
+        let len = if header_len == 3 {
+            (buf[2] >> 1) as usize
+        } else {
+            ((buf[2] >> 1) as usize) | ((buf[3] as usize) << 7)
+        };
+
+        buf.advance(header_len);
+
+        let dlci = address >> 2;
+        let poll_final = control & PF_BIT != 0;
+
+        // credits are only present in UIH frames with the P/F bit set, and
+        // never on the multiplexer control channel
+        let credits = if frame_type == FrameType::Uih && poll_final && dlci != 0 {
+            if !buf.has_remaining() {
+                return Err(Error::InvalidFrame);
+            }
+
+            Some(buf.get_u8())
+        } else {
+            None
+        };
+
+        if buf.len() != len {
+            return Err(Error::InvalidFrame);
+        }
+
+        Ok(Self {
+            dlci,
+            cr: address & 0x02 != 0,
+            frame_type,
+            poll_final,
+            credits,
+            payload: buf,
+        })
+    }
+}
+
+/// The type of a multiplexer control message, which is sent in UIH frames on
+/// DLCI 0.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlType {
+    /// DLC parameter negotiation
+    Pn = 0x20,
+    /// Power saving control
+    Psc = 0x10,
+    /// Multiplexer close down
+    Cld = 0x30,
+    /// Test
+    Test = 0x08,
+    /// Flow control on
+    Fcon = 0x28,
+    /// Flow control off
+    Fcoff = 0x18,
+    /// Modem status
+    Msc = 0x38,
+    /// Non-supported command response
+    Nsc = 0x04,
+    /// Remote port negotiation
+    Rpn = 0x24,
+    /// Remote line status
+    Rls = 0x14,
+}
+
+impl ControlType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0x20 => ControlType::Pn,
+            0x10 => ControlType::Psc,
+            0x30 => ControlType::Cld,
+            0x08 => ControlType::Test,
+            0x28 => ControlType::Fcon,
+            0x18 => ControlType::Fcoff,
+            0x38 => ControlType::Msc,
+            0x04 => ControlType::Nsc,
+            0x24 => ControlType::Rpn,
+            0x14 => ControlType::Rls,
+            _ => return None,
+        })
+    }
+}
+
+/// A multiplexer control message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlMessage {
+    /// The raw type field (without the EA and C/R bits), which may not be a
+    /// known [`ControlType`].
+    pub raw_type: u8,
+    /// Whether this is a command (as opposed to a response).
+    pub command: bool,
+    pub value: Bytes,
+}
+
+impl ControlMessage {
+    pub fn new(control_type: ControlType, command: bool, value: Bytes) -> Self {
+        Self {
+            raw_type: control_type as u8,
+            command,
+            value,
+        }
+    }
+
+    pub fn control_type(&self) -> Option<ControlType> {
+        ControlType::from_u8(self.raw_type)
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.value.len() + 3);
+        buf.put_u8((self.raw_type << 2) | ((self.command as u8) << 1) | 0x01);
+
+        let len = self.value.len();
+        if len <= 0x7F {
+            buf.put_u8(((len as u8) << 1) | 0x01);
+        } else {
+            buf.put_u8(((len & 0x7F) as u8) << 1);
+            buf.put_u8((len >> 7) as u8);
+        }
+
+        buf.put_slice(&self.value[..]);
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < 2 {
+            return Err(Error::InvalidFrame);
+        }
+
+        let type_byte = buf.get_u8();
+        let mut len = 0usize;
+        let mut shift = 0;
+
+        loop {
+            if !buf.has_remaining() || shift > 14 {
+                return Err(Error::InvalidFrame);
+            }
+
+            let byte = buf.get_u8();
+            len |= ((byte >> 1) as usize) << shift;
+            shift += 7;
+
+            if byte & 0x01 == 0x01 {
+                break;
+            }
+        }
+
+        if buf.len() < len {
+            return Err(Error::InvalidFrame);
+        }
+
+        Ok(Self {
+            raw_type: type_byte >> 2,
+            command: type_byte & 0x02 != 0,
+            value: buf.split_to(len),
+        })
+    }
+}
+
+/// The values carried by a DLC parameter negotiation message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterNegotiation {
+    pub dlci: u8,
+    /// The convergence layer field. `0xF0` in a command requests credit
+    /// based flow control, and `0xE0` in a response accepts it.
+    pub convergence_layer: u8,
+    pub priority: u8,
+    pub mtu: u16,
+    /// The initial number of credits granted to the receiver.
+    pub credits: u8,
+}
+
+impl ParameterNegotiation {
+    pub const CFC_REQUEST: u8 = 0xF0;
+    pub const CFC_ACCEPT: u8 = 0xE0;
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(8);
+        buf.put_u8(self.dlci & 0x3F);
+        buf.put_u8(self.convergence_layer);
+        buf.put_u8(self.priority & 0x3F);
+        buf.put_u8(0); // acknowledgement timer, unused
+        buf.put_u16_le(self.mtu);
+        buf.put_u8(0); // maximum number of retransmissions, unused
+        buf.put_u8(self.credits & 0x07);
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.len() < 8 {
+            return Err(Error::InvalidFrame);
+        }
+
+        let dlci = buf.get_u8() & 0x3F;
+        let convergence_layer = buf.get_u8() & 0xF0;
+        let priority = buf.get_u8() & 0x3F;
+        buf.advance(1);
+        let mtu = buf.get_u16_le();
+        buf.advance(1);
+        let credits = buf.get_u8() & 0x07;
+
+        Ok(Self {
+            dlci,
+            convergence_layer,
+            priority,
+            mtu,
+            credits,
+        })
+    }
+}
+
+/// The frame check sequence defined in TS 07.10: a reversed CRC-8 with the
+/// polynomial x^8 + x^2 + x + 1.
+pub fn fcs(data: &[u8]) -> u8 {
+    let crc = data.iter().fold(0xFFu8, |crc, byte| {
+        let mut crc = crc ^ byte;
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 {
+                (crc >> 1) ^ 0xE0
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    });
+
+    0xFF - crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_frames() {
+        let sabm = Frame::new(0, true, FrameType::Sabm, true);
+        assert_eq!(&sabm.encode()[..], &[0x03, 0x3F, 0x01, 0x1C]);
+
+        let ua = Frame::new(0, true, FrameType::Ua, true);
+        assert_eq!(&ua.encode()[..], &[0x03, 0x73, 0x01, 0xD7]);
+
+        assert_eq!(Frame::parse(ua.encode()).unwrap(), ua);
+    }
+
+    #[test]
+    fn uih_round_trip() {
+        let frame = Frame::uih(2, true, Some(7), Bytes::from(vec![0xAA; 200]));
+        let parsed = Frame::parse(frame.encode()).unwrap();
+        assert_eq!(parsed, frame);
+
+        let mut corrupted = frame.encode().to_vec();
+        corrupted[0] ^= 0x04;
+        assert!(Frame::parse(Bytes::from(corrupted)).is_err());
+    }
+}
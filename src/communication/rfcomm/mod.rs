@@ -0,0 +1,683 @@
+//! A user space implementation of the RFCOMM multiplexer, running on top of a
+//! raw L2CAP connection.
+//!
+//! The kernel already provides RFCOMM sockets, which can be used through
+//! [`BluetoothStream`] with [`Protocol::RFCOMM`]. This module is
+//! useful on systems where the kernel RFCOMM module is unavailable, or when
+//! several channels need to be multiplexed over one link by the same process
+//! (for example when implementing the server side of a multi-channel
+//! profile).
+//!
+//! An [`RfcommSession`] does not spawn any tasks: incoming frames are only
+//! processed while one of its methods is being awaited. Call
+//! [`RfcommSession::recv`] in a loop to keep the session going.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use error::Error;
+use frame::*;
+
+use super::stream::{BluetoothListener, BluetoothStream};
+use crate::{Address, AddressType, Protocol};
+
+mod error;
+pub mod frame;
+
+pub const RFCOMM_PSM: u16 = 0x0003;
+
+/// The maximum frame size that is proposed when opening a channel. This is
+/// the default L2CAP MTU minus the largest RFCOMM header.
+pub const DEFAULT_MTU: u16 = 667;
+
+/// The maximum frame size that is used if a channel is opened without
+/// parameter negotiation.
+const FALLBACK_MTU: u16 = 127;
+
+/// The number of credits that are granted to the remote device when a channel
+/// is opened, which is also the maximum that can be negotiated.
+const INITIAL_CREDITS: u8 = 7;
+
+/// V.24 signals sent in Modem Status commands: EA, RTC, RTR and DV set.
+const MODEM_STATUS: u8 = 0x8D;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RfcommEvent {
+    /// The remote device opened a channel on one of the server channels that
+    /// this session is listening on.
+    Opened { dlci: u8 },
+    /// Data was received on a channel.
+    Data { dlci: u8, data: Bytes },
+    /// The remote device closed a channel.
+    Closed { dlci: u8 },
+    /// The remote device closed the whole session. No further events will be
+    /// received.
+    SessionClosed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChannelState {
+    Negotiating,
+    Negotiated,
+    Opening,
+    Open,
+    Refused,
+    Closing,
+}
+
+#[derive(Debug)]
+struct Channel {
+    state: ChannelState,
+    mtu: u16,
+    /// Whether credit based flow control is used on this channel.
+    cfc: bool,
+    /// The number of frames that may still be sent to the remote device.
+    tx_credits: u16,
+    /// The number of frames that the remote device may still send.
+    rx_credits: u16,
+}
+
+impl Channel {
+    fn new(state: ChannelState) -> Self {
+        Self {
+            state,
+            mtu: FALLBACK_MTU,
+            cfc: false,
+            tx_credits: 0,
+            rx_credits: 0,
+        }
+    }
+}
+
+/// An RFCOMM multiplexer session with a remote device. Each session can carry
+/// up to 30 channels, which are identified by their DLCI (data link
+/// connection identifier).
+pub struct RfcommSession {
+    stream: BluetoothStream,
+    /// Whether this side opened the session. This determines the C/R bits of
+    /// all frames and the direction bit of the DLCIs opened by this side.
+    initiator: bool,
+    open: bool,
+    closed: bool,
+    /// Set when the remote device sent a Flow Control Off command.
+    flow_stopped: bool,
+    channels: HashMap<u8, Channel>,
+    server_channels: HashSet<u8>,
+    events: VecDeque<RfcommEvent>,
+}
+
+impl RfcommSession {
+    /// Opens an RFCOMM session with a remote device.
+    pub async fn connect(address: Address) -> Result<Self, Error> {
+        let stream =
+            BluetoothStream::connect(Protocol::L2CAP, address, AddressType::BREDR, RFCOMM_PSM)
+                .await?;
+
+        let mut session = Self::new(stream, true);
+        session
+            .send_frame(Frame::new(0, true, FrameType::Sabm, true))
+            .await?;
+
+        while !session.open {
+            session.process().await?;
+
+            if session.closed {
+                return Err(Error::Refused(0));
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Creates a session from an L2CAP connection that a remote device opened
+    /// on [`RFCOMM_PSM`]. The multiplexer is started once the remote device
+    /// requests it, which happens while the session is processing frames.
+    pub fn accept(stream: BluetoothStream) -> Self {
+        Self::new(stream, false)
+    }
+
+    /// Creates a listener for incoming RFCOMM sessions on the local adapter
+    /// with the given address. Use [`RfcommSession::accept`] on the streams it
+    /// returns.
+    pub fn bind(address: Address) -> Result<BluetoothListener, Error> {
+        Ok(BluetoothListener::bind(
+            Protocol::L2CAP,
+            address,
+            AddressType::BREDR,
+            RFCOMM_PSM,
+        )?)
+    }
+
+    fn new(stream: BluetoothStream, initiator: bool) -> Self {
+        Self {
+            stream,
+            initiator,
+            open: false,
+            closed: false,
+            flow_stopped: false,
+            channels: HashMap::new(),
+            server_channels: HashSet::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Allows the remote device to open channels on `server_channel`. Opened
+    /// channels are reported through [`RfcommEvent::Opened`].
+    pub fn listen(&mut self, server_channel: u8) {
+        self.server_channels.insert(server_channel & 0x1F);
+    }
+
+    /// Stops accepting new channels on `server_channel`. Channels which are
+    /// already open are not affected.
+    pub fn unlisten(&mut self, server_channel: u8) {
+        self.server_channels.remove(&(server_channel & 0x1F));
+    }
+
+    /// Listens on the lowest server channel that this session is not
+    /// listening on yet, and returns it, or `None` if all 30 channels are
+    /// taken. As with the kernel's
+    /// [`bind_rfcomm_auto`](BluetoothListener::bind_rfcomm_auto), the channel
+    /// has to be published in an SDP record for clients to find it.
+    pub fn listen_auto(&mut self) -> Option<u8> {
+        let server_channel = (1..=30).find(|c| !self.server_channels.contains(c))?;
+        self.server_channels.insert(server_channel);
+        Some(server_channel)
+    }
+
+    /// The server channels that this session is listening on, in ascending
+    /// order.
+    pub fn server_channels(&self) -> Vec<u8> {
+        let mut server_channels: Vec<u8> = self.server_channels.iter().copied().collect();
+        server_channels.sort_unstable();
+        server_channels
+    }
+
+    /// Opens a channel to a server channel on the remote device, and returns
+    /// the DLCI of the new channel.
+    pub async fn open(&mut self, server_channel: u8) -> Result<u8, Error> {
+        let dlci = ((server_channel & 0x1F) << 1) | (!self.initiator as u8);
+
+        if self.channels.contains_key(&dlci) {
+            return Err(Error::Refused(dlci));
+        }
+
+        self.channels
+            .insert(dlci, Channel::new(ChannelState::Negotiating));
+
+        let pn = ParameterNegotiation {
+            dlci,
+            convergence_layer: ParameterNegotiation::CFC_REQUEST,
+            priority: 7,
+            mtu: DEFAULT_MTU,
+            credits: INITIAL_CREDITS,
+        };
+
+        self.send_control(ControlMessage::new(ControlType::Pn, true, pn.encode()))
+            .await?;
+        self.wait_for_state(dlci, ChannelState::Negotiating).await?;
+
+        // a device that does not support parameter negotiation answers with
+        // a non-supported command response, in which case the defaults apply
+        if let Some(channel) = self.channels.get_mut(&dlci) {
+            channel.state = ChannelState::Opening;
+        }
+
+        self.send_frame(Frame::new(dlci, self.initiator, FrameType::Sabm, true))
+            .await?;
+        self.wait_for_state(dlci, ChannelState::Opening).await?;
+
+        match self.channels.get(&dlci).map(|c| c.state) {
+            Some(ChannelState::Open) => {}
+            _ => {
+                self.channels.remove(&dlci);
+                return Err(Error::Refused(dlci));
+            }
+        }
+
+        self.send_modem_status(dlci).await?;
+
+        Ok(dlci)
+    }
+
+    /// Sends data on an open channel. The data is split into multiple frames
+    /// if it is larger than the frame size of the channel. If the remote device
+    /// has not granted enough credits, incoming frames are processed until it
+    /// does.
+    pub async fn send(&mut self, dlci: u8, data: &[u8]) -> Result<(), Error> {
+        let mtu = match self.channels.get(&dlci) {
+            Some(channel) if channel.state == ChannelState::Open => channel.mtu as usize,
+            _ => return Err(Error::NotOpen(dlci)),
+        };
+
+        for chunk in data.chunks(mtu.max(1)) {
+            loop {
+                let channel = self.channels.get(&dlci).ok_or(Error::NotOpen(dlci))?;
+
+                if channel.state != ChannelState::Open {
+                    return Err(Error::NotOpen(dlci));
+                }
+
+                let blocked = if channel.cfc {
+                    channel.tx_credits == 0
+                } else {
+                    self.flow_stopped
+                };
+
+                if !blocked {
+                    break;
+                }
+
+                self.process().await?;
+            }
+
+            let channel = self.channels.get_mut(&dlci).unwrap();
+
+            if channel.cfc {
+                channel.tx_credits -= 1;
+            }
+
+            // grant new credits along with the data if the remote device is
+            // about to run out
+            let credits = Self::take_credit_grant(channel);
+
+            self.send_frame(Frame::uih(
+                dlci,
+                self.initiator,
+                credits,
+                Bytes::copy_from_slice(chunk),
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the next event on this session, while answering the frames
+    /// that the multiplexer handles on its own.
+    pub async fn recv(&mut self) -> Result<RfcommEvent, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+
+            self.process().await?;
+        }
+    }
+
+    /// Closes a channel.
+    pub async fn close(&mut self, dlci: u8) -> Result<(), Error> {
+        match self.channels.get_mut(&dlci) {
+            Some(channel) if channel.state == ChannelState::Open => {
+                channel.state = ChannelState::Closing;
+            }
+            _ => return Err(Error::NotOpen(dlci)),
+        }
+
+        self.send_frame(Frame::new(dlci, self.initiator, FrameType::Disc, true))
+            .await?;
+        self.wait_for_state(dlci, ChannelState::Closing).await?;
+        self.channels.remove(&dlci);
+
+        Ok(())
+    }
+
+    /// Closes all channels and then the session itself.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+
+        let open: Vec<u8> = self
+            .channels
+            .iter()
+            .filter(|(_, c)| c.state == ChannelState::Open)
+            .map(|(dlci, _)| *dlci)
+            .collect();
+
+        for dlci in open {
+            self.close(dlci).await?;
+        }
+
+        self.send_frame(Frame::new(0, self.initiator, FrameType::Disc, true))
+            .await?;
+
+        while self.open && !self.closed {
+            match self.process().await {
+                Ok(()) => {}
+                Err(Error::SessionClosed) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// Returns the maximum frame size of an open channel.
+    pub fn mtu(&self, dlci: u8) -> Option<u16> {
+        self.channels
+            .get(&dlci)
+            .filter(|c| c.state == ChannelState::Open)
+            .map(|c| c.mtu)
+    }
+
+    async fn wait_for_state(&mut self, dlci: u8, state: ChannelState) -> Result<(), Error> {
+        while self.channels.get(&dlci).map(|c| c.state) == Some(state) {
+            self.process().await?;
+        }
+
+        if self.closed {
+            return Err(Error::SessionClosed);
+        }
+
+        Ok(())
+    }
+
+    fn take_credit_grant(channel: &mut Channel) -> Option<u8> {
+        if channel.cfc && channel.rx_credits <= INITIAL_CREDITS as u16 / 2 {
+            let grant = INITIAL_CREDITS as u16 - channel.rx_credits;
+            channel.rx_credits += grant;
+            Some(grant as u8)
+        } else {
+            None
+        }
+    }
+
+    async fn send_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        self.stream.write_all(&frame.encode()[..]).await?;
+        Ok(())
+    }
+
+    async fn send_control(&mut self, msg: ControlMessage) -> Result<(), Error> {
+        self.send_frame(Frame::uih(0, self.initiator, None, msg.encode()))
+            .await
+    }
+
+    async fn send_modem_status(&mut self, dlci: u8) -> Result<(), Error> {
+        let mut value = BytesMut::with_capacity(2);
+        value.put_u8((dlci << 2) | 0x03);
+        value.put_u8(MODEM_STATUS);
+
+        self.send_control(ControlMessage::new(ControlType::Msc, true, value.freeze()))
+            .await
+    }
+
+    /// Reads one frame from the remote device and handles it.
+    async fn process(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::SessionClosed);
+        }
+
+        let mut buf = BytesMut::with_capacity(DEFAULT_MTU as usize + 8);
+
+        if self.stream.read_buf(&mut buf).await? == 0 {
+            self.close_session();
+            return Ok(());
+        }
+
+        let frame = Frame::parse(buf.freeze())?;
+
+        if frame.dlci == 0 {
+            self.handle_mux_frame(frame).await
+        } else {
+            self.handle_channel_frame(frame).await
+        }
+    }
+
+    fn close_session(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.open = false;
+            self.channels.clear();
+            self.events.push_back(RfcommEvent::SessionClosed);
+        }
+    }
+
+    async fn handle_mux_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        match frame.frame_type {
+            FrameType::Sabm => {
+                if self.initiator {
+                    self.send_frame(Frame::new(0, !self.initiator, FrameType::Dm, true))
+                        .await?;
+                } else {
+                    self.open = true;
+                    self.send_frame(Frame::new(0, !self.initiator, FrameType::Ua, true))
+                        .await?;
+                }
+            }
+            FrameType::Ua => {
+                if self.open {
+                    // answer to our disconnect request
+                    self.close_session();
+                } else {
+                    self.open = true;
+                }
+            }
+            FrameType::Dm => self.close_session(),
+            FrameType::Disc => {
+                self.send_frame(Frame::new(0, !self.initiator, FrameType::Ua, true))
+                    .await?;
+                self.close_session();
+            }
+            FrameType::Uih => {
+                let msg = ControlMessage::parse(frame.payload)?;
+                self.handle_control(msg).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_channel_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        let dlci = frame.dlci;
+        let state = self.channels.get(&dlci).map(|c| c.state);
+
+        match frame.frame_type {
+            FrameType::Sabm => {
+                let accept = self.open
+                    && self.server_channels.contains(&(dlci >> 1))
+                    && !matches!(state, Some(ChannelState::Open));
+
+                if !accept {
+                    return self
+                        .send_frame(Frame::new(dlci, !self.initiator, FrameType::Dm, true))
+                        .await;
+                }
+
+                let channel = self
+                    .channels
+                    .entry(dlci)
+                    .or_insert_with(|| Channel::new(ChannelState::Negotiated));
+                channel.state = ChannelState::Open;
+
+                self.send_frame(Frame::new(dlci, !self.initiator, FrameType::Ua, true))
+                    .await?;
+                self.send_modem_status(dlci).await?;
+                self.events.push_back(RfcommEvent::Opened { dlci });
+            }
+            FrameType::Ua => match state {
+                Some(ChannelState::Opening) => {
+                    self.channels.get_mut(&dlci).unwrap().state = ChannelState::Open;
+                }
+                Some(ChannelState::Closing) => {
+                    self.channels.remove(&dlci);
+                }
+                _ => {}
+            },
+            FrameType::Dm => match state {
+                Some(ChannelState::Opening) => {
+                    self.channels.get_mut(&dlci).unwrap().state = ChannelState::Refused;
+                }
+                Some(ChannelState::Open) => {
+                    self.channels.remove(&dlci);
+                    self.events.push_back(RfcommEvent::Closed { dlci });
+                }
+                Some(_) => {
+                    self.channels.remove(&dlci);
+                }
+                None => {}
+            },
+            FrameType::Disc => {
+                self.send_frame(Frame::new(dlci, !self.initiator, FrameType::Ua, true))
+                    .await?;
+
+                if let Some(channel) = self.channels.remove(&dlci) {
+                    if channel.state == ChannelState::Open {
+                        self.events.push_back(RfcommEvent::Closed { dlci });
+                    }
+                }
+            }
+            FrameType::Uih => {
+                let channel = match self.channels.get_mut(&dlci) {
+                    Some(channel) if channel.state == ChannelState::Open => channel,
+                    _ => {
+                        return self
+                            .send_frame(Frame::new(dlci, !self.initiator, FrameType::Dm, true))
+                            .await
+                    }
+                };
+
+                if let Some(credits) = frame.credits {
+                    channel.tx_credits = channel.tx_credits.saturating_add(credits as u16);
+                }
+
+                if frame.payload.is_empty() {
+                    return Ok(());
+                }
+
+                if channel.cfc {
+                    channel.rx_credits = channel.rx_credits.saturating_sub(1);
+                }
+
+                let credits = Self::take_credit_grant(channel);
+
+                self.events.push_back(RfcommEvent::Data {
+                    dlci,
+                    data: frame.payload,
+                });
+
+                if credits.is_some() {
+                    self.send_frame(Frame::uih(dlci, self.initiator, credits, Bytes::new()))
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_control(&mut self, msg: ControlMessage) -> Result<(), Error> {
+        let control_type = match msg.control_type() {
+            Some(control_type) => control_type,
+            None => {
+                if msg.command {
+                    let value = Bytes::copy_from_slice(&[(msg.raw_type << 2) | 0x03]);
+                    self.send_control(ControlMessage::new(ControlType::Nsc, false, value))
+                        .await?;
+                }
+
+                return Ok(());
+            }
+        };
+
+        if !msg.command {
+            match control_type {
+                ControlType::Pn => {
+                    let pn = ParameterNegotiation::parse(msg.value)?;
+
+                    if let Some(channel) = self.channels.get_mut(&pn.dlci) {
+                        if channel.state == ChannelState::Negotiating {
+                            channel.state = ChannelState::Negotiated;
+                            channel.mtu = pn.mtu.min(DEFAULT_MTU);
+                            channel.cfc = pn.convergence_layer == ParameterNegotiation::CFC_ACCEPT;
+
+                            if channel.cfc {
+                                channel.tx_credits = pn.credits as u16;
+                                channel.rx_credits = INITIAL_CREDITS as u16;
+                            }
+                        }
+                    }
+                }
+                ControlType::Nsc => {
+                    // the remote device does not support parameter
+                    // negotiation, so fall back to the defaults
+                    for channel in self.channels.values_mut() {
+                        if channel.state == ChannelState::Negotiating {
+                            channel.state = ChannelState::Negotiated;
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            return Ok(());
+        }
+
+        let response = match control_type {
+            ControlType::Pn => {
+                let request = ParameterNegotiation::parse(msg.value)?;
+                let cfc = request.convergence_layer == ParameterNegotiation::CFC_REQUEST;
+
+                let mut channel = Channel::new(ChannelState::Negotiated);
+                channel.mtu = request.mtu.clamp(1, DEFAULT_MTU);
+                channel.cfc = cfc;
+
+                if cfc {
+                    channel.tx_credits = request.credits as u16;
+                    channel.rx_credits = INITIAL_CREDITS as u16;
+                }
+
+                let response = ParameterNegotiation {
+                    dlci: request.dlci,
+                    convergence_layer: if cfc {
+                        ParameterNegotiation::CFC_ACCEPT
+                    } else {
+                        0
+                    },
+                    priority: request.priority,
+                    mtu: channel.mtu,
+                    credits: if cfc { INITIAL_CREDITS } else { 0 },
+                };
+
+                // parameters may only be negotiated before the channel is
+                // opened
+                if !matches!(
+                    self.channels.get(&request.dlci).map(|c| c.state),
+                    Some(ChannelState::Open)
+                ) {
+                    self.channels.insert(request.dlci, channel);
+                }
+
+                response.encode()
+            }
+            ControlType::Rpn if msg.value.len() == 1 => {
+                // a request for the current settings: 9600 baud, 8N1, no flow
+                // control
+                Bytes::copy_from_slice(&[msg.value[0], 0x03, 0x03, 0x00, 0x11, 0x13, 0xFF, 0x3F])
+            }
+            ControlType::Fcon => {
+                self.flow_stopped = false;
+                Bytes::new()
+            }
+            ControlType::Fcoff => {
+                self.flow_stopped = true;
+                Bytes::new()
+            }
+            ControlType::Cld => {
+                self.send_control(ControlMessage::new(ControlType::Cld, false, Bytes::new()))
+                    .await?;
+                self.close_session();
+                return Ok(());
+            }
+            ControlType::Nsc => return Ok(()),
+            // modem status, remote port settings, line status, power saving
+            // and test commands are answered with the value that was sent
+            _ => msg.value,
+        };
+
+        self.send_control(ControlMessage::new(control_type, false, response))
+            .await
+    }
+}
@@ -6,32 +6,490 @@ use std::os::unix::net::UnixStream as StdUnixStream;
 
 use libc;
 use num_traits::FromPrimitive;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
 use tokio::io::unix::AsyncFd;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, WriteHalf};
+use tokio::net::unix::{self as tokio_unix, OwnedWriteHalf, WriteHalf};
 use tokio::net::UnixStream;
 
+use super::l2cap::{echo, ECHO_TIMEOUT};
+use super::port::{check_port, Psm, RfcommChannel};
+use crate::capture::{AttachedLogger, Direction, PacketLogger};
+use crate::management::{get_controller_info, ClassOfDevice, Controller, ManagementStream};
 use crate::util::check_error;
 use crate::{Address, AddressType, Protocol};
 
 union SockAddr {
     l2: bluez_sys::sockaddr_l2,
     rc: bluez_sys::sockaddr_rc,
+    iso: super::iso::sockaddr_iso,
+}
+
+/// The error that is returned when a socket is created with a [`Protocol`]
+/// that it does not support, such as [`Protocol::HCI`] for a
+/// [`BluetoothStream`]. It is returned inside of an [`std::io::Error`] of
+/// kind [`InvalidInput`](std::io::ErrorKind::InvalidInput), and can be
+/// retrieved using [`std::io::Error::get_ref`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("bluetooth protocol {protocol:?} cannot be used with {socket}")]
+pub struct UnsupportedProtocol {
+    pub protocol: Protocol,
+    /// The name of the socket type, such as `"BluetoothListener"`.
+    pub socket: &'static str,
+}
+
+impl From<UnsupportedProtocol> for std::io::Error {
+    fn from(err: UnsupportedProtocol) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// The error that is returned when a connection could not be established
+/// because the remote device did not answer, for example because it is out
+/// of range or turned off. It is returned inside of an [`std::io::Error`]
+/// with the same kind as `source`, and can be retrieved using
+/// [`std::io::Error::get_ref`].
+#[derive(Error, Debug)]
+#[error("device {address} is unreachable")]
+pub struct DeviceUnreachable {
+    pub address: Address,
+    /// The error that the kernel reported, which is usually `ETIMEDOUT`
+    /// or `EHOSTDOWN`.
+    #[source]
+    pub source: std::io::Error,
+}
+
+/// Converts the errors which mean that the remote device did not answer into
+/// a [`DeviceUnreachable`], and leaves other errors unchanged.
+fn connect_error(err: std::io::Error, address: Address) -> std::io::Error {
+    match err.raw_os_error() {
+        Some(libc::ETIMEDOUT) | Some(libc::EHOSTDOWN) | Some(libc::EHOSTUNREACH) => {
+            std::io::Error::new(
+                err.kind(),
+                DeviceUnreachable {
+                    address,
+                    source: err,
+                },
+            )
+        }
+        _ => err,
+    }
+}
+
+/// Waits until a non-blocking `connect` on `fd`, which returned `res`, has
+/// completed, and checks whether it was successful. `fd` is closed if the
+/// connection fails or if the future is dropped.
+pub(super) async fn finish_connect(
+    fd: OwnedFd,
+    res: Result<libc::c_int, std::io::Error>,
+    address: Address,
+) -> Result<OwnedFd, std::io::Error> {
+    match res {
+        Ok(_) => return Ok(fd),
+        // should always get EINPROGRESS if socket is initialized using SOCK_NONBLOCK
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) => return Err(connect_error(err, address)),
+    }
+
+    // wait until the file descriptor becomes writeable
+    let afd = AsyncFd::new(fd)?;
+    let _ = afd.writable().await?;
+    let fd = afd.into_inner();
+
+    let mut error: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    check_error(unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut _ as *mut _,
+            &mut len,
+        )
+    })?;
+
+    if error != 0 {
+        return Err(connect_error(
+            std::io::Error::from_raw_os_error(error),
+            address,
+        ));
+    }
+
+    Ok(fd)
+}
+
+const L2CAP_CONNINFO: libc::c_int = 0x02;
+const SOL_RFCOMM: libc::c_int = 18;
+const RFCOMM_CONNINFO: libc::c_int = 0x02;
+
+/// The layout of both `l2cap_conninfo` and `rfcomm_conninfo`.
+#[repr(C)]
+struct conninfo {
+    hci_handle: u16,
+    dev_class: [u8; 3],
+}
+
+/// The HCI connection that a [`BluetoothStream`] runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The handle of the ACL or LE connection, which identifies it in HCI
+    /// commands and events and in `btmon` traces.
+    pub handle: u16,
+    /// The class of device that the remote device reported when the
+    /// connection was created. This is empty for LE connections.
+    pub class: ClassOfDevice,
+}
+
+const SOL_BLUETOOTH: libc::c_int = 274;
+const BT_SECURITY: libc::c_int = 4;
+const BT_SNDMTU: libc::c_int = 12;
+const BT_RCVMTU: libc::c_int = 13;
+const BT_MODE: libc::c_int = 15;
+const L2CAP_OPTIONS: libc::c_int = 0x01;
+
+/// The mode of an L2CAP channel, which decides how it is segmented and how
+/// its flow is controlled. The mode has to be chosen before the channel is
+/// connected, see [`BluetoothStream::connect_l2cap_mode`].
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum L2capMode {
+    Basic = 0x00,
+    EnhancedRetransmission = 0x01,
+    Streaming = 0x02,
+    /// LE Credit Based Flow Control, which is what LE channels use unless
+    /// another mode is chosen.
+    LeFlowControl = 0x03,
+    /// Enhanced Credit Based Flow Control, which Enhanced ATT bearers use.
+    /// The kernel only supports it if the `enable_ecred` parameter of the
+    /// `bluetooth` module is set.
+    ExtendedFlowControl = 0x04,
+}
+
+#[repr(C)]
+struct bt_security {
+    level: u8,
+    key_size: u8,
+}
+
+/// The security level of a connection, which decides how the kernel
+/// authenticates and encrypts the link that it runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecurityLevel {
+    /// Only allowed for SDP. The link is not encrypted.
+    Sdp,
+    /// The link is not encrypted, except that Secure Simple Pairing
+    /// encrypts BR/EDR links anyway.
+    Low,
+    /// The link is encrypted, but its key may not be authenticated.
+    Medium,
+    /// The link is encrypted with an authenticated key.
+    High,
+    /// The link is encrypted with an authenticated key which was created by
+    /// Secure Connections with a 128-bit key.
+    Fips,
+    Other(u8),
+}
+
+impl SecurityLevel {
+    /// The value that the kernel uses for this level in `BT_SECURITY`.
+    pub fn value(self) -> u8 {
+        match self {
+            SecurityLevel::Sdp => 0,
+            SecurityLevel::Low => 1,
+            SecurityLevel::Medium => 2,
+            SecurityLevel::High => 3,
+            SecurityLevel::Fips => 4,
+            SecurityLevel::Other(level) => level,
+        }
+    }
+}
+
+impl From<u8> for SecurityLevel {
+    fn from(level: u8) -> Self {
+        match level {
+            0 => SecurityLevel::Sdp,
+            1 => SecurityLevel::Low,
+            2 => SecurityLevel::Medium,
+            3 => SecurityLevel::High,
+            4 => SecurityLevel::Fips,
+            level => SecurityLevel::Other(level),
+        }
+    }
+}
+
+/// The security of the link that a [`BluetoothStream`] runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityInfo {
+    pub level: SecurityLevel,
+    /// The size of the encryption key in bytes, between 7 and 16, or 0 if the
+    /// link is not encrypted. The kernel does not report this for RFCOMM
+    /// sockets, where it is always 0.
+    pub key_size: u8,
+}
+
+impl SecurityInfo {
+    /// Whether the link is encrypted. Always `false` for RFCOMM sockets, see
+    /// [`SecurityInfo::key_size`].
+    pub fn is_encrypted(&self) -> bool {
+        self.key_size > 0
+    }
+}
+
+/// The security that a [`BluetoothListener`] requires from incoming
+/// connections, see [`BluetoothListener::set_security_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityPolicy {
+    /// The lowest security level that connections are accepted with.
+    pub level: SecurityLevel,
+    /// Requires a key that is protected against man-in-the-middle attacks,
+    /// which raises the level to at least [`SecurityLevel::High`].
+    pub require_mitm: bool,
+    /// Requires a key that was created by Secure Connections, which raises
+    /// the level to [`SecurityLevel::Fips`] and the key size to 16 bytes.
+    pub secure_connections_only: bool,
+}
+
+impl SecurityPolicy {
+    /// The security level that is requested from the kernel for this policy.
+    pub fn required_level(&self) -> SecurityLevel {
+        let mut level = self.level.value();
+
+        if self.require_mitm {
+            level = level.max(SecurityLevel::High.value());
+        }
+
+        if self.secure_connections_only {
+            level = level.max(SecurityLevel::Fips.value());
+        }
+
+        level.into()
+    }
+
+    /// Whether a connection with the security `info` over `proto` satisfies
+    /// this policy. The key size is only checked for protocols that report
+    /// it, see [`SecurityInfo::key_size`].
+    pub fn is_satisfied_by(&self, proto: Protocol, info: &SecurityInfo) -> bool {
+        let required = self.required_level().value();
+
+        if info.level.value() < required {
+            return false;
+        }
+
+        if proto == Protocol::RFCOMM {
+            return true;
+        }
+
+        if required >= SecurityLevel::Medium.value() && !info.is_encrypted() {
+            return false;
+        }
+
+        !self.secure_connections_only || info.key_size == 16
+    }
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        Self {
+            level: SecurityLevel::Low,
+            require_mitm: false,
+            secure_connections_only: false,
+        }
+    }
+}
+
+/// The error that is returned by [`BluetoothListener::accept`] when a device
+/// connected with less security than the [`SecurityPolicy`] of the listener
+/// requires. The connection is closed, and the listener can still be used.
+/// It is returned inside of an [`std::io::Error`] of kind
+/// [`PermissionDenied`](std::io::ErrorKind::PermissionDenied).
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("device {address} connected with {actual:?}, which does not satisfy {required:?}")]
+pub struct SecurityDowngrade {
+    pub address: Address,
+    pub required: SecurityPolicy,
+    pub actual: SecurityInfo,
+}
+
+impl From<SecurityDowngrade> for std::io::Error {
+    fn from(err: SecurityDowngrade) -> Self {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, err)
+    }
+}
+
+/// Reads the `BT_SECURITY` option of `fd`.
+fn get_security(fd: RawFd) -> Result<SecurityInfo, std::io::Error> {
+    let mut security = MaybeUninit::<bt_security>::zeroed();
+    let mut len = std::mem::size_of::<bt_security>() as libc::socklen_t;
+
+    check_error(unsafe {
+        libc::getsockopt(
+            fd,
+            SOL_BLUETOOTH,
+            BT_SECURITY,
+            security.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+
+    let security = unsafe { security.assume_init() };
+
+    Ok(SecurityInfo {
+        level: security.level.into(),
+        key_size: security.key_size,
+    })
+}
+
+/// Returns the protocol of the Bluetooth socket `fd`, or an error if `fd` is
+/// not a Bluetooth socket.
+fn socket_protocol(fd: RawFd) -> Result<Protocol, std::io::Error> {
+    let mut optval: libc::c_int = 0;
+    let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    check_error(unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_DOMAIN,
+            &mut optval as *mut _ as *mut _,
+            &mut optlen as *mut _,
+        )
+    })?;
+
+    if optval != libc::AF_BLUETOOTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "socket domain is not bluetooth",
+        ));
+    }
+
+    let mut optval: libc::c_int = 0;
+    let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    check_error(unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PROTOCOL,
+            &mut optval as *mut _ as *mut _,
+            &mut optlen as *mut _,
+        )
+    })?;
+
+    FromPrimitive::from_i32(optval).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "socket has invalid protocol",
+        )
+    })
+}
+
+fn set_nonblocking(fd: RawFd) -> Result<(), std::io::Error> {
+    let flags = check_error(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    check_error(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+    Ok(())
+}
+
+/// The number of connections that the kernel queues for a
+/// [`BluetoothListener`] until they are accepted, unless it is changed with
+/// [`BluetoothListener::set_backlog`].
+pub const DEFAULT_BACKLOG: libc::c_int = 128;
+
+/// The address of one end of a Bluetooth socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BtSockAddr {
+    pub address: Address,
+    /// RFCOMM sockets are always [`AddressType::BREDR`].
+    pub address_type: AddressType,
+    pub protocol: Protocol,
+    /// The PSM for L2CAP, the channel for RFCOMM, and 0 for ISO, which does
+    /// not have ports.
+    pub port: u16,
+}
+
+impl BtSockAddr {
+    fn from_raw(proto: Protocol, addr: &SockAddr) -> Self {
+        let (address, address_type, port) = match proto {
+            Protocol::L2CAP => unsafe {
+                (
+                    addr.l2.l2_bdaddr.into(),
+                    AddressType::from_socket_u8(addr.l2.l2_bdaddr_type),
+                    addr.l2.l2_psm,
+                )
+            },
+            Protocol::RFCOMM => unsafe {
+                (
+                    addr.rc.rc_bdaddr.into(),
+                    AddressType::BREDR,
+                    addr.rc.rc_channel as u16,
+                )
+            },
+            Protocol::ISO => unsafe {
+                (
+                    addr.iso.iso_bdaddr.into(),
+                    AddressType::from_socket_u8(addr.iso.iso_bdaddr_type),
+                    0,
+                )
+            },
+            _ => unreachable!(),
+        };
+
+        BtSockAddr {
+            address,
+            address_type,
+            protocol: proto,
+            port,
+        }
+    }
+}
+
+fn sockaddr_len(proto: Protocol) -> libc::socklen_t {
+    (match proto {
+        Protocol::L2CAP => std::mem::size_of::<bluez_sys::sockaddr_l2>(),
+        Protocol::RFCOMM => std::mem::size_of::<bluez_sys::sockaddr_rc>(),
+        Protocol::ISO => std::mem::size_of::<super::iso::sockaddr_iso>(),
+        _ => unreachable!(),
+    }) as libc::socklen_t
+}
+
+/// Gets the address of a socket using `getsockname` or `getpeername`.
+fn get_sockaddr(
+    fd: RawFd,
+    proto: Protocol,
+    get_name: unsafe extern "C" fn(
+        libc::c_int,
+        *mut libc::sockaddr,
+        *mut libc::socklen_t,
+    ) -> libc::c_int,
+) -> Result<BtSockAddr, std::io::Error> {
+    let mut addr: SockAddr = unsafe { std::mem::zeroed() };
+    let mut addr_len = sockaddr_len(proto);
+
+    check_error(unsafe { get_name(fd, &mut addr as *mut _ as *mut _, &mut addr_len) })?;
+
+    Ok(BtSockAddr::from_raw(proto, &addr))
 }
 
 /// A Bluetooth socket which can accept connections from remote Bluetooth
 /// devices. You can accept new connections using the
 /// [`accept`](`BluetoothListener::accept`) method.
 pub struct BluetoothListener {
-    inner: AsyncFd<RawFd>,
-    proto: Protocol,
+    pub(super) inner: AsyncFd<OwnedFd>,
+    pub(super) proto: Protocol,
+    pub(super) policy: Option<SecurityPolicy>,
 }
 
 impl BluetoothListener {
     /// Creates a new `BluetoothListener` bound to the specified address, port, and protocol.
+    /// A `port` of 0 makes the kernel pick a free PSM or channel, as
+    /// [`bind_rfcomm_auto`](BluetoothListener::bind_rfcomm_auto) does. Other
+    /// ports are checked with [`Psm::new`] and [`RfcommChannel::new`], and an
+    /// [`InvalidPort`](super::InvalidPort) is returned if they are not valid.
     pub fn bind(
         proto: Protocol,
         addr: Address,
@@ -41,19 +499,16 @@ impl BluetoothListener {
         let flags = match proto {
             Protocol::L2CAP => libc::SOCK_SEQPACKET,
             Protocol::RFCOMM => libc::SOCK_STREAM,
-            other => panic!(
-                "bluetooth protocol {:?} cannot be used with BluetoothListener",
-                other
-            ),
+            other => {
+                return Err(UnsupportedProtocol {
+                    protocol: other,
+                    socket: "BluetoothListener",
+                }
+                .into())
+            }
         };
 
-        let fd: RawFd = check_error(unsafe {
-            libc::socket(
-                libc::AF_BLUETOOTH,
-                libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK | flags,
-                proto as libc::c_int,
-            )
-        })?;
+        check_port(proto, addr_type, port, true)?;
 
         let (addr, addr_len) = match proto {
             Protocol::L2CAP => (
@@ -61,7 +516,7 @@ impl BluetoothListener {
                     l2: bluez_sys::sockaddr_l2 {
                         l2_family: libc::AF_BLUETOOTH as u16,
                         l2_bdaddr: addr.into(),
-                        l2_bdaddr_type: addr_type as u8,
+                        l2_bdaddr_type: addr_type.to_socket_u8(),
                         l2_psm: port,
                         l2_cid: 0,
                     },
@@ -81,44 +536,145 @@ impl BluetoothListener {
             _ => unreachable!(),
         };
 
-        if let Err(err) = check_error(unsafe {
-            libc::bind(
-                fd,
-                &addr as *const SockAddr as *const libc::sockaddr,
-                addr_len as u32,
-            )
-        }) {
-            unsafe {
-                libc::close(fd);
-            }
+        Self::bind_sockaddr(proto, flags, &addr, addr_len)
+    }
 
-            return Err(err);
-        }
+    /// Creates a listener on the fixed ATT channel of LE links, on which
+    /// centrals reach the GATT server of the adapter with the address `addr`,
+    /// such as a [`GattServer`](super::att::GattServer).
+    ///
+    /// Only one socket can listen on the channel of an adapter, so this fails
+    /// with [`AddrInUse`](std::io::ErrorKind::AddrInUse) while bluetoothd
+    /// serves GATT on it.
+    pub fn bind_att(addr: Address) -> Result<Self, std::io::Error> {
+        let sockaddr = SockAddr {
+            l2: bluez_sys::sockaddr_l2 {
+                l2_family: libc::AF_BLUETOOTH as u16,
+                l2_bdaddr: addr.into(),
+                l2_bdaddr_type: AddressType::LEPublic.to_socket_u8(),
+                l2_psm: 0,
+                l2_cid: super::ATT_CID,
+            },
+        };
 
-        if let Err(err) = check_error(unsafe { libc::listen(fd, 128) }) {
-            unsafe {
-                libc::close(fd);
-            }
+        Self::bind_sockaddr(
+            Protocol::L2CAP,
+            libc::SOCK_SEQPACKET,
+            &sockaddr,
+            std::mem::size_of::<bluez_sys::sockaddr_l2>(),
+        )
+    }
 
-            return Err(err);
-        }
+    /// Creates a socket of type `flags`, binds it to `addr` and starts
+    /// listening on it.
+    fn bind_sockaddr(
+        proto: Protocol,
+        flags: libc::c_int,
+        addr: &SockAddr,
+        addr_len: usize,
+    ) -> Result<Self, std::io::Error> {
+        // the socket is closed when this is dropped, so every error closes it
+        let fd = unsafe {
+            OwnedFd::from_raw_fd(check_error(libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK | flags,
+                proto as libc::c_int,
+            ))?)
+        };
+
+        check_error(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                addr as *const SockAddr as *const libc::sockaddr,
+                addr_len as u32,
+            )
+        })?;
+        check_error(unsafe { libc::listen(fd.as_raw_fd(), DEFAULT_BACKLOG) })?;
 
         Ok(BluetoothListener {
             inner: AsyncFd::new(fd)?,
             proto,
+            policy: None,
         })
     }
 
+    /// Creates an L2CAP listener on `psm`, which may be [`Psm::dynamic`].
+    pub fn bind_l2cap(
+        addr: Address,
+        addr_type: AddressType,
+        psm: Psm,
+    ) -> Result<Self, std::io::Error> {
+        Self::bind(Protocol::L2CAP, addr, addr_type, psm.into())
+    }
+
+    /// Creates an RFCOMM listener on `channel`, which may be
+    /// [`RfcommChannel::any`].
+    pub fn bind_rfcomm(addr: Address, channel: RfcommChannel) -> Result<Self, std::io::Error> {
+        Self::bind(Protocol::RFCOMM, addr, AddressType::BREDR, channel.into())
+    }
+
+    /// Creates an RFCOMM listener on a free server channel of the adapter
+    /// with the address `addr`.
+    ///
+    /// The kernel picks the lowest channel that no other listener on the
+    /// adapter uses when the socket starts listening, and this fails if all 30
+    /// channels are taken. Read the channel with
+    /// [`local_addr`](BluetoothListener::local_addr) and publish it in the
+    /// SDP record of the service, since clients have no other way to find it.
+    /// The channel is freed when the listener is dropped.
+    pub fn bind_rfcomm_auto(addr: Address) -> Result<Self, std::io::Error> {
+        Self::bind(Protocol::RFCOMM, addr, AddressType::BREDR, 0)
+    }
+
+    /// Creates a new `BluetoothListener` bound to the controller with the
+    /// index `controller`, such as 0 for `hci0`. The address of the
+    /// controller is looked up with
+    /// [`get_controller_info`](crate::management::get_controller_info).
+    pub async fn bind_controller(
+        proto: Protocol,
+        controller: Controller,
+        addr_type: AddressType,
+        port: u16,
+    ) -> Result<Self, crate::Error> {
+        let mut socket = ManagementStream::open()?;
+        let info = get_controller_info(&mut socket, controller, None).await?;
+
+        Ok(Self::bind(proto, info.address, addr_type, port)?)
+    }
+
     /// Accepts a new incoming connection to this listener. Upon success,
     /// returns the connection, the address of the remote device, and the remote
     /// port.
     pub async fn accept(&self) -> Result<(BluetoothStream, (Address, u16)), std::io::Error> {
+        let (sock, addr) = self.accept_sockaddr().await?;
+        Ok((sock, (addr.address, addr.port)))
+    }
+
+    /// Accepts new incoming connections until one is accepted by `filter`,
+    /// and returns it like [`accept`](BluetoothListener::accept).
+    /// Connections that are rejected by `filter` are closed immediately.
+    ///
+    /// This can be used for simple access control, such as only accepting
+    /// connections from paired devices on an RFCOMM server.
+    pub async fn accept_filtered(
+        &self,
+        mut filter: impl FnMut(&BtSockAddr) -> bool,
+    ) -> Result<(BluetoothStream, (Address, u16)), std::io::Error> {
+        loop {
+            let (sock, addr) = self.accept_sockaddr().await?;
+
+            if filter(&addr) {
+                return Ok((sock, (addr.address, addr.port)));
+            }
+        }
+    }
+
+    /// Accepts a new incoming connection to this listener, and returns the
+    /// connection and the address of the remote device, including its
+    /// address type.
+    pub async fn accept_sockaddr(&self) -> Result<(BluetoothStream, BtSockAddr), std::io::Error> {
         let mut addr: SockAddr = unsafe { std::mem::zeroed() };
-        let mut addr_len = match self.proto {
-            Protocol::L2CAP => std::mem::size_of::<bluez_sys::sockaddr_l2>(),
-            Protocol::RFCOMM => std::mem::size_of::<bluez_sys::sockaddr_rc>(),
-            _ => unreachable!(),
-        } as u32;
+        let mut addr_len = sockaddr_len(self.proto);
 
         let fd = loop {
             let res = self.inner.readable().await?.try_io(|_fd| {
@@ -137,44 +693,105 @@ impl BluetoothListener {
             }
         };
 
-        let addr = match self.proto {
-            Protocol::L2CAP => unsafe { (addr.l2.l2_bdaddr.into(), addr.l2.l2_psm) },
-            Protocol::RFCOMM => unsafe { (addr.rc.rc_bdaddr.into(), addr.rc.rc_channel as u16) },
-            _ => unreachable!(),
-        };
+        let addr = BtSockAddr::from_raw(self.proto, &addr);
 
         let sock = BluetoothStream {
             inner: UnixStream::from_std(unsafe { StdUnixStream::from_raw_fd(fd) })?,
             proto: self.proto,
+            logger: None,
         };
 
+        if let Some(policy) = self.policy {
+            let actual = sock.security_info()?;
+
+            // the stream is closed when it is dropped
+            if !policy.is_satisfied_by(self.proto, &actual) {
+                return Err(SecurityDowngrade {
+                    address: addr.address,
+                    required: policy,
+                    actual,
+                }
+                .into());
+            }
+        }
+
         Ok((sock, addr))
     }
 
-    /// Returns the address and port that this listener is listening on.
-    pub fn local_addr(&self) -> Result<(Address, u16), std::io::Error> {
-        let mut addr: SockAddr = unsafe { std::mem::zeroed() };
-        let mut addr_len = match self.proto {
-            Protocol::L2CAP => std::mem::size_of::<bluez_sys::sockaddr_l2>(),
-            Protocol::RFCOMM => std::mem::size_of::<bluez_sys::sockaddr_rc>(),
-            _ => unreachable!(),
-        } as u32;
+    /// Requires incoming connections to satisfy `policy`.
+    ///
+    /// The required security level is set on the listening socket, so that
+    /// the kernel authenticates and encrypts links before it hands
+    /// connections to this listener. Connections that still do not satisfy
+    /// the policy when they are accepted are closed, and
+    /// [`accept`](BluetoothListener::accept) returns a [`SecurityDowngrade`]
+    /// for them instead of silently accepting an unencrypted link. The
+    /// controller settings that the policy depends on, such as Secure
+    /// Connections Only mode, are configured with
+    /// [`apply_security_policy`](crate::management::apply_security_policy).
+    pub fn set_security_policy(&mut self, policy: SecurityPolicy) -> Result<(), std::io::Error> {
+        let security = bt_security {
+            level: policy.required_level().value(),
+            key_size: 0,
+        };
 
         check_error(unsafe {
-            libc::getsockname(
+            libc::setsockopt(
                 self.inner.as_raw_fd(),
-                &mut addr as *mut _ as *mut _,
-                &mut addr_len,
+                SOL_BLUETOOTH,
+                BT_SECURITY,
+                &security as *const bt_security as *const libc::c_void,
+                std::mem::size_of::<bt_security>() as libc::socklen_t,
             )
         })?;
 
-        let addr = match self.proto {
-            Protocol::L2CAP => unsafe { (addr.l2.l2_bdaddr.into(), addr.l2.l2_psm) },
-            Protocol::RFCOMM => unsafe { (addr.rc.rc_bdaddr.into(), addr.rc.rc_channel as u16) },
-            _ => unreachable!(),
-        };
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    /// The policy that was set with
+    /// [`set_security_policy`](BluetoothListener::set_security_policy), if
+    /// any.
+    pub fn security_policy(&self) -> Option<SecurityPolicy> {
+        self.policy
+    }
+
+    /// Takes ownership of the listening socket `fd`, after checking that it
+    /// is a Bluetooth socket of a supported protocol. Whether it listens is
+    /// not checked, because Bluetooth sockets do not report their listening
+    /// state through `SO_ACCEPTCONN`. `fd` is closed if it is rejected.
+    ///
+    /// This is meant for services that are started by a systemd `.socket`
+    /// unit with `ListenSequentialPacket=` (L2CAP) or `ListenStream=`
+    /// (RFCOMM), which pass the listening sockets starting at fd 3 and set
+    /// `LISTEN_FDS` to their number.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor which is not owned by anything
+    /// else, as for [`FromRawFd::from_raw_fd`].
+    pub unsafe fn from_raw_fd_checked(fd: RawFd) -> Result<Self, std::io::Error> {
+        Self::try_from(OwnedFd::from_raw_fd(fd))
+    }
 
-        Ok(addr)
+    /// Sets the maximum number of connections that the kernel queues until
+    /// they are accepted. Listeners are created with a backlog of
+    /// [`DEFAULT_BACKLOG`].
+    pub fn set_backlog(&self, backlog: u32) -> Result<(), std::io::Error> {
+        let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+        check_error(unsafe { libc::listen(self.inner.as_raw_fd(), backlog) })?;
+        Ok(())
+    }
+
+    /// Returns the address and port that this listener is listening on.
+    pub fn local_addr(&self) -> Result<(Address, u16), std::io::Error> {
+        self.local_sockaddr().map(|addr| (addr.address, addr.port))
+    }
+
+    /// Returns the address that this listener is listening on, including its
+    /// address type and protocol.
+    pub fn local_sockaddr(&self) -> Result<BtSockAddr, std::io::Error> {
+        get_sockaddr(self.inner.as_raw_fd(), self.proto, libc::getsockname)
     }
 }
 
@@ -184,47 +801,102 @@ impl AsRawFd for BluetoothListener {
     }
 }
 
+impl IntoRawFd for BluetoothListener {
+    fn into_raw_fd(self) -> RawFd {
+        OwnedFd::from(self).into_raw_fd()
+    }
+}
+
+impl From<BluetoothListener> for OwnedFd {
+    fn from(listener: BluetoothListener) -> Self {
+        listener.inner.into_inner()
+    }
+}
+
+/// Converts a listening Bluetooth socket, such as one that was passed by
+/// systemd socket activation, into a [`BluetoothListener`]. The socket is
+/// made non-blocking.
+///
+/// Only the protocol of the socket is checked. Whether it listens cannot be
+/// checked, because Bluetooth sockets have their own listening state, which
+/// `SO_ACCEPTCONN` does not report.
+impl TryFrom<OwnedFd> for BluetoothListener {
+    type Error = std::io::Error;
+
+    fn try_from(fd: OwnedFd) -> Result<Self, Self::Error> {
+        let proto = socket_protocol(fd.as_raw_fd())?;
+
+        match proto {
+            Protocol::L2CAP | Protocol::RFCOMM | Protocol::ISO => {}
+            other => {
+                return Err(UnsupportedProtocol {
+                    protocol: other,
+                    socket: "BluetoothListener",
+                }
+                .into())
+            }
+        };
+
+        set_nonblocking(fd.as_raw_fd())?;
+
+        Ok(BluetoothListener {
+            inner: AsyncFd::new(fd)?,
+            proto,
+            policy: None,
+        })
+    }
+}
+
 /// A structure representing an active Bluetooth connection. This socket can be
 /// connected directly using [`BluetoothStream::connect`], or it can be accepted
 /// from a [`BluetoothListener`].
 #[derive(Debug)]
 pub struct BluetoothStream {
-    inner: UnixStream,
-    proto: Protocol,
+    pub(super) inner: UnixStream,
+    pub(super) proto: Protocol,
+    pub(super) logger: Option<AttachedLogger>,
 }
 
 impl BluetoothStream {
-    /// Connects to a remote Bluetooth device.
+    /// Connects to a remote Bluetooth device. `port` is checked like it is
+    /// for [`BluetoothListener::bind`], except that it cannot be 0.
     pub async fn connect(
         proto: Protocol,
         addr: Address,
         addr_type: AddressType,
         port: u16,
+    ) -> Result<Self, std::io::Error> {
+        Self::connect_with_mode(proto, addr, addr_type, port, None).await
+    }
+
+    async fn connect_with_mode(
+        proto: Protocol,
+        addr: Address,
+        addr_type: AddressType,
+        port: u16,
+        mode: Option<L2capMode>,
     ) -> Result<Self, std::io::Error> {
         let flags = match proto {
             Protocol::L2CAP => libc::SOCK_SEQPACKET,
             Protocol::RFCOMM => libc::SOCK_STREAM,
-            other => panic!(
-                "bluetooth protocol {:?} cannot be used with BluetoothStream",
-                other
-            ),
+            other => {
+                return Err(UnsupportedProtocol {
+                    protocol: other,
+                    socket: "BluetoothStream",
+                }
+                .into())
+            }
         };
 
-        let fd: RawFd = check_error(unsafe {
-            libc::socket(
-                libc::AF_BLUETOOTH,
-                libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK | flags,
-                proto as libc::c_int,
-            )
-        })?;
+        check_port(proto, addr_type, port, false)?;
 
-        let (addr, addr_len) = match proto {
+        let (sockaddr, addr_len) = match proto {
             Protocol::L2CAP => (
                 SockAddr {
                     l2: bluez_sys::sockaddr_l2 {
                         l2_family: libc::AF_BLUETOOTH as u16,
                         l2_bdaddr: addr.into(),
-                        l2_bdaddr_type: addr_type as u8,
+                        l2_bdaddr_type: addr_type.to_socket_u8(),
                         l2_psm: port,
                         l2_cid: 0,
                     },
@@ -244,126 +916,390 @@ impl BluetoothStream {
             _ => unreachable!(),
         };
 
-        let res = unsafe {
+        Self::connect_sockaddr(proto, flags, addr, &sockaddr, addr_len, mode).await
+    }
+
+    /// Opens the fixed ATT channel of the LE link to a remote device, on
+    /// which its GATT server is reached, for example with an
+    /// [`AttBearer`](super::att::AttBearer). The kernel creates the link if
+    /// the device is not connected yet.
+    ///
+    /// Unlike Enhanced ATT bearers, the channel does not need an encrypted
+    /// link, and it starts with an MTU of
+    /// [`DEFAULT_LE_MTU`](super::att::DEFAULT_LE_MTU) until the MTU is
+    /// exchanged.
+    pub async fn connect_att(
+        addr: Address,
+        addr_type: AddressType,
+    ) -> Result<Self, std::io::Error> {
+        if addr_type == AddressType::BREDR {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the fixed att channel only exists on le links",
+            ));
+        }
+
+        let sockaddr = SockAddr {
+            l2: bluez_sys::sockaddr_l2 {
+                l2_family: libc::AF_BLUETOOTH as u16,
+                l2_bdaddr: addr.into(),
+                l2_bdaddr_type: addr_type.to_socket_u8(),
+                l2_psm: 0,
+                l2_cid: super::ATT_CID,
+            },
+        };
+
+        Self::connect_sockaddr(
+            Protocol::L2CAP,
+            libc::SOCK_SEQPACKET,
+            addr,
+            &sockaddr,
+            std::mem::size_of::<bluez_sys::sockaddr_l2>(),
+            None,
+        )
+        .await
+    }
+
+    /// Creates a socket of type `flags` and connects it to `addr`, which
+    /// belongs to the device `remote`.
+    async fn connect_sockaddr(
+        proto: Protocol,
+        flags: libc::c_int,
+        remote: Address,
+        addr: &SockAddr,
+        addr_len: usize,
+        mode: Option<L2capMode>,
+    ) -> Result<Self, std::io::Error> {
+        // the socket is closed when this is dropped, including when this
+        // future is dropped before the connection completes
+        let fd = unsafe {
+            OwnedFd::from_raw_fd(check_error(libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK | flags,
+                proto as libc::c_int,
+            ))?)
+        };
+
+        if let Some(mode) = mode {
+            let mode = mode as u8;
+
+            check_error(unsafe {
+                libc::setsockopt(
+                    fd.as_raw_fd(),
+                    SOL_BLUETOOTH,
+                    BT_MODE,
+                    &mode as *const u8 as *const libc::c_void,
+                    std::mem::size_of::<u8>() as libc::socklen_t,
+                )
+            })?;
+        }
+
+        let res = check_error(unsafe {
             libc::connect(
-                fd,
-                &addr as *const SockAddr as *const libc::sockaddr,
+                fd.as_raw_fd(),
+                addr as *const SockAddr as *const libc::sockaddr,
                 addr_len as u32,
             )
-        };
+        });
 
-        match check_error(res) {
-            Ok(_) => {}
-            // should always get EINPROGRESS if socket is initialized using SOCK_NONBLOCK
-            Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {
-                // wait until the file descriptor becomes writeable
-                let afd = AsyncFd::new(fd)?;
-                let _ = afd.writable().await?;
-            }
-            other => {
-                other?;
-            }
-        }
+        let fd = finish_connect(fd, res, remote).await?;
 
         Ok(BluetoothStream {
-            inner: UnixStream::from_std(unsafe { StdUnixStream::from_raw_fd(fd) })?,
+            inner: UnixStream::from_std(StdUnixStream::from(fd))?,
             proto,
+            logger: None,
         })
     }
 
+    /// Opens an L2CAP channel to `psm` on a remote device.
+    pub async fn connect_l2cap(
+        addr: Address,
+        addr_type: AddressType,
+        psm: Psm,
+    ) -> Result<Self, std::io::Error> {
+        Self::connect(Protocol::L2CAP, addr, addr_type, psm.into()).await
+    }
+
+    /// Opens an L2CAP channel to `psm` on a remote device in `mode`.
+    pub async fn connect_l2cap_mode(
+        addr: Address,
+        addr_type: AddressType,
+        psm: Psm,
+        mode: L2capMode,
+    ) -> Result<Self, std::io::Error> {
+        Self::connect_with_mode(Protocol::L2CAP, addr, addr_type, psm.into(), Some(mode)).await
+    }
+
+    /// Opens an RFCOMM channel to `channel` on a remote device.
+    pub async fn connect_rfcomm(
+        addr: Address,
+        channel: RfcommChannel,
+    ) -> Result<Self, std::io::Error> {
+        Self::connect(Protocol::RFCOMM, addr, AddressType::BREDR, channel.into()).await
+    }
+
+    /// Connects to a remote Bluetooth device like
+    /// [`connect`](BluetoothStream::connect), but gives up after `timeout`.
+    ///
+    /// Without a timeout, connecting to a device that is not in range only
+    /// fails once the page timeout of the controller has passed, which can
+    /// take a long time. If the timeout passes, the error wraps a
+    /// [`DeviceUnreachable`].
+    pub async fn connect_timeout(
+        proto: Protocol,
+        addr: Address,
+        addr_type: AddressType,
+        port: u16,
+        timeout: Duration,
+    ) -> Result<Self, std::io::Error> {
+        match tokio::time::timeout(timeout, Self::connect(proto, addr, addr_type, port)).await {
+            Ok(res) => res,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                DeviceUnreachable {
+                    address: addr,
+                    source: std::io::Error::from_raw_os_error(libc::ETIMEDOUT),
+                },
+            )),
+        }
+    }
+
     /// Sets the maximum transmission unit (MTU) of this Bluetooth connection.
+    /// This is only supported for L2CAP connections.
     pub fn set_mtu(&mut self, mtu: u16) -> std::io::Result<()> {
-        let mut options = std::mem::MaybeUninit::<bluez_sys::l2cap_options>::uninit();
-        let mut len = std::mem::size_of::<bluez_sys::l2cap_options>() as libc::socklen_t;
+        let mut options = self.l2cap_options()?;
+        let len = std::mem::size_of::<bluez_sys::l2cap_options>() as libc::socklen_t;
+
+        options.omtu = mtu;
+        options.imtu = mtu;
 
         check_error(unsafe {
-            libc::getsockopt(
+            libc::setsockopt(
                 self.inner.as_raw_fd(),
                 bluez_sys::SOL_L2CAP as i32,
-                0x01, /* L2CAP_OPTIONS */
-                &mut options as *mut MaybeUninit<bluez_sys::l2cap_options> as *mut _,
+                L2CAP_OPTIONS,
+                &options as *const bluez_sys::l2cap_options as *const libc::c_void,
+                len,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// The largest packet that the remote device accepts on this channel,
+    /// as negotiated when the channel was configured. This is only supported
+    /// for L2CAP connections.
+    pub fn send_mtu(&self) -> std::io::Result<u16> {
+        self.mtu(|options| options.omtu, BT_SNDMTU)
+    }
+
+    /// The largest packet that this side accepts on this channel. This is
+    /// only supported for L2CAP connections.
+    pub fn recv_mtu(&self) -> std::io::Result<u16> {
+        self.mtu(|options| options.imtu, BT_RCVMTU)
+    }
+
+    /// Reads an MTU from the L2CAP options, or from the `BT_SNDMTU` or
+    /// `BT_RCVMTU` option for LE channels, for which the kernel does not
+    /// report the L2CAP options.
+    fn mtu(
+        &self,
+        field: impl FnOnce(&bluez_sys::l2cap_options) -> u16,
+        name: libc::c_int,
+    ) -> std::io::Result<u16> {
+        match self.l2cap_options() {
+            Ok(options) => return Ok(field(&options)),
+            Err(err) if err.raw_os_error() != Some(libc::EINVAL) => return Err(err),
+            Err(_) => {}
+        }
+
+        let mut mtu: u16 = 0;
+        let mut len = std::mem::size_of::<u16>() as libc::socklen_t;
+
+        check_error(unsafe {
+            libc::getsockopt(
+                self.inner.as_raw_fd(),
+                SOL_BLUETOOTH,
+                name,
+                &mut mtu as *mut u16 as *mut libc::c_void,
                 &mut len,
             )
         })?;
 
-        let mut options = unsafe { options.assume_init() };
+        Ok(mtu)
+    }
 
-        options.omtu = mtu;
-        options.imtu = mtu;
+    fn l2cap_options(&self) -> std::io::Result<bluez_sys::l2cap_options> {
+        if self.proto != Protocol::L2CAP {
+            return Err(UnsupportedProtocol {
+                protocol: self.proto,
+                socket: "BluetoothStream::mtu",
+            }
+            .into());
+        }
+
+        let mut options = MaybeUninit::<bluez_sys::l2cap_options>::uninit();
+        let mut len = std::mem::size_of::<bluez_sys::l2cap_options>() as libc::socklen_t;
 
         check_error(unsafe {
-            libc::setsockopt(
+            libc::getsockopt(
                 self.inner.as_raw_fd(),
                 bluez_sys::SOL_L2CAP as i32,
-                0x01, /* L2CAP_OPTIONS */
-                &options as *const bluez_sys::l2cap_options as *const libc::c_void,
-                len,
+                L2CAP_OPTIONS,
+                &mut options as *mut MaybeUninit<bluez_sys::l2cap_options> as *mut _,
+                &mut len,
             )
         })?;
 
-        Ok(())
+        Ok(unsafe { options.assume_init() })
     }
 
-    /// Gets the local address and port of this Bluetooth connection.
-    pub fn local_addr(&self) -> Result<(Address, u16), std::io::Error> {
-        let mut addr: SockAddr = unsafe { std::mem::zeroed() };
-        let mut addr_len = match self.proto {
-            Protocol::L2CAP => std::mem::size_of::<bluez_sys::sockaddr_l2>(),
-            Protocol::RFCOMM => std::mem::size_of::<bluez_sys::sockaddr_rc>(),
-            _ => unreachable!(),
-        } as u32;
+    /// Reads the HCI connection that this stream runs on, which links it to
+    /// the connection that the management API reports for the same device.
+    /// This is only supported for L2CAP and RFCOMM connections.
+    pub fn connection_info(&self) -> Result<ConnectionInfo, std::io::Error> {
+        let (level, name) = match self.proto {
+            Protocol::L2CAP => (bluez_sys::SOL_L2CAP as libc::c_int, L2CAP_CONNINFO),
+            Protocol::RFCOMM => (SOL_RFCOMM, RFCOMM_CONNINFO),
+            other => {
+                return Err(UnsupportedProtocol {
+                    protocol: other,
+                    socket: "BluetoothStream::connection_info",
+                }
+                .into())
+            }
+        };
+
+        let mut info = MaybeUninit::<conninfo>::zeroed();
+        let mut len = std::mem::size_of::<conninfo>() as libc::socklen_t;
 
         check_error(unsafe {
-            libc::getsockname(
+            libc::getsockopt(
                 self.inner.as_raw_fd(),
-                &mut addr as *mut _ as *mut _,
-                &mut addr_len,
+                level,
+                name,
+                info.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
             )
         })?;
 
-        let addr = match self.proto {
-            Protocol::L2CAP => unsafe { (addr.l2.l2_bdaddr.into(), addr.l2.l2_psm) },
-            Protocol::RFCOMM => unsafe { (addr.rc.rc_bdaddr.into(), addr.rc.rc_channel as u16) },
-            _ => unreachable!(),
-        };
+        let info = unsafe { info.assume_init() };
 
-        Ok(addr)
+        Ok(ConnectionInfo {
+            handle: info.hci_handle,
+            class: ClassOfDevice::from_bytes(info.dev_class),
+        })
     }
 
-    /// Gets the remote address and port of this Bluetooth connection.
-    pub fn peer_addr(&self) -> Result<(Address, u16), std::io::Error> {
-        let mut addr: SockAddr = unsafe { std::mem::zeroed() };
-        let mut addr_len = match self.proto {
-            Protocol::L2CAP => std::mem::size_of::<bluez_sys::sockaddr_l2>(),
-            Protocol::RFCOMM => std::mem::size_of::<bluez_sys::sockaddr_rc>(),
-            _ => unreachable!(),
-        } as u32;
+    /// Reads the security level of this connection and the size of the key
+    /// that the link is encrypted with. Applications can check this before
+    /// exchanging sensitive data, since a connection can be opened at a lower
+    /// level than the remote service expects.
+    pub fn security_info(&self) -> Result<SecurityInfo, std::io::Error> {
+        get_security(self.inner.as_raw_fd())
+    }
+
+    /// Checks that the link to the remote device is still alive, and returns
+    /// the round-trip time if it could be measured. This can be used as a
+    /// keepalive for connections which otherwise only notice that the remote
+    /// device is gone when the supervision timeout of the link expires.
+    ///
+    /// On BR/EDR, this sends an L2CAP Echo Request over the signaling channel
+    /// of the link, as `l2ping` does. The request is answered by the host
+    /// stack of the remote device, so it works whatever the protocol of this
+    /// stream is. It needs the `CAP_NET_RAW` capability, and fails with
+    /// [`TimedOut`](std::io::ErrorKind::TimedOut) if no answer arrives within
+    /// 10 seconds.
+    ///
+    /// LE links have no echo, and without the capability the echo cannot be
+    /// sent. In those cases, this only checks for a pending socket error and,
+    /// on RFCOMM, makes a zero-length write. That only detects connections
+    /// which the kernel already knows are broken, and nothing is sent to the
+    /// remote device, so `None` is returned instead of a round-trip time.
+    pub async fn ping(&self) -> Result<Option<Duration>, std::io::Error> {
+        let peer = self.peer_sockaddr()?;
+
+        if peer.address_type == AddressType::BREDR {
+            let local = self.local_sockaddr()?.address;
+
+            match echo(Some(local), peer.address, b"ping", ECHO_TIMEOUT).await {
+                Err(err) if matches!(err.raw_os_error(), Some(libc::EPERM | libc::EACCES)) => {}
+                res => return res.map(Some),
+            }
+        }
+
+        // a zero-length write on an L2CAP or ISO socket would send an empty
+        // packet, but RFCOMM does not send anything for one
+        if self.proto == Protocol::RFCOMM {
+            check_error(unsafe {
+                libc::send(
+                    self.inner.as_raw_fd(),
+                    std::ptr::null(),
+                    0,
+                    libc::MSG_NOSIGNAL | libc::MSG_DONTWAIT,
+                ) as libc::c_int
+            })?;
+        }
+
+        let mut error: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
 
         check_error(unsafe {
-            libc::getpeername(
+            libc::getsockopt(
                 self.inner.as_raw_fd(),
-                &mut addr as *mut _ as *mut _,
-                &mut addr_len,
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                &mut error as *mut _ as *mut _,
+                &mut len,
             )
         })?;
 
-        let addr = match self.proto {
-            Protocol::L2CAP => unsafe { (addr.l2.l2_bdaddr.into(), addr.l2.l2_psm) },
-            Protocol::RFCOMM => unsafe { (addr.rc.rc_bdaddr.into(), addr.rc.rc_channel as u16) },
-            _ => unreachable!(),
-        };
+        if error != 0 {
+            return Err(std::io::Error::from_raw_os_error(error));
+        }
+
+        Ok(None)
+    }
+
+    /// Gets the local address and port of this Bluetooth connection.
+    pub fn local_addr(&self) -> Result<(Address, u16), std::io::Error> {
+        self.local_sockaddr().map(|addr| (addr.address, addr.port))
+    }
 
-        Ok(addr)
+    /// Gets the local address of this Bluetooth connection, including its
+    /// address type and protocol.
+    pub fn local_sockaddr(&self) -> Result<BtSockAddr, std::io::Error> {
+        get_sockaddr(self.inner.as_raw_fd(), self.proto, libc::getsockname)
+    }
+
+    /// Gets the remote address and port of this Bluetooth connection.
+    pub fn peer_addr(&self) -> Result<(Address, u16), std::io::Error> {
+        self.peer_sockaddr().map(|addr| (addr.address, addr.port))
+    }
+
+    /// Gets the remote address of this Bluetooth connection, including its
+    /// address type and protocol.
+    pub fn peer_sockaddr(&self) -> Result<BtSockAddr, std::io::Error> {
+        get_sockaddr(self.inner.as_raw_fd(), self.proto, libc::getpeername)
+    }
+
+    /// Attaches a logger which receives the data of every read and write on
+    /// this stream, or detaches the current logger if `logger` is `None`.
+    /// Reads and writes on the halves of a split stream are not logged.
+    pub fn set_packet_logger(&mut self, logger: Option<Arc<dyn PacketLogger>>) {
+        self.logger = logger.map(AttachedLogger::new);
     }
 
     /// Splits this stream into a borrowed reading half and a borrowed writing half.
     pub fn split(&mut self) -> (ReadHalf, WriteHalf) {
-        self.inner.split()
+        let (read, write) = self.inner.split();
+        (ReadHalf(read), write)
     }
 
     /// Splits this stream into a owned reading half and a owned writing half.
     pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
-        self.inner.into_split()
+        let (read, write) = self.inner.into_split();
+        (OwnedReadHalf(read), write)
     }
 
     /// Converts a [`BluetoothStream`] into a [`UnixStream`].
@@ -373,57 +1309,77 @@ impl BluetoothStream {
 
     /// Converts a [`UnixStream`] into a [`BluetoothStream`]. This method will
     /// check that `stream` is actually a bluetooth stream, and will panic if it
-    /// is not.
+    /// is not. See [`BluetoothStream::try_from_unix`] for a version which
+    /// returns an error instead.
     pub fn from_unix(stream: UnixStream) -> Self {
-        let mut optval: libc::c_int = 0;
-        let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        Self::try_from_unix(stream).unwrap()
+    }
 
-        check_error(unsafe {
-            libc::getsockopt(
-                stream.as_raw_fd(),
-                libc::SOL_SOCKET,
-                libc::SO_DOMAIN,
-                &mut optval as *mut _ as *mut _,
-                &mut optlen as *mut _,
-            )
-        })
-        .unwrap();
+    /// Converts a [`UnixStream`] into a [`BluetoothStream`], returning an
+    /// error if `stream` is not a Bluetooth socket of a protocol that
+    /// [`BluetoothStream`] supports.
+    pub fn try_from_unix(stream: UnixStream) -> Result<Self, std::io::Error> {
+        let proto = socket_protocol(stream.as_raw_fd())?;
 
-        assert_eq!(optval, libc::AF_BLUETOOTH, "socket domain is not bluetooth");
+        match proto {
+            Protocol::L2CAP | Protocol::RFCOMM | Protocol::ISO => {}
+            other => {
+                return Err(UnsupportedProtocol {
+                    protocol: other,
+                    socket: "BluetoothStream",
+                }
+                .into())
+            }
+        };
 
-        let mut optval: libc::c_int = 0;
-        let mut optlen = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        Ok(Self {
+            inner: stream,
+            proto,
+            logger: None,
+        })
+    }
 
+    /// Creates a stream of protocol `proto` which is connected to a local
+    /// socket instead of a remote device, so that tests can play the part of
+    /// the remote device. The socket keeps message boundaries, like L2CAP.
+    #[cfg(test)]
+    pub(crate) fn pair(proto: Protocol) -> Result<(Self, UnixStream), std::io::Error> {
+        let mut fds = [0 as RawFd; 2];
         check_error(unsafe {
-            libc::getsockopt(
-                stream.as_raw_fd(),
-                libc::SOL_SOCKET,
-                libc::SO_PROTOCOL,
-                &mut optval as *mut _ as *mut _,
-                &mut optlen as *mut _,
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
             )
-        })
-        .unwrap();
-
-        let proto = FromPrimitive::from_i32(optval).expect("socket has invalid protocol");
+        })?;
 
-        match proto {
-            Protocol::L2CAP | Protocol::RFCOMM => {}
-            other => panic!(
-                "bluetooth protocol {:?} cannot be used with BluetoothStream",
-                other
-            ),
+        let (ours, theirs) = unsafe {
+            (
+                StdUnixStream::from_raw_fd(fds[0]),
+                StdUnixStream::from_raw_fd(fds[1]),
+            )
         };
 
-        Self {
-            inner: stream,
+        let stream = Self {
+            inner: UnixStream::from_std(ours)?,
             proto,
-        }
+            logger: None,
+        };
+
+        Ok((stream, UnixStream::from_std(theirs)?))
     }
 
     fn pin_get_inner(self: Pin<&mut Self>) -> Pin<&mut UnixStream> {
         unsafe { self.map_unchecked_mut(|s| &mut s.inner) }
     }
+
+    /// Deregisters the socket from the tokio runtime, and returns its file
+    /// descriptor, which the caller then owns. If the socket cannot be
+    /// deregistered, it is closed and the error is returned.
+    pub fn try_into_raw_fd(self) -> Result<RawFd, std::io::Error> {
+        Ok(self.inner.into_std()?.into_raw_fd())
+    }
 }
 
 impl AsRawFd for BluetoothStream {
@@ -432,6 +1388,28 @@ impl AsRawFd for BluetoothStream {
     }
 }
 
+/// Deregisters the socket from the tokio runtime, like
+/// [`BluetoothStream::try_into_raw_fd`].
+impl TryFrom<BluetoothStream> for OwnedFd {
+    type Error = std::io::Error;
+
+    fn try_from(stream: BluetoothStream) -> Result<Self, Self::Error> {
+        Ok(stream.inner.into_std()?.into())
+    }
+}
+
+/// Converts a connected Bluetooth socket into a [`BluetoothStream`], like
+/// [`BluetoothStream::try_from_unix`]. The socket is made non-blocking.
+impl TryFrom<OwnedFd> for BluetoothStream {
+    type Error = std::io::Error;
+
+    fn try_from(fd: OwnedFd) -> Result<Self, Self::Error> {
+        let stream = StdUnixStream::from(fd);
+        stream.set_nonblocking(true)?;
+        Self::try_from_unix(UnixStream::from_std(stream)?)
+    }
+}
+
 impl AsRef<UnixStream> for BluetoothStream {
     fn as_ref(&self) -> &UnixStream {
         &self.inner
@@ -450,7 +1428,14 @@ impl AsyncWrite for BluetoothStream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
-        AsyncWrite::poll_write(self.pin_get_inner(), cx, buf)
+        let this = self.get_mut();
+        let len = ready!(AsyncWrite::poll_write(Pin::new(&mut this.inner), cx, buf))?;
+
+        if let Some(logger) = &this.logger {
+            logger.log(this.proto, Direction::Sent, &buf[..len]);
+        }
+
+        Poll::Ready(Ok(len))
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
@@ -468,6 +1453,197 @@ impl AsyncRead for BluetoothStream {
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        AsyncRead::poll_read(self.pin_get_inner(), cx, buf)
+        let this = self.get_mut();
+        let start = buf.filled().len();
+
+        ready!(poll_read_packet(&this.inner, cx, buf))?;
+
+        if let Some(logger) = &this.logger {
+            logger.log(this.proto, Direction::Received, &buf.filled()[start..]);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads one packet from `socket` into `buf`.
+fn poll_read_packet(
+    socket: &UnixStream,
+    cx: &mut Context<'_>,
+    buf: &mut tokio::io::ReadBuf<'_>,
+) -> Poll<std::io::Result<()>> {
+    // each read of an L2CAP socket returns one packet. try_read only waits
+    // for readiness again once the socket reports that it is empty, whereas
+    // the poll_read of tokio treats a read that does not fill the buffer as
+    // draining the socket, which loses the wakeup for packets that are
+    // already queued behind it
+    let len = loop {
+        match socket.try_read(buf.initialize_unfilled()) {
+            Ok(len) => break len,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                ready!(socket.poll_read_ready(cx))?
+            }
+            Err(err) => return Poll::Ready(Err(err)),
+        }
+    };
+
+    buf.advance(len);
+    Poll::Ready(Ok(()))
+}
+
+/// The reading half of a [`BluetoothStream`] which was split with
+/// [`BluetoothStream::split`]. Like the stream, it reads one packet at a
+/// time.
+#[derive(Debug)]
+pub struct ReadHalf<'a>(tokio_unix::ReadHalf<'a>);
+
+impl AsRef<UnixStream> for ReadHalf<'_> {
+    fn as_ref(&self) -> &UnixStream {
+        self.0.as_ref()
+    }
+}
+
+impl AsyncRead for ReadHalf<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        poll_read_packet(self.get_mut().0.as_ref(), cx, buf)
+    }
+}
+
+/// The reading half of a [`BluetoothStream`] which was split with
+/// [`BluetoothStream::into_split`]. Like the stream, it reads one packet at
+/// a time.
+#[derive(Debug)]
+pub struct OwnedReadHalf(tokio_unix::OwnedReadHalf);
+
+impl OwnedReadHalf {
+    /// Returns the reading half of the socket, which can be reunited with
+    /// the writing half.
+    pub fn into_inner(self) -> tokio_unix::OwnedReadHalf {
+        self.0
+    }
+}
+
+impl AsRef<UnixStream> for OwnedReadHalf {
+    fn as_ref(&self) -> &UnixStream {
+        self.0.as_ref()
+    }
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        poll_read_packet(self.get_mut().0.as_ref(), cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn split_reads_queued_packets() {
+        let (mut stream, mut remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+
+        // both packets are queued before the first read
+        remote.write_all(&[0x01, 0x02]).await.unwrap();
+        remote.write_all(&[0x03]).await.unwrap();
+
+        let (mut read, _) = stream.split();
+        let mut buf = [0u8; 16];
+        assert_eq!(read.read(&mut buf).await.unwrap(), 2);
+        assert_eq!(read.read(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 0x03);
+
+        remote.write_all(&[0x04, 0x05, 0x06]).await.unwrap();
+        remote.write_all(&[0x07]).await.unwrap();
+
+        let (mut read, _write) = stream.into_split();
+        assert_eq!(read.read(&mut buf).await.unwrap(), 3);
+        assert_eq!(read.read(&mut buf).await.unwrap(), 1);
+        assert_eq!(buf[0], 0x07);
+    }
+
+    #[tokio::test]
+    async fn into_owned_fd() {
+        let (stream, mut remote) = BluetoothStream::pair(Protocol::L2CAP).unwrap();
+
+        // the socket stays open once it leaves the runtime
+        let fd = OwnedFd::try_from(stream).unwrap();
+        let mut socket = std::os::unix::net::UnixStream::from(fd);
+        std::io::Write::write_all(&mut socket, &[0x01]).unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(remote.read(&mut buf).await.unwrap(), 1);
+    }
+
+    fn info(level: SecurityLevel, key_size: u8) -> SecurityInfo {
+        SecurityInfo { level, key_size }
+    }
+
+    #[test]
+    fn mitm_policy() {
+        let policy = SecurityPolicy {
+            level: SecurityLevel::Medium,
+            require_mitm: true,
+            secure_connections_only: false,
+        };
+        assert_eq!(policy.required_level(), SecurityLevel::High);
+
+        // an unauthenticated key is not enough
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Medium, 16)));
+        assert!(policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::High, 7)));
+        // the level alone does not prove that the link is encrypted
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::High, 0)));
+
+        // a higher level is kept
+        let policy = SecurityPolicy {
+            level: SecurityLevel::Fips,
+            ..policy
+        };
+        assert_eq!(policy.required_level(), SecurityLevel::Fips);
+    }
+
+    #[test]
+    fn secure_connections_only_policy() {
+        let policy = SecurityPolicy {
+            secure_connections_only: true,
+            ..SecurityPolicy::default()
+        };
+        assert_eq!(policy.required_level(), SecurityLevel::Fips);
+
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::High, 16)));
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Fips, 15)));
+        assert!(policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Fips, 16)));
+    }
+
+    #[test]
+    fn rfcomm_key_size_exemption() {
+        // RFCOMM sockets always report a key size of 0, so only the level is
+        // checked
+        let policy = SecurityPolicy {
+            level: SecurityLevel::Medium,
+            require_mitm: false,
+            secure_connections_only: true,
+        };
+        assert!(policy.is_satisfied_by(Protocol::RFCOMM, &info(SecurityLevel::Fips, 0)));
+        assert!(!policy.is_satisfied_by(Protocol::RFCOMM, &info(SecurityLevel::High, 0)));
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Fips, 0)));
+
+        let policy = SecurityPolicy {
+            level: SecurityLevel::Medium,
+            ..SecurityPolicy::default()
+        };
+        assert!(policy.is_satisfied_by(Protocol::RFCOMM, &info(SecurityLevel::Medium, 0)));
+        assert!(!policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Medium, 0)));
+        assert!(policy.is_satisfied_by(Protocol::L2CAP, &info(SecurityLevel::Medium, 7)));
     }
 }
@@ -0,0 +1,192 @@
+#[cfg(feature = "sdp")]
+use crate::communication::discovery;
+#[cfg(feature = "communication")]
+use crate::communication::{att, avdtp, avrcp, hid, obex, rfcomm};
+#[cfg(feature = "management")]
+use crate::security::smp;
+#[cfg(feature = "management")]
+use crate::{hci, management};
+
+/// An error from any part of this library.
+///
+/// I/O errors and invalid data are reported as [`Error::Io`] and
+/// [`Error::InvalidData`] regardless of the module that they came from. Other
+/// errors keep the error type of their module, which is available through
+/// [`std::error::Error::source`] as well.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[source] std::io::Error),
+
+    #[error("Invalid data was received.")]
+    InvalidData,
+
+    #[cfg(feature = "management")]
+    #[error("Management error: {0}")]
+    Management(#[source] management::Error),
+
+    #[cfg(feature = "sdp")]
+    #[error("Service discovery error: {0}")]
+    Sdp(#[source] discovery::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("RFCOMM error: {0}")]
+    Rfcomm(#[source] rfcomm::Error),
+
+    #[cfg(feature = "management")]
+    #[error("HCI error: {0}")]
+    Hci(#[source] hci::Error),
+
+    #[cfg(feature = "management")]
+    #[error("Pairing error: {0}")]
+    Pairing(#[source] management::PairingError),
+
+    #[cfg(feature = "management")]
+    #[error("SMP error: {0}")]
+    Smp(#[source] smp::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("AVDTP error: {0}")]
+    Avdtp(#[source] avdtp::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("AVRCP error: {0}")]
+    Avrcp(#[source] avrcp::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("OBEX error: {0}")]
+    Obex(#[source] obex::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("HID error: {0}")]
+    Hid(#[source] hid::Error),
+
+    #[cfg(feature = "communication")]
+    #[error("ATT error: {0}")]
+    Att(#[source] att::Error),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "management")]
+impl From<management::Error> for Error {
+    fn from(err: management::Error) -> Self {
+        match err {
+            management::Error::IO { source } => Error::Io(source),
+            management::Error::InvalidData => Error::InvalidData,
+            err => Error::Management(err),
+        }
+    }
+}
+
+#[cfg(feature = "sdp")]
+impl From<discovery::Error> for Error {
+    fn from(err: discovery::Error) -> Self {
+        match err {
+            discovery::Error::Io(err) => Error::Io(err),
+            discovery::Error::InvalidResponse => Error::InvalidData,
+            err => Error::Sdp(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<rfcomm::Error> for Error {
+    fn from(err: rfcomm::Error) -> Self {
+        match err {
+            rfcomm::Error::Io(err) => Error::Io(err),
+            rfcomm::Error::InvalidFrame | rfcomm::Error::InvalidChecksum => Error::InvalidData,
+            err => Error::Rfcomm(err),
+        }
+    }
+}
+
+#[cfg(feature = "management")]
+impl From<hci::Error> for Error {
+    fn from(err: hci::Error) -> Self {
+        match err {
+            hci::Error::Io(err) => Error::Io(err),
+            hci::Error::InvalidEvent => Error::InvalidData,
+            err => Error::Hci(err),
+        }
+    }
+}
+
+#[cfg(feature = "management")]
+impl From<management::PairingError> for Error {
+    fn from(err: management::PairingError) -> Self {
+        match err {
+            management::PairingError::Management(err) => err.into(),
+            err => Error::Pairing(err),
+        }
+    }
+}
+
+#[cfg(feature = "management")]
+impl From<smp::Error> for Error {
+    fn from(err: smp::Error) -> Self {
+        match err {
+            smp::Error::InvalidPdu => Error::InvalidData,
+            err => Error::Smp(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<avdtp::Error> for Error {
+    fn from(err: avdtp::Error) -> Self {
+        match err {
+            avdtp::Error::Io(err) => Error::Io(err),
+            avdtp::Error::InvalidPacket => Error::InvalidData,
+            err => Error::Avdtp(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<avrcp::Error> for Error {
+    fn from(err: avrcp::Error) -> Self {
+        match err {
+            avrcp::Error::Io(err) => Error::Io(err),
+            avrcp::Error::InvalidPacket => Error::InvalidData,
+            err => Error::Avrcp(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<obex::Error> for Error {
+    fn from(err: obex::Error) -> Self {
+        match err {
+            obex::Error::Io(err) => Error::Io(err),
+            obex::Error::InvalidPacket => Error::InvalidData,
+            err => Error::Obex(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<hid::Error> for Error {
+    fn from(err: hid::Error) -> Self {
+        match err {
+            hid::Error::Io(err) => Error::Io(err),
+            hid::Error::InvalidPacket => Error::InvalidData,
+            err => Error::Hid(err),
+        }
+    }
+}
+
+#[cfg(feature = "communication")]
+impl From<att::Error> for Error {
+    fn from(err: att::Error) -> Self {
+        match err {
+            att::Error::Io(err) => Error::Io(err),
+            att::Error::InvalidPdu => Error::InvalidData,
+            err => Error::Att(err),
+        }
+    }
+}
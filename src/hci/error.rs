@@ -0,0 +1,19 @@
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the controller sent an invalid event")]
+    InvalidEvent,
+
+    #[error("command {opcode:#06x} failed with status {status:#04x}")]
+    CommandFailed { opcode: u16, status: u8 },
+
+    #[error(
+        "the parameters of command {opcode:#06x} are {len} bytes long, but at most 255 are allowed"
+    )]
+    ParamsTooLong { opcode: u16, len: usize },
+
+    #[error("the controller did not answer command {0:#06x}")]
+    TimedOut(u16),
+}
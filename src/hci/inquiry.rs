@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use futures::future::{select, Either};
+use tokio::time::Instant;
+
+use super::HciSocket;
+use crate::management::{
+    start_discovery, stop_discovery, Controller, DeviceClass, EirData, Event,
+    ManagementStream, ServiceClasses,
+};
+use crate::util::BufExt;
+use crate::{Address, AddressType};
+
+const EVT_INQUIRY_RESULT: u8 = 0x02;
+const EVT_INQUIRY_RESULT_WITH_RSSI: u8 = 0x22;
+const EVT_EXTENDED_INQUIRY_RESULT: u8 = 0x2F;
+
+/// The values from an inquiry response that a BR/EDR controller needs to
+/// page a device quickly, which the management API does not report. Passing
+/// them to HCI commands which connect to the device, such as Create
+/// Connection or Remote Name Request, saves the controller from searching
+/// for the device for up to the whole page timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrEdrDiscoveryDetails {
+    /// How often the device scans for pages: 0x00 for R0, 0x01 for R1 and
+    /// 0x02 for R2.
+    pub page_scan_repetition_mode: u8,
+    /// Bits 2 to 16 of the difference between the clocks of the device and
+    /// the controller. Set bit 15 to mark it as valid when passing it to HCI
+    /// commands.
+    pub clock_offset: u16,
+}
+
+/// A BR/EDR device which answered an inquiry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InquiryResult {
+    pub address: Address,
+    pub rssi: i8,
+    /// The class of device, or `None` if the device did not report one.
+    pub class: Option<(DeviceClass, ServiceClasses)>,
+    /// The name of the device, if it was included in its extended inquiry
+    /// response.
+    pub name: Option<String>,
+    /// The paging details from the latest inquiry response of the device.
+    /// These are only available if the process has the `CAP_NET_RAW`
+    /// capability, since they are read from a raw HCI socket.
+    pub details: Option<BrEdrDiscoveryDetails>,
+}
+
+/// Searches for discoverable BR/EDR devices for up to `duration`, and
+/// returns every device that answered, with the latest values that it
+/// reported.
+///
+/// Unlike the HCI Inquiry command, this goes through the discovery of the
+/// management API, so the kernel resolves conflicts with other users of the
+/// controller and updates its list of known devices. The inquiry ends early
+/// if the kernel ends discovery on its own.
+pub async fn inquire(
+    controller: Controller,
+    duration: Duration,
+) -> Result<Vec<InquiryResult>, crate::Error> {
+    let mut socket = ManagementStream::open_for(controller)?;
+    let deadline = Instant::now() + duration;
+
+    start_discovery(&mut socket, controller, AddressType::BREDR.into(), None).await?;
+
+    // raw HCI sockets receive a copy of the inquiry results that the
+    // discovery causes, which are the only source of the paging details
+    let mut sniffer = HciSocket::open_with_events(
+        controller,
+        &[
+            EVT_INQUIRY_RESULT,
+            EVT_INQUIRY_RESULT_WITH_RSSI,
+            EVT_EXTENDED_INQUIRY_RESULT,
+        ],
+    )
+    .ok();
+
+    let mut results: Vec<InquiryResult> = Vec::new();
+    let mut details = HashMap::new();
+
+    loop {
+        let next = {
+            let hci_event = async {
+                match sniffer.as_mut() {
+                    Some(sniffer) => sniffer.read_event().await,
+                    None => futures::future::pending().await,
+                }
+            };
+
+            // receive is cancel safe, see ManagementStream::receive
+            let next = select(Box::pin(socket.receive()), Box::pin(hci_event));
+            match tokio::time::timeout_at(deadline, next).await {
+                Ok(Either::Left((response, _))) => Some(Ok(response)),
+                Ok(Either::Right((event, _))) => Some(Err(event)),
+                Err(_) => None,
+            }
+        };
+
+        let response = match next {
+            Some(Ok(response)) => response?,
+            Some(Err(Ok((event_code, param)))) => {
+                for (address, found) in parse_inquiry_results(event_code, param) {
+                    details.insert(address, found);
+
+                    if let Some(result) = results.iter_mut().find(|r| r.address == address) {
+                        result.details = Some(found);
+                    }
+                }
+
+                continue;
+            }
+            // the details are optional, so discovery goes on without them
+            Some(Err(Err(_))) => {
+                sniffer = None;
+                continue;
+            }
+            None => {
+                // discovery may have ended just before the deadline
+                let _ =
+                    stop_discovery(&mut socket, controller, AddressType::BREDR.into(), None)
+                        .await;
+                break;
+            }
+        };
+
+        if response.controller != controller {
+            continue;
+        }
+
+        match response.event {
+            Event::DeviceFound {
+                address,
+                address_type: AddressType::BREDR,
+                rssi,
+                ref eir_data,
+                ..
+            } => {
+                let eir = EirData::parse(eir_data);
+                let result = InquiryResult {
+                    address,
+                    rssi,
+                    class: eir
+                        .class_of_device
+                        .map(|class| (class.device_class(), class.service_classes())),
+                    name: eir.name().map(str::to_owned),
+                    details: details.get(&address).copied(),
+                };
+
+                match results.iter_mut().find(|r| r.address == address) {
+                    Some(existing) => {
+                        existing.rssi = result.rssi;
+                        existing.class = result.class.or(existing.class);
+                        existing.name = result.name.or_else(|| existing.name.take());
+                        existing.details = result.details.or(existing.details);
+                    }
+                    None => results.push(result),
+                }
+            }
+            Event::Discovering {
+                discovering: false, ..
+            } => break,
+            _ => {}
+        }
+    }
+
+    Ok(results)
+}
+
+/// Reads the paging details of every device in an Inquiry Result, Inquiry
+/// Result with RSSI or Extended Inquiry Result event, which hold one entry
+/// per device, each with the address, page scan repetition mode and the
+/// other fields of the device.
+fn parse_inquiry_results(
+    event_code: u8,
+    mut param: Bytes,
+) -> Vec<(Address, BrEdrDiscoveryDetails)> {
+    if !param.has_remaining() {
+        return vec![];
+    }
+
+    let count = param.get_u8() as usize;
+
+    // the size of each entry, and the size of the fields between the page
+    // scan repetition mode and the clock offset
+    let (entry_len, skipped_len) = match event_code {
+        // page scan period mode, page scan mode and class of device
+        EVT_INQUIRY_RESULT => (14, 2 + 3),
+        // some controllers also include the page scan mode, which the
+        // kernel recognizes by the length of the entries as well
+        EVT_INQUIRY_RESULT_WITH_RSSI if count > 0 && param.remaining() == count * 15 => (15, 2 + 3),
+        // page scan period mode and class of device
+        EVT_INQUIRY_RESULT_WITH_RSSI => (14, 1 + 3),
+        EVT_EXTENDED_INQUIRY_RESULT => (14 + 240, 1 + 3),
+        _ => return vec![],
+    };
+
+    if param.remaining() < count * entry_len {
+        return vec![];
+    }
+
+    (0..count)
+        .map(|_| {
+            let mut entry = param.split_to(entry_len);
+            let address = entry.get_address();
+            let page_scan_repetition_mode = entry.get_u8();
+            entry.advance(skipped_len);
+
+            (
+                address,
+                BrEdrDiscoveryDetails {
+                    page_scan_repetition_mode,
+                    clock_offset: entry.get_u16_le() & 0x7FFF,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inquiry_result_with_rssi() {
+        #[rustfmt::skip]
+        let param = Bytes::from_static(&[
+            0x02,
+            // address, page scan repetition mode, reserved, class of device,
+            // clock offset and rssi of each device
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x00,
+            0x0C, 0x02, 0x5A, 0x34, 0x92, 0xC4,
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x02, 0x00,
+            0x04, 0x04, 0x24, 0x10, 0x00, 0xB0,
+        ]);
+
+        let results = parse_inquiry_results(EVT_INQUIRY_RESULT_WITH_RSSI, param);
+        assert_eq!(
+            results,
+            [
+                (
+                    Address::from([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+                    BrEdrDiscoveryDetails {
+                        page_scan_repetition_mode: 0x01,
+                        clock_offset: 0x1234,
+                    }
+                ),
+                (
+                    Address::from([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+                    BrEdrDiscoveryDetails {
+                        page_scan_repetition_mode: 0x02,
+                        clock_offset: 0x0010,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn inquiry_result() {
+        #[rustfmt::skip]
+        let param = Bytes::from_static(&[
+            0x02,
+            // address, page scan repetition mode, two reserved bytes, class
+            // of device and clock offset of each device
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x00, 0x00,
+            0x0C, 0x02, 0x5A, 0x34, 0x92,
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x02, 0x00, 0x00,
+            0x04, 0x04, 0x24, 0x10, 0x00,
+        ]);
+
+        let results = parse_inquiry_results(EVT_INQUIRY_RESULT, param);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].1.clock_offset, 0x1234);
+        assert_eq!(
+            results[1],
+            (
+                Address::from([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+                BrEdrDiscoveryDetails {
+                    page_scan_repetition_mode: 0x02,
+                    clock_offset: 0x0010,
+                }
+            )
+        );
+
+        // the second entry is incomplete
+        let param = Bytes::copy_from_slice(&[&[0x02][..], &[0; 20][..]].concat());
+        assert!(parse_inquiry_results(EVT_INQUIRY_RESULT, param).is_empty());
+    }
+}
@@ -0,0 +1,404 @@
+//! Raw access to the Host Controller Interface (HCI) of a controller.
+//!
+//! The management API covers almost everything that applications need, and
+//! should be preferred because the kernel keeps track of the state that it
+//! changes. For example, the class of device of a controller is part of
+//! [`get_controller_info`](crate::management::get_controller_info). This
+//! module is a fallback for values that the management API does not expose,
+//! such as the transmit power of inquiry responses, or for procedures that
+//! it only runs as part of discovery, such as requesting the name of a remote
+//! device with [`remote_name`].
+//!
+//! [`inquire`] searches for BR/EDR devices. It is built on the discovery of
+//! the management API, and is only part of this module because it replaces
+//! the HCI Inquiry command, and because it reads the page scan repetition
+//! mode and clock offset of the devices from the HCI events that the
+//! discovery causes.
+//!
+//! [`PeriodicSync`] synchronizes to the periodic advertising train of a
+//! broadcaster through an ISO socket, and reads the periodic advertising
+//! reports from the HCI events, because the kernel does not pass them on.
+//!
+//! Sending HCI commands requires the `CAP_NET_RAW` capability, and the
+//! controller has to be powered.
+
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::unix::AsyncFd;
+
+pub use self::error::Error;
+pub use self::inquiry::*;
+#[cfg(feature = "communication")]
+pub use self::periodic::*;
+use crate::management::Controller;
+use crate::util::{check_error, BufExt};
+use crate::{Address, Protocol};
+
+mod error;
+mod inquiry;
+#[cfg(feature = "communication")]
+mod periodic;
+
+const HCI_CHANNEL_RAW: u16 = 0;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+
+const EVT_REMOTE_NAME_REQUEST_COMPLETE: u8 = 0x07;
+const EVT_COMMAND_COMPLETE: u8 = 0x0E;
+const EVT_COMMAND_STATUS: u8 = 0x0F;
+
+/// How long to wait for the controller to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the name of a remote device. This has to be longer
+/// than the page timeout, which is 5.12 seconds by default.
+const REMOTE_NAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read Inquiry Response Transmit Power Level (Core spec, Vol 4, Part E,
+/// 7.3.61).
+pub const OP_READ_INQUIRY_RESPONSE_TX_POWER_LEVEL: u16 = opcode(0x03, 0x0058);
+
+/// Remote Name Request (Core spec, Vol 4, Part E, 7.1.19).
+pub const OP_REMOTE_NAME_REQUEST: u16 = opcode(0x01, 0x0019);
+
+/// Read BD_ADDR (Core spec, Vol 4, Part E, 7.4.6).
+pub const OP_READ_BD_ADDR: u16 = opcode(0x04, 0x0009);
+
+/// LE Periodic Advertising Create Sync (Core spec, Vol 4, Part E, 7.8.67).
+/// The kernel sends it when an ISO socket synchronizes to a broadcaster.
+pub const OP_LE_PERIODIC_ADVERTISING_CREATE_SYNC: u16 = opcode(0x08, 0x0044);
+
+/// Combines an opcode group field (OGF) and an opcode command field (OCF) into
+/// an HCI opcode.
+pub const fn opcode(ogf: u8, ocf: u16) -> u16 {
+    ((ogf as u16) << 10) | (ocf & 0x03FF)
+}
+
+#[repr(C)]
+struct hci_filter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// A raw HCI socket which is bound to one controller.
+///
+/// The socket only receives the events that answer commands, so it can be used
+/// alongside the management API without interfering with it.
+pub struct HciSocket {
+    inner: AsyncFd<OwnedFd>,
+    controller: Controller,
+}
+
+impl HciSocket {
+    pub fn open(controller: Controller) -> Result<Self, Error> {
+        Self::open_with_events(controller, &[])
+    }
+
+    /// Opens a socket which also receives the events with the codes in
+    /// `events`, such as the results of an inquiry that the kernel started.
+    /// They are read with [`HciSocket::read_event`].
+    fn open_with_events(controller: Controller, events: &[u8]) -> Result<Self, Error> {
+        let fd = check_error(unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                Protocol::HCI as libc::c_int,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let addr = bluez_sys::sockaddr_hci {
+            hci_family: libc::AF_BLUETOOTH as u16,
+            hci_dev: controller.into(),
+            hci_channel: HCI_CHANNEL_RAW,
+        };
+
+        let mut filter = hci_filter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [
+                (1 << EVT_REMOTE_NAME_REQUEST_COMPLETE)
+                    | (1 << EVT_COMMAND_COMPLETE)
+                    | (1 << EVT_COMMAND_STATUS),
+                0,
+            ],
+            opcode: 0,
+        };
+
+        for &event in events {
+            filter.event_mask[(event / 32) as usize & 1] |= 1 << (event % 32);
+        }
+
+        check_error(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const bluez_sys::sockaddr_hci as *const libc::sockaddr,
+                std::mem::size_of::<bluez_sys::sockaddr_hci>() as u32,
+            )
+        })?;
+
+        check_error(unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                SOL_HCI,
+                HCI_FILTER,
+                &filter as *const hci_filter as *const libc::c_void,
+                std::mem::size_of::<hci_filter>() as libc::socklen_t,
+            )
+        })?;
+
+        Ok(Self {
+            inner: AsyncFd::new(fd)?,
+            controller,
+        })
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    /// Sends a command and waits until the controller completes it. The first
+    /// return parameter of almost every command is a status, which is checked
+    /// and removed; the remaining return parameters are returned.
+    ///
+    /// The parameters of a command are at most 255 bytes long; longer
+    /// parameters are rejected with [`Error::ParamsTooLong`].
+    pub async fn command(&mut self, opcode: u16, param: &[u8]) -> Result<Bytes, Error> {
+        self.send_command(opcode, param).await?;
+
+        tokio::time::timeout(COMMAND_TIMEOUT, self.wait_for_completion(opcode))
+            .await
+            .map_err(|_| Error::TimedOut(opcode))?
+    }
+
+    async fn send_command(&mut self, opcode: u16, param: &[u8]) -> Result<(), Error> {
+        let len = u8::try_from(param.len()).map_err(|_| Error::ParamsTooLong {
+            opcode,
+            len: param.len(),
+        })?;
+
+        let mut packet = BytesMut::with_capacity(4 + param.len());
+        packet.put_u8(HCI_COMMAND_PKT);
+        packet.put_u16_le(opcode);
+        packet.put_u8(len);
+        packet.put_slice(param);
+        self.write(&packet[..]).await?;
+        Ok(())
+    }
+
+    /// Reads the next event, and returns its event code and parameters.
+    async fn read_event(&mut self) -> Result<(u8, Bytes), Error> {
+        loop {
+            let mut packet = self.read().await?;
+
+            if packet.len() < 3 || packet.get_u8() != HCI_EVENT_PKT {
+                continue;
+            }
+
+            let event_code = packet.get_u8();
+            let len = packet.get_u8() as usize;
+
+            if packet.len() < len {
+                return Err(Error::InvalidEvent);
+            }
+
+            packet.truncate(len);
+            return Ok((event_code, packet));
+        }
+    }
+
+    async fn wait_for_completion(&mut self, opcode: u16) -> Result<Bytes, Error> {
+        loop {
+            let (event_code, mut packet) = self.read_event().await?;
+            let len = packet.len();
+
+            match event_code {
+                EVT_COMMAND_COMPLETE if len >= 4 => {
+                    // skip the number of commands that may be sent
+                    packet.advance(1);
+
+                    if packet.get_u16_le() != opcode {
+                        continue;
+                    }
+
+                    return match packet.get_u8() {
+                        0 => Ok(packet),
+                        status => Err(Error::CommandFailed { opcode, status }),
+                    };
+                }
+                EVT_COMMAND_STATUS if len >= 4 => {
+                    let status = packet.get_u8();
+                    packet.advance(1);
+
+                    if packet.get_u16_le() != opcode {
+                        continue;
+                    }
+
+                    // a successful status means that the command continues in
+                    // the background, which none of the commands that are sent
+                    // through this socket do
+                    if status != 0 {
+                        return Err(Error::CommandFailed { opcode, status });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn read(&self) -> Result<Bytes, std::io::Error> {
+        let mut buf = vec![0u8; 260];
+
+        loop {
+            let mut guard = self.inner.readable().await?;
+
+            match guard.try_io(|fd| {
+                check_error(unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    ) as libc::c_int
+                })
+            }) {
+                Ok(len) => {
+                    buf.truncate(len? as usize);
+                    return Ok(buf.into());
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    async fn write(&self, packet: &[u8]) -> Result<(), std::io::Error> {
+        loop {
+            let mut guard = self.inner.writable().await?;
+
+            match guard.try_io(|fd| {
+                check_error(unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                    ) as libc::c_int
+                })
+            }) {
+                Ok(res) => return res.map(|_| ()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsRawFd for HciSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Reads the power level, in dBm, that a BR/EDR controller uses to transmit
+/// its responses to inquiries. The management API does not expose this value,
+/// so it is read with an HCI command.
+pub async fn read_inquiry_response_tx_power(controller: Controller) -> Result<i8, Error> {
+    let mut socket = HciSocket::open(controller)?;
+    let mut param = socket
+        .command(OP_READ_INQUIRY_RESPONSE_TX_POWER_LEVEL, &[])
+        .await?;
+
+    if param.remaining() < 1 {
+        return Err(Error::InvalidEvent);
+    }
+
+    Ok(param.get_i8())
+}
+
+/// Requests the name of the BR/EDR device `address`. The controller connects
+/// to the device for this if it is not connected already, so the device has
+/// to be in range and connectable, but it does not have to be discoverable.
+///
+/// This is useful for devices that were found without a name, for example
+/// because their extended inquiry response did not include one.
+pub async fn remote_name(controller: Controller, address: Address) -> Result<String, Error> {
+    let mut socket = HciSocket::open(controller)?;
+
+    let mut param = BytesMut::with_capacity(10);
+    param.put_slice(address.as_ref());
+    // page scan repetition mode R2, which works with every device
+    param.put_u8(0x02);
+    param.put_u8(0x00);
+    // clock offset, which is not known
+    param.put_u16_le(0x0000);
+
+    socket.send_command(OP_REMOTE_NAME_REQUEST, &param).await?;
+
+    tokio::time::timeout(REMOTE_NAME_TIMEOUT, async {
+        loop {
+            let (event_code, mut packet) = socket.read_event().await?;
+
+            match event_code {
+                EVT_COMMAND_STATUS if packet.len() >= 4 => {
+                    let status = packet.get_u8();
+                    packet.advance(1);
+
+                    if packet.get_u16_le() == OP_REMOTE_NAME_REQUEST && status != 0 {
+                        return Err(Error::CommandFailed {
+                            opcode: OP_REMOTE_NAME_REQUEST,
+                            status,
+                        });
+                    }
+                }
+                EVT_REMOTE_NAME_REQUEST_COMPLETE if packet.len() >= 7 => {
+                    let status = packet.get_u8();
+
+                    if packet.get_address() != address {
+                        continue;
+                    }
+
+                    if status != 0 {
+                        return Err(Error::CommandFailed {
+                            opcode: OP_REMOTE_NAME_REQUEST,
+                            status,
+                        });
+                    }
+
+                    let len = packet.iter().position(|&b| b == 0).unwrap_or(packet.len());
+                    return Ok(String::from_utf8_lossy(&packet[..len]).into_owned());
+                }
+                _ => {}
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::TimedOut(OP_REMOTE_NAME_REQUEST))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn params_too_long() {
+        let (a, _b) = UnixDatagram::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut socket = HciSocket {
+            inner: AsyncFd::new(OwnedFd::from(a)).unwrap(),
+            controller: Controller(0),
+        };
+
+        assert!(matches!(
+            socket.command(OP_READ_BD_ADDR, &[0; 256]).await,
+            Err(Error::ParamsTooLong {
+                opcode: OP_READ_BD_ADDR,
+                len: 256
+            })
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use futures::stream::{self, Stream};
+
+use super::*;
+use crate::communication::iso::IsoBroadcastQos;
+use crate::communication::BluetoothListener;
+use crate::AddressType;
+
+const EVT_LE_META: u8 = 0x3E;
+
+const LE_PERIODIC_ADVERTISING_SYNC_ESTABLISHED: u8 = 0x0E;
+const LE_PERIODIC_ADVERTISING_REPORT: u8 = 0x0F;
+const LE_PERIODIC_ADVERTISING_SYNC_LOST: u8 = 0x10;
+
+/// The value of the transmit power and the RSSI if the controller does not
+/// know them.
+const NOT_AVAILABLE: i8 = 127;
+
+/// One periodic advertisement of a broadcaster, such as the BASE of an
+/// Auracast broadcast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeriodicReport {
+    /// The transmit power in dBm, if the broadcaster includes it.
+    pub tx_power: Option<i8>,
+    /// The RSSI in dBm, if the controller knows it.
+    pub rssi: Option<i8>,
+    /// The advertising data, which is reassembled if the controller reported
+    /// it in several fragments.
+    pub data: Bytes,
+    /// Whether the controller failed to receive the rest of the data, which is
+    /// then missing from `data`.
+    pub truncated: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SyncEvent {
+    Established {
+        status: u8,
+        handle: u16,
+        sid: u8,
+        address: Address,
+        phy: u8,
+        interval: u16,
+    },
+    Report {
+        handle: u16,
+        tx_power: i8,
+        rssi: i8,
+        data_status: u8,
+        data: Bytes,
+    },
+    Lost {
+        handle: u16,
+    },
+}
+
+/// Parses the LE Meta events that belong to periodic advertising sync, and
+/// ignores all other events.
+fn parse_sync_event(event_code: u8, mut param: Bytes) -> Option<SyncEvent> {
+    if event_code != EVT_LE_META || param.is_empty() {
+        return None;
+    }
+
+    match param.get_u8() {
+        LE_PERIODIC_ADVERTISING_SYNC_ESTABLISHED if param.len() >= 14 => {
+            let status = param.get_u8();
+            let handle = param.get_u16_le();
+            let sid = param.get_u8();
+            // the type of the address is not checked, because the controller
+            // reports identity addresses with different types
+            param.advance(1);
+            let address = param.get_address();
+            let phy = param.get_u8();
+            let interval = param.get_u16_le();
+
+            Some(SyncEvent::Established {
+                status,
+                handle,
+                sid,
+                address,
+                phy,
+                interval,
+            })
+        }
+        LE_PERIODIC_ADVERTISING_REPORT if param.len() >= 7 => {
+            let handle = param.get_u16_le();
+            let tx_power = param.get_i8();
+            let rssi = param.get_i8();
+            // skip the CTE type
+            param.advance(1);
+            let data_status = param.get_u8();
+            let len = param.get_u8() as usize;
+
+            if param.len() < len {
+                return None;
+            }
+
+            param.truncate(len);
+
+            Some(SyncEvent::Report {
+                handle,
+                tx_power,
+                rssi,
+                data_status,
+                data: param,
+            })
+        }
+        LE_PERIODIC_ADVERTISING_SYNC_LOST if param.len() >= 2 => Some(SyncEvent::Lost {
+            handle: param.get_u16_le(),
+        }),
+        _ => None,
+    }
+}
+
+fn available(value: i8) -> Option<i8> {
+    Some(value).filter(|&value| value != NOT_AVAILABLE)
+}
+
+/// Joins the fragments of periodic advertising reports.
+#[derive(Debug, Default)]
+struct ReportAssembler {
+    tx_power: Option<i8>,
+    data: BytesMut,
+}
+
+impl ReportAssembler {
+    fn push(
+        &mut self,
+        tx_power: i8,
+        rssi: i8,
+        data_status: u8,
+        data: &[u8],
+    ) -> Option<PeriodicReport> {
+        // only the first fragment is sure to carry the transmit power
+        if self.data.is_empty() {
+            self.tx_power = available(tx_power);
+        }
+
+        self.data.put_slice(data);
+
+        match data_status {
+            // more fragments follow
+            0x01 => None,
+            data_status => Some(PeriodicReport {
+                tx_power: self.tx_power.take(),
+                rssi: available(rssi),
+                data: self.data.split().freeze(),
+                truncated: data_status == 0x02,
+            }),
+        }
+    }
+}
+
+/// A sync to the periodic advertising train of a broadcaster.
+///
+/// The kernel creates and owns the sync through an ISO socket, so that it
+/// does not terminate the sync as one that nobody uses. The sync ends when
+/// this is dropped, which closes the socket.
+pub struct PeriodicSync {
+    socket: HciSocket,
+    // keeps the sync alive
+    _listener: BluetoothListener,
+    handle: u16,
+    phy: u8,
+    interval: Duration,
+    assembler: ReportAssembler,
+    lost: bool,
+}
+
+impl PeriodicSync {
+    /// Synchronizes `controller` to the periodic advertising train of the
+    /// broadcaster `broadcaster`, which advertises with the advertising set
+    /// `sid`, and waits until the controller has received the first
+    /// periodic advertisement. Once established, the controller loses the
+    /// sync if it receives no advertisement within the sync timeout in `qos`.
+    ///
+    /// This needs the
+    /// [`EXP_FEATURE_ISO_SOCKET`](crate::management::EXP_FEATURE_ISO_SOCKET)
+    /// experimental feature. The controller keeps looking for the broadcaster
+    /// until it is found, so the returned future should be used with a
+    /// timeout; dropping it cancels the sync.
+    pub async fn establish(
+        controller: Controller,
+        broadcaster: Address,
+        broadcaster_type: AddressType,
+        sid: u8,
+        qos: IsoBroadcastQos,
+    ) -> Result<Self, Error> {
+        let mut socket = HciSocket::open_with_events(controller, &[EVT_LE_META])?;
+
+        // ISO sockets are bound to the address of a controller, not its index
+        let mut local = socket.command(OP_READ_BD_ADDR, &[]).await?;
+        if local.len() < 6 {
+            return Err(Error::InvalidEvent);
+        }
+        let local = local.get_address();
+
+        let listener =
+            BluetoothListener::bind_periodic_sync(local, broadcaster, broadcaster_type, sid, qos)?;
+
+        loop {
+            let (event_code, param) = socket.read_event().await?;
+
+            match parse_sync_event(event_code, param) {
+                Some(SyncEvent::Established {
+                    status,
+                    handle,
+                    sid: event_sid,
+                    address,
+                    phy,
+                    interval,
+                }) if address == broadcaster && event_sid == sid => {
+                    if status != 0 {
+                        return Err(Error::CommandFailed {
+                            opcode: OP_LE_PERIODIC_ADVERTISING_CREATE_SYNC,
+                            status,
+                        });
+                    }
+
+                    return Ok(PeriodicSync {
+                        socket,
+                        _listener: listener,
+                        handle,
+                        phy,
+                        // in units of 1.25 ms
+                        interval: Duration::from_micros(interval as u64 * 1250),
+                        assembler: ReportAssembler::default(),
+                        lost: false,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The handle that the controller assigned to the sync.
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    /// The PHY of the periodic advertising train: 0x01 for LE 1M, 0x02 for LE
+    /// 2M and 0x03 for LE Coded.
+    pub fn phy(&self) -> u8 {
+        self.phy
+    }
+
+    /// The interval between periodic advertisements.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Waits for the next periodic advertisement. Returns `None` once the
+    /// controller has lost the sync, for example because the broadcaster
+    /// stopped advertising.
+    pub async fn next_report(&mut self) -> Result<Option<PeriodicReport>, Error> {
+        while !self.lost {
+            let (event_code, param) = self.socket.read_event().await?;
+
+            match parse_sync_event(event_code, param) {
+                Some(SyncEvent::Report {
+                    handle,
+                    tx_power,
+                    rssi,
+                    data_status,
+                    data,
+                }) if handle == self.handle => {
+                    if let Some(report) = self.assembler.push(tx_power, rssi, data_status, &data) {
+                        return Ok(Some(report));
+                    }
+                }
+                Some(SyncEvent::Lost { handle }) if handle == self.handle => self.lost = true,
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns a stream of the periodic advertisements, which ends when the
+    /// controller loses the sync or after the first error.
+    pub fn reports(&mut self) -> impl Stream<Item = Result<PeriodicReport, Error>> + '_ {
+        stream::unfold((self, false), |(sync, done)| async move {
+            if done {
+                return None;
+            }
+
+            match sync.next_report().await {
+                Ok(Some(report)) => Some((Ok(report), (sync, false))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), (sync, true))),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn established() {
+        let param = Bytes::from_static(&[
+            0x0E, 0x00, 0x01, 0x00, 0x03, 0x01, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x02, 0x50,
+            0x00, 0x05,
+        ]);
+
+        assert_eq!(
+            parse_sync_event(EVT_LE_META, param),
+            Some(SyncEvent::Established {
+                status: 0,
+                handle: 1,
+                sid: 3,
+                address: Address::from([0x06, 0x05, 0x04, 0x03, 0x02, 0x01]),
+                phy: 2,
+                interval: 0x50,
+            })
+        );
+    }
+
+    #[test]
+    fn lost_and_other_events() {
+        let lost = Bytes::from_static(&[0x10, 0x01, 0x00]);
+        assert_eq!(
+            parse_sync_event(EVT_LE_META, lost.clone()),
+            Some(SyncEvent::Lost { handle: 1 })
+        );
+
+        assert_eq!(parse_sync_event(EVT_COMMAND_COMPLETE, lost), None);
+        // an extended advertising report
+        assert_eq!(
+            parse_sync_event(EVT_LE_META, Bytes::from_static(&[0x0D, 0x00])),
+            None
+        );
+        // a report which is shorter than its data length
+        assert_eq!(
+            parse_sync_event(
+                EVT_LE_META,
+                Bytes::from_static(&[0x0F, 0x01, 0x00, 0x7F, 0xC4, 0xFF, 0x00, 0x04, 0x01])
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn reassembly() {
+        let mut assembler = ReportAssembler::default();
+
+        let first =
+            Bytes::from_static(&[0x0F, 0x01, 0x00, 0x04, 0xC4, 0xFF, 0x01, 0x02, 0xAA, 0xBB]);
+        let (tx_power, rssi, data_status, data) = match parse_sync_event(EVT_LE_META, first) {
+            Some(SyncEvent::Report {
+                handle: 1,
+                tx_power,
+                rssi,
+                data_status,
+                data,
+            }) => (tx_power, rssi, data_status, data),
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(assembler.push(tx_power, rssi, data_status, &data), None);
+
+        let report = assembler.push(NOT_AVAILABLE, -58, 0x00, &[0xCC]).unwrap();
+        assert_eq!(
+            report,
+            PeriodicReport {
+                tx_power: Some(4),
+                rssi: Some(-58),
+                data: Bytes::from_static(&[0xAA, 0xBB, 0xCC]),
+                truncated: false,
+            }
+        );
+
+        assert_eq!(assembler.push(NOT_AVAILABLE, -58, 0x01, &[0x01]), None);
+        let report = assembler
+            .push(NOT_AVAILABLE, NOT_AVAILABLE, 0x02, &[])
+            .unwrap();
+        assert_eq!(report.tx_power, None);
+        assert_eq!(report.rssi, None);
+        assert_eq!(&report.data[..], &[0x01]);
+        assert!(report.truncated);
+    }
+}
@@ -1,28 +1,77 @@
 //! # Management
-//! 
+//!
 //! For managing the Bluetooth controllers on your device (pairing, discovery,
 //! broadcasting, etc.), you can use the management API. This is contained
 //! inside of the [`management`] module, where the central type is
-//! [`management::ManagementStream`].
-//! 
+//! [`management::ManagementStream`]. Commands are sent with the functions
+//! in that module, such as [`management::set_powered`], which take the
+//! stream as their first argument.
+//!
 //! # Communication
-//! 
+//!
 //! For communicating with other bluetooth devices, you have a couple of
 //! options. You can use L2CAP streams or RFCOMM streams, both of which are
 //! exposed through [`BluetoothStream`](crate::communication::BluetoothStream).
-//! 
+//!
 //! This library also contains an implementation of Service Discovery Protocol
 //! (SDP) which operates over L2CAP and is availabile in the
 //! [`communication::discovery`](crate::communication::discovery) module.
 //!
+//! # Central and peripheral roles
+//!
+//! The [`central`] module combines discovery, connecting and reconnecting
+//! devices through the management API with a GATT client, for applications
+//! that act as an LE central. The [`peripheral`] module combines advertising with a GATT
+//! server and listening sockets, for applications that act as an LE
+//! peripheral.
+//!
+//! # Raw HCI
+//!
+//! The [`hci`] module sends HCI commands directly to a controller, for the
+//! few values that the management API does not expose.
+//!
+//! # Blocking API
+//!
+//! With the `blocking` feature, the [`blocking`] module wraps the most
+//! common types for applications that do not use an async runtime.
+//!
+//! # Async runtimes
+//!
+//! The sockets in this library are driven by tokio by default, so they have
+//! to be created and used inside of a tokio runtime. The management API can
+//! be driven by another runtime, such as async-std or smol, through the
+//! [`runtime`] module. Otherwise, applications that use another runtime can
+//! run the futures of this library on a tokio runtime of their own, or use
+//! the `blocking` feature on a separate thread.
+//!
 //! # Permissions
 //! Commands that just query information, such as
 //! [`get_controller_info`](crate::management::get_controller_info),
 //! will usually work. However, commands that try to change any settings, such
 //! as
 //! [`set_powered`](crate::management::set_powered)
-//! will fail with 'permission denied' errors if your process does not have the
-//! `CAP_NET_ADMIN` capability.
+//! will fail with
+//! [`MissingCapability`](crate::management::Error::MissingCapability) errors
+//! if your process does not have the `CAP_NET_ADMIN` capability.
+//! [`check_permissions`](crate::management::check_permissions) checks this
+//! up front.
+//!
+//! # Features
+//!
+//! The `management`, `communication` and `sdp` features are enabled by
+//! default. Tools that only control the controllers can turn off the default
+//! features and enable just `management`, which leaves out L2CAP, RFCOMM, SDP
+//! and the profiles that run over them. `communication` needs `management`,
+//! because streams are bound to controllers and read connection information
+//! through the management socket, and `sdp` needs `communication`. The
+//! management socket itself is driven by tokio as well, so tokio's `net`
+//! feature is needed in any case.
+//!
+//! # Errors
+//! Each module has its own error type. All of them can be converted into
+//! [`Error`], which sorts them into failure classes such as I/O errors and
+//! invalid data, so applications can handle failures from different modules
+//! in the same way.
 
 #[macro_use]
 extern crate num_derive;
@@ -30,9 +79,32 @@ extern crate num_derive;
 extern crate thiserror;
 
 pub use address::*;
+pub use error::Error;
+pub use uuid::*;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "management")]
+pub mod capture;
+#[cfg(feature = "communication")]
+pub mod central;
+#[cfg(feature = "communication")]
 pub mod communication;
+#[cfg(feature = "management")]
+pub mod hci;
+#[cfg(feature = "management")]
 pub mod management;
+#[cfg(feature = "communication")]
+pub mod peripheral;
+#[cfg(feature = "management")]
+pub mod runtime;
+#[cfg(feature = "management")]
+pub mod security;
+#[cfg(all(feature = "management", any(test, feature = "test-util")))]
+pub mod testing;
 
 mod address;
+mod error;
+#[cfg(feature = "management")]
 mod util;
+mod uuid;
@@ -1,3 +1,8 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
 use super::*;
 use crate::util::BufExt;
 use enumflags2::{bitflags, BitFlags};
@@ -101,21 +106,11 @@ pub async fn add_advertising(
     info: AdvertisingParams,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<u8> {
-    let mut param = BytesMut::with_capacity(11 + info.adv_data.len() + info.scan_rsp.len());
-    param.put_u8(info.instance);
-    param.put_u32_le(info.flags.bits());
-    param.put_u16_le(info.duration);
-    param.put_u16_le(info.timeout);
-    param.put_u8(info.adv_data.len() as u8);
-    param.put_u8(info.scan_rsp.len() as u8);
-    param.put_slice(&info.adv_data[..]);
-    param.put_slice(&info.scan_rsp[..]);
-
     let (_, param) = exec_command(
         socket,
         Command::AddAdvertising,
         controller,
-        Some(param.freeze()),
+        Some(encode_add_advertising(&info)),
         event_tx,
     )
     .await?;
@@ -123,6 +118,19 @@ pub async fn add_advertising(
     Ok(param.ok_or(Error::NoData)?.get_u8())
 }
 
+pub(crate) fn encode_add_advertising(info: &AdvertisingParams) -> Bytes {
+    let mut param = BytesMut::with_capacity(11 + info.adv_data.len() + info.scan_rsp.len());
+    param.put_u8(info.instance);
+    param.put_u32_le(info.flags.bits());
+    param.put_u16_le(info.duration);
+    param.put_u16_le(info.timeout);
+    param.put_u8(info.adv_data.len() as u8);
+    param.put_u8(info.scan_rsp.len() as u8);
+    param.put_slice(&info.adv_data[..]);
+    param.put_slice(&info.scan_rsp[..]);
+    param.freeze()
+}
+
 ///	This command is used to remove an advertising instance that
 ///	can be used to switch a Bluetooth Low Energy controller into
 ///	advertising mode.
@@ -149,14 +157,11 @@ pub async fn remove_advertising(
     instance: u8,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<u8> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(instance);
-
     let (_, param) = exec_command(
         socket,
         Command::RemoveAdvertising,
         controller,
-        Some(param.freeze()),
+        Some(encode_remove_advertising(instance)),
         event_tx,
     )
     .await?;
@@ -164,6 +169,10 @@ pub async fn remove_advertising(
     Ok(param.ok_or(Error::NoData)?.get_u8())
 }
 
+pub(crate) fn encode_remove_advertising(instance: u8) -> Bytes {
+    u8_bytes(instance)
+}
+
 ///	The Read Advertising Features command returns the overall maximum
 ///	size of advertising data and scan response data fields. That size is
 ///	valid when no Flags are used. However when certain Flags are used,
@@ -181,16 +190,14 @@ pub async fn get_advertising_size(
     socket: &mut ManagementStream,
     controller: Controller,
     instance: u8,
+    flags: BitFlags<AdvertisingFlags>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<AdvertisingSizeInfo> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(instance);
-
     let (_, param) = exec_command(
         socket,
         Command::GetAdvertisingSizeInfo,
         controller,
-        Some(param.freeze()),
+        Some(encode_get_advertising_size(instance, flags)),
         event_tx,
     )
     .await?;
@@ -204,6 +211,16 @@ pub async fn get_advertising_size(
     })
 }
 
+pub(crate) fn encode_get_advertising_size(
+    instance: u8,
+    flags: BitFlags<AdvertisingFlags>,
+) -> Bytes {
+    let mut param = BytesMut::with_capacity(5);
+    param.put_u8(instance);
+    param.put_u32_le(flags.bits());
+    param.freeze()
+}
+
 pub struct AdvertisingFeaturesInfo {
     pub supported_flags: BitFlags<AdvertisingFlags>,
     pub max_adv_data_len: u8,
@@ -219,6 +236,7 @@ pub struct AdvertisingSizeInfo {
     pub max_scan_rsp_len: u8,
 }
 
+#[derive(Debug, Clone)]
 pub struct AdvertisingParams {
     pub instance: u8,
 
@@ -331,3 +349,548 @@ pub enum AdvertisingFlags {
     /// Indicates support for advertising in secondary channel in LE CODED PHY.
     SecondaryChannelLECoded = 1 << 9,
 }
+
+/// The advertising and scan response data of an instance that is rotated by
+/// an [`AdvertisingScheduler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvertisingPayload {
+    pub adv_data: Vec<u8>,
+    pub scan_rsp: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceState {
+    /// The instance was added by the scheduler and is being advertised in
+    /// turn with the other instances.
+    Active,
+    /// The timeout of the instance passed, and the scheduler will add it
+    /// again with its next payload.
+    Expired,
+    /// The instance was added through another management socket, so its
+    /// parameters are not known.
+    External,
+}
+
+/// An advertising instance that is tracked by an [`AdvertisingScheduler`].
+#[derive(Debug, Clone)]
+pub struct ScheduledInstance {
+    instance: u8,
+    params: Option<AdvertisingParams>,
+    payloads: Vec<AdvertisingPayload>,
+    next_payload: usize,
+    renew: bool,
+    state: InstanceState,
+    expires_at: Option<Instant>,
+}
+
+impl ScheduledInstance {
+    pub fn instance(&self) -> u8 {
+        self.instance
+    }
+
+    pub fn state(&self) -> InstanceState {
+        self.state
+    }
+
+    /// The parameters that the instance was last added with, or `None` if
+    /// it was added through another management socket.
+    pub fn params(&self) -> Option<&AdvertisingParams> {
+        self.params.as_ref()
+    }
+
+    /// When the timeout of the instance passes, if it has one.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    /// How long the instance is advertised before the controller switches to
+    /// the next instance, if there is more than one.
+    pub fn slot_duration(&self) -> Option<Duration> {
+        self.params.as_ref().map(|params| match params.duration {
+            0 => Duration::from_secs(2),
+            duration => Duration::from_secs(duration as u64),
+        })
+    }
+
+    /// How many times the instance is scheduled before its timeout passes,
+    /// if there is more than one instance. The last slot is shortened to end
+    /// at the timeout, so an instance with a timeout of 5 seconds and a
+    /// duration of 2 seconds is scheduled 3 times. Returns `None` if the
+    /// instance has no timeout.
+    pub fn slots(&self) -> Option<u32> {
+        let timeout = self.params.as_ref()?.timeout as u64;
+        let duration = self.slot_duration()?.as_secs();
+
+        match timeout {
+            0 => None,
+            timeout => Some(timeout.div_ceil(duration) as u32),
+        }
+    }
+}
+
+/// A change to the advertising instances of an [`AdvertisingScheduler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingUpdate {
+    /// An instance was added through another management socket.
+    Added(u8),
+    /// An instance was removed through another management socket, or its
+    /// timeout passed and it is not renewed.
+    Removed(u8),
+    /// The timeout of an instance that was added with
+    /// [`AdvertisingScheduler::add_rotating`] passed, and it was added again
+    /// with its next payload.
+    Renewed(u8),
+}
+
+/// How long after the expected expiry of an instance the scheduler waits for
+/// the kernel to report that it was removed before it renews the instance
+/// anyway. The kernel and the scheduler start their timers at slightly
+/// different times.
+const EXPIRY_GRACE: Duration = Duration::from_secs(1);
+
+/// Keeps track of the advertising instances of one controller.
+///
+/// The kernel advertises each instance for its `duration` in turn and
+/// removes instances whose `timeout` has passed, as described in
+/// [`add_advertising`]. The scheduler follows this using Advertising Added
+/// and Advertising Removed events, so that applications can see which
+/// instances exist, and adds instances that were added with
+/// [`AdvertisingScheduler::add_rotating`] again when their timeout passes,
+/// with the next of their payloads.
+///
+/// Like [`DiscoverySession`], the scheduler only renews instances while
+/// [`AdvertisingScheduler::run`] is being awaited.
+#[derive(Debug)]
+pub struct AdvertisingScheduler {
+    controller: Controller,
+    instances: BTreeMap<u8, ScheduledInstance>,
+}
+
+impl AdvertisingScheduler {
+    pub fn new(controller: Controller) -> Self {
+        Self {
+            controller,
+            instances: BTreeMap::new(),
+        }
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    /// The instances that are known to the scheduler, ordered by their
+    /// identifiers.
+    pub fn instances(&self) -> impl Iterator<Item = &ScheduledInstance> {
+        self.instances.values()
+    }
+
+    pub fn instance(&self, instance: u8) -> Option<&ScheduledInstance> {
+        self.instances.get(&instance)
+    }
+
+    /// Adds an advertising instance using [`add_advertising`], and returns
+    /// its identifier.
+    pub async fn add(
+        &mut self,
+        socket: &mut ManagementStream,
+        params: AdvertisingParams,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8> {
+        self.insert(socket, params, vec![], false, event_tx).await
+    }
+
+    /// Adds an advertising instance which is added again every time its
+    /// timeout passes, cycling through `payloads`. The instance is first
+    /// added with the first payload, replacing the data in `params`. With a
+    /// timeout of 0, the instance never expires and only the first payload
+    /// is used.
+    ///
+    /// If `payloads` is empty, the data in `params` is used every time.
+    pub async fn add_rotating(
+        &mut self,
+        socket: &mut ManagementStream,
+        params: AdvertisingParams,
+        payloads: Vec<AdvertisingPayload>,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8> {
+        self.insert(socket, params, payloads, true, event_tx).await
+    }
+
+    async fn insert(
+        &mut self,
+        socket: &mut ManagementStream,
+        params: AdvertisingParams,
+        mut payloads: Vec<AdvertisingPayload>,
+        renew: bool,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8> {
+        if payloads.is_empty() {
+            payloads.push(AdvertisingPayload {
+                adv_data: params.adv_data.clone(),
+                scan_rsp: params.scan_rsp.clone(),
+            });
+        }
+
+        let mut scheduled = ScheduledInstance {
+            instance: params.instance,
+            params: Some(params),
+            payloads,
+            next_payload: 0,
+            renew,
+            state: InstanceState::Active,
+            expires_at: None,
+        };
+
+        let instance = Self::add_next(socket, self.controller, &mut scheduled, event_tx).await?;
+        scheduled.instance = instance;
+        self.instances.insert(instance, scheduled);
+        Ok(instance)
+    }
+
+    /// Removes an advertising instance using [`remove_advertising`]. An
+    /// `instance` of 0 removes all instances.
+    pub async fn remove(
+        &mut self,
+        socket: &mut ManagementStream,
+        instance: u8,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8> {
+        let removed = remove_advertising(socket, self.controller, instance, event_tx).await?;
+
+        match instance {
+            0 => self.instances.clear(),
+            instance => {
+                self.instances.remove(&instance);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Updates the state of the scheduler based on an event, and returns the
+    /// change that it caused, if any. [`AdvertisingScheduler::run`] calls
+    /// this for every event it receives; call it yourself for events that
+    /// were received elsewhere.
+    pub fn handle_event(&mut self, response: &Response) -> Option<AdvertisingUpdate> {
+        if response.controller != self.controller {
+            return None;
+        }
+
+        match response.event {
+            Event::AdvertisingAdded { instance } => {
+                // an instance that is already tracked keeps its parameters
+                // and payloads, so that it is still renewed
+                if self.instances.contains_key(&instance) {
+                    return None;
+                }
+
+                self.instances.insert(
+                    instance,
+                    ScheduledInstance {
+                        instance,
+                        params: None,
+                        payloads: vec![],
+                        next_payload: 0,
+                        renew: false,
+                        state: InstanceState::External,
+                        expires_at: None,
+                    },
+                );
+                Some(AdvertisingUpdate::Added(instance))
+            }
+            Event::AdvertisingRemoved { instance } => {
+                let scheduled = self.instances.get_mut(&instance)?;
+
+                // the kernel also reports instances that it removed because
+                // their timeout passed
+                let expired = scheduled
+                    .expires_at
+                    .is_some_and(|at| Instant::now() + EXPIRY_GRACE >= at);
+
+                if expired && scheduled.renew {
+                    scheduled.state = InstanceState::Expired;
+                    None
+                } else {
+                    self.instances.remove(&instance);
+                    Some(AdvertisingUpdate::Removed(instance))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Processes events and renews instances when their timeout passes,
+    /// until the instances change, and returns the change.
+    ///
+    /// All other events received while this function is running are
+    /// forwarded to `event_tx`. If renewing an instance fails, it is no
+    /// longer tracked and the error is returned.
+    pub async fn run(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<AdvertisingUpdate> {
+        loop {
+            let expired = self
+                .instances
+                .values()
+                .find(|scheduled| scheduled.state == InstanceState::Expired)
+                .map(|scheduled| scheduled.instance);
+
+            if let Some(instance) = expired {
+                self.renew(socket, instance, event_tx.clone()).await?;
+                return Ok(AdvertisingUpdate::Renewed(instance));
+            }
+
+            let deadline = self
+                .instances
+                .values()
+                .filter(|scheduled| scheduled.renew)
+                .filter_map(|scheduled| scheduled.expires_at)
+                .min();
+
+            let response = match deadline {
+                // receive is cancel safe, see ManagementStream::receive
+                Some(at) => {
+                    match tokio::time::timeout_at(at + EXPIRY_GRACE, socket.receive()).await {
+                        Ok(response) => response?,
+                        Err(_) => {
+                            for scheduled in self.instances.values_mut() {
+                                if scheduled.renew && scheduled.expires_at == Some(at) {
+                                    scheduled.state = InstanceState::Expired;
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                }
+                None => socket.receive().await?,
+            };
+
+            if let Some(update) = self.handle_event(&response) {
+                return Ok(update);
+            }
+
+            if let Some(event_tx) = &event_tx {
+                let _ = event_tx.send(response).await;
+            }
+        }
+    }
+
+    async fn renew(
+        &mut self,
+        socket: &mut ManagementStream,
+        instance: u8,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<()> {
+        let mut scheduled = match self.instances.remove(&instance) {
+            Some(scheduled) => scheduled,
+            None => return Ok(()),
+        };
+
+        Self::add_next(socket, self.controller, &mut scheduled, event_tx).await?;
+        self.instances.insert(instance, scheduled);
+        Ok(())
+    }
+
+    /// Adds `scheduled` with its next payload.
+    async fn add_next(
+        socket: &mut ManagementStream,
+        controller: Controller,
+        scheduled: &mut ScheduledInstance,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8> {
+        let mut params = scheduled.params.clone().ok_or(Error::NoData)?;
+        let payload = &scheduled.payloads[scheduled.next_payload];
+        params.adv_data = payload.adv_data.clone();
+        params.scan_rsp = payload.scan_rsp.clone();
+        let timeout = params.timeout;
+
+        let instance = add_advertising(socket, controller, params, event_tx).await?;
+
+        scheduled.next_payload = (scheduled.next_payload + 1) % scheduled.payloads.len();
+        scheduled.state = InstanceState::Active;
+        scheduled.expires_at = match timeout {
+            0 => None,
+            timeout => Some(Instant::now() + Duration::from_secs(timeout as u64)),
+        };
+
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{MockKernel, MockScript, MOCK_CONTROLLER};
+
+    const ADVERTISING_ADDED: u16 = 0x0023;
+    const ADVERTISING_REMOVED: u16 = 0x0024;
+
+    fn rotating_params(timeout: u16) -> AdvertisingParams {
+        AdvertisingParams {
+            instance: 1,
+            flags: BitFlags::empty(),
+            duration: 0,
+            timeout,
+            adv_data: vec![],
+            scan_rsp: vec![],
+        }
+    }
+
+    fn payload(byte: u8) -> AdvertisingPayload {
+        AdvertisingPayload {
+            adv_data: vec![0x02, 0xFF, byte],
+            scan_rsp: vec![],
+        }
+    }
+
+    #[test]
+    fn instance_slots() {
+        let scheduled = ScheduledInstance {
+            instance: 1,
+            params: Some(AdvertisingParams {
+                instance: 1,
+                flags: BitFlags::empty(),
+                duration: 2,
+                timeout: 5,
+                adv_data: vec![],
+                scan_rsp: vec![],
+            }),
+            payloads: vec![],
+            next_payload: 0,
+            renew: false,
+            state: InstanceState::Active,
+            expires_at: None,
+        };
+
+        assert_eq!(scheduled.slot_duration(), Some(Duration::from_secs(2)));
+        assert_eq!(scheduled.slots(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn added_event_keeps_tracked_instances() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let script = MockScript::new().reply(Command::AddAdvertising, [0x01]);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        let mut scheduler = AdvertisingScheduler::new(controller);
+        scheduler
+            .add_rotating(&mut socket, rotating_params(10), vec![payload(1)], None)
+            .await
+            .unwrap();
+
+        let added = |instance| Response {
+            controller,
+            event: Event::AdvertisingAdded { instance },
+        };
+
+        assert_eq!(scheduler.handle_event(&added(1)), None);
+        let tracked = scheduler.instance(1).unwrap();
+        assert_eq!(tracked.state(), InstanceState::Active);
+        assert!(tracked.params().is_some());
+
+        assert_eq!(
+            scheduler.handle_event(&added(2)),
+            Some(AdvertisingUpdate::Added(2))
+        );
+        assert_eq!(
+            scheduler.instance(2).unwrap().state(),
+            InstanceState::External
+        );
+
+        drop(socket);
+        kernel.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_renews_expired_instances() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        // the kernel removes the instance as soon as it was added, which is
+        // within the grace period of its timeout of 1 second
+        let script = MockScript::new()
+            .reply(Command::AddAdvertising, [0x01])
+            .then_event(ADVERTISING_REMOVED, [0x01])
+            .reply(Command::AddAdvertising, [0x01]);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        let mut scheduler = AdvertisingScheduler::new(controller);
+        let instance = scheduler
+            .add_rotating(
+                &mut socket,
+                rotating_params(1),
+                vec![payload(1), payload(2)],
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(instance, 1);
+
+        assert_eq!(
+            scheduler.run(&mut socket, None).await.unwrap(),
+            AdvertisingUpdate::Renewed(1)
+        );
+        assert_eq!(
+            scheduler.instance(1).unwrap().state(),
+            InstanceState::Active
+        );
+
+        drop(socket);
+        let commands = kernel.await.unwrap().unwrap();
+        assert_eq!(commands.len(), 2);
+        // instance, flags, duration, timeout, data lengths, data
+        assert_eq!(&commands[0].param[11..], &[0x02, 0xFF, 0x01]);
+        assert_eq!(&commands[1].param[11..], &[0x02, 0xFF, 0x02]);
+    }
+
+    #[tokio::test]
+    async fn run_reports_external_changes() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let script = MockScript::new()
+            .reply(Command::AddAdvertising, [0x01])
+            .then_event(ADVERTISING_ADDED, [0x01])
+            .then_event(ADVERTISING_ADDED, [0x02])
+            .then_event(ADVERTISING_REMOVED, [0x01]);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        let mut scheduler = AdvertisingScheduler::new(controller);
+        scheduler
+            .add(&mut socket, rotating_params(0), None)
+            .await
+            .unwrap();
+
+        let (event_tx, mut event_rx) = mpsc::channel(4);
+
+        // the event about the tracked instance is passed on
+        assert_eq!(
+            scheduler
+                .run(&mut socket, Some(event_tx.clone()))
+                .await
+                .unwrap(),
+            AdvertisingUpdate::Added(2)
+        );
+        assert!(matches!(
+            event_rx.try_recv().unwrap().event,
+            Event::AdvertisingAdded { instance: 1 }
+        ));
+
+        // an instance without a timeout is not renewed
+        assert_eq!(
+            scheduler.run(&mut socket, Some(event_tx)).await.unwrap(),
+            AdvertisingUpdate::Removed(1)
+        );
+        assert_eq!(
+            scheduler
+                .instances()
+                .map(ScheduledInstance::instance)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        drop(socket);
+        kernel.await.unwrap().unwrap();
+    }
+}
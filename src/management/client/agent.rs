@@ -0,0 +1,608 @@
+use super::interact::get_address;
+use super::*;
+use crate::AddressType;
+
+/// Answers the authentication requests that the kernel raises while a
+/// pairing is in progress.
+///
+/// The kernel sends a Pin Code Request, User Confirmation Request or User
+/// Passkey Request event depending on the IO capabilities of both devices,
+/// and expects a reply before the pairing can continue. An agent provides
+/// those replies.
+pub trait PairingAgent {
+    /// The IO capability that should be advertised to the remote device
+    /// while pairing with this agent.
+    fn io_capability(&self) -> IoCapability;
+
+    /// Called for legacy (pre-SSP) BR/EDR pairing. Return `None` to reject
+    /// the request. If `secure` is true, a 16 byte PIN code is required (see
+    /// [`PinCode::is_secure`]).
+    fn pin_code(
+        &mut self,
+        address: Address,
+        address_type: AddressType,
+        secure: bool,
+    ) -> Option<PinCode>;
+
+    /// Called when the user should confirm that `value` is displayed on both
+    /// devices. If `confirm_hint` is true, there is no value to compare and
+    /// a simple "Yes/No" question should be asked instead. Return `true` to
+    /// accept the pairing.
+    fn confirm(
+        &mut self,
+        address: Address,
+        address_type: AddressType,
+        value: Passkey,
+        confirm_hint: bool,
+    ) -> bool;
+
+    /// Called when the user should enter the passkey that is displayed on
+    /// the remote device. Return `None` to reject the request.
+    fn passkey(&mut self, address: Address, address_type: AddressType) -> Option<Passkey>;
+
+    /// Whether the device may pair at all. The kernel completes Just Works
+    /// pairing on its own when neither device needs protection against
+    /// man-in-the-middle attacks, without asking the agent, so
+    /// [`pairing_window`] unpairs a device which is not accepted once it has
+    /// paired.
+    fn accepts(&self, _address: Address, _address_type: AddressType) -> bool {
+        true
+    }
+
+    /// Called when `passkey` should be displayed to the user so that it can
+    /// be entered on the remote device. `entered` is the number of digits
+    /// that the user has entered on the remote side so far.
+    fn display_passkey(
+        &mut self,
+        _address: Address,
+        _address_type: AddressType,
+        _passkey: Passkey,
+        _entered: u8,
+    ) {
+    }
+}
+
+/// A [`PairingAgent`] for devices without a display or keyboard, which
+/// accepts "Just Works" pairing and rejects every other kind of
+/// authentication.
+///
+/// A confirmation request is only accepted if the kernel sets `confirm_hint`,
+/// meaning that there is no value to compare. Requests that carry a value
+/// for numeric comparison are rejected, because accepting them without
+/// showing the value to the user would defeat the protection against
+/// man-in-the-middle attacks that the comparison provides.
+///
+/// With an allowlist, confirmation requests from other devices are rejected.
+/// Since the kernel does not ask before it accepts Just Works pairing with
+/// [`IoCapability::NoInputNoOutput`], [`pairing_window`] also unpairs devices
+/// that are not on the allowlist after they have paired. Pairing that was
+/// accepted outside of [`pairing_window`] is not checked.
+#[derive(Debug, Clone)]
+pub struct JustWorksPolicy {
+    io_capability: IoCapability,
+    /// The devices that pairing is accepted from, or `None` to accept any
+    /// device.
+    allowlist: Option<Vec<Address>>,
+}
+
+impl JustWorksPolicy {
+    /// Creates a policy which accepts pairing from any device, and advertises
+    /// [`IoCapability::NoInputNoOutput`].
+    pub fn new() -> Self {
+        Self {
+            io_capability: IoCapability::NoInputNoOutput,
+            allowlist: None,
+        }
+    }
+
+    /// Only accepts pairing from the devices in `addresses`.
+    pub fn with_allowlist(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.allowlist = Some(addresses.into_iter().collect());
+        self
+    }
+
+    /// Sets the IO capability that is advertised while pairing. This should
+    /// still lead to Just Works pairing, such as
+    /// [`IoCapability::DisplayOnly`] on a device whose display cannot show a
+    /// passkey.
+    pub fn with_io_capability(mut self, io_capability: IoCapability) -> Self {
+        self.io_capability = io_capability;
+        self
+    }
+
+    /// Adds `address` to the allowlist. If the policy accepted any device
+    /// before, it now only accepts `address`.
+    pub fn allow(&mut self, address: Address) {
+        self.allowlist.get_or_insert_with(Vec::new).push(address);
+    }
+
+    /// Whether pairing is accepted from `address`.
+    pub fn is_allowed(&self, address: Address) -> bool {
+        match &self.allowlist {
+            Some(allowlist) => allowlist.contains(&address),
+            None => true,
+        }
+    }
+}
+
+impl Default for JustWorksPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PairingAgent for JustWorksPolicy {
+    fn io_capability(&self) -> IoCapability {
+        self.io_capability
+    }
+
+    fn pin_code(
+        &mut self,
+        _address: Address,
+        _address_type: AddressType,
+        _secure: bool,
+    ) -> Option<PinCode> {
+        None
+    }
+
+    fn confirm(
+        &mut self,
+        address: Address,
+        _address_type: AddressType,
+        _value: Passkey,
+        confirm_hint: bool,
+    ) -> bool {
+        confirm_hint && self.is_allowed(address)
+    }
+
+    fn accepts(&self, address: Address, _address_type: AddressType) -> bool {
+        self.is_allowed(address)
+    }
+
+    fn passkey(&mut self, _address: Address, _address_type: AddressType) -> Option<Passkey> {
+        None
+    }
+}
+
+/// Sends a Pair Device command and answers the authentication events that
+/// belong to it using `agent` until the command completes. If there is no
+/// agent, every authentication request is rejected.
+pub(crate) async fn drive_pairing(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    io_capability: IoCapability,
+    mut agent: Option<&mut dyn PairingAgent>,
+    mut event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<(Address, AddressType)> {
+    socket
+        .send(Request {
+            opcode: Command::PairDevice,
+            controller,
+            param: encode_pair_device(address, address_type, io_capability),
+        })
+        .await?;
+
+    loop {
+        let response = socket.receive().await?;
+
+        let reply = match response.event {
+            Event::CommandComplete {
+                opcode: Command::PairDevice,
+                status,
+                ref param,
+            } => {
+                return match status {
+                    CommandStatus::Success => get_address(Some(param.clone())),
+                    _ => Err(
+                        Error::command_failed(Command::PairDevice, controller, status)
+                            .with_context(format!("pairing with {}", address)),
+                    ),
+                }
+            }
+
+            Event::CommandStatus {
+                opcode: Command::PairDevice,
+                status,
+            } if !matches!(status, CommandStatus::Success) => {
+                return Err(
+                    Error::command_failed(Command::PairDevice, controller, status)
+                        .with_context(format!("pairing with {}", address)),
+                )
+            }
+
+            ref event
+                if response.controller == controller
+                    && authentication_address(event).map(|(address, _)| address)
+                        == Some(address) =>
+            {
+                answer_authentication(&mut agent, event)
+            }
+
+            // replies to the authentication requests complete on their own;
+            // the outcome of the pairing is reported by the Pair Device command
+            Event::CommandComplete { opcode, .. } if is_authentication_reply(opcode) => None,
+
+            _ => {
+                if let Some(event_tx) = &mut event_tx {
+                    let _ = event_tx.send(response).await;
+                }
+
+                None
+            }
+        };
+
+        if let Some((opcode, param)) = reply {
+            socket
+                .send(Request {
+                    opcode,
+                    controller,
+                    param,
+                })
+                .await?;
+        }
+    }
+}
+
+/// Wraps an agent to remember whether it declined a request, so that a
+/// failed pairing can be blamed on the user instead of the remote device.
+pub(crate) struct RecordingAgent<'a> {
+    pub(crate) inner: &'a mut dyn PairingAgent,
+    pub(crate) declined: bool,
+}
+
+impl PairingAgent for RecordingAgent<'_> {
+    fn io_capability(&self) -> IoCapability {
+        self.inner.io_capability()
+    }
+
+    fn pin_code(
+        &mut self,
+        address: Address,
+        address_type: AddressType,
+        secure: bool,
+    ) -> Option<PinCode> {
+        let pin_code = self.inner.pin_code(address, address_type, secure);
+        self.declined |= pin_code.is_none();
+        pin_code
+    }
+
+    fn confirm(
+        &mut self,
+        address: Address,
+        address_type: AddressType,
+        value: Passkey,
+        confirm_hint: bool,
+    ) -> bool {
+        let accept = self
+            .inner
+            .confirm(address, address_type, value, confirm_hint);
+        self.declined |= !accept;
+        accept
+    }
+
+    fn passkey(&mut self, address: Address, address_type: AddressType) -> Option<Passkey> {
+        let passkey = self.inner.passkey(address, address_type);
+        self.declined |= passkey.is_none();
+        passkey
+    }
+
+    fn accepts(&self, address: Address, address_type: AddressType) -> bool {
+        self.inner.accepts(address, address_type)
+    }
+
+    fn display_passkey(
+        &mut self,
+        address: Address,
+        address_type: AddressType,
+        passkey: Passkey,
+        entered: u8,
+    ) {
+        self.inner
+            .display_passkey(address, address_type, passkey, entered)
+    }
+}
+
+/// Returns the device that an authentication request is for, or `None` if
+/// `event` is not an authentication request.
+pub(crate) fn authentication_address(event: &Event) -> Option<(Address, AddressType)> {
+    match *event {
+        Event::PinCodeRequest {
+            address,
+            address_type,
+            ..
+        }
+        | Event::UserConfirmationRequest {
+            address,
+            address_type,
+            ..
+        }
+        | Event::UserPasskeyRequest {
+            address,
+            address_type,
+        }
+        | Event::PasskeyNotify {
+            address,
+            address_type,
+            ..
+        } => Some((address, address_type)),
+        _ => None,
+    }
+}
+
+/// Whether `opcode` is one of the commands that reply to authentication
+/// requests.
+pub(crate) fn is_authentication_reply(opcode: Command) -> bool {
+    matches!(
+        opcode,
+        Command::PinCodeReply
+            | Command::PinCodeNegativeReply
+            | Command::UserConfirmationReply
+            | Command::UserConfirmationNegativeReply
+            | Command::UserPasskeyReply
+            | Command::UserPasskeyNegativeReply
+    )
+}
+
+/// Asks `agent` how to answer an authentication request, and returns the
+/// command which sends the answer. If there is no agent, the request is
+/// rejected. Returns `None` for events which do not need an answer.
+pub(crate) fn answer_authentication(
+    agent: &mut Option<&mut dyn PairingAgent>,
+    event: &Event,
+) -> Option<(Command, Bytes)> {
+    match *event {
+        Event::PinCodeRequest {
+            address,
+            address_type,
+            secure,
+        } => {
+            let pin_code = agent
+                .as_mut()
+                .and_then(|agent| agent.pin_code(address, address_type, secure));
+
+            Some(encode_pin_code_reply(address, address_type, pin_code))
+        }
+
+        Event::UserConfirmationRequest {
+            address,
+            address_type,
+            confirm_hint,
+            value,
+        } => {
+            let accept = agent
+                .as_mut()
+                .is_some_and(|agent| agent.confirm(address, address_type, value, confirm_hint));
+
+            Some(encode_user_confirmation_reply(
+                address,
+                address_type,
+                accept,
+            ))
+        }
+
+        Event::UserPasskeyRequest {
+            address,
+            address_type,
+        } => {
+            let passkey = agent
+                .as_mut()
+                .and_then(|agent| agent.passkey(address, address_type));
+
+            Some(encode_user_passkey_reply(address, address_type, passkey))
+        }
+
+        Event::PasskeyNotify {
+            address,
+            address_type,
+            passkey,
+            entered,
+        } => {
+            if let Some(agent) = agent.as_mut() {
+                agent.display_passkey(address, address_type, passkey, entered);
+            }
+
+            None
+        }
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::management::{pair_device_with_options, CommandOptions, PairingError};
+    use crate::testing::mock::{
+        address_param, converse, MockKernel, MOCK_ADDRESS, MOCK_CONTROLLER,
+    };
+
+    /// A User Confirmation Request for the mock device, without a value to
+    /// compare if `value` is `None`.
+    fn confirmation_request(value: Option<u32>) -> Vec<u8> {
+        let mut event = address_param(MOCK_ADDRESS, AddressType::LEPublic);
+        event.push(value.is_none() as u8);
+        event.extend_from_slice(&value.unwrap_or(0).to_le_bytes());
+        event
+    }
+
+    #[tokio::test]
+    async fn just_works_confirmation() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let reply = address_param(MOCK_ADDRESS, AddressType::LEPublic);
+        let mut agent = JustWorksPolicy::new();
+
+        let kernel = async {
+            let pair = kernel.receive_command().await.unwrap();
+            assert_eq!(pair.opcode, Command::PairDevice);
+
+            kernel
+                .send_event(MOCK_CONTROLLER, 0x000F, &confirmation_request(None))
+                .await
+                .unwrap();
+
+            let confirm = kernel.answer(&reply).await.unwrap();
+            assert_eq!(confirm.opcode, Command::UserConfirmationReply);
+            assert_eq!(&confirm.param[..], &reply[..]);
+
+            kernel
+                .command_complete(
+                    MOCK_CONTROLLER,
+                    Command::PairDevice,
+                    CommandStatus::Success,
+                    &reply,
+                )
+                .await
+                .unwrap();
+        };
+
+        let paired = converse(
+            drive_pairing(
+                &mut socket,
+                MOCK_CONTROLLER,
+                MOCK_ADDRESS,
+                AddressType::LEPublic,
+                IoCapability::NoInputNoOutput,
+                Some(&mut agent),
+                None,
+            ),
+            kernel,
+        )
+        .await;
+        assert_eq!(paired.unwrap(), (MOCK_ADDRESS, AddressType::LEPublic));
+    }
+
+    #[tokio::test]
+    async fn rejected_comparison() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let reply = address_param(MOCK_ADDRESS, AddressType::LEPublic);
+        let mut agent = JustWorksPolicy::new();
+        let options = CommandOptions::new().with_timeout(Duration::from_secs(5));
+
+        let kernel = async {
+            // the IO capability of the controller is not changed
+            let pair = kernel.receive_command().await.unwrap();
+            assert_eq!(pair.opcode, Command::PairDevice);
+            assert_eq!(pair.param[7], IoCapability::NoInputNoOutput as u8);
+
+            // numeric comparison, which the policy refuses
+            kernel
+                .send_event(MOCK_CONTROLLER, 0x000F, &confirmation_request(Some(123456)))
+                .await
+                .unwrap();
+
+            let confirm = kernel.answer(&reply).await.unwrap();
+            assert_eq!(confirm.opcode, Command::UserConfirmationNegativeReply);
+
+            kernel
+                .command_complete(
+                    MOCK_CONTROLLER,
+                    Command::PairDevice,
+                    CommandStatus::AuthenticationFailed,
+                    &reply,
+                )
+                .await
+                .unwrap();
+        };
+
+        let paired = converse(
+            pair_device_with_options(
+                &mut socket,
+                MOCK_CONTROLLER,
+                MOCK_ADDRESS,
+                AddressType::LEPublic,
+                agent.io_capability(),
+                Some(&mut agent),
+                &options,
+                None,
+            ),
+            kernel,
+        )
+        .await;
+        assert!(matches!(paired, Err(PairingError::UserRejected { .. })));
+    }
+
+    #[tokio::test]
+    async fn authentication_failed() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let reply = address_param(MOCK_ADDRESS, AddressType::LEPublic);
+        let mut agent = JustWorksPolicy::new();
+        let options = CommandOptions::default();
+
+        let kernel = async {
+            let pair = kernel.receive_command().await.unwrap();
+            kernel
+                .command_complete(
+                    pair.controller,
+                    pair.opcode,
+                    CommandStatus::AuthenticationFailed,
+                    &reply,
+                )
+                .await
+                .unwrap();
+        };
+
+        let paired = converse(
+            pair_device_with_options(
+                &mut socket,
+                MOCK_CONTROLLER,
+                MOCK_ADDRESS,
+                AddressType::LEPublic,
+                agent.io_capability(),
+                Some(&mut agent),
+                &options,
+                None,
+            ),
+            kernel,
+        )
+        .await;
+        assert!(matches!(
+            paired,
+            Err(PairingError::AuthenticationFailed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn permission_denied() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let mut agent = JustWorksPolicy::new();
+        let options = CommandOptions::default();
+
+        let kernel = async {
+            let pair = kernel.receive_command().await.unwrap();
+            kernel
+                .command_status(
+                    pair.controller,
+                    pair.opcode,
+                    CommandStatus::PermissionDenied,
+                )
+                .await
+                .unwrap();
+        };
+
+        let paired = converse(
+            pair_device_with_options(
+                &mut socket,
+                MOCK_CONTROLLER,
+                MOCK_ADDRESS,
+                AddressType::LEPublic,
+                agent.io_capability(),
+                Some(&mut agent),
+                &options,
+                None,
+            ),
+            kernel,
+        )
+        .await;
+        assert!(matches!(
+            paired,
+            Err(PairingError::Management(Error::MissingCapability {
+                opcode: Command::PairDevice,
+                ..
+            }))
+        ));
+    }
+}
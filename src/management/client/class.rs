@@ -15,25 +15,30 @@ use super::*;
 ///	In case the controller is powered off, Unknown will be returned
 ///	for the class of device parameter. And after power on the new
 ///	value will be announced via class of device changed event.
+///
+/// Only the major and minor device class of `class` are used. The service
+/// classes are derived by the kernel from the UUIDs added using
+/// [`add_uuid`].
 pub async fn set_device_class(
     socket: &mut ManagementStream,
     controller: Controller,
-    device_class: DeviceClass,
+    class: ClassOfDevice,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<(DeviceClass, ServiceClasses)> {
-    let mut param = BytesMut::with_capacity(2);
-    param.put_u16_le(device_class.into());
-
+) -> Result<ClassOfDevice> {
     let (_, param) = exec_command(
         socket,
         Command::SetDeviceClass,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_device_class(class)),
         event_tx,
     )
     .await?;
 
-    Ok(device_class_from_bytes(param.ok_or(Error::NoData)?))
+    Ok(class_of_device_from_buf(&mut param.ok_or(Error::NoData)?))
+}
+
+pub(crate) fn encode_set_device_class(class: ClassOfDevice) -> Bytes {
+    Bytes::copy_from_slice(&[class.major(), class.minor() << 2])
 }
 
 ///	This command is used to add a UUID to be published in EIR data.
@@ -53,21 +58,24 @@ pub async fn add_uuid(
     uuid: [u8; 16],
     svc_hint: ServiceClasses,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<(DeviceClass, ServiceClasses)> {
-    let mut param = BytesMut::with_capacity(17);
-    param.put_slice(&uuid[..]);
-    param.put_u8((svc_hint.bits() >> 16) as u8);
-
+) -> Result<ClassOfDevice> {
     let (_, param) = exec_command(
         socket,
         Command::AddUUID,
         controller,
-        Some(param.freeze()),
+        Some(encode_add_uuid(uuid, svc_hint)),
         event_tx,
     )
     .await?;
 
-    Ok(device_class_from_bytes(param.ok_or(Error::NoData)?))
+    Ok(class_of_device_from_buf(&mut param.ok_or(Error::NoData)?))
+}
+
+pub(crate) fn encode_add_uuid(uuid: [u8; 16], svc_hint: ServiceClasses) -> Bytes {
+    let mut param = BytesMut::with_capacity(17);
+    param.put_slice(&uuid[..]);
+    param.put_u8((svc_hint.bits() >> 16) as u8);
+    param.freeze()
 }
 
 ///	This command is used to remove a UUID previously added using the
@@ -87,17 +95,19 @@ pub async fn remove_uuid(
     controller: Controller,
     uuid: [u8; 16],
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<(DeviceClass, ServiceClasses)> {
-    let param = BytesMut::from(&uuid[..]);
-
+) -> Result<ClassOfDevice> {
     let (_, param) = exec_command(
         socket,
         Command::RemoveUUID,
         controller,
-        Some(param.freeze()),
+        Some(encode_remove_uuid(uuid)),
         event_tx,
     )
     .await?;
 
-    Ok(device_class_from_bytes(param.ok_or(Error::NoData)?))
+    Ok(class_of_device_from_buf(&mut param.ok_or(Error::NoData)?))
+}
+
+pub(crate) fn encode_remove_uuid(uuid: [u8; 16]) -> Bytes {
+    Bytes::copy_from_slice(&uuid[..])
 }
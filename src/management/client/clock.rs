@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::*;
+use crate::AddressType;
+
+/// The Bluetooth clock has 28 bits and wraps around after about 23 hours.
+const CLOCK_MASK: u32 = 0x0FFF_FFFF;
+
+/// The length of a clock tick, which is half of a slot.
+pub const CLOCK_TICK: Duration = Duration::from_nanos(312_500);
+
+/// The difference `a - b` between two readings of the 28-bit clock, taking
+/// wrap-arounds into account.
+fn clock_diff(a: u32, b: u32) -> i32 {
+    let diff = a.wrapping_sub(b) & CLOCK_MASK;
+    // sign-extend from 28 bits
+    ((diff << 4) as i32) >> 4
+}
+
+/// A reading of the local and piconet clocks, taken by a
+/// [`ClockDriftMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSample {
+    pub local_clock: u32,
+    pub piconet_clock: u32,
+    /// The accuracy of `piconet_clock` in clock ticks, if the controller
+    /// knows it.
+    pub accuracy: Option<u16>,
+}
+
+impl ClockSample {
+    /// The offset of the piconet clock from the local clock, in clock ticks.
+    pub fn offset(&self) -> i32 {
+        clock_diff(self.piconet_clock, self.local_clock)
+    }
+}
+
+/// The drift of the piconet clock of a connection against the local clock,
+/// estimated by a [`ClockDriftMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockDrift {
+    /// The offset of the piconet clock from the local clock in the latest
+    /// sample, in clock ticks.
+    pub offset: i32,
+    /// How fast the piconet clock runs compared to the local clock, in parts
+    /// per million. Positive values mean that the piconet clock is faster.
+    pub drift_ppm: f64,
+    /// The accuracy of the latest sample, in clock ticks.
+    pub accuracy: Option<u16>,
+    /// How much the accuracy changed over the samples that the estimate is
+    /// based on, in clock ticks. Positive values mean that it got worse.
+    pub accuracy_trend: Option<i32>,
+    /// The number of samples that the estimate is based on.
+    pub samples: usize,
+}
+
+/// Samples the clocks of a connection with [`get_clock_info`] at a fixed
+/// interval, and estimates how the piconet clock drifts against the local
+/// clock, for applications that have to keep audio of several devices in
+/// sync.
+///
+/// The drift is the slope of a least-squares fit of the clock offset over
+/// the latest samples, so that single readings that are off by a tick do not
+/// dominate it. Like [`DiscoverySession`], the monitor only takes samples
+/// while [`ClockDriftMonitor::sample`] is being awaited.
+#[derive(Debug, Clone)]
+pub struct ClockDriftMonitor {
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    interval: Duration,
+    window: usize,
+    samples: VecDeque<ClockSample>,
+    next_sample: Option<Instant>,
+}
+
+impl ClockDriftMonitor {
+    /// Creates a monitor for the connection to `address`, which takes a
+    /// sample every `interval` and bases its estimate on the latest 16
+    /// samples.
+    pub fn new(
+        controller: Controller,
+        address: Address,
+        address_type: AddressType,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            controller,
+            address,
+            address_type,
+            interval,
+            window: 16,
+            samples: VecDeque::new(),
+            next_sample: None,
+        }
+    }
+
+    /// Sets the number of samples that the estimate is based on, which is at
+    /// least 2.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(2);
+        self
+    }
+
+    pub fn samples(&self) -> &VecDeque<ClockSample> {
+        &self.samples
+    }
+
+    /// Waits until the next sample is due, takes it and returns the updated
+    /// estimate. The estimate is `None` until there are at least two
+    /// samples.
+    ///
+    /// This fails with a [`CommandError`](Error::CommandError) if the device
+    /// is not connected.
+    pub async fn sample(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<Option<ClockDrift>> {
+        if let Some(at) = self.next_sample {
+            tokio::time::sleep_until(at).await;
+        }
+
+        self.next_sample = Some(Instant::now() + self.interval);
+
+        let info = get_clock_info(
+            socket,
+            self.controller,
+            self.address,
+            self.address_type,
+            event_tx,
+        )
+        .await?;
+
+        self.push(ClockSample {
+            local_clock: info.local_clock,
+            piconet_clock: info.piconet_clock.ok_or(Error::NoData)?,
+            accuracy: info.accuracy,
+        });
+
+        Ok(self.estimate())
+    }
+
+    /// Adds a sample that was taken elsewhere.
+    pub fn push(&mut self, sample: ClockSample) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    /// Estimates the drift from the samples that have been taken, or returns
+    /// `None` if there are less than two.
+    pub fn estimate(&self) -> Option<ClockDrift> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+
+        if self.samples.len() < 2 {
+            return None;
+        }
+
+        // the time of each sample on the local clock and the change of the
+        // offset since the first sample, both in ticks
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|sample| {
+                (
+                    clock_diff(sample.local_clock, first.local_clock) as f64,
+                    (sample.offset() - first.offset()) as f64,
+                )
+            })
+            .collect();
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+        let (covariance, variance) = points.iter().fold((0.0, 0.0), |(cov, var), (x, y)| {
+            (
+                cov + (x - mean_x) * (y - mean_y),
+                var + (x - mean_x) * (x - mean_x),
+            )
+        });
+
+        let drift_ppm = if variance > 0.0 {
+            covariance / variance * 1_000_000.0
+        } else {
+            0.0
+        };
+
+        let accuracy_trend = match (first.accuracy, last.accuracy) {
+            (Some(first), Some(last)) => Some(last as i32 - first as i32),
+            _ => None,
+        };
+
+        Some(ClockDrift {
+            offset: last.offset(),
+            drift_ppm,
+            accuracy: last.accuracy,
+            accuracy_trend,
+            samples: self.samples.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_estimate() {
+        let mut monitor = ClockDriftMonitor::new(
+            Controller(0),
+            Address::zero(),
+            AddressType::BREDR,
+            Duration::from_secs(1),
+        )
+        .with_window(4);
+
+        // the piconet clock gains a tick every 10000 ticks, and the local
+        // clock wraps around in between
+        let start = CLOCK_MASK - 15_000;
+        for i in 0..5u32 {
+            let local = start.wrapping_add(i * 10_000) & CLOCK_MASK;
+            monitor.push(ClockSample {
+                local_clock: local,
+                piconet_clock: local.wrapping_add(100 + i) & CLOCK_MASK,
+                accuracy: Some(2 + i as u16),
+            });
+        }
+
+        let drift = monitor.estimate().unwrap();
+        assert_eq!(drift.samples, 4);
+        assert_eq!(drift.offset, 104);
+        assert!((drift.drift_ppm - 100.0).abs() < 1e-6);
+        assert_eq!(drift.accuracy_trend, Some(3));
+    }
+}
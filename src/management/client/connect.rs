@@ -0,0 +1,658 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use enumflags2::BitFlags;
+
+use super::*;
+use crate::AddressType;
+
+/// Options for [`connect_device`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectOptions {
+    /// If set, the device will be paired after it has connected, using this
+    /// IO capability. When an agent is provided to [`connect_device`], this
+    /// should usually be the agent's IO capability.
+    pub pair: Option<IoCapability>,
+
+    /// How long to wait for the whole workflow to finish. `None` waits
+    /// forever.
+    pub timeout: Option<Duration>,
+}
+
+/// A device that was connected using [`connect_device`].
+#[derive(Debug)]
+pub struct ConnectedDevice {
+    pub controller: Controller,
+    pub address: Address,
+    pub address_type: AddressType,
+
+    /// The flags from the Device Connected event. Empty if the device was
+    /// already connected.
+    pub flags: BitFlags<DeviceFlag>,
+
+    /// The EIR data from the Device Connected event. Empty if the device was
+    /// already connected.
+    pub eir_data: Bytes,
+
+    /// Whether the device was paired as part of connecting.
+    pub paired: bool,
+}
+
+impl ConnectedDevice {
+    /// Disconnects this device. The device stays in the action list of the
+    /// controller, so it will be reconnected automatically unless it is
+    /// removed as well (see [`ConnectedDevice::forget`]).
+    pub async fn disconnect(
+        &self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<()> {
+        disconnect(
+            socket,
+            self.controller,
+            self.address,
+            self.address_type,
+            event_tx,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes this device from the action list of the controller, so that it
+    /// is no longer connected automatically, and then disconnects it.
+    pub async fn forget(
+        &self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<()> {
+        remove_device(
+            socket,
+            self.controller,
+            self.address,
+            self.address_type,
+            event_tx.clone(),
+        )
+        .await?;
+
+        self.disconnect(socket, event_tx).await
+    }
+}
+
+/// Connects to a device and optionally pairs with it.
+///
+/// For LE devices, the device is added to the action list of the controller
+/// with the `AutoConnect` action, and this function waits until the kernel
+/// reports that the device has connected. The device stays in the action list
+/// afterwards, so the kernel will reconnect it when it becomes available
+/// again.
+///
+/// For BR/EDR devices, the kernel only creates a connection as part of
+/// pairing, so `options.pair` has to be set. The device is added with the
+/// `AllowConnect` action so that it may reconnect on its own later on. If
+/// `options.pair` is not set, this function fails with Invalid Parameters,
+/// which is what the kernel would answer to an `AutoConnect` request for a
+/// BR/EDR device.
+///
+/// If the device is already connected, the connection step is skipped.
+///
+/// Authentication requests raised during pairing are answered by `agent`. If
+/// no agent is provided, they are rejected, so only pairing methods that do
+/// not involve the user will succeed.
+///
+/// Events that are received while this function is running and are not part
+/// of the workflow are forwarded to `event_tx`.
+pub async fn connect_device(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    options: ConnectOptions,
+    agent: Option<&mut dyn PairingAgent>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ConnectedDevice> {
+    let workflow = connect_device_inner(
+        socket,
+        controller,
+        address,
+        address_type,
+        options,
+        agent,
+        event_tx,
+    );
+
+    match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, workflow)
+            .await
+            .map_err(|_| Error::TimedOut)?,
+        None => workflow.await,
+    }
+}
+
+/// The transports over which a device has been found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FoundDevice {
+    /// Whether the device answered a BR/EDR inquiry.
+    pub bredr: bool,
+    /// The address type that the device advertised with over LE, if it was
+    /// found over LE.
+    pub le_address_type: Option<AddressType>,
+    /// Whether any of the LE advertisements of the device accepted
+    /// connections, which the kernel reports with the Not Connectable flag of
+    /// the Device Found event.
+    pub le_connectable: bool,
+}
+
+impl FoundDevice {
+    /// The address type to connect to the device with. Devices which were
+    /// found over BR/EDR are connected over BR/EDR, since dual-mode devices
+    /// usually expect their classic profiles to be used; other devices are
+    /// connected over LE if they accept connections there.
+    pub fn best_address_type(&self) -> Option<AddressType> {
+        if self.bredr {
+            Some(AddressType::BREDR)
+        } else {
+            self.connectable_le_address_type()
+        }
+    }
+
+    fn connectable_le_address_type(&self) -> Option<AddressType> {
+        self.le_address_type.filter(|_| self.le_connectable)
+    }
+}
+
+/// Remembers the transports over which devices have been found, from the
+/// Device Found events that are passed to [`DeviceCache::handle_event`], so
+/// that they can be connected with the right address type. See
+/// [`connect_best`].
+#[derive(Debug, Clone, Default)]
+pub struct DeviceCache {
+    devices: HashMap<(Controller, Address), FoundDevice>,
+}
+
+impl DeviceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the device of a Device Found event. Other events are ignored.
+    pub fn handle_event(&mut self, response: &Response) {
+        if let Event::DeviceFound {
+            address,
+            address_type,
+            flags,
+            ..
+        } = response.event
+        {
+            let device = self
+                .devices
+                .entry((response.controller, address))
+                .or_default();
+
+            match address_type {
+                AddressType::BREDR => device.bredr = true,
+                address_type => {
+                    device.le_address_type = Some(address_type);
+                    // scan responses of connectable advertisements do not
+                    // make the device unconnectable
+                    device.le_connectable |= !flags.contains(DeviceFlag::NotConnectable);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, controller: Controller, address: Address) -> Option<&FoundDevice> {
+        self.devices.get(&(controller, address))
+    }
+
+    /// The address type to connect to `address` with, or `None` if it has not
+    /// been found on `controller`. See [`FoundDevice::best_address_type`].
+    pub fn best_address_type(
+        &self,
+        controller: Controller,
+        address: Address,
+    ) -> Option<AddressType> {
+        self.get(controller, address)
+            .and_then(FoundDevice::best_address_type)
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+}
+
+/// Connects to a device like [`connect_device`], using the address type that
+/// `cache` recorded for it instead of one that is passed in. This avoids the
+/// Connect Failed errors that come from connecting to an LE-only device with
+/// the BR/EDR address type, or the other way around.
+///
+/// A device that was found over both transports is connected over BR/EDR,
+/// unless `options.pair` is not set, because BR/EDR devices can only be
+/// connected by pairing; it is then connected over LE. Fails with
+/// [`Error::DeviceNotFound`] if the device has not been found, and with
+/// [`Error::DeviceNotConnectable`] if it has only been found through LE
+/// advertisements which do not accept connections.
+pub async fn connect_best(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    address: Address,
+    cache: &DeviceCache,
+    options: ConnectOptions,
+    agent: Option<&mut dyn PairingAgent>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ConnectedDevice> {
+    let device = cache
+        .get(controller, address)
+        .copied()
+        .ok_or(Error::DeviceNotFound {
+            controller,
+            address,
+        })?;
+
+    let address_type = match (options.pair, device.connectable_le_address_type()) {
+        (None, Some(le_address_type)) => Some(le_address_type),
+        _ => device.best_address_type(),
+    };
+
+    let address_type = address_type.ok_or(Error::DeviceNotConnectable {
+        controller,
+        address,
+    })?;
+
+    connect_device(
+        socket,
+        controller,
+        address,
+        address_type,
+        options,
+        agent,
+        event_tx,
+    )
+    .await
+}
+
+async fn connect_device_inner(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    options: ConnectOptions,
+    agent: Option<&mut dyn PairingAgent>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ConnectedDevice> {
+    let mut device = ConnectedDevice {
+        controller,
+        address,
+        address_type,
+        flags: BitFlags::empty(),
+        eir_data: Bytes::new(),
+        paired: false,
+    };
+
+    let connected = get_connections(socket, controller, event_tx.clone())
+        .await?
+        .contains(&(address, address_type));
+
+    if address_type == AddressType::BREDR {
+        let io_capability = options.pair.ok_or_else(|| Error::CommandError {
+            opcode: Command::AddDevice,
+            controller,
+            status: CommandStatus::InvalidParams,
+            context: Some(format!(
+                "BR/EDR device {} can only be connected by pairing",
+                address
+            )),
+        })?;
+
+        // a device that is already connected only has to be paired
+        if !connected {
+            add_device(
+                socket,
+                controller,
+                address,
+                address_type,
+                AddDeviceAction::AllowConnect,
+                event_tx.clone(),
+            )
+            .await?;
+        }
+
+        pair(socket, &mut device, io_capability, agent, event_tx).await?;
+        return Ok(device);
+    }
+
+    if !connected {
+        add_device(
+            socket,
+            controller,
+            address,
+            address_type,
+            AddDeviceAction::AutoConnect,
+            event_tx.clone(),
+        )
+        .await?;
+
+        let connection = wait_for_event(
+            socket,
+            |response| {
+                if response.controller != controller {
+                    return None;
+                }
+
+                match &response.event {
+                    Event::DeviceConnected {
+                        address: evt_address,
+                        flags,
+                        eir_data,
+                        ..
+                    } if *evt_address == address => Some(Ok((*flags, eir_data.clone()))),
+                    Event::ConnectFailed {
+                        address: evt_address,
+                        status,
+                        ..
+                    } if *evt_address == address => Some(Err(Error::command_failed(
+                        Command::AddDevice,
+                        controller,
+                        CommandStatus::from(*status),
+                    )
+                    .with_context(format!("connecting to {}", address)))),
+                    _ => None,
+                }
+            },
+            event_tx.clone(),
+        )
+        .await?;
+
+        let (flags, eir_data) = connection?;
+        device.flags = flags;
+        device.eir_data = eir_data;
+    }
+
+    if let Some(io_capability) = options.pair {
+        pair(socket, &mut device, io_capability, agent, event_tx).await?;
+    }
+
+    Ok(device)
+}
+
+async fn pair(
+    socket: &mut ManagementStream,
+    device: &mut ConnectedDevice,
+    io_capability: IoCapability,
+    agent: Option<&mut dyn PairingAgent>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<()> {
+    match drive_pairing(
+        socket,
+        device.controller,
+        device.address,
+        device.address_type,
+        io_capability,
+        agent,
+        event_tx,
+    )
+    .await
+    {
+        // the kernel reports the identity address if it learned one while
+        // pairing
+        Ok((address, address_type)) => {
+            device.address = address;
+            device.address_type = address_type;
+        }
+        Err(Error::CommandError {
+            status: CommandStatus::AlreadyPaired,
+            ..
+        }) => {}
+        Err(err) => return Err(err),
+    }
+
+    device.paired = true;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{
+        address_param, MockScript, MockTransport, MOCK_ADDRESS, MOCK_CONTROLLER,
+    };
+
+    /// The reply to Get Connections when the mock device is connected.
+    fn connections() -> Vec<u8> {
+        [
+            &[0x01, 0x00][..],
+            &address_param(MOCK_ADDRESS, AddressType::LEPublic),
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn le_auto_connect() {
+        let address = MOCK_ADDRESS;
+
+        // no flags, and the flags of the advertisement as eir data
+        let mut connected = address_param(address, AddressType::LEPublic);
+        connected.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x02, 0x01, 0x06]);
+
+        let script = MockScript::new()
+            .reply(Command::GetConnections, [0x00, 0x00])
+            .reply(
+                Command::AddDevice,
+                address_param(address, AddressType::LEPublic),
+            )
+            .then_event(0x000B, connected);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let device = connect_device(
+            &mut socket,
+            MOCK_CONTROLLER,
+            address,
+            AddressType::LEPublic,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(device.address, address);
+        assert_eq!(device.address_type, AddressType::LEPublic);
+        assert_eq!(&device.eir_data[..], &[0x02, 0x01, 0x06]);
+        assert!(!device.paired);
+
+        let commands = transport.commands();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1].opcode, Command::AddDevice);
+        // the AutoConnect action
+        assert_eq!(commands[1].param[7], 0x02);
+    }
+
+    #[tokio::test]
+    async fn already_connected() {
+        let address = MOCK_ADDRESS;
+
+        let script = MockScript::new().reply(Command::GetConnections, connections());
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let device = connect_device(
+            &mut socket,
+            MOCK_CONTROLLER,
+            address,
+            AddressType::LEPublic,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(device.flags.is_empty());
+        assert!(device.eir_data.is_empty());
+
+        let commands = transport.commands();
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bredr_without_pairing() {
+        let script = MockScript::new().reply(Command::GetConnections, [0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let err = connect_device(
+            &mut socket,
+            MOCK_CONTROLLER,
+            MOCK_ADDRESS,
+            AddressType::BREDR,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.command_status(), Some(CommandStatus::InvalidParams));
+
+        let commands = transport.commands();
+        assert!(commands
+            .iter()
+            .all(|command| command.opcode != Command::AddDevice));
+    }
+
+    #[tokio::test]
+    async fn bredr_already_connected() {
+        let bredr = address_param(MOCK_ADDRESS, AddressType::BREDR);
+        let script = MockScript::new()
+            .reply(
+                Command::GetConnections,
+                [&[0x01, 0x00][..], &bredr].concat(),
+            )
+            .reply(Command::PairDevice, bredr.clone());
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let device = connect_device(
+            &mut socket,
+            MOCK_CONTROLLER,
+            MOCK_ADDRESS,
+            AddressType::BREDR,
+            ConnectOptions {
+                pair: Some(IoCapability::NoInputNoOutput),
+                ..ConnectOptions::default()
+            },
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(device.paired);
+
+        let opcodes: Vec<_> = transport.commands().into_iter().map(|c| c.opcode).collect();
+        assert_eq!(opcodes, [Command::GetConnections, Command::PairDevice]);
+    }
+
+    fn device_found(address_type: AddressType, flags: BitFlags<DeviceFlag>) -> Response {
+        Response {
+            event: Event::DeviceFound {
+                address: MOCK_ADDRESS,
+                address_type,
+                rssi: -60,
+                flags,
+                eir_data: Bytes::new(),
+            },
+            controller: MOCK_CONTROLLER,
+        }
+    }
+
+    #[test]
+    fn cache_uses_device_found_flags() {
+        let mut cache = DeviceCache::new();
+        let address = MOCK_ADDRESS;
+
+        cache.handle_event(&device_found(
+            AddressType::LERandom,
+            DeviceFlag::NotConnectable.into(),
+        ));
+        assert_eq!(cache.best_address_type(MOCK_CONTROLLER, address), None);
+
+        // a connectable advertisement, followed by the scan response
+        cache.handle_event(&device_found(AddressType::LERandom, BitFlags::empty()));
+        cache.handle_event(&device_found(
+            AddressType::LERandom,
+            DeviceFlag::NotConnectable | DeviceFlag::ScanResponse,
+        ));
+        assert_eq!(
+            cache.best_address_type(MOCK_CONTROLLER, address),
+            Some(AddressType::LERandom)
+        );
+
+        cache.handle_event(&device_found(AddressType::BREDR, BitFlags::empty()));
+        assert_eq!(
+            cache.best_address_type(MOCK_CONTROLLER, address),
+            Some(AddressType::BREDR)
+        );
+        assert_eq!(cache.best_address_type(Controller(1), address), None);
+    }
+
+    #[tokio::test]
+    async fn connect_best_errors() {
+        let (mut socket, transport) = MockTransport::stream(MockScript::new());
+        let mut cache = DeviceCache::new();
+
+        let err = connect_best(
+            &mut socket,
+            MOCK_CONTROLLER,
+            MOCK_ADDRESS,
+            &cache,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::DeviceNotFound { .. }));
+
+        cache.handle_event(&device_found(
+            AddressType::LEPublic,
+            DeviceFlag::NotConnectable.into(),
+        ));
+        let err = connect_best(
+            &mut socket,
+            MOCK_CONTROLLER,
+            MOCK_ADDRESS,
+            &cache,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::DeviceNotConnectable { .. }));
+
+        // no command is sent for either
+        assert!(transport.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn connect_best_dual_mode_without_pairing() {
+        let mut cache = DeviceCache::new();
+        cache.handle_event(&device_found(AddressType::BREDR, BitFlags::empty()));
+        cache.handle_event(&device_found(AddressType::LEPublic, BitFlags::empty()));
+
+        let script = MockScript::new().reply(Command::GetConnections, connections());
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let device = connect_best(
+            &mut socket,
+            MOCK_CONTROLLER,
+            MOCK_ADDRESS,
+            &cache,
+            ConnectOptions::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(device.address_type, AddressType::LEPublic);
+
+        // the device is already connected, so nothing else is sent
+        assert_eq!(transport.commands().len(), 1);
+    }
+}
@@ -1,4 +1,9 @@
-use enumflags2::BitFlags;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use tokio::time::Instant;
 
 use super::*;
 use crate::util::BufExt;
@@ -24,17 +29,14 @@ use crate::util::BufExt;
 pub async fn start_discovery(
     socket: &mut ManagementStream,
     controller: Controller,
-    address_types: BitFlags<AddressTypeFlag>,
+    address_types: AddressTypes,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<BitFlags<AddressTypeFlag>> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(address_types.bits());
-
+) -> Result<AddressTypes> {
     let (_, param) = exec_command(
         socket,
         Command::StartDiscovery,
         controller,
-        Some(param.freeze()),
+        Some(encode_start_discovery(address_types)),
         event_tx,
     )
     .await?;
@@ -42,6 +44,10 @@ pub async fn start_discovery(
     Ok(param.ok_or(Error::NoData)?.get_flags_u8())
 }
 
+pub(crate) fn encode_start_discovery(address_types: AddressTypes) -> Bytes {
+    u8_bytes(address_types.bits())
+}
+
 /// This command is used to stop the discovery process started using
 ///	the Start Discovery command.
 ///
@@ -49,17 +55,14 @@ pub async fn start_discovery(
 pub async fn stop_discovery(
     socket: &mut ManagementStream,
     controller: Controller,
-    address_types: BitFlags<AddressTypeFlag>,
+    address_types: AddressTypes,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<BitFlags<AddressTypeFlag>> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(address_types.bits());
-
+) -> Result<AddressTypes> {
     let (_, param) = exec_command(
         socket,
         Command::StopDiscovery,
         controller,
-        Some(param.freeze()),
+        Some(encode_stop_discovery(address_types)),
         event_tx,
     )
     .await?;
@@ -67,6 +70,10 @@ pub async fn stop_discovery(
     Ok(param.ok_or(Error::NoData)?.get_flags_u8())
 }
 
+pub(crate) fn encode_stop_discovery(address_types: AddressTypes) -> Bytes {
+    u8_bytes(address_types.bits())
+}
+
 ///	This command is used to start the process of discovering remote
 ///	devices with a specific UUID. A Device Found event will be sent
 ///	for each discovered device.
@@ -93,25 +100,20 @@ pub async fn stop_discovery(
 pub async fn start_service_discovery(
     socket: &mut ManagementStream,
     controller: Controller,
-    address_types: BitFlags<AddressTypeFlag>,
+    address_types: AddressTypes,
     rssi_threshold: i8,
     uuids: Vec<[u8; 16]>,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<BitFlags<AddressTypeFlag>> {
-    let mut param = BytesMut::with_capacity(4 + 16 * uuids.len());
-    param.put_u8(address_types.bits());
-    param.put_i8(rssi_threshold);
-    param.put_u16_le(uuids.len() as u16);
-
-    for uuid in uuids {
-        param.put_slice(&uuid[..]);
-    }
-
+) -> Result<AddressTypes> {
     let (_, param) = exec_command(
         socket,
         Command::StartServiceDiscovery,
         controller,
-        Some(param.freeze()),
+        Some(encode_start_service_discovery(
+            address_types,
+            rssi_threshold,
+            &uuids,
+        )),
         event_tx,
     )
     .await?;
@@ -119,6 +121,23 @@ pub async fn start_service_discovery(
     Ok(param.ok_or(Error::NoData)?.get_flags_u8())
 }
 
+pub(crate) fn encode_start_service_discovery(
+    address_types: AddressTypes,
+    rssi_threshold: i8,
+    uuids: &[[u8; 16]],
+) -> Bytes {
+    let mut param = BytesMut::with_capacity(4 + 16 * uuids.len());
+    param.put_u8(address_types.bits());
+    param.put_i8(rssi_threshold);
+    param.put_u16_le(uuids.len() as u16);
+
+    for uuid in uuids {
+        param.put_slice(&uuid[..]);
+    }
+
+    param.freeze()
+}
+
 ///	This command is used to start the process of discovering remote
 ///	devices using the limited discovery procedure. A Device Found event
 ///	will be sent for each discovered device.
@@ -135,20 +154,401 @@ pub async fn start_service_discovery(
 pub async fn start_limited_discovery(
     socket: &mut ManagementStream,
     controller: Controller,
-    address_types: BitFlags<AddressTypeFlag>,
+    address_types: AddressTypes,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<BitFlags<AddressTypeFlag>> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(address_types.bits());
-
+) -> Result<AddressTypes> {
     let (_, param) = exec_command(
         socket,
         Command::StartLimitedDiscovery,
         controller,
-        Some(param.freeze()),
+        Some(encode_start_limited_discovery(address_types)),
         event_tx,
     )
     .await?;
 
     Ok(param.ok_or(Error::NoData)?.get_flags_u8())
 }
+
+pub(crate) fn encode_start_limited_discovery(address_types: AddressTypes) -> Bytes {
+    u8_bytes(address_types.bits())
+}
+
+/// How a [`DiscoverySession`] restarts discovery after the kernel ends a
+/// discovery cycle on its own.
+#[derive(Debug, Clone, Copy)]
+pub struct ContinuousDiscovery {
+    /// The delay before discovery is restarted after the first cycle that
+    /// ended.
+    pub initial_delay: Duration,
+
+    /// The delay is doubled after every restart that fails, up to this value.
+    pub max_delay: Duration,
+
+    /// A random delay of up to this value is added to every restart, so that
+    /// several sessions do not restart in lockstep.
+    pub jitter: Duration,
+}
+
+impl Default for ContinuousDiscovery {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Which command a [`DiscoverySession`] uses to start discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryFilter {
+    /// Uses [`start_discovery`].
+    None,
+
+    /// Uses [`start_service_discovery`].
+    Service {
+        rssi_threshold: i8,
+        uuids: Vec<[u8; 16]>,
+    },
+
+    /// Uses [`start_limited_discovery`].
+    Limited,
+}
+
+/// Discovers devices on one controller, and keeps the filter that discovery
+/// was started with so that it can be restarted with the same filter.
+///
+/// The kernel stops discovery after every cycle, and may stop it in the
+/// middle of interleaved discovery, reporting this with a Discovering event.
+/// With [`DiscoverySession::continuous`], the session starts discovery again
+/// when that happens, unless [`DiscoverySession::stop`] has been called.
+///
+/// Like [`ReconnectPolicy`], the session only restarts discovery while
+/// [`DiscoverySession::run`] is being awaited.
+#[derive(Debug)]
+pub struct DiscoverySession {
+    controller: Controller,
+    address_types: AddressTypes,
+    filter: DiscoveryFilter,
+    continuous: Option<ContinuousDiscovery>,
+    active: bool,
+    restarts: u32,
+    next_restart: Option<Instant>,
+    /// Events that were received while discovery was being restarted, which
+    /// have not been processed yet.
+    pending: VecDeque<Response>,
+}
+
+impl DiscoverySession {
+    pub fn new(controller: Controller, address_types: AddressTypes) -> Self {
+        Self {
+            controller,
+            address_types,
+            filter: DiscoveryFilter::None,
+            continuous: None,
+            active: false,
+            restarts: 0,
+            next_restart: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Sets the filter that is used whenever discovery is started.
+    pub fn with_filter(mut self, filter: DiscoveryFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Restarts discovery whenever the kernel ends it, until
+    /// [`DiscoverySession::stop`] is called.
+    pub fn continuous(mut self, config: ContinuousDiscovery) -> Self {
+        self.continuous = Some(config);
+        self
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    pub fn filter(&self) -> &DiscoveryFilter {
+        &self.filter
+    }
+
+    /// Whether discovery has been started and not stopped using
+    /// [`DiscoverySession::stop`].
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts discovery, and returns the address types that discovery was
+    /// started for.
+    pub async fn start(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<AddressTypes> {
+        let address_types = self.start_discovery(socket, event_tx).await?;
+        self.active = true;
+        self.restarts = 0;
+        self.next_restart = None;
+        Ok(address_types)
+    }
+
+    /// Stops discovery. The session will not restart discovery until
+    /// [`DiscoverySession::start`] is called again.
+    pub async fn stop(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<AddressTypes> {
+        self.active = false;
+        self.next_restart = None;
+        stop_discovery(socket, self.controller, self.address_types, event_tx).await
+    }
+
+    /// Updates the state of the session based on an event.
+    /// [`DiscoverySession::run`] calls this for every event it receives;
+    /// call it yourself for events that were received elsewhere.
+    pub fn handle_event(&mut self, response: &Response) {
+        if response.controller != self.controller {
+            return;
+        }
+
+        match response.event {
+            Event::Discovering {
+                discovering: true, ..
+            } => {
+                self.next_restart = None;
+            }
+            Event::Discovering {
+                discovering: false, ..
+            } => {
+                if let (true, Some(config)) = (self.active, self.continuous) {
+                    self.next_restart = Some(Instant::now() + self.delay(&config));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Processes events and restarts discovery when it is due, until a Device
+    /// Found event is received for the controller, which is returned.
+    ///
+    /// All other events received while this function is running are
+    /// forwarded to `event_tx`, including the ones received while discovery
+    /// is restarted. If restarting discovery fails with an error that is not
+    /// [transient](Error::is_transient), the error is returned and the
+    /// session stops restarting discovery; the events received meanwhile are
+    /// processed by the next call to this function.
+    pub async fn run(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<Response> {
+        loop {
+            let response = match (self.pending.pop_front(), self.next_restart) {
+                (Some(response), _) => response,
+                // receive is cancel safe, see ManagementStream::receive
+                (None, Some(at)) => match tokio::time::timeout_at(at, socket.receive()).await {
+                    Ok(response) => response?,
+                    Err(_) => {
+                        self.restart(socket).await?;
+                        continue;
+                    }
+                },
+                (None, None) => socket.receive().await?,
+            };
+
+            self.handle_event(&response);
+
+            if response.controller == self.controller
+                && matches!(response.event, Event::DeviceFound { .. })
+            {
+                return Ok(response);
+            }
+
+            if let Some(event_tx) = &event_tx {
+                let _ = event_tx.send(response).await;
+            }
+        }
+    }
+
+    /// When discovery is due to be restarted, if the kernel has ended it.
+    pub(crate) fn next_restart(&self) -> Option<Instant> {
+        self.next_restart
+    }
+
+    /// Events that were received while discovery was being restarted and
+    /// have not been processed yet, for helpers which restart discovery
+    /// without [`DiscoverySession::run`].
+    pub(crate) fn take_pending(&mut self) -> VecDeque<Response> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Restarts discovery. The events received meanwhile are queued in
+    /// `pending` rather than forwarded, so that the state of the session is
+    /// updated for them before anyone else sees them.
+    pub(crate) async fn restart(&mut self, socket: &mut ManagementStream) -> Result<()> {
+        self.next_restart = None;
+
+        let (result, responses) =
+            collect_events(|event_tx| self.start_discovery(socket, Some(event_tx))).await;
+        self.pending.extend(responses);
+
+        match result {
+            Ok(_) => {
+                self.restarts = 0;
+                Ok(())
+            }
+            Err(err) if err.is_transient() => {
+                self.restarts += 1;
+
+                if let Some(config) = self.continuous {
+                    self.next_restart = Some(Instant::now() + self.delay(&config));
+                }
+
+                Ok(())
+            }
+            Err(err) => {
+                self.active = false;
+                Err(err)
+            }
+        }
+    }
+
+    async fn start_discovery(
+        &self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<AddressTypes> {
+        match &self.filter {
+            DiscoveryFilter::None => {
+                start_discovery(socket, self.controller, self.address_types, event_tx).await
+            }
+            DiscoveryFilter::Service {
+                rssi_threshold,
+                uuids,
+            } => {
+                start_service_discovery(
+                    socket,
+                    self.controller,
+                    self.address_types,
+                    *rssi_threshold,
+                    uuids.clone(),
+                    event_tx,
+                )
+                .await
+            }
+            DiscoveryFilter::Limited => {
+                start_limited_discovery(socket, self.controller, self.address_types, event_tx).await
+            }
+        }
+    }
+
+    fn delay(&self, config: &ContinuousDiscovery) -> Duration {
+        let factor = 1u32.checked_shl(self.restarts).unwrap_or(u32::MAX);
+
+        let delay = config
+            .initial_delay
+            .checked_mul(factor)
+            .unwrap_or(config.max_delay)
+            .min(config.max_delay);
+
+        delay + jitter(config.jitter)
+    }
+}
+
+/// A random duration of at most `max`.
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    // every RandomState is seeded differently, which is random enough to
+    // spread restarts out without depending on a random number generator
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % (max.as_nanos() as u64 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{converse, device_found, MockKernel, MOCK_ADDRESS, MOCK_CONTROLLER};
+    use crate::AddressType;
+
+    #[tokio::test]
+    async fn continuous_restart() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let le = AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom;
+        let mut session = DiscoverySession::new(controller, le).continuous(ContinuousDiscovery {
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+        });
+
+        let (started, request) =
+            futures::join!(session.start(&mut socket, None), kernel.answer(&[0x06]));
+        started.unwrap();
+        assert_eq!(request.unwrap().opcode, Command::StartDiscovery);
+
+        // the kernel ends discovery, and the session starts it again before
+        // the next device is found
+        let kernel = async {
+            kernel
+                .send_event(controller, 0x0013, &[0x06, 0x00])
+                .await
+                .unwrap();
+            let request = kernel.answer(&[0x06]).await.unwrap();
+            assert_eq!(request.opcode, Command::StartDiscovery);
+
+            let found = device_found(MOCK_ADDRESS, AddressType::LEPublic, -60, &[]);
+            kernel.send_event(controller, 0x0012, &found).await.unwrap();
+        };
+
+        let found = converse(session.run(&mut socket, None), kernel).await;
+        assert!(matches!(found.unwrap().event, Event::DeviceFound { .. }));
+        assert!(session.is_active());
+    }
+
+    #[tokio::test]
+    async fn device_found_during_restart() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+        let le = AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom;
+        let mut session = DiscoverySession::new(controller, le).continuous(ContinuousDiscovery {
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+        });
+
+        let (started, _) = futures::join!(session.start(&mut socket, None), kernel.answer(&[0x06]));
+        started.unwrap();
+
+        // the device is found before the kernel answers the restart, so it is
+        // received by start_discovery
+        let kernel = async {
+            kernel
+                .send_event(controller, 0x0013, &[0x06, 0x00])
+                .await
+                .unwrap();
+            let request = kernel.receive_command().await.unwrap();
+            assert_eq!(request.opcode, Command::StartDiscovery);
+
+            let found = device_found(MOCK_ADDRESS, AddressType::LEPublic, -60, &[]);
+            kernel.send_event(controller, 0x0012, &found).await.unwrap();
+            kernel
+                .command_complete(
+                    controller,
+                    Command::StartDiscovery,
+                    CommandStatus::Success,
+                    &[0x06],
+                )
+                .await
+                .unwrap();
+        };
+
+        let found = converse(session.run(&mut socket, None), kernel).await;
+        assert!(matches!(found.unwrap().event, Event::DeviceFound { .. }));
+    }
+}
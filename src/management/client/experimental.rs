@@ -0,0 +1,115 @@
+use enumflags2::{bitflags, BitFlags};
+
+use super::*;
+use crate::Uuid128;
+
+/// Enables debug features of the kernel.
+pub const EXP_FEATURE_DEBUG: Uuid128 = Uuid128(0xd4992530_b9ec_469f_ab01_6c481c47da1c);
+
+/// Allows the controller to be central and peripheral at the same time.
+pub const EXP_FEATURE_LE_SIMULTANEOUS_ROLES: Uuid128 =
+    Uuid128(0x671b10b5_42c0_4696_9227_eb28d1b049d6);
+
+/// Offloads the resolution of private addresses to the controller.
+pub const EXP_FEATURE_RPA_RESOLUTION: Uuid128 = Uuid128(0x15c0a148_c273_11ea_b3de_0242ac130004);
+
+pub const EXP_FEATURE_QUALITY_REPORT: Uuid128 = Uuid128(0x330859bc_7506_492d_9370_9a6f0614037f);
+
+pub const EXP_FEATURE_OFFLOAD_CODECS: Uuid128 = Uuid128(0xa6695ace_ee7f_4fb9_881a_5fac66c629af);
+
+/// Enables ISO sockets, which the kernel uses for LE Audio. Synchronizing to
+/// periodic advertising trains and broadcast isochronous groups, such as
+/// Auracast broadcasts, is done through ISO sockets.
+pub const EXP_FEATURE_ISO_SOCKET: Uuid128 = Uuid128(0x6fbaf188_05e0_496a_9885_d6ddfdb4e03e);
+
+pub const EXP_FEATURE_MESH: Uuid128 = Uuid128(0x2ce463d7_7a03_4d8d_bf05_5f24e8f36e76);
+
+#[repr(u32)]
+#[bitflags]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExperimentalFeatureFlag {
+    Enabled = 1 << 0,
+    /// Changing the feature changes the supported settings of the
+    /// controller.
+    ChangesSupportedSettings = 1 << 1,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExperimentalFeature {
+    pub uuid: Uuid128,
+    pub flags: BitFlags<ExperimentalFeatureFlag>,
+}
+
+impl ExperimentalFeature {
+    pub fn enabled(&self) -> bool {
+        self.flags.contains(ExperimentalFeatureFlag::Enabled)
+    }
+}
+
+fn get_feature<B: Buf>(buf: &mut B) -> ExperimentalFeature {
+    ExperimentalFeature {
+        uuid: Uuid128(buf.get_u128_le()),
+        flags: BitFlags::from_bits_truncate(buf.get_u32_le()),
+    }
+}
+
+/// This command is used to retrieve the supported experimental features by
+/// the host stack. Use [`Controller::none()`] for the features which are not
+/// specific to a controller.
+pub async fn get_experimental_features(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<Vec<ExperimentalFeature>> {
+    let (_, param) = exec_command(
+        socket,
+        Command::ReadExperimentalFeaturesInfo,
+        controller,
+        None,
+        event_tx,
+    )
+    .await?;
+
+    let mut param = param.ok_or(Error::NoData)?;
+    let count = param.get_u16_le() as usize;
+
+    if param.remaining() < count * 20 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((0..count).map(|_| get_feature(&mut param)).collect())
+}
+
+/// This command is used to enable or disable an experimental feature. Most
+/// features can only be changed while the controller is powered off.
+pub async fn set_experimental_feature(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    uuid: Uuid128,
+    enabled: bool,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ExperimentalFeature> {
+    let (_, param) = exec_command(
+        socket,
+        Command::SetExperimentalFeature,
+        controller,
+        Some(encode_set_experimental_feature(uuid, enabled)),
+        event_tx,
+    )
+    .await?;
+
+    let mut param = param.ok_or(Error::NoData)?;
+
+    if param.remaining() < 20 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(get_feature(&mut param))
+}
+
+pub(crate) fn encode_set_experimental_feature(uuid: Uuid128, enabled: bool) -> Bytes {
+    let mut param = BytesMut::with_capacity(17);
+    param.put_u128_le(uuid.0);
+    param.put_u8(enabled as u8);
+    param.freeze()
+}
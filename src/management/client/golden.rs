@@ -0,0 +1,667 @@
+//! Byte-exact checks of the messages that commands send.
+//!
+//! Most tests run a command on a [`ManagementStream`] which is connected to a
+//! [`MockKernel`], compare the message that it sends with a vector and answer
+//! it with a Command Complete event. The parameters of every command that has
+//! any are built by an `encode_*` function next to the command, which works
+//! without a socket, and those functions are checked against the same
+//! vectors as well. This catches byte order and field order mistakes without
+//! a controller.
+//!
+//! The vectors were written out by hand from the layouts in the mgmt-api
+//! documentation of BlueZ, byte for byte in the order that btmon prints the
+//! parameters of a command, and checked against the parameter sizes that the
+//! kernel expects. They were not captured from btmgmt, as there is no
+//! controller where these tests run; a capture made with `btmon -w` while
+//! running the same command in btmgmt is the way to settle a disputed vector.
+
+use std::future::Future;
+
+use super::*;
+use crate::testing::mock::MockKernel;
+use crate::{AddressType, Uuid128};
+use enumflags2::BitFlags;
+
+const HCI1: Controller = Controller(1);
+
+fn address() -> Address {
+    Address::from([0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+}
+
+/// Runs `command`, checks that it sends `request` and answers it with a
+/// successful Command Complete event whose return parameters are `reply`.
+async fn golden<T>(
+    kernel: &mut MockKernel,
+    command: impl Future<Output = Result<T>>,
+    request: &[u8],
+    reply: &[u8],
+) -> T {
+    let kernel = async {
+        let sent = kernel.receive_raw().await.unwrap();
+        assert_eq!(&sent[..], request);
+
+        kernel
+            .command_complete(
+                Controller::from(u16::from_le_bytes([sent[2], sent[3]])),
+                Command::from(u16::from_le_bytes([sent[0], sent[1]])),
+                CommandStatus::Success,
+                reply,
+            )
+            .await
+            .unwrap();
+    };
+
+    let (result, ()) = futures::join!(command, kernel);
+    result.unwrap()
+}
+
+/// The return parameters of commands which reply with an address.
+const ADDRESS_REPLY: [u8; 7] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01];
+
+#[tokio::test]
+async fn settings() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let powered = [0x01, 0x00, 0x00, 0x00];
+
+    #[rustfmt::skip]
+    golden(p, set_powered(s, HCI1, true, None),
+        &[0x05, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_discoverable(s, HCI1, DiscoverableMode::Limited, Some(0x012C), None),
+        &[0x06, 0x00, 0x01, 0x00, 0x03, 0x00, 0x02, 0x2C, 0x01], &powered).await;
+    // the timeout is sent even if there is none
+    #[rustfmt::skip]
+    golden(p, set_discoverable(s, HCI1, DiscoverableMode::None, None, None),
+        &[0x06, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_connectable(s, HCI1, false, None),
+        &[0x07, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_bondable(s, HCI1, true, None),
+        &[0x09, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_ssp(s, HCI1, true, None),
+        &[0x0B, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_le(s, HCI1, true, None),
+        &[0x0D, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_io_capability(s, HCI1, IoCapability::KeyboardDisplay, None),
+        &[0x18, 0x00, 0x01, 0x00, 0x01, 0x00, 0x04], &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_device_id(s, HCI1, 0x0002, 0x1D6B, 0x0246, 0x0537, None),
+        &[0x28, 0x00, 0x01, 0x00, 0x08, 0x00,
+          0x02, 0x00, 0x6B, 0x1D, 0x46, 0x02, 0x37, 0x05], &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_scan_parameters(s, HCI1, 0x0060, 0x0030, None),
+        &[0x2C, 0x00, 0x01, 0x00, 0x04, 0x00, 0x60, 0x00, 0x30, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_static_address(s, HCI1, address(), None),
+        &[0x2B, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+        &powered).await;
+}
+
+#[tokio::test]
+async fn local_name() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+
+    let mut request = vec![0x0F, 0x00, 0x01, 0x00, 0x04, 0x01];
+    let mut names = [0u8; 260];
+    names[..4].copy_from_slice(b"rust");
+    names[249..251].copy_from_slice(b"rs");
+    request.extend_from_slice(&names);
+
+    let reply = golden(
+        &mut kernel,
+        set_local_name(&mut socket, HCI1, "rust", Some("rs"), None),
+        &request,
+        &names,
+    )
+    .await;
+    assert_eq!(reply, ("rust".to_owned(), "rs".to_owned()));
+}
+
+#[tokio::test]
+async fn class() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let uuid = [
+        0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x0B, 0x11, 0x00,
+        0x00,
+    ];
+
+    let mut request = vec![0x10, 0x00, 0x01, 0x00, 0x11, 0x00];
+    request.extend_from_slice(&uuid);
+    request.push(0x20);
+    golden(
+        p,
+        add_uuid(s, HCI1, uuid, BitFlags::from(ServiceClass::Audio), None),
+        &request,
+        &[0x00, 0x00, 0x20],
+    )
+    .await;
+
+    let mut request = vec![0x11, 0x00, 0x01, 0x00, 0x10, 0x00];
+    request.extend_from_slice(&uuid);
+    golden(p, remove_uuid(s, HCI1, uuid, None), &request, &[0; 3]).await;
+}
+
+#[tokio::test]
+async fn discovery() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let le = AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom;
+
+    #[rustfmt::skip]
+    golden(p, start_discovery(s, HCI1, le, None),
+        &[0x23, 0x00, 0x01, 0x00, 0x01, 0x00, 0x06], &[0x06]).await;
+    #[rustfmt::skip]
+    golden(p, stop_discovery(s, HCI1, le, None),
+        &[0x24, 0x00, 0x01, 0x00, 0x01, 0x00, 0x06], &[0x06]).await;
+    #[rustfmt::skip]
+    golden(p, start_service_discovery(s, HCI1, le, -70, vec![[0xAA; 16]], None),
+        &[0x3A, 0x00, 0x01, 0x00, 0x14, 0x00, 0x06, 0xBA, 0x01, 0x00,
+          0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA,
+          0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA],
+        &[0x06]).await;
+    #[rustfmt::skip]
+    golden(p, confirm_name(s, HCI1, address(), AddressType::LEPublic, true, None),
+        &[0x25, 0x00, 0x01, 0x00, 0x08, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x01],
+        &ADDRESS_REPLY).await;
+}
+
+#[tokio::test]
+async fn interact() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let le = AddressType::LEPublic;
+
+    #[rustfmt::skip]
+    golden(p, disconnect(s, HCI1, address(), le, None),
+        &[0x14, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, pair_device(s, HCI1, address(), le, IoCapability::NoInputNoOutput, None),
+        &[0x19, 0x00, 0x01, 0x00, 0x08, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x03],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, unpair_device(s, HCI1, address(), le, true, None),
+        &[0x1B, 0x00, 0x01, 0x00, 0x08, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x01],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, user_confirmation_reply(s, HCI1, address(), le, false, None),
+        &[0x1D, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, user_passkey_reply(s, HCI1, address(), le, Some(Passkey::new(123456).unwrap()), None),
+        &[0x1E, 0x00, 0x01, 0x00, 0x0B, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x40, 0xE2, 0x01, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, pin_code_reply(s, HCI1, address(), AddressType::BREDR, Some(PinCode::new("0000").unwrap()), None),
+        &[0x16, 0x00, 0x01, 0x00, 0x18, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00, 0x04,
+          b'0', b'0', b'0', b'0', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, add_device(s, HCI1, address(), le, AddDeviceAction::AutoConnect, None),
+        &[0x33, 0x00, 0x01, 0x00, 0x08, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x02],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, remove_device(s, HCI1, address(), le, None),
+        &[0x34, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01],
+        &ADDRESS_REPLY).await;
+}
+
+#[tokio::test]
+async fn more_settings() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let powered = [0x01, 0x00, 0x00, 0x00];
+
+    #[rustfmt::skip]
+    golden(p, set_fast_connectable(s, HCI1, true, None),
+        &[0x08, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_link_security(s, HCI1, true, None),
+        &[0x0A, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_high_speed(s, HCI1, false, None),
+        &[0x0C, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_device_class(s, HCI1, ClassOfDevice::from(0x5A020C), None),
+        &[0x0E, 0x00, 0x01, 0x00, 0x02, 0x00, 0x02, 0x0C], &[0x0C, 0x02, 0x5A]).await;
+    #[rustfmt::skip]
+    golden(p, set_advertising(s, HCI1, LeAdvertisingMode::WithConnectable, None),
+        &[0x29, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_bredr(s, HCI1, true, None),
+        &[0x2A, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_secure_connections_mode(s, HCI1, SecureConnectionsMode::Only, None),
+        &[0x2D, 0x00, 0x01, 0x00, 0x01, 0x00, 0x02], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_debug_mode(s, HCI1, DebugKeysMode::Persist, None),
+        &[0x2E, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_privacy_mode(s, HCI1, PrivacyMode::Limited, [0xA5; 16], None),
+        &[0x2F, 0x00, 0x01, 0x00, 0x11, 0x00, 0x02,
+          0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5,
+          0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5, 0xA5],
+        &powered).await;
+    #[rustfmt::skip]
+    golden(p, set_external_config(s, HCI1, true, None),
+        &[0x38, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &[0x00, 0x00, 0x00, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, set_public_address(s, HCI1, address(), None),
+        &[0x39, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+        &[0x00, 0x00, 0x00, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, set_appearance(s, HCI1, 0x03C1, None),
+        &[0x43, 0x00, 0x01, 0x00, 0x02, 0x00, 0xC1, 0x03], &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_phy_config(s, HCI1, PhyFlag::LE1MTx | PhyFlag::LE1MRx | PhyFlag::LE2MTx, None),
+        &[0x45, 0x00, 0x01, 0x00, 0x04, 0x00, 0x00, 0x0E, 0x00, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_wideband_speech(s, HCI1, true, None),
+        &[0x47, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &powered).await;
+}
+
+#[tokio::test]
+async fn default_config() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let params = [
+        (
+            SystemConfigParameterType::BREDRPageScanInterval,
+            vec![0x00, 0x08],
+        ),
+        (
+            SystemConfigParameterType::LEAdvertisementMinInterval,
+            vec![0xA0, 0x00],
+        ),
+    ];
+
+    #[rustfmt::skip]
+    golden(p, set_default_system_config(s, HCI1, &params, None),
+        &[0x4C, 0x00, 0x01, 0x00, 0x0A, 0x00,
+          0x01, 0x00, 0x02, 0x00, 0x08,
+          0x0A, 0x00, 0x02, 0xA0, 0x00],
+        &[]).await;
+    #[rustfmt::skip]
+    golden(p, set_default_runtime_config(s, HCI1, &[], None),
+        &[0x4E, 0x00, 0x01, 0x00, 0x00, 0x00], &[]).await;
+
+    #[rustfmt::skip]
+    let config = golden(p, get_default_system_config(s, HCI1, None),
+        &[0x4B, 0x00, 0x01, 0x00, 0x00, 0x00],
+        &[0x01, 0x00, 0x02, 0x00, 0x08]).await;
+    assert_eq!(
+        config.get(SystemConfigParameterType::BREDRPageScanInterval),
+        Some(0x0800)
+    );
+    #[rustfmt::skip]
+    golden(p, get_default_runtime_config(s, HCI1, None),
+        &[0x4D, 0x00, 0x01, 0x00, 0x00, 0x00], &[]).await;
+}
+
+#[tokio::test]
+async fn queries() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+
+    #[rustfmt::skip]
+    golden(p, get_mgmt_version(s, None),
+        &[0x01, 0x00, 0xFF, 0xFF, 0x00, 0x00], &[0x01, 0x16, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, get_controller_list(s, None),
+        &[0x03, 0x00, 0xFF, 0xFF, 0x00, 0x00], &[0x01, 0x00, 0x01, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, get_controller_info(s, HCI1, None),
+        &[0x04, 0x00, 0x01, 0x00, 0x00, 0x00], &[0; 280]).await;
+    #[rustfmt::skip]
+    golden(p, get_connections(s, HCI1, None),
+        &[0x15, 0x00, 0x01, 0x00, 0x00, 0x00], &[0x00, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, get_connection_info(s, HCI1, address(), AddressType::LEPublic, None),
+        &[0x31, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01],
+        &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0xC4, 0x04, 0x0A]).await;
+    #[rustfmt::skip]
+    golden(p, get_clock_info(s, HCI1, address(), AddressType::BREDR, None),
+        &[0x32, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00,
+          0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0xFF, 0xFF]).await;
+    #[rustfmt::skip]
+    golden(p, get_unconfigured_controller_list(s, None),
+        &[0x36, 0x00, 0xFF, 0xFF, 0x00, 0x00], &[0x00, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, get_controller_config_info(s, HCI1, None),
+        &[0x37, 0x00, 0x01, 0x00, 0x00, 0x00], &[0; 10]).await;
+    #[rustfmt::skip]
+    golden(p, get_ext_controller_list(s, None),
+        &[0x3C, 0x00, 0xFF, 0xFF, 0x00, 0x00], &[0x00, 0x00]).await;
+    #[rustfmt::skip]
+    golden(p, get_ext_controller_info(s, HCI1, None),
+        &[0x42, 0x00, 0x01, 0x00, 0x00, 0x00], &[0; 19]).await;
+    #[rustfmt::skip]
+    golden(p, get_phy_config(s, HCI1, None),
+        &[0x44, 0x00, 0x01, 0x00, 0x00, 0x00], &[0; 12]).await;
+    #[rustfmt::skip]
+    golden(p, get_experimental_features(s, HCI1, None),
+        &[0x49, 0x00, 0x01, 0x00, 0x00, 0x00], &[0x00, 0x00]).await;
+}
+
+#[tokio::test]
+async fn more_interact() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let br = AddressType::BREDR;
+
+    #[rustfmt::skip]
+    golden(p, pin_code_reply(s, HCI1, address(), br, None, None),
+        &[0x17, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, cancel_pair_device(s, HCI1, address(), br, None),
+        &[0x1A, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, user_confirmation_reply(s, HCI1, address(), br, true, None),
+        &[0x1C, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, user_passkey_reply(s, HCI1, address(), br, None, None),
+        &[0x1F, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, block_device(s, HCI1, address(), br, None),
+        &[0x26, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, unblock_device(s, HCI1, address(), br, None),
+        &[0x27, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, start_limited_discovery(s, HCI1, BitFlags::from(AddressTypeFlag::BREDR), None),
+        &[0x41, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &[0x01]).await;
+}
+
+#[test]
+fn load() {
+    let le = AddressType::LEPublic;
+    let value: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F,
+    ];
+
+    let link_key = LinkKey::new(
+        address(),
+        AddressType::BREDR,
+        LinkKeyType::AuthenticatedCombinationP256,
+        value,
+        0,
+    );
+    #[rustfmt::skip]
+    assert_eq!(&encode_load_link_keys(&[link_key], false)[..], &[
+        0x00, 0x01, 0x00,
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00, 0x08,
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x00,
+    ][..]);
+
+    let long_term_key = LongTermKey::new(
+        address(),
+        le,
+        LongTermKeyType::AuthenticatedP256,
+        0x00,
+        16,
+        0x1234,
+        0x0102_0304_0506_0708,
+        value,
+    );
+    #[rustfmt::skip]
+    assert_eq!(&encode_load_long_term_keys(&[long_term_key])[..], &[
+        0x01, 0x00,
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01, 0x03, 0x00, 0x10,
+        0x34, 0x12,
+        0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    ][..]);
+
+    let irk = IdentityResolvingKey::new(address(), AddressType::LERandom, value);
+    #[rustfmt::skip]
+    assert_eq!(&encode_load_identity_resolving_keys(&[irk])[..], &[
+        0x01, 0x00,
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x02,
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    ][..]);
+
+    let params = ConnectionParams::from_raw(address(), le, 0x0018, 0x0028, 0x0000, 0x002A).unwrap();
+    #[rustfmt::skip]
+    assert_eq!(&encode_load_connection_parameters(&[params])[..], &[
+        0x01, 0x00,
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01,
+        0x18, 0x00, 0x28, 0x00, 0x00, 0x00, 0x2A, 0x00,
+    ][..]);
+
+    let blocked = [
+        BlockedKey::new(BlockedKeyType::LinkKey, value),
+        BlockedKey::new(BlockedKeyType::IdentityResolvingKey, [0xFF; 16]),
+    ];
+    #[rustfmt::skip]
+    assert_eq!(&encode_load_blocked_keys(&blocked)[..], &[
+        0x02, 0x00,
+        0x00,
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x02,
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ][..]);
+}
+
+#[tokio::test]
+async fn load_commands() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+
+    #[rustfmt::skip]
+    golden(p, load_link_keys(s, HCI1, vec![], true, None),
+        &[0x12, 0x00, 0x01, 0x00, 0x03, 0x00, 0x01, 0x00, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, load_long_term_keys(s, HCI1, vec![], None),
+        &[0x13, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, load_identity_resolving_keys(s, HCI1, vec![], None),
+        &[0x30, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, load_connection_parameters(s, HCI1, vec![], None),
+        &[0x35, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00], &[]).await;
+    #[rustfmt::skip]
+    golden(p, load_blocked_keys(s, HCI1, vec![], None),
+        &[0x46, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00], &[]).await;
+}
+
+#[tokio::test]
+async fn advertising() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let flags = AdvertisingFlags::EnterConnectable | AdvertisingFlags::AdvertiseDiscoverable;
+    let params = AdvertisingParams {
+        instance: 1,
+        flags,
+        duration: 0x0002,
+        timeout: 0x003C,
+        adv_data: vec![0x03, 0x03, 0x0F, 0x18],
+        scan_rsp: vec![0x02, 0x0A, 0x00],
+    };
+
+    #[rustfmt::skip]
+    golden(p, add_advertising(s, HCI1, params, None),
+        &[0x3E, 0x00, 0x01, 0x00, 0x12, 0x00,
+          0x01, 0x03, 0x00, 0x00, 0x00, 0x02, 0x00, 0x3C, 0x00, 0x04, 0x03,
+          0x03, 0x03, 0x0F, 0x18, 0x02, 0x0A, 0x00],
+        &[0x01]).await;
+    #[rustfmt::skip]
+    golden(p, remove_advertising(s, HCI1, 1, None),
+        &[0x3F, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &[0x01]).await;
+    #[rustfmt::skip]
+    let size = golden(p, get_advertising_size(s, HCI1, 1, flags, None),
+        &[0x40, 0x00, 0x01, 0x00, 0x05, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00],
+        &[0x01, 0x03, 0x00, 0x00, 0x00, 0x1C, 0x1F]).await;
+    assert_eq!(size.flags, flags);
+    assert_eq!(size.max_adv_data_len, 0x1C);
+    #[rustfmt::skip]
+    golden(p, get_advertising_features(s, HCI1, None),
+        &[0x3D, 0x00, 0x01, 0x00, 0x00, 0x00],
+        &[0x00, 0x00, 0x00, 0x00, 0x1F, 0x1F, 0x05, 0x00]).await;
+}
+
+#[tokio::test]
+async fn oob_and_experimental() {
+    let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+    let s = &mut socket;
+    let p = &mut kernel;
+    let data = OutOfBandData {
+        hash_192: [0x19; 16],
+        randomizer_192: [0x29; 16],
+        hash_256: Some([0x56; 16]),
+        randomizer_256: Some([0x66; 16]),
+    };
+
+    let mut request = vec![
+        0x21, 0x00, 0x01, 0x00, 0x47, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00,
+    ];
+    for byte in &[0x19, 0x29, 0x56, 0x66] {
+        request.extend_from_slice(&[*byte; 16]);
+    }
+    golden(
+        p,
+        add_remote_oob_data(s, HCI1, address(), AddressType::BREDR, data, None),
+        &request,
+        &ADDRESS_REPLY,
+    )
+    .await;
+
+    #[rustfmt::skip]
+    golden(p, remove_remote_oob_data(s, HCI1, address(), AddressType::BREDR, None),
+        &[0x22, 0x00, 0x01, 0x00, 0x07, 0x00,
+          0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00],
+        &ADDRESS_REPLY).await;
+    #[rustfmt::skip]
+    golden(p, read_local_oob_data(s, HCI1, None),
+        &[0x20, 0x00, 0x01, 0x00, 0x00, 0x00], &[0; 32]).await;
+    #[rustfmt::skip]
+    golden(p, read_local_oob_ext_data(s, HCI1, BitFlags::from(AddressTypeFlag::BREDR), None),
+        &[0x3B, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01], &[0x01, 0x00, 0x00]).await;
+
+    // the UUID is sent in little-endian order
+    let uuid = Uuid128(0x330859bc_7506_492d_9370_9a6f0614037f);
+    let mut reply = uuid.0.to_le_bytes().to_vec();
+    reply.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+    #[rustfmt::skip]
+    golden(p, set_experimental_feature(s, HCI1, uuid, true, None),
+        &[0x4A, 0x00, 0x01, 0x00, 0x11, 0x00,
+          0x7F, 0x03, 0x14, 0x06, 0x6F, 0x9A, 0x70, 0x93,
+          0x2D, 0x49, 0x06, 0x75, 0xBC, 0x59, 0x08, 0x33, 0x01],
+        &reply).await;
+}
+
+#[test]
+fn encoders() {
+    let le = AddressType::LEPublic;
+    let br = AddressType::BREDR;
+    let address_le = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x01];
+    let address_br = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x00];
+    let with = |address: [u8; 7], extra: &[u8]| [&address[..], extra].concat();
+    let uuid = Uuid128(0x330859bc_7506_492d_9370_9a6f0614037f);
+    let le_types = AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom;
+
+    // the parameters of each command, without the header
+    #[rustfmt::skip]
+    let vectors: Vec<(&str, Bytes, Vec<u8>)> = vec![
+        ("set_powered", encode_set_powered(true), vec![0x01]),
+        ("set_discoverable", encode_set_discoverable(DiscoverableMode::General, None), vec![0x01, 0x00, 0x00]),
+        ("set_connectable", encode_set_connectable(false), vec![0x00]),
+        ("set_fast_connectable", encode_set_fast_connectable(true), vec![0x01]),
+        ("set_bondable", encode_set_bondable(true), vec![0x01]),
+        ("set_link_security", encode_set_link_security(true), vec![0x01]),
+        ("set_ssp", encode_set_ssp(true), vec![0x01]),
+        ("set_high_speed", encode_set_high_speed(false), vec![0x00]),
+        ("set_le", encode_set_le(true), vec![0x01]),
+        ("set_device_class", encode_set_device_class(ClassOfDevice::from(0x5A020C)), vec![0x02, 0x0C]),
+        ("add_uuid", encode_add_uuid([0xAA; 16], BitFlags::from(ServiceClass::Audio)), [&[0xAA; 16][..], &[0x20]].concat()),
+        ("remove_uuid", encode_remove_uuid([0xAA; 16]), vec![0xAA; 16]),
+        ("disconnect", encode_disconnect(address(), le), address_le.to_vec()),
+        ("set_io_capability", encode_set_io_capability(IoCapability::DisplayOnly), vec![0x00]),
+        ("pair_device", encode_pair_device(address(), le, IoCapability::KeyboardDisplay), with(address_le, &[0x04])),
+        ("cancel_pair_device", encode_cancel_pair_device(address(), br), address_br.to_vec()),
+        ("unpair_device", encode_unpair_device(address(), br, false), with(address_br, &[0x00])),
+        ("user_confirmation_reply", encode_user_confirmation_reply(address(), le, false).1, address_le.to_vec()),
+        ("remove_remote_oob_data", encode_remove_remote_oob_data(address(), br), address_br.to_vec()),
+        ("start_discovery", encode_start_discovery(le_types), vec![0x06]),
+        ("stop_discovery", encode_stop_discovery(BitFlags::from(AddressTypeFlag::BREDR)), vec![0x01]),
+        ("confirm_name", encode_confirm_name(address(), le, false), with(address_le, &[0x00])),
+        ("block_device", encode_block_device(address(), le), address_le.to_vec()),
+        ("unblock_device", encode_unblock_device(address(), le), address_le.to_vec()),
+        ("set_advertising", encode_set_advertising(LeAdvertisingMode::Enabled), vec![0x02]),
+        ("set_bredr", encode_set_bredr(false), vec![0x00]),
+        ("set_static_address", encode_set_static_address(address()), address_le[..6].to_vec()),
+        ("set_secure_connections_mode", encode_set_secure_connections_mode(SecureConnectionsMode::Enabled), vec![0x01]),
+        ("set_debug_mode", encode_set_debug_mode(DebugKeysMode::PersistAndGenerate), vec![0x02]),
+        ("get_connection_info", encode_get_connection_info(address(), le), address_le.to_vec()),
+        ("get_clock_info", encode_get_clock_info(address(), br), address_br.to_vec()),
+        ("add_device", encode_add_device(address(), le, AddDeviceAction::AllowConnect), with(address_le, &[0x01])),
+        ("remove_device", encode_remove_device(address(), le), address_le.to_vec()),
+        ("set_external_config", encode_set_external_config(false), vec![0x00]),
+        ("set_public_address", encode_set_public_address(address()), address_le[..6].to_vec()),
+        ("read_local_oob_ext_data", encode_read_local_oob_ext_data(le_types), vec![0x06]),
+        ("remove_advertising", encode_remove_advertising(0), vec![0x00]),
+        ("get_advertising_size", encode_get_advertising_size(2, BitFlags::empty()), vec![0x02, 0x00, 0x00, 0x00, 0x00]),
+        ("start_limited_discovery", encode_start_limited_discovery(le_types), vec![0x06]),
+        ("set_appearance", encode_set_appearance(0x0340), vec![0x40, 0x03]),
+        ("set_phy_config", encode_set_phy_config(PhyFlag::BR1M1Slot | PhyFlag::LECodedRx), vec![0x01, 0x40, 0x00, 0x00]),
+        ("set_wideband_speech", encode_set_wideband_speech(false), vec![0x00]),
+        ("set_experimental_feature", encode_set_experimental_feature(uuid, false), [&uuid.0.to_le_bytes()[..], &[0x00]].concat()),
+        ("set_default_system_config", encode_set_default_system_config(&[(SystemConfigParameterType::LEAdvertisementMaxInterval, vec![0xF0, 0x00])]), vec![0x0B, 0x00, 0x02, 0xF0, 0x00]),
+        ("set_default_runtime_config", encode_set_default_runtime_config(&[]), vec![]),
+    ];
+
+    for (command, encoded, expected) in vectors {
+        assert_eq!(&encoded[..], &expected[..], "{}", command);
+    }
+
+    assert_eq!(
+        encode_user_confirmation_reply(address(), le, true).0,
+        Command::UserConfirmationReply
+    );
+}
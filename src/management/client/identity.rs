@@ -0,0 +1,114 @@
+use super::*;
+use crate::AddressType;
+
+/// The identity address that a controller uses on LE, returned by
+/// [`ensure_identity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub address: Address,
+    /// [`AddressType::LEPublic`] for the public address of the controller,
+    /// or [`AddressType::LERandom`] for a static random address.
+    pub address_type: AddressType,
+    /// Whether [`ensure_identity`] generated a new static random address,
+    /// which the application should store and pass again next time, so that
+    /// bonded devices still recognize the controller.
+    pub generated: bool,
+}
+
+/// Makes sure that `controller` has an identity address on LE, and returns
+/// it.
+///
+/// Controllers with a public address use it as their identity. LE-only
+/// controllers that report a zero public address need a static random
+/// address, which can only be set while the controller is powered off, as
+/// described in [`set_static_address`]. For these, this sets `static_address`,
+/// or a newly generated static random address if it is `None`. A controller
+/// that is powered is powered off for this and then on again, which drops its
+/// connections.
+///
+/// The kernel does not report which static address is configured, so the
+/// address is set every time. Pass the address that was returned the first
+/// time to keep the identity stable.
+pub async fn ensure_identity(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    static_address: Option<Address>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<Identity> {
+    let info = get_controller_info(socket, controller, event_tx.clone()).await?;
+
+    if info.address != Address::zero() {
+        return Ok(Identity {
+            address: info.address,
+            address_type: AddressType::LEPublic,
+            generated: false,
+        });
+    }
+
+    let (address, generated) = match static_address {
+        Some(address) if address.is_static_random() => (address, false),
+        Some(address) => return Err(Error::InvalidStaticAddress { address }),
+        None => (Address::random_static()?, true),
+    };
+
+    let powered = info.current_settings.contains(ControllerSetting::Powered);
+
+    if powered {
+        set_powered(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    let result = set_static_address(socket, controller, address, event_tx.clone()).await;
+
+    // power the controller on again even if the address was rejected
+    if powered {
+        set_powered(socket, controller, true, event_tx).await?;
+    }
+
+    result?;
+
+    Ok(Identity {
+        address,
+        address_type: AddressType::LERandom,
+        generated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{controller_info, MockScript, MockTransport, MOCK_CONTROLLER};
+
+    #[test]
+    fn static_addresses() {
+        let address = Address::random_static().unwrap();
+        assert!(address.is_static_random());
+        assert_eq!(<[u8; 6]>::from(address)[5] & 0xC0, 0xC0);
+
+        assert!(!Address::new([0x00, 0x00, 0x00, 0x00, 0x00, 0xC0]).is_static_random());
+        assert!(!Address::new([0xFF; 6]).is_static_random());
+        assert!(!Address::new([0x01, 0x00, 0x00, 0x00, 0x00, 0x40]).is_static_random());
+    }
+
+    #[tokio::test]
+    async fn ensure_identity_sets_static_address() {
+        // zero public address, powered and le
+        let script = MockScript::new()
+            .reply(Command::ReadControllerInfo, controller_info(0x0201))
+            .reply(Command::SetPowered, [0x00, 0x02, 0x00, 0x00])
+            .reply(Command::SetStaticAddress, [0x00, 0x82, 0x00, 0x00])
+            .reply(Command::SetPowered, [0x01, 0x82, 0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let address = Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]);
+        let identity = ensure_identity(&mut socket, MOCK_CONTROLLER, Some(address), None)
+            .await
+            .unwrap();
+        assert_eq!(identity.address, address);
+        assert_eq!(identity.address_type, AddressType::LERandom);
+        assert!(!identity.generated);
+
+        let commands = transport.commands();
+        assert_eq!(commands[2].opcode, Command::SetStaticAddress);
+        assert_eq!(&commands[2].param[..], address.as_ref());
+    }
+}
@@ -5,13 +5,20 @@ use crate::AddressType;
 #[inline]
 pub(crate) fn get_address(param: Option<Bytes>) -> Result<(Address, AddressType)> {
     let mut param = param.ok_or(Error::NoData)?;
-    Ok((param.get_address(), param.get_primitive_u8()))
+    Ok((
+        param.get_address(),
+        AddressType::from_mgmt_u8(param.get_u8()),
+    ))
+}
+
+pub(crate) fn u8_bytes(value: u8) -> Bytes {
+    Bytes::copy_from_slice(&[value])
 }
 
 pub(crate) fn address_bytes(address: Address, address_type: AddressType) -> Bytes {
     let mut param = BytesMut::with_capacity(7);
     param.put_slice(address.as_ref());
-    param.put_u8(address_type as u8);
+    param.put_u8(address_type.to_mgmt_u8());
     param.freeze()
 }
 
@@ -22,7 +29,7 @@ pub(crate) fn address_bytes_with_u8(
 ) -> Bytes {
     let mut param = BytesMut::with_capacity(8);
     param.put_slice(address.as_ref());
-    param.put_u8(address_type as u8);
+    param.put_u8(address_type.to_mgmt_u8());
     param.put_u8(extra);
     param.freeze()
 }
@@ -49,11 +56,7 @@ pub async fn confirm_name(
         socket,
         Command::ConfirmName,
         controller,
-        Some(address_bytes_with_u8(
-            address,
-            address_type,
-            name_known as u8,
-        )),
+        Some(encode_confirm_name(address, address_type, name_known)),
         event_tx,
     )
     .await?;
@@ -61,6 +64,14 @@ pub async fn confirm_name(
     get_address(param)
 }
 
+pub(crate) fn encode_confirm_name(
+    address: Address,
+    address_type: AddressType,
+    name_known: bool,
+) -> Bytes {
+    address_bytes_with_u8(address, address_type, name_known as u8)
+}
+
 /// This command is used to add a device to the list of devices
 ///	which should be blocked from being connected to the local
 ///	controller.
@@ -86,7 +97,7 @@ pub async fn block_device(
         socket,
         Command::BlockDevice,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_block_device(address, address_type)),
         event_tx,
     )
     .await?;
@@ -94,6 +105,10 @@ pub async fn block_device(
     get_address(param)
 }
 
+pub(crate) fn encode_block_device(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 /// This command is used to remove a device from the list of blocked
 ///	devices (where it was added to using the Block Device command).
 ///
@@ -112,7 +127,7 @@ pub async fn unblock_device(
         socket,
         Command::UnblockDevice,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_unblock_device(address, address_type)),
         event_tx,
     )
     .await?;
@@ -120,6 +135,10 @@ pub async fn unblock_device(
     get_address(param)
 }
 
+pub(crate) fn encode_unblock_device(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 ///	This command is used to force the disconnection of a currently
 ///	connected device.
 ///
@@ -135,7 +154,7 @@ pub async fn disconnect(
         socket,
         Command::Disconnect,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_disconnect(address, address_type)),
         event_tx,
     )
     .await?;
@@ -143,42 +162,51 @@ pub async fn disconnect(
     get_address(param)
 }
 
+pub(crate) fn encode_disconnect(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 ///	This command is used to respond to a PIN Code request event.
-/// Pin code can be at most 16 bytes. Passing None will send a
-/// negative PIN code response.
+/// Passing None will send a negative PIN code response.
 ///	This command can only be used when the controller is powered.
 pub async fn pin_code_reply(
     socket: &mut ManagementStream,
     controller: Controller,
     address: Address,
     address_type: AddressType,
-    pin_code: Option<Vec<u8>>,
+    pin_code: Option<PinCode>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<(Address, AddressType)> {
-    let mut param;
-    let opcode;
-
-    if let Some(pin_code) = pin_code {
-        opcode = Command::PinCodeReply;
-        param = BytesMut::with_capacity(24);
-        param.put_slice(address.as_ref());
-        param.put_u8(address_type as u8);
-        param.put_u8(pin_code.len() as u8);
-        param.put_slice(&pin_code[..]);
-        param.resize(24, 0);
-    } else {
-        opcode = Command::PinCodeNegativeReply;
-        param = BytesMut::with_capacity(7);
-        param.put_slice(address.as_ref());
-        param.put_u8(address_type as u8);
-    }
-
-    let (_, param) =
-        exec_command(socket, opcode, controller, Some(param.freeze()), event_tx).await?;
+    let (opcode, param) = encode_pin_code_reply(address, address_type, pin_code);
+    let (_, param) = exec_command(socket, opcode, controller, Some(param), event_tx).await?;
 
     get_address(param)
 }
 
+/// Returns the command which answers a PIN Code request with `pin_code`, or
+/// rejects it if there is none, together with its parameters.
+pub(crate) fn encode_pin_code_reply(
+    address: Address,
+    address_type: AddressType,
+    pin_code: Option<PinCode>,
+) -> (Command, Bytes) {
+    match pin_code {
+        Some(pin_code) => {
+            let mut param = BytesMut::with_capacity(24);
+            param.put_slice(address.as_ref());
+            param.put_u8(address_type.to_mgmt_u8());
+            param.put_u8(pin_code.as_bytes().len() as u8);
+            param.put_slice(pin_code.as_bytes());
+            param.resize(24, 0);
+            (Command::PinCodeReply, param.freeze())
+        }
+        None => (
+            Command::PinCodeNegativeReply,
+            address_bytes(address, address_type),
+        ),
+    }
+}
+
 ///	This command is used to trigger pairing with a remote device.
 ///	The IO_Capability command parameter is used to temporarily (for
 ///	this pairing event only) override the global IO Capability (set
@@ -212,11 +240,7 @@ pub async fn pair_device(
         socket,
         Command::PairDevice,
         controller,
-        Some(address_bytes_with_u8(
-            address,
-            address_type,
-            io_capability as u8,
-        )),
+        Some(encode_pair_device(address, address_type, io_capability)),
         event_tx,
     )
     .await?;
@@ -224,6 +248,14 @@ pub async fn pair_device(
     get_address(param)
 }
 
+pub(crate) fn encode_pair_device(
+    address: Address,
+    address_type: AddressType,
+    io_capability: IoCapability,
+) -> Bytes {
+    address_bytes_with_u8(address, address_type, io_capability as u8)
+}
+
 ///	The `address` and `address_type` parameters should match what was
 ///	given to a preceding Pair Device command.
 ///
@@ -239,7 +271,7 @@ pub async fn cancel_pair_device(
         socket,
         Command::CancelPairDevice,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_cancel_pair_device(address, address_type)),
         event_tx,
     )
     .await?;
@@ -247,6 +279,10 @@ pub async fn cancel_pair_device(
     get_address(param)
 }
 
+pub(crate) fn encode_cancel_pair_device(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 ///	Removes all keys associated with the remote device.
 ///
 ///	The disconnect parameter tells the kernel whether to forcefully
@@ -275,11 +311,7 @@ pub async fn unpair_device(
         socket,
         Command::UnpairDevice,
         controller,
-        Some(address_bytes_with_u8(
-            address,
-            address_type,
-            disconnect as u8,
-        )),
+        Some(encode_unpair_device(address, address_type, disconnect)),
         event_tx,
     )
     .await?;
@@ -287,6 +319,14 @@ pub async fn unpair_device(
     get_address(param)
 }
 
+pub(crate) fn encode_unpair_device(
+    address: Address,
+    address_type: AddressType,
+    disconnect: bool,
+) -> Bytes {
+    address_bytes_with_u8(address, address_type, disconnect as u8)
+}
+
 ///	This command is used to respond to a User Confirmation Request
 ///	event. This command can only be used when the controller is powered.
 pub async fn user_confirmation_reply(
@@ -297,22 +337,28 @@ pub async fn user_confirmation_reply(
     reply: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<(Address, AddressType)> {
-    let (_, param) = exec_command(
-        socket,
-        if reply {
-            Command::UserConfirmationReply
-        } else {
-            Command::UserConfirmationNegativeReply
-        },
-        controller,
-        Some(address_bytes(address, address_type)),
-        event_tx,
-    )
-    .await?;
+    let (opcode, param) = encode_user_confirmation_reply(address, address_type, reply);
+    let (_, param) = exec_command(socket, opcode, controller, Some(param), event_tx).await?;
 
     get_address(param)
 }
 
+/// Returns the command which accepts or rejects a User Confirmation request,
+/// together with its parameters.
+pub(crate) fn encode_user_confirmation_reply(
+    address: Address,
+    address_type: AddressType,
+    reply: bool,
+) -> (Command, Bytes) {
+    let opcode = if reply {
+        Command::UserConfirmationReply
+    } else {
+        Command::UserConfirmationNegativeReply
+    };
+
+    (opcode, address_bytes(address, address_type))
+}
+
 ///	This command is used to respond to a User Passkey Request
 ///	event. Passing None for passkey will send a negative response.
 /// This command can only be used when the controller is powered.
@@ -321,31 +367,37 @@ pub async fn user_passkey_reply(
     controller: Controller,
     address: Address,
     address_type: AddressType,
-    passkey: Option<u32>,
+    passkey: Option<Passkey>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<(Address, AddressType)> {
-    let opcode;
-    let mut param;
-
-    if let Some(passkey) = passkey {
-        opcode = Command::UserPasskeyReply;
-        param = BytesMut::with_capacity(11);
-        param.put_slice(address.as_ref());
-        param.put_u8(address_type as u8);
-        param.put_u32_le(passkey);
-    } else {
-        opcode = Command::UserPasskeyNegativeReply;
-        param = BytesMut::with_capacity(7);
-        param.put_slice(address.as_ref());
-        param.put_u8(address_type as u8);
-    }
-
-    let (_, param) =
-        exec_command(socket, opcode, controller, Some(param.freeze()), event_tx).await?;
+    let (opcode, param) = encode_user_passkey_reply(address, address_type, passkey);
+    let (_, param) = exec_command(socket, opcode, controller, Some(param), event_tx).await?;
 
     get_address(param)
 }
 
+/// Returns the command which answers a User Passkey request with `passkey`,
+/// or rejects it if there is none, together with its parameters.
+pub(crate) fn encode_user_passkey_reply(
+    address: Address,
+    address_type: AddressType,
+    passkey: Option<Passkey>,
+) -> (Command, Bytes) {
+    match passkey {
+        Some(passkey) => {
+            let mut param = BytesMut::with_capacity(11);
+            param.put_slice(address.as_ref());
+            param.put_u8(address_type.to_mgmt_u8());
+            param.put_u32_le(passkey.value());
+            (Command::UserPasskeyReply, param.freeze())
+        }
+        None => (
+            Command::UserPasskeyNegativeReply,
+            address_bytes(address, address_type),
+        ),
+    }
+}
+
 ///	This command is used to add a device to the action list. The
 ///	action list allows scanning for devices and enables incoming
 ///	connections from known devices.
@@ -386,7 +438,7 @@ pub async fn add_device(
         socket,
         Command::AddDevice,
         controller,
-        Some(address_bytes_with_u8(address, address_type, action as u8)),
+        Some(encode_add_device(address, address_type, action)),
         event_tx,
     )
     .await?;
@@ -394,6 +446,14 @@ pub async fn add_device(
     get_address(param)
 }
 
+pub(crate) fn encode_add_device(
+    address: Address,
+    address_type: AddressType,
+    action: AddDeviceAction,
+) -> Bytes {
+    address_bytes_with_u8(address, address_type, action.into())
+}
+
 ///	This command is used to remove a device from the action list
 ///	previously added by using the Add Device command.
 ///
@@ -413,10 +473,14 @@ pub async fn remove_device(
         socket,
         Command::RemoveDevice,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_remove_device(address, address_type)),
         event_tx,
     )
     .await?;
 
     get_address(param)
 }
+
+pub(crate) fn encode_remove_device(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
@@ -1,4 +1,8 @@
+use std::convert::TryFrom;
+use std::fmt;
+
 use super::*;
+use crate::util::BufExt;
 use crate::AddressType;
 
 /// This command is used to feed the kernel with currently known
@@ -23,23 +27,11 @@ pub async fn load_link_keys(
     debug: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(3 + keys.len() * 25);
-    param.put_u8(debug as u8);
-    param.put_u16_le(keys.len() as u16);
-
-    for key in keys {
-        param.put_slice(key.address.as_ref());
-        param.put_u8(key.address_type as u8);
-        param.put_u8(key.key_type as u8);
-        param.put_slice(&key.value[..]);
-        param.put_u8(key.pin_length);
-    }
-
     let (_, _param) = exec_command(
         socket,
         Command::LoadLinkKeys,
         controller,
-        Some(param.freeze()),
+        Some(encode_load_link_keys(&keys, debug)),
         event_tx,
     )
     .await?;
@@ -47,6 +39,18 @@ pub async fn load_link_keys(
     Ok(())
 }
 
+pub(crate) fn encode_load_link_keys(keys: &[LinkKey], debug: bool) -> Bytes {
+    let mut param = BytesMut::with_capacity(3 + keys.len() * 25);
+    param.put_u8(debug as u8);
+    param.put_u16_le(keys.len() as u16);
+
+    for key in keys {
+        key.put(&mut param);
+    }
+
+    param.freeze()
+}
+
 ///	This command is used to feed the kernel with currently known
 ///	(SMP) Long Term Keys. The command does not need to be called
 ///	again upon the receipt of New Long Term Key events since the
@@ -65,25 +69,11 @@ pub async fn load_long_term_keys(
     keys: Vec<LongTermKey>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(2 + keys.len() * 32);
-    param.put_u16_le(keys.len() as u16);
-
-    for key in keys {
-        param.put_slice(key.address.as_ref());
-        param.put_u8(key.address_type as u8);
-        param.put_u8(key.key_type as u8);
-        param.put_u8(key.master);
-        param.put_u8(key.encryption_size);
-        param.put_u16_le(key.encryption_diversifier);
-        param.put_u64_le(key.random_number);
-        param.put_slice(&key.value[..]);
-    }
-
     let (_, _param) = exec_command(
         socket,
         Command::LoadLongTermKeys,
         controller,
-        Some(param.freeze()),
+        Some(encode_load_long_term_keys(&keys)),
         event_tx,
     )
     .await?;
@@ -91,6 +81,17 @@ pub async fn load_long_term_keys(
     Ok(())
 }
 
+pub(crate) fn encode_load_long_term_keys(keys: &[LongTermKey]) -> Bytes {
+    let mut param = BytesMut::with_capacity(2 + keys.len() * 32);
+    param.put_u16_le(keys.len() as u16);
+
+    for key in keys {
+        key.put(&mut param);
+    }
+
+    param.freeze()
+}
+
 ///	This command is used to feed the kernel with currently known
 ///	identity resolving keys. The command does not need to be called
 ///	again upon the receipt of New Identity Resolving Key events
@@ -109,20 +110,11 @@ pub async fn load_identity_resolving_keys(
     keys: Vec<IdentityResolvingKey>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(2 + keys.len() * 23);
-    param.put_u16_le(keys.len() as u16);
-
-    for key in keys {
-        param.put_slice(key.address.as_ref());
-        param.put_u8(key.address_type as u8);
-        param.put_slice(&key.value[..]);
-    }
-
     let (_, _param) = exec_command(
         socket,
         Command::LoadIdentityResolvingKeys,
         controller,
-        Some(param.freeze()),
+        Some(encode_load_identity_resolving_keys(&keys)),
         event_tx,
     )
     .await?;
@@ -130,6 +122,17 @@ pub async fn load_identity_resolving_keys(
     Ok(())
 }
 
+pub(crate) fn encode_load_identity_resolving_keys(keys: &[IdentityResolvingKey]) -> Bytes {
+    let mut param = BytesMut::with_capacity(2 + keys.len() * 23);
+    param.put_u16_le(keys.len() as u16);
+
+    for key in keys {
+        key.put(&mut param);
+    }
+
+    param.freeze()
+}
+
 ///	This command is used to load connection parameters from several
 ///	devices into kernel. Currently this is only supported on controllers
 ///	with Low Energy support.
@@ -141,35 +144,57 @@ pub async fn load_identity_resolving_keys(
 ///	`connection_latency` and `supervision_timeout` parameters should
 ///	be configured as described in Core 4.1 spec, Vol 2, 7.8.12.
 ///
+/// Each entry is checked with [`ConnectionParams::validate`] before the
+/// command is sent. Like the kernel, which ignores the entries that it
+/// considers invalid, an invalid entry does not stop the others from being
+/// loaded; the entries that were left out are returned together with the
+/// reason.
+///
 ///	This command can be used when the controller is not powered.
 pub async fn load_connection_parameters(
     socket: &mut ManagementStream,
     controller: Controller,
     connection_params: Vec<ConnectionParams>,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<()> {
+) -> Result<Vec<(ConnectionParams, Error)>> {
+    let mut rejected = vec![];
+    let connection_params: Vec<_> = connection_params
+        .into_iter()
+        .filter(|cxn_param| match cxn_param.validate() {
+            Ok(()) => true,
+            Err(err) => {
+                rejected.push((*cxn_param, err));
+                false
+            }
+        })
+        .collect();
+
+    let (_, _param) = exec_command(
+        socket,
+        Command::LoadConnectionParameters,
+        controller,
+        Some(encode_load_connection_parameters(&connection_params)),
+        event_tx,
+    )
+    .await?;
+
+    Ok(rejected)
+}
+
+pub(crate) fn encode_load_connection_parameters(connection_params: &[ConnectionParams]) -> Bytes {
     let mut param = BytesMut::with_capacity(2 + connection_params.len() * 15);
     param.put_u16_le(connection_params.len() as u16);
 
     for cxn_param in connection_params {
         param.put_slice(cxn_param.address.as_ref());
-        param.put_u8(cxn_param.address_type as u8);
+        param.put_u8(cxn_param.address_type.to_mgmt_u8());
         param.put_u16_le(cxn_param.min_connection_interval);
         param.put_u16_le(cxn_param.max_connection_interval);
         param.put_u16_le(cxn_param.connection_latency);
         param.put_u16_le(cxn_param.supervision_timeout);
     }
 
-    let (_, _param) = exec_command(
-        socket,
-        Command::LoadConnectionParameters,
-        controller,
-        Some(param.freeze()),
-        event_tx,
-    )
-    .await?;
-
-    Ok(())
+    param.freeze()
 }
 
 /// This command is used to feed the kernel a list of keys that
@@ -185,19 +210,11 @@ pub async fn load_blocked_keys(
     keys: Vec<BlockedKey>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(2 + keys.len() * 17);
-    param.put_u16_le(keys.len() as u16);
-
-    for key in keys {
-        param.put_u8(key.key_type as u8);
-        param.put_slice(&key.value[..]);
-    }
-
     let (_, _param) = exec_command(
         socket,
         Command::LoadBlockedKeys,
         controller,
-        Some(param.freeze()),
+        Some(encode_load_blocked_keys(&keys)),
         event_tx,
     )
     .await?;
@@ -205,7 +222,22 @@ pub async fn load_blocked_keys(
     Ok(())
 }
 
-#[derive(Debug)]
+pub(crate) fn encode_load_blocked_keys(keys: &[BlockedKey]) -> Bytes {
+    let mut param = BytesMut::with_capacity(2 + keys.len() * 17);
+    param.put_u16_le(keys.len() as u16);
+
+    for key in keys {
+        param.put_u8(key.key_type as u8);
+        param.put_slice(&key.value[..]);
+    }
+
+    param.freeze()
+}
+
+/// A BR/EDR link key, as it is loaded with [`load_link_keys`]. It can be
+/// parsed from the 25 bytes that follow the store hint in a New Link Key
+/// event, or taken from the event with [`LinkKey::from_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LinkKey {
     pub address: Address,
     pub address_type: AddressType,
@@ -214,21 +246,146 @@ pub struct LinkKey {
     pub pin_length: u8,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
-#[repr(u8)]
+impl LinkKey {
+    pub fn new(
+        address: Address,
+        address_type: AddressType,
+        key_type: LinkKeyType,
+        value: [u8; 16],
+        pin_length: u8,
+    ) -> Self {
+        Self {
+            address,
+            address_type,
+            key_type,
+            value,
+            pin_length,
+        }
+    }
+
+    /// Returns the key from a [`Event::NewLinkKey`], or `None` if `event` is
+    /// a different event.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match *event {
+            Event::NewLinkKey {
+                address,
+                address_type,
+                key_type,
+                value,
+                pin_length,
+                ..
+            } => Some(Self::new(
+                address,
+                address_type,
+                key_type,
+                value,
+                pin_length,
+            )),
+            _ => None,
+        }
+    }
+
+    fn put(&self, buf: &mut BytesMut) {
+        buf.put_slice(self.address.as_ref());
+        buf.put_u8(self.address_type.to_mgmt_u8());
+        buf.put_u8(self.key_type.into());
+        buf.put_slice(&self.value[..]);
+        buf.put_u8(self.pin_length);
+    }
+}
+
+impl TryFrom<&[u8]> for LinkKey {
+    type Error = Error;
+
+    fn try_from(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != 25 {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Self {
+            address: buf.get_address(),
+            address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+            key_type: buf.get_u8().into(),
+            value: buf.get_array_u8(),
+            pin_length: buf.get_u8(),
+        })
+    }
+}
+
+/// The type of a link key. Types which this library does not know about are
+/// kept in [`LinkKeyType::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum LinkKeyType {
-    Combination = 0x00,
-    LocalUnit = 0x01,
-    RemoteUnit = 0x02,
-    DebugCombination = 0x03,
-    UnauthenticatedCombinationP192 = 0x04,
-    AuthenticatedCombinationP192 = 0x05,
-    ChangedCombination = 0x06,
-    UnauthenticatedCombinationP256 = 0x07,
-    AuthenticatedCombinationP256 = 0x08,
-}
-
-#[derive(Debug)]
+    Combination,
+    LocalUnit,
+    RemoteUnit,
+    DebugCombination,
+    UnauthenticatedCombinationP192,
+    AuthenticatedCombinationP192,
+    ChangedCombination,
+    UnauthenticatedCombinationP256,
+    AuthenticatedCombinationP256,
+    Other(u8),
+}
+
+impl From<u8> for LinkKeyType {
+    fn from(key_type: u8) -> Self {
+        match key_type {
+            0x00 => LinkKeyType::Combination,
+            0x01 => LinkKeyType::LocalUnit,
+            0x02 => LinkKeyType::RemoteUnit,
+            0x03 => LinkKeyType::DebugCombination,
+            0x04 => LinkKeyType::UnauthenticatedCombinationP192,
+            0x05 => LinkKeyType::AuthenticatedCombinationP192,
+            0x06 => LinkKeyType::ChangedCombination,
+            0x07 => LinkKeyType::UnauthenticatedCombinationP256,
+            0x08 => LinkKeyType::AuthenticatedCombinationP256,
+            key_type => LinkKeyType::Other(key_type),
+        }
+    }
+}
+
+impl From<LinkKeyType> for u8 {
+    fn from(key_type: LinkKeyType) -> Self {
+        match key_type {
+            LinkKeyType::Combination => 0x00,
+            LinkKeyType::LocalUnit => 0x01,
+            LinkKeyType::RemoteUnit => 0x02,
+            LinkKeyType::DebugCombination => 0x03,
+            LinkKeyType::UnauthenticatedCombinationP192 => 0x04,
+            LinkKeyType::AuthenticatedCombinationP192 => 0x05,
+            LinkKeyType::ChangedCombination => 0x06,
+            LinkKeyType::UnauthenticatedCombinationP256 => 0x07,
+            LinkKeyType::AuthenticatedCombinationP256 => 0x08,
+            LinkKeyType::Other(key_type) => key_type,
+        }
+    }
+}
+
+impl fmt::Display for LinkKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LinkKeyType::Combination => "combination",
+            LinkKeyType::LocalUnit => "local unit",
+            LinkKeyType::RemoteUnit => "remote unit",
+            LinkKeyType::DebugCombination => "debug combination",
+            LinkKeyType::UnauthenticatedCombinationP192 => "unauthenticated combination (P-192)",
+            LinkKeyType::AuthenticatedCombinationP192 => "authenticated combination (P-192)",
+            LinkKeyType::ChangedCombination => "changed combination",
+            LinkKeyType::UnauthenticatedCombinationP256 => "unauthenticated combination (P-256)",
+            LinkKeyType::AuthenticatedCombinationP256 => "authenticated combination (P-256)",
+            LinkKeyType::Other(value) => return write!(f, "unknown key type 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// An LE long term key, as it is loaded with [`load_long_term_keys`]. It can
+/// be parsed from the 36 bytes that follow the store hint in a New Long Term
+/// Key event, or taken from the event with [`LongTermKey::from_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LongTermKey {
     pub address: Address,
     pub address_type: AddressType,
@@ -240,41 +397,332 @@ pub struct LongTermKey {
     pub value: [u8; 16],
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
-#[repr(u8)]
+impl LongTermKey {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        address: Address,
+        address_type: AddressType,
+        key_type: LongTermKeyType,
+        master: u8,
+        encryption_size: u8,
+        encryption_diversifier: u16,
+        random_number: u64,
+        value: [u8; 16],
+    ) -> Self {
+        Self {
+            address,
+            address_type,
+            key_type,
+            master,
+            encryption_size,
+            encryption_diversifier,
+            random_number,
+            value,
+        }
+    }
+
+    /// Returns the key from a [`Event::NewLongTermKey`], or `None` if `event`
+    /// is a different event.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match *event {
+            Event::NewLongTermKey {
+                address,
+                address_type,
+                key_type,
+                master,
+                encryption_size,
+                encryption_diversifier,
+                random_number,
+                value,
+                ..
+            } => Some(Self::new(
+                address,
+                address_type,
+                key_type,
+                master,
+                encryption_size,
+                encryption_diversifier,
+                random_number,
+                value,
+            )),
+            _ => None,
+        }
+    }
+
+    fn put(&self, buf: &mut BytesMut) {
+        buf.put_slice(self.address.as_ref());
+        buf.put_u8(self.address_type.to_mgmt_u8());
+        buf.put_u8(self.key_type.into());
+        buf.put_u8(self.master);
+        buf.put_u8(self.encryption_size);
+        buf.put_u16_le(self.encryption_diversifier);
+        buf.put_u64_le(self.random_number);
+        buf.put_slice(&self.value[..]);
+    }
+}
+
+impl TryFrom<&[u8]> for LongTermKey {
+    type Error = Error;
+
+    fn try_from(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != 36 {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Self {
+            address: buf.get_address(),
+            address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+            key_type: buf.get_u8().into(),
+            master: buf.get_u8(),
+            encryption_size: buf.get_u8(),
+            encryption_diversifier: buf.get_u16_le(),
+            random_number: buf.get_u64_le(),
+            value: buf.get_array_u8(),
+        })
+    }
+}
+
+/// The type of a long term key. Types which this library does not know
+/// about are kept in [`LongTermKeyType::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum LongTermKeyType {
-    UnauthenticatedLegacy = 0x00,
+    UnauthenticatedLegacy,
     AuthenticatedLegacy,
     UnauthenticatedP256,
     AuthenticatedP256,
     DebugP256,
+    Other(u8),
+}
+
+impl From<u8> for LongTermKeyType {
+    fn from(key_type: u8) -> Self {
+        match key_type {
+            0x00 => LongTermKeyType::UnauthenticatedLegacy,
+            0x01 => LongTermKeyType::AuthenticatedLegacy,
+            0x02 => LongTermKeyType::UnauthenticatedP256,
+            0x03 => LongTermKeyType::AuthenticatedP256,
+            0x04 => LongTermKeyType::DebugP256,
+            key_type => LongTermKeyType::Other(key_type),
+        }
+    }
 }
 
-#[derive(Debug)]
+impl From<LongTermKeyType> for u8 {
+    fn from(key_type: LongTermKeyType) -> Self {
+        match key_type {
+            LongTermKeyType::UnauthenticatedLegacy => 0x00,
+            LongTermKeyType::AuthenticatedLegacy => 0x01,
+            LongTermKeyType::UnauthenticatedP256 => 0x02,
+            LongTermKeyType::AuthenticatedP256 => 0x03,
+            LongTermKeyType::DebugP256 => 0x04,
+            LongTermKeyType::Other(key_type) => key_type,
+        }
+    }
+}
+
+impl fmt::Display for LongTermKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LongTermKeyType::UnauthenticatedLegacy => "unauthenticated legacy",
+            LongTermKeyType::AuthenticatedLegacy => "authenticated legacy",
+            LongTermKeyType::UnauthenticatedP256 => "unauthenticated (P-256)",
+            LongTermKeyType::AuthenticatedP256 => "authenticated (P-256)",
+            LongTermKeyType::DebugP256 => "debug (P-256)",
+            LongTermKeyType::Other(value) => return write!(f, "unknown key type 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// An identity resolving key, as it is loaded with
+/// [`load_identity_resolving_keys`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IdentityResolvingKey {
     pub address: Address,
     pub address_type: AddressType,
     pub value: [u8; 16],
 }
 
+impl IdentityResolvingKey {
+    pub fn new(address: Address, address_type: AddressType, value: [u8; 16]) -> Self {
+        Self {
+            address,
+            address_type,
+            value,
+        }
+    }
+
+    fn put(&self, buf: &mut BytesMut) {
+        buf.put_slice(self.address.as_ref());
+        buf.put_u8(self.address_type.to_mgmt_u8());
+        buf.put_slice(&self.value[..]);
+    }
+}
+
+impl TryFrom<&[u8]> for IdentityResolvingKey {
+    type Error = Error;
+
+    fn try_from(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != 23 {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Self {
+            address: buf.get_address(),
+            address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+            value: buf.get_array_u8(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BlockedKey {
     pub key_type: BlockedKeyType,
     pub value: [u8; 16],
 }
 
+impl BlockedKey {
+    pub fn new(key_type: BlockedKeyType, value: [u8; 16]) -> Self {
+        Self { key_type, value }
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
 pub enum BlockedKeyType {
-    LinkKey = 1 << 0,
-    LongTermKey = 1 << 1,
-    IdentityResolvingKey = 1 << 2,
+    LinkKey = 0x00,
+    LongTermKey = 0x01,
+    IdentityResolvingKey = 0x02,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
-#[repr(u8)]
+/// The type of a signature resolving key. Types which this library does not
+/// know about are kept in [`SignatureResolvingKeyType::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum SignatureResolvingKeyType {
-    UnauthenticatedLocalCSRK = 0x00,
-    UnauthenticatedRemoteCSRK = 0x01,
-    AuthenticatedLocalCSRK = 0x02,
+    UnauthenticatedLocalCSRK,
+    UnauthenticatedRemoteCSRK,
+    AuthenticatedLocalCSRK,
     AuthenticatedRemoteCSRK,
+    Other(u8),
+}
+
+impl From<u8> for SignatureResolvingKeyType {
+    fn from(key_type: u8) -> Self {
+        match key_type {
+            0x00 => SignatureResolvingKeyType::UnauthenticatedLocalCSRK,
+            0x01 => SignatureResolvingKeyType::UnauthenticatedRemoteCSRK,
+            0x02 => SignatureResolvingKeyType::AuthenticatedLocalCSRK,
+            0x03 => SignatureResolvingKeyType::AuthenticatedRemoteCSRK,
+            key_type => SignatureResolvingKeyType::Other(key_type),
+        }
+    }
+}
+
+impl From<SignatureResolvingKeyType> for u8 {
+    fn from(key_type: SignatureResolvingKeyType) -> Self {
+        match key_type {
+            SignatureResolvingKeyType::UnauthenticatedLocalCSRK => 0x00,
+            SignatureResolvingKeyType::UnauthenticatedRemoteCSRK => 0x01,
+            SignatureResolvingKeyType::AuthenticatedLocalCSRK => 0x02,
+            SignatureResolvingKeyType::AuthenticatedRemoteCSRK => 0x03,
+            SignatureResolvingKeyType::Other(key_type) => key_type,
+        }
+    }
+}
+
+impl fmt::Display for SignatureResolvingKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SignatureResolvingKeyType::UnauthenticatedLocalCSRK => "unauthenticated local CSRK",
+            SignatureResolvingKeyType::UnauthenticatedRemoteCSRK => "unauthenticated remote CSRK",
+            SignatureResolvingKeyType::AuthenticatedLocalCSRK => "authenticated local CSRK",
+            SignatureResolvingKeyType::AuthenticatedRemoteCSRK => "authenticated remote CSRK",
+            SignatureResolvingKeyType::Other(value) => {
+                return write!(f, "unknown key type 0x{:02x}", value)
+            }
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{MockKernel, MockScript};
+
+    #[test]
+    fn link_key_round_trip() {
+        let key = LinkKey::new(
+            Address::from([1, 2, 3, 4, 5, 6]),
+            AddressType::BREDR,
+            LinkKeyType::AuthenticatedCombinationP256,
+            [0xAA; 16],
+            0,
+        );
+
+        let mut buf = BytesMut::new();
+        key.put(&mut buf);
+        assert_eq!(LinkKey::try_from(&buf[..]).unwrap(), key);
+        assert!(LinkKey::try_from(&buf[1..]).is_err());
+
+        let key = LongTermKey::new(
+            Address::from([1, 2, 3, 4, 5, 6]),
+            AddressType::LERandom,
+            LongTermKeyType::AuthenticatedP256,
+            1,
+            16,
+            0x1234,
+            0x0102_0304_0506_0708,
+            [0x55; 16],
+        );
+
+        let mut buf = BytesMut::new();
+        key.put(&mut buf);
+        assert_eq!(LongTermKey::try_from(&buf[..]).unwrap(), key);
+    }
+
+    #[tokio::test]
+    async fn invalid_connection_params_are_left_out() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let script = MockScript::new().reply(Command::LoadConnectionParameters, []);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        let valid = ConnectionParams::from_raw(
+            Address::from([1, 2, 3, 4, 5, 6]),
+            AddressType::LEPublic,
+            6,
+            40,
+            4,
+            100,
+        )
+        .unwrap();
+        let invalid = ConnectionParams {
+            address: Address::from([6, 5, 4, 3, 2, 1]),
+            min_connection_interval: 0x0C81,
+            ..valid
+        };
+
+        let rejected =
+            load_connection_parameters(&mut socket, Controller(0), vec![invalid, valid], None)
+                .await
+                .unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, invalid);
+        assert!(matches!(
+            rejected[0].1,
+            Error::InvalidConnectionParams { .. }
+        ));
+
+        drop(socket);
+        let commands = kernel.await.unwrap().unwrap();
+        #[rustfmt::skip]
+        assert_eq!(&commands[0].param[..], &[
+            0x01, 0x00,
+            1, 2, 3, 4, 5, 6, 0x01, 0x06, 0x00, 0x28, 0x00, 0x04, 0x00, 0x64, 0x00,
+        ]);
+    }
 }
@@ -3,14 +3,28 @@ use std::ffi::CString;
 use bytes::*;
 
 pub use advertising::*;
+pub use agent::*;
 pub use class::*;
+pub use clock::*;
+pub use connect::*;
 pub use discovery::*;
+pub use experimental::*;
+pub use identity::*;
 pub use interact::*;
 pub use load::*;
 pub use oob::*;
+pub use options::*;
+pub use pairing::*;
 pub use params::*;
+pub use permissions::*;
+pub use pipeline::*;
 pub use query::*;
+pub use raw::*;
+pub use reconnect::*;
+pub use retry::*;
 pub use settings::*;
+pub use task::*;
+pub use watch::*;
 
 use tokio::sync::mpsc;
 
@@ -20,16 +34,65 @@ use crate::management::{Error, Result};
 use crate::Address;
 
 mod advertising;
+mod agent;
 mod class;
+mod clock;
+mod connect;
 mod discovery;
+mod experimental;
+#[cfg(test)]
+mod golden;
+mod identity;
 mod interact;
 mod load;
 mod oob;
+mod options;
+mod pairing;
 mod params;
+mod permissions;
+mod pipeline;
 mod query;
+mod raw;
+mod reconnect;
+mod retry;
 mod settings;
+mod task;
+mod watch;
 
 async fn exec_command(
+    socket: &mut ManagementStream,
+    opcode: Command,
+    controller: Controller,
+    param: Option<Bytes>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<(Controller, Option<Bytes>)> {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        let span = tracing::debug_span!("exec_command", ?opcode, %controller);
+        let start = std::time::Instant::now();
+        let result = exec_command_untraced(socket, opcode, controller, param, event_tx)
+            .instrument(span.clone())
+            .await;
+        let latency = start.elapsed();
+
+        span.in_scope(|| match &result {
+            Ok(_) => tracing::debug!(?latency, "command succeeded"),
+            Err(Error::CommandError { status, .. }) => {
+                tracing::debug!(?latency, ?status, "command failed")
+            }
+            Err(err) => tracing::debug!(?latency, %err, "command failed"),
+        });
+
+        result
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    exec_command_untraced(socket, opcode, controller, param, event_tx).await
+}
+
+async fn exec_command_untraced(
     socket: &mut ManagementStream,
     opcode: Command,
     controller: Controller,
@@ -37,6 +100,7 @@ async fn exec_command(
     mut event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<(Controller, Option<Bytes>)> {
     let param = param.unwrap_or(Bytes::new());
+    socket.set_last_reply(None);
 
     // send request
     socket
@@ -59,10 +123,12 @@ async fn exec_command(
                 param,
                 opcode: evt_opcode,
             } if opcode == evt_opcode => {
+                socket.set_last_reply(Some(param.clone()));
+
                 return match status {
                     CommandStatus::Success => Ok((response.controller, Some(param))),
-                    _ => Err(Error::CommandError { opcode, status }),
-                }
+                    _ => Err(Error::command_failed(opcode, controller, status)),
+                };
             }
 
             Event::CommandStatus {
@@ -71,7 +137,7 @@ async fn exec_command(
             } if opcode == evt_opcode => {
                 return match status {
                     CommandStatus::Success => Ok((response.controller, None)),
-                    _ => Err(Error::CommandError { opcode, status }),
+                    _ => Err(Error::command_failed(opcode, controller, status)),
                 }
             }
 
@@ -83,3 +149,50 @@ async fn exec_command(
         }
     }
 }
+
+/// Receives responses until `filter` returns `Some`, forwarding every response
+/// that it rejects to `event_tx`.
+async fn wait_for_event<T>(
+    socket: &mut ManagementStream,
+    mut filter: impl FnMut(&Response) -> Option<T>,
+    mut event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<T> {
+    loop {
+        let response = socket.receive().await?;
+
+        if let Some(value) = filter(&response) {
+            return Ok(value);
+        }
+
+        if let Some(event_tx) = &mut event_tx {
+            let _ = event_tx.send(response).await;
+        }
+    }
+}
+
+/// Runs `workflow` with an event sender of its own, and returns the events
+/// that it forwarded along with its result. Helpers which keep state based on
+/// events use this for the commands that they run themselves, so that the
+/// events which those commands receive are not missed.
+async fn collect_events<T, F>(
+    workflow: impl FnOnce(mpsc::Sender<Response>) -> F,
+) -> (T, Vec<Response>)
+where
+    F: std::future::Future<Output = T>,
+{
+    let (event_tx, mut event_rx) = mpsc::channel(16);
+
+    // the events are received while the workflow is running, so that it does
+    // not wait for room in the channel
+    let collect = async move {
+        let mut responses = vec![];
+
+        while let Some(response) = event_rx.recv().await {
+            responses.push(response);
+        }
+
+        responses
+    };
+
+    futures::join!(workflow(event_tx), collect)
+}
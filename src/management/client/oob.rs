@@ -1,5 +1,4 @@
 use crate::AddressType;
-use enumflags2::BitFlags;
 
 use super::interact::{address_bytes, get_address};
 use super::*;
@@ -53,14 +52,14 @@ pub async fn read_local_oob_data(
 pub async fn read_local_oob_ext_data(
     socket: &mut ManagementStream,
     controller: Controller,
-    address_types: BitFlags<AddressTypeFlag>,
+    address_types: AddressTypes,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<(BitFlags<AddressTypeFlag>, Bytes)> {
+) -> Result<(AddressTypes, Bytes)> {
     let (_, param) = exec_command(
         socket,
         Command::ReadLocalOutOfBandExtended,
         controller,
-        Some(Bytes::copy_from_slice(&[address_types.bits() as u8])),
+        Some(encode_read_local_oob_ext_data(address_types)),
         event_tx,
     )
     .await?;
@@ -78,6 +77,10 @@ pub async fn read_local_oob_ext_data(
     ))
 }
 
+pub(crate) fn encode_read_local_oob_ext_data(address_types: AddressTypes) -> Bytes {
+    u8_bytes(address_types.bits())
+}
+
 ///	This command is used to provide Out of Band data for a remote
 ///	device.
 ///
@@ -119,9 +122,26 @@ pub async fn add_remote_oob_data(
     data: OutOfBandData,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<(Address, AddressType)> {
-    let mut param = BytesMut::with_capacity(39);
+    let (_, param) = exec_command(
+        socket,
+        Command::AddRemoteOutOfBand,
+        controller,
+        Some(encode_add_remote_oob_data(address, address_type, &data)),
+        event_tx,
+    )
+    .await?;
+
+    get_address(param)
+}
+
+pub(crate) fn encode_add_remote_oob_data(
+    address: Address,
+    address_type: AddressType,
+    data: &OutOfBandData,
+) -> Bytes {
+    let mut param = BytesMut::with_capacity(71);
     param.put_slice(address.as_ref());
-    param.put_u8(address_type as u8);
+    param.put_u8(address_type.to_mgmt_u8());
     param.put_slice(&data.hash_192[..]);
     param.put_slice(&data.randomizer_192[..]);
 
@@ -132,16 +152,7 @@ pub async fn add_remote_oob_data(
         param.put_slice(&randomizer_256[..]);
     }
 
-    let (_, param) = exec_command(
-        socket,
-        Command::AddRemoteOutOfBand,
-        controller,
-        Some(param.freeze()),
-        event_tx,
-    )
-    .await?;
-
-    get_address(param)
+    param.freeze()
 }
 
 /// This command is used to remove data added using the Add Remote
@@ -163,7 +174,7 @@ pub async fn remove_remote_oob_data(
         socket,
         Command::RemoveRemoteOutOfBand,
         controller,
-        Some(address_bytes(address, address_type)),
+        Some(encode_remove_remote_oob_data(address, address_type)),
         event_tx,
     )
     .await?;
@@ -171,6 +182,10 @@ pub async fn remove_remote_oob_data(
     get_address(param)
 }
 
+pub(crate) fn encode_remove_remote_oob_data(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 #[derive(Debug)]
 pub struct OutOfBandData {
     pub hash_192: [u8; 16],
@@ -0,0 +1,278 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{select, BoxFuture, Either};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use super::agent::{drive_pairing, RecordingAgent};
+use super::*;
+use crate::management::PairingError;
+use crate::AddressType;
+
+/// A token which can be used to cancel commands that are run with
+/// [`CommandOptions`]. Clones of a token share its state, so a token can be
+/// cancelled from another task.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelTokenInner>,
+}
+
+#[derive(Debug, Default)]
+struct CancelTokenInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every command that is running with this token, and every
+    /// command that is started with it afterwards.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+
+            if self.is_cancelled() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Limits how long a command may run, for use with [`run_with_options`] and
+/// the `_with_options` variants of long-running commands such as
+/// [`pair_device_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct CommandOptions {
+    /// The command fails with [`Error::TimedOut`] if it has not completed by
+    /// this time.
+    pub deadline: Option<Instant>,
+
+    /// The command fails with [`Error::Cancelled`] when this token is
+    /// cancelled.
+    pub cancel_token: Option<CancelToken>,
+}
+
+impl CommandOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the deadline to `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    async fn aborted(&self) -> Error {
+        let deadline = async {
+            match self.deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        let cancelled = async {
+            match &self.cancel_token {
+                Some(cancel_token) => cancel_token.cancelled().await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        match select(Box::pin(deadline), Box::pin(cancelled)).await {
+            Either::Left(_) => Error::TimedOut,
+            Either::Right(_) => Error::Cancelled,
+        }
+    }
+}
+
+/// Runs a command until it completes, its deadline passes or it is
+/// cancelled, whichever happens first.
+///
+/// `command` is called with `socket`, and usually boxes the future of a
+/// command function, such as
+/// `|socket| set_powered(socket, controller, true, None).boxed()`.
+///
+/// A command that is aborted may still be carried out by the kernel, and its
+/// reply is then received as an unrelated event by the next command on
+/// `socket`. Commands which have a way to be cancelled in the kernel have
+/// their own `_with_options` variant which does so.
+pub async fn run_with_options<T, F>(
+    socket: &mut ManagementStream,
+    options: &CommandOptions,
+    command: F,
+) -> Result<T>
+where
+    F: for<'a> FnOnce(&'a mut ManagementStream) -> BoxFuture<'a, Result<T>>,
+{
+    if options
+        .cancel_token
+        .as_ref()
+        .is_some_and(CancelToken::is_cancelled)
+    {
+        return Err(Error::Cancelled);
+    }
+
+    match select(command(socket), Box::pin(options.aborted())).await {
+        Either::Left((result, _)) => result,
+        Either::Right((err, _)) => Err(err),
+    }
+}
+
+/// Sends a Cancel Pair Device command when it is dropped while armed, so that
+/// the kernel stops pairing when the future that paired is dropped.
+struct CancelPairingOnDrop {
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    armed: bool,
+}
+
+impl Drop for CancelPairingOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            // the kernel looks up the pairing by controller and address, so
+            // the cancellation does not have to be sent on the same socket
+            let _ = ManagementStream::send_detached(Request {
+                opcode: Command::CancelPairDevice,
+                controller: self.controller,
+                param: encode_cancel_pair_device(self.address, self.address_type),
+            });
+        }
+    }
+}
+
+/// Pairs with a remote device like [`pair_device`], but stops when the
+/// deadline in `options` passes or its token is cancelled. In that case, a
+/// Cancel Pair Device command is sent so that the kernel stops pairing as
+/// well, and [`PairingError::TimedOut`] or [`Error::Cancelled`] is returned.
+///
+/// The pairing is also cancelled if the returned future is dropped before it
+/// completes, for example because it lost a `select!` against another
+/// future. Pass [`CommandOptions::default`] to only get this behavior.
+///
+/// If there is an `agent`, it answers the authentication requests of the
+/// remote device, and a failure after it declined one is reported as
+/// [`PairingError::UserRejected`]. Otherwise, the requests are left to
+/// another agent, such as the one of bluetoothd. `io_capability` is sent
+/// with the Pair Device command and only applies to this pairing, so the
+/// IO capability that [`set_io_capability`] sets for the controller is left
+/// unchanged. It should usually be the
+/// [`io_capability`](PairingAgent::io_capability) of the agent.
+#[allow(clippy::too_many_arguments)]
+pub async fn pair_device_with_options(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    address: Address,
+    address_type: AddressType,
+    io_capability: IoCapability,
+    agent: Option<&mut dyn PairingAgent>,
+    options: &CommandOptions,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> std::result::Result<(Address, AddressType), PairingError> {
+    if options
+        .cancel_token
+        .as_ref()
+        .is_some_and(CancelToken::is_cancelled)
+    {
+        return Err(Error::Cancelled.into());
+    }
+
+    let mut guard = CancelPairingOnDrop {
+        controller,
+        address,
+        address_type,
+        armed: true,
+    };
+
+    let mut agent = agent.map(|inner| RecordingAgent {
+        inner,
+        declined: false,
+    });
+
+    let result = {
+        let event_tx = event_tx.clone();
+        let pairing = async {
+            match agent.as_mut() {
+                Some(agent) => {
+                    drive_pairing(
+                        socket,
+                        controller,
+                        address,
+                        address_type,
+                        io_capability,
+                        Some(agent),
+                        event_tx,
+                    )
+                    .await
+                }
+                None => {
+                    pair_device(
+                        socket,
+                        controller,
+                        address,
+                        address_type,
+                        io_capability,
+                        event_tx,
+                    )
+                    .await
+                }
+            }
+        };
+
+        match select(Box::pin(pairing), Box::pin(options.aborted())).await {
+            Either::Left((result, _)) => result,
+            Either::Right((err, _)) => Err(err),
+        }
+    };
+
+    guard.armed = false;
+    let declined = agent.is_some_and(|agent| agent.declined);
+
+    match result {
+        Ok(paired) => Ok(paired),
+        Err(err @ Error::TimedOut) | Err(err @ Error::Cancelled) => {
+            // the kernel replies to the Pair Device command with a failure
+            // once pairing has been cancelled
+            let _ = cancel_pair_device(socket, controller, address, address_type, event_tx).await;
+
+            Err(match err {
+                Error::TimedOut => PairingError::TimedOut { address },
+                err => err.into(),
+            })
+        }
+        Err(err) if declined && err.command_status().is_some() => {
+            Err(PairingError::UserRejected { address })
+        }
+        Err(Error::CommandError {
+            status: CommandStatus::AuthenticationFailed,
+            ..
+        }) => Err(PairingError::AuthenticationFailed { address }),
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,307 @@
+use std::time::Duration;
+
+use super::agent::{answer_authentication, authentication_address, is_authentication_reply};
+use super::*;
+use crate::AddressType;
+
+/// Makes the controller bondable, connectable and discoverable for
+/// `duration`, and waits for a remote device to pair with it. This is the
+/// usual "press a button to pair" flow.
+///
+/// Authentication requests from the remote device are answered by `agent`.
+/// The window ends as soon as a device has paired, which is detected by the
+/// kernel reporting a new link key or long term key that should be stored.
+/// Afterwards, the settings that were changed are restored, even if the
+/// pairing failed. A device that `agent` does not accept (see
+/// [`PairingAgent::accepts`]) is unpaired and disconnected, and the window
+/// goes on.
+///
+/// A controller that is already discoverable is left as it is, since the
+/// kernel does not report the remaining discoverable timeout, and setting
+/// the mode again would replace it. It stays limited or general
+/// discoverable with the same timeout, which may end before the window.
+///
+/// Returns the device which paired, or `None` if no device paired before the
+/// window ended.
+pub async fn pairing_window(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    duration: Duration,
+    mut agent: Option<&mut dyn PairingAgent>,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<Option<(Address, AddressType)>> {
+    let previous = get_controller_info(socket, controller, event_tx.clone())
+        .await?
+        .current_settings;
+    let was_discoverable = previous.contains(ControllerSetting::Discoverable);
+
+    let result = async {
+        set_bondable(socket, controller, true, event_tx.clone()).await?;
+        set_connectable(socket, controller, true, event_tx.clone()).await?;
+
+        if !was_discoverable {
+            // the kernel turns discoverability off by itself when the window
+            // ends, in case this future is dropped before the settings are
+            // restored
+            let timeout = duration.as_secs().clamp(1, u16::MAX as u64) as u16;
+            set_discoverable(
+                socket,
+                controller,
+                DiscoverableMode::General,
+                Some(timeout),
+                event_tx.clone(),
+            )
+            .await?;
+        }
+
+        let wait = wait_for_pairing(socket, controller, &mut agent, event_tx.clone());
+
+        match tokio::time::timeout(duration, wait).await {
+            Ok(paired) => paired.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+    .await;
+
+    let restored: Result<()> = async {
+        if !was_discoverable {
+            set_discoverable(
+                socket,
+                controller,
+                DiscoverableMode::None,
+                None,
+                event_tx.clone(),
+            )
+            .await?;
+        }
+
+        if !previous.contains(ControllerSetting::Connectable) {
+            set_connectable(socket, controller, false, event_tx.clone()).await?;
+        }
+
+        if !previous.contains(ControllerSetting::Pairable) {
+            set_bondable(socket, controller, false, event_tx.clone()).await?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    let paired = result?;
+    restored?;
+    Ok(paired)
+}
+
+async fn wait_for_pairing(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    agent: &mut Option<&mut dyn PairingAgent>,
+    mut event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<(Address, AddressType)> {
+    loop {
+        let response = socket.receive().await?;
+
+        if response.controller == controller {
+            match response.event {
+                Event::NewLinkKey {
+                    store_hint: true,
+                    address,
+                    address_type,
+                    ..
+                }
+                | Event::NewLongTermKey {
+                    store_hint: true,
+                    address,
+                    address_type,
+                    ..
+                } => {
+                    if agent
+                        .as_ref()
+                        .is_some_and(|agent| !agent.accepts(address, address_type))
+                    {
+                        // the key is not passed on, so that it is not stored
+                        unpair_device(
+                            socket,
+                            controller,
+                            address,
+                            address_type,
+                            true,
+                            event_tx.clone(),
+                        )
+                        .await?;
+                        continue;
+                    }
+
+                    if let Some(event_tx) = &mut event_tx {
+                        let _ = event_tx.send(response).await;
+                    }
+
+                    return Ok((address, address_type));
+                }
+
+                ref event if authentication_address(event).is_some() => {
+                    if let Some((opcode, param)) = answer_authentication(agent, event) {
+                        socket
+                            .send(Request {
+                                opcode,
+                                controller,
+                                param,
+                            })
+                            .await?;
+                    }
+
+                    continue;
+                }
+
+                Event::CommandComplete { opcode, .. } if is_authentication_reply(opcode) => {
+                    continue
+                }
+
+                _ => {}
+            }
+        }
+
+        if let Some(event_tx) = &mut event_tx {
+            let _ = event_tx.send(response).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{
+        address_param, controller_info, MockCommand, MockScript, MockTransport, MOCK_ADDRESS,
+        MOCK_CONTROLLER,
+    };
+
+    /// A device which is not on the allowlist of the tests.
+    fn unknown() -> Address {
+        Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06])
+    }
+
+    /// A New Long Term Key event that should be stored, for [`unknown`].
+    fn new_long_term_key() -> Vec<u8> {
+        new_long_term_key_for(unknown())
+    }
+
+    fn new_long_term_key_for(address: Address) -> Vec<u8> {
+        let mut event = vec![0x01];
+        event.extend_from_slice(&address_param(address, AddressType::LEPublic));
+        event.extend_from_slice(&[0x00; 29]);
+        event
+    }
+
+    async fn run_window(current_settings: u8) -> (Option<Address>, Vec<MockCommand>) {
+        let script = MockScript::new()
+            .reply(
+                Command::ReadControllerInfo,
+                controller_info(current_settings.into()),
+            )
+            .reply(Command::SetPairable, [0x00; 4])
+            .reply(Command::SetConnectable, [0x00; 4]);
+
+        let script = if current_settings & 0x08 == 0 {
+            script
+                .reply(Command::SetDiscoverable, [0x00; 4])
+                .then_event(0x000A, new_long_term_key())
+                .reply(Command::SetDiscoverable, [0x00; 4])
+                .reply(Command::SetConnectable, [0x00; 4])
+                .reply(Command::SetPairable, [0x00; 4])
+        } else {
+            script.then_event(0x000A, new_long_term_key())
+        };
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let paired = pairing_window(
+            &mut socket,
+            MOCK_CONTROLLER,
+            Duration::from_secs(30),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        (paired.map(|(address, _)| address), transport.commands())
+    }
+
+    #[tokio::test]
+    async fn window_restores_settings() {
+        // powered and br/edr only
+        let (paired, commands) = run_window(0x81).await;
+        assert_eq!(paired, Some(unknown()));
+
+        let commands: Vec<_> = commands
+            .into_iter()
+            .map(|c| (c.opcode, c.param.to_vec()))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                (Command::ReadControllerInfo, vec![]),
+                (Command::SetPairable, vec![0x01]),
+                (Command::SetConnectable, vec![0x01]),
+                (Command::SetDiscoverable, vec![0x01, 30, 0]),
+                (Command::SetDiscoverable, vec![0x00, 0x00, 0x00]),
+                (Command::SetConnectable, vec![0x00]),
+                (Command::SetPairable, vec![0x00]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn window_keeps_discoverable_mode() {
+        // powered, connectable, discoverable, bondable and br/edr
+        let (paired, commands) = run_window(0x9B).await;
+        assert!(paired.is_some());
+
+        // setting the mode again would replace its timeout
+        let opcodes: Vec<_> = commands.into_iter().map(|c| c.opcode).collect();
+        assert_eq!(
+            opcodes,
+            [
+                Command::ReadControllerInfo,
+                Command::SetPairable,
+                Command::SetConnectable,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn window_unpairs_devices_outside_allowlist() {
+        let allowed = MOCK_ADDRESS;
+        let mut agent = JustWorksPolicy::new().with_allowlist([allowed]);
+
+        // powered, connectable, discoverable, bondable and br/edr
+        let script = MockScript::new()
+            .reply(Command::ReadControllerInfo, controller_info(0x9B))
+            .reply(Command::SetPairable, [0x00; 4])
+            .reply(Command::SetConnectable, [0x00; 4])
+            .then_event(0x000A, new_long_term_key())
+            .reply(
+                Command::UnpairDevice,
+                address_param(unknown(), AddressType::LEPublic),
+            )
+            .then_event(0x000A, new_long_term_key_for(allowed));
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let paired = pairing_window(
+            &mut socket,
+            MOCK_CONTROLLER,
+            Duration::from_secs(30),
+            Some(&mut agent),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(paired, Some((allowed, AddressType::LEPublic)));
+
+        let commands = transport.commands();
+        assert_eq!(commands[3].opcode, Command::UnpairDevice);
+        let mut unpair = address_param(unknown(), AddressType::LEPublic);
+        // disconnect
+        unpair.push(0x01);
+        assert_eq!(&commands[3].param[..], &unpair[..]);
+    }
+}
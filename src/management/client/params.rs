@@ -1,7 +1,12 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::Hash;
+use std::time::Duration;
 
 use enumflags2::{bitflags, BitFlags};
 
+use crate::management::Error;
 use crate::{Address, AddressType};
 
 // all of these structs are defined as packed structs here
@@ -45,6 +50,8 @@ pub enum LeAdvertisingMode {
     Enabled = 2,
 }
 
+/// One bit of [`AddressTypes`]. Use [`AddressType`] for single values, and
+/// [`AddressTypes`] for sets.
 #[repr(u8)]
 #[bitflags]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -54,7 +61,37 @@ pub enum AddressTypeFlag {
     LERandom = 1 << 2,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// A set of address types, such as the types that discovery searches for.
+///
+/// A set can be built from [`AddressType`]s with
+/// `AddressTypes::from_iter([AddressType::LEPublic, AddressType::LERandom])`
+/// (which needs `std::iter::FromIterator` in scope on edition 2018) and
+/// checked with `types.contains(AddressType::BREDR)`. Unknown address
+/// types have no bit, so they are never part of a set.
+pub type AddressTypes = BitFlags<AddressTypeFlag>;
+
+impl From<AddressType> for AddressTypes {
+    fn from(address_type: AddressType) -> Self {
+        match address_type {
+            AddressType::BREDR => AddressTypeFlag::BREDR.into(),
+            AddressType::LEPublic => AddressTypeFlag::LEPublic.into(),
+            AddressType::LERandom => AddressTypeFlag::LERandom.into(),
+            AddressType::Other(_) => BitFlags::empty(),
+        }
+    }
+}
+
+impl From<AddressTypeFlag> for AddressType {
+    fn from(flag: AddressTypeFlag) -> Self {
+        match flag {
+            AddressTypeFlag::BREDR => AddressType::BREDR,
+            AddressTypeFlag::LEPublic => AddressType::LEPublic,
+            AddressTypeFlag::LERandom => AddressType::LERandom,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, ToPrimitive)]
 #[repr(u8)]
 pub enum IoCapability {
     DisplayOnly = 0,
@@ -104,26 +141,139 @@ pub struct ClockInfo {
 pub enum DeviceFlag {
     ConfirmName = 1 << 0,
     LegacyPairing = 1 << 1,
+    NotConnectable = 1 << 2,
+    InitiatedConnection = 1 << 3,
+    NameRequestFailed = 1 << 4,
+    ScanResponse = 1 << 5,
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
+/// The reason for a disconnection. Reasons which this library does not know
+/// about are kept in [`DisconnectionReason::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum DisconnectionReason {
-    Unspecified = 0,
-    Timeout = 1,
-    TerminatedLocal = 2,
-    TerminatedRemote = 3,
+    Unspecified,
+    Timeout,
+    TerminatedLocal,
+    TerminatedRemote,
+    AuthenticationFailure,
+    LocalHostSuspend,
+    Other(u8),
 }
 
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
+impl From<u8> for DisconnectionReason {
+    fn from(reason: u8) -> Self {
+        match reason {
+            0 => DisconnectionReason::Unspecified,
+            1 => DisconnectionReason::Timeout,
+            2 => DisconnectionReason::TerminatedLocal,
+            3 => DisconnectionReason::TerminatedRemote,
+            4 => DisconnectionReason::AuthenticationFailure,
+            5 => DisconnectionReason::LocalHostSuspend,
+            reason => DisconnectionReason::Other(reason),
+        }
+    }
+}
+
+impl From<DisconnectionReason> for u8 {
+    fn from(reason: DisconnectionReason) -> Self {
+        match reason {
+            DisconnectionReason::Unspecified => 0,
+            DisconnectionReason::Timeout => 1,
+            DisconnectionReason::TerminatedLocal => 2,
+            DisconnectionReason::TerminatedRemote => 3,
+            DisconnectionReason::AuthenticationFailure => 4,
+            DisconnectionReason::LocalHostSuspend => 5,
+            DisconnectionReason::Other(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for DisconnectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisconnectionReason::Unspecified => "unspecified",
+            DisconnectionReason::Timeout => "connection timeout",
+            DisconnectionReason::TerminatedLocal => "terminated by local host",
+            DisconnectionReason::TerminatedRemote => "terminated by remote device",
+            DisconnectionReason::AuthenticationFailure => "authentication failure",
+            DisconnectionReason::LocalHostSuspend => "local host suspended",
+            DisconnectionReason::Other(value) => {
+                return write!(f, "unknown reason 0x{:02x}", value)
+            }
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// What the kernel does when a device that was added with
+/// [`add_device`](crate::management::add_device) is found. Actions which this
+/// library does not know about are kept in [`AddDeviceAction::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum AddDeviceAction {
-    BackgroundScan = 0,
-    AllowConnect = 1,
-    AutoConnect = 2,
+    BackgroundScan,
+    AllowConnect,
+    AutoConnect,
+    Other(u8),
 }
 
-#[derive(Debug)]
+impl From<u8> for AddDeviceAction {
+    fn from(action: u8) -> Self {
+        match action {
+            0 => AddDeviceAction::BackgroundScan,
+            1 => AddDeviceAction::AllowConnect,
+            2 => AddDeviceAction::AutoConnect,
+            action => AddDeviceAction::Other(action),
+        }
+    }
+}
+
+/// Kept from when this was a plain enum, so that code which parses actions
+/// with [`FromPrimitive`](num_traits::FromPrimitive) still compiles. Every
+/// value that fits in a `u8` is now parsed, into [`AddDeviceAction::Other`]
+/// if it is not known. Casting an action with `as u8` is no longer possible;
+/// use `u8::from` instead.
+impl num_traits::FromPrimitive for AddDeviceAction {
+    fn from_i64(n: i64) -> Option<Self> {
+        u8::try_from(n).ok().map(Self::from)
+    }
+
+    fn from_u64(n: u64) -> Option<Self> {
+        u8::try_from(n).ok().map(Self::from)
+    }
+}
+
+impl From<AddDeviceAction> for u8 {
+    fn from(action: AddDeviceAction) -> Self {
+        match action {
+            AddDeviceAction::BackgroundScan => 0,
+            AddDeviceAction::AllowConnect => 1,
+            AddDeviceAction::AutoConnect => 2,
+            AddDeviceAction::Other(action) => action,
+        }
+    }
+}
+
+impl fmt::Display for AddDeviceAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AddDeviceAction::BackgroundScan => "background scan",
+            AddDeviceAction::AllowConnect => "allow connect",
+            AddDeviceAction::AutoConnect => "auto-connect",
+            AddDeviceAction::Other(value) => return write!(f, "unknown action 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// The parameters of an LE connection, as described in Core 4.1 spec, Vol 2,
+/// 7.8.12. The fields hold the values that are sent to the controller: the
+/// connection intervals are in units of 1.25 ms, and the supervision timeout
+/// is in units of 10 ms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ConnectionParams {
     pub address: Address,
     pub address_type: AddressType,
@@ -133,6 +283,110 @@ pub struct ConnectionParams {
     pub supervision_timeout: u16,
 }
 
+impl ConnectionParams {
+    const INTERVAL_UNIT: Duration = Duration::from_micros(1250);
+    const TIMEOUT_UNIT: Duration = Duration::from_millis(10);
+
+    /// Creates connection parameters from durations, which are rounded down
+    /// to the units of the controller. Returns an error if the parameters are
+    /// outside of the ranges that are allowed by the specification.
+    pub fn new(
+        address: Address,
+        address_type: AddressType,
+        min_connection_interval: Duration,
+        max_connection_interval: Duration,
+        connection_latency: u16,
+        supervision_timeout: Duration,
+    ) -> Result<Self, Error> {
+        let to_units = |duration: Duration, unit: Duration| {
+            u16::try_from(duration.as_micros() / unit.as_micros()).map_err(|_| {
+                Error::InvalidConnectionParams {
+                    reason: "duration is out of range",
+                }
+            })
+        };
+
+        Self::from_raw(
+            address,
+            address_type,
+            to_units(min_connection_interval, Self::INTERVAL_UNIT)?,
+            to_units(max_connection_interval, Self::INTERVAL_UNIT)?,
+            connection_latency,
+            to_units(supervision_timeout, Self::TIMEOUT_UNIT)?,
+        )
+    }
+
+    /// Creates connection parameters from values in the units of the
+    /// controller. Returns an error if the parameters are outside of the
+    /// ranges that are allowed by the specification.
+    pub fn from_raw(
+        address: Address,
+        address_type: AddressType,
+        min_connection_interval: u16,
+        max_connection_interval: u16,
+        connection_latency: u16,
+        supervision_timeout: u16,
+    ) -> Result<Self, Error> {
+        let params = Self {
+            address,
+            address_type,
+            min_connection_interval,
+            max_connection_interval,
+            connection_latency,
+            supervision_timeout,
+        };
+
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Checks that these parameters are within the ranges that are allowed by
+    /// the specification.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason| Err(Error::InvalidConnectionParams { reason });
+
+        if !(0x0006..=0x0C80).contains(&self.min_connection_interval)
+            || !(0x0006..=0x0C80).contains(&self.max_connection_interval)
+        {
+            return invalid("connection interval must be between 7.5 ms and 4 s");
+        }
+
+        if self.min_connection_interval > self.max_connection_interval {
+            return invalid("minimum connection interval is greater than the maximum");
+        }
+
+        if self.connection_latency > 0x01F3 {
+            return invalid("connection latency must be at most 499");
+        }
+
+        if !(0x000A..=0x0C80).contains(&self.supervision_timeout) {
+            return invalid("supervision timeout must be between 100 ms and 32 s");
+        }
+
+        // the timeout has to be long enough for the peripheral to skip
+        // `connection_latency` connection events
+        if self.supervision_timeout()
+            <= self.max_interval() * (1 + self.connection_latency as u32) * 2
+        {
+            return invalid("supervision timeout is too short for the connection latency");
+        }
+
+        Ok(())
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        Self::INTERVAL_UNIT * self.min_connection_interval as u32
+    }
+
+    pub fn max_interval(&self) -> Duration {
+        Self::INTERVAL_UNIT * self.max_connection_interval as u32
+    }
+
+    pub fn supervision_timeout(&self) -> Duration {
+        Self::TIMEOUT_UNIT * self.supervision_timeout as u32
+    }
+}
+
 #[repr(u32)]
 #[bitflags]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -148,20 +402,60 @@ pub struct ControllerConfigInfo {
     pub missing_options: BitFlags<ControllerConfigOptions>,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
-#[repr(u8)]
+/// The type of a controller. Types which this library does not know about
+/// are kept in [`ControllerType::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum ControllerType {
-    Primary = 0x00,
-    Unconfigured = 0x01,
-    AlternateMacPhy = 0x02,
+    Primary,
+    Unconfigured,
+    AlternateMacPhy,
+    Other(u8),
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
-#[repr(u8)]
+impl From<u8> for ControllerType {
+    fn from(controller_type: u8) -> Self {
+        match controller_type {
+            0x00 => ControllerType::Primary,
+            0x01 => ControllerType::Unconfigured,
+            0x02 => ControllerType::AlternateMacPhy,
+            controller_type => ControllerType::Other(controller_type),
+        }
+    }
+}
+
+impl From<ControllerType> for u8 {
+    fn from(controller_type: ControllerType) -> Self {
+        match controller_type {
+            ControllerType::Primary => 0x00,
+            ControllerType::Unconfigured => 0x01,
+            ControllerType::AlternateMacPhy => 0x02,
+            ControllerType::Other(controller_type) => controller_type,
+        }
+    }
+}
+
+impl fmt::Display for ControllerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ControllerType::Primary => "primary",
+            ControllerType::Unconfigured => "unconfigured",
+            ControllerType::AlternateMacPhy => "AMP",
+            ControllerType::Other(value) => return write!(f, "unknown type 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// The bus that a controller is attached to. Buses which this library does
+/// not know about are kept in [`ControllerBus::Other`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum ControllerBus {
-    Virtual = 0x00,
-    USB = 0x01,
-    PCMCIA = 0x02,
+    Virtual,
+    USB,
+    PCMCIA,
     UART,
     RS232,
     PCI,
@@ -169,6 +463,71 @@ pub enum ControllerBus {
     SPI,
     I2C,
     SMD,
+    VirtIO,
+    IPC,
+    Other(u8),
+}
+
+impl From<u8> for ControllerBus {
+    fn from(bus: u8) -> Self {
+        match bus {
+            0x00 => ControllerBus::Virtual,
+            0x01 => ControllerBus::USB,
+            0x02 => ControllerBus::PCMCIA,
+            0x03 => ControllerBus::UART,
+            0x04 => ControllerBus::RS232,
+            0x05 => ControllerBus::PCI,
+            0x06 => ControllerBus::SDIO,
+            0x07 => ControllerBus::SPI,
+            0x08 => ControllerBus::I2C,
+            0x09 => ControllerBus::SMD,
+            0x0A => ControllerBus::VirtIO,
+            0x0B => ControllerBus::IPC,
+            bus => ControllerBus::Other(bus),
+        }
+    }
+}
+
+impl From<ControllerBus> for u8 {
+    fn from(bus: ControllerBus) -> Self {
+        match bus {
+            ControllerBus::Virtual => 0x00,
+            ControllerBus::USB => 0x01,
+            ControllerBus::PCMCIA => 0x02,
+            ControllerBus::UART => 0x03,
+            ControllerBus::RS232 => 0x04,
+            ControllerBus::PCI => 0x05,
+            ControllerBus::SDIO => 0x06,
+            ControllerBus::SPI => 0x07,
+            ControllerBus::I2C => 0x08,
+            ControllerBus::SMD => 0x09,
+            ControllerBus::VirtIO => 0x0A,
+            ControllerBus::IPC => 0x0B,
+            ControllerBus::Other(bus) => bus,
+        }
+    }
+}
+
+impl fmt::Display for ControllerBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ControllerBus::Virtual => "virtual",
+            ControllerBus::USB => "USB",
+            ControllerBus::PCMCIA => "PCMCIA",
+            ControllerBus::UART => "UART",
+            ControllerBus::RS232 => "RS232",
+            ControllerBus::PCI => "PCI",
+            ControllerBus::SDIO => "SDIO",
+            ControllerBus::SPI => "SPI",
+            ControllerBus::I2C => "I2C",
+            ControllerBus::SMD => "SMD",
+            ControllerBus::VirtIO => "virtio",
+            ControllerBus::IPC => "IPC",
+            ControllerBus::Other(value) => return write!(f, "unknown bus 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
 }
 
 pub struct PhyConfig {
@@ -231,7 +590,383 @@ pub enum SystemConfigParameterType {
     LEAutoconnectTimeout,
 }
 
+/// The default system configuration of a controller, decoded from the
+/// Type/Length/Value list that the kernel uses for it. Every parameter is a
+/// 16-bit value; intervals, windows and timeouts are in the units of the
+/// controller, such as 0.625 ms for scan intervals. Values of another length,
+/// which a newer kernel might send, are kept as they were received in
+/// [`other_values`](SystemConfig::other_values).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemConfig {
+    values: HashMap<SystemConfigParameterType, u16>,
+    other_values: HashMap<SystemConfigParameterType, Vec<u8>>,
+}
+
+impl SystemConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of `parameter`, or `None` if it was not included or
+    /// is not 16 bits long.
+    pub fn get(&self, parameter: SystemConfigParameterType) -> Option<u16> {
+        self.values.get(&parameter).copied()
+    }
+
+    pub fn set(&mut self, parameter: SystemConfigParameterType, value: u16) {
+        self.other_values.remove(&parameter);
+        self.values.insert(parameter, value);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SystemConfigParameterType, u16)> + '_ {
+        self.values
+            .iter()
+            .map(|(parameter, value)| (*parameter, *value))
+    }
+
+    /// Returns the parameters whose values are not 16 bits long, which
+    /// [`get`](SystemConfig::get) and [`iter`](SystemConfig::iter) leave out.
+    pub fn other_values(&self) -> impl Iterator<Item = (SystemConfigParameterType, &[u8])> + '_ {
+        self.other_values
+            .iter()
+            .map(|(parameter, value)| (*parameter, &value[..]))
+    }
+
+    /// Encodes the parameters for [`set_default_system_config`], including
+    /// the ones in [`other_values`](SystemConfig::other_values), which are
+    /// sent back unchanged.
+    pub fn to_params(&self) -> Vec<(SystemConfigParameterType, Vec<u8>)> {
+        self.iter()
+            .map(|(parameter, value)| (parameter, value.to_le_bytes().to_vec()))
+            .chain(
+                self.other_values
+                    .iter()
+                    .map(|(parameter, value)| (*parameter, value.clone())),
+            )
+            .collect()
+    }
+}
+
+/// Decodes a Type/Length/Value map. Values which are not 16 bits long are
+/// kept in [`other_values`](SystemConfig::other_values).
+impl From<HashMap<SystemConfigParameterType, Vec<u8>>> for SystemConfig {
+    fn from(params: HashMap<SystemConfigParameterType, Vec<u8>>) -> Self {
+        let mut config = Self::default();
+
+        for (parameter, value) in params {
+            match <[u8; 2]>::try_from(&value[..]) {
+                Ok(bytes) => {
+                    config.values.insert(parameter, u16::from_le_bytes(bytes));
+                }
+                Err(_) => {
+                    config.other_values.insert(parameter, value);
+                }
+            }
+        }
+
+        config
+    }
+}
+
+/// A trade-off between how quickly a BR/EDR controller can be found and
+/// connected to by remote devices, and how much power it uses while scanning.
+/// See [`set_fast_pairing_profile`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FastPairingProfile {
+    /// Interlaced page and inquiry scans every 160 ms.
+    Fast,
+    /// The kernel's defaults: a page scan every 1.28 s and an inquiry scan
+    /// every 2.56 s.
+    Balanced,
+    /// Page and inquiry scans every 2.56 s, which is the longest interval
+    /// that is allowed.
+    PowerSaving,
+}
+
+impl FastPairingProfile {
+    /// The system configuration parameters that this profile sets. Intervals
+    /// and windows are in units of 0.625 ms.
+    pub fn system_config(&self) -> Vec<(SystemConfigParameterType, Vec<u8>)> {
+        let (scan_type, page_scan_interval, inquiry_scan_interval): (u16, u16, u16) = match self {
+            FastPairingProfile::Fast => (1, 0x0100, 0x0100),
+            FastPairingProfile::Balanced => (0, 0x0800, 0x1000),
+            FastPairingProfile::PowerSaving => (0, 0x1000, 0x1000),
+        };
+
+        // the scan window is the same for every profile, so the interval
+        // alone determines the duty cycle
+        let window: u16 = 0x0012;
+
+        vec![
+            (
+                SystemConfigParameterType::BREDRPageScanType,
+                scan_type.to_le_bytes().to_vec(),
+            ),
+            (
+                SystemConfigParameterType::BREDRPageScanInterval,
+                page_scan_interval.to_le_bytes().to_vec(),
+            ),
+            (
+                SystemConfigParameterType::BREDRPageScanWindow,
+                window.to_le_bytes().to_vec(),
+            ),
+            (
+                SystemConfigParameterType::BREDRInquiryScanType,
+                scan_type.to_le_bytes().to_vec(),
+            ),
+            (
+                SystemConfigParameterType::BREDRInquiryScanInterval,
+                inquiry_scan_interval.to_le_bytes().to_vec(),
+            ),
+            (
+                SystemConfigParameterType::BREDRInquiryScanWindow,
+                window.to_le_bytes().to_vec(),
+            ),
+        ]
+    }
+}
+
+/// The LE scan parameters that are used for connection establishment and
+/// passive scanning, as described in Core 4.1 spec, Vol 2, 7.8.10. The fields
+/// hold the values that are sent to the controller, in units of 0.625 ms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanParams {
+    pub interval: u16,
+    pub window: u16,
+}
+
+impl ScanParams {
+    const UNIT: Duration = Duration::from_micros(625);
+
+    /// Creates scan parameters from durations, which are rounded down to the
+    /// units of the controller. Returns an error if the parameters are outside
+    /// of the ranges that are allowed by the specification.
+    pub fn new(interval: Duration, window: Duration) -> Result<Self, Error> {
+        let to_units = |duration: Duration| {
+            u16::try_from(duration.as_micros() / Self::UNIT.as_micros()).map_err(|_| {
+                Error::InvalidScanParams {
+                    reason: "duration is out of range",
+                }
+            })
+        };
+
+        Self::from_raw(to_units(interval)?, to_units(window)?)
+    }
+
+    /// Creates scan parameters from values in the units of the controller.
+    /// Returns an error if the parameters are outside of the ranges that are
+    /// allowed by the specification.
+    pub fn from_raw(interval: u16, window: u16) -> Result<Self, Error> {
+        let params = Self { interval, window };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Checks that these parameters are within the ranges that are allowed by
+    /// the specification.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason| Err(Error::InvalidScanParams { reason });
+
+        if !(0x0004..=0x4000).contains(&self.interval) {
+            return invalid("scan interval must be between 2.5 ms and 10.24 s");
+        }
+
+        if !(0x0004..=0x4000).contains(&self.window) {
+            return invalid("scan window must be between 2.5 ms and 10.24 s");
+        }
+
+        if self.window > self.interval {
+            return invalid("scan window is longer than the scan interval");
+        }
+
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Self::UNIT * self.interval as u32
+    }
+
+    pub fn window(&self) -> Duration {
+        Self::UNIT * self.window as u32
+    }
+
+    /// The fraction of the time that the controller spends scanning, between
+    /// 0 and 1.
+    pub fn duty_cycle(&self) -> f64 {
+        self.window as f64 / self.interval as f64
+    }
+}
+
+/// A trade-off between how quickly an LE controller finds and connects to
+/// advertising devices, and how much power it uses while scanning. See
+/// [`set_scan_profile`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScanProfile {
+    /// Scans all the time, with a window and an interval of 60 ms.
+    LowLatency,
+    /// The kernel's defaults: a 30 ms window every 60 ms.
+    Balanced,
+    /// An 11.25 ms window every 1.28 s, which the kernel also uses for
+    /// background scanning.
+    LowPower,
+}
+
+impl ScanProfile {
+    pub fn params(&self) -> ScanParams {
+        let (interval, window) = match self {
+            ScanProfile::LowLatency => (0x0060, 0x0060),
+            ScanProfile::Balanced => (0x0060, 0x0030),
+            ScanProfile::LowPower => (0x0800, 0x0012),
+        };
+
+        ScanParams { interval, window }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, FromPrimitive)]
 //#[repr(u16)] once there are known variants
 #[non_exhaustive]
 pub enum RuntimeConfigParameterType {}
+
+/// The default runtime configuration of a controller. No runtime parameters
+/// are defined yet, so the values are kept as they were received.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub params: HashMap<RuntimeConfigParameterType, Vec<u8>>,
+}
+
+impl From<HashMap<RuntimeConfigParameterType, Vec<u8>>> for RuntimeConfig {
+    fn from(params: HashMap<RuntimeConfigParameterType, Vec<u8>>) -> Self {
+        Self { params }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::FromIterator;
+
+    use super::*;
+
+    #[test]
+    fn add_device_action_from_primitive() {
+        use num_traits::FromPrimitive;
+
+        assert_eq!(
+            AddDeviceAction::from_u8(2),
+            Some(AddDeviceAction::AutoConnect)
+        );
+        assert_eq!(
+            AddDeviceAction::from_u32(7),
+            Some(AddDeviceAction::Other(7))
+        );
+        assert_eq!(AddDeviceAction::from_i32(-1), None);
+        assert_eq!(AddDeviceAction::from_u16(0x100), None);
+    }
+
+    #[test]
+    fn connection_params_units() {
+        let params = ConnectionParams::new(
+            Address::zero(),
+            AddressType::LEPublic,
+            Duration::from_micros(7500),
+            Duration::from_millis(50),
+            4,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        assert_eq!(params.min_connection_interval, 0x0006);
+        assert_eq!(params.max_connection_interval, 0x0028);
+        assert_eq!(params.supervision_timeout, 0x0064);
+        assert_eq!(params.max_interval(), Duration::from_millis(50));
+
+        // 1 s is not longer than (1 + 9) * 50 ms * 2
+        assert!(ConnectionParams::new(
+            Address::zero(),
+            AddressType::LEPublic,
+            Duration::from_micros(7500),
+            Duration::from_millis(50),
+            9,
+            Duration::from_secs(1),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn scan_params_units() {
+        let params =
+            ScanParams::new(Duration::from_millis(100), Duration::from_micros(12500)).unwrap();
+
+        assert_eq!(params.interval, 0x00A0);
+        assert_eq!(params.window, 0x0014);
+        assert_eq!(params.window(), Duration::from_micros(12500));
+        assert_eq!(params.duty_cycle(), 0.125);
+
+        assert!(ScanParams::new(Duration::from_millis(10), Duration::from_millis(20)).is_err());
+        assert!(ScanParams::new(Duration::from_secs(11), Duration::from_millis(20)).is_err());
+
+        for profile in [
+            ScanProfile::LowLatency,
+            ScanProfile::Balanced,
+            ScanProfile::LowPower,
+        ] {
+            assert!(profile.params().validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn address_types() {
+        let le = AddressTypes::from_iter([AddressType::LEPublic, AddressType::LERandom]);
+        assert_eq!(le, AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom);
+        assert!(le.contains(AddressType::LERandom));
+        assert!(!le.contains(AddressType::BREDR));
+
+        assert!(AddressTypes::from(AddressType::Other(0x07)).is_empty());
+        assert_eq!(
+            le.iter().map(AddressType::from).collect::<Vec<_>>(),
+            vec![AddressType::LEPublic, AddressType::LERandom]
+        );
+    }
+
+    #[test]
+    fn system_config_tlv() {
+        let mut params = HashMap::new();
+        params.insert(
+            SystemConfigParameterType::BREDRPageScanInterval,
+            vec![0x00, 0x08],
+        );
+        // not 16 bits long, so it is kept as it is
+        params.insert(SystemConfigParameterType::BREDRPageScanWindow, vec![0x12]);
+
+        let mut config = SystemConfig::from(params);
+        assert_eq!(
+            config.get(SystemConfigParameterType::BREDRPageScanInterval),
+            Some(0x0800)
+        );
+        assert_eq!(
+            config.get(SystemConfigParameterType::BREDRPageScanWindow),
+            None
+        );
+        assert_eq!(
+            config.other_values().collect::<Vec<_>>(),
+            vec![(SystemConfigParameterType::BREDRPageScanWindow, &[0x12][..])]
+        );
+
+        let mut params = config.to_params();
+        params.sort_by_key(|(parameter, _)| *parameter as u16);
+        assert_eq!(
+            params,
+            vec![
+                (
+                    SystemConfigParameterType::BREDRPageScanInterval,
+                    vec![0x00, 0x08]
+                ),
+                (SystemConfigParameterType::BREDRPageScanWindow, vec![0x12]),
+            ]
+        );
+
+        config.set(SystemConfigParameterType::BREDRPageScanWindow, 0x0012);
+        assert_eq!(config.other_values().count(), 0);
+        assert_eq!(config.to_params().len(), 2);
+    }
+}
@@ -0,0 +1,71 @@
+use super::*;
+
+/// Checks whether `socket` may run commands that change settings, which needs
+/// the `CAP_NET_ADMIN` capability. Returns [`Error::MissingCapability`] if it
+/// may not, so that applications can tell the user up front instead of
+/// failing on the first such command.
+///
+/// The kernel decides this once, when the socket is opened, so granting the
+/// capability afterwards does not help this socket. The check sends a Set
+/// Powered command without a controller, which the kernel rejects with
+/// Invalid Index once the permission check has passed, so nothing changes.
+pub async fn check_permissions(
+    socket: &mut ManagementStream,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<()> {
+    let result = exec_command(
+        socket,
+        Command::SetPowered,
+        Controller::none(),
+        Some(encode_set_powered(false)),
+        event_tx,
+    )
+    .await;
+
+    match result {
+        Ok(_)
+        | Err(Error::CommandError {
+            status: CommandStatus::InvalidIndex,
+            ..
+        }) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{MockKernel, MockScript};
+
+    #[tokio::test]
+    async fn missing_capability() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+
+        let script = MockScript::new()
+            .fail(Command::SetPowered, CommandStatus::InvalidIndex)
+            .fail(Command::SetPowered, CommandStatus::PermissionDenied)
+            .fail(Command::SetPowered, CommandStatus::PermissionDenied);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        check_permissions(&mut socket, None).await.unwrap();
+
+        assert!(matches!(
+            check_permissions(&mut socket, None).await,
+            Err(Error::MissingCapability {
+                required: "CAP_NET_ADMIN",
+                opcode: Command::SetPowered,
+                ..
+            })
+        ));
+
+        let err = set_powered(&mut socket, Controller(0), true, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.command_status(), Some(CommandStatus::PermissionDenied));
+        assert!(err.to_string().contains("CAP_NET_ADMIN"));
+
+        drop(socket);
+        let commands = kernel.await.unwrap().unwrap();
+        assert_eq!(commands[0].controller, Controller::none());
+    }
+}
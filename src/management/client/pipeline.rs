@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use super::*;
+
+/// A batch of commands which are sent to the kernel without waiting for the
+/// reply to the previous command.
+///
+/// Every command function, such as [`set_powered`], sends one command and
+/// waits for its reply before returning, so a sequence of commands takes one
+/// round trip per command. A pipeline keeps up to
+/// [`max_in_flight`](CommandPipeline::with_max_in_flight) commands
+/// outstanding at the same time, which speeds up sequences like configuring
+/// several settings on every controller at startup.
+///
+/// Replies are matched to commands by their opcode and controller index. The
+/// kernel replies to the commands for each controller in the order in which
+/// they were sent.
+#[derive(Debug)]
+pub struct CommandPipeline {
+    requests: Vec<Request>,
+    max_in_flight: usize,
+}
+
+impl CommandPipeline {
+    pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+    pub fn new() -> Self {
+        Self {
+            requests: Vec::new(),
+            max_in_flight: Self::DEFAULT_MAX_IN_FLIGHT,
+        }
+    }
+
+    /// Sets the maximum number of commands that are outstanding at the same
+    /// time. A value of 0 is treated as 1.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Adds a command to the pipeline, and returns its index in the results
+    /// of [`execute`](CommandPipeline::execute).
+    pub fn push(&mut self, opcode: Command, controller: Controller, param: Bytes) -> usize {
+        self.requests.push(Request {
+            opcode,
+            controller,
+            param,
+        });
+        self.requests.len() - 1
+    }
+
+    /// Adds a command which takes a single boolean parameter, such as
+    /// [`Command::SetPowered`] or [`Command::SetBondable`].
+    pub fn push_setting(
+        &mut self,
+        opcode: Command,
+        controller: Controller,
+        enabled: bool,
+    ) -> usize {
+        self.push(opcode, controller, Bytes::copy_from_slice(&[enabled as u8]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Sends all commands and waits for their replies. The result of each
+    /// command is returned at the same index as it was pushed at, and holds
+    /// the parameters of the reply, which are `None` if the kernel replied
+    /// with a Command Status event.
+    ///
+    /// A command that fails does not stop the other commands. The outer
+    /// error is only returned if the socket fails, in which case the state of
+    /// the outstanding commands is unknown.
+    ///
+    /// All events that are not replies to the commands in the pipeline are
+    /// forwarded to `event_tx`.
+    pub async fn execute(
+        self,
+        socket: &mut ManagementStream,
+        mut event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<Vec<Result<Option<Bytes>>>> {
+        let mut results: Vec<Option<Result<Option<Bytes>>>> =
+            self.requests.iter().map(|_| None).collect();
+        let mut pending = self.requests.into_iter().enumerate();
+        let mut in_flight: VecDeque<(usize, Command, Controller)> = VecDeque::new();
+
+        loop {
+            while in_flight.len() < self.max_in_flight {
+                match pending.next() {
+                    Some((index, request)) => {
+                        in_flight.push_back((index, request.opcode, request.controller));
+                        socket.send(request).await?;
+                    }
+                    None => break,
+                }
+            }
+
+            if in_flight.is_empty() {
+                break;
+            }
+
+            let response = socket.receive().await?;
+
+            let (opcode, status, param) = match response.event {
+                Event::CommandComplete {
+                    opcode,
+                    status,
+                    ref param,
+                } => (opcode, status, Some(param.clone())),
+                Event::CommandStatus { opcode, status } => (opcode, status, None),
+                _ => {
+                    if let Some(event_tx) = &mut event_tx {
+                        let _ = event_tx.send(response).await;
+                    }
+
+                    continue;
+                }
+            };
+
+            // the oldest outstanding command with the same opcode and
+            // controller is the one that this is a reply to
+            let position = in_flight
+                .iter()
+                .position(|&(_, in_flight_opcode, controller)| {
+                    in_flight_opcode == opcode && controller == response.controller
+                });
+
+            let (index, controller) = match position.and_then(|p| in_flight.remove(p)) {
+                Some((index, _, controller)) => (index, controller),
+                None => {
+                    if let Some(event_tx) = &mut event_tx {
+                        let _ = event_tx.send(response).await;
+                    }
+
+                    continue;
+                }
+            };
+
+            results[index] = Some(match status {
+                CommandStatus::Success => Ok(param),
+                _ => Err(Error::command_failed(opcode, controller, status)),
+            });
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every command in the pipeline has a reply"))
+            .collect())
+    }
+}
+
+impl Default for CommandPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,4 @@
 use crate::AddressType;
-use std::collections::HashMap;
 
 use crate::management::interface::ControllerInfoExt;
 use crate::util::BufExt;
@@ -93,15 +92,26 @@ pub async fn get_controller_info(
 
     let mut param = param.ok_or(Error::NoData)?;
 
+    let address = param.get_address();
+    let bluetooth_version = param.get_u8();
+    let manufacturer = param.get_u16_le();
+    let supported_settings = param.get_flags_u32_le();
+    let current_settings = param.get_flags_u32_le();
+    let class_of_device = class_of_device_from_buf(&mut param);
+    let name_bytes = param.split_to(249).get_c_bytes();
+    let short_name_bytes = param.get_c_bytes();
+
     Ok(ControllerInfo {
-        address: param.get_address(),
-        bluetooth_version: param.get_u8(),
-        manufacturer: param.get_u16_le(),
-        supported_settings: param.get_flags_u32_le(),
-        current_settings: param.get_flags_u32_le(),
-        class_of_device: device_class_from_bytes(param.split_to(3)),
-        name: param.split_to(249).get_c_string(),
-        short_name: param.get_c_string(),
+        address,
+        bluetooth_version,
+        manufacturer,
+        supported_settings,
+        current_settings,
+        class_of_device,
+        name: String::from_utf8_lossy(&name_bytes).into_owned(),
+        short_name: String::from_utf8_lossy(&short_name_bytes).into_owned(),
+        name_bytes,
+        short_name_bytes,
     })
 }
 
@@ -126,7 +136,10 @@ pub async fn get_connections(
     let mut connections = Vec::with_capacity(count);
 
     for _ in 0..count {
-        connections.push((param.get_address(), param.get_primitive_u8()));
+        connections.push((
+            param.get_address(),
+            AddressType::from_mgmt_u8(param.get_u8()),
+        ));
     }
 
     Ok(connections)
@@ -140,15 +153,11 @@ pub async fn get_connection_info(
     address_type: AddressType,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ConnectionInfo> {
-    let mut param = BytesMut::with_capacity(7);
-    param.put_slice(address.as_ref());
-    param.put_u8(address_type as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::GetConnectionInfo,
         controller,
-        Some(param.freeze()),
+        Some(encode_get_connection_info(address, address_type)),
         event_tx,
     )
     .await?;
@@ -156,7 +165,7 @@ pub async fn get_connection_info(
     let mut param = param.ok_or(Error::NoData)?;
     Ok(ConnectionInfo {
         address: param.get_address(),
-        address_type: param.get_primitive_u8(),
+        address_type: AddressType::from_mgmt_u8(param.get_u8()),
         rssi: if param[0] != 127 {
             Some(param.get_i8())
         } else {
@@ -175,7 +184,14 @@ pub async fn get_connection_info(
     })
 }
 
+pub(crate) fn encode_get_connection_info(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 /// This command is used to get local and piconet clock information.
+///
+/// To follow how the piconet clock drifts over time, use a
+/// [`ClockDriftMonitor`].
 pub async fn get_clock_info(
     socket: &mut ManagementStream,
     controller: Controller,
@@ -183,15 +199,11 @@ pub async fn get_clock_info(
     address_type: AddressType,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ClockInfo> {
-    let mut param = BytesMut::with_capacity(7);
-    param.put_slice(address.as_ref());
-    param.put_u8(address_type as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::GetClockInfo,
         controller,
-        Some(param.freeze()),
+        Some(encode_get_clock_info(address, address_type)),
         event_tx,
     )
     .await?;
@@ -199,7 +211,7 @@ pub async fn get_clock_info(
     let mut param = param.ok_or(Error::NoData)?;
 
     let address = param.get_address();
-    let address_type = param.get_primitive_u8();
+    let address_type = AddressType::from_mgmt_u8(param.get_u8());
     let local_clock = param.get_u32_le();
 
     let mut piconet_clock = None;
@@ -222,6 +234,10 @@ pub async fn get_clock_info(
     })
 }
 
+pub(crate) fn encode_get_clock_info(address: Address, address_type: AddressType) -> Bytes {
+    address_bytes(address, address_type)
+}
+
 ///	This command returns the list of currently unconfigured controllers.
 ///	Unconfigured controllers added after calling this command can be
 ///	monitored using the Unconfigured Index Added event.
@@ -344,8 +360,8 @@ pub async fn get_ext_controller_list(
     for _ in 0..count {
         index.push((
             Controller(param.get_u16_le()),
-            param.get_primitive_u8(),
-            param.get_primitive_u8(),
+            param.get_u8().into(),
+            param.get_u8().into(),
         ));
     }
     Ok(index)
@@ -430,7 +446,7 @@ pub async fn get_default_runtime_config(
     socket: &mut ManagementStream,
     controller: Controller,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<HashMap<RuntimeConfigParameterType, Vec<u8>>> {
+) -> Result<RuntimeConfig> {
     let (_, param) = exec_command(
         socket,
         Command::ReadDefaultRuntimeConfig,
@@ -441,16 +457,19 @@ pub async fn get_default_runtime_config(
     .await?;
 
     let mut param = param.ok_or(Error::NoData)?;
-    Ok(param.get_tlv_map())
+    Ok(param.get_tlv_map().into())
 }
 
 /// This command can be used at any time and will return a list of
 /// supported default parameters as well as their current value.
+///
+/// Values which are not 16 bits long are not decoded, but they are not lost
+/// either: see [`SystemConfig::other_values`].
 pub async fn get_default_system_config(
     socket: &mut ManagementStream,
     controller: Controller,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<HashMap<SystemConfigParameterType, Vec<u8>>> {
+) -> Result<SystemConfig> {
     let (_, param) = exec_command(
         socket,
         Command::ReadDefaultSystemConfig,
@@ -461,5 +480,5 @@ pub async fn get_default_system_config(
     .await?;
 
     let mut param = param.ok_or(Error::NoData)?;
-    Ok(param.get_tlv_map())
+    Ok(param.get_tlv_map().into())
 }
@@ -0,0 +1,40 @@
+use futures::future::BoxFuture;
+
+use super::*;
+
+/// Sends a command with the parameters `param` and waits for the kernel to
+/// answer it. Returns the controller that answered and the unparsed
+/// parameters of the Command Complete event, or `None` if the command was
+/// answered by a Command Status event.
+///
+/// This can be used for commands that this library does not have a function
+/// for, or whose replies it does not parse completely.
+pub async fn exec_command_raw(
+    socket: &mut ManagementStream,
+    opcode: Command,
+    controller: Controller,
+    param: Bytes,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<(Controller, Option<Bytes>)> {
+    exec_command(socket, opcode, controller, Some(param), event_tx).await
+}
+
+/// Runs a command, and returns its parsed result together with the unparsed
+/// parameters of the Command Complete event that answered it, so that the
+/// exact reply of the kernel can be logged.
+///
+/// `command` is called with `socket`, and usually boxes the future of a
+/// command function, such as
+/// `|socket| get_controller_info(socket, controller, None).boxed()`. If the
+/// command function sends several commands, the reply to the last one is
+/// returned.
+pub async fn with_raw_reply<T, F>(
+    socket: &mut ManagementStream,
+    command: F,
+) -> Result<(T, Option<Bytes>)>
+where
+    F: for<'a> FnOnce(&'a mut ManagementStream) -> BoxFuture<'a, Result<T>>,
+{
+    let value = command(socket).await?;
+    Ok((value, socket.last_reply().cloned()))
+}
@@ -0,0 +1,336 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::*;
+use crate::AddressType;
+
+/// How a device should be reconnected by a [`ReconnectPolicy`].
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    /// The delay before the first reconnection attempt.
+    pub initial_delay: Duration,
+
+    /// The delay is doubled after every failed attempt, up to this value.
+    pub max_delay: Duration,
+
+    /// The number of attempts after which the policy gives up on the device
+    /// until it is connected again by other means. `None` retries forever.
+    pub max_attempts: Option<u32>,
+
+    /// Whether the device should also be reconnected when the disconnection
+    /// was initiated by this host, for example using
+    /// [`disconnect`](crate::management::disconnect).
+    pub after_local_disconnect: bool,
+
+    /// The options that are passed to [`connect_device`] for every attempt.
+    pub options: ConnectOptions,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+            after_local_disconnect: false,
+            options: ConnectOptions {
+                pair: None,
+                timeout: Some(Duration::from_secs(30)),
+            },
+        }
+    }
+}
+
+/// Something that happened while a [`ReconnectPolicy`] was running.
+#[derive(Debug)]
+pub enum ReconnectEvent {
+    /// A device was reconnected.
+    Reconnected(ConnectedDevice),
+
+    /// A reconnection attempt failed. If `retry_in` is `None`, the maximum
+    /// number of attempts has been reached and the policy will not try to
+    /// reconnect the device again until it is connected by other means.
+    AttemptFailed {
+        controller: Controller,
+        address: Address,
+        address_type: AddressType,
+        attempt: u32,
+        error: Error,
+        retry_in: Option<Duration>,
+    },
+}
+
+#[derive(Debug)]
+struct WatchedDevice {
+    address_type: AddressType,
+    config: ReconnectConfig,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl WatchedDevice {
+    fn delay(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempts).unwrap_or(u32::MAX);
+
+        self.config
+            .initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.config.max_delay)
+            .min(self.config.max_delay)
+    }
+}
+
+/// Reconnects devices after they have been disconnected, waiting longer after
+/// every failed attempt.
+///
+/// The kernel can already reconnect LE devices on its own (see
+/// [`AddDeviceAction::AutoConnect`]), but it will not do so for BR/EDR
+/// devices, and it will not pair with the device again. This policy
+/// watches for Device Disconnected events and uses [`connect_device`] to
+/// bring the connection back.
+///
+/// The policy only makes progress while [`ReconnectPolicy::run`] is being
+/// awaited.
+#[derive(Debug, Default)]
+pub struct ReconnectPolicy {
+    devices: HashMap<(Controller, Address), WatchedDevice>,
+    /// Events that were received during a reconnection attempt, which have
+    /// not been processed yet.
+    pending: VecDeque<Response>,
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching a device. If the device was already being watched, its
+    /// configuration is replaced.
+    pub fn watch(
+        &mut self,
+        controller: Controller,
+        address: Address,
+        address_type: AddressType,
+        config: ReconnectConfig,
+    ) {
+        self.devices.insert(
+            (controller, address),
+            WatchedDevice {
+                address_type,
+                config,
+                attempts: 0,
+                next_attempt: None,
+            },
+        );
+    }
+
+    /// Stops watching a device. Returns `false` if the device was not being
+    /// watched.
+    pub fn unwatch(&mut self, controller: Controller, address: Address) -> bool {
+        self.devices.remove(&(controller, address)).is_some()
+    }
+
+    /// Schedules a reconnection attempt for a device that is known to be
+    /// disconnected, for example because it was not connected when it
+    /// started being watched.
+    pub fn schedule(&mut self, controller: Controller, address: Address) {
+        if let Some(device) = self.devices.get_mut(&(controller, address)) {
+            device.next_attempt = Some(Instant::now() + device.delay());
+        }
+    }
+
+    /// Updates the state of the policy based on an event. [`ReconnectPolicy::run`]
+    /// calls this for every event it receives; call it yourself for events
+    /// that were received elsewhere.
+    pub fn handle_event(&mut self, response: &Response) {
+        match &response.event {
+            Event::DeviceDisconnected {
+                address, reason, ..
+            } => {
+                if let Some(device) = self.devices.get_mut(&(response.controller, *address)) {
+                    if *reason != DisconnectionReason::TerminatedLocal
+                        || device.config.after_local_disconnect
+                    {
+                        device.attempts = 0;
+                        device.next_attempt = Some(Instant::now() + device.delay());
+                    }
+                }
+            }
+            Event::DeviceConnected { address, .. } => {
+                if let Some(device) = self.devices.get_mut(&(response.controller, *address)) {
+                    device.attempts = 0;
+                    device.next_attempt = None;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Processes events and makes reconnection attempts when they are due,
+    /// until something happens that should be reported to the caller.
+    ///
+    /// All events received while this function is running are forwarded to
+    /// `event_tx`, including the ones received during a reconnection attempt.
+    /// Those are passed to [`ReconnectPolicy::handle_event`] once the attempt
+    /// has finished, so if the attempt is reported, they are processed by the
+    /// next call to this function.
+    pub async fn run(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<ReconnectEvent> {
+        loop {
+            let response = match (self.pending.pop_front(), self.next_attempt()) {
+                (Some(response), _) => response,
+                (None, Some((at, key))) => {
+                    // receive is cancel safe, see ManagementStream::receive
+                    match tokio::time::timeout_at(at, socket.receive()).await {
+                        Ok(response) => response?,
+                        Err(_) => {
+                            if let Some(event) = self.attempt(socket, key).await {
+                                return Ok(event);
+                            }
+
+                            continue;
+                        }
+                    }
+                }
+                (None, None) => socket.receive().await?,
+            };
+
+            self.handle_event(&response);
+
+            if let Some(event_tx) = &event_tx {
+                let _ = event_tx.send(response).await;
+            }
+        }
+    }
+
+    /// The device that is due to be reconnected first, and when.
+    pub(crate) fn next_attempt(&self) -> Option<(Instant, (Controller, Address))> {
+        self.devices
+            .iter()
+            .filter_map(|(key, device)| device.next_attempt.map(|at| (at, *key)))
+            .min_by_key(|(at, _)| *at)
+    }
+
+    /// Events that were received during reconnection attempts and have not
+    /// been processed yet, for helpers which make the attempts without
+    /// [`ReconnectPolicy::run`].
+    pub(crate) fn take_pending(&mut self) -> VecDeque<Response> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Makes a reconnection attempt. The events received meanwhile are queued
+    /// in `pending` rather than forwarded, so that the state of the policy is
+    /// updated for them before anyone else sees them.
+    pub(crate) async fn attempt(
+        &mut self,
+        socket: &mut ManagementStream,
+        (controller, address): (Controller, Address),
+    ) -> Option<ReconnectEvent> {
+        let device = self.devices.get_mut(&(controller, address))?;
+        device.next_attempt = None;
+        device.attempts += 1;
+
+        let (address_type, options) = (device.address_type, device.config.options);
+
+        let (result, responses) = collect_events(|event_tx| {
+            connect_device(
+                socket,
+                controller,
+                address,
+                address_type,
+                options,
+                None,
+                Some(event_tx),
+            )
+        })
+        .await;
+        self.pending.extend(responses);
+
+        // the device may have been unwatched by the time the attempt finishes
+        let device = self.devices.get_mut(&(controller, address))?;
+
+        match result {
+            Ok(connected) => {
+                device.attempts = 0;
+                Some(ReconnectEvent::Reconnected(connected))
+            }
+            Err(error) => {
+                let attempt = device.attempts;
+
+                let retry_in = match device.config.max_attempts {
+                    Some(max) if attempt >= max => None,
+                    _ => Some(device.delay()),
+                };
+
+                device.next_attempt = retry_in.map(|delay| Instant::now() + delay);
+
+                Some(ReconnectEvent::AttemptFailed {
+                    controller,
+                    address,
+                    address_type,
+                    attempt,
+                    error,
+                    retry_in,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{
+        address_param, device_connected, MockScript, MockTransport, MOCK_ADDRESS, MOCK_CONTROLLER,
+    };
+
+    #[tokio::test]
+    async fn events_during_attempt() {
+        let controller = MOCK_CONTROLLER;
+        let device = MOCK_ADDRESS;
+        let other = Address::from([0x11, 0x22, 0x33, 0x44, 0x55, 0x77]);
+
+        // disconnected because the connection was terminated locally
+        let mut disconnected = address_param(other, AddressType::LEPublic);
+        disconnected.push(0x03);
+
+        // the other device disconnects while the first one is being
+        // reconnected, so the event is received by connect_device
+        let script = MockScript::new()
+            .reply(Command::GetConnections, [0x00, 0x00])
+            .reply(
+                Command::AddDevice,
+                address_param(device, AddressType::LEPublic),
+            )
+            .then_event(0x000C, disconnected)
+            .then_event(0x000B, device_connected(device, AddressType::LEPublic));
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let config = ReconnectConfig {
+            initial_delay: Duration::from_millis(1),
+            ..ReconnectConfig::default()
+        };
+
+        let mut policy = ReconnectPolicy::new();
+        policy.watch(controller, device, AddressType::LEPublic, config);
+        policy.watch(controller, other, AddressType::LEPublic, config);
+        policy.schedule(controller, device);
+
+        let (event_tx, mut event_rx) = mpsc::channel(4);
+        let event = policy.run(&mut socket, Some(event_tx)).await.unwrap();
+        assert!(matches!(event, ReconnectEvent::Reconnected(_)));
+
+        // the event is processed before it is forwarded
+        assert!(event_rx.try_recv().is_err());
+        for response in policy.take_pending() {
+            policy.handle_event(&response);
+        }
+        assert_eq!(policy.next_attempt().unwrap().1, (controller, other));
+        assert_eq!(transport.commands().len(), 2);
+    }
+}
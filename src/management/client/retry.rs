@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use super::*;
+
+/// How [`retry_with_backoff`] retries a command which failed with a transient
+/// error.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// The delay before the first retry.
+    pub initial_delay: Duration,
+
+    /// The delay is doubled after every failed attempt, up to this value.
+    pub max_delay: Duration,
+
+    /// The number of attempts, including the first one, after which the last
+    /// error is returned.
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Runs a command, and runs it again after a delay for as long as it fails
+/// with an error that is [transient](Error::is_transient). Other errors are
+/// returned immediately.
+///
+/// `command` is called with `socket` for every attempt, and usually boxes
+/// the future of a command function, such as
+/// `|socket| set_powered(socket, controller, true, None).boxed()`.
+pub async fn retry_with_backoff<T, F>(
+    socket: &mut ManagementStream,
+    policy: RetryPolicy,
+    mut command: F,
+) -> Result<T>
+where
+    F: for<'a> FnMut(&'a mut ManagementStream) -> BoxFuture<'a, Result<T>>,
+{
+    let mut delay = policy.initial_delay;
+    let mut attempt = 1;
+
+    loop {
+        match command(socket).await {
+            Err(err) if err.is_transient() && attempt < policy.max_attempts => {
+                tokio::time::sleep(delay).await;
+
+                delay = delay
+                    .checked_mul(2)
+                    .unwrap_or(policy.max_delay)
+                    .min(policy.max_delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
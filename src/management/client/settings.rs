@@ -1,6 +1,10 @@
+use std::time::Duration;
+
 use bytes::{Buf, BufMut, BytesMut};
 use enumflags2::BitFlags;
 
+#[cfg(feature = "communication")]
+use crate::communication::{SecurityLevel, SecurityPolicy};
 use crate::management::interface::Command;
 use crate::management::interface::{Controller, ControllerSettings};
 use crate::management::Result;
@@ -13,8 +17,11 @@ use crate::util::BufExt;
 ///	command parameters also include a short name which will be used
 ///	in case the full name doesn't fit within EIR/AD data.
 ///
-/// Name can be at most 248 bytes. Short name can be at most 10 bytes.
-/// This function returns a pair of OsStrings in the order (name, short_name).
+/// Name can be at most 248 bytes. Short name can be at most 10 bytes. Both
+/// limits are in bytes of UTF-8, not in characters.
+/// This function returns a pair of strings in the order (name, short_name).
+/// The kernel answers with the names that were sent, so unlike the names in
+/// [`ControllerInfo`], these are always valid UTF-8.
 ///
 ///	This command can be used when the controller is not powered and
 ///	all settings will be programmed once powered.
@@ -29,7 +36,7 @@ pub async fn set_local_name(
     name: &str,
     short_name: Option<&str>,
     event_tx: Option<mpsc::Sender<Response>>,
-) -> Result<(CString, CString)> {
+) -> Result<(String, String)> {
     if name.len() > 248 {
         return Err(Error::NameTooLong {
             name: name.to_owned(),
@@ -45,8 +52,25 @@ pub async fn set_local_name(
             });
         }
     }
-    let short_name = short_name.unwrap_or("");
 
+    let (_, param) = exec_command(
+        socket,
+        Command::SetLocalName,
+        controller,
+        Some(encode_set_local_name(name, short_name.unwrap_or(""))?),
+        event_tx,
+    )
+    .await?;
+
+    let mut param = param.ok_or(Error::NoData)?;
+    Ok((
+        param.split_to(249).get_c_string_lossy(),
+        param.get_c_string_lossy(),
+    ))
+}
+
+/// `name` and `short_name` must not be longer than 248 and 10 bytes.
+pub(crate) fn encode_set_local_name(name: &str, short_name: &str) -> Result<Bytes> {
     let mut param = BytesMut::with_capacity(260);
     param.resize(260, 0); // initialize w/ zeros
 
@@ -57,17 +81,38 @@ pub async fn set_local_name(
         .as_bytes_with_nul()
         .copy_to_slice(&mut param[249..][..=short_name.len()]);
 
-    let (_, param) = exec_command(
+    Ok(param.freeze())
+}
+
+/// Sets the local name of a controller like [`set_local_name`], deriving the
+/// short name from `name`. The short name is `name` truncated to at most 10
+/// bytes, without splitting a UTF-8 character.
+pub async fn set_local_name_auto(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    name: &str,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<(String, String)> {
+    set_local_name(
         socket,
-        Command::SetLocalName,
         controller,
-        Some(param.freeze()),
+        name,
+        Some(derive_short_name(name)),
         event_tx,
     )
-    .await?;
+    .await
+}
 
-    let mut param = param.ok_or(Error::NoData)?;
-    Ok((param.split_to(249).get_c_string(), param.get_c_string()))
+/// Truncates `name` to the maximum length of a short name, on a character
+/// boundary.
+fn derive_short_name(name: &str) -> &str {
+    let mut len = name.len().min(10);
+
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &name[..len]
 }
 
 /// This command is used to power on or off a controller.
@@ -90,14 +135,11 @@ pub async fn set_powered(
     powered: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(powered as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetPowered,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_powered(powered)),
         event_tx,
     )
     .await?;
@@ -105,6 +147,212 @@ pub async fn set_powered(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_powered(powered: bool) -> Bytes {
+    u8_bytes(powered as u8)
+}
+
+/// Powers a controller off and on again, which recovers many controllers
+/// that stopped responding, and returns its settings once it is powered on.
+///
+/// The kernel replies to Set Powered once the change has taken effect, and
+/// only sends New Settings events to other sockets, so each step has settled
+/// when its reply reports the new state. If a reply still reports the old
+/// state, because another socket changed the power state at the same time,
+/// this waits for a New Settings event that reports the new state.
+///
+/// Fails with [`Error::TimedOut`] if the controller is not powered on again
+/// within `timeout`.
+pub async fn power_cycle(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    timeout: Duration,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ControllerSettings> {
+    let cycle = async {
+        let settings = set_powered(socket, controller, false, event_tx.clone()).await?;
+        settle_power(socket, controller, false, settings, event_tx.clone()).await?;
+
+        let settings = set_powered(socket, controller, true, event_tx.clone()).await?;
+        settle_power(socket, controller, true, settings, event_tx).await
+    };
+
+    tokio::time::timeout(timeout, cycle)
+        .await
+        .map_err(|_| Error::TimedOut)?
+}
+
+/// Waits until the Powered setting of `controller` is `powered`, starting
+/// from the `settings` that the last reply reported.
+async fn settle_power(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    powered: bool,
+    settings: ControllerSettings,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ControllerSettings> {
+    if settings.contains(ControllerSetting::Powered) == powered {
+        return Ok(settings);
+    }
+
+    wait_for_event(
+        socket,
+        |response| match response.event {
+            Event::NewSettings { settings }
+                if response.controller == controller
+                    && settings.contains(ControllerSetting::Powered) == powered =>
+            {
+                Some(settings)
+            }
+            _ => None,
+        },
+        event_tx,
+    )
+    .await
+}
+
+/// The settings that [`apply_settings`] brings a controller to. Settings which
+/// are `None` are left as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DesiredSettings {
+    pub powered: Option<bool>,
+    pub connectable: Option<bool>,
+    pub fast_connectable: Option<bool>,
+    /// The discoverable mode, and the timeout in seconds which
+    /// [`DiscoverableMode::Limited`] requires.
+    pub discoverable: Option<(DiscoverableMode, Option<u16>)>,
+    pub bondable: Option<bool>,
+    pub link_security: Option<bool>,
+    pub ssp: Option<bool>,
+    pub bredr: Option<bool>,
+    pub le: Option<bool>,
+}
+
+impl DesiredSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Brings the settings of a controller to `desired`, and returns the
+/// settings that it ends up with.
+///
+/// Only the settings which differ from the current settings are changed, in
+/// an order which avoids the Rejected errors that some combinations cause:
+/// LE or BR/EDR is enabled before the other one is disabled, connectable is
+/// switched on before and off after discoverable, and the controller is
+/// powered on last so that the other settings are programmed at once.
+/// Disabling BR/EDR needs the controller to be powered off, so it is powered
+/// off for that and then powered on again unless `desired.powered` is
+/// `Some(false)`.
+///
+/// The settings do not tell general and limited discoverable mode apart, so
+/// Set Discoverable is sent to a controller which is already discoverable
+/// only if a timeout is given.
+pub async fn apply_settings(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    desired: DesiredSettings,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ControllerSettings> {
+    use ControllerSetting::*;
+
+    let mut settings = get_controller_info(socket, controller, event_tx.clone())
+        .await?
+        .current_settings;
+
+    let power_on = desired.powered.unwrap_or(settings.contains(Powered));
+
+    if settings.contains(Powered)
+        && (!power_on || change(settings, BREDR, desired.bredr) == Some(false))
+    {
+        settings = set_powered(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    if change(settings, LE, desired.le) == Some(true) {
+        settings = set_le(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    if change(settings, BREDR, desired.bredr) == Some(true) {
+        settings = set_bredr(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    if let Some((DiscoverableMode::None, _)) = desired.discoverable {
+        if settings.contains(Discoverable) {
+            settings = set_discoverable(
+                socket,
+                controller,
+                DiscoverableMode::None,
+                None,
+                event_tx.clone(),
+            )
+            .await?;
+        }
+    }
+
+    if change(settings, FastConnectable, desired.fast_connectable) == Some(false) {
+        settings = set_fast_connectable(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    if change(settings, Connectable, desired.connectable) == Some(false) {
+        settings = set_connectable(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    if change(settings, BREDR, desired.bredr) == Some(false) {
+        settings = set_bredr(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    if change(settings, LE, desired.le) == Some(false) {
+        settings = set_le(socket, controller, false, event_tx.clone()).await?;
+    }
+
+    if let Some(ssp) = change(settings, SecureSimplePairing, desired.ssp) {
+        settings = set_ssp(socket, controller, ssp, event_tx.clone()).await?;
+    }
+
+    if let Some(link_security) = change(settings, LinkLevelSecurity, desired.link_security) {
+        settings = set_link_security(socket, controller, link_security, event_tx.clone()).await?;
+    }
+
+    if let Some(bondable) = change(settings, Pairable, desired.bondable) {
+        settings = set_bondable(socket, controller, bondable, event_tx.clone()).await?;
+    }
+
+    if change(settings, Connectable, desired.connectable) == Some(true) {
+        settings = set_connectable(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    if change(settings, FastConnectable, desired.fast_connectable) == Some(true) {
+        settings = set_fast_connectable(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    if power_on && !settings.contains(Powered) {
+        settings = set_powered(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    // a discoverable timeout is only accepted while powered, so this is last
+    match desired.discoverable {
+        Some((mode, timeout))
+            if mode != DiscoverableMode::None
+                && (timeout.is_some() || !settings.contains(Discoverable)) =>
+        {
+            settings = set_discoverable(socket, controller, mode, timeout, event_tx).await?;
+        }
+        _ => {}
+    }
+
+    Ok(settings)
+}
+
+/// Returns the value that `setting` has to be changed to, or `None` if it
+/// already has the desired value.
+fn change(
+    settings: ControllerSettings,
+    setting: ControllerSetting,
+    desired: Option<bool>,
+) -> Option<bool> {
+    desired.filter(|&enabled| settings.contains(setting) != enabled)
+}
+
 /// This command is used to set the discoverable property of a
 ///	controller.
 ///
@@ -130,17 +378,11 @@ pub async fn set_discoverable(
     timeout: Option<u16>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(3);
-    param.put_u8(discoverability as u8);
-    if let Some(timeout) = timeout {
-        param.put_u16_le(timeout);
-    }
-
     let (_, param) = exec_command(
         socket,
         Command::SetDiscoverable,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_discoverable(discoverability, timeout)),
         event_tx,
     )
     .await?;
@@ -148,6 +390,118 @@ pub async fn set_discoverable(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_discoverable(
+    discoverability: DiscoverableMode,
+    timeout: Option<u16>,
+) -> Bytes {
+    // the kernel only accepts the command with both fields, and a timeout of
+    // 0 means that there is none
+    let mut param = BytesMut::with_capacity(3);
+    param.put_u8(discoverability as u8);
+    param.put_u16_le(timeout.unwrap_or(0));
+    param.freeze()
+}
+
+/// How long after the end of a limited discoverable period
+/// [`make_limited_discoverable`] waits for the kernel to report it before it
+/// restores the settings anyway.
+const LIMITED_DISCOVERABLE_GRACE: Duration = Duration::from_secs(2);
+
+/// Makes the controller limited discoverable for `duration`, and restores
+/// the discoverable and connectable settings afterwards.
+///
+/// Limited discoverable mode needs a timeout and the connectable setting,
+/// so the controller is made connectable first if it is not. The kernel sets
+/// the Limited Discoverable bit of the class of device and the advertising
+/// flags while the mode is active, and clears them when the timeout passes.
+/// Devices should only be limited discoverable for a short time, at most a
+/// minute according to the specification. The controller has to be powered.
+///
+/// This returns once the period is over and the settings have been restored.
+/// A controller that was general discoverable before is made general
+/// discoverable again, without a timeout. If the future is dropped before
+/// that, the settings are not restored. Events received in the meantime are
+/// forwarded to `event_tx`.
+pub async fn make_limited_discoverable(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    duration: Duration,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ControllerSettings> {
+    let timeout = match duration.as_secs() {
+        secs @ 1..=0xFFFF => secs as u16,
+        _ => return Err(Error::InvalidDiscoverableTimeout { duration }),
+    };
+
+    let info = get_controller_info(socket, controller, event_tx.clone()).await?;
+    let was_connectable = info
+        .current_settings
+        .contains(ControllerSetting::Connectable);
+    let was_discoverable = info
+        .current_settings
+        .contains(ControllerSetting::Discoverable);
+
+    if !was_connectable {
+        set_connectable(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    set_discoverable(
+        socket,
+        controller,
+        DiscoverableMode::Limited,
+        Some(timeout),
+        event_tx.clone(),
+    )
+    .await?;
+
+    // the kernel reports the end of the period with a New Settings event
+    let ended = wait_for_event(
+        socket,
+        |response| match response.event {
+            Event::NewSettings { settings }
+                if response.controller == controller
+                    && !settings.contains(ControllerSetting::Discoverable) =>
+            {
+                Some(settings)
+            }
+            _ => None,
+        },
+        event_tx.clone(),
+    );
+
+    let mut settings = match tokio::time::timeout(
+        Duration::from_secs(timeout as u64) + LIMITED_DISCOVERABLE_GRACE,
+        ended,
+    )
+    .await
+    {
+        Ok(settings) => settings?,
+        // the event was missed, so the settings from before are stale
+        Err(_) => {
+            get_controller_info(socket, controller, event_tx.clone())
+                .await?
+                .current_settings
+        }
+    };
+
+    if was_discoverable {
+        settings = set_discoverable(
+            socket,
+            controller,
+            DiscoverableMode::General,
+            None,
+            event_tx.clone(),
+        )
+        .await?;
+    }
+
+    if !was_connectable {
+        settings = set_connectable(socket, controller, false, event_tx).await?;
+    }
+
+    Ok(settings)
+}
+
 /// This command is used to set the connectable property of a
 ///	controller.
 ///
@@ -179,14 +533,11 @@ pub async fn set_connectable(
     connectable: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(connectable as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetConnectable,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_connectable(connectable)),
         event_tx,
     )
     .await?;
@@ -194,6 +545,10 @@ pub async fn set_connectable(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_connectable(connectable: bool) -> Bytes {
+    u8_bytes(connectable as u8)
+}
+
 /// This command is used to set the controller into a connectable
 ///	state where the page scan parameters have been set in a way to
 ///	favor faster connect times with the expense of higher power
@@ -213,14 +568,11 @@ pub async fn set_fast_connectable(
     fast_connectable: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(fast_connectable as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetFastConnectable,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_fast_connectable(fast_connectable)),
         event_tx,
     )
     .await?;
@@ -228,6 +580,10 @@ pub async fn set_fast_connectable(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_fast_connectable(fast_connectable: bool) -> Bytes {
+    u8_bytes(fast_connectable as u8)
+}
+
 /// This command is used to set the bondable (pairable) property of an
 ///	controller.
 ///
@@ -244,14 +600,11 @@ pub async fn set_bondable(
     bondable: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(bondable as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetPairable,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_bondable(bondable)),
         event_tx,
     )
     .await?;
@@ -259,6 +612,10 @@ pub async fn set_bondable(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_bondable(bondable: bool) -> Bytes {
+    u8_bytes(bondable as u8)
+}
+
 ///	This command is used to either enable or disable link level
 ///	security for an controller (also known as Security Mode 3).
 ///
@@ -274,14 +631,11 @@ pub async fn set_link_security(
     link_security: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(link_security as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetLinkSecurity,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_link_security(link_security)),
         event_tx,
     )
     .await?;
@@ -289,6 +643,10 @@ pub async fn set_link_security(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_link_security(link_security: bool) -> Bytes {
+    u8_bytes(link_security as u8)
+}
+
 ///	This command is used to enable/disable Secure Simple Pairing
 ///	support for a controller.
 ///
@@ -307,14 +665,11 @@ pub async fn set_ssp(
     ssp: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(ssp as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetSecureSimplePairing,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_ssp(ssp)),
         event_tx,
     )
     .await?;
@@ -322,6 +677,10 @@ pub async fn set_ssp(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_ssp(ssp: bool) -> Bytes {
+    u8_bytes(ssp as u8)
+}
+
 ///	This command is used to enable/disable Bluetooth High Speed
 ///	support for a controller.
 ///
@@ -345,14 +704,11 @@ pub async fn set_high_speed(
     high_speed: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(high_speed as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetHighSpeed,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_high_speed(high_speed)),
         event_tx,
     )
     .await?;
@@ -360,6 +716,10 @@ pub async fn set_high_speed(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_high_speed(high_speed: bool) -> Bytes {
+    u8_bytes(high_speed as u8)
+}
+
 /// This command is used to enable/disable Low Energy support for a
 ///	controller.
 ///
@@ -381,14 +741,11 @@ pub async fn set_le(
     le: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(le as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetLowEnergy,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_le(le)),
         event_tx,
     )
     .await?;
@@ -396,6 +753,10 @@ pub async fn set_le(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_le(le: bool) -> Bytes {
+    u8_bytes(le as u8)
+}
+
 /// This command is used to enable LE advertising on a controller
 ///	that supports it.
 ///
@@ -436,14 +797,11 @@ pub async fn set_advertising(
     mode: LeAdvertisingMode,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(mode as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetAdvertising,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_advertising(mode)),
         event_tx,
     )
     .await?;
@@ -451,6 +809,10 @@ pub async fn set_advertising(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_advertising(mode: LeAdvertisingMode) -> Bytes {
+    u8_bytes(mode as u8)
+}
+
 /// This command is used to enable or disable BR/EDR support
 ///	on a dual-mode controller.
 ///
@@ -466,14 +828,11 @@ pub async fn set_bredr(
     enabled: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(enabled as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetBREDR,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_bredr(enabled)),
         event_tx,
     )
     .await?;
@@ -481,6 +840,10 @@ pub async fn set_bredr(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_bredr(enabled: bool) -> Bytes {
+    u8_bytes(enabled as u8)
+}
+
 ///	This command is used to set the IO Capability used for pairing.
 ///	The command accepts both SSP and SMP values.
 ///
@@ -495,14 +858,11 @@ pub async fn set_io_capability(
     io_capability: IoCapability,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(io_capability as u8);
-
     let (_, _param) = exec_command(
         socket,
         Command::SetIOCapability,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_io_capability(io_capability)),
         event_tx,
     )
     .await?;
@@ -510,6 +870,10 @@ pub async fn set_io_capability(
     Ok(())
 }
 
+pub(crate) fn encode_set_io_capability(io_capability: IoCapability) -> Bytes {
+    u8_bytes(io_capability as u8)
+}
+
 /// This command can be used when the controller is not powered and
 ///	all settings will be programmed once powered.
 ///
@@ -533,17 +897,11 @@ pub async fn set_device_id(
     version: u16,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(8);
-    param.put_u16_le(source);
-    param.put_u16_le(vendor);
-    param.put_u16_le(product);
-    param.put_u16_le(version);
-
     let (_, _param) = exec_command(
         socket,
         Command::SetDeviceID,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_device_id(source, vendor, product, version)),
         event_tx,
     )
     .await?;
@@ -551,9 +909,21 @@ pub async fn set_device_id(
     Ok(())
 }
 
+pub(crate) fn encode_set_device_id(source: u16, vendor: u16, product: u16, version: u16) -> Bytes {
+    let mut param = BytesMut::with_capacity(8);
+    param.put_u16_le(source);
+    param.put_u16_le(vendor);
+    param.put_u16_le(product);
+    param.put_u16_le(version);
+    param.freeze()
+}
+
 /// This command allows for setting the Low Energy scan parameters
 ///	used for connection establishment and passive scanning. It is
 ///	only supported on controllers with LE support.
+///
+/// `interval` and `window` are in units of 0.625 ms. See [`set_scan_params`]
+/// and [`set_scan_profile`] for versions which take durations or profiles.
 pub async fn set_scan_parameters(
     socket: &mut ManagementStream,
     controller: Controller,
@@ -561,15 +931,11 @@ pub async fn set_scan_parameters(
     window: u16,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(4);
-    param.put_u16_le(interval);
-    param.put_u16_le(window);
-
     let (_, _param) = exec_command(
         socket,
         Command::SetScanParameters,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_scan_parameters(interval, window)),
         event_tx,
     )
     .await?;
@@ -577,6 +943,35 @@ pub async fn set_scan_parameters(
     Ok(())
 }
 
+pub(crate) fn encode_set_scan_parameters(interval: u16, window: u16) -> Bytes {
+    let mut param = BytesMut::with_capacity(4);
+    param.put_u16_le(interval);
+    param.put_u16_le(window);
+    param.freeze()
+}
+
+/// Sets the LE scan parameters like [`set_scan_parameters`], after checking
+/// that they are within the ranges that are allowed by the specification.
+pub async fn set_scan_params(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    params: ScanParams,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<()> {
+    params.validate()?;
+    set_scan_parameters(socket, controller, params.interval, params.window, event_tx).await
+}
+
+/// Sets the LE scan parameters to those of `profile`.
+pub async fn set_scan_profile(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    profile: ScanProfile,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<()> {
+    set_scan_params(socket, controller, profile.params(), event_tx).await
+}
+
 ///	This command allows for setting the static random address. It is
 ///	only supported on controllers with LE support. The static random
 ///	address is suppose to be valid for the lifetime of the
@@ -606,13 +1001,11 @@ pub async fn set_static_address(
     address: Address,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let param = BytesMut::from(address.as_ref());
-
     let (_, param) = exec_command(
         socket,
         Command::SetStaticAddress,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_static_address(address)),
         event_tx,
     )
     .await?;
@@ -620,6 +1013,10 @@ pub async fn set_static_address(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_static_address(address: Address) -> Bytes {
+    Bytes::copy_from_slice(address.as_ref())
+}
+
 ///	This command is used to enable/disable Secure Connections
 ///	support for a controller.
 ///
@@ -642,14 +1039,11 @@ pub async fn set_secure_connections_mode(
     mode: SecureConnectionsMode,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(mode as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetSecureConnections,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_secure_connections_mode(mode)),
         event_tx,
     )
     .await?;
@@ -657,6 +1051,53 @@ pub async fn set_secure_connections_mode(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_secure_connections_mode(mode: SecureConnectionsMode) -> Bytes {
+    u8_bytes(mode as u8)
+}
+
+/// Configures the controller settings that `policy` depends on, so that the
+/// BR/EDR links of the connections that a listener with the policy accepts
+/// can reach its security level:
+///
+/// - Secure Simple Pairing is enabled if the policy requires encryption and
+///   the controller supports it.
+/// - Secure Connections Only mode is enabled if the policy requires Secure
+///   Connections.
+///
+/// Settings that already have the right value are not changed. Returns the
+/// settings of the controller afterwards.
+#[cfg(feature = "communication")]
+pub async fn apply_security_policy(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    policy: SecurityPolicy,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<ControllerSettings> {
+    let info = get_controller_info(socket, controller, event_tx.clone()).await?;
+    let mut settings = info.current_settings;
+
+    let encrypted = policy.required_level().value() >= SecurityLevel::Medium.value();
+
+    if encrypted
+        && info
+            .supported_settings
+            .contains(ControllerSetting::SecureSimplePairing)
+        && !settings.contains(ControllerSetting::SecureSimplePairing)
+    {
+        settings = set_ssp(socket, controller, true, event_tx.clone()).await?;
+    }
+
+    // the settings do not tell Secure Connections apart from Secure
+    // Connections Only, so the mode is always set
+    if policy.secure_connections_only {
+        settings =
+            set_secure_connections_mode(socket, controller, SecureConnectionsMode::Only, event_tx)
+                .await?;
+    }
+
+    Ok(settings)
+}
+
 /// This command is used to tell the kernel whether to accept the
 ///	usage of debug keys or not.
 ///
@@ -680,14 +1121,11 @@ pub async fn set_debug_mode(
     mode: DebugKeysMode,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(mode as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetDebugKeys,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_debug_mode(mode)),
         event_tx,
     )
     .await?;
@@ -695,6 +1133,10 @@ pub async fn set_debug_mode(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_debug_mode(mode: DebugKeysMode) -> Bytes {
+    u8_bytes(mode as u8)
+}
+
 ///	This command is used to enable Low Energy Privacy feature using
 ///	resolvable private addresses.
 ///
@@ -736,15 +1178,11 @@ pub async fn set_privacy_mode(
     identity_resolving_key: [u8; 16],
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(17);
-    param.put_u8(mode as u8);
-    param.put_slice(&identity_resolving_key[..]);
-
     let (_, param) = exec_command(
         socket,
         Command::SetPrivacy,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_privacy_mode(mode, identity_resolving_key)),
         event_tx,
     )
     .await?;
@@ -752,6 +1190,16 @@ pub async fn set_privacy_mode(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_privacy_mode(
+    mode: PrivacyMode,
+    identity_resolving_key: [u8; 16],
+) -> Bytes {
+    let mut param = BytesMut::with_capacity(17);
+    param.put_u8(mode as u8);
+    param.put_slice(&identity_resolving_key[..]);
+    param.freeze()
+}
+
 ///	This command allows to change external configuration option to
 ///	indicate that a controller is now configured or unconfigured.
 ///
@@ -775,13 +1223,11 @@ pub async fn set_external_config(
     config: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let param = BytesMut::from([config as u8].as_ref() as &[u8]);
-
     let (_, param) = exec_command(
         socket,
         Command::SetExternalConfig,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_external_config(config)),
         event_tx,
     )
     .await?;
@@ -789,6 +1235,10 @@ pub async fn set_external_config(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_external_config(config: bool) -> Bytes {
+    u8_bytes(config as u8)
+}
+
 ///	This command allows configuration of public address. Since a vendor
 ///	specific procedure is required, this command might not be supported
 ///	by all controllers. Actually most likely only a handful embedded
@@ -821,13 +1271,11 @@ pub async fn set_public_address(
     address: Address,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let param = BytesMut::from(address.as_ref());
-
     let (_, param) = exec_command(
         socket,
         Command::SetPublicAddress,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_public_address(address)),
         event_tx,
     )
     .await?;
@@ -835,6 +1283,10 @@ pub async fn set_public_address(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_public_address(address: Address) -> Bytes {
+    Bytes::copy_from_slice(address.as_ref())
+}
+
 ///	This command is used to set the appearance value of a controller.
 ///
 ///	This command can be used when the controller is not
@@ -851,14 +1303,11 @@ pub async fn set_appearance(
     appearance: u16,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(2);
-    param.put_u16_le(appearance);
-
     let (_, _param) = exec_command(
         socket,
         Command::SetAppearance,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_appearance(appearance)),
         event_tx,
     )
     .await?;
@@ -866,6 +1315,10 @@ pub async fn set_appearance(
     Ok(())
 }
 
+pub(crate) fn encode_set_appearance(appearance: u16) -> Bytes {
+    Bytes::copy_from_slice(&appearance.to_le_bytes())
+}
+
 ///	on the PHY configuration. It is remembered over power cycles.
 pub async fn set_phy_config(
     socket: &mut ManagementStream,
@@ -873,14 +1326,11 @@ pub async fn set_phy_config(
     selected_phys: BitFlags<PhyFlag>,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let mut param = BytesMut::with_capacity(4);
-    param.put_u32_le(selected_phys.bits());
-
     let (_, _param) = exec_command(
         socket,
         Command::SetPhyConfig,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_phy_config(selected_phys)),
         event_tx,
     )
     .await?;
@@ -888,6 +1338,10 @@ pub async fn set_phy_config(
     Ok(())
 }
 
+pub(crate) fn encode_set_phy_config(selected_phys: BitFlags<PhyFlag>) -> Bytes {
+    Bytes::copy_from_slice(&selected_phys.bits().to_le_bytes())
+}
+
 /// This command is used to enable/disable Wideband Speech
 /// support for a controller.
 ///
@@ -905,14 +1359,11 @@ pub async fn set_wideband_speech(
     enabled: bool,
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<ControllerSettings> {
-    let mut param = BytesMut::with_capacity(1);
-    param.put_u8(enabled as u8);
-
     let (_, param) = exec_command(
         socket,
         Command::SetWidebandSpeech,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_wideband_speech(enabled)),
         event_tx,
     )
     .await?;
@@ -920,6 +1371,10 @@ pub async fn set_wideband_speech(
     Ok(param.ok_or(Error::NoData)?.get_flags_u32_le())
 }
 
+pub(crate) fn encode_set_wideband_speech(enabled: bool) -> Bytes {
+    u8_bytes(enabled as u8)
+}
+
 /// This command is used to set a list of default runtime parameters.
 ///
 /// This command can be used at any time and will change the runtime
@@ -934,24 +1389,11 @@ pub async fn set_default_runtime_config(
     params: &[(RuntimeConfigParameterType, Vec<u8>)],
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
-    let size = params
-        .iter()
-        .fold(0, |acc, (_, value)| acc + 3 + value.len());
-    let mut param = BytesMut::with_capacity(size);
-
-    #[allow(unreachable_code, unused_variables)]
-    // until we have constants in RuntimeConfigParameterType
-    for (parameter_type, value) in params {
-        param.put_u16_le(unimplemented!("*parameter_type as u16"));
-        param.put_u8(value.len() as u8);
-        param.put_slice(value);
-    }
-
     let (_, _param) = exec_command(
         socket,
-        Command::SetDefaultSystemConfig,
+        Command::SetDefaultRuntimeConfig,
         controller,
-        Some(param.freeze()),
+        Some(encode_set_default_runtime_config(params)),
         event_tx,
     )
     .await?;
@@ -959,6 +1401,15 @@ pub async fn set_default_runtime_config(
     Ok(())
 }
 
+pub(crate) fn encode_set_default_runtime_config(
+    params: &[(RuntimeConfigParameterType, Vec<u8>)],
+) -> Bytes {
+    encode_tlv_list(params.iter().map(
+        // RuntimeConfigParameterType has no variants yet, so the list is empty
+        |(parameter_type, _)| match *parameter_type {},
+    ))
+}
+
 /// This command is used to set a list of default controller parameters.
 ///
 /// This command can be used when the controller is not powered and
@@ -972,25 +1423,200 @@ pub async fn set_default_system_config(
     params: &[(SystemConfigParameterType, Vec<u8>)],
     event_tx: Option<mpsc::Sender<Response>>,
 ) -> Result<()> {
+    let (_, _param) = exec_command(
+        socket,
+        Command::SetDefaultSystemConfig,
+        controller,
+        Some(encode_set_default_system_config(params)),
+        event_tx,
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) fn encode_set_default_system_config(
+    params: &[(SystemConfigParameterType, Vec<u8>)],
+) -> Bytes {
+    encode_tlv_list(
+        params
+            .iter()
+            .map(|(parameter_type, value)| (*parameter_type as u16, &value[..])),
+    )
+}
+
+/// Encodes the Type/Length/Value list of the Set Default System and Runtime
+/// Configuration commands.
+pub(crate) fn encode_tlv_list<'a>(params: impl Iterator<Item = (u16, &'a [u8])> + Clone) -> Bytes {
     let size = params
-        .iter()
+        .clone()
         .fold(0, |acc, (_, value)| acc + 3 + value.len());
     let mut param = BytesMut::with_capacity(size);
 
     for (parameter_type, value) in params {
-        param.put_u16_le(*parameter_type as u16);
+        param.put_u16_le(parameter_type);
         param.put_u8(value.len() as u8);
         param.put_slice(value);
     }
 
-    let (_, _param) = exec_command(
-        socket,
-        Command::SetDefaultSystemConfig,
-        controller,
-        Some(param.freeze()),
-        event_tx,
-    )
-    .await?;
+    param.freeze()
+}
 
-    Ok(())
+/// Sets the BR/EDR page scan and inquiry scan parameters of a controller
+/// according to `profile`, using [`set_default_system_config`].
+///
+/// This command can be used when the controller is not powered, and the
+/// parameters only affect scans which are started after they are set.
+pub async fn set_fast_pairing_profile(
+    socket: &mut ManagementStream,
+    controller: Controller,
+    profile: FastPairingProfile,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> Result<()> {
+    set_default_system_config(socket, controller, &profile.system_config(), event_tx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{
+        controller_info, converse, MockKernel, MockScript, MockTransport, MOCK_CONTROLLER,
+    };
+
+    #[tokio::test]
+    async fn power_cycle_settles() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+
+        let kernel = async {
+            let off = kernel.answer(&[0x00, 0x00, 0x00, 0x00]).await.unwrap();
+            assert_eq!(&off.param[..], &[0x00]);
+
+            // the reply still reports the old state, so the cycle only
+            // settles with the event that follows it
+            let on = kernel.answer(&[0x00, 0x00, 0x00, 0x00]).await.unwrap();
+            assert_eq!(&on.param[..], &[0x01]);
+
+            kernel
+                .send_event(MOCK_CONTROLLER, 0x0006, &[0x01, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+        };
+
+        let settings = converse(
+            power_cycle(&mut socket, MOCK_CONTROLLER, Duration::from_secs(5), None),
+            kernel,
+        )
+        .await;
+        assert!(settings.unwrap().contains(ControllerSetting::Powered));
+    }
+
+    #[tokio::test]
+    async fn limited_discoverable_restores_settings() {
+        // powered and br/edr, but neither connectable nor discoverable
+        let script = MockScript::new()
+            .reply(Command::ReadControllerInfo, controller_info(0x81))
+            .reply(Command::SetConnectable, [0x83, 0x00, 0x00, 0x00])
+            .reply(Command::SetDiscoverable, [0x8B, 0x00, 0x00, 0x00])
+            .then_event(0x0006, [0x83, 0x00, 0x00, 0x00])
+            .reply(Command::SetConnectable, [0x81, 0x00, 0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let settings =
+            make_limited_discoverable(&mut socket, MOCK_CONTROLLER, Duration::from_secs(30), None)
+                .await
+                .unwrap();
+        assert!(!settings.contains(ControllerSetting::Connectable));
+
+        let commands: Vec<_> = transport
+            .commands()
+            .into_iter()
+            .map(|c| (c.opcode, c.param.to_vec()))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                (Command::ReadControllerInfo, vec![]),
+                (Command::SetConnectable, vec![0x01]),
+                (Command::SetDiscoverable, vec![0x02, 30, 0]),
+                (Command::SetConnectable, vec![0x00]),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limited_discoverable_rereads_settings() {
+        // powered, connectable and br/edr. the end of the period is never
+        // reported, and pairable was turned on in the meantime
+        let script = MockScript::new()
+            .reply(Command::ReadControllerInfo, controller_info(0x83))
+            .reply(Command::ReadControllerInfo, controller_info(0x93))
+            .reply(Command::SetDiscoverable, [0x8B, 0x00, 0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let settings =
+            make_limited_discoverable(&mut socket, MOCK_CONTROLLER, Duration::from_secs(30), None)
+                .await
+                .unwrap();
+        assert!(settings.contains(ControllerSetting::Pairable));
+
+        let opcodes: Vec<_> = transport.commands().into_iter().map(|c| c.opcode).collect();
+        assert_eq!(
+            opcodes,
+            [
+                Command::ReadControllerInfo,
+                Command::SetDiscoverable,
+                Command::ReadControllerInfo,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn limited_discoverable_rejects_duration() {
+        let (mut socket, transport) = MockTransport::stream(MockScript::new());
+
+        for duration in [Duration::from_millis(500), Duration::from_secs(0x10000)] {
+            let err = make_limited_discoverable(&mut socket, MOCK_CONTROLLER, duration, None)
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                Error::InvalidDiscoverableTimeout { duration: d } if d == duration
+            ));
+        }
+
+        assert!(transport.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn apply_settings_skips_unchanged() {
+        // powered, connectable, ssp and br/edr
+        let script = MockScript::new()
+            .reply(Command::ReadControllerInfo, controller_info(0xC3))
+            .reply(Command::SetPairable, [0xD3, 0x00, 0x00, 0x00])
+            .reply(Command::SetDiscoverable, [0xDB, 0x00, 0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        let desired = DesiredSettings {
+            powered: Some(true),
+            connectable: Some(true),
+            discoverable: Some((DiscoverableMode::General, None)),
+            bondable: Some(true),
+            ssp: Some(true),
+            ..DesiredSettings::new()
+        };
+        let settings = apply_settings(&mut socket, MOCK_CONTROLLER, desired, None)
+            .await
+            .unwrap();
+        assert!(settings.contains(ControllerSetting::Discoverable));
+
+        let commands: Vec<_> = transport.commands().into_iter().map(|c| c.opcode).collect();
+        assert_eq!(
+            commands,
+            [
+                Command::ReadControllerInfo,
+                Command::SetPairable,
+                Command::SetDiscoverable
+            ]
+        );
+    }
 }
@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::future::{join, pending, select, BoxFuture, Either};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::oneshot;
+
+use super::*;
+
+/// The number of events that are buffered for the event receiver, and the
+/// number of commands that are buffered for the task.
+const CHANNEL_CAPACITY: usize = 64;
+
+type TaskCommand = Box<
+    dyn for<'a> FnOnce(
+            &'a mut ManagementStream,
+            Option<mpsc::Sender<Response>>,
+        ) -> BoxFuture<'a, ()>
+        + Send,
+>;
+
+/// A task which owns a [`ManagementStream`], runs the commands that are sent
+/// to it through [`ManagementHandle`]s, and forwards every other event.
+///
+/// This is the recommended way to use the management API from applications
+/// that need to react to events while they run commands: the task receives
+/// events all the time, so none of them are missed between commands, and the
+/// socket does not have to be shared between tasks.
+///
+/// The event channel holds up to 64 events. The task never waits for room in
+/// it, because an application that waits for a command while it is not
+/// receiving events would otherwise wait forever. Events that do not fit
+/// are dropped instead, and counted by
+/// [`ManagementHandle::dropped_events`].
+#[derive(Debug)]
+pub struct ManagementTask {
+    socket: ManagementStream,
+    commands: mpsc::Receiver<TaskCommand>,
+    events: mpsc::Sender<Response>,
+    dropped_events: Arc<AtomicU64>,
+    shutdown: Option<oneshot::Receiver<()>>,
+}
+
+impl ManagementTask {
+    /// Spawns a task on the current tokio runtime which owns `socket`.
+    ///
+    /// Returns a handle for running commands, a receiver for every event that
+    /// does not answer a command, and a sender which stops the task when a
+    /// value is sent on it. Dropping the sender does not stop the task; the
+    /// task also stops once every handle and the event receiver have been
+    /// dropped, or when the socket fails.
+    pub fn spawn(
+        socket: ManagementStream,
+    ) -> (
+        ManagementHandle,
+        mpsc::Receiver<Response>,
+        oneshot::Sender<()>,
+    ) {
+        let (task, handle, event_rx, shutdown_tx) = Self::new(socket);
+        tokio::spawn(task.run());
+        (handle, event_rx, shutdown_tx)
+    }
+
+    /// Creates a task without spawning it, for applications that want to
+    /// drive it themselves with [`ManagementTask::run`].
+    pub fn new(
+        socket: ManagementStream,
+    ) -> (
+        Self,
+        ManagementHandle,
+        mpsc::Receiver<Response>,
+        oneshot::Sender<()>,
+    ) {
+        let (command_tx, command_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (event_tx, event_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let dropped_events = Arc::new(AtomicU64::new(0));
+
+        let task = Self {
+            socket,
+            commands: command_rx,
+            events: event_tx,
+            dropped_events: dropped_events.clone(),
+            shutdown: Some(shutdown_rx),
+        };
+
+        (
+            task,
+            ManagementHandle {
+                commands: command_tx,
+                dropped_events,
+            },
+            event_rx,
+            shutdown_tx,
+        )
+    }
+
+    /// Runs the task until it is stopped. Returns an error if the socket
+    /// fails.
+    pub async fn run(mut self) -> Result<()> {
+        let mut commands_closed = false;
+
+        loop {
+            if commands_closed && self.events.is_closed() {
+                return Ok(());
+            }
+
+            let shutdown_rx = &mut self.shutdown;
+            let shutdown = async {
+                match shutdown_rx {
+                    Some(shutdown_rx) => shutdown_rx.await.is_ok(),
+                    None => pending().await,
+                }
+            };
+
+            let command_rx = &mut self.commands;
+            let command = async {
+                if commands_closed {
+                    pending().await
+                } else {
+                    command_rx.recv().await
+                }
+            };
+
+            // both receives are cancel safe, so whichever loses the race does
+            // not lose anything
+            let next = match select(
+                Box::pin(shutdown),
+                select(Box::pin(command), Box::pin(self.socket.receive())),
+            )
+            .await
+            {
+                Either::Left((true, _)) => return Ok(()),
+                Either::Left((false, _)) => {
+                    // the sender was dropped without stopping the task
+                    self.shutdown = None;
+                    continue;
+                }
+                Either::Right((Either::Left((command, _)), _)) => Either::Left(command),
+                Either::Right((Either::Right((response, _)), _)) => Either::Right(response?),
+            };
+
+            match next {
+                Either::Left(Some(command)) => {
+                    // commands wait for room in the channel that they are
+                    // given, so it is drained while they run
+                    let (event_tx, mut event_rx) = mpsc::channel(CHANNEL_CAPACITY);
+                    let (events, dropped_events) = (&self.events, &self.dropped_events);
+
+                    let forward = async {
+                        while let Some(response) = event_rx.recv().await {
+                            forward_event(events, dropped_events, response);
+                        }
+                    };
+
+                    join(command(&mut self.socket, Some(event_tx)), forward).await;
+                }
+                Either::Left(None) => commands_closed = true,
+                Either::Right(response) => {
+                    forward_event(&self.events, &self.dropped_events, response)
+                }
+            }
+        }
+    }
+}
+
+fn forward_event(events: &mpsc::Sender<Response>, dropped_events: &AtomicU64, response: Response) {
+    // events are also dropped if nobody receives them anymore, but those
+    // are not counted
+    if let Err(TrySendError::Full(_)) = events.try_send(response) {
+        dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A handle for running commands on a [`ManagementTask`]. Handles can be
+/// cloned and sent to other tasks.
+#[derive(Debug, Clone)]
+pub struct ManagementHandle {
+    commands: mpsc::Sender<TaskCommand>,
+    dropped_events: Arc<AtomicU64>,
+}
+
+impl ManagementHandle {
+    /// Runs a command on the task and waits for its result. Commands run one
+    /// at a time, in the order in which they were sent.
+    ///
+    /// `command` is called with the socket of the task and the sender of its
+    /// event channel, and usually boxes the future of a command function,
+    /// such as
+    /// `|socket, event_tx| set_powered(socket, controller, true, event_tx).boxed()`.
+    ///
+    /// Fails with [`Error::TaskStopped`] if the task has stopped.
+    pub async fn run<T, F>(&self, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: for<'a> FnOnce(
+                &'a mut ManagementStream,
+                Option<mpsc::Sender<Response>>,
+            ) -> BoxFuture<'a, Result<T>>
+            + Send
+            + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let command: TaskCommand = Box::new(move |socket, event_tx| {
+            Box::pin(async move {
+                let _ = result_tx.send(command(socket, event_tx).await);
+            })
+        });
+
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| Error::TaskStopped)?;
+
+        result_rx.await.map_err(|_| Error::TaskStopped)?
+    }
+
+    /// The number of events that the task dropped because the event channel
+    /// was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the task has stopped.
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::testing::mock::MockKernel;
+
+    #[tokio::test]
+    async fn full_event_channel() {
+        let (socket, mut kernel) = MockKernel::pair().unwrap();
+        let (handle, _event_rx, _shutdown_tx) = ManagementTask::spawn(socket);
+
+        // nobody receives these events
+        for _ in 0..CHANNEL_CAPACITY + 8 {
+            kernel
+                .send_event(Controller(0), 0x0006, &[0x01, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+        }
+
+        let powered = handle
+            .run(|socket, event_tx| set_powered(socket, Controller(0), true, event_tx).boxed());
+        let kernel = async {
+            let command = kernel.answer(&[0x01, 0x00, 0x00, 0x00]).await.unwrap();
+            assert_eq!(command.opcode, Command::SetPowered);
+        };
+
+        let (powered, ()) = futures::join!(powered, kernel);
+        powered.unwrap();
+        assert_eq!(handle.dropped_events(), 8);
+    }
+}
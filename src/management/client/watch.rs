@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use futures::stream::{self, Stream};
+
+use super::*;
+
+/// A change in the set of controllers that are available on the system.
+#[derive(Debug)]
+pub enum ControllerEvent {
+    /// A controller was added, or was already present when the watcher was
+    /// created.
+    Added(Controller, ControllerInfo),
+    /// A controller was removed.
+    Removed(Controller),
+}
+
+struct WatchState<'a> {
+    socket: &'a mut ManagementStream,
+    event_tx: Option<mpsc::Sender<Response>>,
+    known: HashSet<u16>,
+    pending: VecDeque<ControllerEvent>,
+    enumerated: bool,
+    done: bool,
+}
+
+/// Returns a stream of the controllers that are added to and removed from the
+/// system, such as USB dongles being plugged in.
+///
+/// The stream starts with an `Added` event for every controller which is
+/// already present. Only configured primary controllers are reported.
+///
+/// Events that are not related to controllers being added or removed are
+/// forwarded to `event_tx`. The stream ends after the first error.
+pub fn watch_controllers(
+    socket: &mut ManagementStream,
+    event_tx: Option<mpsc::Sender<Response>>,
+) -> impl Stream<Item = Result<ControllerEvent>> + '_ {
+    let state = WatchState {
+        socket,
+        event_tx,
+        known: HashSet::new(),
+        pending: VecDeque::new(),
+        enumerated: false,
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        match next_controller_event(&mut state).await {
+            Ok(event) => Some((Ok(event), state)),
+            Err(err) => {
+                state.done = true;
+                Some((Err(err), state))
+            }
+        }
+    })
+}
+
+async fn next_controller_event(state: &mut WatchState<'_>) -> Result<ControllerEvent> {
+    if !state.enumerated {
+        state.enumerated = true;
+
+        for controller in get_controller_list(state.socket, state.event_tx.clone()).await? {
+            if let Some(event) = controller_added(state, controller).await? {
+                state.pending.push_back(event);
+            }
+        }
+    }
+
+    loop {
+        if let Some(event) = state.pending.pop_front() {
+            return Ok(event);
+        }
+
+        let response = state.socket.receive().await?;
+        let controller = response.controller;
+
+        let event = match response.event {
+            Event::IndexAdded
+            | Event::ExtendedIndexAdded {
+                controller_type: ControllerType::Primary,
+                ..
+            } => controller_added(state, controller).await?,
+
+            Event::IndexRemoved
+            | Event::ExtendedIndexRemoved {
+                controller_type: ControllerType::Primary,
+                ..
+            } => {
+                if state.known.remove(&controller.0) {
+                    Some(ControllerEvent::Removed(controller))
+                } else {
+                    None
+                }
+            }
+
+            _ => {
+                if let Some(event_tx) = &mut state.event_tx {
+                    let _ = event_tx.send(response).await;
+                }
+
+                None
+            }
+        };
+
+        if let Some(event) = event {
+            return Ok(event);
+        }
+    }
+}
+
+async fn controller_added(
+    state: &mut WatchState<'_>,
+    controller: Controller,
+) -> Result<Option<ControllerEvent>> {
+    // both Index Added and Extended Index Added can be received for the same
+    // controller
+    if state.known.contains(&controller.0) {
+        return Ok(None);
+    }
+
+    match get_controller_info(state.socket, controller, state.event_tx.clone()).await {
+        Ok(info) => {
+            state.known.insert(controller.0);
+            Ok(Some(ControllerEvent::Added(controller, info)))
+        }
+        // the controller was removed again before it could be queried
+        Err(Error::CommandError {
+            status: CommandStatus::InvalidIndex,
+            ..
+        }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A change of a single controller setting, with its new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingChange {
+    PoweredChanged(bool),
+    ConnectableChanged(bool),
+    FastConnectableChanged(bool),
+    DiscoverableChanged(bool),
+    PairableChanged(bool),
+    LinkLevelSecurityChanged(bool),
+    SecureSimplePairingChanged(bool),
+    BREDRChanged(bool),
+    HighSpeedChanged(bool),
+    LEChanged(bool),
+    AdvertisingChanged(bool),
+    SecureConnectionChanged(bool),
+    DebugKeysChanged(bool),
+    PrivacyChanged(bool),
+    ConfigurationChanged(bool),
+    StaticAddressChanged(bool),
+    PhyConfigurationChanged(bool),
+    WidebandSpeechChanged(bool),
+}
+
+impl SettingChange {
+    pub fn new(setting: ControllerSetting, enabled: bool) -> Self {
+        match setting {
+            ControllerSetting::Powered => Self::PoweredChanged(enabled),
+            ControllerSetting::Connectable => Self::ConnectableChanged(enabled),
+            ControllerSetting::FastConnectable => Self::FastConnectableChanged(enabled),
+            ControllerSetting::Discoverable => Self::DiscoverableChanged(enabled),
+            ControllerSetting::Pairable => Self::PairableChanged(enabled),
+            ControllerSetting::LinkLevelSecurity => Self::LinkLevelSecurityChanged(enabled),
+            ControllerSetting::SecureSimplePairing => Self::SecureSimplePairingChanged(enabled),
+            ControllerSetting::BREDR => Self::BREDRChanged(enabled),
+            ControllerSetting::HighSpeed => Self::HighSpeedChanged(enabled),
+            ControllerSetting::LE => Self::LEChanged(enabled),
+            ControllerSetting::Advertising => Self::AdvertisingChanged(enabled),
+            ControllerSetting::SecureConnection => Self::SecureConnectionChanged(enabled),
+            ControllerSetting::DebugKeys => Self::DebugKeysChanged(enabled),
+            ControllerSetting::Privacy => Self::PrivacyChanged(enabled),
+            ControllerSetting::Configuration => Self::ConfigurationChanged(enabled),
+            ControllerSetting::StaticAddress => Self::StaticAddressChanged(enabled),
+            ControllerSetting::PhyConfiguration => Self::PhyConfigurationChanged(enabled),
+            ControllerSetting::WidebandSpeech => Self::WidebandSpeechChanged(enabled),
+        }
+    }
+
+    /// The setting that changed.
+    pub fn setting(&self) -> ControllerSetting {
+        match self {
+            Self::PoweredChanged(_) => ControllerSetting::Powered,
+            Self::ConnectableChanged(_) => ControllerSetting::Connectable,
+            Self::FastConnectableChanged(_) => ControllerSetting::FastConnectable,
+            Self::DiscoverableChanged(_) => ControllerSetting::Discoverable,
+            Self::PairableChanged(_) => ControllerSetting::Pairable,
+            Self::LinkLevelSecurityChanged(_) => ControllerSetting::LinkLevelSecurity,
+            Self::SecureSimplePairingChanged(_) => ControllerSetting::SecureSimplePairing,
+            Self::BREDRChanged(_) => ControllerSetting::BREDR,
+            Self::HighSpeedChanged(_) => ControllerSetting::HighSpeed,
+            Self::LEChanged(_) => ControllerSetting::LE,
+            Self::AdvertisingChanged(_) => ControllerSetting::Advertising,
+            Self::SecureConnectionChanged(_) => ControllerSetting::SecureConnection,
+            Self::DebugKeysChanged(_) => ControllerSetting::DebugKeys,
+            Self::PrivacyChanged(_) => ControllerSetting::Privacy,
+            Self::ConfigurationChanged(_) => ControllerSetting::Configuration,
+            Self::StaticAddressChanged(_) => ControllerSetting::StaticAddress,
+            Self::PhyConfigurationChanged(_) => ControllerSetting::PhyConfiguration,
+            Self::WidebandSpeechChanged(_) => ControllerSetting::WidebandSpeech,
+        }
+    }
+
+    /// Whether the setting is enabled now.
+    pub fn enabled(&self) -> bool {
+        match *self {
+            Self::PoweredChanged(enabled)
+            | Self::ConnectableChanged(enabled)
+            | Self::FastConnectableChanged(enabled)
+            | Self::DiscoverableChanged(enabled)
+            | Self::PairableChanged(enabled)
+            | Self::LinkLevelSecurityChanged(enabled)
+            | Self::SecureSimplePairingChanged(enabled)
+            | Self::BREDRChanged(enabled)
+            | Self::HighSpeedChanged(enabled)
+            | Self::LEChanged(enabled)
+            | Self::AdvertisingChanged(enabled)
+            | Self::SecureConnectionChanged(enabled)
+            | Self::DebugKeysChanged(enabled)
+            | Self::PrivacyChanged(enabled)
+            | Self::ConfigurationChanged(enabled)
+            | Self::StaticAddressChanged(enabled)
+            | Self::PhyConfigurationChanged(enabled)
+            | Self::WidebandSpeechChanged(enabled) => enabled,
+        }
+    }
+
+    /// The changes between two sets of settings, in the order of the bits of
+    /// the settings.
+    pub fn diff(previous: ControllerSettings, current: ControllerSettings) -> Vec<Self> {
+        (previous ^ current)
+            .iter()
+            .map(|setting| Self::new(setting, current.contains(setting)))
+            .collect()
+    }
+}
+
+/// The settings of a controller changed, reported by [`SettingsWatcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsChange {
+    pub controller: Controller,
+    /// The settings of the controller after the change.
+    pub settings: ControllerSettings,
+    /// The settings that changed, which is never empty.
+    pub changes: Vec<SettingChange>,
+}
+
+/// Tracks the settings of controllers from New Settings events, and reports
+/// which settings changed instead of the whole set.
+///
+/// The kernel sends New Settings to every management socket except the one
+/// that made the change, so changes made through the socket that the
+/// watcher receives on are not reported. The watcher only makes progress
+/// while [`SettingsWatcher::next_change`] is being awaited, or when events
+/// are passed to [`SettingsWatcher::handle_event`].
+#[derive(Debug, Default, Clone)]
+pub struct SettingsWatcher {
+    settings: HashMap<Controller, ControllerSettings>,
+}
+
+impl SettingsWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking a controller whose settings are known, for example
+    /// from the `current_settings` of its [`ControllerInfo`]. If the
+    /// controller was already being tracked, its settings are replaced
+    /// without reporting a change.
+    pub fn track(&mut self, controller: Controller, settings: ControllerSettings) {
+        self.settings.insert(controller, settings);
+    }
+
+    /// Reads the settings of a controller and starts tracking it.
+    pub async fn watch(
+        &mut self,
+        socket: &mut ManagementStream,
+        controller: Controller,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<ControllerSettings> {
+        let info = get_controller_info(socket, controller, event_tx).await?;
+        self.track(controller, info.current_settings);
+        Ok(info.current_settings)
+    }
+
+    /// Stops tracking a controller. Returns `false` if it was not being
+    /// tracked.
+    pub fn untrack(&mut self, controller: Controller) -> bool {
+        self.settings.remove(&controller).is_some()
+    }
+
+    /// The last known settings of a controller, if it is being tracked.
+    pub fn settings(&self, controller: Controller) -> Option<ControllerSettings> {
+        self.settings.get(&controller).copied()
+    }
+
+    /// Updates the settings of the tracked controllers based on an event,
+    /// and returns the change if the event changed any. Controllers that are
+    /// removed from the system stop being tracked.
+    pub fn handle_event(&mut self, response: &Response) -> Option<SettingsChange> {
+        match &response.event {
+            Event::NewSettings { settings } => {
+                let previous = self.settings.get_mut(&response.controller)?;
+                let changes = SettingChange::diff(*previous, *settings);
+                *previous = *settings;
+
+                if changes.is_empty() {
+                    return None;
+                }
+
+                Some(SettingsChange {
+                    controller: response.controller,
+                    settings: *settings,
+                    changes,
+                })
+            }
+            Event::IndexRemoved => {
+                self.settings.remove(&response.controller);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Processes events until the settings of a tracked controller change.
+    ///
+    /// All other events received while this function is running are
+    /// forwarded to `event_tx`.
+    pub async fn next_change(
+        &mut self,
+        socket: &mut ManagementStream,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<SettingsChange> {
+        loop {
+            let response = socket.receive().await?;
+
+            if let Some(change) = self.handle_event(&response) {
+                return Ok(change);
+            }
+
+            if let Some(event_tx) = &event_tx {
+                let _ = event_tx.send(response).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{converse, MockKernel, MOCK_CONTROLLER};
+
+    #[tokio::test]
+    async fn settings_changes() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+        let controller = MOCK_CONTROLLER;
+
+        let mut watcher = SettingsWatcher::new();
+        watcher.track(
+            controller,
+            ControllerSetting::Powered | ControllerSetting::LE,
+        );
+
+        let kernel = async {
+            // an untracked controller, a new setting that does not change
+            // anything, and then discoverable on and le off
+            kernel
+                .send_event(Controller(1), 0x0006, &[0x00, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+            kernel
+                .send_event(controller, 0x0006, &[0x01, 0x02, 0x00, 0x00])
+                .await
+                .unwrap();
+            kernel
+                .send_event(controller, 0x0006, &[0x09, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+        };
+
+        let change = converse(watcher.next_change(&mut socket, None), kernel).await;
+        let change = change.unwrap();
+
+        assert_eq!(change.controller, controller);
+        assert_eq!(
+            change.changes,
+            vec![
+                SettingChange::DiscoverableChanged(true),
+                SettingChange::LEChanged(false),
+            ]
+        );
+        assert_eq!(
+            watcher.settings(controller),
+            Some(ControllerSetting::Powered | ControllerSetting::Discoverable)
+        );
+        assert_eq!(watcher.settings(Controller(1)), None);
+    }
+}
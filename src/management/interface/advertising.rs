@@ -0,0 +1,163 @@
+use bytes::Bytes;
+
+use crate::management::interface::{EirData, Event};
+use crate::management::DeviceFlag;
+use crate::{Address, AddressType};
+
+/// A PHY that LE advertisements can be sent on.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, ToPrimitive)]
+pub enum LePhy {
+    Le1M = 0x01,
+    Le2M = 0x02,
+    LeCoded = 0x03,
+}
+
+/// Whether the data of an advertising report is all of the data that was
+/// advertised.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, ToPrimitive)]
+pub enum AdvertisingDataStatus {
+    Complete = 0b00,
+    /// More reports with the rest of the data will follow.
+    Incomplete = 0b01,
+    /// The controller could not receive the rest of the data.
+    Truncated = 0b10,
+}
+
+/// An advertisement that was received from a remote device, with the
+/// metadata that extended advertising adds.
+///
+/// The management API only reports part of this metadata, so the fields
+/// which it does not report are `None` in reports made with
+/// [`AdvertisingReport::from_device_found`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvertisingReport {
+    pub address: Address,
+    pub address_type: AddressType,
+    pub connectable: bool,
+    /// Whether the advertisement can be answered with a scan request.
+    /// `None` if this is not known.
+    pub scannable: Option<bool>,
+    /// Whether the advertisement was directed at this device. `None` if this
+    /// is not known.
+    pub directed: Option<bool>,
+    pub scan_response: bool,
+    /// Whether the advertisement was sent using legacy advertising PDUs.
+    /// `None` if this is not known.
+    pub legacy: Option<bool>,
+    pub data_status: AdvertisingDataStatus,
+    pub primary_phy: Option<LePhy>,
+    pub secondary_phy: Option<LePhy>,
+    /// The advertising set ID.
+    pub sid: Option<u8>,
+    pub tx_power: Option<i8>,
+    pub rssi: Option<i8>,
+    /// The interval of the periodic advertising that belongs to this
+    /// advertisement, in units of 1.25 ms.
+    pub periodic_interval: Option<u16>,
+    pub data: Bytes,
+}
+
+impl AdvertisingReport {
+    /// Creates a report from a Device Found event. Returns `None` for other
+    /// events.
+    pub fn from_device_found(event: &Event) -> Option<Self> {
+        match event {
+            Event::DeviceFound {
+                address,
+                address_type,
+                rssi,
+                flags,
+                eir_data,
+            } => Some(Self {
+                address: *address,
+                address_type: *address_type,
+                connectable: !flags.contains(DeviceFlag::NotConnectable),
+                // only scannable advertisements are followed by a scan
+                // response, but the kernel does not report the type of the
+                // others
+                scannable: if flags.contains(DeviceFlag::ScanResponse) {
+                    Some(true)
+                } else {
+                    None
+                },
+                directed: None,
+                scan_response: flags.contains(DeviceFlag::ScanResponse),
+                legacy: None,
+                data_status: AdvertisingDataStatus::Complete,
+                primary_phy: None,
+                secondary_phy: None,
+                sid: None,
+                tx_power: EirData::parse(eir_data).tx_power,
+                // the kernel reports 127 if the RSSI is not available
+                rssi: if *rssi == 127 { None } else { Some(*rssi) },
+                periodic_interval: None,
+                data: eir_data.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The Event_Type field of an LE Extended Advertising Report for this
+    /// report. Properties which are not known are left unset.
+    pub fn event_type(&self) -> u16 {
+        self.connectable as u16
+            | (self.scannable.unwrap_or(false) as u16) << 1
+            | (self.directed.unwrap_or(false) as u16) << 2
+            | (self.scan_response as u16) << 3
+            | (self.legacy.unwrap_or(false) as u16) << 4
+            | (self.data_status as u16) << 5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use enumflags2::BitFlags;
+
+    use super::*;
+
+    fn device_found(flags: BitFlags<DeviceFlag>) -> AdvertisingReport {
+        let event = Event::DeviceFound {
+            address: Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+            address_type: AddressType::LEPublic,
+            rssi: -60,
+            flags,
+            eir_data: Bytes::from_static(&[0x02, 0x0A, 0x04]),
+        };
+
+        AdvertisingReport::from_device_found(&event).unwrap()
+    }
+
+    #[test]
+    fn from_device_found() {
+        let report = device_found(BitFlags::empty());
+        assert!(report.connectable);
+        assert_eq!(report.scannable, None);
+        assert_eq!(report.directed, None);
+        assert!(!report.scan_response);
+        assert_eq!(report.tx_power, Some(4));
+        assert_eq!(report.rssi, Some(-60));
+        assert_eq!(report.event_type(), 0b0000001);
+
+        let report = device_found(DeviceFlag::NotConnectable | DeviceFlag::ScanResponse);
+        assert!(!report.connectable);
+        assert_eq!(report.scannable, Some(true));
+        assert_eq!(report.directed, None);
+        assert!(report.scan_response);
+        assert_eq!(report.event_type(), 0b0001010);
+    }
+
+    #[test]
+    fn event_type() {
+        let report = AdvertisingReport {
+            scannable: Some(false),
+            directed: Some(true),
+            legacy: Some(true),
+            data_status: AdvertisingDataStatus::Truncated,
+            ..device_found(BitFlags::empty())
+        };
+
+        assert_eq!(report.event_type(), 0b1010101);
+    }
+}
@@ -24,7 +24,11 @@ pub enum DeviceClass {
     Phone(PhoneDeviceClass),
 
     /// The parameter is the amount of utilisation the access point currently has, expressed as a
-    /// fraction.
+    /// fraction. A utilisation of `1.0` means that no service is available.
+    ///
+    /// The utilisation is encoded as a 3-bit load factor (see
+    /// [`DeviceClass::access_point`]), so it is rounded to the nearest of
+    /// eight levels when it is converted to bits.
     AccessPoint(f64),
     AudioVideo(AudioVideoDeviceClass),
     Peripheral {
@@ -145,6 +149,150 @@ pub enum HealthDeviceClass {
     Unknown,
 }
 
+/// A Class of Device value, as sent over the air and by the kernel. Unlike a
+/// [`DeviceClass`], this keeps all 24 bits, including minor classes and
+/// reserved bits which this library does not know about, so it can be
+/// converted back to bits without losing anything.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct ClassOfDevice(u32);
+
+impl ClassOfDevice {
+    pub fn new(device_class: DeviceClass, service_classes: ServiceClasses) -> Self {
+        let device_bits: u16 = device_class.into();
+        Self(device_bits as u32 | service_classes.bits())
+    }
+
+    /// Creates a Class of Device from its bits. Bits above the 24th are
+    /// ignored.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits & 0x00FF_FFFF)
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Self {
+        Self(bytes[0] as u32 | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16))
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// The bytes of this Class of Device, in the little-endian order that is
+    /// used by HCI and the management API.
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let bytes = self.0.to_le_bytes();
+        [bytes[0], bytes[1], bytes[2]]
+    }
+
+    /// The major device class, in bits 8-12.
+    pub fn major(&self) -> u8 {
+        ((self.0 >> 8) & 0b11111) as u8
+    }
+
+    /// The minor device class, in bits 2-7.
+    pub fn minor(&self) -> u8 {
+        ((self.0 >> 2) & 0b111111) as u8
+    }
+
+    pub fn device_class(&self) -> DeviceClass {
+        device_class_from_u32(self.0).0
+    }
+
+    pub fn service_classes(&self) -> ServiceClasses {
+        ServiceClasses::from_bits_truncate(self.0)
+    }
+
+    /// Returns a copy of this Class of Device with the given device class,
+    /// keeping the service classes.
+    pub fn with_device_class(self, device_class: DeviceClass) -> Self {
+        let device_bits: u16 = device_class.into();
+        Self((self.0 & !0x1FFC) | device_bits as u32)
+    }
+
+    /// Returns a copy of this Class of Device with the given service classes,
+    /// keeping the device class.
+    pub fn with_service_classes(self, service_classes: ServiceClasses) -> Self {
+        Self((self.0 & 0x1FFF) | service_classes.bits())
+    }
+}
+
+impl From<u32> for ClassOfDevice {
+    fn from(bits: u32) -> Self {
+        Self::from_bits(bits)
+    }
+}
+
+impl From<ClassOfDevice> for u32 {
+    fn from(class: ClassOfDevice) -> Self {
+        class.0
+    }
+}
+
+impl From<DeviceClass> for ClassOfDevice {
+    fn from(device_class: DeviceClass) -> Self {
+        Self::new(device_class, ServiceClasses::empty())
+    }
+}
+
+impl From<ClassOfDevice> for (DeviceClass, ServiceClasses) {
+    fn from(class: ClassOfDevice) -> Self {
+        device_class_from_u32(class.0)
+    }
+}
+
+/// Builds a [`ClassOfDevice`] from a device class and service classes, for
+/// use with [`set_device_class`](crate::management::set_device_class).
+///
+/// For example, `CodBuilder::new().device_class(DeviceClass::headphones())
+/// .service_class(ServiceClass::Audio).build()` describes headphones. Note
+/// that the kernel derives the service classes of a controller from the
+/// hints passed to [`add_uuid`](crate::management::add_uuid), which can be
+/// taken from [`ClassOfDevice::service_classes`].
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct CodBuilder {
+    class: ClassOfDevice,
+}
+
+impl CodBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn device_class(mut self, device_class: DeviceClass) -> Self {
+        self.class = self.class.with_device_class(device_class);
+        self
+    }
+
+    /// Adds a service class to the ones that were already set.
+    pub fn service_class(mut self, service_class: ServiceClass) -> Self {
+        self.class = self
+            .class
+            .with_service_classes(self.class.service_classes() | service_class);
+        self
+    }
+
+    /// Replaces the service classes.
+    pub fn service_classes(mut self, service_classes: ServiceClasses) -> Self {
+        self.class = self.class.with_service_classes(service_classes);
+        self
+    }
+
+    pub fn build(self) -> ClassOfDevice {
+        self.class
+    }
+}
+
+impl From<CodBuilder> for ClassOfDevice {
+    fn from(builder: CodBuilder) -> Self {
+        builder.build()
+    }
+}
+
+pub(crate) fn class_of_device_from_buf<B: Buf>(class: &mut B) -> ClassOfDevice {
+    let mut bytes = [0u8; 3];
+    class.copy_to_slice(&mut bytes[..]);
+    ClassOfDevice::from_bytes(bytes)
+}
+
 pub fn device_class_from_bytes(class: Bytes) -> (DeviceClass, ServiceClasses) {
     let bits = class[0] as u32 | ((class[1] as u32) << 8) | ((class[2] as u32) << 16);
     device_class_from_u32(bits)
@@ -156,6 +304,106 @@ pub fn device_class_from_buf<B: Buf>(class: &mut B) -> (DeviceClass, ServiceClas
     device_class_from_array(items)
 }
 
+impl DeviceClass {
+    pub fn desktop() -> Self {
+        DeviceClass::Computer(ComputerDeviceClass::Desktop)
+    }
+
+    pub fn laptop() -> Self {
+        DeviceClass::Computer(ComputerDeviceClass::Laptop)
+    }
+
+    pub fn tablet() -> Self {
+        DeviceClass::Computer(ComputerDeviceClass::Tablet)
+    }
+
+    pub fn smartphone() -> Self {
+        DeviceClass::Phone(PhoneDeviceClass::Smartphone)
+    }
+
+    pub fn headset() -> Self {
+        DeviceClass::AudioVideo(AudioVideoDeviceClass::Headset)
+    }
+
+    pub fn headphones() -> Self {
+        DeviceClass::AudioVideo(AudioVideoDeviceClass::Headphones)
+    }
+
+    pub fn hands_free() -> Self {
+        DeviceClass::AudioVideo(AudioVideoDeviceClass::HandsFree)
+    }
+
+    pub fn loudspeaker() -> Self {
+        DeviceClass::AudioVideo(AudioVideoDeviceClass::Loudspeaker)
+    }
+
+    pub fn keyboard() -> Self {
+        DeviceClass::Peripheral {
+            keyboard: true,
+            pointer: false,
+            class: PeripheralDeviceClass::Uncategorized,
+        }
+    }
+
+    /// A pointing device, such as a mouse.
+    pub fn mouse() -> Self {
+        DeviceClass::Peripheral {
+            keyboard: false,
+            pointer: true,
+            class: PeripheralDeviceClass::Uncategorized,
+        }
+    }
+
+    pub fn gamepad() -> Self {
+        DeviceClass::Peripheral {
+            keyboard: false,
+            pointer: false,
+            class: PeripheralDeviceClass::Gamepad,
+        }
+    }
+
+    pub fn wristwatch() -> Self {
+        DeviceClass::Wearable(WearableDeviceClass::Wristwatch)
+    }
+
+    pub fn printer() -> Self {
+        DeviceClass::Imaging {
+            display: false,
+            camera: false,
+            scanner: false,
+            printer: true,
+        }
+    }
+
+    /// Creates a LAN/network access point device class from a 3-bit load
+    /// factor: 0 means fully available, 1-6 each cover a sixth of the
+    /// capacity being used, and 7 means that no service is available. Higher
+    /// bits are ignored.
+    pub fn access_point(load_factor: u8) -> Self {
+        DeviceClass::AccessPoint(match load_factor & 0b111 {
+            0 => 0.,
+            7 => 1.,
+            // the middle of the range covered by the load factor
+            n => (n as f64 - 0.5) / 6.,
+        })
+    }
+
+    /// Returns the 3-bit load factor of an access point, or `None` if this is
+    /// not an access point.
+    pub fn load_factor(&self) -> Option<u8> {
+        match *self {
+            DeviceClass::AccessPoint(utilisation) => Some(if utilisation <= 0. {
+                0
+            } else if utilisation >= 1. {
+                7
+            } else {
+                ((utilisation * 6.).ceil() as u8).clamp(1, 6)
+            }),
+            _ => None,
+        }
+    }
+}
+
 pub fn device_class_from_array(class: [u8; 3]) -> (DeviceClass, ServiceClasses) {
     let bits = class[0] as u32 | ((class[1] as u32) << 8) | ((class[2] as u32) << 16);
     device_class_from_u32(bits)
@@ -189,7 +437,8 @@ pub fn device_class_from_u32(class: u32) -> (DeviceClass, ServiceClasses) {
             0b000101 => PhoneDeviceClass::ISDN,
             _ => PhoneDeviceClass::Unknown,
         }),
-        0b00011 => DeviceClass::AccessPoint(0.),
+        // load factor in bits 5-7, bits 2-4 are reserved
+        0b00011 => DeviceClass::access_point(class_bits[5..8].load::<u8>()),
         0b00100 => DeviceClass::AudioVideo(match class_bits[2..8].load::<u8>() {
             0b000001 => AudioVideoDeviceClass::Headset,
             0b000010 => AudioVideoDeviceClass::HandsFree,
@@ -305,8 +554,8 @@ impl From<DeviceClass> for u16 {
                 }
             }
             DeviceClass::AccessPoint(..) => {
-                // bits |= 0b00011 << 8;
-                unimplemented!()
+                bits |= 0b00011 << 8;
+                bits |= (val.load_factor().unwrap() as u16) << 5;
             }
             DeviceClass::AudioVideo(minor) => {
                 bits |= 0b00100 << 8;
@@ -447,4 +696,64 @@ mod tests {
         let (c1, _) = device_class_from_u32(b as u32);
         assert_eq!(c, c1);
     }
+
+    #[test]
+    pub fn class_of_device_round_trip() {
+        for bits in (0..0x00FF_FFFF).step_by(0x1F3) {
+            let class = ClassOfDevice::from_bits(bits);
+            assert_eq!(ClassOfDevice::from_bytes(class.to_bytes()), class);
+            assert_eq!(class.bits(), bits);
+        }
+
+        let class = ClassOfDevice::new(
+            DeviceClass::Computer(ComputerDeviceClass::Laptop),
+            ServiceClass::Audio | ServiceClass::Networking,
+        );
+        assert_eq!(class.bits(), 0x22010C);
+        assert_eq!(
+            class.device_class(),
+            DeviceClass::Computer(ComputerDeviceClass::Laptop)
+        );
+
+        let class = class.with_device_class(DeviceClass::Phone(PhoneDeviceClass::Smartphone));
+        assert_eq!(class.bits(), 0x22020C);
+    }
+
+    #[test]
+    pub fn access_point_load_factor() {
+        for load_factor in 0..8 {
+            let class = DeviceClass::access_point(load_factor);
+            assert_eq!(class.load_factor(), Some(load_factor));
+
+            let bits: u16 = class.into();
+            assert_eq!(bits, 0x0300 | (load_factor as u16) << 5);
+            assert_eq!(device_class_from_u32(bits as u32).0, class);
+        }
+
+        // the reserved minor bits do not change the utilisation
+        assert_eq!(
+            device_class_from_u32(0x0300 | 0b111 << 2).0,
+            DeviceClass::access_point(0)
+        );
+
+        assert_eq!(DeviceClass::AccessPoint(0.1).load_factor(), Some(1));
+        assert_eq!(DeviceClass::AccessPoint(0.5).load_factor(), Some(3));
+        assert_eq!(DeviceClass::AccessPoint(0.99).load_factor(), Some(6));
+    }
+
+    #[test]
+    pub fn cod_builder() {
+        let class = CodBuilder::new()
+            .device_class(DeviceClass::headphones())
+            .service_class(ServiceClass::Audio)
+            .service_class(ServiceClass::Rendering)
+            .build();
+
+        assert_eq!(class.bits(), 0x240418);
+        assert_eq!(class.device_class(), DeviceClass::headphones());
+        assert_eq!(
+            class.service_classes(),
+            ServiceClass::Audio | ServiceClass::Rendering
+        );
+    }
 }
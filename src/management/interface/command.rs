@@ -1,35 +1,148 @@
 use std::fmt;
 
-#[repr(u8)]
-#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug)]
+/// The status of a command. Statuses which this library does not know about
+/// are kept in [`CommandStatus::Other`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum CommandStatus {
-    Success = 0x00,
-    UnknownCommand = 0x01,
-    NotConnected = 0x02,
-    Failed = 0x03,
-    ConnectFailed = 0x04,
-    AuthenticationFailed = 0x05,
-    NotPaired = 0x06,
-    NoResources = 0x07,
-    Timeout = 0x08,
-    AlreadyConnected = 0x09,
-    Busy = 0x0A,
-    Rejected = 0x0B,
-    NotSupported = 0x0C,
-    InvalidParams = 0x0D,
-    Disconnected = 0x0E,
-    NotPowered = 0x0F,
-    Cancelled = 0x10,
-    InvalidIndex = 0x11,
-    RFKilled = 0x12,
-    AlreadyPaired = 0x13,
-    PermissionDenied = 0x14,
+    Success,
+    UnknownCommand,
+    NotConnected,
+    Failed,
+    ConnectFailed,
+    AuthenticationFailed,
+    NotPaired,
+    NoResources,
+    Timeout,
+    AlreadyConnected,
+    Busy,
+    Rejected,
+    NotSupported,
+    InvalidParams,
+    Disconnected,
+    NotPowered,
+    Cancelled,
+    InvalidIndex,
+    RFKilled,
+    AlreadyPaired,
+    PermissionDenied,
+    Other(u8),
 }
 
-#[repr(u16)]
-#[derive(Eq, PartialEq, FromPrimitive, ToPrimitive, Copy, Clone, Debug)]
+impl From<u8> for CommandStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            0x00 => CommandStatus::Success,
+            0x01 => CommandStatus::UnknownCommand,
+            0x02 => CommandStatus::NotConnected,
+            0x03 => CommandStatus::Failed,
+            0x04 => CommandStatus::ConnectFailed,
+            0x05 => CommandStatus::AuthenticationFailed,
+            0x06 => CommandStatus::NotPaired,
+            0x07 => CommandStatus::NoResources,
+            0x08 => CommandStatus::Timeout,
+            0x09 => CommandStatus::AlreadyConnected,
+            0x0A => CommandStatus::Busy,
+            0x0B => CommandStatus::Rejected,
+            0x0C => CommandStatus::NotSupported,
+            0x0D => CommandStatus::InvalidParams,
+            0x0E => CommandStatus::Disconnected,
+            0x0F => CommandStatus::NotPowered,
+            0x10 => CommandStatus::Cancelled,
+            0x11 => CommandStatus::InvalidIndex,
+            0x12 => CommandStatus::RFKilled,
+            0x13 => CommandStatus::AlreadyPaired,
+            0x14 => CommandStatus::PermissionDenied,
+            status => CommandStatus::Other(status),
+        }
+    }
+}
+
+impl From<CommandStatus> for u8 {
+    fn from(status: CommandStatus) -> Self {
+        match status {
+            CommandStatus::Success => 0x00,
+            CommandStatus::UnknownCommand => 0x01,
+            CommandStatus::NotConnected => 0x02,
+            CommandStatus::Failed => 0x03,
+            CommandStatus::ConnectFailed => 0x04,
+            CommandStatus::AuthenticationFailed => 0x05,
+            CommandStatus::NotPaired => 0x06,
+            CommandStatus::NoResources => 0x07,
+            CommandStatus::Timeout => 0x08,
+            CommandStatus::AlreadyConnected => 0x09,
+            CommandStatus::Busy => 0x0A,
+            CommandStatus::Rejected => 0x0B,
+            CommandStatus::NotSupported => 0x0C,
+            CommandStatus::InvalidParams => 0x0D,
+            CommandStatus::Disconnected => 0x0E,
+            CommandStatus::NotPowered => 0x0F,
+            CommandStatus::Cancelled => 0x10,
+            CommandStatus::InvalidIndex => 0x11,
+            CommandStatus::RFKilled => 0x12,
+            CommandStatus::AlreadyPaired => 0x13,
+            CommandStatus::PermissionDenied => 0x14,
+            CommandStatus::Other(status) => status,
+        }
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CommandStatus::Success => "success",
+            CommandStatus::UnknownCommand => "unknown command",
+            CommandStatus::NotConnected => "not connected",
+            CommandStatus::Failed => "failed",
+            CommandStatus::ConnectFailed => "connect failed",
+            CommandStatus::AuthenticationFailed => "authentication failed",
+            CommandStatus::NotPaired => "not paired",
+            CommandStatus::NoResources => "no resources",
+            CommandStatus::Timeout => "timeout",
+            CommandStatus::AlreadyConnected => "already connected",
+            CommandStatus::Busy => "busy",
+            CommandStatus::Rejected => "rejected",
+            CommandStatus::NotSupported => "not supported",
+            CommandStatus::InvalidParams => "invalid parameters",
+            CommandStatus::Disconnected => "disconnected",
+            CommandStatus::NotPowered => "not powered",
+            CommandStatus::Cancelled => "cancelled",
+            CommandStatus::InvalidIndex => "invalid index",
+            CommandStatus::RFKilled => "blocked through rfkill",
+            CommandStatus::AlreadyPaired => "already paired",
+            CommandStatus::PermissionDenied => "permission denied",
+            CommandStatus::Other(value) => return write!(f, "unknown status 0x{:02x}", value),
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl CommandStatus {
+    /// Whether a command that failed with this status may succeed if it is
+    /// sent again later without changing anything, for example because the
+    /// controller was busy. Statuses such as `NotSupported` or
+    /// `InvalidParams` are permanent.
+    ///
+    /// `NotPowered` is considered transient because the controller is often
+    /// being powered on by someone else while the command is sent.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            CommandStatus::Busy
+                | CommandStatus::NoResources
+                | CommandStatus::Timeout
+                | CommandStatus::NotPowered
+        )
+    }
+}
+
+/// A command of the management API. Opcodes which this library does not
+/// know about are kept in [`Command::Other`].
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Hash)]
+#[non_exhaustive]
 pub enum Command {
-    ReadVersionInfo = 0x0001,
+    ReadVersionInfo,
     ReadSupportedCommands,
     ReadControllerIndexList,
     ReadControllerInfo,
@@ -112,10 +225,202 @@ pub enum Command {
     ReadAdvertisementMonitorFeatures,
     AddAdvertisementPatternsMonitor,
     RemoveAdvertisementMonitor,
+    Other(u16),
+}
+
+impl From<u16> for Command {
+    fn from(opcode: u16) -> Self {
+        match opcode {
+            0x0001 => Command::ReadVersionInfo,
+            0x0002 => Command::ReadSupportedCommands,
+            0x0003 => Command::ReadControllerIndexList,
+            0x0004 => Command::ReadControllerInfo,
+            0x0005 => Command::SetPowered,
+            0x0006 => Command::SetDiscoverable,
+            0x0007 => Command::SetConnectable,
+            0x0008 => Command::SetFastConnectable,
+            0x0009 => Command::SetPairable,
+            0x000A => Command::SetLinkSecurity,
+            0x000B => Command::SetSecureSimplePairing,
+            0x000C => Command::SetHighSpeed,
+            0x000D => Command::SetLowEnergy,
+            0x000E => Command::SetDeviceClass,
+            0x000F => Command::SetLocalName,
+            0x0010 => Command::AddUUID,
+            0x0011 => Command::RemoveUUID,
+            0x0012 => Command::LoadLinkKeys,
+            0x0013 => Command::LoadLongTermKeys,
+            0x0014 => Command::Disconnect,
+            0x0015 => Command::GetConnections,
+            0x0016 => Command::PinCodeReply,
+            0x0017 => Command::PinCodeNegativeReply,
+            0x0018 => Command::SetIOCapability,
+            0x0019 => Command::PairDevice,
+            0x001A => Command::CancelPairDevice,
+            0x001B => Command::UnpairDevice,
+            0x001C => Command::UserConfirmationReply,
+            0x001D => Command::UserConfirmationNegativeReply,
+            0x001E => Command::UserPasskeyReply,
+            0x001F => Command::UserPasskeyNegativeReply,
+            0x0020 => Command::ReadLocalOutOfBand,
+            0x0021 => Command::AddRemoteOutOfBand,
+            0x0022 => Command::RemoveRemoteOutOfBand,
+            0x0023 => Command::StartDiscovery,
+            0x0024 => Command::StopDiscovery,
+            0x0025 => Command::ConfirmName,
+            0x0026 => Command::BlockDevice,
+            0x0027 => Command::UnblockDevice,
+            0x0028 => Command::SetDeviceID,
+            0x0029 => Command::SetAdvertising,
+            0x002A => Command::SetBREDR,
+            0x002B => Command::SetStaticAddress,
+            0x002C => Command::SetScanParameters,
+            0x002D => Command::SetSecureConnections,
+            0x002E => Command::SetDebugKeys,
+            0x002F => Command::SetPrivacy,
+            0x0030 => Command::LoadIdentityResolvingKeys,
+            0x0031 => Command::GetConnectionInfo,
+            0x0032 => Command::GetClockInfo,
+            0x0033 => Command::AddDevice,
+            0x0034 => Command::RemoveDevice,
+            0x0035 => Command::LoadConnectionParameters,
+            0x0036 => Command::ReadUnconfiguredControllerIndexList,
+            0x0037 => Command::ReadControllerConfigInfo,
+            0x0038 => Command::SetExternalConfig,
+            0x0039 => Command::SetPublicAddress,
+            0x003A => Command::StartServiceDiscovery,
+            0x003B => Command::ReadLocalOutOfBandExtended,
+            0x003C => Command::ReadExtendedControllerIndexList,
+            0x003D => Command::ReadAdvertisingFeatures,
+            0x003E => Command::AddAdvertising,
+            0x003F => Command::RemoveAdvertising,
+            0x0040 => Command::GetAdvertisingSizeInfo,
+            0x0041 => Command::StartLimitedDiscovery,
+            0x0042 => Command::ReadExtendedControllerInfo,
+            0x0043 => Command::SetAppearance,
+            0x0044 => Command::GetPhyConfig,
+            0x0045 => Command::SetPhyConfig,
+            0x0046 => Command::LoadBlockedKeys,
+            0x0047 => Command::SetWidebandSpeech,
+            0x0048 => Command::ReadSecurityInfo,
+            0x0049 => Command::ReadExperimentalFeaturesInfo,
+            0x004A => Command::SetExperimentalFeature,
+            0x004B => Command::ReadDefaultSystemConfig,
+            0x004C => Command::SetDefaultSystemConfig,
+            0x004D => Command::ReadDefaultRuntimeConfig,
+            0x004E => Command::SetDefaultRuntimeConfig,
+            0x004F => Command::GetDeviceFlags,
+            0x0050 => Command::SetDeviceFlags,
+            0x0051 => Command::ReadAdvertisementMonitorFeatures,
+            0x0052 => Command::AddAdvertisementPatternsMonitor,
+            0x0053 => Command::RemoveAdvertisementMonitor,
+            opcode => Command::Other(opcode),
+        }
+    }
+}
+
+impl From<Command> for u16 {
+    fn from(opcode: Command) -> Self {
+        match opcode {
+            Command::ReadVersionInfo => 0x0001,
+            Command::ReadSupportedCommands => 0x0002,
+            Command::ReadControllerIndexList => 0x0003,
+            Command::ReadControllerInfo => 0x0004,
+            Command::SetPowered => 0x0005,
+            Command::SetDiscoverable => 0x0006,
+            Command::SetConnectable => 0x0007,
+            Command::SetFastConnectable => 0x0008,
+            Command::SetPairable => 0x0009,
+            Command::SetLinkSecurity => 0x000A,
+            Command::SetSecureSimplePairing => 0x000B,
+            Command::SetHighSpeed => 0x000C,
+            Command::SetLowEnergy => 0x000D,
+            Command::SetDeviceClass => 0x000E,
+            Command::SetLocalName => 0x000F,
+            Command::AddUUID => 0x0010,
+            Command::RemoveUUID => 0x0011,
+            Command::LoadLinkKeys => 0x0012,
+            Command::LoadLongTermKeys => 0x0013,
+            Command::Disconnect => 0x0014,
+            Command::GetConnections => 0x0015,
+            Command::PinCodeReply => 0x0016,
+            Command::PinCodeNegativeReply => 0x0017,
+            Command::SetIOCapability => 0x0018,
+            Command::PairDevice => 0x0019,
+            Command::CancelPairDevice => 0x001A,
+            Command::UnpairDevice => 0x001B,
+            Command::UserConfirmationReply => 0x001C,
+            Command::UserConfirmationNegativeReply => 0x001D,
+            Command::UserPasskeyReply => 0x001E,
+            Command::UserPasskeyNegativeReply => 0x001F,
+            Command::ReadLocalOutOfBand => 0x0020,
+            Command::AddRemoteOutOfBand => 0x0021,
+            Command::RemoveRemoteOutOfBand => 0x0022,
+            Command::StartDiscovery => 0x0023,
+            Command::StopDiscovery => 0x0024,
+            Command::ConfirmName => 0x0025,
+            Command::BlockDevice => 0x0026,
+            Command::UnblockDevice => 0x0027,
+            Command::SetDeviceID => 0x0028,
+            Command::SetAdvertising => 0x0029,
+            Command::SetBREDR => 0x002A,
+            Command::SetStaticAddress => 0x002B,
+            Command::SetScanParameters => 0x002C,
+            Command::SetSecureConnections => 0x002D,
+            Command::SetDebugKeys => 0x002E,
+            Command::SetPrivacy => 0x002F,
+            Command::LoadIdentityResolvingKeys => 0x0030,
+            Command::GetConnectionInfo => 0x0031,
+            Command::GetClockInfo => 0x0032,
+            Command::AddDevice => 0x0033,
+            Command::RemoveDevice => 0x0034,
+            Command::LoadConnectionParameters => 0x0035,
+            Command::ReadUnconfiguredControllerIndexList => 0x0036,
+            Command::ReadControllerConfigInfo => 0x0037,
+            Command::SetExternalConfig => 0x0038,
+            Command::SetPublicAddress => 0x0039,
+            Command::StartServiceDiscovery => 0x003A,
+            Command::ReadLocalOutOfBandExtended => 0x003B,
+            Command::ReadExtendedControllerIndexList => 0x003C,
+            Command::ReadAdvertisingFeatures => 0x003D,
+            Command::AddAdvertising => 0x003E,
+            Command::RemoveAdvertising => 0x003F,
+            Command::GetAdvertisingSizeInfo => 0x0040,
+            Command::StartLimitedDiscovery => 0x0041,
+            Command::ReadExtendedControllerInfo => 0x0042,
+            Command::SetAppearance => 0x0043,
+            Command::GetPhyConfig => 0x0044,
+            Command::SetPhyConfig => 0x0045,
+            Command::LoadBlockedKeys => 0x0046,
+            Command::SetWidebandSpeech => 0x0047,
+            Command::ReadSecurityInfo => 0x0048,
+            Command::ReadExperimentalFeaturesInfo => 0x0049,
+            Command::SetExperimentalFeature => 0x004A,
+            Command::ReadDefaultSystemConfig => 0x004B,
+            Command::SetDefaultSystemConfig => 0x004C,
+            Command::ReadDefaultRuntimeConfig => 0x004D,
+            Command::SetDefaultRuntimeConfig => 0x004E,
+            Command::GetDeviceFlags => 0x004F,
+            Command::SetDeviceFlags => 0x0050,
+            Command::ReadAdvertisementMonitorFeatures => 0x0051,
+            Command::AddAdvertisementPatternsMonitor => 0x0052,
+            Command::RemoveAdvertisementMonitor => 0x0053,
+            Command::Other(opcode) => opcode,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Other(opcode) => write!(f, "unknown command 0x{:04x}", opcode),
+            command => fmt::Debug::fmt(command, f),
+        }
+    }
 }
 
 impl fmt::LowerHex for CommandStatus {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{:x}", *self as u8)
+        write!(f, "{:x}", u8::from(*self))
     }
 }
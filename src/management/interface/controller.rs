@@ -1,13 +1,13 @@
-use std::ffi::CString;
 use std::fmt::{Display, Formatter};
 
 use bytes::Bytes;
 use enumflags2::{bitflags, BitFlags};
 
-use crate::management::interface::class::{DeviceClass, ServiceClasses};
+use crate::management::interface::class::ClassOfDevice;
+use crate::management::interface::eir::EirData;
 use crate::Address;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct Controller(pub(crate) u16);
 
 impl Display for Controller {
@@ -22,6 +22,13 @@ impl From<Controller> for u16 {
     }
 }
 
+/// Refers to the controller with the given index, such as 0 for `hci0`.
+impl From<u16> for Controller {
+    fn from(index: u16) -> Self {
+        Controller(index)
+    }
+}
+
 impl Controller {
     pub fn none() -> Controller {
         Controller(0xFFFF)
@@ -35,9 +42,13 @@ pub struct ControllerInfo {
     pub manufacturer: u16,
     pub supported_settings: ControllerSettings,
     pub current_settings: ControllerSettings,
-    pub class_of_device: (DeviceClass, ServiceClasses),
-    pub name: CString,
-    pub short_name: CString,
+    pub class_of_device: ClassOfDevice,
+    /// The name of the controller. Invalid UTF-8 is replaced with U+FFFD;
+    /// the name as it was received is in `name_bytes`.
+    pub name: String,
+    pub short_name: String,
+    pub name_bytes: Bytes,
+    pub short_name_bytes: Bytes,
 }
 
 pub struct ControllerInfoExt {
@@ -58,6 +69,37 @@ pub struct ControllerInfoExt {
     pub eir_data: Bytes,
 }
 
+impl ControllerInfoExt {
+    /// Parses `eir_data` into its structures, such as the local name,
+    /// appearance and device ID.
+    pub fn eir(&self) -> EirData {
+        EirData::parse(&self.eir_data)
+    }
+
+    /// Converts this information into the form that is returned by
+    /// [`get_controller_info`](crate::management::get_controller_info), taking
+    /// the class of device and names from `eir_data`. Values that are missing
+    /// from `eir_data` are left empty; for example, LE-only controllers have a
+    /// class of device of zero.
+    pub fn parsed(&self) -> ControllerInfo {
+        let eir = self.eir();
+
+        ControllerInfo {
+            address: self.address,
+            bluetooth_version: self.bluetooth_version,
+            manufacturer: self.manufacturer,
+            supported_settings: self.supported_settings,
+            current_settings: self.current_settings,
+            class_of_device: eir.class_of_device.unwrap_or_default(),
+            name: eir.local_name.unwrap_or_default(),
+            short_name: eir.short_name.unwrap_or_default(),
+            name_bytes: EirData::find(&self.eir_data, EirData::COMPLETE_NAME).unwrap_or_default(),
+            short_name_bytes: EirData::find(&self.eir_data, EirData::SHORT_NAME)
+                .unwrap_or_default(),
+        }
+    }
+}
+
 #[bitflags]
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u32)]
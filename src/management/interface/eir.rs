@@ -0,0 +1,186 @@
+use bytes::{Buf, Bytes};
+
+use super::class::{class_of_device_from_buf, ClassOfDevice};
+use crate::{Uuid, Uuid128, Uuid16, Uuid32};
+
+/// The Device ID record of a device, as defined in the Device ID profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    /// `0x0001` if `vendor` was assigned by the Bluetooth SIG, `0x0002` if
+    /// it was assigned by the USB Implementer's Forum.
+    pub source: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// Extended Inquiry Response (EIR) or advertising data, split into its
+/// structures.
+///
+/// The structures which this library knows about are parsed into the typed
+/// fields; all other structures are kept in `other`, keyed by their data type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EirData {
+    pub flags: Option<u8>,
+    pub uuids: Vec<Uuid>,
+    /// The complete local name.
+    pub local_name: Option<String>,
+    /// The shortened local name, which is used if the complete name does not
+    /// fit.
+    pub short_name: Option<String>,
+    pub tx_power: Option<i8>,
+    /// The class of device, which is only present for BR/EDR devices.
+    pub class_of_device: Option<ClassOfDevice>,
+    pub device_id: Option<DeviceId>,
+    pub appearance: Option<u16>,
+    /// Manufacturer specific data, keyed by company identifier.
+    pub manufacturer_data: Vec<(u16, Bytes)>,
+    pub other: Vec<(u8, Bytes)>,
+}
+
+impl EirData {
+    pub const FLAGS: u8 = 0x01;
+    pub const INCOMPLETE_UUID16: u8 = 0x02;
+    pub const COMPLETE_UUID16: u8 = 0x03;
+    pub const INCOMPLETE_UUID32: u8 = 0x04;
+    pub const COMPLETE_UUID32: u8 = 0x05;
+    pub const INCOMPLETE_UUID128: u8 = 0x06;
+    pub const COMPLETE_UUID128: u8 = 0x07;
+    pub const SHORT_NAME: u8 = 0x08;
+    pub const COMPLETE_NAME: u8 = 0x09;
+    pub const TX_POWER: u8 = 0x0A;
+    pub const CLASS_OF_DEVICE: u8 = 0x0D;
+    pub const DEVICE_ID: u8 = 0x10;
+    pub const APPEARANCE: u8 = 0x19;
+    pub const MANUFACTURER_DATA: u8 = 0xFF;
+
+    /// Parses EIR data. Parsing stops at the first structure with a length of
+    /// zero (which marks the start of padding) or at a structure which is
+    /// truncated.
+    pub fn parse(data: &Bytes) -> Self {
+        let mut eir = EirData::default();
+
+        for (data_type, mut value) in Self::structures(data) {
+            match data_type {
+                Self::FLAGS if !value.is_empty() => eir.flags = Some(value.get_u8()),
+                Self::INCOMPLETE_UUID16 | Self::COMPLETE_UUID16 => {
+                    while value.remaining() >= 2 {
+                        eir.uuids.push(Uuid16(value.get_u16_le()).into());
+                    }
+                }
+                Self::INCOMPLETE_UUID32 | Self::COMPLETE_UUID32 => {
+                    while value.remaining() >= 4 {
+                        eir.uuids.push(Uuid32(value.get_u32_le()).into());
+                    }
+                }
+                Self::INCOMPLETE_UUID128 | Self::COMPLETE_UUID128 => {
+                    while value.remaining() >= 16 {
+                        eir.uuids.push(Uuid128(value.get_u128_le()).into());
+                    }
+                }
+                Self::SHORT_NAME => {
+                    eir.short_name = Some(String::from_utf8_lossy(&value[..]).into_owned())
+                }
+                Self::COMPLETE_NAME => {
+                    eir.local_name = Some(String::from_utf8_lossy(&value[..]).into_owned())
+                }
+                Self::TX_POWER if !value.is_empty() => eir.tx_power = Some(value.get_i8()),
+                Self::CLASS_OF_DEVICE if value.len() >= 3 => {
+                    eir.class_of_device = Some(class_of_device_from_buf(&mut value))
+                }
+                Self::DEVICE_ID if value.len() >= 8 => {
+                    eir.device_id = Some(DeviceId {
+                        source: value.get_u16_le(),
+                        vendor: value.get_u16_le(),
+                        product: value.get_u16_le(),
+                        version: value.get_u16_le(),
+                    })
+                }
+                Self::APPEARANCE if value.len() >= 2 => eir.appearance = Some(value.get_u16_le()),
+                Self::MANUFACTURER_DATA if value.len() >= 2 => {
+                    let company = value.get_u16_le();
+                    eir.manufacturer_data.push((company, value));
+                }
+                _ => eir.other.push((data_type, value)),
+            }
+        }
+
+        eir
+    }
+
+    /// The value of the first structure of `data_type` in `data`, as it was
+    /// received. This is useful for the names, which are not guaranteed to be
+    /// valid UTF-8 and are converted lossily by [`EirData::parse`].
+    pub fn find(data: &Bytes, data_type: u8) -> Option<Bytes> {
+        Self::structures(data)
+            .find(|(found, _)| *found == data_type)
+            .map(|(_, value)| value)
+    }
+
+    /// Splits EIR data into its structures, with the same rules as
+    /// [`EirData::parse`].
+    fn structures(data: &Bytes) -> impl Iterator<Item = (u8, Bytes)> {
+        let mut buf = data.clone();
+
+        std::iter::from_fn(move || {
+            let len = *buf.first()? as usize;
+
+            if len == 0 || buf.remaining() <= len {
+                return None;
+            }
+
+            buf.advance(1);
+            let data_type = buf.get_u8();
+            Some((data_type, buf.split_to(len - 1)))
+        })
+    }
+
+    /// Returns the complete local name if there is one, and the shortened
+    /// local name otherwise.
+    pub fn name(&self) -> Option<&str> {
+        self.local_name.as_deref().or(self.short_name.as_deref())
+    }
+}
+
+impl From<&Bytes> for EirData {
+    fn from(data: &Bytes) -> Self {
+        Self::parse(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn eir() {
+        let data = Bytes::from_static(&[
+            0x05, 0x09, b'h', b'o', b's', b't', // complete name
+            0x03, 0x19, 0x80, 0x00, // appearance
+            0x04, 0x0d, 0x0c, 0x01, 0x1c, // class of device
+            0x09, 0x10, 0x02, 0x00, 0x6b, 0x1d, 0x46, 0x02, 0x37, 0x05, // device id
+            0x03, 0x03, 0x0a, 0x11, // 16-bit uuids
+            0x02, 0x42, 0x01, // unknown
+            0x00, 0x00, // padding
+        ]);
+
+        let eir = EirData::parse(&data);
+        assert_eq!(eir.name(), Some("host"));
+        assert_eq!(eir.appearance, Some(0x0080));
+        assert_eq!(
+            eir.class_of_device,
+            Some(ClassOfDevice::from_bits(0x1c010c))
+        );
+        assert_eq!(
+            eir.device_id,
+            Some(DeviceId {
+                source: 0x0002,
+                vendor: 0x1d6b,
+                product: 0x0246,
+                version: 0x0537,
+            })
+        );
+        assert_eq!(eir.uuids, vec![Uuid::Uuid16(Uuid16(0x110a))]);
+        assert_eq!(eir.other, vec![(0x42, Bytes::from_static(&[0x01]))]);
+    }
+}
@@ -1,15 +1,12 @@
-use std::ffi::CString;
-
 use bytes::Bytes;
 use enumflags2::BitFlags;
 
 use crate::address::AddressType;
 use crate::management::client::*;
-use crate::management::interface::class::{DeviceClass, ServiceClasses};
+use crate::management::interface::class::ClassOfDevice;
 use crate::management::interface::controller::ControllerSettings;
-use crate::management::interface::{Command, CommandStatus};
+use crate::management::interface::{Command, CommandStatus, Passkey};
 use crate::Address;
-use std::collections::HashMap;
 
 #[derive(Debug)]
 pub enum Event {
@@ -54,13 +51,18 @@ pub enum Event {
     /// This event indicates that the Class of Device value for the
     /// controller has changed. When the controller is powered off the
     /// Class of Device value will always be reported as zero.
-    ClassOfDeviceChanged {
-        class: (DeviceClass, ServiceClasses),
-    },
+    ClassOfDeviceChanged { class: ClassOfDevice },
 
     /// This event indicates that the local name of the controller has
-    /// changed.
-    LocalNameChanged { name: CString, short_name: CString },
+    /// changed. Invalid UTF-8 in the names is replaced with U+FFFD; the
+    /// names as they were received are in `name_bytes` and
+    /// `short_name_bytes`.
+    LocalNameChanged {
+        name: String,
+        short_name: String,
+        name_bytes: Bytes,
+        short_name_bytes: Bytes,
+    },
 
     /// This event indicates that a new link key has bee generated for a
     /// remote device. The `store_hint` parameter indicates whether the
@@ -150,7 +152,7 @@ pub enum Event {
         address: Address,
         address_type: AddressType,
         confirm_hint: bool,
-        value: u32,
+        value: Passkey,
     },
 
     /// This event is used to request a passkey from user space. The
@@ -196,7 +198,7 @@ pub enum Event {
     /// devices. This discovering state can come and go multiple times
     /// between a StartDiscover and a StopDiscovery command.
     Discovering {
-        address_type: BitFlags<AddressTypeFlag>,
+        address_type: AddressTypes,
         discovering: bool,
     },
 
@@ -236,7 +238,7 @@ pub enum Event {
     PasskeyNotify {
         address: Address,
         address_type: AddressType,
-        passkey: u32,
+        passkey: Passkey,
         entered: u8,
     },
 
@@ -449,9 +451,7 @@ pub enum Event {
     ///	one through which the change was trigged. In addition it will
     ///	only be sent to sockets that have issues the Read Default System
     ///	Configuration command.
-    DefaultSystemConfigChanged {
-        params: HashMap<SystemConfigParameterType, Vec<u8>>,
-    },
+    DefaultSystemConfigChanged { params: SystemConfig },
 
     ///	This event indicates the change of default runtime parameter values.
     ///
@@ -459,7 +459,14 @@ pub enum Event {
     ///	one through which the change was trigged. In addition it will
     ///	only be sent to sockets that have issues the Read Default Runtime
     ///	Configuration command.
-    DefaultRuntimeConfigChanged {
-        params: HashMap<RuntimeConfigParameterType, Vec<u8>>,
-    },
+    DefaultRuntimeConfigChanged { params: RuntimeConfig },
+
+    /// An event that this library does not know about, which is usually
+    /// one that was added in a newer kernel. `code` is the event code and
+    /// `param` contains the parameters of the event.
+    ///
+    /// These events are only returned if the stream's
+    /// [`UnknownEventPolicy`](crate::management::UnknownEventPolicy) is
+    /// [`Forward`](crate::management::UnknownEventPolicy::Forward).
+    Unknown { code: u16, param: Bytes },
 }
@@ -1,13 +1,19 @@
+pub use self::advertising::*;
 pub use self::class::*;
 pub use self::command::*;
 pub use self::controller::*;
+pub use self::eir::*;
 pub use self::event::*;
-pub(super) use self::request::*;
+pub use self::passkey::*;
+pub use self::request::*;
 pub use self::response::*;
 
+mod advertising;
 mod class;
 mod command;
 mod controller;
+mod eir;
 mod event;
+mod passkey;
 mod request;
 mod response;
@@ -0,0 +1,113 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use crate::management::Error;
+
+/// A PIN code for legacy (pre-SSP) BR/EDR pairing, which is between 1 and 16
+/// bytes long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinCode(Vec<u8>);
+
+impl PinCode {
+    /// The maximum length of a PIN code, which is also the length of PIN
+    /// codes that are used for secure pairing.
+    pub const MAX_LEN: usize = 16;
+
+    pub fn new(pin_code: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        let pin_code = pin_code.into();
+
+        if pin_code.is_empty() {
+            return Err(Error::PinCodeEmpty);
+        }
+
+        if pin_code.len() > Self::MAX_LEN {
+            return Err(Error::PinCodeTooLong {
+                max_len: Self::MAX_LEN as u32,
+            });
+        }
+
+        Ok(Self(pin_code))
+    }
+
+    /// Whether this PIN code is long enough to be used when the kernel
+    /// requests a secure PIN code.
+    pub fn is_secure(&self) -> bool {
+        self.0.len() == Self::MAX_LEN
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl TryFrom<&str> for PinCode {
+    type Error = Error;
+
+    fn try_from(pin_code: &str) -> Result<Self, Self::Error> {
+        Self::new(pin_code)
+    }
+}
+
+impl AsRef<[u8]> for PinCode {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// A six digit passkey for Secure Simple Pairing and LE pairing, which is
+/// between 0 and 999999. Passkeys are displayed with leading zeros.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Passkey(u32);
+
+impl Passkey {
+    pub const MAX: u32 = 999_999;
+
+    pub fn new(passkey: u32) -> Result<Self, Error> {
+        if passkey > Self::MAX {
+            return Err(Error::PasskeyOutOfRange { passkey });
+        }
+
+        Ok(Self(passkey))
+    }
+
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for Passkey {
+    type Error = Error;
+
+    fn try_from(passkey: u32) -> Result<Self, Self::Error> {
+        Self::new(passkey)
+    }
+}
+
+impl From<Passkey> for u32 {
+    fn from(passkey: Passkey) -> Self {
+        passkey.0
+    }
+}
+
+impl Display for Passkey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:06}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert!(PinCode::new("").is_err());
+        assert!(PinCode::new("0000").is_ok());
+        assert!(PinCode::new([0u8; 16].to_vec()).unwrap().is_secure());
+        assert!(PinCode::new([0u8; 17].to_vec()).is_err());
+
+        assert_eq!(Passkey::new(42).unwrap().to_string(), "000042");
+        assert_eq!(Passkey::new(999_999).unwrap().to_string(), "999999");
+        assert!(Passkey::new(1_000_000).is_err());
+    }
+}
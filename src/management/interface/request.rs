@@ -2,24 +2,128 @@ use bytes::*;
 
 use crate::management::interface::command::Command;
 use crate::management::interface::controller::Controller;
+use crate::management::Error;
+
+/// The length of the header that every management message starts with.
+pub const MGMT_HEADER_LEN: usize = 6;
+
+/// The header that every message on a management socket starts with. For
+/// commands `code` is the opcode, and for events it is the event code.
+///
+/// # Bytes layout
+///
+/// ```plain
+///   Code (2 Octets)
+///   Controller Index (2 Octets)
+///   Parameter Length (2 Octets)
+/// ```
+///
+/// All fields are little endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MgmtHeader {
+    pub code: u16,
+    pub controller: Controller,
+    pub param_len: u16,
+}
+
+impl MgmtHeader {
+    /// Reads a header from the start of `buf`. Returns
+    /// [`Error::InvalidData`] if `buf` is too short.
+    pub fn parse<T: Buf>(buf: &mut T) -> Result<Self, Error> {
+        if buf.remaining() < MGMT_HEADER_LEN {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Self {
+            code: buf.get_u16_le(),
+            controller: Controller(buf.get_u16_le()),
+            param_len: buf.get_u16_le(),
+        })
+    }
+
+    pub fn encode<T: BufMut>(&self, buf: &mut T) {
+        buf.put_u16_le(self.code);
+        buf.put_u16_le(self.controller.into());
+        buf.put_u16_le(self.param_len);
+    }
+}
 
 /// A command that is ready to be sent to the management API.
-#[derive(Debug)]
+///
+/// Requests are sent with [`ManagementStream::send`], or can be encoded with
+/// [`Request::encode`] for tools that write to a socket themselves. Messages
+/// that the kernel sends back are parsed with [`Response::parse`].
+///
+/// [`ManagementStream::send`]: crate::management::ManagementStream::send
+/// [`Response::parse`]: crate::management::Response::parse
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Request {
     pub opcode: Command,
     pub controller: Controller,
     pub param: Bytes,
 }
 
+impl Request {
+    pub fn new(opcode: Command, controller: Controller, param: impl Into<Bytes>) -> Self {
+        Self {
+            opcode,
+            controller,
+            param: param.into(),
+        }
+    }
+
+    /// Encodes this request, including its header.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(MGMT_HEADER_LEN + self.param.len());
+
+        MgmtHeader {
+            code: self.opcode.into(),
+            controller: self.controller,
+            param_len: self.param.len() as u16,
+        }
+        .encode(&mut buf);
+        buf.put_slice(&self.param[..]);
+
+        buf.freeze()
+    }
+
+    /// Parses a command that was sent to a management socket, including its
+    /// header. Returns [`Error::InvalidData`] if the message is truncated or
+    /// its length does not match its header.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut buf = data;
+        let header = MgmtHeader::parse(&mut buf)?;
+
+        if buf.len() != header.param_len as usize {
+            return Err(Error::InvalidData);
+        }
+
+        Ok(Self {
+            opcode: Command::from(header.code),
+            controller: header.controller,
+            param: Bytes::copy_from_slice(buf),
+        })
+    }
+}
+
 impl From<Request> for Bytes {
     fn from(val: Request) -> Self {
-        let mut buf = BytesMut::with_capacity(6 + val.param.len());
+        val.encode()
+    }
+}
 
-        buf.put_u16_le(val.opcode as u16);
-        buf.put_u16_le(val.controller.into());
-        buf.put_u16_le(val.param.len() as u16);
-        buf.put(val.param);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        buf.freeze()
+    #[test]
+    fn round_trip() {
+        let request = Request::new(Command::SetPowered, Controller(1), vec![0x01]);
+        let buf = request.encode();
+        assert_eq!(&buf[..], &[0x05, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(Request::parse(&buf).unwrap(), request);
+
+        assert!(Request::parse(&buf[..6]).is_err());
+        assert!(Request::parse(&buf[..3]).is_err());
     }
 }
@@ -1,39 +1,107 @@
 use bytes::*;
 use enumflags2::BitFlags;
-use num_traits::FromPrimitive;
 
 use crate::management::client::ConnectionParams;
+use crate::management::interface::command::{Command, CommandStatus};
 use crate::management::interface::controller::Controller;
 use crate::management::interface::event::Event;
+use crate::management::interface::passkey::Passkey;
+use crate::management::interface::request::{MgmtHeader, MGMT_HEADER_LEN};
 use crate::management::Error;
 use crate::util::BufExt;
-use crate::Address;
+use crate::{Address, AddressType};
 
 /// A response from the BlueZ management API. This can be a response to a
 /// command that was issued, or an event that was sent in response to an outside
 /// stimulus.
+#[derive(Debug)]
 pub struct Response {
     pub event: Event,
     pub controller: Controller,
 }
 
+/// The length of the fixed-size parameters of each event, which have to be
+/// present for the event to be parsed. Variable-size parameters are checked
+/// while they are parsed.
+fn min_param_len(evt_code: u16) -> usize {
+    match evt_code {
+        0x0001 | 0x0002 => 3,
+        0x0003 => 1,
+        0x0006 => 4,
+        0x0007 => 3,
+        0x0008 => 249,
+        0x0009 => 26,
+        0x000A => 37,
+        0x000B => 13,
+        0x000C..=0x000E => 8,
+        0x000F => 12,
+        0x0010 => 7,
+        0x0011 => 8,
+        0x0012 => 14,
+        0x0013 => 2,
+        0x0014..=0x0016 => 7,
+        0x0017 => 12,
+        0x0018 => 30,
+        0x0019 => 25,
+        0x001A => 8,
+        0x001B => 7,
+        0x001C => 16,
+        0x001F => 4,
+        0x0020 | 0x0021 => 2,
+        0x0022 => 3,
+        0x0023 | 0x0024 => 1,
+        0x0025 => 2,
+        0x0026 => 4,
+        0x0027 => 20,
+        _ => 0,
+    }
+}
+
+/// Reads EIR data which is prefixed by its length.
+fn get_eir_data<T: Buf>(buf: &mut T) -> Result<Bytes, Error> {
+    let len = buf.get_u16_le() as usize;
+
+    if buf.remaining() < len {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(buf.copy_to_bytes(len))
+}
+
+/// Parses a single message that was received on a management socket,
+/// including its header. Returns [`Error::InvalidData`] if the message is
+/// truncated or its length does not match its header, instead of panicking,
+/// so this can be used on untrusted input.
+pub fn parse_mgmt_event(data: &[u8]) -> Result<Response, Error> {
+    let header = MgmtHeader::parse(&mut &data[..])?;
+
+    if data.len() != MGMT_HEADER_LEN + header.param_len as usize {
+        return Err(Error::InvalidData);
+    }
+
+    Response::parse(data)
+}
+
 impl Response {
+    /// Parses a message that was received on a management socket, including
+    /// its header. See also [`parse_mgmt_event`], which checks the length in
+    /// the header as well.
     pub fn parse<T: Buf>(mut buf: T) -> Result<Self, Error> {
-        let evt_code = buf.get_u16_le();
-        let controller = Controller(buf.get_u16_le());
-        buf.advance(2); // we already know param length
+        let header = MgmtHeader::parse(&mut buf)?;
+        let evt_code = header.code;
+        let controller = header.controller;
+
+        if buf.remaining() < min_param_len(evt_code) {
+            return Err(Error::InvalidData);
+        }
 
         Ok(Response {
             controller,
             event: match evt_code {
                 0x0001 | 0x0002 => {
-                    let opcode = buf.get_u16_le();
-                    let opcode =
-                        FromPrimitive::from_u16(opcode).ok_or(Error::UnknownOpcode { opcode })?;
+                    let opcode = Command::from(buf.get_u16_le());
 
-                    let status = buf.get_u8();
-                    let status =
-                        FromPrimitive::from_u8(status).ok_or(Error::UnknownStatus { status })?;
+                    let status = CommandStatus::from(buf.get_u8());
 
                     if evt_code == 0x0001 {
                         Event::CommandComplete {
@@ -52,31 +120,36 @@ impl Response {
                     settings: BitFlags::from_bits_truncate(buf.get_u32_le()),
                 },
                 0x0007 => Event::ClassOfDeviceChanged {
-                    class: super::device_class_from_buf(&mut buf),
+                    class: super::class_of_device_from_buf(&mut buf),
                 },
                 0x0008 => {
-                    let name = {
+                    let name_bytes = {
                         let mut arr = [0u8; 249];
                         buf.copy_to_slice(&mut arr[..]);
-                        (&arr[..]).get_c_string()
+                        (&arr[..]).get_c_bytes()
                     };
-                    let short_name = buf.get_c_string();
+                    let short_name_bytes = buf.get_c_bytes();
 
-                    Event::LocalNameChanged { name, short_name }
+                    Event::LocalNameChanged {
+                        name: String::from_utf8_lossy(&name_bytes).into_owned(),
+                        short_name: String::from_utf8_lossy(&short_name_bytes).into_owned(),
+                        name_bytes,
+                        short_name_bytes,
+                    }
                 }
                 0x0009 => Event::NewLinkKey {
                     store_hint: buf.get_bool(),
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
-                    key_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    key_type: buf.get_u8().into(),
                     value: buf.get_array_u8(),
                     pin_length: buf.get_u8(),
                 },
                 0x000A => Event::NewLongTermKey {
                     store_hint: buf.get_bool(),
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
-                    key_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    key_type: buf.get_u8().into(),
                     master: buf.get_u8(),
                     encryption_size: buf.get_u8(),
                     encryption_diversifier: buf.get_u16_le(),
@@ -85,52 +158,46 @@ impl Response {
                 },
                 0x000B => Event::DeviceConnected {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     flags: BitFlags::from_bits_truncate(buf.get_u32_le()),
-                    eir_data: {
-                        let len = buf.get_u16_le() as usize;
-                        buf.copy_to_bytes(len)
-                    },
+                    eir_data: get_eir_data(&mut buf)?,
                 },
                 0x000C => Event::DeviceDisconnected {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
-                    reason: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    reason: buf.get_u8().into(),
                 },
                 0x000D => Event::ConnectFailed {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     status: buf.get_u8(),
                 },
                 0x000E => Event::PinCodeRequest {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     secure: buf.get_bool(),
                 },
                 0x000F => Event::UserConfirmationRequest {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     confirm_hint: buf.get_bool(),
-                    value: buf.get_u32_le(),
+                    value: Passkey::new(buf.get_u32_le()).map_err(|_| Error::InvalidData)?,
                 },
                 0x0010 => Event::UserPasskeyRequest {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                 },
                 0x0011 => Event::AuthenticationFailed {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     status: buf.get_u8(),
                 },
                 0x0012 => Event::DeviceFound {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     rssi: buf.get_i8(),
                     flags: BitFlags::from_bits_truncate(buf.get_u32_le()),
-                    eir_data: {
-                        let len = buf.get_u16_le() as usize;
-                        buf.copy_to_bytes(len)
-                    },
+                    eir_data: get_eir_data(&mut buf)?,
                 },
                 0x0013 => Event::Discovering {
                     address_type: BitFlags::from_bits_truncate(buf.get_u8()),
@@ -138,50 +205,50 @@ impl Response {
                 },
                 0x0014 => Event::DeviceBlocked {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                 },
                 0x0015 => Event::DeviceUnblocked {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                 },
                 0x0016 => Event::DeviceUnpaired {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                 },
                 0x0017 => Event::PasskeyNotify {
                     address: Address::from_buf(&mut buf),
-                    address_type: FromPrimitive::from_u8(buf.get_u8()).ok_or(Error::InvalidData)?,
-                    passkey: buf.get_u32_le(),
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    passkey: Passkey::new(buf.get_u32_le()).map_err(|_| Error::InvalidData)?,
                     entered: buf.get_u8(),
                 },
                 0x0018 => Event::NewIdentityResolvingKey {
                     store_hint: buf.get_bool(),
                     random_address: buf.get_address(),
                     address: buf.get_address(),
-                    address_type: buf.get_primitive_u8(),
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                     value: buf.get_array_u8(),
                 },
                 0x0019 => Event::NewSignatureResolvingKey {
                     store_hint: buf.get_bool(),
                     address: buf.get_address(),
-                    address_type: buf.get_primitive_u8(),
-                    key_type: buf.get_primitive_u8(),
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    key_type: buf.get_u8().into(),
                     value: buf.get_array_u8(),
                 },
                 0x001A => Event::DeviceAdded {
                     address: buf.get_address(),
-                    address_type: buf.get_primitive_u8(),
-                    action: buf.get_primitive_u8(),
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    action: buf.get_u8().into(),
                 },
                 0x001B => Event::DeviceRemoved {
                     address: buf.get_address(),
-                    address_type: buf.get_primitive_u8(),
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                 },
                 0x001C => Event::NewConnectionParams {
                     store_hint: buf.get_bool(),
                     param: ConnectionParams {
                         address: buf.get_address(),
-                        address_type: buf.get_primitive_u8(),
+                        address_type: AddressType::from_mgmt_u8(buf.get_u8()),
                         min_connection_interval: buf.get_u16_le(),
                         max_connection_interval: buf.get_u16_le(),
                         connection_latency: buf.get_u16_le(),
@@ -194,19 +261,16 @@ impl Response {
                     missing_options: BitFlags::from_bits_truncate(buf.get_u32_le()),
                 },
                 0x0020 => Event::ExtendedIndexAdded {
-                    controller_type: buf.get_primitive_u8(),
-                    controller_bus: buf.get_primitive_u8(),
+                    controller_type: buf.get_u8().into(),
+                    controller_bus: buf.get_u8().into(),
                 },
                 0x0021 => Event::ExtendedIndexRemoved {
-                    controller_type: buf.get_primitive_u8(),
-                    controller_bus: buf.get_primitive_u8(),
+                    controller_type: buf.get_u8().into(),
+                    controller_bus: buf.get_u8().into(),
                 },
                 0x0022 => Event::LocalOutOfBandExtDataUpdated {
-                    address_type: buf.get_primitive_u8(),
-                    eir_data: {
-                        let len = buf.get_u16_le() as usize;
-                        buf.copy_to_bytes(len)
-                    },
+                    address_type: AddressType::from_mgmt_u8(buf.get_u8()),
+                    eir_data: get_eir_data(&mut buf)?,
                 },
                 0x0023 => Event::AdvertisingAdded {
                     instance: buf.get_u8(),
@@ -215,10 +279,7 @@ impl Response {
                     instance: buf.get_u8(),
                 },
                 0x0025 => Event::ExtControllerInfoChanged {
-                    eir_data: {
-                        let len = buf.get_u16_le() as usize;
-                        buf.copy_to_bytes(len)
-                    },
+                    eir_data: get_eir_data(&mut buf)?,
                 },
                 0x0026 => Event::PhyConfigChanged {
                     selected_phys: BitFlags::from_bits_truncate(buf.get_u32_le()),
@@ -228,13 +289,105 @@ impl Response {
                     flags: buf.get_u32_le(),
                 },
                 0x0028 => Event::DefaultSystemConfigChanged {
-                    params: buf.get_tlv_map(),
+                    params: buf.get_tlv_map().into(),
                 },
                 0x0029 => Event::DefaultRuntimeConfigChanged {
-                    params: buf.get_tlv_map(),
+                    params: buf.get_tlv_map().into(),
+                },
+                code => Event::Unknown {
+                    code,
+                    param: buf.copy_to_bytes(buf.remaining()),
                 },
-                _ => return Err(Error::UnknownEventCode { evt_code }),
             },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_event() {
+        let buf: &[u8] = &[0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0xAB, 0xCD];
+        let response = Response::parse(buf).unwrap();
+
+        match response.event {
+            Event::Unknown { code, param } => {
+                assert_eq!(code, 0x0100);
+                assert_eq!(&param[..], &[0xAB, 0xCD]);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn unknown_status() {
+        let buf: &[u8] = &[0x02, 0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x42];
+        let response = Response::parse(buf).unwrap();
+
+        match response.event {
+            Event::CommandStatus { status, .. } => {
+                assert_eq!(status, CommandStatus::Other(0x42));
+                assert_eq!(u8::from(status), 0x42);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let buf: &[u8] = &[0x02, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x01, 0x00];
+        let response = Response::parse(buf).unwrap();
+
+        match response.event {
+            Event::CommandStatus { opcode, .. } => {
+                assert_eq!(opcode, Command::Other(0x0100));
+                assert_eq!(u16::from(opcode), 0x0100);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn truncated_event() {
+        // Device Found whose EIR data is longer than the message
+        let buf: &[u8] = &[
+            0x12, 0x00, 0x00, 0x00, 0x0E, 0x00, 1, 2, 3, 4, 5, 6, 0x00, 0xC4, 0, 0, 0, 0, 0x05,
+            0x00,
+        ];
+        assert!(matches!(parse_mgmt_event(buf), Err(Error::InvalidData)));
+
+        // Device Disconnected without a reason
+        let buf: &[u8] = &[0x0C, 0x00, 0x00, 0x00, 0x07, 0x00, 1, 2, 3, 4, 5, 6, 0x00];
+        assert!(matches!(parse_mgmt_event(buf), Err(Error::InvalidData)));
+
+        // header length does not match
+        let buf: &[u8] = &[0x04, 0x00, 0x00, 0x00, 0x01, 0x00];
+        assert!(matches!(parse_mgmt_event(buf), Err(Error::InvalidData)));
+    }
+
+    #[test]
+    fn invalid_name() {
+        let mut buf = vec![0x08, 0x00, 0x00, 0x00, 0x04, 0x01];
+        buf.extend_from_slice(b"caf\xC3");
+        buf.resize(6 + 249, 0);
+        buf.extend_from_slice(b"c\xFF\0");
+        buf.resize(6 + 260, 0);
+
+        match Response::parse(&buf[..]).unwrap().event {
+            Event::LocalNameChanged {
+                name,
+                short_name,
+                name_bytes,
+                short_name_bytes,
+            } => {
+                assert_eq!(name, "caf\u{FFFD}");
+                assert_eq!(&name_bytes[..], b"caf\xC3");
+                assert_eq!(short_name, "c\u{FFFD}");
+                assert_eq!(&short_name_bytes[..], b"c\xFF");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
@@ -2,9 +2,11 @@ mod client;
 pub mod interface;
 pub mod result;
 mod stream;
+mod transport;
 
 pub use client::*;
 pub use interface::*;
-pub use result::Error;
+pub use result::{Error, PairingError};
 pub(crate) use result::Result;
-pub use stream::ManagementStream;
+pub use stream::{ManagementStream, UnknownEventPolicy};
+pub use transport::ManagementTransport;
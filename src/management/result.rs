@@ -1,4 +1,5 @@
-use crate::management::interface::{Command, CommandStatus};
+use crate::management::interface::{Command, CommandStatus, Controller};
+use crate::Address;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -13,10 +14,20 @@ pub enum Error {
         #[source]
         source: ::std::io::Error,
     },
-    #[error("Command {:?} returned {:?}.", opcode, status)]
+    #[error(
+        "Command {:?} on {} returned {:?}{}.",
+        opcode,
+        controller,
+        status,
+        .context.as_ref().map(|context| format!(" ({})", context)).unwrap_or_default()
+    )]
     CommandError {
         opcode: Command,
+        controller: Controller,
         status: CommandStatus,
+        /// A description of what the command was doing, such as the device
+        /// that it was sent for.
+        context: Option<String>,
     },
     #[error("Unknown opcode: {:x}.", opcode)]
     UnknownOpcode { opcode: u16 },
@@ -26,6 +37,10 @@ pub enum Error {
     UnknownEventCode { evt_code: u16 },
     #[error("Timed out.")]
     TimedOut,
+    #[error("The command was cancelled.")]
+    Cancelled,
+    #[error("The management task has stopped.")]
+    TaskStopped,
     #[error("The socket received invalid data.")]
     InvalidData,
     #[error(
@@ -41,6 +56,148 @@ pub enum Error {
     },
     #[error("The pin code is too long; the maximum length is {} bytes.", max_len)]
     PinCodeTooLong { max_len: u32 },
+    #[error("The pin code is empty.")]
+    PinCodeEmpty,
+    #[error("The passkey {} is out of range; the maximum is 999999.", passkey)]
+    PasskeyOutOfRange { passkey: u32 },
+    #[error("Invalid connection parameters: {}.", reason)]
+    InvalidConnectionParams { reason: &'static str },
+    #[error("Invalid scan parameters: {}.", reason)]
+    InvalidScanParams { reason: &'static str },
+    /// The kernel only accepts discoverable timeouts of whole seconds, from
+    /// 1 to 65535.
+    #[error("{:?} is not a valid discoverable timeout.", duration)]
+    InvalidDiscoverableTimeout { duration: std::time::Duration },
+    #[error("{} is not a valid static random address.", address)]
+    InvalidStaticAddress { address: Address },
+    /// The device has not been found by discovery on the controller, so it
+    /// is not known how to connect to it.
+    #[error("Device {} has not been found on {}.", address, controller)]
+    DeviceNotFound {
+        controller: Controller,
+        address: Address,
+    },
+    /// The device has only been found through advertisements which do not
+    /// accept connections.
+    #[error("Device {} on {} does not accept connections.", address, controller)]
+    DeviceNotConnectable {
+        controller: Controller,
+        address: Address,
+    },
+    /// The kernel rejected a command because the socket was opened by a
+    /// process without the capability that the command needs. The kernel
+    /// checks the capability when the socket is opened, so the socket has to
+    /// be opened again once the process has it.
+    #[error(
+        "Command {:?} on {} was denied because the process does not have {}; run it as root or grant it with `setcap cap_net_admin+ep`.",
+        opcode,
+        controller,
+        required
+    )]
+    MissingCapability {
+        required: &'static str,
+        opcode: Command,
+        controller: Controller,
+    },
+}
+
+impl Error {
+    /// The error for a command that failed with `status`. Permission Denied
+    /// becomes [`Error::MissingCapability`], because the kernel only reports
+    /// it for sockets that were opened without `CAP_NET_ADMIN`.
+    pub(crate) fn command_failed(
+        opcode: Command,
+        controller: Controller,
+        status: CommandStatus,
+    ) -> Self {
+        match status {
+            CommandStatus::PermissionDenied => Error::MissingCapability {
+                required: "CAP_NET_ADMIN",
+                opcode,
+                controller,
+            },
+            status => Error::CommandError {
+                opcode,
+                controller,
+                status,
+                context: None,
+            },
+        }
+    }
+
+    /// The status that a command failed with, if this error is a
+    /// [`Error::CommandError`] or an [`Error::MissingCapability`].
+    pub fn command_status(&self) -> Option<CommandStatus> {
+        match self {
+            Error::CommandError { status, .. } => Some(*status),
+            Error::MissingCapability { .. } => Some(CommandStatus::PermissionDenied),
+            _ => None,
+        }
+    }
+
+    /// Whether a command failed because the controller or the kernel does not
+    /// support it.
+    pub fn is_not_supported(&self) -> bool {
+        matches!(
+            self.command_status(),
+            Some(CommandStatus::NotSupported) | Some(CommandStatus::UnknownCommand)
+        )
+    }
+
+    /// Whether a command failed because the controller is not powered.
+    pub fn is_not_powered(&self) -> bool {
+        matches!(self.command_status(), Some(CommandStatus::NotPowered))
+    }
+
+    /// Whether a command failed because the controller is busy with another
+    /// operation, so it may succeed if it is retried later.
+    pub fn is_busy(&self) -> bool {
+        matches!(self.command_status(), Some(CommandStatus::Busy))
+    }
+
+    /// Whether the operation that failed may succeed if it is tried again
+    /// later. See [`CommandStatus::is_transient`].
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::TimedOut => true,
+            Error::CommandError { status, .. } => status.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Attaches a description of what a failed command was doing. Errors
+    /// other than [`Error::CommandError`] are returned unchanged.
+    pub fn with_context(self, context: impl Into<String>) -> Self {
+        match self {
+            Error::CommandError {
+                opcode,
+                controller,
+                status,
+                ..
+            } => Error::CommandError {
+                opcode,
+                controller,
+                status,
+                context: Some(context.into()),
+            },
+            err => err,
+        }
+    }
+}
+
+/// The ways in which
+/// [`pair_device_with_options`](crate::management::pair_device_with_options)
+/// can fail.
+#[derive(Error, Debug)]
+pub enum PairingError {
+    #[error("Authentication with {} failed.", address)]
+    AuthenticationFailed { address: Address },
+    #[error("The agent rejected pairing with {}.", address)]
+    UserRejected { address: Address },
+    #[error("Pairing with {} timed out.", address)]
+    TimedOut { address: Address },
+    #[error(transparent)]
+    Management(#[from] Error),
 }
 
 impl From<std::io::Error> for Error {
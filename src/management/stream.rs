@@ -1,27 +1,113 @@
 use std::os::unix::net::UnixStream as StdUnixStream;
 
-use std::u16;
-
 use crate::address::Protocol;
 use bytes::*;
+use futures::{ready, Stream};
 use libc;
-use std::os::unix::io::{FromRawFd, RawFd};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+#[cfg(any(test, feature = "test-util"))]
 use tokio::net::UnixStream;
 
-use crate::management::interface::{Request, Response};
+use crate::capture::PacketLogger;
+use crate::management::interface::{Controller, Event, Request, Response};
+use crate::management::transport::{ManagementTransport, SocketTransport};
 use crate::management::Error;
+use crate::runtime::{Reactor, TokioReactor};
+
+/// What a [`ManagementStream`] does when it receives an event that this
+/// library does not know about.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum UnknownEventPolicy {
+    /// Return the event as an [`Event::Unknown`], so that applications keep
+    /// working on newer kernels and can log the events that they missed.
+    #[default]
+    Forward,
+    /// Return an [`Error::UnknownEventCode`] instead of the event.
+    Error,
+}
 
-#[derive(Debug)]
-pub struct ManagementStream(
-    // reads need to be buffered so that methods like read_exact do not end up
-    // dropping data and writes cannot be buffered so that we don't have to
-    // worry about flushing them
-    BufReader<UnixStream>,
-);
+/// A socket connected to the management interface of the kernel.
+///
+/// The kernel delivers events for every controller to every management
+/// socket, but only sends the replies to a command to the socket that issued
+/// it. Multiple streams can be opened at the same time, so commands can be
+/// issued concurrently by giving each task its own stream. On hosts with many
+/// controllers, a stream per controller with
+/// [`set_controller_filter`](ManagementStream::set_controller_filter) allows
+/// the events of each controller to be processed separately.
+pub struct ManagementStream {
+    transport: Box<dyn ManagementTransport>,
+    filter: Option<Controller>,
+    unknown_event_policy: UnknownEventPolicy,
+    last_reply: Option<Bytes>,
+}
 
 impl ManagementStream {
+    /// Opens a new management socket, which is driven by the tokio runtime
+    /// that the calling task runs on.
     pub fn open() -> Result<Self, std::io::Error> {
+        Self::open_with(&TokioReactor)
+    }
+
+    /// Opens a new management socket, which is driven by `reactor` instead
+    /// of tokio. See the [`runtime`](crate::runtime) module.
+    pub fn open_with(reactor: &dyn Reactor) -> Result<Self, std::io::Error> {
+        let fd = Self::open_fd()?;
+        Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd) }, reactor)
+    }
+
+    pub(crate) fn from_fd(fd: OwnedFd, reactor: &dyn Reactor) -> Result<Self, std::io::Error> {
+        Ok(Self::with_transport(SocketTransport::new(
+            reactor.register(fd)?,
+        )))
+    }
+
+    /// Creates a stream which sends its requests on `transport` instead of a
+    /// management socket. See [`ManagementTransport`].
+    pub fn with_transport<T: ManagementTransport + 'static>(transport: T) -> Self {
+        ManagementStream {
+            transport: Box::new(transport),
+            filter: None,
+            unknown_event_policy: UnknownEventPolicy::default(),
+            last_reply: None,
+        }
+    }
+
+    /// Creates a stream which is connected to a local socket instead of the
+    /// kernel, so that tests can check the messages that commands send and
+    /// answer them. The socket keeps message boundaries, like the
+    /// management channel. See [`MockKernel`](crate::testing::mock::MockKernel).
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn pair() -> Result<(Self, UnixStream), std::io::Error> {
+        let mut fds = [0 as RawFd; 2];
+        crate::util::check_error(unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        })?;
+
+        let (ours, theirs) = unsafe {
+            (
+                OwnedFd::from_raw_fd(fds[0]),
+                StdUnixStream::from_raw_fd(fds[1]),
+            )
+        };
+
+        Ok((
+            Self::from_fd(ours, &TokioReactor)?,
+            UnixStream::from_std(theirs)?,
+        ))
+    }
+
+    fn open_fd() -> Result<RawFd, std::io::Error> {
         let fd: RawFd = unsafe {
             libc::socket(
                 libc::AF_BLUETOOTH,
@@ -57,29 +143,252 @@ impl ManagementStream {
             return Err(err);
         }
 
-        Ok(ManagementStream(BufReader::new(UnixStream::from_std(
-            unsafe { StdUnixStream::from_raw_fd(fd) },
-        )?)))
+        Ok(fd)
+    }
+
+    /// Opens a new non-blocking management socket without registering it with
+    /// a runtime.
+    ///
+    /// Requests are sent by writing `Bytes::from(request)` to the socket, and
+    /// every read returns exactly one message, which can be parsed with
+    /// [`Response::parse`]. To use the command functions of this library with
+    /// another runtime, use [`open_with`](ManagementStream::open_with)
+    /// instead.
+    pub fn open_raw() -> Result<OwnedFd, std::io::Error> {
+        let fd = Self::open_fd()?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Deregisters the socket from its runtime, and returns its file
+    /// descriptor, which the caller then owns. If the socket cannot be
+    /// deregistered, it is closed and the error is returned. Streams which
+    /// were created with
+    /// [`with_transport`](ManagementStream::with_transport) fail if their
+    /// transport does not use a socket.
+    pub fn try_into_raw_fd(self) -> Result<RawFd, std::io::Error> {
+        Ok(self.transport.into_fd()?.into_raw_fd())
+    }
+
+    /// Sends `request` on a new socket which is closed right away, without
+    /// waiting for the reply. This does not need a runtime, so it can be used
+    /// from `Drop` implementations.
+    pub(crate) fn send_detached(request: Request) -> Result<(), std::io::Error> {
+        let fd = Self::open_fd()?;
+        let buf: Bytes = request.into();
+
+        let res = unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        let err = std::io::Error::last_os_error();
+
+        unsafe {
+            libc::close(fd);
+        }
+
+        if res < 0 {
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Opens a new management socket which only receives events for
+    /// `controller`. See
+    /// [`set_controller_filter`](ManagementStream::set_controller_filter).
+    pub fn open_for(controller: Controller) -> Result<Self, std::io::Error> {
+        let mut stream = Self::open()?;
+        stream.set_controller_filter(Some(controller));
+        Ok(stream)
+    }
+
+    /// Sets the controller whose events should be returned by
+    /// [`receive`](ManagementStream::receive). Events for other controllers
+    /// are dropped. Replies to the commands sent on this stream are always
+    /// returned, whichever controller they were sent to, and so are events
+    /// that do not belong to a controller.
+    pub fn set_controller_filter(&mut self, controller: Option<Controller>) {
+        self.filter = controller;
+    }
+
+    pub fn controller_filter(&self) -> Option<Controller> {
+        self.filter
+    }
+
+    /// Sets what happens when an event that this library does not know about
+    /// is received. By default, the event is returned as an
+    /// [`Event::Unknown`].
+    pub fn set_unknown_event_policy(&mut self, policy: UnknownEventPolicy) {
+        self.unknown_event_policy = policy;
+    }
+
+    pub fn unknown_event_policy(&self) -> UnknownEventPolicy {
+        self.unknown_event_policy
+    }
+
+    /// The parameters of the Command Complete event that answered the last
+    /// command which was sent by one of the command functions, such as
+    /// [`get_controller_info`](crate::management::get_controller_info), before
+    /// they were parsed. This is `None` if the command was answered by a
+    /// Command Status event instead.
+    ///
+    /// See also [`with_raw_reply`](crate::management::with_raw_reply).
+    pub fn last_reply(&self) -> Option<&Bytes> {
+        self.last_reply.as_ref()
+    }
+
+    pub(crate) fn set_last_reply(&mut self, reply: Option<Bytes>) {
+        self.last_reply = reply;
+    }
+
+    /// Attaches a logger which receives every message that is sent or
+    /// received on this socket, or detaches the current logger if `logger`
+    /// is `None`. See
+    /// [`ManagementTransport::set_packet_logger`] for streams which were
+    /// created with [`with_transport`](ManagementStream::with_transport).
+    pub fn set_packet_logger(&mut self, logger: Option<Arc<dyn PacketLogger>>) {
+        self.transport.set_packet_logger(logger);
     }
 
     /// Returns either an error or the number of bytes that were sent.
     pub async fn send(&mut self, request: Request) -> Result<usize, std::io::Error> {
-        let buf: Bytes = request.into();
-        self.0.write(&buf).await
+        futures::future::poll_fn(|cx| self.transport.poll_send(cx, &request)).await
     }
 
+    /// Waits for the next event.
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe. The kernel delivers every message in one read,
+    /// and a message is only taken from the socket by the poll that returns
+    /// it, so a `receive` which is dropped because it timed out or lost a
+    /// `select` has not consumed anything. The next `receive` returns the
+    /// event that it would have returned.
     pub async fn receive(&mut self) -> Result<Response, Error> {
-        // read 6 byte header
-        let mut header = [0u8; 6];
-        self.0.read_exact(&mut header).await?;
+        futures::future::poll_fn(|cx| self.poll_receive(cx)).await
+    }
+
+    /// Returns the next event if one has already been received, or `None`
+    /// without waiting if there is none. This is useful for polling the
+    /// stream from a loop which cannot await, such as the tick handler of a
+    /// GUI.
+    pub fn try_receive(&mut self) -> Result<Option<Response>, Error> {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        match self.poll_receive(&mut cx) {
+            Poll::Ready(response) => response.map(Some),
+            Poll::Pending => Ok(None),
+        }
+    }
+
+    /// Polls for the next event, registering the current task to be woken up
+    /// when one is received. Unlike [`receive`](ManagementStream::receive),
+    /// this can be used from hand-written futures. `ManagementStream` also
+    /// implements [`Stream`], which is based on this method.
+    pub fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response, Error>> {
+        loop {
+            let response = ready!(self.poll_receive_unfiltered(cx))?;
+
+            if self.accepts(&response) {
+                return Poll::Ready(Ok(response));
+            }
+        }
+    }
+
+    fn accepts(&self, response: &Response) -> bool {
+        match self.filter {
+            None => true,
+            Some(controller) => {
+                response.controller == controller
+                    || response.controller == Controller::none()
+                    || matches!(
+                        response.event,
+                        Event::CommandComplete { .. } | Event::CommandStatus { .. }
+                    )
+            }
+        }
+    }
+
+    fn poll_receive_unfiltered(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response, Error>> {
+        let response = ready!(self.transport.poll_receive(cx))?;
+
+        if let Event::Unknown { code, .. } = response.event {
+            if self.unknown_event_policy == UnknownEventPolicy::Error {
+                return Poll::Ready(Err(Error::UnknownEventCode { evt_code: code }));
+            }
+        }
+
+        Poll::Ready(Ok(response))
+    }
+}
 
-        let param_size = u16::from_le_bytes([header[4], header[5]]) as usize;
+impl fmt::Debug for ManagementStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagementStream")
+            .field("fd", &self.transport.raw_fd())
+            .field("filter", &self.filter)
+            .field("unknown_event_policy", &self.unknown_event_policy)
+            .field("last_reply", &self.last_reply)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Streams which were created with
+/// [`with_transport`](ManagementStream::with_transport) return -1 if their
+/// transport does not use a socket.
+impl AsRawFd for ManagementStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.transport.raw_fd().unwrap_or(-1)
+    }
+}
+
+/// Deregisters the socket from its runtime, like
+/// [`ManagementStream::try_into_raw_fd`].
+impl TryFrom<ManagementStream> for OwnedFd {
+    type Error = std::io::Error;
+
+    fn try_from(stream: ManagementStream) -> Result<Self, Self::Error> {
+        stream.transport.into_fd()
+    }
+}
+
+/// Converts a socket which is bound to the management channel into a
+/// [`ManagementStream`]. The socket is made non-blocking.
+impl TryFrom<OwnedFd> for ManagementStream {
+    type Error = std::io::Error;
+
+    fn try_from(fd: OwnedFd) -> Result<Self, Self::Error> {
+        let mut domain: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+        if unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_DOMAIN,
+                &mut domain as *mut _ as *mut _,
+                &mut len,
+            )
+        } < 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        if domain != libc::AF_BLUETOOTH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "socket domain is not bluetooth",
+            ));
+        }
+
+        let stream = StdUnixStream::from(fd);
+        stream.set_nonblocking(true)?;
+
+        Self::from_fd(stream.into(), &TokioReactor)
+    }
+}
 
-        // read rest of message
-        let mut body = vec![0u8; param_size];
-        self.0.read_exact(&mut body[..]).await?;
+impl Stream for ManagementStream {
+    type Item = Result<Response, Error>;
 
-        // make buffer by chaining header and body
-        Response::parse(Buf::chain(&header[..], &body[..]))
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_receive(cx).map(Some)
     }
 }
@@ -0,0 +1,161 @@
+use std::io;
+use std::os::unix::io::{OwnedFd, RawFd};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::ready;
+
+use crate::address::Protocol;
+use crate::capture::{AttachedLogger, Direction, PacketLogger};
+use crate::management::interface::{parse_mgmt_event, Request, Response};
+use crate::management::Error;
+use crate::runtime::Registration;
+use crate::util::check_error;
+
+/// The length of the longest message that the kernel can send: a 6 byte
+/// header and up to 65535 bytes of parameters.
+const MAX_MESSAGE_LEN: usize = 6 + u16::MAX as usize;
+
+/// Carries the requests and responses of a
+/// [`ManagementStream`](crate::management::ManagementStream).
+///
+/// The stream sends its requests on the management socket of the kernel,
+/// unless it is created with
+/// [`ManagementStream::with_transport`](crate::management::ManagementStream::with_transport).
+/// Another transport lets the command functions of this library run
+/// without a controller or `CAP_NET_ADMIN`, for example the
+/// [`MockTransport`](crate::testing::mock::MockTransport) of the `testing`
+/// feature, which answers them in memory.
+pub trait ManagementTransport: Send + Sync {
+    /// Sends `request`, and returns the number of bytes that were sent.
+    /// Registers the current task to be woken up if it cannot be sent yet.
+    fn poll_send(&mut self, cx: &mut Context<'_>, request: &Request) -> Poll<io::Result<usize>>;
+
+    /// Polls for the next response, registering the current task to be woken
+    /// up when one is received. This must not lose a response if the task
+    /// stops polling before it returns one, so that
+    /// [`ManagementStream::receive`](crate::management::ManagementStream::receive)
+    /// stays cancel safe.
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response, Error>>;
+
+    /// The file descriptor of the socket that the transport uses, if it has
+    /// one.
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Returns the socket that the transport uses. Transports which do not
+    /// use a socket fail with [`io::ErrorKind::Unsupported`].
+    fn into_fd(self: Box<Self>) -> io::Result<OwnedFd> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Attaches a logger which receives every message that is sent or
+    /// received, or detaches the current logger if `logger` is `None`.
+    /// Transports which do not have the messages in their encoded form
+    /// ignore it.
+    fn set_packet_logger(&mut self, logger: Option<Arc<dyn PacketLogger>>) {
+        let _ = logger;
+    }
+}
+
+/// The transport of a management socket, which is driven by a
+/// [`Reactor`](crate::runtime::Reactor).
+pub(crate) struct SocketTransport {
+    // writes cannot be buffered so that we don't have to worry about
+    // flushing them
+    inner: Box<dyn Registration>,
+    // holds the message that is being parsed; every read returns one whole
+    // message, so nothing is kept between reads
+    read_buf: Vec<u8>,
+    logger: Option<AttachedLogger>,
+}
+
+impl SocketTransport {
+    pub(crate) fn new(inner: Box<dyn Registration>) -> Self {
+        SocketTransport {
+            inner,
+            read_buf: vec![0; MAX_MESSAGE_LEN],
+            logger: None,
+        }
+    }
+}
+
+impl ManagementTransport for SocketTransport {
+    fn poll_send(&mut self, cx: &mut Context<'_>, request: &Request) -> Poll<io::Result<usize>> {
+        let buf: Bytes = request.clone().into();
+
+        let len = ready!(self.inner.poll_write_with(cx, &mut |fd| {
+            check_error(unsafe {
+                libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) as libc::c_int
+            })
+            .map(|len| len as usize)
+        }))?;
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(data = %crate::util::Hex(&buf), "management send");
+
+        if let Some(logger) = &self.logger {
+            logger.log(Protocol::HCI, Direction::Sent, &buf);
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response, Error>> {
+        // the kernel delivers every message in one read. the reactor only
+        // waits for readiness again once a read reports that the socket is
+        // empty, so a read that does not fill the buffer does not lose the
+        // wakeup for messages that are already queued behind it
+        let read_buf = &mut self.read_buf;
+        let len = ready!(self.inner.poll_read_with(cx, &mut |fd| {
+            check_error(unsafe {
+                libc::read(
+                    fd,
+                    read_buf.as_mut_ptr() as *mut libc::c_void,
+                    read_buf.len(),
+                ) as libc::c_int
+            })
+            .map(|len| len as usize)
+        }))?;
+        let buf = &self.read_buf[..len];
+
+        if buf.is_empty() {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()));
+        }
+
+        // 6 byte header, which ends with the length of the parameters
+        let len = if buf.len() >= 6 {
+            6 + u16::from_le_bytes([buf[4], buf[5]]) as usize
+        } else {
+            usize::MAX
+        };
+
+        if buf.len() < len {
+            // drop the incomplete message
+            return Poll::Ready(Err(Error::InvalidData));
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(data = %crate::util::Hex(&buf[..len]), "management recv");
+
+        if let Some(logger) = &self.logger {
+            logger.log(Protocol::HCI, Direction::Received, &buf[..len]);
+        }
+
+        Poll::Ready(parse_mgmt_event(&buf[..len]))
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.inner.as_raw_fd())
+    }
+
+    fn into_fd(self: Box<Self>) -> io::Result<OwnedFd> {
+        self.inner.into_fd()
+    }
+
+    fn set_packet_logger(&mut self, logger: Option<Arc<dyn PacketLogger>>) {
+        self.logger = logger.map(AttachedLogger::new);
+    }
+}
@@ -0,0 +1,596 @@
+//! The LE peripheral role in one object.
+//!
+//! A [`Peripheral`] enables LE on a controller, advertises connectably using
+//! an advertising instance, serves a [`GattServer`] and accepts channels on
+//! any number of L2CAP and RFCOMM listeners. [`Peripheral::next_event`]
+//! reports centrals connecting and disconnecting, subscribing to and writing
+//! characteristics and opening channels as one stream of
+//! [`PeripheralEvent`]s, so an application does not have to wait on the
+//! management socket, each listener and each ATT bearer itself.
+//!
+//! The GATT server only runs if it is given a listener with
+//! [`Peripheral::serve_gatt`], which bluetoothd has to leave free.
+
+use bytes::Bytes;
+use futures::future::{select_all, BoxFuture};
+use futures::FutureExt;
+use tokio::sync::mpsc;
+
+use crate::communication::att::{self, GattConnection, GattEvent, GattServer};
+use crate::communication::{BluetoothListener, BluetoothStream};
+use crate::management::{
+    self, add_advertising, remove_advertising, set_le, set_powered, AdvertisingFlags,
+    AdvertisingParams, Controller, DisconnectionReason, Event, ManagementStream, Response,
+};
+use crate::{Address, AddressType, Error};
+
+/// Something that happened while a [`Peripheral`] was running.
+#[derive(Debug)]
+pub enum PeripheralEvent {
+    /// A central connected to the controller.
+    CentralConnected {
+        address: Address,
+        address_type: AddressType,
+    },
+
+    /// A central disconnected from the controller.
+    CentralDisconnected {
+        address: Address,
+        address_type: AddressType,
+        reason: DisconnectionReason,
+    },
+
+    /// A central opened a channel to one of the listeners.
+    ChannelOpened {
+        stream: BluetoothStream,
+        address: Address,
+        /// The PSM or RFCOMM channel of the listener that accepted the
+        /// channel.
+        port: u16,
+    },
+
+    /// A central enabled or disabled notifications or indications of the
+    /// characteristic whose value has the handle `handle`.
+    Subscribed {
+        address: Address,
+        handle: u16,
+        notify: bool,
+        indicate: bool,
+    },
+
+    /// A central wrote the value of the characteristic whose value has the
+    /// handle `handle`. The GATT server has already stored the value.
+    Written {
+        address: Address,
+        handle: u16,
+        value: Bytes,
+    },
+
+    /// The advertising instance was removed, because its timeout passed or
+    /// because it was removed through another management socket.
+    AdvertisingStopped,
+}
+
+/// What became ready while a [`Peripheral`] was waiting.
+enum Ready {
+    Response(Result<Response, management::Error>),
+    Channel(Result<(BluetoothStream, (Address, u16)), std::io::Error>),
+    Bearer(Result<(BluetoothStream, (Address, u16)), std::io::Error>),
+    Pdu(usize, Result<Bytes, att::Error>),
+}
+
+/// Advertises on a controller and accepts connections from centrals.
+///
+/// Like [`ReconnectPolicy`](crate::management::ReconnectPolicy), the
+/// peripheral only accepts channels and answers GATT requests while
+/// [`Peripheral::next_event`] is being awaited. Dropping the peripheral does
+/// not remove its advertising instance; call [`Peripheral::stop_advertising`]
+/// first.
+pub struct Peripheral {
+    socket: ManagementStream,
+    controller: Controller,
+    instance: Option<u8>,
+    listeners: Vec<BluetoothListener>,
+    server: GattServer,
+    att_listener: Option<BluetoothListener>,
+    connections: Vec<(Address, GattConnection)>,
+}
+
+impl Peripheral {
+    pub fn new(socket: ManagementStream, controller: Controller) -> Self {
+        Self {
+            socket,
+            controller,
+            instance: None,
+            listeners: vec![],
+            server: GattServer::new(),
+            att_listener: None,
+            connections: vec![],
+        }
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    /// The advertising instance of the peripheral, if it is advertising.
+    pub fn instance(&self) -> Option<u8> {
+        self.instance
+    }
+
+    /// Accepts channels on `listener`, which has to be bound to the address
+    /// of the controller, or to [`Address::zero`](crate::Address::zero).
+    pub fn listen(&mut self, listener: BluetoothListener) {
+        self.listeners.push(listener);
+    }
+
+    pub fn listeners(&self) -> &[BluetoothListener] {
+        &self.listeners
+    }
+
+    /// Serves `server` to the centrals that open an ATT bearer on `listener`,
+    /// which usually comes from
+    /// [`BluetoothListener::bind_att`](crate::communication::BluetoothListener::bind_att).
+    /// Replaces the server and listener of an earlier call, but keeps the
+    /// bearers that are already open.
+    pub fn serve_gatt(&mut self, server: GattServer, listener: BluetoothListener) {
+        self.server = server;
+        self.att_listener = Some(listener);
+    }
+
+    pub fn gatt_server(&self) -> &GattServer {
+        &self.server
+    }
+
+    /// The GATT server of the peripheral, for example to change values
+    /// without notifying anybody.
+    pub fn gatt_server_mut(&mut self) -> &mut GattServer {
+        &mut self.server
+    }
+
+    /// Changes the value of the characteristic whose value has the handle
+    /// `handle`, and sends it to every central that subscribed to it.
+    /// Returns the number of centrals that it was sent to, or fails with
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput) if the server has
+    /// no such characteristic.
+    ///
+    /// Bearers that cannot be written to anymore are closed; the
+    /// disconnection of their centrals is still reported by
+    /// [`Peripheral::next_event`].
+    pub async fn notify(&mut self, handle: u16, value: Bytes) -> Result<usize, Error> {
+        if !self.server.set_value(handle, value.clone()) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput).into());
+        }
+
+        let mut sent = 0;
+        let mut index = 0;
+
+        while index < self.connections.len() {
+            match self.connections[index].1.notify(handle, &value).await {
+                Ok(notified) => {
+                    sent += notified as usize;
+                    index += 1;
+                }
+                Err(_) => {
+                    self.connections.remove(index);
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// The management socket of the peripheral, for commands that it does
+    /// not wrap.
+    pub fn socket(&mut self) -> &mut ManagementStream {
+        &mut self.socket
+    }
+
+    /// Enables LE, adds an advertising instance with `params` and powers on
+    /// the controller. The instance is always connectable, regardless of the
+    /// global connectable setting. If the peripheral is already advertising,
+    /// its instance is replaced.
+    ///
+    /// Returns the identifier of the instance.
+    pub async fn advertise(
+        &mut self,
+        mut params: AdvertisingParams,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<u8, Error> {
+        set_le(&mut self.socket, self.controller, true, event_tx.clone()).await?;
+
+        // the instance is only replaced once the kernel accepts the new one
+        if let Some(instance) = self.instance {
+            params.instance = instance;
+        }
+
+        params.flags |= AdvertisingFlags::EnterConnectable;
+        let instance =
+            add_advertising(&mut self.socket, self.controller, params, event_tx.clone()).await?;
+        self.instance = Some(instance);
+
+        set_powered(&mut self.socket, self.controller, true, event_tx).await?;
+
+        Ok(instance)
+    }
+
+    /// Removes the advertising instance of the peripheral, if there is one.
+    /// Centrals that are already connected stay connected.
+    pub async fn stop_advertising(
+        &mut self,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<(), Error> {
+        if let Some(instance) = self.instance.take() {
+            remove_advertising(&mut self.socket, self.controller, instance, event_tx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the next central to connect or disconnect, to subscribe to
+    /// or write a characteristic, or to open a channel, or for advertising to
+    /// stop. GATT requests that do not change anything, such as reads, are
+    /// answered without returning.
+    ///
+    /// All other events received while this function is running are
+    /// forwarded to `event_tx`.
+    pub async fn next_event(
+        &mut self,
+        event_tx: Option<mpsc::Sender<Response>>,
+    ) -> Result<PeripheralEvent, Error> {
+        loop {
+            let ready = {
+                // receive is cancel safe, see ManagementStream::receive, and
+                // so are accept and GattConnection::receive
+                let mut futures: Vec<BoxFuture<'_, Ready>> =
+                    vec![self.socket.receive().map(Ready::Response).boxed()];
+
+                for listener in &self.listeners {
+                    futures.push(listener.accept().map(Ready::Channel).boxed());
+                }
+
+                if let Some(listener) = &self.att_listener {
+                    futures.push(listener.accept().map(Ready::Bearer).boxed());
+                }
+
+                for (index, (_, connection)) in self.connections.iter_mut().enumerate() {
+                    futures.push(
+                        connection
+                            .receive()
+                            .map(move |pdu| Ready::Pdu(index, pdu))
+                            .boxed(),
+                    );
+                }
+
+                select_all(futures).await.0
+            };
+
+            match ready {
+                Ready::Response(response) => {
+                    let response = response?;
+
+                    if let Some(event) = self.handle_event(&response) {
+                        return Ok(event);
+                    }
+
+                    if let Some(event_tx) = &event_tx {
+                        let _ = event_tx.send(response).await;
+                    }
+                }
+                Ready::Channel(accepted) => {
+                    let (stream, (address, port)) = accepted?;
+
+                    return Ok(PeripheralEvent::ChannelOpened {
+                        stream,
+                        address,
+                        port,
+                    });
+                }
+                Ready::Bearer(accepted) => {
+                    let (stream, (address, _)) = accepted?;
+                    self.connections
+                        .push((address, GattConnection::new(stream)));
+                }
+                Ready::Pdu(index, pdu) => {
+                    let (address, connection) = &mut self.connections[index];
+                    let address = *address;
+
+                    let answered = match pdu {
+                        Ok(pdu) => connection.answer(&mut self.server, pdu).await,
+                        Err(err) => Err(err),
+                    };
+
+                    match answered {
+                        Ok(Some(event)) => return Ok(gatt_event(address, event)),
+                        Ok(None) => {}
+                        // the central closed the bearer or is gone, which the
+                        // management socket reports as a disconnection
+                        Err(_) => {
+                            self.connections.remove(index);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_event(&mut self, response: &Response) -> Option<PeripheralEvent> {
+        if response.controller != self.controller {
+            return None;
+        }
+
+        match response.event {
+            Event::DeviceConnected {
+                address,
+                address_type,
+                ..
+            } => Some(PeripheralEvent::CentralConnected {
+                address,
+                address_type,
+            }),
+            Event::DeviceDisconnected {
+                address,
+                address_type,
+                reason,
+            } => Some(PeripheralEvent::CentralDisconnected {
+                address,
+                address_type,
+                reason,
+            }),
+            Event::AdvertisingRemoved { instance } if self.instance == Some(instance) => {
+                self.instance = None;
+                Some(PeripheralEvent::AdvertisingStopped)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the management socket and the listeners of the peripheral.
+    /// The GATT server stops, and its bearers are closed.
+    pub fn into_inner(self) -> (ManagementStream, Vec<BluetoothListener>) {
+        (self.socket, self.listeners)
+    }
+}
+
+fn gatt_event(address: Address, event: GattEvent) -> PeripheralEvent {
+    match event {
+        GattEvent::Subscribed {
+            handle,
+            notify,
+            indicate,
+        } => PeripheralEvent::Subscribed {
+            address,
+            handle,
+            notify,
+            indicate,
+        },
+        GattEvent::Written { handle, value } => PeripheralEvent::Written {
+            address,
+            handle,
+            value,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use enumflags2::BitFlags;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::communication::att::{AttPdu, Characteristic, CharacteristicProperty};
+    use crate::management::{Command, CommandStatus};
+    use crate::testing::mock::{
+        address_param, device_connected, MockScript, MockTransport, MOCK_ADDRESS, MOCK_CONTROLLER,
+    };
+
+    const DEVICE_CONNECTED: u16 = 0x000B;
+    const DEVICE_DISCONNECTED: u16 = 0x000C;
+    const ADVERTISING_REMOVED: u16 = 0x0024;
+
+    fn params() -> AdvertisingParams {
+        AdvertisingParams {
+            instance: 1,
+            flags: BitFlags::empty(),
+            duration: 0,
+            timeout: 0,
+            adv_data: vec![],
+            scan_rsp: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn advertise_keeps_instance() {
+        let script = MockScript::new()
+            .reply(Command::SetLowEnergy, [0x00, 0x02, 0x00, 0x00])
+            .reply(Command::SetPowered, [0x01, 0x02, 0x00, 0x00])
+            .reply(Command::AddAdvertising, [0x03])
+            .fail(Command::AddAdvertising, CommandStatus::InvalidParams);
+        let (socket, transport) = MockTransport::stream(script);
+        let mut peripheral = Peripheral::new(socket, MOCK_CONTROLLER);
+
+        assert_eq!(peripheral.advertise(params(), None).await.unwrap(), 3);
+        assert!(peripheral.advertise(params(), None).await.is_err());
+        assert_eq!(peripheral.instance(), Some(3));
+
+        let added: Vec<_> = transport
+            .commands()
+            .into_iter()
+            .filter(|command| command.opcode == Command::AddAdvertising)
+            .collect();
+        assert_eq!(added.len(), 2);
+        // the instance is always connectable, and the second call replaces
+        // the instance that the kernel assigned
+        assert_eq!(added[0].param[0], 1);
+        assert_eq!(added[0].param[1] & 0x01, 0x01);
+        assert_eq!(added[1].param[0], 3);
+    }
+
+    #[tokio::test]
+    async fn management_events() {
+        let script = MockScript::new()
+            .reply(Command::SetLowEnergy, [0x00, 0x02, 0x00, 0x00])
+            .reply(Command::SetPowered, [0x01, 0x02, 0x00, 0x00])
+            .reply(Command::AddAdvertising, [0x01]);
+        let (socket, transport) = MockTransport::stream(script);
+        let mut peripheral = Peripheral::new(socket, MOCK_CONTROLLER);
+        peripheral.advertise(params(), None).await.unwrap();
+
+        transport.send_event(
+            MOCK_CONTROLLER,
+            DEVICE_CONNECTED,
+            &device_connected(MOCK_ADDRESS, AddressType::LERandom),
+        );
+        assert!(matches!(
+            peripheral.next_event(None).await.unwrap(),
+            PeripheralEvent::CentralConnected {
+                address: MOCK_ADDRESS,
+                address_type: AddressType::LERandom,
+            }
+        ));
+
+        // other instances are not the peripheral's
+        transport.send_event(MOCK_CONTROLLER, ADVERTISING_REMOVED, &[0x02]);
+        transport.send_event(MOCK_CONTROLLER, ADVERTISING_REMOVED, &[0x01]);
+        let (event_tx, mut event_rx) = mpsc::channel(1);
+        assert!(matches!(
+            peripheral.next_event(Some(event_tx)).await.unwrap(),
+            PeripheralEvent::AdvertisingStopped
+        ));
+        assert!(matches!(
+            event_rx.recv().await.unwrap().event,
+            Event::AdvertisingRemoved { instance: 2 }
+        ));
+        assert_eq!(peripheral.instance(), None);
+
+        let mut disconnected = address_param(MOCK_ADDRESS, AddressType::LERandom);
+        disconnected.push(0x03);
+        transport.send_event(MOCK_CONTROLLER, DEVICE_DISCONNECTED, &disconnected);
+        assert!(matches!(
+            peripheral.next_event(None).await.unwrap(),
+            PeripheralEvent::CentralDisconnected {
+                address: MOCK_ADDRESS,
+                reason: DisconnectionReason::TerminatedRemote,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn gatt_events() {
+        let (socket, transport) = MockTransport::stream(MockScript::new());
+        let mut peripheral = Peripheral::new(socket, MOCK_CONTROLLER);
+
+        let handles = peripheral.gatt_server_mut().add_service(
+            0x180Fu16,
+            vec![
+                Characteristic::new(
+                    0x2A19u16,
+                    CharacteristicProperty::Read | CharacteristicProperty::Notify,
+                    Bytes::from_static(&[0x64]),
+                ),
+                Characteristic::new(0x2A3Du16, CharacteristicProperty::Write, Bytes::new()),
+            ],
+        );
+        assert_eq!(handles, vec![3, 6]);
+
+        // a bearer as if it had been accepted from the ATT listener
+        let (stream, mut central) = BluetoothStream::pair(crate::Protocol::L2CAP).unwrap();
+        peripheral
+            .connections
+            .push((MOCK_ADDRESS, GattConnection::new(stream)));
+
+        // the peripheral may answer several requests before the central
+        // reads, and the read of tokio would take the first short packet as
+        // the end of the queued ones
+        async fn receive(central: &mut tokio::net::UnixStream) -> AttPdu {
+            let mut buf = [0; 64];
+            loop {
+                central.readable().await.unwrap();
+
+                match central.try_read(&mut buf) {
+                    Ok(len) => return AttPdu::parse(Bytes::copy_from_slice(&buf[..len])).unwrap(),
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                    Err(err) => panic!("{}", err),
+                }
+            }
+        }
+
+        // reads are answered without an event
+        let requests = [
+            AttPdu::ReadRequest { handle: 3 },
+            AttPdu::WriteRequest {
+                handle: 6,
+                value: Bytes::from_static(b"on"),
+            },
+        ];
+        for request in &requests {
+            central.write_all(&request.encode()[..]).await.unwrap();
+        }
+
+        let (event, response) = futures::join!(peripheral.next_event(None), async {
+            let response = receive(&mut central).await;
+            assert_eq!(
+                response,
+                AttPdu::ReadResponse {
+                    value: Bytes::from_static(&[0x64]),
+                }
+            );
+            receive(&mut central).await
+        });
+        assert_eq!(response, AttPdu::WriteResponse);
+        match event.unwrap() {
+            PeripheralEvent::Written {
+                address,
+                handle,
+                value,
+            } => {
+                assert_eq!(address, MOCK_ADDRESS);
+                assert_eq!(handle, 6);
+                assert_eq!(&value[..], b"on");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let subscribe = AttPdu::WriteRequest {
+            handle: 4,
+            value: Bytes::from_static(&[0x01, 0x00]),
+        };
+        central.write_all(&subscribe.encode()[..]).await.unwrap();
+        assert!(matches!(
+            peripheral.next_event(None).await.unwrap(),
+            PeripheralEvent::Subscribed {
+                address: MOCK_ADDRESS,
+                handle: 3,
+                notify: true,
+                indicate: false,
+            }
+        ));
+        assert_eq!(receive(&mut central).await, AttPdu::WriteResponse);
+
+        let value = Bytes::from_static(&[0x32]);
+        assert_eq!(peripheral.notify(3, value.clone()).await.unwrap(), 1);
+        assert_eq!(
+            receive(&mut central).await,
+            AttPdu::HandleValueNotification { handle: 3, value }
+        );
+        assert!(peripheral.notify(5, Bytes::new()).await.is_err());
+
+        // a closed bearer is dropped without an event, before the next
+        // event arrives
+        drop(central);
+        let (event, ()) = futures::join!(peripheral.next_event(None), async {
+            tokio::task::yield_now().await;
+            transport.send_event(
+                MOCK_CONTROLLER,
+                DEVICE_CONNECTED,
+                &device_connected(MOCK_ADDRESS, AddressType::LERandom),
+            );
+        });
+        assert!(matches!(
+            event.unwrap(),
+            PeripheralEvent::CentralConnected { .. }
+        ));
+        assert!(peripheral.connections.is_empty());
+    }
+}
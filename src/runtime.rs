@@ -0,0 +1,227 @@
+//! The integration of sockets with an async runtime.
+//!
+//! A [`Reactor`] tells tasks when a socket can be read or written. The
+//! [`ManagementStream`](crate::management::ManagementStream) is driven by a
+//! reactor, which is [`TokioReactor`] unless the stream is opened with
+//! [`ManagementStream::open_with`](crate::management::ManagementStream::open_with).
+//! This lets applications that use async-std or smol run the commands of the
+//! management API without a tokio runtime. For example, a reactor based on
+//! `async-io`, which both of them use, looks like this:
+//!
+//! ```ignore
+//! struct AsyncIoReactor;
+//!
+//! impl Reactor for AsyncIoReactor {
+//!     fn register(&self, fd: OwnedFd) -> io::Result<Box<dyn Registration>> {
+//!         Ok(Box::new(AsyncIoRegistration(async_io::Async::new(fd)?)))
+//!     }
+//! }
+//!
+//! struct AsyncIoRegistration(async_io::Async<OwnedFd>);
+//!
+//! impl Registration for AsyncIoRegistration {
+//!     fn poll_read_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+//!         loop {
+//!             match op(self.0.as_raw_fd()) {
+//!                 Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+//!                     ready!(self.0.poll_readable(cx))?
+//!                 }
+//!                 res => return Poll::Ready(res),
+//!             }
+//!         }
+//!     }
+//!
+//!     // poll_write_with is the same with poll_writable
+//!
+//!     fn into_fd(self: Box<Self>) -> io::Result<OwnedFd> {
+//!         self.0.into_inner()
+//!     }
+//! }
+//! ```
+//!
+//! Commands that wait for a timeout, such as
+//! [`make_limited_discoverable`](crate::management::make_limited_discoverable),
+//! use tokio timers, and the sockets of the
+//! [`communication`](crate::communication) module implement the I/O traits of
+//! tokio, so they still need a tokio runtime.
+
+use std::io;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::task::{Context, Poll};
+
+use futures::ready;
+use tokio::io::unix::AsyncFd;
+
+/// An operation on a non-blocking socket, which returns
+/// [`io::ErrorKind::WouldBlock`] if the socket is not ready.
+pub type IoOp<'a> = &'a mut dyn FnMut(RawFd) -> io::Result<usize>;
+
+/// Registers sockets with an async runtime.
+pub trait Reactor {
+    /// Registers a non-blocking socket, so that tasks which wait for it are
+    /// woken up when it becomes readable or writable.
+    fn register(&self, fd: OwnedFd) -> io::Result<Box<dyn Registration>>;
+}
+
+/// A socket which was registered with a [`Reactor`].
+pub trait Registration: AsRawFd + Send + Sync {
+    /// Runs `op` once the socket is readable, until it does not fail with
+    /// [`io::ErrorKind::WouldBlock`]. Registers the current task to be woken
+    /// up if the socket is not readable.
+    fn poll_read_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>>;
+
+    /// Like [`poll_read_with`](Registration::poll_read_with), but waits for
+    /// the socket to be writable.
+    fn poll_write_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>>;
+
+    /// Deregisters the socket, and returns it.
+    fn into_fd(self: Box<Self>) -> io::Result<OwnedFd>;
+}
+
+/// The reactor of the tokio runtime that the calling task runs on. Sockets
+/// have to be registered inside of a tokio runtime.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TokioReactor;
+
+impl Reactor for TokioReactor {
+    fn register(&self, fd: OwnedFd) -> io::Result<Box<dyn Registration>> {
+        Ok(Box::new(TokioRegistration(AsyncFd::new(fd)?)))
+    }
+}
+
+struct TokioRegistration(AsyncFd<OwnedFd>);
+
+impl AsRawFd for TokioRegistration {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Registration for TokioRegistration {
+    fn poll_read_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.0.poll_read_ready(cx))?;
+
+            match guard.try_io(|fd| op(fd.as_raw_fd())) {
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_write_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.0.poll_write_ready(cx))?;
+
+            match guard.try_io(|fd| op(fd.as_raw_fd())) {
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn into_fd(self: Box<Self>) -> io::Result<OwnedFd> {
+        Ok(self.0.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixStream;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::management::{Command, Controller, Event, ManagementStream, Request};
+
+    /// A reactor which wakes the task right away whenever a socket is not
+    /// ready, so that the stream can be driven without any runtime.
+    struct SpinReactor;
+
+    struct SpinRegistration(OwnedFd);
+
+    impl AsRawFd for SpinRegistration {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl SpinRegistration {
+        fn poll_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+            match op(self.0.as_raw_fd()) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            }
+        }
+    }
+
+    impl Registration for SpinRegistration {
+        fn poll_read_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+            self.poll_with(cx, op)
+        }
+
+        fn poll_write_with(&self, cx: &mut Context<'_>, op: IoOp<'_>) -> Poll<io::Result<usize>> {
+            self.poll_with(cx, op)
+        }
+
+        fn into_fd(self: Box<Self>) -> io::Result<OwnedFd> {
+            Ok(self.0)
+        }
+    }
+
+    impl Reactor for SpinReactor {
+        fn register(&self, fd: OwnedFd) -> io::Result<Box<dyn Registration>> {
+            Ok(Box::new(SpinRegistration(fd)))
+        }
+    }
+
+    #[test]
+    fn without_tokio() {
+        let mut fds = [0 as RawFd; 2];
+        crate::util::check_error(unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        })
+        .unwrap();
+
+        let (ours, mut theirs) = unsafe {
+            (
+                OwnedFd::from_raw_fd(fds[0]),
+                UnixStream::from_raw_fd(fds[1]),
+            )
+        };
+        let mut stream = ManagementStream::from_fd(ours, &SpinReactor).unwrap();
+
+        futures::executor::block_on(stream.send(Request {
+            opcode: Command::ReadVersionInfo,
+            controller: Controller::none(),
+            param: Bytes::new(),
+        }))
+        .unwrap();
+
+        let mut buf = [0u8; 16];
+        let len = theirs.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[0x01, 0x00, 0xFF, 0xFF, 0x00, 0x00]);
+
+        // New Settings on controller 0: powered
+        theirs
+            .write_all(&[0x06, 0x00, 0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00])
+            .unwrap();
+
+        let response = futures::executor::block_on(stream.receive()).unwrap();
+        assert_eq!(response.controller, Controller(0));
+        assert!(matches!(response.event, Event::NewSettings { .. }));
+
+        let fd = stream.try_into_raw_fd().unwrap();
+        drop(unsafe { OwnedFd::from_raw_fd(fd) });
+    }
+}
@@ -0,0 +1,331 @@
+//! The cryptographic toolbox of the Security Manager.
+//!
+//! These functions compute the values that are exchanged during pairing,
+//! such as the confirm values of LE legacy pairing and the DHKey checks of
+//! LE Secure Connections, for stacks that run SMP themselves and for
+//! checking out-of-band data. Like the PDUs in [`smp`](super::smp), all
+//! inputs and outputs are in little-endian order, the order in which they
+//! are sent over the air; the specification writes them with the most
+//! significant byte first.
+
+use std::convert::TryInto;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+use crate::{Address, AddressType};
+
+/// The salt of [`f5`].
+const F5_SALT: [u8; 16] = [
+    0xbe, 0x83, 0x60, 0x5a, 0xdb, 0x0b, 0x37, 0x60, 0x38, 0xa5, 0xf5, 0xaa, 0x91, 0x83, 0x88, 0x6c,
+];
+
+/// The key ID of [`f5`], "btle".
+const F5_KEY_ID: [u8; 4] = [0x65, 0x6c, 0x74, 0x62];
+
+fn reversed<const N: usize>(mut value: [u8; N]) -> [u8; N] {
+    value.reverse();
+    value
+}
+
+fn encrypt_block(cipher: &Aes128, block: [u8; 16]) -> [u8; 16] {
+    let mut block = GenericArray::from(block);
+    cipher.encrypt_block(&mut block);
+    block.into()
+}
+
+fn xor(a: [u8; 16], b: &[u8; 16]) -> [u8; 16] {
+    let mut out = a;
+    out.iter_mut().zip(b).for_each(|(a, b)| *a ^= b);
+    out
+}
+
+/// Doubles `block` in GF(2^128), as the subkeys of CMAC are derived.
+fn double(block: [u8; 16]) -> [u8; 16] {
+    let mut out = [0u8; 16];
+
+    for i in 0..16 {
+        let carry = block.get(i + 1).map_or(0, |next| next >> 7);
+        out[i] = (block[i] << 1) | carry;
+    }
+
+    if block[0] & 0x80 != 0 {
+        out[15] ^= 0x87;
+    }
+
+    out
+}
+
+/// AES-CMAC as defined in RFC 4493, with the most significant byte first.
+fn aes_cmac_msb(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    let cipher = Aes128::new(&GenericArray::from(*key));
+
+    let k1 = double(encrypt_block(&cipher, [0u8; 16]));
+    let k2 = double(k1);
+
+    let blocks = message.len().max(1).div_ceil(16);
+    let (head, last) = message.split_at((blocks - 1) * 16);
+
+    let mut x = [0u8; 16];
+    for block in head.chunks(16) {
+        x = encrypt_block(&cipher, xor(x, block.try_into().unwrap()));
+    }
+
+    let last = if last.len() == 16 {
+        xor(last.try_into().unwrap(), &k1)
+    } else {
+        let mut padded = [0u8; 16];
+        padded[..last.len()].copy_from_slice(last);
+        padded[last.len()] = 0x80;
+        xor(padded, &k2)
+    };
+
+    encrypt_block(&cipher, xor(x, &last))
+}
+
+/// AES-CMAC of `message` with `key`, both in little-endian order.
+fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    let mut message = message.to_vec();
+    message.reverse();
+
+    reversed(aes_cmac_msb(&reversed(*key), &message))
+}
+
+/// The 7-byte form of an address that [`f5`] and [`f6`] use: the address
+/// followed by 0 for public and 1 for random addresses.
+fn address_with_type(address: Address, address_type: AddressType) -> [u8; 7] {
+    let mut out = [0u8; 7];
+    out[..6].copy_from_slice(address.as_ref());
+    out[6] = (address_type == AddressType::LERandom) as u8;
+    out
+}
+
+/// The security function `e`, AES-128 of `data` with `key`.
+pub fn e(key: &[u8; 16], data: &[u8; 16]) -> [u8; 16] {
+    let cipher = Aes128::new(&GenericArray::from(reversed(*key)));
+    reversed(encrypt_block(&cipher, reversed(*data)))
+}
+
+/// The random address hash function `ah`, which computes the hash of a
+/// resolvable private address from its 24-bit `prand` and an identity
+/// resolving key. See [`rpa`](super::rpa).
+pub fn ah(irk: &[u8; 16], prand: [u8; 3]) -> [u8; 3] {
+    let mut data = [0u8; 16];
+    data[..3].copy_from_slice(&prand);
+
+    let out = e(irk, &data);
+    [out[0], out[1], out[2]]
+}
+
+/// The confirm value generation function `c1` of LE legacy pairing.
+///
+/// `preq` and `pres` are the Pairing Request and Pairing Response PDUs,
+/// including their opcode. `ia` and `ra` are the addresses of the initiator
+/// and the responder.
+#[allow(clippy::too_many_arguments)]
+pub fn c1(
+    k: &[u8; 16],
+    r: &[u8; 16],
+    preq: &[u8; 7],
+    pres: &[u8; 7],
+    ia: Address,
+    iat: AddressType,
+    ra: Address,
+    rat: AddressType,
+) -> [u8; 16] {
+    let mut p1 = [0u8; 16];
+    p1[0] = (iat == AddressType::LERandom) as u8;
+    p1[1] = (rat == AddressType::LERandom) as u8;
+    p1[2..9].copy_from_slice(preq);
+    p1[9..].copy_from_slice(pres);
+
+    let mut p2 = [0u8; 16];
+    p2[..6].copy_from_slice(ra.as_ref());
+    p2[6..12].copy_from_slice(ia.as_ref());
+
+    e(k, &xor(e(k, &xor(*r, &p1)), &p2))
+}
+
+/// The key generation function `s1` of LE legacy pairing, which computes the
+/// STK from the random values `r1` of the responder and `r2` of the
+/// initiator.
+pub fn s1(k: &[u8; 16], r1: &[u8; 16], r2: &[u8; 16]) -> [u8; 16] {
+    let mut r = [0u8; 16];
+    r[..8].copy_from_slice(&r2[..8]);
+    r[8..].copy_from_slice(&r1[..8]);
+
+    e(k, &r)
+}
+
+/// The confirm value generation function `f4` of LE Secure Connections.
+/// `u` and `v` are the X coordinates of public keys, `x` is a random value
+/// and `z` is 0, or a bit of the passkey for passkey entry.
+pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
+    let mut m = [0u8; 65];
+    m[0] = z;
+    m[1..33].copy_from_slice(v);
+    m[33..].copy_from_slice(u);
+
+    aes_cmac(x, &m)
+}
+
+/// The key generation function `f5` of LE Secure Connections, which derives
+/// the MacKey and the LTK from the DHKey `w`, the random values of both
+/// devices and their addresses. Returns `(mac_key, ltk)`.
+pub fn f5(
+    w: &[u8; 32],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    a1: (Address, AddressType),
+    a2: (Address, AddressType),
+) -> ([u8; 16], [u8; 16]) {
+    let t = aes_cmac(&F5_SALT, w);
+
+    let mut m = [0u8; 53];
+    // the length of the derived key, 256 bits
+    m[0..2].copy_from_slice(&[0x00, 0x01]);
+    m[2..9].copy_from_slice(&address_with_type(a2.0, a2.1));
+    m[9..16].copy_from_slice(&address_with_type(a1.0, a1.1));
+    m[16..32].copy_from_slice(n2);
+    m[32..48].copy_from_slice(n1);
+    m[48..52].copy_from_slice(&F5_KEY_ID);
+
+    m[52] = 0;
+    let mac_key = aes_cmac(&t, &m);
+    m[52] = 1;
+    let ltk = aes_cmac(&t, &m);
+
+    (mac_key, ltk)
+}
+
+/// The check value generation function `f6` of LE Secure Connections, which
+/// computes the DHKey checks.
+///
+/// `io_cap` holds the IO Capability, OOB Data Flag and AuthReq fields of the
+/// pairing PDU of the device, in the order of the PDU.
+pub fn f6(
+    w: &[u8; 16],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    r: &[u8; 16],
+    io_cap: &[u8; 3],
+    a1: (Address, AddressType),
+    a2: (Address, AddressType),
+) -> [u8; 16] {
+    let mut m = [0u8; 65];
+    m[0..7].copy_from_slice(&address_with_type(a2.0, a2.1));
+    m[7..14].copy_from_slice(&address_with_type(a1.0, a1.1));
+    m[14..17].copy_from_slice(io_cap);
+    m[17..33].copy_from_slice(r);
+    m[33..49].copy_from_slice(n2);
+    m[49..].copy_from_slice(n1);
+
+    aes_cmac(w, &m)
+}
+
+/// The numeric comparison value generation function `g2` of LE Secure
+/// Connections, which returns the six-digit number that both users compare.
+pub fn g2(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], y: &[u8; 16]) -> u32 {
+    let mut m = [0u8; 80];
+    m[0..16].copy_from_slice(y);
+    m[16..48].copy_from_slice(v);
+    m[48..].copy_from_slice(u);
+
+    let out = aes_cmac(x, &m);
+    u32::from_le_bytes([out[0], out[1], out[2], out[3]]) % 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a value that is written with the most significant byte first,
+    /// like in the specification, into little-endian order.
+    fn le<const N: usize>(hex: &str) -> [u8; N] {
+        let hex: String = hex.split_whitespace().collect();
+        let mut out = [0u8; N];
+
+        for (i, byte) in out.iter_mut().rev().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn legacy_pairing() {
+        // the examples of the core specification, vol 3, part h, 2.2.3 and
+        // 2.2.4
+        let k = [0u8; 16];
+        let confirm = c1(
+            &k,
+            &le("5783d521 56ad6f0e 6388274e c6702ee0"),
+            &le("07071000000101"),
+            &le("05000800000302"),
+            Address::new(le("a1a2a3a4a5a6")),
+            AddressType::LERandom,
+            Address::new(le("b1b2b3b4b5b6")),
+            AddressType::LEPublic,
+        );
+        assert_eq!(confirm, le("1e1e3fef 878988ea d2a74dc5 bef13b86"));
+
+        let stk = s1(
+            &k,
+            &le("000f0e0d 0c0b0a09 11223344 55667788"),
+            &le("01020304 05060708 99aabbcc ddeeff00"),
+        );
+        assert_eq!(stk, le("9a1fe1f0 e8b0f49b 5b4216ae 796da062"));
+    }
+
+    #[test]
+    fn secure_connections() {
+        // the sample data of the core specification, vol 3, part h, d.2 to
+        // d.5
+        let u = le("20b003d2 f297be2c 5e2c83a7 e9f9a5b9 eff49111 acf4fddb cc030148 0e359de6");
+        let v = le("55188b3d 32f6bb9a 900afcfb eed4e72a 59cb9ac2 f19d7cfb 6b4fdd49 f47fc5fd");
+        let n1 = le("d5cb8454 d177733e ffffb2ec 712baeab");
+        let n2 = le("a6e8e7cc 25a75f6e 216583f7 ff3dc4cf");
+        let a1 = (Address::new(le("56123737bfce")), AddressType::LEPublic);
+        let a2 = (Address::new(le("a713702dcfc1")), AddressType::LEPublic);
+
+        assert_eq!(
+            f4(&u, &v, &n1, 0),
+            le("f2c916f1 07a9bd1c f1eda1be a974872d")
+        );
+
+        let w = le("ec0234a3 57c8ad05 341010a6 0a397d9b 99796b13 b4f866f1 868d34f3 73bfa698");
+        let (mac_key, ltk) = f5(&w, &n1, &n2, a1, a2);
+        assert_eq!(mac_key, le("2965f176 a1084a02 fd3f6a20 ce636e20"));
+        assert_eq!(ltk, le("69867911 69d7cd23 980522b5 94750a38"));
+
+        let check = f6(
+            &mac_key,
+            &n1,
+            &n2,
+            &le("12a3343b b453bb54 08da42d2 0c2d0fc8"),
+            &le("010102"),
+            a1,
+            a2,
+        );
+        assert_eq!(check, le("e3c47398 9cd0e8c5 d26c0b09 da958f61"));
+
+        assert_eq!(g2(&u, &v, &n1, &n2), 0x2f9ed5ba % 1_000_000);
+    }
+
+    #[test]
+    fn cmac() {
+        // the examples of rfc 4493
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        assert_eq!(
+            aes_cmac_msb(&key, &[]),
+            [
+                0xbb, 0x1d, 0x69, 0x29, 0xe9, 0x59, 0x37, 0x28, 0x7f, 0xa3, 0x7d, 0x12, 0x9b, 0x75,
+                0x67, 0x46
+            ]
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Security protocols for stacks which do not rely on the kernel's
+//! implementation of them, such as stacks that drive a controller through the
+//! HCI user channel.
+
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "crypto")]
+pub mod pairing;
+#[cfg(feature = "crypto")]
+pub mod rpa;
+pub mod smp;
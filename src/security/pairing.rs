@@ -0,0 +1,1596 @@
+//! The pairing procedure of the Security Manager, for stacks which run SMP
+//! themselves.
+//!
+//! [`Pairing`] is a state machine which does no I/O. The stack passes it the
+//! PDUs that arrive on [`SMP_CID`](super::smp::SMP_CID), the answers of the
+//! user and the changes of the link's encryption, and gets back the
+//! [`Action`]s that it has to take, such as sending PDUs or starting
+//! encryption. Both LE legacy pairing and LE Secure Connections are
+//! supported, with all of their methods: Just Works, Passkey Entry, Numeric
+//! Comparison and Out of Band. The Security Manager Timeout of 30 seconds is
+//! left to the stack, which sends nothing once it expires.
+//!
+//! The random values, keys and passkeys are taken from a random number
+//! generator that the stack passes in, which has to be cryptographically
+//! secure.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+
+use enumflags2::BitFlags;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+
+use super::crypto::{c1, f4, f5, f6, g2, s1};
+use super::smp::{
+    pairing_method, uses_secure_connections, AuthRequirement, Error, KeyDistribution, Opcode,
+    PairingFailedReason, PairingFeatures, PairingMethod, Pdu,
+};
+use crate::management::{IoCapability, Passkey};
+use crate::{Address, AddressType};
+
+/// The number of rounds of Passkey Entry with Secure Connections, one for
+/// each bit of the passkey.
+const PASSKEY_ROUNDS: u32 = 20;
+
+/// The smallest encryption key size that the specification allows.
+const MIN_KEY_SIZE: u8 = 7;
+
+type Step = Result<(), PairingFailedReason>;
+
+type Rng = Box<dyn FnMut(&mut [u8]) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// The central, which sends the Pairing Request and starts encryption.
+    Initiator,
+    Responder,
+}
+
+/// The identity that a device distributes, so that it can be recognized
+/// when it uses resolvable private addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub irk: [u8; 16],
+    pub address: Address,
+    pub address_type: AddressType,
+}
+
+/// A Long Term Key, with the EDIV and Rand values that identify it. These
+/// are 0 for keys from Secure Connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionKey {
+    pub value: [u8; 16],
+    pub ediv: u16,
+    pub rand: u64,
+}
+
+/// The values that Secure Connections exchanges out of band.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OobValues {
+    pub random: [u8; 16],
+    pub confirm: [u8; 16],
+}
+
+/// A P-256 key pair for Secure Connections.
+#[derive(Debug, Clone)]
+pub struct KeyPair {
+    secret: SecretKey,
+    x: [u8; 32],
+    y: [u8; 32],
+}
+
+impl KeyPair {
+    pub fn generate(mut rng: impl FnMut(&mut [u8])) -> Self {
+        // fewer than one in 2^32 values is not a valid private key
+        let secret = loop {
+            let mut bytes = [0u8; 32];
+            rng(&mut bytes);
+
+            if let Ok(secret) = SecretKey::from_slice(&bytes) {
+                break secret;
+            }
+        };
+
+        let point = secret.public_key().to_encoded_point(false);
+        let mut x = [0u8; 32];
+        let mut y = [0u8; 32];
+        x.copy_from_slice(point.x().unwrap());
+        y.copy_from_slice(point.y().unwrap());
+        x.reverse();
+        y.reverse();
+
+        Self { secret, x, y }
+    }
+
+    /// The coordinates of the public key, in little-endian order.
+    pub fn public_key(&self) -> ([u8; 32], [u8; 32]) {
+        (self.x, self.y)
+    }
+
+    /// Computes the DHKey with the public key of the remote device, which is
+    /// rejected if it is not on the curve.
+    fn dh_key(&self, x: &[u8; 32], y: &[u8; 32]) -> Result<[u8; 32], PairingFailedReason> {
+        let mut sec1 = [0u8; 65];
+        sec1[0] = 0x04;
+        sec1[1..33].copy_from_slice(x);
+        sec1[33..].copy_from_slice(y);
+        sec1[1..33].reverse();
+        sec1[33..].reverse();
+
+        let public = PublicKey::from_sec1_bytes(&sec1[..])
+            .map_err(|_| PairingFailedReason::InvalidParameters)?;
+        let shared =
+            p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), public.as_affine());
+
+        let mut dh_key = [0u8; 32];
+        dh_key.copy_from_slice(shared.raw_secret_bytes());
+        dh_key.reverse();
+        Ok(dh_key)
+    }
+}
+
+/// The key pair and random value of this device whose [`OobValues`] were
+/// sent to the remote device out of band, such as over NFC.
+#[derive(Debug, Clone)]
+pub struct LocalOobData {
+    key_pair: KeyPair,
+    random: [u8; 16],
+}
+
+impl LocalOobData {
+    pub fn generate(mut rng: impl FnMut(&mut [u8])) -> Self {
+        let key_pair = KeyPair::generate(&mut rng);
+        let mut random = [0u8; 16];
+        rng(&mut random);
+
+        Self { key_pair, random }
+    }
+
+    /// The values to send to the remote device.
+    pub fn values(&self) -> OobValues {
+        let x = &self.key_pair.x;
+
+        OobValues {
+            random: self.random,
+            confirm: f4(x, x, &self.random, 0),
+        }
+    }
+}
+
+/// Data that was exchanged out of band before pairing.
+#[derive(Debug, Clone)]
+pub enum OobData {
+    /// The Temporary Key of legacy pairing, which both devices know.
+    Legacy([u8; 16]),
+    SecureConnections {
+        /// The data whose values were sent to the remote device.
+        local: Option<LocalOobData>,
+        /// The values that were received from the remote device.
+        remote: Option<OobValues>,
+    },
+}
+
+/// What this device offers and requires when it pairs.
+#[derive(Debug, Clone)]
+pub struct PairingConfig {
+    pub io_capability: IoCapability,
+    /// Pairing fails if [`AuthRequirement::Mitm`] is set and the method that
+    /// the IO capabilities allow is Just Works.
+    pub auth_req: BitFlags<AuthRequirement>,
+    pub max_key_size: u8,
+    pub min_key_size: u8,
+    /// Rejects pairing if the remote device does not support Secure
+    /// Connections.
+    pub secure_connections_only: bool,
+    /// The keys that are requested from the remote device.
+    pub remote_keys: BitFlags<KeyDistribution>,
+    /// The identity to distribute. The IRK is only offered if this is set.
+    pub identity: Option<Identity>,
+    /// The CSRK to distribute. It is only offered if this is set.
+    pub csrk: Option<[u8; 16]>,
+    pub oob: Option<OobData>,
+}
+
+impl Default for PairingConfig {
+    fn default() -> Self {
+        Self {
+            io_capability: IoCapability::NoInputNoOutput,
+            auth_req: AuthRequirement::Bonding | AuthRequirement::SecureConnections,
+            max_key_size: 16,
+            min_key_size: MIN_KEY_SIZE,
+            secure_connections_only: false,
+            remote_keys: KeyDistribution::EncKey | KeyDistribution::IdKey,
+            identity: None,
+            csrk: None,
+            oob: None,
+        }
+    }
+}
+
+/// The result of a successful pairing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingKeys {
+    pub secure_connections: bool,
+    /// Whether the keys are protected against man-in-the-middle attacks.
+    pub authenticated: bool,
+    pub key_size: u8,
+    /// The key that the central encrypts the link with when it reconnects:
+    /// the LTK of Secure Connections, or the LTK that the responder
+    /// distributed with legacy pairing.
+    pub ltk: Option<EncryptionKey>,
+    /// The LTK that the initiator distributed with legacy pairing, which is
+    /// used if the devices reconnect with their roles swapped.
+    pub initiator_ltk: Option<EncryptionKey>,
+    pub remote_identity: Option<Identity>,
+    pub remote_csrk: Option<[u8; 16]>,
+}
+
+/// What the stack has to do next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Send(Pdu),
+    /// Shows the passkey to the user, who enters it on the remote device.
+    DisplayPasskey(Passkey),
+    /// Asks the user for the passkey that the remote device displays, which
+    /// is passed to [`Pairing::passkey`].
+    RequestPasskey,
+    /// Asks the user whether the remote device displays the same number. The
+    /// answer is passed to [`Pairing::confirm`].
+    ConfirmNumber(Passkey),
+    /// The initiator starts encryption with this key, and the responder
+    /// answers the LTK request of its controller with it, with an EDIV and
+    /// Rand of 0. Then the stack calls [`Pairing::encrypted`].
+    StartEncryption([u8; 16]),
+    Complete(PairingKeys),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Features,
+    Authentication,
+    DhKeyCheck,
+    Encryption,
+    KeyDistribution,
+    Complete,
+    Failed,
+}
+
+/// One pairing with a remote device, from the Pairing Request to the
+/// distribution of keys.
+///
+/// Methods that return [`Error::Aborted`] have aborted the pairing, and the
+/// stack has to send a [`Pdu::PairingFailed`] with the reason.
+pub struct Pairing {
+    role: Role,
+    config: PairingConfig,
+    initiator: (Address, AddressType),
+    responder: (Address, AddressType),
+    rng: Rng,
+    phase: Phase,
+
+    preq: [u8; 7],
+    pres: [u8; 7],
+    initiator_features: Option<PairingFeatures>,
+    responder_features: Option<PairingFeatures>,
+    method: PairingMethod,
+    initiator_keys: BitFlags<KeyDistribution>,
+    responder_keys: BitFlags<KeyDistribution>,
+
+    passkey: Option<u32>,
+    waiting_for_passkey: bool,
+    local_random: [u8; 16],
+    remote_random: Option<[u8; 16]>,
+    remote_confirm: Option<[u8; 16]>,
+    confirm_sent: bool,
+    random_sent: bool,
+
+    key_pair: Option<KeyPair>,
+    remote_public: Option<[u8; 32]>,
+    dh_key: [u8; 32],
+    round: u32,
+    waiting_for_confirmation: bool,
+    confirmed: bool,
+    mac_key: [u8; 16],
+    ltk: [u8; 16],
+    remote_check: Option<[u8; 16]>,
+    check_sent: bool,
+
+    expected: VecDeque<Opcode>,
+    pending_key: [u8; 16],
+    local_keys_sent: bool,
+    keys: PairingKeys,
+}
+
+impl Pairing {
+    /// Starts pairing as the initiator. `local` and `remote` are the
+    /// addresses that the link was created with.
+    pub fn initiate(
+        config: PairingConfig,
+        local: (Address, AddressType),
+        remote: (Address, AddressType),
+        rng: impl FnMut(&mut [u8]) + Send + 'static,
+    ) -> (Self, Vec<Action>) {
+        let mut pairing = Self::new(Role::Initiator, config, local, remote, Box::new(rng));
+
+        let request = pairing.features();
+        pairing.initiator_features = Some(request);
+        pairing.preq = pdu_bytes(&Pdu::PairingRequest(request));
+
+        (pairing, vec![Action::Send(Pdu::PairingRequest(request))])
+    }
+
+    /// Prepares to pair as the responder, once the Pairing Request arrives.
+    pub fn respond(
+        config: PairingConfig,
+        local: (Address, AddressType),
+        remote: (Address, AddressType),
+        rng: impl FnMut(&mut [u8]) + Send + 'static,
+    ) -> Self {
+        Self::new(Role::Responder, config, remote, local, Box::new(rng))
+    }
+
+    fn new(
+        role: Role,
+        config: PairingConfig,
+        initiator: (Address, AddressType),
+        responder: (Address, AddressType),
+        rng: Rng,
+    ) -> Self {
+        Self {
+            role,
+            config,
+            initiator,
+            responder,
+            rng,
+            phase: Phase::Features,
+            preq: [0; 7],
+            pres: [0; 7],
+            initiator_features: None,
+            responder_features: None,
+            method: PairingMethod::JustWorks,
+            initiator_keys: BitFlags::empty(),
+            responder_keys: BitFlags::empty(),
+            passkey: None,
+            waiting_for_passkey: false,
+            local_random: [0; 16],
+            remote_random: None,
+            remote_confirm: None,
+            confirm_sent: false,
+            random_sent: false,
+            key_pair: None,
+            remote_public: None,
+            dh_key: [0; 32],
+            round: 0,
+            waiting_for_confirmation: false,
+            confirmed: false,
+            mac_key: [0; 16],
+            ltk: [0; 16],
+            remote_check: None,
+            check_sent: false,
+            expected: VecDeque::new(),
+            pending_key: [0; 16],
+            local_keys_sent: false,
+            keys: PairingKeys {
+                secure_connections: false,
+                authenticated: false,
+                key_size: 0,
+                ltk: None,
+                initiator_ltk: None,
+                remote_identity: None,
+                remote_csrk: None,
+            },
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// The method that was selected, once the features have been exchanged.
+    pub fn method(&self) -> Option<PairingMethod> {
+        match self.phase {
+            Phase::Features => None,
+            _ => Some(self.method),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.phase == Phase::Complete
+    }
+
+    /// Handles a PDU from the remote device.
+    pub fn handle(&mut self, pdu: Pdu) -> Result<Vec<Action>, Error> {
+        match pdu {
+            Pdu::PairingFailed(reason) => {
+                self.phase = Phase::Failed;
+                return Err(Error::PairingFailed(reason));
+            }
+            // keypresses are only informational, and security requests ask
+            // for the pairing that is already running
+            Pdu::KeypressNotification(_) | Pdu::SecurityRequest(_) => return Ok(vec![]),
+            _ => {}
+        }
+
+        if matches!(self.phase, Phase::Complete | Phase::Failed) {
+            return Err(Error::UnexpectedPdu(pdu.opcode()));
+        }
+
+        self.step(|this, actions| match (this.phase, this.role, pdu) {
+            (Phase::Features, Role::Responder, Pdu::PairingRequest(request)) => {
+                this.on_request(request, actions)
+            }
+            (Phase::Features, Role::Initiator, Pdu::PairingResponse(response)) => {
+                this.on_response(response, actions)
+            }
+            (Phase::Authentication, _, Pdu::PairingPublicKey { x, y }) => {
+                this.on_public_key(x, y, actions)
+            }
+            (Phase::Authentication, _, Pdu::PairingConfirm(confirm)) => {
+                this.on_confirm(confirm, actions)
+            }
+            (Phase::Authentication, _, Pdu::PairingRandom(random)) => {
+                this.on_random(random, actions)
+            }
+            (Phase::DhKeyCheck, _, Pdu::PairingDhKeyCheck(check)) => {
+                this.on_dh_key_check(check, actions)
+            }
+            (Phase::KeyDistribution, _, pdu) => this.on_key(pdu, actions),
+            _ => Err(PairingFailedReason::UnspecifiedReason),
+        })
+    }
+
+    /// Passes the passkey that the user entered after
+    /// [`Action::RequestPasskey`].
+    pub fn passkey(&mut self, passkey: Passkey) -> Result<Vec<Action>, Error> {
+        if !self.waiting_for_passkey {
+            return Err(Error::UnexpectedInput);
+        }
+
+        self.waiting_for_passkey = false;
+        self.passkey = Some(passkey.value());
+
+        self.step(|this, actions| {
+            if this.keys.secure_connections {
+                this.sc_progress(actions)
+            } else {
+                this.legacy_progress(actions)
+            }
+        })
+    }
+
+    /// Passes whether the user confirmed that the numbers match after
+    /// [`Action::ConfirmNumber`].
+    pub fn confirm(&mut self, matches: bool) -> Result<Vec<Action>, Error> {
+        if !self.waiting_for_confirmation {
+            return Err(Error::UnexpectedInput);
+        }
+
+        self.waiting_for_confirmation = false;
+
+        self.step(|this, actions| {
+            if !matches {
+                return Err(PairingFailedReason::NumericComparisonFailed);
+            }
+
+            this.confirmed = true;
+            this.check_progress(actions)
+        })
+    }
+
+    /// Tells the pairing that the link has been encrypted with the key of
+    /// [`Action::StartEncryption`], so that the keys can be distributed.
+    pub fn encrypted(&mut self) -> Result<Vec<Action>, Error> {
+        if self.phase != Phase::Encryption {
+            return Err(Error::UnexpectedInput);
+        }
+
+        self.step(|this, actions| {
+            this.phase = Phase::KeyDistribution;
+
+            let remote_keys = match this.role {
+                Role::Initiator => this.responder_keys,
+                Role::Responder => this.initiator_keys,
+            };
+
+            for key in remote_keys.iter() {
+                this.expected.extend(match key {
+                    KeyDistribution::EncKey => {
+                        &[Opcode::EncryptionInformation, Opcode::CentralIdentification][..]
+                    }
+                    KeyDistribution::IdKey => &[
+                        Opcode::IdentityInformation,
+                        Opcode::IdentityAddressInformation,
+                    ][..],
+                    KeyDistribution::SignKey => &[Opcode::SigningInformation][..],
+                    KeyDistribution::LinkKey => &[][..],
+                });
+            }
+
+            // the responder distributes its keys first
+            if this.role == Role::Responder {
+                this.send_keys(actions);
+            }
+
+            this.distribution_progress(actions)
+        })
+    }
+
+    /// Aborts the pairing, for example because the user declined it, and
+    /// returns the PDU to send.
+    pub fn abort(&mut self, reason: PairingFailedReason) -> Pdu {
+        self.phase = Phase::Failed;
+        Pdu::PairingFailed(reason)
+    }
+
+    fn step(
+        &mut self,
+        f: impl FnOnce(&mut Self, &mut Vec<Action>) -> Step,
+    ) -> Result<Vec<Action>, Error> {
+        let mut actions = vec![];
+
+        match f(self, &mut actions) {
+            Ok(()) => Ok(actions),
+            Err(reason) => {
+                self.phase = Phase::Failed;
+                Err(Error::Aborted(reason))
+            }
+        }
+    }
+
+    fn random<const N: usize>(&mut self) -> [u8; N] {
+        let mut value = [0u8; N];
+        (self.rng)(&mut value);
+        value
+    }
+
+    /// The features of this device, before they are matched with the
+    /// request of the initiator.
+    fn features(&self) -> PairingFeatures {
+        let mut local_keys = BitFlags::from(KeyDistribution::EncKey);
+
+        if self.config.identity.is_some() {
+            local_keys |= KeyDistribution::IdKey;
+        }
+
+        if self.config.csrk.is_some() {
+            local_keys |= KeyDistribution::SignKey;
+        }
+
+        let (initiator_keys, responder_keys) = match self.role {
+            Role::Initiator => (local_keys, self.config.remote_keys),
+            Role::Responder => (self.config.remote_keys, local_keys),
+        };
+
+        PairingFeatures {
+            io_capability: self.config.io_capability,
+            oob_data_present: self.has_remote_oob(),
+            auth_req: self.config.auth_req,
+            max_key_size: self.config.max_key_size,
+            initiator_keys,
+            responder_keys,
+        }
+    }
+
+    fn has_remote_oob(&self) -> bool {
+        matches!(
+            self.config.oob,
+            Some(OobData::Legacy(_))
+                | Some(OobData::SecureConnections {
+                    remote: Some(_),
+                    ..
+                })
+        )
+    }
+
+    fn remote_features(&self) -> &PairingFeatures {
+        match self.role {
+            Role::Initiator => self.responder_features.as_ref(),
+            Role::Responder => self.initiator_features.as_ref(),
+        }
+        .unwrap()
+    }
+
+    fn on_request(&mut self, request: PairingFeatures, actions: &mut Vec<Action>) -> Step {
+        let local = self.features();
+        let response = PairingFeatures {
+            initiator_keys: request.initiator_keys & local.initiator_keys,
+            responder_keys: request.responder_keys & local.responder_keys,
+            ..local
+        };
+
+        self.preq = pdu_bytes(&Pdu::PairingRequest(request));
+        self.pres = pdu_bytes(&Pdu::PairingResponse(response));
+        self.initiator_features = Some(request);
+        self.responder_features = Some(response);
+
+        self.negotiate()?;
+        actions.push(Action::Send(Pdu::PairingResponse(response)));
+        self.start_authentication(actions)
+    }
+
+    fn on_response(&mut self, response: PairingFeatures, actions: &mut Vec<Action>) -> Step {
+        self.pres = pdu_bytes(&Pdu::PairingResponse(response));
+        self.responder_features = Some(response);
+
+        self.negotiate()?;
+        self.start_authentication(actions)
+    }
+
+    fn negotiate(&mut self) -> Step {
+        let initiator = self.initiator_features.unwrap();
+        let responder = self.responder_features.unwrap();
+
+        let key_size = initiator.max_key_size.min(responder.max_key_size);
+        if key_size < self.config.min_key_size.max(MIN_KEY_SIZE) {
+            return Err(PairingFailedReason::EncryptionKeySize);
+        }
+
+        let secure_connections = uses_secure_connections(&initiator, &responder);
+        if !secure_connections && self.config.secure_connections_only {
+            return Err(PairingFailedReason::AuthenticationRequirements);
+        }
+
+        self.method = pairing_method(&initiator, &responder);
+        if self.config.auth_req.contains(AuthRequirement::Mitm) && !self.method.is_authenticated() {
+            return Err(PairingFailedReason::AuthenticationRequirements);
+        }
+
+        // the LTK of secure connections is derived rather than distributed,
+        // and the derivation of link keys for br/edr is not supported
+        let mut ignored = BitFlags::from(KeyDistribution::LinkKey);
+        if secure_connections {
+            ignored |= KeyDistribution::EncKey;
+        }
+
+        self.initiator_keys = initiator.initiator_keys & responder.initiator_keys;
+        self.initiator_keys.remove(ignored);
+        self.responder_keys = initiator.responder_keys & responder.responder_keys;
+        self.responder_keys.remove(ignored);
+
+        self.keys.secure_connections = secure_connections;
+        self.keys.authenticated = self.method.is_authenticated();
+        self.keys.key_size = key_size;
+        Ok(())
+    }
+
+    fn start_authentication(&mut self, actions: &mut Vec<Action>) -> Step {
+        self.phase = Phase::Authentication;
+        self.local_random = self.random();
+
+        if let PairingMethod::PasskeyEntry {
+            initiator_inputs,
+            responder_inputs,
+        } = self.method
+        {
+            let inputs = match self.role {
+                Role::Initiator => initiator_inputs,
+                Role::Responder => responder_inputs,
+            };
+
+            if inputs {
+                self.waiting_for_passkey = true;
+                actions.push(Action::RequestPasskey);
+            } else {
+                let passkey = u32::from_le_bytes(self.random()) % (Passkey::MAX + 1);
+                self.passkey = Some(passkey);
+                actions.push(Action::DisplayPasskey(Passkey::new(passkey).unwrap()));
+            }
+        }
+
+        if !self.keys.secure_connections {
+            if self.method == PairingMethod::OutOfBand && self.tk().is_none() {
+                return Err(PairingFailedReason::OobNotAvailable);
+            }
+
+            return self.legacy_progress(actions);
+        }
+
+        let local_oob = match &self.config.oob {
+            Some(OobData::SecureConnections {
+                local: Some(local), ..
+            }) => Some(local.key_pair.clone()),
+            _ => None,
+        };
+
+        // the remote device can only check our public key against the
+        // values that it received, if it was generated along with them
+        if self.remote_features().oob_data_present && local_oob.is_none() {
+            return Err(PairingFailedReason::OobNotAvailable);
+        }
+
+        let key_pair = match local_oob {
+            Some(key_pair) => key_pair,
+            None => KeyPair::generate(&mut self.rng),
+        };
+
+        if self.role == Role::Initiator {
+            actions.push(Action::Send(Pdu::PairingPublicKey {
+                x: key_pair.x,
+                y: key_pair.y,
+            }));
+        }
+
+        self.key_pair = Some(key_pair);
+        Ok(())
+    }
+
+    /// The Temporary Key of legacy pairing, once it is known.
+    fn tk(&self) -> Option<[u8; 16]> {
+        match self.method {
+            PairingMethod::PasskeyEntry { .. } => self.passkey.map(passkey_value),
+            PairingMethod::OutOfBand => match &self.config.oob {
+                Some(OobData::Legacy(tk)) => Some(*tk),
+                _ => None,
+            },
+            _ => Some([0; 16]),
+        }
+    }
+
+    fn legacy_confirm(&self, tk: &[u8; 16], random: &[u8; 16]) -> [u8; 16] {
+        c1(
+            tk,
+            random,
+            &self.preq,
+            &self.pres,
+            self.initiator.0,
+            self.initiator.1,
+            self.responder.0,
+            self.responder.1,
+        )
+    }
+
+    /// Sends the confirm value of legacy pairing once the TK is known, which
+    /// the responder only does after it received the initiator's.
+    fn legacy_progress(&mut self, actions: &mut Vec<Action>) -> Step {
+        let tk = match self.tk() {
+            Some(tk) => tk,
+            None => return Ok(()),
+        };
+
+        let may_confirm = self.role == Role::Initiator || self.remote_confirm.is_some();
+
+        if !self.confirm_sent && may_confirm {
+            let confirm = self.legacy_confirm(&tk, &self.local_random);
+            self.confirm_sent = true;
+            actions.push(Action::Send(Pdu::PairingConfirm(confirm)));
+        }
+
+        Ok(())
+    }
+
+    /// The X coordinates of the public keys of the initiator and the
+    /// responder, once they have been exchanged.
+    fn public_keys(&self) -> Option<([u8; 32], [u8; 32])> {
+        let local = self.key_pair.as_ref()?.x;
+        let remote = self.remote_public?;
+
+        Some(match self.role {
+            Role::Initiator => (local, remote),
+            Role::Responder => (remote, local),
+        })
+    }
+
+    /// The bit of the passkey for the current round of Passkey Entry.
+    fn passkey_bit(&self) -> Option<u8> {
+        self.passkey
+            .map(|passkey| 0x80 | ((passkey >> self.round) & 1) as u8)
+    }
+
+    fn on_public_key(&mut self, x: [u8; 32], y: [u8; 32], actions: &mut Vec<Action>) -> Step {
+        let key_pair = match &self.key_pair {
+            Some(key_pair) if self.remote_public.is_none() => key_pair,
+            _ => return Err(PairingFailedReason::UnspecifiedReason),
+        };
+
+        // a device which reflects our own key back would know the DHKey
+        if x == key_pair.x {
+            return Err(PairingFailedReason::InvalidParameters);
+        }
+
+        self.dh_key = key_pair.dh_key(&x, &y)?;
+
+        if self.role == Role::Responder {
+            actions.push(Action::Send(Pdu::PairingPublicKey {
+                x: key_pair.x,
+                y: key_pair.y,
+            }));
+        }
+
+        self.remote_public = Some(x);
+
+        if self.method == PairingMethod::OutOfBand && self.has_remote_oob() {
+            match &self.config.oob {
+                Some(OobData::SecureConnections {
+                    remote: Some(values),
+                    ..
+                }) => {
+                    if f4(&x, &x, &values.random, 0) != values.confirm {
+                        return Err(PairingFailedReason::ConfirmValueFailed);
+                    }
+                }
+                _ => return Err(PairingFailedReason::OobNotAvailable),
+            }
+        }
+
+        self.sc_progress(actions)
+    }
+
+    /// Sends the confirm and random values of Secure Connections once the
+    /// values that they depend on are known.
+    fn sc_progress(&mut self, actions: &mut Vec<Action>) -> Step {
+        let (pka, pkb) = match self.public_keys() {
+            Some(keys) => keys,
+            None => return Ok(()),
+        };
+
+        match (self.role, self.method) {
+            (Role::Initiator, PairingMethod::JustWorks)
+            | (Role::Initiator, PairingMethod::NumericComparison) => {
+                if self.remote_confirm.is_some() && !self.random_sent {
+                    self.send_random(actions);
+                }
+            }
+            (Role::Responder, PairingMethod::JustWorks)
+            | (Role::Responder, PairingMethod::NumericComparison) => {
+                if !self.confirm_sent {
+                    let confirm = f4(&pkb, &pka, &self.local_random, 0);
+                    self.send_confirm(confirm, actions);
+                }
+            }
+            (Role::Initiator, PairingMethod::PasskeyEntry { .. }) => {
+                if let Some(z) = self.passkey_bit() {
+                    if !self.confirm_sent {
+                        let confirm = f4(&pka, &pkb, &self.local_random, z);
+                        self.send_confirm(confirm, actions);
+                    } else if self.remote_confirm.is_some() && !self.random_sent {
+                        self.send_random(actions);
+                    }
+                }
+            }
+            (Role::Responder, PairingMethod::PasskeyEntry { .. }) => {
+                if let Some(z) = self.passkey_bit() {
+                    if self.remote_confirm.is_some() && !self.confirm_sent {
+                        let confirm = f4(&pkb, &pka, &self.local_random, z);
+                        self.send_confirm(confirm, actions);
+                    }
+                }
+            }
+            (Role::Initiator, PairingMethod::OutOfBand) => {
+                if !self.random_sent {
+                    self.send_random(actions);
+                }
+            }
+            (Role::Responder, PairingMethod::OutOfBand) => {}
+        }
+
+        Ok(())
+    }
+
+    fn send_confirm(&mut self, confirm: [u8; 16], actions: &mut Vec<Action>) {
+        self.confirm_sent = true;
+        actions.push(Action::Send(Pdu::PairingConfirm(confirm)));
+    }
+
+    fn send_random(&mut self, actions: &mut Vec<Action>) {
+        self.random_sent = true;
+        actions.push(Action::Send(Pdu::PairingRandom(self.local_random)));
+    }
+
+    fn on_confirm(&mut self, confirm: [u8; 16], actions: &mut Vec<Action>) -> Step {
+        let secure_connections = self.keys.secure_connections;
+        let expected = match (self.role, self.method) {
+            (Role::Initiator, _) if !secure_connections => self.confirm_sent,
+            (Role::Responder, _) if !secure_connections => true,
+            (_, PairingMethod::OutOfBand) => false,
+            (Role::Initiator, PairingMethod::PasskeyEntry { .. }) => self.confirm_sent,
+            (Role::Initiator, _) => self.remote_public.is_some(),
+            (Role::Responder, PairingMethod::PasskeyEntry { .. }) => self.remote_public.is_some(),
+            (Role::Responder, _) => false,
+        };
+
+        if !expected || self.remote_confirm.is_some() {
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+
+        self.remote_confirm = Some(confirm);
+
+        if self.keys.secure_connections {
+            self.sc_progress(actions)
+        } else if self.role == Role::Initiator {
+            self.send_random(actions);
+            Ok(())
+        } else {
+            self.legacy_progress(actions)
+        }
+    }
+
+    fn on_random(&mut self, random: [u8; 16], actions: &mut Vec<Action>) -> Step {
+        let expected = match self.role {
+            Role::Initiator => self.random_sent,
+            Role::Responder
+                if self.keys.secure_connections && self.method == PairingMethod::OutOfBand =>
+            {
+                self.remote_public.is_some()
+            }
+            Role::Responder => self.confirm_sent,
+        };
+
+        if !expected || self.remote_random.is_some() {
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+
+        if self.keys.secure_connections {
+            self.on_sc_random(random, actions)
+        } else {
+            self.on_legacy_random(random, actions)
+        }
+    }
+
+    fn on_legacy_random(&mut self, random: [u8; 16], actions: &mut Vec<Action>) -> Step {
+        let tk = self.tk().unwrap();
+
+        if Some(self.legacy_confirm(&tk, &random)) != self.remote_confirm {
+            return Err(PairingFailedReason::ConfirmValueFailed);
+        }
+
+        let stk = match self.role {
+            Role::Initiator => s1(&tk, &random, &self.local_random),
+            Role::Responder => {
+                self.send_random(actions);
+                s1(&tk, &self.local_random, &random)
+            }
+        };
+
+        self.remote_random = Some(random);
+        self.start_encryption(stk, actions);
+        Ok(())
+    }
+
+    fn on_sc_random(&mut self, random: [u8; 16], actions: &mut Vec<Action>) -> Step {
+        let (pka, pkb) = self.public_keys().unwrap();
+
+        // the responder's confirm value is checked by the initiator, and the
+        // initiator's is checked by the responder with passkey entry only
+        let expected_confirm = match (self.role, self.method) {
+            (Role::Initiator, PairingMethod::PasskeyEntry { .. }) => {
+                Some(f4(&pkb, &pka, &random, self.passkey_bit().unwrap()))
+            }
+            (Role::Responder, PairingMethod::PasskeyEntry { .. }) => {
+                Some(f4(&pka, &pkb, &random, self.passkey_bit().unwrap()))
+            }
+            (Role::Initiator, PairingMethod::OutOfBand) => None,
+            (Role::Initiator, _) => Some(f4(&pkb, &pka, &random, 0)),
+            (Role::Responder, _) => None,
+        };
+
+        if expected_confirm.is_some() && expected_confirm != self.remote_confirm {
+            return Err(PairingFailedReason::ConfirmValueFailed);
+        }
+
+        if self.role == Role::Responder {
+            self.send_random(actions);
+        }
+
+        if let PairingMethod::PasskeyEntry { .. } = self.method {
+            self.round += 1;
+
+            if self.round < PASSKEY_ROUNDS {
+                self.local_random = self.random();
+                self.remote_confirm = None;
+                self.confirm_sent = false;
+                self.random_sent = false;
+                return self.sc_progress(actions);
+            }
+        }
+
+        self.remote_random = Some(random);
+        self.start_dh_key_check(actions)
+    }
+
+    /// The random values of the initiator and the responder.
+    fn nonces(&self) -> ([u8; 16], [u8; 16]) {
+        let remote = self.remote_random.unwrap();
+
+        match self.role {
+            Role::Initiator => (self.local_random, remote),
+            Role::Responder => (remote, self.local_random),
+        }
+    }
+
+    fn start_dh_key_check(&mut self, actions: &mut Vec<Action>) -> Step {
+        self.phase = Phase::DhKeyCheck;
+
+        let (na, nb) = self.nonces();
+        let (mac_key, ltk) = f5(&self.dh_key, &na, &nb, self.initiator, self.responder);
+        self.mac_key = mac_key;
+        self.ltk = ltk;
+
+        if self.method == PairingMethod::NumericComparison {
+            let (pka, pkb) = self.public_keys().unwrap();
+            let number = Passkey::new(g2(&pka, &pkb, &na, &nb)).unwrap();
+
+            self.waiting_for_confirmation = true;
+            actions.push(Action::ConfirmNumber(number));
+            Ok(())
+        } else {
+            self.confirmed = true;
+            self.check_progress(actions)
+        }
+    }
+
+    /// The values `ra` and `rb` that the DHKey checks of the initiator and
+    /// the responder include.
+    fn check_values(&self) -> ([u8; 16], [u8; 16]) {
+        match self.method {
+            PairingMethod::PasskeyEntry { .. } => {
+                let r = passkey_value(self.passkey.unwrap());
+                (r, r)
+            }
+            PairingMethod::OutOfBand => {
+                // each side uses 0 for the value that the other did not get
+                let local = match &self.config.oob {
+                    Some(OobData::SecureConnections {
+                        local: Some(local), ..
+                    }) if self.remote_features().oob_data_present => local.random,
+                    _ => [0; 16],
+                };
+                let remote = match &self.config.oob {
+                    Some(OobData::SecureConnections {
+                        remote: Some(remote),
+                        ..
+                    }) => remote.random,
+                    _ => [0; 16],
+                };
+
+                match self.role {
+                    Role::Initiator => (local, remote),
+                    Role::Responder => (remote, local),
+                }
+            }
+            _ => ([0; 16], [0; 16]),
+        }
+    }
+
+    /// The DHKey checks of the initiator and the responder.
+    fn dh_key_checks(&self) -> ([u8; 16], [u8; 16]) {
+        let (na, nb) = self.nonces();
+        let (ra, rb) = self.check_values();
+        let io_cap = |features: &PairingFeatures| {
+            [
+                features.io_capability as u8,
+                features.oob_data_present as u8,
+                features.auth_req.bits(),
+            ]
+        };
+
+        let ea = f6(
+            &self.mac_key,
+            &na,
+            &nb,
+            &rb,
+            &io_cap(self.initiator_features.as_ref().unwrap()),
+            self.initiator,
+            self.responder,
+        );
+        let eb = f6(
+            &self.mac_key,
+            &nb,
+            &na,
+            &ra,
+            &io_cap(self.responder_features.as_ref().unwrap()),
+            self.responder,
+            self.initiator,
+        );
+
+        (ea, eb)
+    }
+
+    /// Sends the DHKey check once the user has confirmed the numbers, which
+    /// the responder only does after it has checked the initiator's.
+    fn check_progress(&mut self, actions: &mut Vec<Action>) -> Step {
+        if !self.confirmed {
+            return Ok(());
+        }
+
+        let (ea, eb) = self.dh_key_checks();
+
+        match self.role {
+            Role::Initiator if !self.check_sent => {
+                self.check_sent = true;
+                actions.push(Action::Send(Pdu::PairingDhKeyCheck(ea)));
+            }
+            Role::Initiator => {}
+            Role::Responder => {
+                if let Some(check) = self.remote_check {
+                    if check != ea {
+                        return Err(PairingFailedReason::DhKeyCheckFailed);
+                    }
+
+                    self.check_sent = true;
+                    actions.push(Action::Send(Pdu::PairingDhKeyCheck(eb)));
+                    self.start_encryption(self.ltk, actions);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_dh_key_check(&mut self, check: [u8; 16], actions: &mut Vec<Action>) -> Step {
+        if self.remote_check.is_some() || (self.role == Role::Initiator && !self.check_sent) {
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+
+        self.remote_check = Some(check);
+
+        match self.role {
+            Role::Initiator => {
+                let (_, eb) = self.dh_key_checks();
+                if check != eb {
+                    return Err(PairingFailedReason::DhKeyCheckFailed);
+                }
+
+                self.start_encryption(self.ltk, actions);
+                Ok(())
+            }
+            Role::Responder => self.check_progress(actions),
+        }
+    }
+
+    /// Shortens a key to the encryption key size that was agreed on.
+    fn shorten(&self, mut key: [u8; 16]) -> [u8; 16] {
+        key[self.keys.key_size as usize..]
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+        key
+    }
+
+    fn start_encryption(&mut self, key: [u8; 16], actions: &mut Vec<Action>) {
+        let key = self.shorten(key);
+
+        if self.keys.secure_connections {
+            self.keys.ltk = Some(EncryptionKey {
+                value: key,
+                ediv: 0,
+                rand: 0,
+            });
+        }
+
+        self.phase = Phase::Encryption;
+        actions.push(Action::StartEncryption(key));
+    }
+
+    fn send_keys(&mut self, actions: &mut Vec<Action>) {
+        let local_keys = match self.role {
+            Role::Initiator => self.initiator_keys,
+            Role::Responder => self.responder_keys,
+        };
+
+        if local_keys.contains(KeyDistribution::EncKey) {
+            let random = self.random();
+            let key = EncryptionKey {
+                value: self.shorten(random),
+                ediv: u16::from_le_bytes(self.random()),
+                rand: u64::from_le_bytes(self.random()),
+            };
+
+            actions.push(Action::Send(Pdu::EncryptionInformation(key.value)));
+            actions.push(Action::Send(Pdu::CentralIdentification {
+                ediv: key.ediv,
+                rand: key.rand,
+            }));
+            self.store_ltk(key, self.role);
+        }
+
+        if let (true, Some(identity)) = (
+            local_keys.contains(KeyDistribution::IdKey),
+            self.config.identity,
+        ) {
+            actions.push(Action::Send(Pdu::IdentityInformation(identity.irk)));
+            actions.push(Action::Send(Pdu::IdentityAddressInformation {
+                random: identity.address_type == AddressType::LERandom,
+                address: identity.address,
+            }));
+        }
+
+        if let (true, Some(csrk)) = (
+            local_keys.contains(KeyDistribution::SignKey),
+            self.config.csrk,
+        ) {
+            actions.push(Action::Send(Pdu::SigningInformation(csrk)));
+        }
+
+        self.local_keys_sent = true;
+    }
+
+    fn store_ltk(&mut self, key: EncryptionKey, distributor: Role) {
+        match distributor {
+            Role::Initiator => self.keys.initiator_ltk = Some(key),
+            Role::Responder => self.keys.ltk = Some(key),
+        }
+    }
+
+    fn on_key(&mut self, pdu: Pdu, actions: &mut Vec<Action>) -> Step {
+        if self.expected.pop_front() != Some(pdu.opcode()) {
+            return Err(PairingFailedReason::UnspecifiedReason);
+        }
+
+        let remote_role = match self.role {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        };
+
+        match pdu {
+            Pdu::EncryptionInformation(key) | Pdu::IdentityInformation(key) => {
+                self.pending_key = key;
+            }
+            Pdu::CentralIdentification { ediv, rand } => {
+                let key = EncryptionKey {
+                    value: self.pending_key,
+                    ediv,
+                    rand,
+                };
+                self.store_ltk(key, remote_role);
+            }
+            Pdu::IdentityAddressInformation { random, address } => {
+                self.keys.remote_identity = Some(Identity {
+                    irk: self.pending_key,
+                    address,
+                    address_type: if random {
+                        AddressType::LERandom
+                    } else {
+                        AddressType::LEPublic
+                    },
+                });
+            }
+            Pdu::SigningInformation(csrk) => self.keys.remote_csrk = Some(csrk),
+            _ => {}
+        }
+
+        self.distribution_progress(actions)
+    }
+
+    /// Completes the pairing once the keys of the remote device have
+    /// arrived, after the initiator has sent its own.
+    fn distribution_progress(&mut self, actions: &mut Vec<Action>) -> Step {
+        if !self.expected.is_empty() {
+            return Ok(());
+        }
+
+        if !self.local_keys_sent {
+            self.send_keys(actions);
+        }
+
+        self.phase = Phase::Complete;
+        actions.push(Action::Complete(self.keys.clone()));
+        Ok(())
+    }
+}
+
+impl Debug for Pairing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pairing")
+            .field("role", &self.role)
+            .field("phase", &self.phase)
+            .field("method", &self.method)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The first 7 bytes of a Pairing Request or Response, which the confirm
+/// values of legacy pairing include.
+fn pdu_bytes(pdu: &Pdu) -> [u8; 7] {
+    let mut out = [0u8; 7];
+    out.copy_from_slice(&pdu.encode()[..]);
+    out
+}
+
+/// A passkey as the 128-bit value that the confirm values include.
+fn passkey_value(passkey: u32) -> [u8; 16] {
+    let mut value = [0u8; 16];
+    value[..4].copy_from_slice(&passkey.to_le_bytes());
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deterministic generator, which is different for each seed.
+    fn rng(seed: u64) -> impl FnMut(&mut [u8]) + Send + 'static {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+
+        move |buf: &mut [u8]| {
+            for byte in buf {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+        }
+    }
+
+    fn address(last: u8) -> (Address, AddressType) {
+        (
+            Address::new([last, 0x22, 0x33, 0x44, 0x55, 0xC6]),
+            AddressType::LERandom,
+        )
+    }
+
+    fn config(io_capability: IoCapability, auth_req: BitFlags<AuthRequirement>) -> PairingConfig {
+        PairingConfig {
+            io_capability,
+            auth_req,
+            ..PairingConfig::default()
+        }
+    }
+
+    /// Runs a pairing between two state machines. The users answer every
+    /// question, and enter `wrong_passkey` instead of the displayed passkey
+    /// if it is set.
+    fn pair(
+        initiator: PairingConfig,
+        responder: PairingConfig,
+        wrong_passkey: Option<u32>,
+    ) -> Result<(PairingKeys, PairingKeys, PairingMethod), Error> {
+        let (mut sides, actions) = {
+            let (initiator, actions) = Pairing::initiate(initiator, address(1), address(2), rng(1));
+            let responder = Pairing::respond(responder, address(2), address(1), rng(2));
+            ([initiator, responder], actions)
+        };
+
+        let mut queue: VecDeque<(usize, Action)> =
+            actions.into_iter().map(|action| (0, action)).collect();
+        let mut displayed = None;
+        let mut requested = vec![];
+        let mut numbers = vec![];
+        let mut encryption_keys = vec![];
+        let mut results = vec![];
+
+        while let Some((side, action)) = queue.pop_front() {
+            let other = 1 - side;
+            let mut answers = vec![];
+
+            match action {
+                Action::Send(pdu) => {
+                    let pdu = Pdu::parse(pdu.encode()).unwrap();
+                    answers.push((other, sides[other].handle(pdu)?));
+                }
+                Action::DisplayPasskey(passkey) => displayed = Some(passkey),
+                Action::RequestPasskey => requested.push(side),
+                Action::ConfirmNumber(number) => {
+                    numbers.push(number);
+                    answers.push((side, sides[side].confirm(true)?));
+                }
+                Action::StartEncryption(key) => {
+                    encryption_keys.push(key);
+
+                    if encryption_keys.len() == 2 {
+                        assert_eq!(encryption_keys[0], encryption_keys[1]);
+                        answers.push((0, sides[0].encrypted()?));
+                        answers.push((1, sides[1].encrypted()?));
+                    }
+                }
+                Action::Complete(keys) => results.push((side, keys)),
+            }
+
+            // both users enter the same passkey if neither device displays one
+            let passkey = match (wrong_passkey, displayed) {
+                (Some(passkey), _) => Some(Passkey::new(passkey).unwrap()),
+                (None, Some(passkey)) => Some(passkey),
+                (None, None) if requested.len() == 2 => Some(Passkey::new(123_456).unwrap()),
+                (None, None) => None,
+            };
+
+            if let Some(passkey) = passkey {
+                for side in requested.drain(..) {
+                    answers.push((side, sides[side].passkey(passkey)?));
+                }
+            }
+
+            for (side, actions) in answers {
+                queue.extend(actions.into_iter().map(|action| (side, action)));
+            }
+        }
+
+        if numbers.len() == 2 {
+            assert_eq!(numbers[0], numbers[1]);
+        }
+
+        results.sort_by_key(|(side, _)| *side);
+        assert_eq!(results.len(), 2);
+        assert!(sides.iter().all(Pairing::is_complete));
+
+        let method = sides[0].method().unwrap();
+        let responder_keys = results.pop().unwrap().1;
+        let initiator_keys = results.pop().unwrap().1;
+        Ok((initiator_keys, responder_keys, method))
+    }
+
+    #[test]
+    fn secure_connections_methods() {
+        let sc = AuthRequirement::Bonding | AuthRequirement::SecureConnections;
+        let mitm = sc | AuthRequirement::Mitm;
+
+        let cases = [
+            (IoCapability::NoInputNoOutput, sc, PairingMethod::JustWorks),
+            (
+                IoCapability::DisplayYesNo,
+                mitm,
+                PairingMethod::NumericComparison,
+            ),
+            (
+                IoCapability::KeyboardOnly,
+                mitm,
+                PairingMethod::PasskeyEntry {
+                    initiator_inputs: true,
+                    responder_inputs: true,
+                },
+            ),
+        ];
+
+        for (io_capability, auth_req, expected) in cases.iter().copied() {
+            let (initiator, responder, method) = pair(
+                config(io_capability, auth_req),
+                config(io_capability, auth_req),
+                None,
+            )
+            .unwrap();
+
+            assert_eq!(method, expected);
+            assert!(initiator.secure_connections);
+            assert_eq!(initiator.authenticated, expected.is_authenticated());
+            assert_eq!(initiator.ltk, responder.ltk);
+            assert_eq!(initiator.ltk.unwrap().ediv, 0);
+            assert_eq!(initiator.initiator_ltk, None);
+        }
+    }
+
+    #[test]
+    fn secure_connections_oob() {
+        let local = LocalOobData::generate(rng(3));
+        let values = local.values();
+
+        // only the initiator received the responder's values
+        let initiator = PairingConfig {
+            oob: Some(OobData::SecureConnections {
+                local: None,
+                remote: Some(values),
+            }),
+            ..PairingConfig::default()
+        };
+        let responder = PairingConfig {
+            oob: Some(OobData::SecureConnections {
+                local: Some(local.clone()),
+                remote: None,
+            }),
+            ..PairingConfig::default()
+        };
+
+        let (initiator_keys, responder_keys, method) =
+            pair(initiator.clone(), responder, None).unwrap();
+        assert_eq!(method, PairingMethod::OutOfBand);
+        assert_eq!(initiator_keys.ltk, responder_keys.ltk);
+
+        // a responder with a different key pair than the values promised
+        let responder = PairingConfig {
+            oob: Some(OobData::SecureConnections {
+                local: Some(LocalOobData::generate(rng(4))),
+                remote: None,
+            }),
+            ..PairingConfig::default()
+        };
+
+        assert!(matches!(
+            pair(initiator, responder, None),
+            Err(Error::Aborted(PairingFailedReason::ConfirmValueFailed))
+        ));
+    }
+
+    #[test]
+    fn legacy_methods() {
+        let mitm = AuthRequirement::Bonding | AuthRequirement::Mitm;
+        let identity = Identity {
+            irk: [0x11; 16],
+            address: Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]),
+            address_type: AddressType::LEPublic,
+        };
+
+        let initiator = PairingConfig {
+            max_key_size: 10,
+            ..config(IoCapability::KeyboardOnly, mitm)
+        };
+        let responder = PairingConfig {
+            identity: Some(identity),
+            remote_keys: KeyDistribution::EncKey.into(),
+            ..config(IoCapability::DisplayOnly, mitm)
+        };
+
+        let (initiator_keys, responder_keys, method) =
+            pair(initiator.clone(), responder.clone(), None).unwrap();
+        assert_eq!(
+            method,
+            PairingMethod::PasskeyEntry {
+                initiator_inputs: true,
+                responder_inputs: false,
+            }
+        );
+        assert!(!initiator_keys.secure_connections);
+        assert_eq!(initiator_keys.key_size, 10);
+        assert_eq!(initiator_keys.ltk, responder_keys.ltk);
+        assert_eq!(initiator_keys.initiator_ltk, responder_keys.initiator_ltk);
+        assert_eq!(&initiator_keys.ltk.unwrap().value[10..], &[0; 6]);
+        assert_eq!(initiator_keys.remote_identity, Some(identity));
+        assert_eq!(responder_keys.remote_identity, None);
+
+        let oob = |tk| PairingConfig {
+            oob: Some(OobData::Legacy(tk)),
+            ..config(IoCapability::NoInputNoOutput, BitFlags::empty())
+        };
+        let (_, _, method) = pair(oob([0x42; 16]), oob([0x42; 16]), None).unwrap();
+        assert_eq!(method, PairingMethod::OutOfBand);
+        assert!(matches!(
+            pair(oob([0x42; 16]), oob([0x43; 16]), None),
+            Err(Error::Aborted(PairingFailedReason::ConfirmValueFailed))
+        ));
+
+        assert!(matches!(
+            pair(initiator, responder, Some(1)),
+            Err(Error::Aborted(PairingFailedReason::ConfirmValueFailed))
+        ));
+    }
+
+    #[test]
+    fn wrong_passkey() {
+        let mitm =
+            AuthRequirement::Bonding | AuthRequirement::Mitm | AuthRequirement::SecureConnections;
+
+        assert!(matches!(
+            pair(
+                config(IoCapability::KeyboardOnly, mitm),
+                config(IoCapability::DisplayOnly, mitm),
+                Some(1),
+            ),
+            Err(Error::Aborted(PairingFailedReason::ConfirmValueFailed))
+        ));
+    }
+
+    #[test]
+    fn requirements() {
+        let sc_only = PairingConfig {
+            secure_connections_only: true,
+            ..PairingConfig::default()
+        };
+        let legacy = config(
+            IoCapability::NoInputNoOutput,
+            AuthRequirement::Bonding.into(),
+        );
+
+        assert!(matches!(
+            pair(sc_only, legacy.clone(), None),
+            Err(Error::Aborted(
+                PairingFailedReason::AuthenticationRequirements
+            ))
+        ));
+
+        let mitm = config(
+            IoCapability::NoInputNoOutput,
+            AuthRequirement::Bonding | AuthRequirement::Mitm,
+        );
+        assert!(matches!(
+            pair(legacy, mitm, None),
+            Err(Error::Aborted(
+                PairingFailedReason::AuthenticationRequirements
+            ))
+        ));
+
+        let (mut pairing, _) =
+            Pairing::initiate(PairingConfig::default(), address(1), address(2), rng(1));
+        assert!(matches!(pairing.confirm(true), Err(Error::UnexpectedInput)));
+        assert!(matches!(
+            pairing.handle(Pdu::PairingRandom([0; 16])),
+            Err(Error::Aborted(PairingFailedReason::UnspecifiedReason))
+        ));
+    }
+}
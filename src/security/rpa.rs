@@ -0,0 +1,69 @@
+//! Resolution of resolvable private addresses.
+//!
+//! LE devices with privacy enabled advertise with resolvable private
+//! addresses, which change regularly. The lower half of such an address is
+//! a hash of the upper half, computed with the identity resolving key that
+//! the device distributed when it was paired. The kernel resolves these
+//! addresses for the keys that were loaded with
+//! [`load_identity_resolving_keys`](crate::management::load_identity_resolving_keys),
+//! but only reports the identity once it is connected; [`resolve_rpa`] lets
+//! applications recognize known devices in Device Found events as well.
+//! The hash function itself is [`ah`](super::crypto::ah).
+
+use super::crypto::ah;
+use crate::management::IdentityResolvingKey;
+use crate::Address;
+
+/// Finds the identity resolving key that `address` was generated with, if
+/// it is a resolvable private address.
+pub fn resolve_rpa_key(
+    address: Address,
+    irks: &[IdentityResolvingKey],
+) -> Option<&IdentityResolvingKey> {
+    if !address.is_resolvable_private() {
+        return None;
+    }
+
+    let bytes: [u8; 6] = address.into();
+    let prand = [bytes[3], bytes[4], bytes[5]];
+
+    irks.iter().find(|irk| ah(&irk.value, prand) == bytes[..3])
+}
+
+/// Maps a resolvable private address to the identity address of the device
+/// that generated it, using the identity resolving keys of the known
+/// devices. Returns `None` if `address` is not a resolvable private address
+/// or if none of the keys resolve it. Use [`resolve_rpa_key`] to get the
+/// type of the identity address as well.
+pub fn resolve_rpa(address: Address, irks: &[IdentityResolvingKey]) -> Option<Address> {
+    resolve_rpa_key(address, irks).map(|irk| irk.address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AddressType;
+
+    #[test]
+    fn resolve() {
+        // the sample data of the core specification, vol 3, part h, d.7
+        let value = [
+            0x9b, 0x7d, 0x39, 0x0a, 0xa6, 0x10, 0x10, 0x34, 0x05, 0xad, 0xc8, 0x57, 0xa3, 0x34,
+            0x02, 0xec,
+        ];
+        assert_eq!(ah(&value, [0x94, 0x81, 0x70]), [0xaa, 0xfb, 0x0d]);
+
+        let identity: Address = "c0:11:22:33:44:55".parse().unwrap();
+        let irks = [IdentityResolvingKey::new(
+            identity,
+            AddressType::LERandom,
+            value,
+        )];
+
+        let rpa: Address = "70:81:94:0d:fb:aa".parse().unwrap();
+        assert_eq!(resolve_rpa(rpa, &irks), Some(identity));
+
+        let other: Address = "70:81:94:0d:fb:ab".parse().unwrap();
+        assert_eq!(resolve_rpa(other, &irks), None);
+    }
+}
@@ -0,0 +1,455 @@
+//! The Security Manager Protocol, which LE devices use to pair and to
+//! distribute keys.
+//!
+//! The kernel normally runs SMP itself. Stacks which drive a controller
+//! through the HCI user channel have to run it on their own, over the fixed
+//! L2CAP channel [`SMP_CID`]. This module contains the PDUs of the protocol
+//! and the selection of the pairing method. With the `crypto` feature,
+//! [`pairing`](super::pairing) runs the whole pairing procedure, and the
+//! functions in [`crypto`](super::crypto) compute the confirm values, keys
+//! and DHKey checks on their own.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use enumflags2::{bitflags, BitFlags};
+use num_traits::FromPrimitive;
+
+use crate::management::IoCapability;
+use crate::Address;
+
+/// The fixed L2CAP channel that SMP uses on LE links.
+pub const SMP_CID: u16 = 0x0006;
+
+/// The fixed L2CAP channel that SMP uses on BR/EDR links, for cross-transport
+/// key derivation.
+pub const SMP_BREDR_CID: u16 = 0x0007;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("the remote device sent an invalid pdu")]
+    InvalidPdu,
+
+    /// The remote device aborted pairing.
+    #[error("pairing failed: {0:?}")]
+    PairingFailed(PairingFailedReason),
+
+    /// This device aborted pairing, and has to send a Pairing Failed PDU
+    /// with the reason.
+    #[error("pairing was aborted: {0:?}")]
+    Aborted(PairingFailedReason),
+
+    #[error("the remote device sent {0:?} after pairing ended")]
+    UnexpectedPdu(Opcode),
+
+    #[error("the pairing did not ask for this input")]
+    UnexpectedInput,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum Opcode {
+    PairingRequest = 0x01,
+    PairingResponse,
+    PairingConfirm,
+    PairingRandom,
+    PairingFailed,
+    EncryptionInformation,
+    CentralIdentification,
+    IdentityInformation,
+    IdentityAddressInformation,
+    SigningInformation,
+    SecurityRequest,
+    PairingPublicKey,
+    PairingDhKeyCheck,
+    KeypressNotification,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum PairingFailedReason {
+    PasskeyEntryFailed = 0x01,
+    OobNotAvailable,
+    AuthenticationRequirements,
+    ConfirmValueFailed,
+    PairingNotSupported,
+    EncryptionKeySize,
+    CommandNotSupported,
+    UnspecifiedReason,
+    RepeatedAttempts,
+    InvalidParameters,
+    DhKeyCheckFailed,
+    NumericComparisonFailed,
+    BrEdrPairingInProgress,
+    CrossTransportKeyDerivationNotAllowed,
+    KeyRejected,
+}
+
+/// The bits of the AuthReq field. Bonding is requested by setting
+/// [`AuthRequirement::Bonding`]; the other value of the 2-bit bonding field
+/// is reserved.
+#[repr(u8)]
+#[bitflags]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AuthRequirement {
+    Bonding = 1 << 0,
+    Mitm = 1 << 2,
+    SecureConnections = 1 << 3,
+    Keypress = 1 << 4,
+    Ct2 = 1 << 5,
+}
+
+#[repr(u8)]
+#[bitflags]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum KeyDistribution {
+    EncKey = 1 << 0,
+    IdKey = 1 << 1,
+    SignKey = 1 << 2,
+    LinkKey = 1 << 3,
+}
+
+/// The parameters of a Pairing Request or Pairing Response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairingFeatures {
+    pub io_capability: IoCapability,
+    pub oob_data_present: bool,
+    pub auth_req: BitFlags<AuthRequirement>,
+    /// The maximum encryption key size, from 7 to 16 bytes.
+    pub max_key_size: u8,
+    pub initiator_keys: BitFlags<KeyDistribution>,
+    pub responder_keys: BitFlags<KeyDistribution>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pdu {
+    PairingRequest(PairingFeatures),
+    PairingResponse(PairingFeatures),
+    PairingConfirm([u8; 16]),
+    PairingRandom([u8; 16]),
+    PairingFailed(PairingFailedReason),
+    /// The Long Term Key that is used with legacy pairing.
+    EncryptionInformation([u8; 16]),
+    CentralIdentification {
+        ediv: u16,
+        rand: u64,
+    },
+    /// The Identity Resolving Key.
+    IdentityInformation([u8; 16]),
+    IdentityAddressInformation {
+        random: bool,
+        address: Address,
+    },
+    /// The Connection Signature Resolving Key.
+    SigningInformation([u8; 16]),
+    SecurityRequest(BitFlags<AuthRequirement>),
+    /// A P-256 public key. Both coordinates are little-endian.
+    PairingPublicKey {
+        x: [u8; 32],
+        y: [u8; 32],
+    },
+    PairingDhKeyCheck([u8; 16]),
+    KeypressNotification(u8),
+}
+
+impl Pdu {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Pdu::PairingRequest(_) => Opcode::PairingRequest,
+            Pdu::PairingResponse(_) => Opcode::PairingResponse,
+            Pdu::PairingConfirm(_) => Opcode::PairingConfirm,
+            Pdu::PairingRandom(_) => Opcode::PairingRandom,
+            Pdu::PairingFailed(_) => Opcode::PairingFailed,
+            Pdu::EncryptionInformation(_) => Opcode::EncryptionInformation,
+            Pdu::CentralIdentification { .. } => Opcode::CentralIdentification,
+            Pdu::IdentityInformation(_) => Opcode::IdentityInformation,
+            Pdu::IdentityAddressInformation { .. } => Opcode::IdentityAddressInformation,
+            Pdu::SigningInformation(_) => Opcode::SigningInformation,
+            Pdu::SecurityRequest(_) => Opcode::SecurityRequest,
+            Pdu::PairingPublicKey { .. } => Opcode::PairingPublicKey,
+            Pdu::PairingDhKeyCheck(_) => Opcode::PairingDhKeyCheck,
+            Pdu::KeypressNotification(_) => Opcode::KeypressNotification,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(65);
+        buf.put_u8(self.opcode() as u8);
+
+        match self {
+            Pdu::PairingRequest(features) | Pdu::PairingResponse(features) => {
+                buf.put_u8(features.io_capability as u8);
+                buf.put_u8(features.oob_data_present as u8);
+                buf.put_u8(features.auth_req.bits());
+                buf.put_u8(features.max_key_size);
+                buf.put_u8(features.initiator_keys.bits());
+                buf.put_u8(features.responder_keys.bits());
+            }
+            Pdu::PairingConfirm(value)
+            | Pdu::PairingRandom(value)
+            | Pdu::EncryptionInformation(value)
+            | Pdu::IdentityInformation(value)
+            | Pdu::SigningInformation(value)
+            | Pdu::PairingDhKeyCheck(value) => buf.put_slice(&value[..]),
+            Pdu::PairingFailed(reason) => buf.put_u8(*reason as u8),
+            Pdu::CentralIdentification { ediv, rand } => {
+                buf.put_u16_le(*ediv);
+                buf.put_u64_le(*rand);
+            }
+            Pdu::IdentityAddressInformation { random, address } => {
+                buf.put_u8(*random as u8);
+                buf.put_slice(address.as_ref());
+            }
+            Pdu::SecurityRequest(auth_req) => buf.put_u8(auth_req.bits()),
+            Pdu::PairingPublicKey { x, y } => {
+                buf.put_slice(&x[..]);
+                buf.put_slice(&y[..]);
+            }
+            Pdu::KeypressNotification(notification) => buf.put_u8(*notification),
+        }
+
+        buf.freeze()
+    }
+
+    pub fn parse(mut buf: Bytes) -> Result<Self, Error> {
+        if buf.is_empty() {
+            return Err(Error::InvalidPdu);
+        }
+
+        let opcode = Opcode::from_u8(buf.get_u8()).ok_or(Error::InvalidPdu)?;
+
+        let len = match opcode {
+            Opcode::PairingRequest | Opcode::PairingResponse => 6,
+            Opcode::PairingFailed | Opcode::SecurityRequest | Opcode::KeypressNotification => 1,
+            Opcode::CentralIdentification => 10,
+            Opcode::IdentityAddressInformation => 7,
+            Opcode::PairingPublicKey => 64,
+            _ => 16,
+        };
+
+        if buf.len() != len {
+            return Err(Error::InvalidPdu);
+        }
+
+        let key = |buf: &mut Bytes| {
+            let mut key = [0u8; 16];
+            buf.copy_to_slice(&mut key[..]);
+            key
+        };
+
+        Ok(match opcode {
+            Opcode::PairingRequest | Opcode::PairingResponse => {
+                let features = PairingFeatures {
+                    io_capability: IoCapability::from_u8(buf.get_u8()).ok_or(Error::InvalidPdu)?,
+                    oob_data_present: buf.get_u8() == 0x01,
+                    auth_req: BitFlags::from_bits_truncate(buf.get_u8()),
+                    max_key_size: buf.get_u8(),
+                    initiator_keys: BitFlags::from_bits_truncate(buf.get_u8()),
+                    responder_keys: BitFlags::from_bits_truncate(buf.get_u8()),
+                };
+
+                if opcode == Opcode::PairingRequest {
+                    Pdu::PairingRequest(features)
+                } else {
+                    Pdu::PairingResponse(features)
+                }
+            }
+            Opcode::PairingConfirm => Pdu::PairingConfirm(key(&mut buf)),
+            Opcode::PairingRandom => Pdu::PairingRandom(key(&mut buf)),
+            Opcode::PairingFailed => Pdu::PairingFailed(
+                PairingFailedReason::from_u8(buf.get_u8())
+                    .unwrap_or(PairingFailedReason::UnspecifiedReason),
+            ),
+            Opcode::EncryptionInformation => Pdu::EncryptionInformation(key(&mut buf)),
+            Opcode::CentralIdentification => Pdu::CentralIdentification {
+                ediv: buf.get_u16_le(),
+                rand: buf.get_u64_le(),
+            },
+            Opcode::IdentityInformation => Pdu::IdentityInformation(key(&mut buf)),
+            Opcode::IdentityAddressInformation => {
+                let random = buf.get_u8() == 0x01;
+                let mut address = [0u8; 6];
+                buf.copy_to_slice(&mut address[..]);
+
+                Pdu::IdentityAddressInformation {
+                    random,
+                    address: Address::from(address),
+                }
+            }
+            Opcode::SigningInformation => Pdu::SigningInformation(key(&mut buf)),
+            Opcode::SecurityRequest => {
+                Pdu::SecurityRequest(BitFlags::from_bits_truncate(buf.get_u8()))
+            }
+            Opcode::PairingPublicKey => {
+                let mut x = [0u8; 32];
+                let mut y = [0u8; 32];
+                buf.copy_to_slice(&mut x[..]);
+                buf.copy_to_slice(&mut y[..]);
+                Pdu::PairingPublicKey { x, y }
+            }
+            Opcode::PairingDhKeyCheck => Pdu::PairingDhKeyCheck(key(&mut buf)),
+            Opcode::KeypressNotification => Pdu::KeypressNotification(buf.get_u8()),
+        })
+    }
+}
+
+/// How the user takes part in a pairing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingMethod {
+    /// No user interaction. The resulting keys are unauthenticated.
+    JustWorks,
+    /// A 6-digit passkey is displayed on one device and entered on the other,
+    /// or entered on both.
+    PasskeyEntry {
+        initiator_inputs: bool,
+        responder_inputs: bool,
+    },
+    /// Both devices display a 6-digit value, and the user confirms that they
+    /// match. Only available with LE Secure Connections.
+    NumericComparison,
+    OutOfBand,
+}
+
+impl PairingMethod {
+    /// Whether the keys that result from this method are protected against
+    /// man-in-the-middle attacks.
+    pub fn is_authenticated(&self) -> bool {
+        !matches!(self, PairingMethod::JustWorks)
+    }
+}
+
+/// Whether both devices support LE Secure Connections, in which case it is
+/// used instead of legacy pairing.
+pub fn uses_secure_connections(initiator: &PairingFeatures, responder: &PairingFeatures) -> bool {
+    initiator
+        .auth_req
+        .contains(AuthRequirement::SecureConnections)
+        && responder
+            .auth_req
+            .contains(AuthRequirement::SecureConnections)
+}
+
+/// Selects the pairing method from the features that were exchanged in the
+/// Pairing Request and the Pairing Response, following the rules in the Core
+/// Specification, Vol 3, Part H, 2.3.5.1.
+pub fn pairing_method(initiator: &PairingFeatures, responder: &PairingFeatures) -> PairingMethod {
+    use IoCapability::*;
+
+    let secure_connections = uses_secure_connections(initiator, responder);
+
+    let oob = if secure_connections {
+        initiator.oob_data_present || responder.oob_data_present
+    } else {
+        initiator.oob_data_present && responder.oob_data_present
+    };
+
+    if oob {
+        return PairingMethod::OutOfBand;
+    }
+
+    if !initiator.auth_req.contains(AuthRequirement::Mitm)
+        && !responder.auth_req.contains(AuthRequirement::Mitm)
+    {
+        return PairingMethod::JustWorks;
+    }
+
+    let passkey = |initiator_inputs, responder_inputs| PairingMethod::PasskeyEntry {
+        initiator_inputs,
+        responder_inputs,
+    };
+
+    match (initiator.io_capability, responder.io_capability) {
+        (NoInputNoOutput, _) | (_, NoInputNoOutput) => PairingMethod::JustWorks,
+        (DisplayOnly, DisplayOnly) | (DisplayOnly, DisplayYesNo) | (DisplayYesNo, DisplayOnly) => {
+            PairingMethod::JustWorks
+        }
+        (DisplayYesNo, DisplayYesNo)
+        | (DisplayYesNo, KeyboardDisplay)
+        | (KeyboardDisplay, DisplayYesNo)
+        | (KeyboardDisplay, KeyboardDisplay)
+            if secure_connections =>
+        {
+            PairingMethod::NumericComparison
+        }
+        (DisplayYesNo, DisplayYesNo) => PairingMethod::JustWorks,
+        (KeyboardOnly, KeyboardOnly) => passkey(true, true),
+        (DisplayOnly, _) | (DisplayYesNo, _) => passkey(false, true),
+        (KeyboardOnly, _) => passkey(true, false),
+        (KeyboardDisplay, KeyboardOnly) | (KeyboardDisplay, KeyboardDisplay) => {
+            passkey(false, true)
+        }
+        (KeyboardDisplay, _) => passkey(true, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(
+        io_capability: IoCapability,
+        auth_req: BitFlags<AuthRequirement>,
+    ) -> PairingFeatures {
+        PairingFeatures {
+            io_capability,
+            oob_data_present: false,
+            auth_req,
+            max_key_size: 16,
+            initiator_keys: KeyDistribution::EncKey | KeyDistribution::IdKey,
+            responder_keys: KeyDistribution::EncKey | KeyDistribution::IdKey,
+        }
+    }
+
+    #[test]
+    fn pairing_request() {
+        let pdu = Pdu::PairingRequest(features(
+            IoCapability::KeyboardDisplay,
+            AuthRequirement::Bonding | AuthRequirement::Mitm | AuthRequirement::SecureConnections,
+        ));
+
+        let buf = pdu.encode();
+        assert_eq!(&buf[..], &[0x01, 0x04, 0x00, 0x0D, 0x10, 0x03, 0x03]);
+        assert_eq!(Pdu::parse(buf).unwrap(), pdu);
+
+        assert!(Pdu::parse(Bytes::from_static(&[0x03, 0x00])).is_err());
+    }
+
+    #[test]
+    fn method_selection() {
+        let mitm = AuthRequirement::Bonding | AuthRequirement::Mitm;
+        let sc = mitm | AuthRequirement::SecureConnections;
+
+        assert_eq!(
+            pairing_method(
+                &features(IoCapability::KeyboardDisplay, sc),
+                &features(IoCapability::DisplayYesNo, sc)
+            ),
+            PairingMethod::NumericComparison
+        );
+        assert_eq!(
+            pairing_method(
+                &features(IoCapability::KeyboardDisplay, mitm),
+                &features(IoCapability::DisplayYesNo, mitm)
+            ),
+            PairingMethod::PasskeyEntry {
+                initiator_inputs: true,
+                responder_inputs: false
+            }
+        );
+        assert_eq!(
+            pairing_method(
+                &features(IoCapability::DisplayOnly, mitm),
+                &features(IoCapability::KeyboardOnly, mitm)
+            ),
+            PairingMethod::PasskeyEntry {
+                initiator_inputs: false,
+                responder_inputs: true
+            }
+        );
+        assert_eq!(
+            pairing_method(
+                &features(IoCapability::KeyboardDisplay, BitFlags::empty()),
+                &features(IoCapability::KeyboardDisplay, BitFlags::empty())
+            ),
+            PairingMethod::JustWorks
+        );
+    }
+}
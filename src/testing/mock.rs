@@ -0,0 +1,547 @@
+//! A stand-in for the management interface of the kernel.
+//!
+//! [`MockKernel::pair`] creates a [`ManagementStream`] which is connected to
+//! a [`MockKernel`] instead of the kernel. Tests receive the commands that
+//! the code under test sends, answer them, and send events, without root
+//! privileges or a controller.
+//!
+//! Tests which only need canned replies can describe them with a
+//! [`MockScript`] and spawn [`MockKernel::serve`] to answer every command
+//! while the code under test runs on the stream. A [`MockTransport`] answers
+//! them in memory instead, so it needs neither a socket nor a tokio runtime.
+//!
+//! [`MOCK_CONTROLLER`], [`MOCK_ADDRESS`] and the functions such as
+//! [`controller_info`] and [`device_found`] build the commands, replies and
+//! events that most tests need, and [`converse`] runs the code under test
+//! against a [`MockKernel`] that is driven by the test.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use crate::management::interface::parse_mgmt_event;
+use crate::management::{
+    Command, CommandStatus, Controller, Error, ManagementStream, ManagementTransport, Request,
+    Response,
+};
+use crate::{Address, AddressType};
+
+const EVT_COMMAND_COMPLETE: u16 = 0x0001;
+const EVT_COMMAND_STATUS: u16 = 0x0002;
+
+/// The controller that tests send their commands to.
+pub const MOCK_CONTROLLER: Controller = Controller(0);
+
+/// The address of the remote device in tests, 66:55:44:33:22:11.
+pub const MOCK_ADDRESS: Address = Address::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+/// The return parameters of Read Controller Info for a controller whose
+/// current settings are `current_settings`. Everything else is zero.
+pub fn controller_info(current_settings: u32) -> Vec<u8> {
+    // address, version, manufacturer, supported settings, current settings,
+    // class of device, name and short name
+    let mut info = vec![0u8; 6 + 1 + 2 + 4 + 4 + 3 + 249 + 11];
+    info[13..17].copy_from_slice(&current_settings.to_le_bytes());
+    info
+}
+
+/// An address and its type, as they start the parameters of most commands
+/// and events that are about a device.
+pub fn address_param(address: Address, address_type: AddressType) -> Vec<u8> {
+    let mut param = address.as_ref().to_vec();
+    param.push(address_type.to_mgmt_u8());
+    param
+}
+
+/// The parameters of a Device Found event for `address`, without flags.
+pub fn device_found(address: Address, address_type: AddressType, rssi: i8, eir: &[u8]) -> Vec<u8> {
+    let mut param = address_param(address, address_type);
+    param.push(rssi as u8);
+    param.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    param.extend_from_slice(&(eir.len() as u16).to_le_bytes());
+    param.extend_from_slice(eir);
+    param
+}
+
+/// The parameters of a Device Connected event for `address`, without flags
+/// and EIR data.
+pub fn device_connected(address: Address, address_type: AddressType) -> Vec<u8> {
+    let mut param = address_param(address, address_type);
+    param.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    param
+}
+
+/// Runs `client`, the code under test, together with `kernel`, which plays
+/// the part of the kernel on a [`MockKernel`], and returns the result of
+/// `client`.
+pub async fn converse<T>(client: impl Future<Output = T>, kernel: impl Future<Output = ()>) -> T {
+    let (result, ()) = futures::join!(client, kernel);
+    result
+}
+
+/// A management command that was sent to a [`MockKernel`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCommand {
+    pub opcode: Command,
+    pub controller: Controller,
+    pub param: Bytes,
+}
+
+/// How a [`MockKernel`] answers a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MockReply {
+    status: CommandStatus,
+    param: Vec<u8>,
+    events: Vec<(u16, Vec<u8>)>,
+}
+
+/// The replies that [`MockKernel::serve`] and [`MockTransport`] send, by
+/// opcode.
+///
+/// Replies for the same opcode are used in the order in which they were
+/// added, and the last one is repeated once the others have been used.
+/// Commands without a reply are answered with a Command Status event with
+/// [`CommandStatus::UnknownCommand`], like the kernel does for commands that
+/// it does not know.
+#[derive(Debug, Clone, Default)]
+pub struct MockScript {
+    replies: HashMap<Command, VecDeque<MockReply>>,
+    last: Option<Command>,
+}
+
+impl MockScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers a command with `opcode` with a successful Command Complete
+    /// event whose return parameters are `param`.
+    pub fn reply(self, opcode: Command, param: impl Into<Vec<u8>>) -> Self {
+        self.push(opcode, CommandStatus::Success, param.into())
+    }
+
+    /// Answers a command with `opcode` with a Command Status event with
+    /// `status`.
+    pub fn fail(self, opcode: Command, status: CommandStatus) -> Self {
+        self.push(opcode, status, vec![])
+    }
+
+    /// Sends an event on the controller of the command after the reply that
+    /// was added last, such as the New Settings event that follows a change
+    /// made through another socket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no reply has been added yet.
+    pub fn then_event(mut self, event_code: u16, param: impl Into<Vec<u8>>) -> Self {
+        let reply = self
+            .last
+            .and_then(|opcode| self.replies.get_mut(&opcode))
+            .and_then(|replies| replies.back_mut())
+            .expect("then_event must follow a reply");
+
+        reply.events.push((event_code, param.into()));
+        self
+    }
+
+    fn push(mut self, opcode: Command, status: CommandStatus, param: Vec<u8>) -> Self {
+        self.replies
+            .entry(opcode)
+            .or_default()
+            .push_back(MockReply {
+                status,
+                param,
+                events: vec![],
+            });
+        self.last = Some(opcode);
+        self
+    }
+
+    fn next(&mut self, opcode: Command) -> Option<MockReply> {
+        let replies = self.replies.get_mut(&opcode)?;
+
+        if replies.len() > 1 {
+            replies.pop_front()
+        } else {
+            replies.front().cloned()
+        }
+    }
+
+    /// Like [`next`](MockScript::next), but answers commands without a reply
+    /// like the kernel.
+    fn next_or_unknown(&mut self, opcode: Command) -> MockReply {
+        self.next(opcode).unwrap_or(MockReply {
+            status: CommandStatus::UnknownCommand,
+            param: vec![],
+            events: vec![],
+        })
+    }
+}
+
+/// Lays out an event with its header, as in the mgmt-api documentation.
+fn encode_event(controller: Controller, event_code: u16, param: &[u8]) -> Bytes {
+    let mut packet = BytesMut::with_capacity(param.len() + 6);
+    packet.put_u16_le(event_code);
+    packet.put_u16_le(controller.into());
+    packet.put_u16_le(param.len() as u16);
+    packet.put_slice(param);
+    packet.freeze()
+}
+
+/// The parameters of a Command Complete event, or of a Command Status event
+/// if `param` is `None`.
+fn encode_reply(opcode: Command, status: CommandStatus, param: Option<&[u8]>) -> Bytes {
+    let param = param.unwrap_or_default();
+    let mut event = BytesMut::with_capacity(param.len() + 3);
+    event.put_u16_le(opcode.into());
+    event.put_u8(status.into());
+    event.put_slice(param);
+    event.freeze()
+}
+
+/// The end of a mock management socket which plays the part of the kernel.
+#[derive(Debug)]
+pub struct MockKernel {
+    inner: UnixStream,
+}
+
+impl MockKernel {
+    /// Creates a [`ManagementStream`] and the mock kernel that it is
+    /// connected to.
+    pub fn pair() -> Result<(ManagementStream, Self), std::io::Error> {
+        let (stream, inner) = ManagementStream::pair()?;
+        Ok((stream, Self { inner }))
+    }
+
+    /// Answers every command according to `script` until the stream is
+    /// dropped, and returns the commands that were received, so that tests
+    /// can check them afterwards.
+    pub async fn serve(
+        mut self,
+        mut script: MockScript,
+    ) -> Result<Vec<MockCommand>, std::io::Error> {
+        let mut commands = vec![];
+
+        loop {
+            let command = match self.receive_command().await {
+                Ok(command) => command,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(commands),
+                Err(err) => return Err(err),
+            };
+
+            let reply = script.next_or_unknown(command.opcode);
+
+            if reply.status == CommandStatus::Success {
+                self.command_complete(
+                    command.controller,
+                    command.opcode,
+                    reply.status,
+                    &reply.param,
+                )
+                .await?;
+            } else {
+                self.command_status(command.controller, command.opcode, reply.status)
+                    .await?;
+            }
+
+            for (event_code, param) in &reply.events {
+                self.send_event(command.controller, *event_code, param)
+                    .await?;
+            }
+
+            commands.push(command);
+        }
+    }
+
+    /// Waits for the next message that was sent on the stream, and returns
+    /// it including its header.
+    pub async fn receive_raw(&mut self) -> Result<Bytes, std::io::Error> {
+        // the header and the largest parameters that its length can describe
+        let mut buf = vec![0u8; 6 + u16::MAX as usize];
+        let len = self.inner.read(&mut buf).await?;
+
+        if len == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        buf.truncate(len);
+        Ok(buf.into())
+    }
+
+    /// Waits for the next command that was sent on the stream.
+    pub async fn receive_command(&mut self) -> Result<MockCommand, std::io::Error> {
+        let mut buf = self.receive_raw().await?;
+
+        if buf.len() < 6 {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+
+        let opcode = Command::from(buf.get_u16_le());
+        let controller = Controller::from(buf.get_u16_le());
+        let len = buf.get_u16_le() as usize;
+
+        Ok(MockCommand {
+            opcode,
+            controller,
+            param: buf.split_to(len.min(buf.len())),
+        })
+    }
+
+    /// Waits for the next command and answers it with a successful Command
+    /// Complete event whose return parameters are `reply`. The command is
+    /// returned so that tests can check it.
+    pub async fn answer(&mut self, reply: &[u8]) -> Result<MockCommand, std::io::Error> {
+        let request = self.receive_command().await?;
+
+        self.command_complete(
+            request.controller,
+            request.opcode,
+            CommandStatus::Success,
+            reply,
+        )
+        .await?;
+
+        Ok(request)
+    }
+
+    /// Sends a Command Complete event.
+    pub async fn command_complete(
+        &mut self,
+        controller: Controller,
+        opcode: Command,
+        status: CommandStatus,
+        param: &[u8],
+    ) -> Result<(), std::io::Error> {
+        let event = encode_reply(opcode, status, Some(param));
+        self.send_event(controller, EVT_COMMAND_COMPLETE, &event)
+            .await
+    }
+
+    /// Sends a Command Status event.
+    pub async fn command_status(
+        &mut self,
+        controller: Controller,
+        opcode: Command,
+        status: CommandStatus,
+    ) -> Result<(), std::io::Error> {
+        let event = encode_reply(opcode, status, None);
+        self.send_event(controller, EVT_COMMAND_STATUS, &event)
+            .await
+    }
+
+    /// Sends an event with the given event code and parameters, laid out as
+    /// in the mgmt-api documentation.
+    pub async fn send_event(
+        &mut self,
+        controller: Controller,
+        event_code: u16,
+        param: &[u8],
+    ) -> Result<(), std::io::Error> {
+        self.send_raw(&encode_event(controller, event_code, param))
+            .await
+    }
+
+    /// Sends a message as it is, including its header.
+    pub async fn send_raw(&mut self, message: &[u8]) -> Result<(), std::io::Error> {
+        self.inner.write_all(message).await
+    }
+}
+
+/// A [`ManagementTransport`] which answers commands in memory, according to
+/// a [`MockScript`].
+///
+/// The replies and events are parsed like the messages of the kernel, so
+/// the code under test sees the same responses as on a [`MockKernel`]. Clones
+/// share their state, so a test can keep one to send events and check the
+/// commands after it gives another to
+/// [`ManagementStream::with_transport`].
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockTransportState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockTransportState {
+    script: MockScript,
+    commands: Vec<MockCommand>,
+    pending: VecDeque<Result<Response, Error>>,
+    waker: Option<Waker>,
+}
+
+impl MockTransport {
+    pub fn new(script: MockScript) -> Self {
+        MockTransport {
+            state: Arc::new(Mutex::new(MockTransportState {
+                script,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Creates a [`ManagementStream`] on a new transport, and returns a clone
+    /// of the transport.
+    pub fn stream(script: MockScript) -> (ManagementStream, Self) {
+        let transport = Self::new(script);
+        (
+            ManagementStream::with_transport(transport.clone()),
+            transport,
+        )
+    }
+
+    /// The commands that were sent so far.
+    pub fn commands(&self) -> Vec<MockCommand> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Queues an event with the given event code and parameters, laid out as
+    /// in the mgmt-api documentation.
+    pub fn send_event(&self, controller: Controller, event_code: u16, param: &[u8]) {
+        self.send_raw(&encode_event(controller, event_code, param));
+    }
+
+    /// Queues a message as it is, including its header.
+    pub fn send_raw(&self, message: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.push_back(parse_mgmt_event(message));
+
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl ManagementTransport for MockTransport {
+    fn poll_send(&mut self, _cx: &mut Context<'_>, request: &Request) -> Poll<io::Result<usize>> {
+        let reply = {
+            let mut state = self.state.lock().unwrap();
+            state.commands.push(MockCommand {
+                opcode: request.opcode,
+                controller: request.controller,
+                param: request.param.clone(),
+            });
+            state.script.next_or_unknown(request.opcode)
+        };
+
+        let event = if reply.status == CommandStatus::Success {
+            let param = encode_reply(request.opcode, reply.status, Some(&reply.param));
+            encode_event(request.controller, EVT_COMMAND_COMPLETE, &param)
+        } else {
+            let param = encode_reply(request.opcode, reply.status, None);
+            encode_event(request.controller, EVT_COMMAND_STATUS, &param)
+        };
+
+        self.send_raw(&event);
+        for (event_code, param) in &reply.events {
+            self.send_event(request.controller, *event_code, param);
+        }
+
+        Poll::Ready(Ok(6 + request.param.len()))
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context<'_>) -> Poll<Result<Response, Error>> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.pending.pop_front() {
+            Some(response) => Poll::Ready(response),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    use crate::management::{
+        load_blocked_keys, set_powered, BlockedKey, BlockedKeyType, ControllerSetting, Event,
+    };
+
+    #[tokio::test]
+    async fn scripted_replies() {
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let controller = Controller::from(0);
+
+        let script = MockScript::new()
+            .fail(Command::SetPowered, CommandStatus::Busy)
+            .reply(Command::SetPowered, [0x01, 0x00, 0x00, 0x00]);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        assert!(matches!(
+            set_powered(&mut socket, controller, true, None).await,
+            Err(Error::CommandError {
+                status: CommandStatus::Busy,
+                ..
+            })
+        ));
+        let settings = set_powered(&mut socket, controller, true, None)
+            .await
+            .unwrap();
+        assert!(settings.contains(ControllerSetting::Powered));
+
+        drop(socket);
+        let commands = kernel.await.unwrap().unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(&commands[1].param[..], &[0x01]);
+    }
+
+    #[tokio::test]
+    async fn large_commands() {
+        let (mut socket, mut kernel) = MockKernel::pair().unwrap();
+
+        // 2000 blocked keys of 17 bytes each
+        let keys = vec![BlockedKey::new(BlockedKeyType::LinkKey, [0xAB; 16]); 2000];
+        let command = load_blocked_keys(&mut socket, Controller(0), keys, None);
+        let kernel = async {
+            let command = kernel.answer(&[]).await.unwrap();
+            assert_eq!(command.param.len(), 2 + 2000 * 17);
+        };
+
+        let (result, ()) = futures::join!(command, kernel);
+        result.unwrap();
+    }
+
+    #[test]
+    fn in_memory_transport() {
+        let script = MockScript::new()
+            .fail(Command::SetPowered, CommandStatus::Busy)
+            .reply(Command::SetPowered, [0x01, 0x00, 0x00, 0x00])
+            .then_event(0x0006, [0x01, 0x00, 0x00, 0x00]);
+        let (mut socket, transport) = MockTransport::stream(script);
+
+        // no runtime is needed, because nothing waits for a socket
+        futures::executor::block_on(async {
+            assert!(matches!(
+                set_powered(&mut socket, Controller(0), true, None).await,
+                Err(Error::CommandError {
+                    status: CommandStatus::Busy,
+                    ..
+                })
+            ));
+            let settings = set_powered(&mut socket, Controller(0), true, None)
+                .await
+                .unwrap();
+            assert!(settings.contains(ControllerSetting::Powered));
+
+            let response = socket.receive().await.unwrap();
+            assert_eq!(response.controller, Controller(0));
+            assert!(matches!(response.event, Event::NewSettings { .. }));
+        });
+
+        let commands = transport.commands();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1].opcode, Command::SetPowered);
+        assert_eq!(&commands[1].param[..], &[0x01]);
+        assert!(socket.try_receive().unwrap().is_none());
+        assert_eq!(socket.as_raw_fd(), -1);
+    }
+}
@@ -0,0 +1,16 @@
+//! Utilities for testing code which uses this library without Bluetooth
+//! hardware. This module is only available with the `test-util` feature.
+//!
+//! [`mock::MockKernel`] stands in for the management interface of the kernel
+//! and needs no privileges, so it can be used to test code that drives
+//! commands and events, such as a
+//! [`DiscoverySession`](crate::management::DiscoverySession) or a
+//! [`PairingAgent`](crate::management::PairingAgent).
+//! [`mock::MockTransport`] answers the commands in memory, without a socket
+//! or a tokio runtime. [`vhci`] creates real controllers in the kernel, for
+//! tests that can run as root. [`replay`] feeds a trace that was recorded
+//! from a real management socket back to the code that produced it.
+
+pub mod mock;
+pub mod replay;
+pub mod vhci;
@@ -0,0 +1,192 @@
+//! Replays traces of management sockets that were written by a
+//! [`Recorder`](crate::capture::Recorder).
+//!
+//! A [`Replayer`] plays the part of the kernel on a [`MockKernel`]: it waits
+//! for each message that the recorded code sent, checks that the code under
+//! test sends the same message, and sends the messages that were received
+//! after it. Events are delivered in exactly the order in which they were
+//! recorded, so a trace of a race between a reply and an event reproduces
+//! the same interleaving every time it is replayed.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+
+use super::mock::MockKernel;
+use crate::capture::{Direction, TRACE_MAGIC};
+
+/// A message in a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub direction: Direction,
+    /// The time between the creation of the recorder and the message.
+    pub timestamp: Duration,
+    /// The message, including its header.
+    pub data: Bytes,
+}
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("an i/o error occurred")]
+    Io(#[from] std::io::Error),
+
+    #[error("the trace is not valid")]
+    InvalidTrace,
+
+    #[error("message {index} of the trace is {expected:02x?}, but {actual:02x?} was sent")]
+    Mismatch {
+        index: usize,
+        expected: Bytes,
+        actual: Bytes,
+    },
+
+    #[error("{actual:02x?} was sent after the end of the trace")]
+    Unexpected { actual: Bytes },
+}
+
+/// A recorded trace which can be replayed with [`Replayer::serve`].
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    records: VecDeque<TraceRecord>,
+}
+
+impl Replayer {
+    /// Reads a trace file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Parses a trace.
+    pub fn parse(mut trace: &[u8]) -> Result<Self, ReplayError> {
+        if !trace.starts_with(TRACE_MAGIC) {
+            return Err(ReplayError::InvalidTrace);
+        }
+        trace.advance(TRACE_MAGIC.len());
+
+        let mut records = VecDeque::new();
+
+        while trace.has_remaining() {
+            if trace.remaining() < 11 {
+                return Err(ReplayError::InvalidTrace);
+            }
+
+            let direction = match trace.get_u8() {
+                0 => Direction::Sent,
+                1 => Direction::Received,
+                _ => return Err(ReplayError::InvalidTrace),
+            };
+            let timestamp = Duration::from_micros(trace.get_u64_le());
+            let len = trace.get_u16_le() as usize;
+
+            if trace.remaining() < len {
+                return Err(ReplayError::InvalidTrace);
+            }
+
+            records.push_back(TraceRecord {
+                direction,
+                timestamp,
+                data: Bytes::copy_from_slice(&trace[..len]),
+            });
+            trace.advance(len);
+        }
+
+        Ok(Self { records })
+    }
+
+    /// The messages of the trace which have not been replayed yet.
+    pub fn records(&self) -> &VecDeque<TraceRecord> {
+        &self.records
+    }
+
+    /// Replays the trace on `kernel` until the stream is dropped.
+    ///
+    /// This fails as soon as the code under test sends a message which is
+    /// different from the next recorded one, or sends anything after the end
+    /// of the trace. Dropping the stream before the end of the trace is not
+    /// an error, since a trace may continue beyond the part that a test is
+    /// interested in.
+    pub async fn serve(mut self, mut kernel: MockKernel) -> Result<(), ReplayError> {
+        let mut index = 0;
+
+        while let Some(record) = self.records.pop_front() {
+            match record.direction {
+                Direction::Received => kernel.send_raw(&record.data).await?,
+                Direction::Sent => {
+                    let actual = match kernel.receive_raw().await {
+                        Ok(actual) => actual,
+                        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                            return Ok(())
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    if actual != record.data {
+                        return Err(ReplayError::Mismatch {
+                            index,
+                            expected: record.data,
+                            actual,
+                        });
+                    }
+                }
+            }
+
+            index += 1;
+        }
+
+        match kernel.receive_raw().await {
+            Ok(actual) => Err(ReplayError::Unexpected { actual }),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::capture::Recorder;
+    use crate::management::{set_powered, Command, Controller, ControllerSetting};
+    use crate::testing::mock::MockScript;
+
+    #[tokio::test]
+    async fn replay_recorded_trace() {
+        let controller = Controller::from(0);
+
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let recorder = Arc::new(Recorder::new(Vec::new()).unwrap());
+        recorder.attach(&mut socket);
+
+        let script = MockScript::new().reply(Command::SetPowered, [0x01, 0x00, 0x00, 0x00]);
+        let kernel = tokio::spawn(kernel.serve(script));
+
+        set_powered(&mut socket, controller, true, None)
+            .await
+            .unwrap();
+        drop(socket);
+        kernel.await.unwrap().unwrap();
+
+        let trace = Arc::try_unwrap(recorder).unwrap().into_inner();
+        let replayer = Replayer::parse(&trace).unwrap();
+        assert_eq!(replayer.records().len(), 2);
+
+        let (mut socket, kernel) = MockKernel::pair().unwrap();
+        let kernel = tokio::spawn(replayer.serve(kernel));
+
+        let settings = set_powered(&mut socket, controller, true, None)
+            .await
+            .unwrap();
+        assert!(settings.contains(ControllerSetting::Powered));
+
+        // the trace does not answer a second command
+        set_powered(&mut socket, controller, false, None).await.ok();
+        drop(socket);
+        assert!(matches!(
+            kernel.await.unwrap(),
+            Err(ReplayError::Unexpected { .. })
+        ));
+    }
+}
@@ -0,0 +1,310 @@
+//! Virtual controllers backed by the kernel's `vhci` driver.
+//!
+//! Opening `/dev/vhci` creates a new HCI controller. The kernel sends the HCI
+//! commands for that controller to the file, and treats anything written to
+//! the file as if it was sent by the controller. [`VirtualController`] answers
+//! the commands that the kernel needs to bring the controller up, and allows
+//! tests to inject events such as advertising reports.
+//!
+//! Creating a virtual controller requires access to `/dev/vhci`, which usually
+//! means running as root.
+
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::unix::AsyncFd;
+
+use crate::management::{AdvertisingReport, Controller};
+use crate::util::check_error;
+use crate::{Address, AddressType};
+
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const HCI_VENDOR_PKT: u8 = 0xFF;
+
+const EVT_COMMAND_COMPLETE: u8 = 0x0E;
+const EVT_LE_META: u8 = 0x3E;
+const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
+const EVT_LE_EXTENDED_ADVERTISING_REPORT: u8 = 0x0D;
+
+/// The manufacturer ID that virtual controllers report: the Linux Foundation.
+const MANUFACTURER: u16 = 0x05F1;
+
+/// An HCI command that the kernel sent to a virtual controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HciCommand {
+    pub opcode: u16,
+    pub param: Bytes,
+}
+
+/// The type of advertisement in an LE advertising report.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingType {
+    /// Connectable undirected advertising
+    AdvInd = 0x00,
+    /// Connectable directed advertising
+    AdvDirectInd = 0x01,
+    /// Scannable undirected advertising
+    AdvScanInd = 0x02,
+    /// Non connectable undirected advertising
+    AdvNonconnInd = 0x03,
+    /// Scan response
+    ScanRsp = 0x04,
+}
+
+/// A virtual LE-only controller.
+///
+/// The controller only makes progress while [`VirtualController::process`]
+/// is being called, so tests usually spawn a task which calls it in a loop.
+///
+/// Dropping it closes the file, which removes the controller.
+pub struct VirtualController {
+    inner: AsyncFd<OwnedFd>,
+    controller: Controller,
+    address: Address,
+}
+
+impl VirtualController {
+    /// Creates a new virtual controller with the given public address.
+    pub async fn open(address: Address) -> Result<Self, std::io::Error> {
+        let fd = check_error(unsafe {
+            libc::open(
+                "/dev/vhci\0".as_ptr() as *const libc::c_char,
+                libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+            )
+        })?;
+
+        let inner = AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd) })?;
+
+        let mut vhci = Self {
+            inner,
+            controller: Controller::none(),
+            address,
+        };
+
+        // ask the kernel to create a primary controller; it answers with the
+        // index of the new controller
+        vhci.write(&[HCI_VENDOR_PKT, 0x00]).await?;
+
+        loop {
+            let mut packet = vhci.read().await?;
+
+            if packet.len() >= 4 && packet.get_u8() == HCI_VENDOR_PKT {
+                packet.advance(1);
+                vhci.controller = Controller(packet.get_u16_le());
+                break;
+            }
+        }
+
+        Ok(vhci)
+    }
+
+    /// The index of this controller, which can be used with the management
+    /// API.
+    pub fn controller(&self) -> Controller {
+        self.controller
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Waits for the next HCI command from the kernel and answers it. The
+    /// command is returned so that tests can check what the kernel asked
+    /// for. Packets other than commands are discarded.
+    pub async fn process(&mut self) -> Result<HciCommand, std::io::Error> {
+        loop {
+            let mut packet = self.read().await?;
+
+            if packet.len() < 4 || packet.get_u8() != HCI_COMMAND_PKT {
+                continue;
+            }
+
+            let opcode = packet.get_u16_le();
+            let len = packet.get_u8() as usize;
+            let command = HciCommand {
+                opcode,
+                param: packet.split_to(len.min(packet.len())),
+            };
+
+            let reply = self.command_reply(&command);
+            self.command_complete(opcode, &reply[..]).await?;
+
+            return Ok(command);
+        }
+    }
+
+    /// Sends an HCI event to the kernel.
+    pub async fn send_event(&mut self, event_code: u8, param: &[u8]) -> Result<(), std::io::Error> {
+        let mut packet = BytesMut::with_capacity(param.len() + 3);
+        packet.put_u8(HCI_EVENT_PKT);
+        packet.put_u8(event_code);
+        packet.put_u8(param.len() as u8);
+        packet.put_slice(param);
+
+        self.write(&packet[..]).await
+    }
+
+    /// Sends a Command Complete event. `param` holds the return parameters,
+    /// starting with the status.
+    pub async fn command_complete(
+        &mut self,
+        opcode: u16,
+        param: &[u8],
+    ) -> Result<(), std::io::Error> {
+        let mut event = BytesMut::with_capacity(param.len() + 3);
+        event.put_u8(1); // number of allowed command packets
+        event.put_u16_le(opcode);
+        event.put_slice(param);
+
+        self.send_event(EVT_COMMAND_COMPLETE, &event[..]).await
+    }
+
+    /// Sends an LE Advertising Report event for a single advertisement. The
+    /// kernel only reports it through the management API while discovery is
+    /// running.
+    pub async fn advertising_report(
+        &mut self,
+        adv_type: AdvertisingType,
+        address: Address,
+        address_type: AddressType,
+        data: &[u8],
+        rssi: i8,
+    ) -> Result<(), std::io::Error> {
+        let mut event = BytesMut::with_capacity(data.len() + 12);
+        event.put_u8(EVT_LE_ADVERTISING_REPORT);
+        event.put_u8(1); // number of reports
+        event.put_u8(adv_type as u8);
+        event.put_u8(address_type.to_hci_le_u8().unwrap_or(0x00));
+        event.put_slice(address.as_ref());
+        event.put_u8(data.len() as u8);
+        event.put_slice(data);
+        event.put_i8(rssi);
+
+        self.send_event(EVT_LE_META, &event[..]).await
+    }
+
+    /// Sends an LE Extended Advertising Report event for a single report,
+    /// which carries the metadata of extended advertising such as the PHYs
+    /// and the advertising set ID. Fields which are `None` are sent as "not
+    /// available".
+    pub async fn extended_advertising_report(
+        &mut self,
+        report: &AdvertisingReport,
+    ) -> Result<(), std::io::Error> {
+        let mut event = BytesMut::with_capacity(report.data.len() + 26);
+        event.put_u8(EVT_LE_EXTENDED_ADVERTISING_REPORT);
+        event.put_u8(1); // number of reports
+        event.put_u16_le(report.event_type());
+        event.put_u8(report.address_type.to_hci_le_u8().unwrap_or(0x00));
+        event.put_slice(report.address.as_ref());
+        event.put_u8(report.primary_phy.map_or(0x01, |phy| phy as u8));
+        event.put_u8(report.secondary_phy.map_or(0x00, |phy| phy as u8));
+        event.put_u8(report.sid.unwrap_or(0xFF));
+        event.put_i8(report.tx_power.unwrap_or(0x7F));
+        event.put_i8(report.rssi.unwrap_or(0x7F));
+        event.put_u16_le(report.periodic_interval.unwrap_or(0));
+        // no direct address
+        event.put_u8(0x00);
+        event.put_slice(&[0x00; 6]);
+        event.put_u8(report.data.len() as u8);
+        event.put_slice(&report.data[..]);
+
+        self.send_event(EVT_LE_META, &event[..]).await
+    }
+
+    /// Builds the return parameters for a command. Commands which are not
+    /// known are answered with success and zeroed return parameters, which is
+    /// enough for the kernel to continue.
+    fn command_reply(&self, command: &HciCommand) -> Bytes {
+        let mut reply = BytesMut::with_capacity(65);
+        reply.put_u8(0x00); // status
+
+        match command.opcode {
+            // Read Local Version Information
+            0x1001 => {
+                reply.put_u8(0x09); // HCI version 5.0
+                reply.put_u16_le(0x0000);
+                reply.put_u8(0x09); // LMP version 5.0
+                reply.put_u16_le(MANUFACTURER);
+                reply.put_u16_le(0x0000);
+            }
+            // Read Local Supported Features
+            0x1003 => {
+                // LE supported, BR/EDR not supported
+                reply.put_slice(&[0x00, 0x00, 0x00, 0x00, 0x60, 0x00, 0x00, 0x00]);
+            }
+            // Read Buffer Size
+            0x1005 => {
+                reply.put_u16_le(1021);
+                reply.put_u8(0);
+                reply.put_u16_le(8);
+                reply.put_u16_le(0);
+            }
+            // Read BD_ADDR
+            0x1009 => reply.put_slice(self.address.as_ref()),
+            // LE Read Buffer Size
+            0x2002 => {
+                reply.put_u16_le(251);
+                reply.put_u8(8);
+            }
+            // LE Read Filter Accept List Size
+            0x200F => reply.put_u8(8),
+            // LE Read Supported States
+            0x201C => reply.put_slice(&[0xFF; 8]),
+            _ => reply.put_slice(&[0x00; 64]),
+        }
+
+        reply.freeze()
+    }
+
+    async fn read(&self) -> Result<Bytes, std::io::Error> {
+        let mut buf = vec![0u8; 1024];
+
+        loop {
+            let mut guard = self.inner.readable().await?;
+
+            match guard.try_io(|fd| {
+                check_error(unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    ) as libc::c_int
+                })
+            }) {
+                Ok(len) => {
+                    buf.truncate(len? as usize);
+                    return Ok(buf.into());
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    async fn write(&self, packet: &[u8]) -> Result<(), std::io::Error> {
+        loop {
+            let mut guard = self.inner.writable().await?;
+
+            match guard.try_io(|fd| {
+                check_error(unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                    ) as libc::c_int
+                })
+            }) {
+                Ok(res) => return res.map(|_| ()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsRawFd for VirtualController {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
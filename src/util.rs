@@ -1,8 +1,7 @@
 use std::collections::HashMap;
-use std::ffi::CString;
 use std::hash::Hash;
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 use enumflags2::{BitFlag, BitFlags};
 use num_traits::FromPrimitive;
 
@@ -29,12 +28,8 @@ pub(crate) trait BufExt: Buf {
         self.get_u8() != 0
     }
 
-    fn get_primitive_u8<T: FromPrimitive>(&mut self) -> T {
-        FromPrimitive::from_u8(self.get_u8()).unwrap()
-    }
-
-    fn get_primitive_u16_le<T: FromPrimitive>(&mut self) -> T {
-        FromPrimitive::from_u16(self.get_u16_le()).unwrap()
+    fn get_primitive_u16_le<T: FromPrimitive>(&mut self) -> Option<T> {
+        FromPrimitive::from_u16(self.get_u16_le())
     }
 
     fn get_flags_u8<T: BitFlag<Numeric = u8>>(&mut self) -> BitFlags<T> {
@@ -49,14 +44,23 @@ pub(crate) trait BufExt: Buf {
         BitFlags::from_bits_truncate(self.get_u32_le())
     }
 
-    fn get_c_string(&mut self) -> CString {
+    /// Reads a NUL-terminated string, without the NUL.
+    fn get_c_bytes(&mut self) -> Bytes {
         let mut bytes = vec![];
-        let mut current = self.get_u8();
-        while current != 0 && self.has_remaining() {
-            bytes.push(current);
-            current = self.get_u8();
+        while self.has_remaining() {
+            match self.get_u8() {
+                0 => break,
+                byte => bytes.push(byte),
+            }
         }
-        unsafe { CString::from_vec_unchecked(bytes) }
+        bytes.into()
+    }
+
+    /// Reads a NUL-terminated string. Invalid UTF-8 sequences are replaced
+    /// with U+FFFD, since names reported by the kernel are not guaranteed to
+    /// be valid UTF-8.
+    fn get_c_string_lossy(&mut self) -> String {
+        String::from_utf8_lossy(&self.get_c_bytes()).into_owned()
     }
 
     /// Parses a list of Type/Length/Value entries into a map keyed by type
@@ -77,12 +81,23 @@ pub(crate) trait BufExt: Buf {
     ///   ...
     /// ```
     ///
+    /// Parameters with types that are not known are skipped.
     fn get_tlv_map<T: FromPrimitive + Eq + Hash>(&mut self) -> HashMap<T, Vec<u8>> {
         let mut parameters = HashMap::new();
-        while self.has_remaining() {
-            let parameter_type: T = self.get_primitive_u16_le();
+        // a truncated entry ends the list
+        while self.remaining() >= 3 {
+            let parameter_type: Option<T> = self.get_primitive_u16_le();
             let value_size = self.get_u8() as usize;
-            parameters.insert(parameter_type, self.get_vec_u8(value_size));
+
+            if self.remaining() < value_size {
+                break;
+            }
+
+            let value = self.get_vec_u8(value_size);
+
+            if let Some(parameter_type) = parameter_type {
+                parameters.insert(parameter_type, value);
+            }
         }
         parameters
     }
@@ -97,3 +112,22 @@ pub(crate) fn check_error(value: libc::c_int) -> Result<libc::c_int, std::io::Er
         Ok(value)
     }
 }
+
+/// Formats bytes as space-separated hex, like btmon does, for logging packets.
+#[cfg(feature = "tracing")]
+pub(crate) struct Hex<'a>(pub &'a [u8]);
+
+#[cfg(feature = "tracing")]
+impl std::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
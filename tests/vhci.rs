@@ -0,0 +1,83 @@
+//! These tests create virtual controllers, so they need access to `/dev/vhci`
+//! and the `CAP_NET_ADMIN` capability. Run them with
+//! `cargo test --features test-util -- --ignored`.
+#![cfg(feature = "test-util")]
+
+use std::time::Duration;
+
+use bluez::management::*;
+use bluez::testing::vhci::{AdvertisingType, VirtualController};
+use bluez::{Address, AddressType};
+
+const LE_SET_SCAN_ENABLE: u16 = 0x200C;
+
+#[tokio::test]
+#[ignore = "requires access to /dev/vhci"]
+async fn advertising_report() -> anyhow::Result<()> {
+    let remote = Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]);
+    let mut vhci =
+        VirtualController::open(Address::new([0x10, 0x20, 0x30, 0x40, 0x50, 0x60])).await?;
+    let controller = vhci.controller();
+
+    tokio::spawn(async move {
+        loop {
+            let command = vhci.process().await.unwrap();
+
+            if command.opcode == LE_SET_SCAN_ENABLE && command.param.first() == Some(&0x01) {
+                vhci.advertising_report(
+                    AdvertisingType::AdvInd,
+                    remote,
+                    AddressType::LERandom,
+                    &[0x02, 0x01, 0x06, 0x05, 0x09, b't', b'e', b's', b't'],
+                    -42,
+                )
+                .await
+                .unwrap();
+            }
+        }
+    });
+
+    let mut socket = ManagementStream::open()?;
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !get_controller_list(&mut socket, None)
+            .await?
+            .contains(&controller)
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        set_powered(&mut socket, controller, true, None).await?;
+        start_discovery(
+            &mut socket,
+            controller,
+            AddressTypeFlag::LERandom.into(),
+            None,
+        )
+        .await?;
+
+        loop {
+            let response = socket.receive().await?;
+
+            if let Event::DeviceFound {
+                address,
+                address_type,
+                rssi,
+                eir_data,
+                ..
+            } = response.event
+            {
+                assert_eq!(address, remote);
+                assert_eq!(address_type, AddressType::LERandom);
+                assert_eq!(rssi, -42);
+                assert_eq!(EirData::parse(&eir_data).name(), Some("test"));
+                break;
+            }
+        }
+
+        anyhow::Ok(())
+    })
+    .await??;
+
+    Ok(())
+}